@@ -0,0 +1,629 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! A GraphQL schema generated from the stored relations, and the execution of queries
+//! against it.
+//!
+//! Each stored relation whose name is a valid GraphQL name gets an object type, named
+//! after the relation with the first letter in upper case, with a field per column, and
+//! a field of `Query` with the name of the relation returning its rows. The arguments of
+//! that field are the columns, each restricting the rows to those with the given value,
+//! and `limit` and `offset`. Each field of `Query` is compiled to a single query against
+//! its relation.
+//!
+//! Only queries are supported, without fragments or directives.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use itertools::Itertools;
+use miette::{bail, ensure, miette, Result};
+use serde_json::{json, Map, Value};
+
+use cozo::{DataValue, DbInstance, ScriptMutability};
+
+use crate::server::Auth;
+
+/// Arguments of every field of `Query` that are not columns.
+const PAGING_ARGS: [&str; 2] = ["limit", "offset"];
+/// How deep selection sets, values and types can be nested in a query
+const MAX_DEPTH: usize = 64;
+
+struct ObjectType {
+    relation: String,
+    name: String,
+    /// The key columns come first
+    fields: Vec<FieldDef>,
+    n_keys: usize,
+}
+
+struct FieldDef {
+    name: String,
+    /// The GraphQL type of the values, without the non-null marker
+    scalar: &'static str,
+    nullable: bool,
+}
+
+/// The types generated from the stored relations.
+pub(crate) struct Schema {
+    types: Vec<ObjectType>,
+}
+
+fn is_name(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !s.starts_with("__")
+}
+
+/// The GraphQL scalar of a column type as written in the schema, and whether it is
+/// nullable. Types with no GraphQL counterpart are given as `Json`.
+fn scalar_of(col_type: &str) -> (&'static str, bool) {
+    let (base, nullable) = match col_type.strip_suffix('?') {
+        Some(base) => (base, true),
+        None => (col_type, false),
+    };
+    let scalar = match base {
+        "Int" => "Int",
+        "Float" => "Float",
+        "String" => "String",
+        "Bool" => "Boolean",
+        _ => "Json",
+    };
+    (scalar, nullable || base == "Any")
+}
+
+impl Schema {
+    pub(crate) fn new(db: &DbInstance) -> Result<Self> {
+        let mut types: Vec<ObjectType> = vec![];
+        for rel in db.describe()?.relations {
+            let mut name = rel.name.clone();
+            if !is_name(&name) || PAGING_ARGS.contains(&name.as_str()) {
+                continue;
+            }
+            name[..1].make_ascii_uppercase();
+            if name == "Query" || types.iter().any(|t| t.name == name) {
+                continue;
+            }
+            let fields = rel
+                .keys
+                .iter()
+                .chain(rel.values.iter())
+                .filter(|col| is_name(&col.name) && !PAGING_ARGS.contains(&col.name.as_str()))
+                .map(|col| {
+                    let (scalar, nullable) = scalar_of(&col.col_type);
+                    FieldDef {
+                        name: col.name.clone(),
+                        scalar,
+                        nullable,
+                    }
+                })
+                .collect_vec();
+            // the rows are told apart by their keys, which must be fields
+            let n_keys = rel.keys.len();
+            if fields.len() < n_keys
+                || !fields[..n_keys]
+                    .iter()
+                    .zip(&rel.keys)
+                    .all(|(f, k)| f.name == k.name)
+            {
+                continue;
+            }
+            types.push(ObjectType {
+                relation: rel.name,
+                name,
+                fields,
+                n_keys,
+            });
+        }
+        Ok(Self { types })
+    }
+
+    /// The schema in the GraphQL schema definition language.
+    pub(crate) fn sdl(&self) -> String {
+        let mut sdl = String::from("scalar Json\n\ntype Query {\n");
+        for t in &self.types {
+            let args = t
+                .fields
+                .iter()
+                .map(|f| format!("{}: {}", f.name, f.scalar))
+                .chain(PAGING_ARGS.iter().map(|arg| format!("{arg}: Int")))
+                .join(", ");
+            writeln!(sdl, "  {}({args}): [{}!]!", t.relation, t.name).unwrap();
+        }
+        sdl.push_str("}\n");
+        for t in &self.types {
+            writeln!(sdl, "\ntype {} {{", t.name).unwrap();
+            for f in &t.fields {
+                let marker = if f.nullable { "" } else { "!" };
+                writeln!(sdl, "  {}: {}{marker}", f.name, f.scalar).unwrap();
+            }
+            sdl.push_str("}\n");
+        }
+        sdl
+    }
+
+    /// Run `query` and return the response, holding either `data` or `errors`.
+    pub(crate) fn execute(
+        &self,
+        db: &DbInstance,
+        auth: &Auth,
+        query: &str,
+        variables: &Map<String, Value>,
+    ) -> Value {
+        match self.execute_inner(db, auth, query, variables) {
+            Ok(data) => json!({ "data": data }),
+            Err(err) => json!({ "data": null, "errors": [{ "message": err.to_string() }] }),
+        }
+    }
+
+    fn execute_inner(
+        &self,
+        db: &DbInstance,
+        auth: &Auth,
+        query: &str,
+        variables: &Map<String, Value>,
+    ) -> Result<Value> {
+        let selections = parse_document(query, variables)?;
+        let mut data = Map::new();
+        for sel in selections {
+            let value = if sel.name == "__typename" {
+                json!("Query")
+            } else {
+                let t = self
+                    .types
+                    .iter()
+                    .find(|t| t.relation == sel.name)
+                    .ok_or_else(|| miette!("Cannot query field '{}' on type 'Query'", sel.name))?;
+                self.resolve(db, auth, t, &sel)?
+            };
+            data.insert(sel.key().to_string(), value);
+        }
+        Ok(Value::Object(data))
+    }
+
+    /// The rows of the relation of `t` selected by `sel`.
+    fn resolve(
+        &self,
+        db: &DbInstance,
+        auth: &Auth,
+        t: &ObjectType,
+        sel: &Selection,
+    ) -> Result<Value> {
+        let Some(sub_selections) = &sel.selections else {
+            bail!(
+                "Field '{}' of type '[{}!]!' must have a selection",
+                sel.name,
+                t.name
+            )
+        };
+        let mut filters = vec![];
+        let mut params = BTreeMap::new();
+        let mut options = vec![];
+        for (arg, value) in &sel.arguments {
+            if PAGING_ARGS.contains(&arg.as_str()) {
+                let n = value
+                    .as_u64()
+                    .ok_or_else(|| miette!("Argument '{arg}' must be a non-negative integer"))?;
+                options.push(format!(":{arg} {n}"));
+            } else if t.fields.iter().any(|f| f.name == *arg) {
+                let param = format!("p{}", params.len());
+                filters.push(format!("{arg} == ${param}"));
+                params.insert(param, DataValue::from(value.clone()));
+            } else {
+                bail!("Unknown argument '{arg}' on field 'Query.{}'", sel.name)
+            }
+        }
+        for sub in sub_selections {
+            ensure!(
+                sub.selections.is_none(),
+                "Field '{}' of type '{}' must not have a selection",
+                sub.name,
+                t.name
+            );
+            ensure!(
+                sub.name == "__typename" || t.fields.iter().any(|f| f.name == sub.name),
+                "Cannot query field '{}' on type '{}'",
+                sub.name,
+                t.name
+            );
+        }
+
+        // the keys are always bound, so that rows with the same selected values are kept
+        let bound = t
+            .fields
+            .iter()
+            .enumerate()
+            .filter(|(i, f)| {
+                *i < t.n_keys
+                    || sub_selections.iter().any(|sub| sub.name == f.name)
+                    || sel.arguments.iter().any(|(arg, _)| *arg == f.name)
+            })
+            .map(|(_, f)| f.name.as_str())
+            .collect_vec();
+        let head = bound
+            .iter()
+            .filter(|name| {
+                t.fields[..t.n_keys].iter().any(|f| f.name == **name)
+                    || sub_selections.iter().any(|sub| sub.name == **name)
+            })
+            .collect_vec();
+        let body = [format!("*{}{{{}}}", t.relation, bound.join(", "))]
+            .into_iter()
+            .chain(filters)
+            .join(", ");
+        let script = format!(
+            "?[{}] := {body} {}",
+            head.iter().join(", "),
+            options.join(" ")
+        );
+        let rows = match &auth.principal {
+            None => db.run_script(&script, params, ScriptMutability::Immutable),
+            Some(principal) => {
+                db.run_script_as(principal, &script, params, ScriptMutability::Immutable)
+            }
+        }?;
+
+        let objects = rows
+            .rows
+            .into_iter()
+            .map(|row| {
+                let object = sub_selections
+                    .iter()
+                    .map(|sub| {
+                        let value = if sub.name == "__typename" {
+                            json!(t.name)
+                        } else {
+                            let i = rows.headers.iter().position(|h| *h == sub.name).unwrap();
+                            Value::from(row[i].clone())
+                        };
+                        (sub.key().to_string(), value)
+                    })
+                    .collect::<Map<_, _>>();
+                Value::Object(object)
+            })
+            .collect_vec();
+        Ok(Value::Array(objects))
+    }
+}
+
+struct Selection {
+    alias: Option<String>,
+    name: String,
+    arguments: Vec<(String, Value)>,
+    selections: Option<Vec<Selection>>,
+}
+
+impl Selection {
+    /// The key of the field in the response.
+    fn key(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Name(String),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Punct(char),
+    Spread,
+}
+
+fn tokenize(src: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = src.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            // commas are insignificant, as whitespace
+            c if c.is_whitespace() || c == ',' || c == '\u{feff}' => {}
+            '#' => {
+                for c in chars.by_ref() {
+                    if c == '\n' || c == '\r' {
+                        break;
+                    }
+                }
+            }
+            '.' => {
+                ensure!(
+                    chars.next() == Some('.') && chars.next() == Some('.'),
+                    "Unexpected '.' in the query"
+                );
+                tokens.push(Token::Spread);
+            }
+            '{' | '}' | '(' | ')' | '[' | ']' | ':' | '$' | '!' | '=' | '@' => {
+                tokens.push(Token::Punct(c))
+            }
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        None | Some('\n') => bail!("Unterminated string in the query"),
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => s.push('\n'),
+                            Some('t') => s.push('\t'),
+                            Some('r') => s.push('\r'),
+                            Some('b') => s.push('\u{8}'),
+                            Some('f') => s.push('\u{c}'),
+                            Some('u') => {
+                                let code: String = chars.by_ref().take(4).collect();
+                                let c = u32::from_str_radix(&code, 16)
+                                    .ok()
+                                    .and_then(char::from_u32)
+                                    .ok_or_else(|| miette!("Bad escape \\u{code} in the query"))?;
+                                s.push(c)
+                            }
+                            Some(c @ ('"' | '\\' | '/')) => s.push(c),
+                            c => bail!("Bad escape {c:?} in the query"),
+                        },
+                        Some(c) => s.push(c),
+                    }
+                }
+                tokens.push(Token::Str(s));
+            }
+            c if c == '-' || c.is_ascii_digit() => {
+                let mut number = c.to_string();
+                while let Some(c) = chars
+                    .next_if(|c| c.is_ascii_alphanumeric() || *c == '.' || *c == '-' || *c == '+')
+                {
+                    number.push(c);
+                }
+                tokens.push(match number.parse::<i64>() {
+                    Ok(i) => Token::Int(i),
+                    Err(_) => Token::Float(
+                        number
+                            .parse()
+                            .map_err(|_| miette!("Bad number {number} in the query"))?,
+                    ),
+                });
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut name = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    name.push(c);
+                }
+                tokens.push(Token::Name(name));
+            }
+            c => bail!("Unexpected character '{c}' in the query"),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    variables: &'a Map<String, Value>,
+    /// The default values of the variables defined by the operation
+    defaults: Map<String, Value>,
+    /// How many selection sets, values and types are being parsed within each other
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+    fn next(&mut self) -> Result<Token> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| miette!("Unexpected end of the query"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+    fn punct(&mut self, c: char) -> bool {
+        if self.peek() == Some(&Token::Punct(c)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+    fn expect(&mut self, c: char) -> Result<()> {
+        ensure!(
+            self.punct(c),
+            "Expected '{c}' in the query, got {:?}",
+            self.peek()
+        );
+        Ok(())
+    }
+    /// Enter a nested selection set, value or type, which is left by decrementing the
+    /// depth once it is parsed.
+    fn nest(&mut self) -> Result<()> {
+        self.depth += 1;
+        ensure!(
+            self.depth <= MAX_DEPTH,
+            "The query is nested more than {MAX_DEPTH} levels deep"
+        );
+        Ok(())
+    }
+    fn name(&mut self) -> Result<String> {
+        match self.next()? {
+            Token::Name(name) => Ok(name),
+            t => bail!("Expected a name in the query, got {t:?}"),
+        }
+    }
+    fn operation(&mut self) -> Result<Vec<Selection>> {
+        if let Some(Token::Name(keyword)) = self.peek() {
+            match keyword.as_str() {
+                "query" => {}
+                "mutation" | "subscription" => bail!("Only queries are supported"),
+                "fragment" => bail!("Fragments are not supported"),
+                k => bail!("Unexpected '{k}' in the query"),
+            }
+            self.pos += 1;
+            if let Some(Token::Name(_)) = self.peek() {
+                self.pos += 1;
+            }
+            if self.punct('(') {
+                while !self.punct(')') {
+                    self.expect('$')?;
+                    let var = self.name()?;
+                    self.expect(':')?;
+                    self.var_type()?;
+                    if self.punct('=') {
+                        let default = self.value()?;
+                        self.defaults.insert(var, default);
+                    }
+                }
+            }
+        }
+        ensure!(
+            self.peek() != Some(&Token::Punct('@')),
+            "Directives are not supported"
+        );
+        let selections = self.selection_set()?;
+        ensure!(
+            self.peek().is_none(),
+            "Only a single operation is supported, without fragments"
+        );
+        Ok(selections)
+    }
+    fn var_type(&mut self) -> Result<()> {
+        self.nest()?;
+        if self.punct('[') {
+            self.var_type()?;
+            self.expect(']')?;
+        } else {
+            self.name()?;
+        }
+        self.punct('!');
+        self.depth -= 1;
+        Ok(())
+    }
+    fn selection_set(&mut self) -> Result<Vec<Selection>> {
+        self.nest()?;
+        self.expect('{')?;
+        let mut selections = vec![];
+        while !self.punct('}') {
+            ensure!(
+                self.peek() != Some(&Token::Spread),
+                "Fragments are not supported"
+            );
+            let mut name = self.name()?;
+            let mut alias = None;
+            if self.punct(':') {
+                alias = Some(name);
+                name = self.name()?;
+            }
+            let mut arguments = vec![];
+            if self.punct('(') {
+                while !self.punct(')') {
+                    let arg = self.name()?;
+                    self.expect(':')?;
+                    arguments.push((arg, self.value()?));
+                }
+            }
+            ensure!(
+                self.peek() != Some(&Token::Punct('@')),
+                "Directives are not supported"
+            );
+            let selections_of_field = if self.peek() == Some(&Token::Punct('{')) {
+                Some(self.selection_set()?)
+            } else {
+                None
+            };
+            selections.push(Selection {
+                alias,
+                name,
+                arguments,
+                selections: selections_of_field,
+            });
+        }
+        self.depth -= 1;
+        Ok(selections)
+    }
+    fn value(&mut self) -> Result<Value> {
+        self.nest()?;
+        let value = match self.next()? {
+            Token::Punct('$') => {
+                let var = self.name()?;
+                match self.variables.get(&var).or_else(|| self.defaults.get(&var)) {
+                    Some(value) => value.clone(),
+                    None => Value::Null,
+                }
+            }
+            Token::Int(i) => json!(i),
+            Token::Float(f) => json!(f),
+            Token::Str(s) => json!(s),
+            Token::Name(name) => match name.as_str() {
+                "true" => json!(true),
+                "false" => json!(false),
+                "null" => Value::Null,
+                // enum values
+                _ => json!(name),
+            },
+            Token::Punct('[') => {
+                let mut items = vec![];
+                while !self.punct(']') {
+                    items.push(self.value()?);
+                }
+                Value::Array(items)
+            }
+            Token::Punct('{') => {
+                let mut fields = Map::new();
+                while !self.punct('}') {
+                    let field = self.name()?;
+                    self.expect(':')?;
+                    fields.insert(field, self.value()?);
+                }
+                Value::Object(fields)
+            }
+            t => bail!("Expected a value in the query, got {t:?}"),
+        };
+        self.depth -= 1;
+        Ok(value)
+    }
+}
+
+fn parse_document(query: &str, variables: &Map<String, Value>) -> Result<Vec<Selection>> {
+    let mut parser = Parser {
+        tokens: tokenize(query)?,
+        pos: 0,
+        variables,
+        defaults: Map::new(),
+        depth: 0,
+    };
+    parser.operation()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nesting_depth() {
+        let variables = Map::new();
+        let too_deep = |query: &str| match parse_document(query, &variables) {
+            Err(err) => err.to_string().contains("nested more than"),
+            Ok(_) => false,
+        };
+        let nested = |open: &str, inner: &str, close: &str, n: usize| {
+            format!("{}{inner}{}", open.repeat(n), close.repeat(n))
+        };
+        let fields = nested("{ a ", "b", " }", MAX_DEPTH);
+        assert!(parse_document(&fields, &variables).is_ok());
+        let fields = nested("{ a ", "b", " }", 100000);
+        assert!(too_deep(&fields));
+
+        let arg = |value: String| format!("{{ a(x: {value}) }}");
+        let list = nested("[", "1", "]", MAX_DEPTH - 2);
+        assert!(parse_document(&arg(list), &variables).is_ok());
+        let list = nested("[", "1", "]", 100000);
+        assert!(too_deep(&arg(list)));
+        let object = nested("{y: ", "1", "}", 100000);
+        assert!(too_deep(&arg(object)));
+
+        let var_type = nested("[", "Int", "]", 100000);
+        let query = format!("query q($v: {var_type}) {{ a }}");
+        assert!(too_deep(&query));
+    }
+}
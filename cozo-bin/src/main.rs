@@ -17,8 +17,11 @@ use crate::repl::{repl_main, ReplArgs};
 use crate::server::{server_main, ServerArgs};
 
 mod client;
+mod graphql;
+mod pg;
 mod repl;
 mod server;
+mod webhook;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -0,0 +1,680 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! A frontend speaking the simple query mode of the PostgreSQL wire protocol, so that
+//! `psql` and other clients can run queries against the server.
+//!
+//! A query starting with `SELECT` is translated from a subset of SQL:
+//!
+//! ```sql
+//! SELECT * | col, ... FROM rel
+//!     [WHERE col op literal [AND ...]]
+//!     [ORDER BY col [ASC | DESC], ...]
+//!     [LIMIT n] [OFFSET n]
+//! ```
+//!
+//! where `op` is one of `=`, `<>`, `!=`, `<`, `<=`, `>`, `>=`, or `IS [NOT] NULL`.
+//! As in CozoScript, the rows returned form a set. `SET` commands are accepted and
+//! ignored, and every other query is run as a CozoScript.
+//!
+//! With a TLS certificate, clients asking for SSL get it, and those that do not are
+//! refused when a password is needed, so that tokens are never sent in the clear.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::thread;
+
+use itertools::Itertools;
+use log::{error, info};
+use miette::{bail, ensure, miette, IntoDiagnostic, Result, WrapErr};
+use rustls::{Certificate, PrivateKey, ServerConfig, ServerConnection, StreamOwned};
+
+use cozo::{DataValue, DbInstance, NamedRows, Num};
+
+use crate::server::{Auth, MyAuth};
+
+const PROTOCOL_VERSION_3: i32 = 196608;
+const SSL_REQUEST: i32 = 80877103;
+const GSSENC_REQUEST: i32 = 80877104;
+const CANCEL_REQUEST: i32 = 80877102;
+
+const BOOL_OID: i32 = 16;
+const INT8_OID: i32 = 20;
+const TEXT_OID: i32 = 25;
+const FLOAT8_OID: i32 = 701;
+
+/// Longest startup message accepted, the same as PostgreSQL itself
+const MAX_STARTUP_LEN: i32 = 10000;
+/// Longest message accepted after the startup
+const MAX_MESSAGE_LEN: i32 = 1 << 26;
+
+/// The TLS configuration of the frontend, from PEM files with the certificate chain
+/// and the private key.
+pub(crate) fn tls_config(cert: &str, key: &str) -> Result<Arc<ServerConfig>> {
+    let open = |path: &str| {
+        File::open(path)
+            .map(std::io::BufReader::new)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("when opening {path}"))
+    };
+    let certs = rustls_pemfile::certs(&mut open(cert)?)
+        .into_diagnostic()?
+        .into_iter()
+        .map(Certificate)
+        .collect_vec();
+    let key = rustls_pemfile::read_all(&mut open(key)?)
+        .into_diagnostic()?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| miette!("no private key in {key}"))?;
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .into_diagnostic()?;
+    Ok(Arc::new(config))
+}
+
+/// Accept connections on `addr` on a background thread, serving each on its own thread.
+/// Connections are encrypted with `tls` when the client asks for SSL.
+pub(crate) fn start_pg_server(
+    addr: &str,
+    db: DbInstance,
+    auth: MyAuth,
+    tls: Option<Arc<ServerConfig>>,
+) -> Result<()> {
+    let listener = TcpListener::bind(addr).into_diagnostic()?;
+    info!("Starting the PostgreSQL frontend at {addr}");
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    error!("cannot accept a PostgreSQL connection: {err}");
+                    continue;
+                }
+            };
+            let db = db.clone();
+            let auth = auth.clone();
+            let tls = tls.clone();
+            thread::spawn(move || {
+                if let Err(err) = serve(stream, db, auth, tls) {
+                    info!("PostgreSQL connection closed: {err}");
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+trait Stream: Read + Write {}
+
+impl<T: Read + Write> Stream for T {}
+
+/// One side of the stream of a connection, which is read and written in turn by the
+/// thread serving it.
+#[derive(Clone)]
+struct Half(Rc<RefCell<Box<dyn Stream>>>);
+
+impl Read for Half {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().read(buf)
+    }
+}
+
+impl Write for Half {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+struct Connection {
+    reader: BufReader<Half>,
+    writer: BufWriter<Half>,
+    encrypted: bool,
+}
+
+impl Connection {
+    fn new(stream: Box<dyn Stream>, encrypted: bool) -> Self {
+        let half = Half(Rc::new(RefCell::new(stream)));
+        Self {
+            reader: BufReader::new(half.clone()),
+            writer: BufWriter::new(half),
+            encrypted,
+        }
+    }
+    fn read_i32(&mut self) -> Result<i32> {
+        let mut buf = [0; 4];
+        self.reader.read_exact(&mut buf).into_diagnostic()?;
+        Ok(i32::from_be_bytes(buf))
+    }
+    /// The body of a message of length `len`, which counts its own four bytes and must
+    /// not be over `max`.
+    fn read_body(&mut self, len: i32, max: i32) -> Result<Vec<u8>> {
+        ensure!((4..=max).contains(&len), "bad message length {len}");
+        let mut buf = vec![0; len as usize - 4];
+        self.reader.read_exact(&mut buf).into_diagnostic()?;
+        Ok(buf)
+    }
+    /// The type and body of the next message, `None` at the end of the stream.
+    fn read_message(&mut self) -> Result<Option<(u8, Vec<u8>)>> {
+        let mut tag = [0];
+        if self.reader.read(&mut tag).into_diagnostic()? == 0 {
+            return Ok(None);
+        }
+        let len = self.read_i32()?;
+        Ok(Some((tag[0], self.read_body(len, MAX_MESSAGE_LEN)?)))
+    }
+    fn send(&mut self, tag: u8, body: &[u8]) -> Result<()> {
+        self.writer.write_all(&[tag]).into_diagnostic()?;
+        self.writer
+            .write_all(&(body.len() as i32 + 4).to_be_bytes())
+            .into_diagnostic()?;
+        self.writer.write_all(body).into_diagnostic()
+    }
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush().into_diagnostic()
+    }
+    fn send_error(&mut self, code: &str, message: &str) -> Result<()> {
+        let mut body = vec![];
+        for (field, value) in [
+            (b'S', "ERROR"),
+            (b'V', "ERROR"),
+            (b'C', code),
+            (b'M', message),
+        ] {
+            body.push(field);
+            put_str(&mut body, value);
+        }
+        body.push(0);
+        self.send(b'E', &body)
+    }
+    fn send_ready(&mut self) -> Result<()> {
+        self.send(b'Z', b"I")?;
+        self.flush()
+    }
+    fn send_rows(&mut self, rows: NamedRows) -> Result<()> {
+        let mut description = (rows.headers.len() as i16).to_be_bytes().to_vec();
+        for (i, header) in rows.headers.iter().enumerate() {
+            put_str(&mut description, header);
+            description.extend_from_slice(&0i32.to_be_bytes());
+            description.extend_from_slice(&0i16.to_be_bytes());
+            description.extend_from_slice(&column_type(&rows.rows, i).to_be_bytes());
+            description.extend_from_slice(&(-1i16).to_be_bytes());
+            description.extend_from_slice(&(-1i32).to_be_bytes());
+            description.extend_from_slice(&0i16.to_be_bytes());
+        }
+        self.send(b'T', &description)?;
+        for row in &rows.rows {
+            let mut data = (row.len() as i16).to_be_bytes().to_vec();
+            for value in row {
+                match as_text(value) {
+                    None => data.extend_from_slice(&(-1i32).to_be_bytes()),
+                    Some(text) => {
+                        data.extend_from_slice(&(text.len() as i32).to_be_bytes());
+                        data.extend_from_slice(text.as_bytes());
+                    }
+                }
+            }
+            self.send(b'D', &data)?;
+        }
+        let mut tag = vec![];
+        put_str(&mut tag, &format!("SELECT {}", rows.rows.len()));
+        self.send(b'C', &tag)
+    }
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(s.as_bytes());
+    buf.push(0);
+}
+
+/// The type of column `i`, from the values it holds.
+fn column_type(rows: &[Vec<DataValue>], i: usize) -> i32 {
+    let mut values = rows
+        .iter()
+        .map(|row| &row[i])
+        .filter(|v| **v != DataValue::Null);
+    let Some(first) = values.next() else {
+        return TEXT_OID;
+    };
+    let oid = match first {
+        DataValue::Bool(_) => BOOL_OID,
+        DataValue::Num(Num::Int(_)) => INT8_OID,
+        DataValue::Num(Num::Float(_)) => FLOAT8_OID,
+        _ => return TEXT_OID,
+    };
+    values.fold(oid, |oid, value| match (oid, value) {
+        (BOOL_OID, DataValue::Bool(_)) => BOOL_OID,
+        (INT8_OID, DataValue::Num(Num::Int(_))) => INT8_OID,
+        (INT8_OID | FLOAT8_OID, DataValue::Num(_)) => FLOAT8_OID,
+        _ => TEXT_OID,
+    })
+}
+
+/// The text format of `value`, `None` for null. Values with no counterpart in
+/// PostgreSQL are sent as their JSON.
+fn as_text(value: &DataValue) -> Option<String> {
+    Some(match value {
+        DataValue::Null => return None,
+        DataValue::Bool(b) => if *b { "t" } else { "f" }.to_string(),
+        DataValue::Num(Num::Int(i)) => i.to_string(),
+        DataValue::Num(Num::Float(f)) => f.to_string(),
+        DataValue::Str(s) => s.to_string(),
+        v => serde_json::Value::from(v.clone()).to_string(),
+    })
+}
+
+/// Read the startup message, once SSL has been set up with `tls` or declined, and
+/// GSSAPI encryption declined. Returns `false` if the connection is only for
+/// cancelling a query.
+fn startup(
+    conn: &mut Connection,
+    stream: &TcpStream,
+    tls: &Option<Arc<ServerConfig>>,
+) -> Result<bool> {
+    loop {
+        let len = conn.read_i32()?;
+        let body = conn.read_body(len, MAX_STARTUP_LEN)?;
+        ensure!(body.len() >= 4, "bad startup message");
+        let code = i32::from_be_bytes(body[..4].try_into().unwrap());
+        match code {
+            SSL_REQUEST if !conn.encrypted && tls.is_some() => {
+                conn.writer.write_all(b"S").into_diagnostic()?;
+                conn.flush()?;
+                // the client only starts the handshake once answered, so nothing is
+                // left in the buffer of the plain connection
+                ensure!(conn.reader.buffer().is_empty(), "data sent before the TLS handshake");
+                let tls = ServerConnection::new(tls.clone().unwrap()).into_diagnostic()?;
+                let stream = stream.try_clone().into_diagnostic()?;
+                *conn = Connection::new(Box::new(StreamOwned::new(tls, stream)), true);
+            }
+            SSL_REQUEST | GSSENC_REQUEST => {
+                conn.writer.write_all(b"N").into_diagnostic()?;
+                conn.flush()?;
+            }
+            CANCEL_REQUEST => return Ok(false),
+            // the parameters, such as the user and the database, are ignored
+            PROTOCOL_VERSION_3 => return Ok(true),
+            _ => {
+                conn.send_error("08P01", &format!("unsupported protocol version {code}"))?;
+                conn.flush()?;
+                bail!("unsupported protocol version {code}")
+            }
+        }
+    }
+}
+
+/// Ask for a password when the server requires a token, which is then the password.
+/// The password is only asked for over SSL when the server has a certificate.
+fn authenticate(conn: &mut Connection, auth: &MyAuth, tls_required: bool) -> Result<Option<Auth>> {
+    if let Some(auth) = auth.without_token() {
+        return Ok(Some(auth));
+    }
+    if tls_required && !conn.encrypted {
+        conn.send_error("28000", "SSL is required to authenticate with a password")?;
+        conn.flush()?;
+        bail!("password authentication refused without SSL")
+    }
+    conn.send(b'R', &3i32.to_be_bytes())?;
+    conn.flush()?;
+    let password = match conn.read_message()? {
+        Some((b'p', body)) => String::from_utf8_lossy(&body)
+            .trim_end_matches('\0')
+            .to_string(),
+        _ => return Ok(None),
+    };
+    Ok(auth.authorize_token(&password))
+}
+
+fn serve(
+    stream: TcpStream,
+    db: DbInstance,
+    auth: MyAuth,
+    tls: Option<Arc<ServerConfig>>,
+) -> Result<()> {
+    let mut conn = Connection::new(Box::new(stream.try_clone().into_diagnostic()?), false);
+    if !startup(&mut conn, &stream, &tls)? {
+        return Ok(());
+    }
+    let Some(auth) = authenticate(&mut conn, &auth, tls.is_some())? else {
+        conn.send_error("28P01", "password authentication failed")?;
+        return conn.flush();
+    };
+    conn.send(b'R', &0i32.to_be_bytes())?;
+    for (name, value) in [
+        ("server_version", "14.0"),
+        ("server_encoding", "UTF8"),
+        ("client_encoding", "UTF8"),
+        ("DateStyle", "ISO"),
+        ("integer_datetimes", "on"),
+        ("standard_conforming_strings", "on"),
+    ] {
+        let mut body = vec![];
+        put_str(&mut body, name);
+        put_str(&mut body, value);
+        conn.send(b'S', &body)?;
+    }
+    let mut key = rand::random::<u64>().to_be_bytes().to_vec();
+    key[..4].copy_from_slice(&std::process::id().to_be_bytes());
+    conn.send(b'K', &key)?;
+    conn.send_ready()?;
+
+    // set by a message of the extended query protocol, until the next sync
+    let mut extended_failed = false;
+    while let Some((tag, body)) = conn.read_message()? {
+        match tag {
+            b'Q' => {
+                let query = String::from_utf8_lossy(&body)
+                    .trim_end_matches('\0')
+                    .to_string();
+                if query.trim().is_empty() {
+                    conn.send(b'I', &[])?;
+                } else if starts_with_keyword(&query, "set") {
+                    let mut tag = vec![];
+                    put_str(&mut tag, "SET");
+                    conn.send(b'C', &tag)?;
+                } else {
+                    match run_query(&db, &auth, &query) {
+                        Ok(rows) => conn.send_rows(rows)?,
+                        Err(err) => conn.send_error("XX000", &err.to_string())?,
+                    }
+                }
+                conn.send_ready()?;
+            }
+            b'X' => return Ok(()),
+            b'S' => {
+                extended_failed = false;
+                conn.send_ready()?;
+            }
+            b'P' | b'B' | b'D' | b'E' | b'C' | b'H' | b'F' => {
+                if !extended_failed {
+                    extended_failed = true;
+                    conn.send_error("0A000", "only the simple query protocol is supported")?;
+                    conn.flush()?;
+                }
+            }
+            _ => {
+                conn.send_error("08P01", &format!("unexpected message type {tag}"))?;
+                conn.flush()?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn starts_with_keyword(query: &str, keyword: &str) -> bool {
+    let query = query.trim_start();
+    query
+        .get(..keyword.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(keyword))
+        && !query[keyword.len()..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn run_query(db: &DbInstance, auth: &Auth, query: &str) -> Result<NamedRows> {
+    let run = |script: &str, params| match &auth.principal {
+        None => db.run_script(script, params, auth.mutability),
+        Some(principal) => db.run_script_as(principal, script, params, auth.mutability),
+    };
+    let (script, params) = if starts_with_keyword(query, "select") {
+        translate_select(&tokenize(query)?, |rel| {
+            let columns = run(&format!("::columns {rel}"), BTreeMap::new())?;
+            Ok(columns
+                .rows
+                .iter()
+                .filter_map(|row| row[0].get_str().map(|s| s.to_string()))
+                .collect())
+        })?
+    } else {
+        (query.to_string(), BTreeMap::new())
+    };
+    Ok(run(&script, params)?)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// A keyword or an identifier that is not quoted
+    Word(String),
+    QuotedIdent(String),
+    Str(String),
+    Number(String),
+    Symbol(&'static str),
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>> {
+    const SYMBOLS: [&str; 12] = [
+        "<>", "!=", "<=", ">=", "=", "<", ">", ",", "*", "(", ")", ";",
+    ];
+    let mut tokens = vec![];
+    let mut rest = query;
+    loop {
+        rest = rest.trim_start();
+        let Some(c) = rest.chars().next() else {
+            return Ok(tokens);
+        };
+        if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Word(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else if c.is_ascii_digit()
+            || (c == '-' && rest[1..].starts_with(|c: char| c.is_ascii_digit()))
+        {
+            let end = rest[1..]
+                .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == 'e' || c == 'E'))
+                .map_or(rest.len(), |i| i + 1);
+            tokens.push(Token::Number(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else if c == '\'' || c == '"' {
+            // a doubled quote stands for the quote itself
+            let mut value = String::new();
+            let mut chars = rest[1..].char_indices();
+            let end = loop {
+                match chars.next() {
+                    None => bail!("unterminated quote in {rest}"),
+                    Some((i, q)) if q == c => {
+                        if rest[i + 2..].starts_with(c) {
+                            value.push(c);
+                            chars.next();
+                        } else {
+                            break i + 2;
+                        }
+                    }
+                    Some((_, other)) => value.push(other),
+                }
+            };
+            tokens.push(if c == '\'' {
+                Token::Str(value)
+            } else {
+                Token::QuotedIdent(value)
+            });
+            rest = &rest[end..];
+        } else {
+            let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(**s)) else {
+                bail!("unexpected character '{c}' in SQL")
+            };
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        }
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos)
+    }
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token
+    }
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword) => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+    fn expect_keyword(&mut self, keyword: &str) -> Result<()> {
+        ensure!(self.keyword(keyword), "expected {keyword} in SQL");
+        Ok(())
+    }
+    fn symbol(&mut self, symbol: &'static str) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+    fn ident(&mut self) -> Result<String> {
+        match self.next() {
+            Some(Token::Word(w)) | Some(Token::QuotedIdent(w)) => Ok(w.clone()),
+            t => bail!("expected an identifier in SQL, got {t:?}"),
+        }
+    }
+    fn literal(&mut self) -> Result<DataValue> {
+        Ok(match self.next() {
+            Some(Token::Str(s)) => DataValue::from(s.as_str()),
+            Some(Token::Number(n)) => match n.parse::<i64>() {
+                Ok(i) => DataValue::from(i),
+                Err(_) => DataValue::from(n.parse::<f64>().into_diagnostic()?),
+            },
+            Some(Token::Word(w)) if w.eq_ignore_ascii_case("true") => DataValue::from(true),
+            Some(Token::Word(w)) if w.eq_ignore_ascii_case("false") => DataValue::from(false),
+            Some(Token::Word(w)) if w.eq_ignore_ascii_case("null") => DataValue::Null,
+            t => bail!("expected a literal in SQL, got {t:?}"),
+        })
+    }
+    fn unsigned(&mut self) -> Result<u64> {
+        match self.next() {
+            Some(Token::Number(n)) => n
+                .parse()
+                .map_err(|_| miette!("expected a non-negative integer in SQL, got {n}")),
+            t => bail!("expected a non-negative integer in SQL, got {t:?}"),
+        }
+    }
+}
+
+/// Translate a `SELECT` into a script and its parameters, `columns` giving the columns
+/// of a relation.
+fn translate_select(
+    tokens: &[Token],
+    columns: impl Fn(&str) -> Result<Vec<String>>,
+) -> Result<(String, BTreeMap<String, DataValue>)> {
+    let mut p = Parser { tokens, pos: 0 };
+    p.expect_keyword("select")?;
+    let projection = if p.peek() == Some(&Token::Symbol("*")) {
+        p.next();
+        None
+    } else {
+        let mut cols = vec![p.ident()?];
+        while p.symbol(",") {
+            cols.push(p.ident()?);
+        }
+        Some(cols)
+    };
+    p.expect_keyword("from")?;
+    let relation = p.ident()?;
+    let head = match projection {
+        Some(cols) => cols,
+        None => columns(&relation)?,
+    };
+
+    let mut bound = head.clone();
+    let mut conditions = vec![];
+    let mut params = BTreeMap::new();
+    if p.keyword("where") {
+        loop {
+            let col = p.ident()?;
+            let condition = if p.keyword("is") {
+                let negated = p.keyword("not");
+                p.expect_keyword("null")?;
+                if negated {
+                    format!("!is_null({col})")
+                } else {
+                    format!("is_null({col})")
+                }
+            } else {
+                let op = match p.next() {
+                    Some(Token::Symbol("=")) => "==",
+                    Some(Token::Symbol("<>" | "!=")) => "!=",
+                    Some(Token::Symbol(op @ ("<" | "<=" | ">" | ">="))) => op,
+                    t => bail!("expected a comparison in SQL, got {t:?}"),
+                };
+                let param = format!("p{}", params.len());
+                params.insert(param.clone(), p.literal()?);
+                // comparisons with null never hold in SQL, but fail in CozoScript
+                format!("!is_null({col}), {col} {op} ${param}")
+            };
+            if !bound.contains(&col) {
+                bound.push(col);
+            }
+            conditions.push(condition);
+            if !p.keyword("and") {
+                break;
+            }
+        }
+    }
+    let mut options = vec![];
+    if p.keyword("order") {
+        p.expect_keyword("by")?;
+        let mut sorts = vec![];
+        loop {
+            let col = p.ident()?;
+            if p.keyword("desc") {
+                sorts.push(format!("-{col}"));
+            } else {
+                p.keyword("asc");
+                sorts.push(col);
+            }
+            if !p.symbol(",") {
+                break;
+            }
+        }
+        options.push(format!(":order {}", sorts.join(", ")));
+    }
+    if p.keyword("limit") {
+        options.push(format!(":limit {}", p.unsigned()?));
+    }
+    if p.keyword("offset") {
+        options.push(format!(":offset {}", p.unsigned()?));
+    }
+    p.symbol(";");
+    if let Some(t) = p.peek() {
+        bail!("unsupported SQL from {t:?}, only a subset of SELECT is supported")
+    }
+
+    let body = [format!("*{relation}{{{}}}", bound.join(", "))]
+        .into_iter()
+        .chain(conditions)
+        .join(", ");
+    let script = format!("?[{}] := {body} {}", head.join(", "), options.join(" "));
+    Ok((script, params))
+}
@@ -14,13 +14,14 @@ use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::thread;
+use std::time::{Duration, Instant};
 // use std::thread;
 
 use axum::body::{boxed, Body, BoxBody};
 use axum::extract::{DefaultBodyLimit, Path, Query, State};
-use axum::http::{header, HeaderName, Method, Request, Response, StatusCode};
+use axum::http::{header, HeaderMap, HeaderName, Method, Request, Response, StatusCode};
 use axum::response::sse::{Event, KeepAlive};
-use axum::response::{Html, Sse};
+use axum::response::{Html, IntoResponse, Sse};
 use axum::routing::{get, post, put};
 use axum::{Extension, Json, Router};
 use clap::Args;
@@ -37,7 +38,11 @@ use tower_http::auth::{AsyncAuthorizeRequest, AsyncRequireAuthorizationLayer};
 use tower_http::compression::CompressionLayer;
 use tower_http::cors::{Any, CorsLayer};
 
-use cozo::{DataValue, DbInstance, format_error_as_json, MultiTransaction, NamedRows, ScriptMutability, SimpleFixedRule};
+use axum_server::tls_rustls::RustlsConfig;
+use crate::graphql::Schema;
+use crate::pg::{start_pg_server, tls_config};
+use crate::webhook::start_webhooks;
+use cozo::{DataValue, DbInstance, format_error_as_json, MultiTransaction, NamedRows, PlanLimits, Principal, ResultFormat, ResultSerializer, ScriptMutability, SimpleFixedRule};
 
 #[derive(Args, Debug)]
 pub(crate) struct ServerArgs {
@@ -65,9 +70,56 @@ pub(crate) struct ServerArgs {
     #[clap(short = 'P', long, default_value_t = 9070)]
     port: u16,
 
-    /// When set, the content of the named table will be used as a token table
+    /// When set, the content of the named table will be used as a token table. Its rows have
+    /// a `token` and a boolean `mutable` column, and optionally a `role` and a `name` column
+    /// giving the role and the principal that scripts authorized by the token run for.
+    /// Tokens are required even when bound to 127.0.0.1.
     #[clap(long)]
     token_table: Option<String>,
+
+    /// PEM file with the certificate chain to serve HTTPS with
+    #[clap(long, requires = "tls_key")]
+    tls_cert: Option<String>,
+
+    /// PEM file with the private key of the certificate given by `--tls-cert`
+    #[clap(long, requires = "tls_cert")]
+    tls_key: Option<String>,
+
+    /// Serve the database metrics in the Prometheus format at `/metrics`
+    #[clap(long)]
+    metrics: bool,
+
+    /// JSON file listing webhooks to POST the changes of every committed transaction to,
+    /// as `[{"url": ..., "relations": [...], "max_attempts": ...}]`. Without `relations`,
+    /// changes to all relations are sent; without `max_attempts`, failed deliveries are
+    /// retried forever. Enables the transaction log of the database.
+    #[clap(long)]
+    webhooks: Option<String>,
+
+    /// Seconds after which a cursor opened by `/cursor` that is not fetched from is closed
+    #[clap(long, default_value_t = 300)]
+    cursor_idle_timeout: u64,
+
+    /// Also serve the PostgreSQL wire protocol on this port, for `psql` and other clients.
+    /// Only the simple query protocol is supported, with queries being CozoScript or
+    /// a subset of SQL `SELECT`. When a token is required, it is given as the password,
+    /// and with `--tls-cert` it is only accepted over SSL.
+    #[clap(long)]
+    pg_port: Option<u16>,
+
+    /// Reject queries estimated to return more rows than this, unless they have the
+    /// option `:disable_plan_limits`
+    #[clap(long, default_value_t = 1e6)]
+    max_plan_rows: f64,
+
+    /// Reject queries whose scans and joins are estimated to produce more rows than this,
+    /// unless they have the option `:disable_plan_limits`
+    #[clap(long, default_value_t = 1e8)]
+    max_plan_cost: f64,
+
+    /// Do not check the plans of queries against `--max-plan-rows` and `--max-plan-cost`
+    #[clap(long)]
+    no_plan_limits: bool,
 }
 
 #[derive(Clone)]
@@ -75,15 +127,139 @@ struct DbState {
     db: DbInstance,
     rule_senders: Arc<Mutex<BTreeMap<u32, crossbeam::channel::Sender<miette::Result<NamedRows>>>>>,
     rule_counter: Arc<AtomicU32>,
-    tx_counter: Arc<AtomicU32>,
-    txs: Arc<Mutex<BTreeMap<u32, Arc<MultiTransaction>>>>,
+    txs: Arc<Mutex<BTreeMap<String, OpenTransaction>>>,
+    cursor_counter: Arc<AtomicU32>,
+    cursors: Arc<Mutex<BTreeMap<u32, Cursor>>>,
+}
+
+/// A transaction started through `/transact`, usable only by the principal that started it.
+struct OpenTransaction {
+    tx: Arc<MultiTransaction>,
+    principal: Option<Principal>,
+}
+
+/// The rows of a query not yet fetched through `/cursor/:id`.
+struct Cursor {
+    rows: std::vec::IntoIter<Vec<DataValue>>,
+    last_used: Instant,
 }
 
 #[derive(Clone)]
-struct MyAuth {
+pub(crate) struct MyAuth {
     skip_auth: bool,
     auth_guard: String,
-    token_table: Option<Arc<(String, DbInstance)>>,
+    token_table: Option<Arc<TokenTable>>,
+}
+
+/// What a request is authorized to do, inserted into its extensions.
+#[derive(Clone)]
+pub(crate) struct Auth {
+    pub(crate) mutability: ScriptMutability,
+    /// Set for requests authorized by the token table
+    pub(crate) principal: Option<Principal>,
+}
+
+struct TokenTable {
+    /// Query returning the `mutable`, `role` and `name` of `$token`
+    query: String,
+    db: DbInstance,
+}
+
+impl TokenTable {
+    fn new(name: &str, db: DbInstance) -> Self {
+        // the `role` and `name` columns are optional
+        let columns = db
+            .run_default(&format!("::columns {name}"))
+            .map(|cols| {
+                cols.rows
+                    .into_iter()
+                    .filter_map(|row| row[0].get_str().map(|s| s.to_string()))
+                    .collect_vec()
+            })
+            .unwrap_or_default();
+        let mut bindings = vec!["token: $token", "mutable"];
+        let mut absent = vec![];
+        for col in ["role", "name"] {
+            if columns.iter().any(|c| c == col) {
+                bindings.push(col);
+            } else {
+                absent.push(format!(", {col} = null"));
+            }
+        }
+        let query = format!(
+            "?[mutable, role, name] := *{name} {{ {} }}{}",
+            bindings.join(", "),
+            absent.join("")
+        );
+        Self { query, db }
+    }
+
+    fn authorize(&self, token: &str) -> Option<Auth> {
+        match self.db.run_script(
+            &self.query,
+            BTreeMap::from([(String::from("token"), DataValue::from(token))]),
+            ScriptMutability::Immutable,
+        ) {
+            Ok(rows) => rows.rows.first().map(|row| {
+                let mutability = if row[0].get_bool() == Some(true) {
+                    ScriptMutability::Mutable
+                } else {
+                    ScriptMutability::Immutable
+                };
+                let principal = match (row[1].get_str(), row[2].get_str()) {
+                    (None, None) => None,
+                    (role, name) => {
+                        let principal = Principal::new(name.unwrap_or("token"));
+                        Some(match role {
+                            None => principal,
+                            Some(role) => principal.with_role(role),
+                        })
+                    }
+                };
+                Auth {
+                    mutability,
+                    principal,
+                }
+            }),
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                None
+            }
+        }
+    }
+}
+
+impl MyAuth {
+    /// The authorization of clients when no token is required.
+    pub(crate) fn without_token(&self) -> Option<Auth> {
+        self.skip_auth.then_some(Auth {
+            mutability: ScriptMutability::Mutable,
+            principal: None,
+        })
+    }
+    /// The authorization given by `token`, either the auth token of the server or a token
+    /// of the token table.
+    pub(crate) fn authorize_token(&self, token: &str) -> Option<Auth> {
+        if !self.auth_guard.is_empty() && token == self.auth_guard {
+            return Some(Auth {
+                mutability: ScriptMutability::Mutable,
+                principal: None,
+            });
+        }
+        self.token_table.as_ref()?.authorize(token)
+    }
+}
+
+/// Routes open to tokens with a role, the others could bypass its grants.
+fn allowed_for_role(path: &str) -> bool {
+    path == "/text-query"
+        || path == "/script"
+        || path == "/cursor"
+        || path == "/graphql"
+        || path.starts_with("/cursor/")
+        || path.starts_with("/call/")
+        || path == "/transact"
+        || path.starts_with("/transact/")
 }
 
 impl<B> AsyncAuthorizeRequest<B> for MyAuth
@@ -100,95 +276,61 @@ impl<B> AsyncAuthorizeRequest<B> for MyAuth
         let token_table = self.token_table.clone();
         Box::pin(async move {
             if skip_auth {
-                request.extensions_mut().insert(ScriptMutability::Mutable);
+                request.extensions_mut().insert(Auth {
+                    mutability: ScriptMutability::Mutable,
+                    principal: None,
+                });
                 return Ok(request);
             }
 
-            let mutability = match request.headers().get("x-cozo-auth") {
-                None => match request.uri().query() {
-                    Some(q_str) => {
-                        let mut bingo = false;
-                        for pair in q_str.split('&') {
-                            if let Some((k, v)) = pair.split_once('=') {
-                                if k == "auth" {
-                                    if v == auth_guard.as_str() {
-                                        bingo = true
-                                    }
-                                    break;
-                                }
-                            }
-                        }
-                        if bingo {
-                            Some(ScriptMutability::Mutable)
-                        } else {
-                            None
-                        }
-                    }
-                    None => match token_table {
-                        None => None,
-                        Some(tt) => {
-                            let (name, db) = tt.as_ref();
-                            if let Some(auth_header) = request.headers().get("Authorization") {
-                                if let Ok(auth_str) = auth_header.to_str() {
-                                    if let Some(token) = auth_str.strip_prefix("Bearer ") {
-                                        match db.run_script(
-                                            &format!("?[mutable] := *{name} {{ token: $token, mutable }}"),
-                                            BTreeMap::from([(String::from("token"), DataValue::from(token))]),
-                                            ScriptMutability::Immutable,
-                                        ) {
-                                            Ok(rows) => match rows.rows.first() {
-                                                None => None,
-                                                Some(val) => {
-                                                    if val[0].get_bool() == Some(true) {
-                                                        Some(ScriptMutability::Mutable)
-                                                    } else {
-                                                        Some(ScriptMutability::Immutable)
-                                                    }
-                                                }
-                                            },
-                                            Err(err) => {
-                                                eprintln!("Error: {}", err);
-                                                None
-                                            }
-                                        }
-                                    } else {
-                                        None
-                                    }
-                                } else {
-                                    None
-                                }
-                            } else {
-                                None
-                            }
-                        }
-                    },
-                },
-                Some(data) => match data.to_str() {
-                    Ok(s) => {
-                        if s == auth_guard.as_str() {
-                            Some(ScriptMutability::Mutable)
-                        } else {
-                            None
-                        }
-                    }
-                    Err(_) => None,
-                },
+            let guarded = match request.headers().get("x-cozo-auth") {
+                None => request.uri().query().is_some_and(|q_str| {
+                    q_str.split('&').any(|pair| match pair.split_once('=') {
+                        Some((k, v)) => k == "auth" && v == auth_guard.as_str(),
+                        None => false,
+                    })
+                }),
+                Some(data) => data.to_str().ok() == Some(auth_guard.as_str()),
             };
-            if let Some(mutability) = mutability {
-                request.extensions_mut().insert(mutability);
-                Ok(request)
-            } else {
-                let unauthorized_response = Response::builder()
-                    .status(StatusCode::UNAUTHORIZED)
-                    .body(boxed(Body::empty()))
-                    .unwrap();
+            if guarded {
+                request.extensions_mut().insert(Auth {
+                    mutability: ScriptMutability::Mutable,
+                    principal: None,
+                });
+                return Ok(request);
+            }
 
-                Err(unauthorized_response)
+            let token = request
+                .headers()
+                .get("Authorization")
+                .and_then(|h| h.to_str().ok())
+                .and_then(|s| s.strip_prefix("Bearer "));
+            let auth = match (token_table, token) {
+                (Some(tt), Some(token)) => tt.authorize(token),
+                _ => None,
+            };
+            match auth {
+                None => Err(status_response(StatusCode::UNAUTHORIZED)),
+                Some(auth) => {
+                    let restricted = auth.principal.as_ref().is_some_and(|p| p.role.is_some());
+                    if restricted && !allowed_for_role(request.uri().path()) {
+                        return Err(status_response(StatusCode::FORBIDDEN));
+                    }
+                    request.extensions_mut().insert(auth);
+                    Ok(request)
+                }
             }
         })
     }
 }
 
+fn status_response(status: StatusCode) -> Response<BoxBody> {
+    Response::builder()
+        .status(status)
+        .body(boxed(Body::empty()))
+        .unwrap()
+}
+
 #[test]
 fn x() {}
 
@@ -201,8 +343,23 @@ pub(crate) async fn server_main(args: ServerArgs) {
             panic!()
         }
     }
+    if !args.no_plan_limits {
+        db.set_plan_limits(PlanLimits {
+            max_cost: Some(args.max_plan_cost),
+            max_rows: Some(args.max_plan_rows),
+        });
+    }
+
+    if let Some(config) = &args.webhooks {
+        let cursor_path = format!("{}.{}.webhooks", args.path, args.engine);
+        if let Err(err) = start_webhooks(&db, config, cursor_path) {
+            error!("{}", err);
+            error!("Starting the webhooks failed, terminate");
+            panic!()
+        }
+    }
 
-    let skip_auth = args.bind == "127.0.0.1";
+    let skip_auth = args.bind == "127.0.0.1" && args.token_table.is_none();
 
     let conf_path = if skip_auth {
         "".to_string()
@@ -229,23 +386,88 @@ pub(crate) async fn server_main(args: ServerArgs) {
     let auth_obj = MyAuth {
         skip_auth,
         auth_guard,
-        token_table: args.token_table.map(|t| Arc::new((t, db.clone()))),
+        token_table: args
+            .token_table
+            .as_ref()
+            .map(|t| Arc::new(TokenTable::new(t, db.clone()))),
     };
 
+    if let Some(port) = args.pg_port {
+        let addr = if Ipv6Addr::from_str(&args.bind).is_ok() {
+            format!("[{}]:{}", args.bind, port)
+        } else {
+            format!("{}:{}", args.bind, port)
+        };
+        let tls = match (&args.tls_cert, &args.tls_key) {
+            (Some(cert), Some(key)) => match tls_config(cert, key) {
+                Ok(config) => Some(config),
+                Err(err) => {
+                    error!("{:?}", err);
+                    error!("Loading the TLS certificate failed, terminate");
+                    panic!()
+                }
+            },
+            _ => None,
+        };
+        if let Err(err) = start_pg_server(&addr, db.clone(), auth_obj.clone(), tls) {
+            error!("{}", err);
+            error!("Starting the PostgreSQL frontend failed, terminate");
+            panic!()
+        }
+    }
+
     let state = DbState {
         db,
         rule_senders: Default::default(),
         rule_counter: Default::default(),
-        tx_counter: Default::default(),
         txs: Default::default(),
+        cursor_counter: Default::default(),
+        cursors: Default::default(),
     };
+    {
+        let cursors = state.cursors.clone();
+        let idle_timeout = Duration::from_secs(args.cursor_idle_timeout);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(
+                idle_timeout.clamp(Duration::from_secs(1), Duration::from_secs(10)),
+            );
+            loop {
+                interval.tick().await;
+                cursors
+                    .lock()
+                    .unwrap()
+                    .retain(|_, cursor| cursor.last_used.elapsed() < idle_timeout);
+            }
+        });
+    }
     let cors = CorsLayer::new()
         .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
         .allow_origin(Any)
         .allow_headers([header::CONTENT_TYPE, HeaderName::from_static("x-cozo-auth")]);
 
-    let app = Router::new()
+    let mut routes = Router::new();
+    if args.metrics {
+        routes = routes.route("/metrics", get(metrics));
+    }
+    #[cfg(feature = "wasm-plugins")]
+    {
+        routes = routes.route(
+            "/plugins/:name",
+            put(register_plugin).delete(unregister_plugin),
+        );
+    }
+    let app = routes
         .route("/text-query", post(text_query))
+        .route("/script", post(run_statements))
+        .route("/cursor", post(open_cursor))
+        .route("/procedures", get(list_procedures))
+        .route(
+            "/procedures/:name",
+            put(register_procedure).delete(unregister_procedure),
+        )
+        .route("/call/:name", post(call_procedure))
+        .route("/cursor/:id", get(fetch_cursor).delete(close_cursor))
+        .route("/graphql", get(graphql_schema).post(graphql_query))
         .route("/export/:relations", get(export_relations))
         .route("/import", put(import_relations))
         .route("/backup", post(backup))
@@ -272,20 +494,43 @@ pub(crate) async fn server_main(args: ServerArgs) {
         SocketAddr::from_str(&format!("{}:{}", args.bind, args.port)).unwrap()
     };
 
-    if args.bind != "127.0.0.1" {
-        warn!("{}", include_str!("./security.txt"));
+    if !skip_auth {
+        if args.bind != "127.0.0.1" {
+            warn!("{}", include_str!("./security.txt"));
+        }
         info!("The auth token is in the file: {conf_path}");
     }
 
-    info!(
-        "Starting Cozo ({}-backed) API at http://{}",
-        args.engine, addr
-    );
-
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+    match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => {
+            let config = match RustlsConfig::from_pem_file(cert, key).await {
+                Ok(config) => config,
+                Err(err) => {
+                    error!("{}", err);
+                    error!("Loading the TLS certificate failed, terminate");
+                    panic!()
+                }
+            };
+            info!(
+                "Starting Cozo ({}-backed) API at https://{}",
+                args.engine, addr
+            );
+            axum_server::bind_rustls(addr, config)
+                .serve(app.into_make_service())
+                .await
+                .unwrap();
+        }
+        _ => {
+            info!(
+                "Starting Cozo ({}-backed) API at http://{}",
+                args.engine, addr
+            );
+            axum::Server::bind(&addr)
+                .serve(app.into_make_service())
+                .await
+                .unwrap();
+        }
+    }
 }
 
 #[derive(serde_derive::Deserialize)]
@@ -294,23 +539,54 @@ struct StartTransactPayload {
 }
 
 async fn start_transact(
+    Extension(auth): Extension<Auth>,
     State(st): State<DbState>,
     Query(payload): Query<StartTransactPayload>,
 ) -> (StatusCode, Json<serde_json::Value>) {
-    let tx = st.db.multi_transaction(payload.write);
-    let id = st.tx_counter.fetch_add(1, Ordering::SeqCst);
-    st.txs.lock().unwrap().insert(id, Arc::new(tx));
+    if payload.write && auth.mutability == ScriptMutability::Immutable {
+        return (
+            StatusCode::FORBIDDEN,
+            json!({"ok": false, "message": "the token cannot write"}).into(),
+        );
+    }
+    let tx = match &auth.principal {
+        None => st.db.multi_transaction(payload.write),
+        Some(principal) => st.db.multi_transaction_as(principal, payload.write),
+    };
+    // ids cannot be guessed, so that a transaction is only reachable by whoever started it
+    let id: String = rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect();
+    st.txs.lock().unwrap().insert(
+        id.clone(),
+        OpenTransaction {
+            tx: Arc::new(tx),
+            principal: auth.principal,
+        },
+    );
     (StatusCode::OK, json!({"ok": true, "id": id}).into())
 }
 
+/// The response to a request for a transaction started by another principal.
+fn not_own_transaction() -> (StatusCode, Json<serde_json::Value>) {
+    (
+        StatusCode::FORBIDDEN,
+        json!({"ok": false, "message": "the transaction was started by another token"}).into(),
+    )
+}
+
 async fn transact_query(
+    Extension(auth): Extension<Auth>,
     State(st): State<DbState>,
-    Path(id): Path<u32>,
+    Path(id): Path<String>,
     Json(payload): Json<QueryPayload>,
 ) -> (StatusCode, Json<serde_json::Value>) {
     let tx = match st.txs.lock().unwrap().get(&id) {
         None => return (StatusCode::NOT_FOUND, json!({"ok": false}).into()),
-        Some(tx) => tx.clone(),
+        Some(open) if open.principal != auth.principal => return not_own_transaction(),
+        Some(open) => open.tx.clone(),
     };
     let src = payload.script.clone();
     let result = spawn_blocking(move || {
@@ -339,13 +615,18 @@ struct FinishTransactPayload {
 }
 
 async fn finish_query(
+    Extension(auth): Extension<Auth>,
     State(st): State<DbState>,
-    Path(id): Path<u32>,
+    Path(id): Path<String>,
     Json(payload): Json<FinishTransactPayload>,
 ) -> (StatusCode, Json<serde_json::Value>) {
-    let tx = match st.txs.lock().unwrap().remove(&id) {
-        None => return (StatusCode::NOT_FOUND, json!({"ok": false}).into()),
-        Some(tx) => tx,
+    let tx = {
+        let mut txs = st.txs.lock().unwrap();
+        match txs.get(&id) {
+            None => return (StatusCode::NOT_FOUND, json!({"ok": false}).into()),
+            Some(open) if open.principal != auth.principal => return not_own_transaction(),
+            Some(_) => txs.remove(&id).unwrap().tx,
+        }
     };
     let res = if payload.abort {
         tx.abort()
@@ -369,29 +650,272 @@ struct QueryPayload {
 }
 
 async fn text_query(
-    Extension(mutability): Extension<ScriptMutability>,
+    Extension(auth): Extension<Auth>,
     State(st): State<DbState>,
+    headers: HeaderMap,
     Json(payload): Json<QueryPayload>,
-) -> (StatusCode, Json<serde_json::Value>) {
+) -> Response<BoxBody> {
+    // results are sent as MessagePack or CBOR if the client asks for them
+    let format = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .and_then(|accept| accept.split(',').find_map(ResultFormat::from_content_type))
+        .unwrap_or_default();
     let params = payload
         .params
         .into_iter()
         .map(|(k, v)| (k, DataValue::from(v)))
         .collect();
-    let immutable = match mutability {
+    let immutable = match auth.mutability {
         ScriptMutability::Mutable => payload.immutable.unwrap_or(false),
         ScriptMutability::Immutable => true,
     };
+    let mutability = if immutable {
+        ScriptMutability::Immutable
+    } else {
+        ScriptMutability::Mutable
+    };
+    let result = spawn_blocking(move || match &auth.principal {
+        None => st.db.run_script_fold_err(&payload.script, params, mutability),
+        Some(principal) => {
+            st.db
+                .run_script_as_fold_err(principal, &payload.script, params, mutability)
+        }
+    })
+        .await;
+    let (code, Json(res)) = match result {
+        Ok(res) => wrap_json(res),
+        Err(err) => internal_error(err),
+    };
+    match format {
+        ResultFormat::Json => (code, Json(res)).into_response(),
+        format => (
+            code,
+            [(header::CONTENT_TYPE, format.content_type())],
+            format.serialize_json(&res),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(serde_derive::Deserialize)]
+struct StatementPayload {
+    script: String,
+    #[serde(default)]
+    params: BTreeMap<String, serde_json::Value>,
+}
+
+#[derive(serde_derive::Deserialize)]
+struct ScriptPayload {
+    statements: Vec<StatementPayload>,
+    immutable: Option<bool>,
+}
+
+/// Run the statements one after the other in a single transaction, committed only if
+/// they all succeed, returning the result of each.
+async fn run_statements(
+    Extension(auth): Extension<Auth>,
+    State(st): State<DbState>,
+    Json(payload): Json<ScriptPayload>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let write = match auth.mutability {
+        ScriptMutability::Mutable => !payload.immutable.unwrap_or(false),
+        ScriptMutability::Immutable => false,
+    };
     let result = spawn_blocking(move || {
-        st.db.run_script_fold_err(
-            &payload.script,
-            params,
-            if immutable {
-                ScriptMutability::Immutable
-            } else {
-                ScriptMutability::Mutable
-            },
+        let tx = match &auth.principal {
+            None => st.db.multi_transaction(write),
+            Some(principal) => st.db.multi_transaction_as(principal, write),
+        };
+        let mut results = vec![];
+        for (i, statement) in payload.statements.into_iter().enumerate() {
+            let params = statement
+                .params
+                .into_iter()
+                .map(|(k, v)| (k, DataValue::from(v)))
+                .collect();
+            match tx.run_script(&statement.script, params) {
+                Ok(res) => results.push(res.into_json()),
+                Err(err) => {
+                    let _ = tx.abort();
+                    let mut json = format_error_as_json(err, Some(&statement.script));
+                    json["statement"] = json!(i);
+                    return json;
+                }
+            }
+        }
+        match tx.commit() {
+            Ok(()) => json!({"ok": true, "results": results}),
+            Err(err) => format_error_as_json(err, None),
+        }
+    })
+        .await;
+    match result {
+        Ok(res) => wrap_json(res),
+        Err(err) => internal_error(err),
+    }
+}
+
+async fn list_procedures(State(st): State<DbState>) -> (StatusCode, Json<serde_json::Value>) {
+    let procedures = st
+        .db
+        .procedures()
+        .into_iter()
+        .map(|p| json!({"name": p.name, "params": p.params, "script": p.script}))
+        .collect_vec();
+    wrap_json(json!({"ok": true, "procedures": procedures}))
+}
+
+#[derive(serde_derive::Deserialize)]
+struct ProcedurePayload {
+    #[serde(default)]
+    params: Vec<String>,
+    script: String,
+}
+
+/// Register a procedure, replacing any procedure of the same name.
+async fn register_procedure(
+    State(st): State<DbState>,
+    Path(name): Path<String>,
+    Json(payload): Json<ProcedurePayload>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let result = spawn_blocking(move || {
+        let params = payload.params.iter().map(|p| p.as_str()).collect_vec();
+        let existing = st.db.procedures().into_iter().find(|p| p.name == name);
+        st.db.unregister_procedure(&name);
+        match st.db.register_procedure(&name, &params, &payload.script) {
+            Ok(()) => json!({"ok": true}),
+            Err(err) => {
+                // keep the procedure being replaced if the new one is invalid
+                if let Some(p) = existing {
+                    let params = p.params.iter().map(|p| p.as_str()).collect_vec();
+                    let _ = st.db.register_procedure(&name, &params, &p.script);
+                }
+                format_error_as_json(err, Some(&payload.script))
+            }
+        }
+    })
+        .await;
+    match result {
+        Ok(res) => wrap_json(res),
+        Err(err) => internal_error(err),
+    }
+}
+
+async fn unregister_procedure(
+    State(st): State<DbState>,
+    Path(name): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if st.db.unregister_procedure(&name) {
+        wrap_json(json!({"ok": true}))
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            json!({"ok": false, "message": format!("No procedure named {name}")}).into(),
         )
+    }
+}
+
+#[cfg(feature = "wasm-plugins")]
+#[derive(serde_derive::Deserialize)]
+struct PluginLimits {
+    fuel: Option<u64>,
+    max_memory_pages: Option<u32>,
+    max_call_depth: Option<usize>,
+}
+
+/// Register the WebAssembly module sent as the body as the fixed rule `name`.
+#[cfg(feature = "wasm-plugins")]
+async fn register_plugin(
+    Extension(auth): Extension<Auth>,
+    State(st): State<DbState>,
+    Path(name): Path<String>,
+    Query(limits): Query<PluginLimits>,
+    module: axum::body::Bytes,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if auth.mutability == ScriptMutability::Immutable {
+        return (
+            StatusCode::FORBIDDEN,
+            json!({"ok": false, "message": "the token cannot write"}).into(),
+        );
+    }
+    let defaults = cozo::WasmLimits::default();
+    let limits = cozo::WasmLimits {
+        fuel: limits.fuel.unwrap_or(defaults.fuel),
+        max_memory_pages: limits.max_memory_pages.unwrap_or(defaults.max_memory_pages),
+        max_call_depth: limits.max_call_depth.unwrap_or(defaults.max_call_depth),
+    };
+    let result = spawn_blocking(move || match st.db.register_wasm_plugin(&name, &module, limits) {
+        Ok(()) => json!({"ok": true}),
+        Err(err) => format_error_as_json(err, None),
+    })
+        .await;
+    match result {
+        Ok(res) => wrap_json(res),
+        Err(err) => internal_error(err),
+    }
+}
+
+#[cfg(feature = "wasm-plugins")]
+async fn unregister_plugin(
+    Extension(auth): Extension<Auth>,
+    State(st): State<DbState>,
+    Path(name): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if auth.mutability == ScriptMutability::Immutable {
+        return (
+            StatusCode::FORBIDDEN,
+            json!({"ok": false, "message": "the token cannot write"}).into(),
+        );
+    }
+    match st.db.unregister_wasm_plugin(&name) {
+        Ok(true) => wrap_json(json!({"ok": true})),
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            json!({"ok": false, "message": format!("No plugin named {name}")}).into(),
+        ),
+        Err(err) => wrap_json(format_error_as_json(err, None)),
+    }
+}
+
+#[derive(serde_derive::Deserialize)]
+struct CallPayload {
+    #[serde(default)]
+    params: BTreeMap<String, serde_json::Value>,
+    immutable: Option<bool>,
+}
+
+/// Run a registered procedure in a single transaction.
+async fn call_procedure(
+    Extension(auth): Extension<Auth>,
+    State(st): State<DbState>,
+    Path(name): Path<String>,
+    Json(payload): Json<CallPayload>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let args = payload
+        .params
+        .into_iter()
+        .map(|(k, v)| (k, DataValue::from(v)))
+        .collect();
+    let mutability = match auth.mutability {
+        ScriptMutability::Mutable if !payload.immutable.unwrap_or(false) => {
+            ScriptMutability::Mutable
+        }
+        _ => ScriptMutability::Immutable,
+    };
+    let result = spawn_blocking(move || {
+        let res = match &auth.principal {
+            None => st.db.call_procedure(&name, args, mutability),
+            Some(principal) => st.db.call_procedure_as(principal, &name, args, mutability),
+        };
+        match res {
+            Ok(rows) => {
+                let mut json = rows.into_json();
+                json["ok"] = json!(true);
+                json
+            }
+            Err(err) => format_error_as_json(err, None),
+        }
     })
         .await;
     match result {
@@ -400,6 +924,139 @@ async fn text_query(
     }
 }
 
+#[derive(serde_derive::Deserialize)]
+struct CursorPayload {
+    script: String,
+    #[serde(default)]
+    params: BTreeMap<String, serde_json::Value>,
+}
+
+/// Run a read-only query and keep its rows, to be fetched page by page.
+async fn open_cursor(
+    Extension(auth): Extension<Auth>,
+    State(st): State<DbState>,
+    Json(payload): Json<CursorPayload>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let params = payload
+        .params
+        .into_iter()
+        .map(|(k, v)| (k, DataValue::from(v)))
+        .collect();
+    let db = st.db.clone();
+    let script = payload.script.clone();
+    let result = spawn_blocking(move || match &auth.principal {
+        None => db.run_script(&script, params, ScriptMutability::Immutable),
+        Some(principal) => {
+            db.run_script_as(principal, &script, params, ScriptMutability::Immutable)
+        }
+    })
+        .await;
+    match result {
+        Ok(Ok(res)) => {
+            let id = st.cursor_counter.fetch_add(1, Ordering::SeqCst);
+            let size = res.rows.len();
+            st.cursors.lock().unwrap().insert(
+                id,
+                Cursor {
+                    rows: res.rows.into_iter(),
+                    last_used: Instant::now(),
+                },
+            );
+            (
+                StatusCode::OK,
+                json!({"ok": true, "id": id, "headers": res.headers, "size": size}).into(),
+            )
+        }
+        Ok(Err(err)) => (
+            StatusCode::BAD_REQUEST,
+            format_error_as_json(err, Some(&payload.script)).into(),
+        ),
+        Err(err) => internal_error(err),
+    }
+}
+
+#[derive(serde_derive::Deserialize)]
+struct FetchCursorPayload {
+    n: Option<usize>,
+}
+
+/// Return the next `n` rows of a cursor, 1000 by default, closing it after the last.
+async fn fetch_cursor(
+    State(st): State<DbState>,
+    Path(id): Path<u32>,
+    Query(payload): Query<FetchCursorPayload>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let mut cursors = st.cursors.lock().unwrap();
+    let cursor = match cursors.get_mut(&id) {
+        None => return (StatusCode::NOT_FOUND, json!({"ok": false}).into()),
+        Some(cursor) => cursor,
+    };
+    let rows = cursor
+        .rows
+        .by_ref()
+        .take(payload.n.unwrap_or(1000))
+        .map(|row| row.into_iter().map(serde_json::Value::from).collect_vec())
+        .collect_vec();
+    cursor.last_used = Instant::now();
+    let done = cursor.rows.len() == 0;
+    if done {
+        cursors.remove(&id);
+    }
+    (
+        StatusCode::OK,
+        json!({"ok": true, "rows": rows, "done": done}).into(),
+    )
+}
+
+async fn close_cursor(
+    State(st): State<DbState>,
+    Path(id): Path<u32>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    match st.cursors.lock().unwrap().remove(&id) {
+        None => (StatusCode::NOT_FOUND, json!({"ok": false}).into()),
+        Some(_) => (StatusCode::OK, json!({"ok": true}).into()),
+    }
+}
+
+/// The GraphQL schema generated from the stored relations.
+async fn graphql_schema(State(st): State<DbState>) -> Response<BoxBody> {
+    match spawn_blocking(move || Schema::new(&st.db)).await {
+        Ok(Ok(schema)) => schema.sdl().into_response(),
+        Ok(Err(err)) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"ok": false, "message": err.to_string()})),
+        )
+            .into_response(),
+        Err(err) => internal_error(err).into_response(),
+    }
+}
+
+#[derive(serde_derive::Deserialize)]
+struct GraphqlPayload {
+    query: String,
+    #[serde(default)]
+    variables: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+async fn graphql_query(
+    Extension(auth): Extension<Auth>,
+    State(st): State<DbState>,
+    Json(payload): Json<GraphqlPayload>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let result = spawn_blocking(move || {
+        let variables = payload.variables.unwrap_or_default();
+        match Schema::new(&st.db) {
+            Ok(schema) => schema.execute(&st.db, &auth, &payload.query, &variables),
+            Err(err) => json!({"data": null, "errors": [{"message": err.to_string()}]}),
+        }
+    })
+        .await;
+    match result {
+        Ok(res) => (StatusCode::OK, res.into()),
+        Err(err) => internal_error(err),
+    }
+}
+
 async fn export_relations(
     State(st): State<DbState>,
     Path(relations): Path<String>,
@@ -667,6 +1324,13 @@ async fn observe_changes(
     Sse::new(stream).keep_alive(KeepAlive::default())
 }
 
+async fn metrics(State(st): State<DbState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        st.db.metrics().to_prometheus(),
+    )
+}
+
 async fn root() -> Html<&'static str> {
     Html(include_str!("./index.html"))
 }
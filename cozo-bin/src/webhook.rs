@@ -0,0 +1,162 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use log::{error, info, warn};
+use miette::{bail, IntoDiagnostic, Result};
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+
+use cozo::{DbInstance, TxChanges};
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A target of the `--webhooks` file, receiving the transactions as JSON POSTs.
+#[derive(Deserialize, Debug, Clone)]
+struct WebhookTarget {
+    url: String,
+    /// Only changes to these relations are sent, all of them if absent
+    relations: Option<Vec<String>>,
+    /// Attempts to deliver a transaction before it is skipped, unlimited if absent
+    max_attempts: Option<u32>,
+}
+
+/// The last transaction delivered to each target, kept in a file so that delivery
+/// resumes there after a restart.
+struct Cursors {
+    path: String,
+    delivered: Mutex<BTreeMap<String, u64>>,
+}
+
+impl Cursors {
+    /// Load the positions of the targets `urls`, dropping those of targets no longer
+    /// configured.
+    fn load(path: String, urls: &[&str]) -> Self {
+        let saved: BTreeMap<String, u64> = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        let delivered = urls
+            .iter()
+            .map(|url| (url.to_string(), saved.get(*url).copied().unwrap_or(0)))
+            .collect();
+        Self {
+            path,
+            delivered: Mutex::new(delivered),
+        }
+    }
+    fn get(&self, url: &str) -> u64 {
+        self.delivered
+            .lock()
+            .unwrap()
+            .get(url)
+            .copied()
+            .unwrap_or(0)
+    }
+    /// Record a delivery, returning the transaction all targets have got to if it
+    /// advanced.
+    fn set(&self, url: &str, tx_id: u64) -> Option<u64> {
+        let mut delivered = self.delivered.lock().unwrap();
+        let before = delivered.values().copied().min();
+        delivered.insert(url.to_string(), tx_id);
+        if let Err(err) = std::fs::write(&self.path, json!(*delivered).to_string()) {
+            error!("saving the webhook positions to {} failed: {err}", self.path);
+        }
+        let after = delivered.values().copied().min();
+        if after > before {
+            after
+        } else {
+            None
+        }
+    }
+}
+
+/// Start delivering the committed transactions to the targets listed in the JSON file
+/// `config`, remembering how far each got in the file `cursor_path`.
+pub(crate) fn start_webhooks(db: &DbInstance, config: &str, cursor_path: String) -> Result<()> {
+    let targets: Vec<WebhookTarget> =
+        serde_json::from_str(&std::fs::read_to_string(config).into_diagnostic()?)
+            .into_diagnostic()?;
+    if targets.is_empty() {
+        bail!("no webhook targets in {config}")
+    }
+    db.enable_tx_log()?;
+    let urls: Vec<&str> = targets.iter().map(|t| t.url.as_str()).collect();
+    let cursors = Arc::new(Cursors::load(cursor_path, &urls));
+    for target in targets {
+        let receiver = db.subscribe_from(cursors.get(&target.url))?;
+        let db = db.clone();
+        let cursors = cursors.clone();
+        info!("Sending transactions to webhook {}", target.url);
+        thread::spawn(move || {
+            for changes in receiver {
+                let tx_id = changes.tx_id;
+                if let Some(body) = report(changes, &target.relations) {
+                    deliver(&target, &body);
+                }
+                // the log is kept for the transactions some target has yet to get
+                if let Some(all_delivered) = cursors.set(&target.url, tx_id) {
+                    if let Err(err) = db.truncate_tx_log(all_delivered) {
+                        error!("truncating the transaction log failed: {err}");
+                    }
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+/// The JSON sent for `changes`, or `None` if no relation of interest was changed.
+fn report(mut changes: TxChanges, relations: &Option<Vec<String>>) -> Option<Value> {
+    if let Some(relations) = relations {
+        changes.relations.retain(|name, _| relations.contains(name));
+    }
+    if changes.relations.is_empty() {
+        return None;
+    }
+    Some(changes.into_json())
+}
+
+/// POST `body` to the target until it answers with a success status, backing off
+/// between the attempts.
+fn deliver(target: &WebhookTarget, body: &Value) {
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let res = minreq::post(&target.url)
+            .with_header("content-type", "application/json")
+            .with_body(body.to_string())
+            .with_timeout(30)
+            .send();
+        let failure = match res {
+            Ok(resp) if (200..300).contains(&resp.status_code) => return,
+            Ok(resp) => format!("status {}", resp.status_code),
+            Err(err) => err.to_string(),
+        };
+        if target.max_attempts.is_some_and(|max| attempts >= max) {
+            error!(
+                "giving up sending transaction {} to webhook {} after {attempts} attempts: \
+                {failure}",
+                body["tx_id"], target.url
+            );
+            return;
+        }
+        warn!(
+            "sending transaction {} to webhook {} failed, retrying in {backoff:?}: {failure}",
+            body["tx_id"], target.url
+        );
+        thread::sleep(backoff);
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
@@ -0,0 +1,119 @@
+/*
+ *  Copyright 2023, The Cozo Project Authors.
+ *
+ *  This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ *  If a copy of the MPL was not distributed with this file,
+ *  You can obtain one at https://mozilla.org/MPL/2.0/.
+ *
+ */
+
+//! Read queries run from an increasing number of threads. Each iteration runs the same
+//! number of queries per thread, so with no contention between readers the time per
+//! iteration stays flat as threads are added.
+
+#![feature(test)]
+
+extern crate test;
+
+use cozo::{DataValue, DbInstance, NamedRows, ScriptMutability};
+use itertools::Itertools;
+use lazy_static::{initialize, lazy_static};
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::thread;
+use test::Bencher;
+
+const QUERIES_PER_THREAD: usize = 1000;
+
+lazy_static! {
+    static ref TEST_DB: DbInstance = {
+        let db = DbInstance::new("mem", "", "").unwrap();
+        db.run_script(
+            ":create plain {k: Int => v}",
+            Default::default(),
+            ScriptMutability::Mutable,
+        )
+        .unwrap();
+        db.import_relations(BTreeMap::from([(
+            "plain".to_string(),
+            NamedRows::new(
+                vec!["k".to_string(), "v".to_string()],
+                (0..10000)
+                    .map(|i| vec![DataValue::from(i as i64), DataValue::from(i as i64)])
+                    .collect_vec(),
+            ),
+        )]))
+        .unwrap();
+        db
+    };
+}
+
+/// Only opens and closes a session: the bookkeeping shared by every query.
+fn trivial_read() {
+    TEST_DB
+        .run_script(
+            "?[x] <- [[1]]",
+            Default::default(),
+            ScriptMutability::Immutable,
+        )
+        .unwrap();
+}
+
+fn single_plain_read() {
+    let i = rand::thread_rng().gen_range(0..10000);
+    TEST_DB
+        .run_script(
+            "?[v] := *plain{k: $id, v}",
+            BTreeMap::from([("id".to_string(), DataValue::from(i as i64))]),
+            ScriptMutability::Immutable,
+        )
+        .unwrap();
+}
+
+fn run_on_threads(threads: usize, query: fn()) {
+    thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| {
+                for _ in 0..QUERIES_PER_THREAD {
+                    query()
+                }
+            });
+        }
+    })
+}
+
+#[bench]
+fn trivial_reads_1_thread(b: &mut Bencher) {
+    initialize(&TEST_DB);
+    b.iter(|| run_on_threads(1, trivial_read))
+}
+
+#[bench]
+fn trivial_reads_4_threads(b: &mut Bencher) {
+    initialize(&TEST_DB);
+    b.iter(|| run_on_threads(4, trivial_read))
+}
+
+#[bench]
+fn trivial_reads_16_threads(b: &mut Bencher) {
+    initialize(&TEST_DB);
+    b.iter(|| run_on_threads(16, trivial_read))
+}
+
+#[bench]
+fn plain_reads_1_thread(b: &mut Bencher) {
+    initialize(&TEST_DB);
+    b.iter(|| run_on_threads(1, single_plain_read))
+}
+
+#[bench]
+fn plain_reads_4_threads(b: &mut Bencher) {
+    initialize(&TEST_DB);
+    b.iter(|| run_on_threads(4, single_plain_read))
+}
+
+#[bench]
+fn plain_reads_16_threads(b: &mut Bencher) {
+    initialize(&TEST_DB);
+    b.iter(|| run_on_threads(16, single_plain_read))
+}
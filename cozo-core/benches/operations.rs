@@ -0,0 +1,190 @@
+/*
+ *  Copyright 2023, The Cozo Project Authors.
+ *
+ *  This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ *  If a copy of the MPL was not distributed with this file,
+ *  You can obtain one at https://mozilla.org/MPL/2.0/.
+ *
+ */
+
+//! Core operations over the dataset of `Db::bench_dataset`, to compare releases with.
+//! The dataset has `COZO_BENCH_NODES` nodes, 10000 by default.
+//!
+//! To check a change for regressions, save a baseline before it and compare after:
+//!
+//! ```text
+//! cargo bench -p cozo --bench operations -- --save-baseline before
+//! cargo bench -p cozo --bench operations -- --baseline before
+//! ```
+
+use std::collections::BTreeMap;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use rand::Rng;
+
+use cozo::{DataValue, DbInstance, ScriptMutability};
+
+/// Rows written by each transaction of the `transact` benchmark.
+const TX_ROWS: u64 = 100;
+
+/// Width of the key ranges of the `range_scan` benchmark.
+const RANGE_WIDTH: u64 = 1000;
+
+fn nodes() -> u64 {
+    std::env::var("COZO_BENCH_NODES")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(10000)
+}
+
+fn dataset_db() -> DbInstance {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    db.bench_dataset(nodes()).unwrap();
+    db
+}
+
+fn query(db: &DbInstance, script: &str, params: BTreeMap<String, DataValue>) {
+    db.run_script(script, params, ScriptMutability::Immutable)
+        .unwrap();
+}
+
+fn random_id(n: u64) -> BTreeMap<String, DataValue> {
+    let id = rand::thread_rng().gen_range(0..n);
+    BTreeMap::from([("id".to_string(), DataValue::from(id as i64))])
+}
+
+fn transact(c: &mut Criterion) {
+    let db = dataset_db();
+    let mut next = nodes();
+    let mut group = c.benchmark_group("transact");
+    group.throughput(Throughput::Elements(TX_ROWS));
+    group.bench_function("put_rows", |b| {
+        b.iter_batched(
+            || {
+                let rows = (next..next + TX_ROWS)
+                    .map(|id| {
+                        DataValue::List(vec![
+                            DataValue::from(id as i64),
+                            DataValue::from(format!("node-{id}")),
+                            DataValue::from(0),
+                            DataValue::from(0.),
+                        ])
+                    })
+                    .collect();
+                next += TX_ROWS;
+                BTreeMap::from([("rows".to_string(), DataValue::List(rows))])
+            },
+            |params| {
+                db.run_script(
+                    "?[id, name, group, score] <- $rows :put bench_node {id => name, group, score}",
+                    params,
+                    ScriptMutability::Mutable,
+                )
+                .unwrap()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+fn point_lookup(c: &mut Criterion) {
+    let db = dataset_db();
+    let n = nodes();
+    c.bench_function("point_lookup", |b| {
+        b.iter_batched(
+            || random_id(n),
+            |params| {
+                query(
+                    &db,
+                    "?[name, score] := *bench_node{id: $id, name, score}",
+                    params,
+                )
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn range_scan(c: &mut Criterion) {
+    let db = dataset_db();
+    let n = nodes();
+    let mut group = c.benchmark_group("range_scan");
+    group.throughput(Throughput::Elements(RANGE_WIDTH.min(n)));
+    group.bench_function("scan", |b| {
+        b.iter_batched(
+            || {
+                let lo = rand::thread_rng().gen_range(0..n.saturating_sub(RANGE_WIDTH).max(1));
+                BTreeMap::from([
+                    ("lo".to_string(), DataValue::from(lo as i64)),
+                    ("hi".to_string(), DataValue::from((lo + RANGE_WIDTH) as i64)),
+                ])
+            },
+            |params| {
+                query(
+                    &db,
+                    "?[sum(score)] := *bench_node{id, score}, id > $lo - 1, id < $hi",
+                    params,
+                )
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+fn recursive_query(c: &mut Criterion) {
+    let db = dataset_db();
+    let n = nodes();
+    c.bench_function("recursive_reachability", |b| {
+        b.iter_batched(
+            || random_id(n),
+            |params| {
+                query(
+                    &db,
+                    r#"
+                    reach[d] := *bench_edge{src: $id, dst: d}
+                    reach[d] := reach[s], *bench_edge{src: s, dst: d}
+                    ?[count(d)] := reach[d]
+                    "#,
+                    params,
+                )
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+/// Fetching a node with its neighbours, as an entity with its references.
+fn pull(c: &mut Criterion) {
+    let db = dataset_db();
+    let n = nodes();
+    c.bench_function("pull_neighbours", |b| {
+        b.iter_batched(
+            || random_id(n),
+            |params| {
+                query(
+                    &db,
+                    r#"
+                    ?[name, score, dst, dst_name, weight] :=
+                        *bench_node{id: $id, name, score},
+                        *bench_edge{src: $id, dst, weight},
+                        *bench_node{id: dst, name: dst_name}
+                    "#,
+                    params,
+                )
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(
+    benches,
+    transact,
+    point_lookup,
+    range_scan,
+    recursive_query,
+    pull
+);
+criterion_main!(benches);
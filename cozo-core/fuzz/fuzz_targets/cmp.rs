@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// the first byte tells where the first value ends
+fuzz_target!(|data: &[u8]| {
+    if let Some((&split, rest)) = data.split_first() {
+        let (a, b) = rest.split_at((split as usize).min(rest.len()));
+        cozo::fuzz::fuzz_cmp(a, b);
+    }
+});
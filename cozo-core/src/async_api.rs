@@ -0,0 +1,718 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Asynchronous wrappers of the [`DbInstance`] API, enabled by the `async` feature.
+//!
+//! The blocking work runs on a dedicated pool of threads, one per CPU, so that awaiting
+//! these futures never blocks the threads of the async executor. The futures do not
+//! depend on any particular executor.
+//!
+//! Writes may also go through a bounded queue served by writer threads of their own,
+//! see [`Db::submit_write`]. The writes of each tenant are queued apart and taken in turn,
+//! see [`Db::submit_write_for`].
+
+use std::collections::{BTreeMap, VecDeque};
+use std::future::Future;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use crossbeam::channel::{unbounded, Sender};
+use lazy_static::lazy_static;
+use log::error;
+use miette::{Diagnostic, Result};
+use thiserror::Error;
+use tokio::sync::{oneshot, OwnedSemaphorePermit, Semaphore, TryAcquireError};
+
+use crate::runtime::db::seconds_since_the_epoch;
+use crate::{
+    CozoError, DataValue, Db, DbInstance, MultiTransaction, NamedRows, Principal,
+    ScriptMutability, Storage,
+};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+lazy_static! {
+    static ref BLOCKING_POOL: Sender<Job> = {
+        let (sender, receiver) = unbounded::<Job>();
+        let size = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(4);
+        for i in 0..size {
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("cozo-async-{i}"))
+                .spawn(move || {
+                    for job in receiver {
+                        // a panicking job drops its result sender, the awaiting side sees it
+                        let _ = catch_unwind(AssertUnwindSafe(job));
+                    }
+                })
+                .expect("failed to spawn a thread of the async pool");
+        }
+        sender
+    };
+}
+
+/// Run `f` on the blocking pool, resolving to its result.
+fn run_blocking<T: Send + 'static>(
+    f: impl FnOnce() -> T + Send + 'static,
+) -> impl Future<Output = T> + Send + 'static {
+    let (sender, receiver) = oneshot::channel();
+    let job: Job = Box::new(move || {
+        let _ = sender.send(f());
+    });
+    BLOCKING_POOL
+        .send(job)
+        .expect("the async pool has shut down");
+    async move { receiver.await.expect("the blocking task panicked") }
+}
+
+impl DbInstance {
+    /// Asynchronous version of [`run_script`](Self::run_script).
+    pub fn run_script_async(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> impl Future<Output = Result<NamedRows, CozoError>> + Send + 'static {
+        let db = self.clone();
+        let payload = payload.to_string();
+        run_blocking(move || db.run_script(&payload, params, mutability))
+    }
+    /// Asynchronous version of [`run_script_as`](Self::run_script_as).
+    pub fn run_script_as_async(
+        &self,
+        principal: &Principal,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> impl Future<Output = Result<NamedRows, CozoError>> + Send + 'static {
+        let db = self.clone();
+        let principal = principal.clone();
+        let payload = payload.to_string();
+        run_blocking(move || db.run_script_as(&principal, &payload, params, mutability))
+    }
+    /// Asynchronous version of [`run_default`](Self::run_default).
+    pub fn run_default_async(
+        &self,
+        payload: &str,
+    ) -> impl Future<Output = Result<NamedRows, CozoError>> + Send + 'static {
+        self.run_script_async(payload, BTreeMap::new(), ScriptMutability::Mutable)
+    }
+    /// Asynchronous version of [`export_relations`](Self::export_relations).
+    pub fn export_relations_async<I, T>(
+        &self,
+        relations: I,
+    ) -> impl Future<Output = Result<BTreeMap<String, NamedRows>, CozoError>> + Send + 'static
+    where
+        T: AsRef<str>,
+        I: Iterator<Item = T>,
+    {
+        let db = self.clone();
+        let relations: Vec<String> = relations.map(|r| r.as_ref().to_string()).collect();
+        run_blocking(move || db.export_relations(relations.iter()))
+    }
+    /// Asynchronous version of [`import_relations`](Self::import_relations).
+    pub fn import_relations_async(
+        &self,
+        data: BTreeMap<String, NamedRows>,
+    ) -> impl Future<Output = Result<(), CozoError>> + Send + 'static {
+        let db = self.clone();
+        run_blocking(move || db.import_relations(data))
+    }
+    /// Asynchronous version of [`backup_db`](Self::backup_db).
+    pub fn backup_db_async(
+        &self,
+        out_file: impl Into<PathBuf>,
+    ) -> impl Future<Output = Result<(), CozoError>> + Send + 'static {
+        let db = self.clone();
+        let out_file = out_file.into();
+        run_blocking(move || db.backup_db(out_file))
+    }
+    /// Asynchronous version of [`restore_backup`](Self::restore_backup).
+    pub fn restore_backup_async(
+        &self,
+        in_file: impl Into<PathBuf>,
+    ) -> impl Future<Output = Result<(), CozoError>> + Send + 'static {
+        let db = self.clone();
+        let in_file = in_file.into();
+        run_blocking(move || db.restore_backup(in_file))
+    }
+    /// Asynchronous version of [`import_from_backup`](Self::import_from_backup).
+    pub fn import_from_backup_async(
+        &self,
+        in_file: impl Into<PathBuf>,
+        relations: &[String],
+    ) -> impl Future<Output = Result<(), CozoError>> + Send + 'static {
+        let db = self.clone();
+        let in_file = in_file.into();
+        let relations = relations.to_vec();
+        run_blocking(move || db.import_from_backup(in_file, &relations))
+    }
+    /// Dispatcher method. See [crate::Db::start_write_queue].
+    pub fn start_write_queue(&self, capacity: usize, writers: usize) {
+        match self {
+            DbInstance::Mem(db) => db.start_write_queue(capacity, writers),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.start_write_queue(capacity, writers),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.start_write_queue(capacity, writers),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.start_write_queue(capacity, writers),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.start_write_queue(capacity, writers),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::stop_write_queue].
+    pub fn stop_write_queue(&self) -> bool {
+        match self {
+            DbInstance::Mem(db) => db.stop_write_queue(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.stop_write_queue(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.stop_write_queue(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.stop_write_queue(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.stop_write_queue(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::submit_write].
+    pub fn submit_write(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> impl Future<Output = Result<TxReport, CozoError>> + Send + 'static {
+        let fut: Pin<Box<dyn Future<Output = Result<TxReport>> + Send>> = match self {
+            DbInstance::Mem(db) => Box::pin(db.submit_write(payload, params)),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => Box::pin(db.submit_write(payload, params)),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => Box::pin(db.submit_write(payload, params)),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => Box::pin(db.submit_write(payload, params)),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => Box::pin(db.submit_write(payload, params)),
+        };
+        async move { Ok(fut.await?) }
+    }
+    /// Dispatcher method. See [crate::Db::try_submit_write].
+    pub fn try_submit_write(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<impl Future<Output = Result<TxReport, CozoError>> + Send + 'static, CozoError> {
+        let fut: Pin<Box<dyn Future<Output = Result<TxReport>> + Send>> = match self {
+            DbInstance::Mem(db) => Box::pin(db.try_submit_write(payload, params)?),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => Box::pin(db.try_submit_write(payload, params)?),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => Box::pin(db.try_submit_write(payload, params)?),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => Box::pin(db.try_submit_write(payload, params)?),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => Box::pin(db.try_submit_write(payload, params)?),
+        };
+        Ok(async move { Ok(fut.await?) })
+    }
+    /// Dispatcher method. See [crate::Db::set_write_weight].
+    pub fn set_write_weight(&self, tenant: &str, weight: u32) -> bool {
+        match self {
+            DbInstance::Mem(db) => db.set_write_weight(tenant, weight),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_write_weight(tenant, weight),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_write_weight(tenant, weight),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_write_weight(tenant, weight),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_write_weight(tenant, weight),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::submit_write_for].
+    pub fn submit_write_for(
+        &self,
+        tenant: &str,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> impl Future<Output = Result<TxReport, CozoError>> + Send + 'static {
+        let fut: Pin<Box<dyn Future<Output = Result<TxReport>> + Send>> = match self {
+            DbInstance::Mem(db) => Box::pin(db.submit_write_for(tenant, payload, params)),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => Box::pin(db.submit_write_for(tenant, payload, params)),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => Box::pin(db.submit_write_for(tenant, payload, params)),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => Box::pin(db.submit_write_for(tenant, payload, params)),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => Box::pin(db.submit_write_for(tenant, payload, params)),
+        };
+        async move { Ok(fut.await?) }
+    }
+    /// Dispatcher method. See [crate::Db::try_submit_write_for].
+    pub fn try_submit_write_for(
+        &self,
+        tenant: &str,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<impl Future<Output = Result<TxReport, CozoError>> + Send + 'static, CozoError> {
+        let fut: Pin<Box<dyn Future<Output = Result<TxReport>> + Send>> = match self {
+            DbInstance::Mem(db) => Box::pin(db.try_submit_write_for(tenant, payload, params)?),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => Box::pin(db.try_submit_write_for(tenant, payload, params)?),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => Box::pin(db.try_submit_write_for(tenant, payload, params)?),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => Box::pin(db.try_submit_write_for(tenant, payload, params)?),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => Box::pin(db.try_submit_write_for(tenant, payload, params)?),
+        };
+        Ok(async move { Ok(fut.await?) })
+    }
+    /// Asynchronous version of [`multi_transaction`](Self::multi_transaction).
+    pub fn transact_async(&self, write: bool) -> AsyncMultiTransaction {
+        AsyncMultiTransaction {
+            inner: Arc::new(self.multi_transaction(write)),
+        }
+    }
+    /// Asynchronous version of [`multi_transaction_as`](Self::multi_transaction_as).
+    pub fn transact_as_async(&self, principal: &Principal, write: bool) -> AsyncMultiTransaction {
+        AsyncMultiTransaction {
+            inner: Arc::new(self.multi_transaction_as(principal, write)),
+        }
+    }
+}
+
+/// A multi-transaction handle whose operations return futures, see
+/// [`DbInstance::transact_async`]. Dropping it without committing aborts the transaction.
+#[derive(Clone)]
+pub struct AsyncMultiTransaction {
+    inner: Arc<MultiTransaction>,
+}
+
+impl AsyncMultiTransaction {
+    /// Runs a single script in the transaction.
+    pub fn run_script(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> impl Future<Output = Result<NamedRows, CozoError>> + Send + 'static {
+        let inner = self.inner.clone();
+        let payload = payload.to_string();
+        run_blocking(move || inner.run_script(&payload, params))
+    }
+    /// Creates a temporary relation in the transaction, see
+    /// [`MultiTransaction::create_temp_rel`].
+    pub fn create_temp_rel(
+        &self,
+        name: &str,
+        data: NamedRows,
+    ) -> impl Future<Output = Result<(), CozoError>> + Send + 'static {
+        let inner = self.inner.clone();
+        let name = name.to_string();
+        run_blocking(move || inner.create_temp_rel(&name, data))
+    }
+    /// Commits the multi-transaction
+    pub fn commit(&self) -> impl Future<Output = Result<(), CozoError>> + Send + 'static {
+        let inner = self.inner.clone();
+        run_blocking(move || inner.commit())
+    }
+    /// Aborts the multi-transaction
+    pub fn abort(&self) -> impl Future<Output = Result<(), CozoError>> + Send + 'static {
+        let inner = self.inner.clone();
+        run_blocking(move || inner.abort())
+    }
+}
+
+/// Outcome of a write submitted with [`Db::submit_write`].
+#[derive(Debug, Clone)]
+pub struct TxReport {
+    /// Rows returned by the script
+    pub rows: NamedRows,
+    /// Seconds the write waited in the queue
+    pub queued: f64,
+    /// Seconds taken to run the write
+    pub took: f64,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The write queue is not started")]
+#[diagnostic(code(db::write_queue_not_started))]
+#[diagnostic(help("Start it with `Db::start_write_queue`"))]
+pub(crate) struct WriteQueueNotStarted;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The write queue is full")]
+#[diagnostic(code(db::write_queue_full))]
+#[diagnostic(help("Wait for the queued writes to finish, or use `submit_write`"))]
+pub(crate) struct WriteQueueFull;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The write queue stopped before running the write")]
+#[diagnostic(code(db::write_queue_stopped))]
+pub(crate) struct WriteQueueStopped;
+
+struct WriteJob {
+    payload: String,
+    params: BTreeMap<String, DataValue>,
+    submitted_at: f64,
+    result: oneshot::Sender<Result<TxReport>>,
+}
+
+/// The queued writes of a tenant, see [`Db::submit_write_for`].
+struct TenantQueue {
+    /// Each write holds the room it takes, given back once a writer takes it
+    jobs: VecDeque<(WriteJob, OwnedSemaphorePermit)>,
+    room: Arc<Semaphore>,
+}
+
+struct FairState {
+    tenants: BTreeMap<String, TenantQueue>,
+    /// Tenants with queued writes, the writers take from the first one
+    rotation: VecDeque<String>,
+    /// Writes taken from the first tenant of the rotation since it came first
+    served: u32,
+    weights: BTreeMap<String, u32>,
+    closed: bool,
+}
+
+/// Queued writes taken in turn from each tenant, as many at a time as the weight of the
+/// tenant.
+struct FairQueue {
+    state: Mutex<FairState>,
+    ready: Condvar,
+    capacity: usize,
+}
+
+impl FairQueue {
+    /// The room in the queue of `tenant`, `None` once the queue is closed.
+    fn room(&self, tenant: &str) -> Option<Arc<Semaphore>> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return None;
+        }
+        let queue = state
+            .tenants
+            .entry(tenant.to_string())
+            .or_insert_with(|| TenantQueue {
+                jobs: Default::default(),
+                room: Arc::new(Semaphore::new(self.capacity)),
+            });
+        Some(queue.room.clone())
+    }
+
+    fn push(&self, tenant: &str, job: WriteJob, permit: OwnedSemaphorePermit) -> Result<()> {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        if state.closed {
+            return Err(WriteQueueStopped.into());
+        }
+        let Some(queue) = state.tenants.get_mut(tenant) else {
+            return Err(WriteQueueStopped.into());
+        };
+        queue.jobs.push_back((job, permit));
+        if queue.jobs.len() == 1 {
+            state.rotation.push_back(tenant.to_string());
+        }
+        self.ready.notify_one();
+        Ok(())
+    }
+
+    /// Wait for the next write, `None` once the queue is closed and all writes are taken.
+    fn pop(&self) -> Option<WriteJob> {
+        let mut guard = self.state.lock().unwrap();
+        loop {
+            let state = &mut *guard;
+            if let Some(tenant) = state.rotation.front().cloned() {
+                let weight = state.weights.get(&tenant).copied().unwrap_or(1).max(1);
+                let queue = state.tenants.get_mut(&tenant).unwrap();
+                let (job, permit) = queue.jobs.pop_front().unwrap();
+                drop(permit);
+                let emptied = queue.jobs.is_empty();
+                // no submitter is waiting for room if nothing else refers to it
+                if emptied && Arc::strong_count(&queue.room) == 1 {
+                    state.tenants.remove(&tenant);
+                }
+                state.served += 1;
+                if emptied {
+                    state.rotation.pop_front();
+                    state.served = 0;
+                } else if state.served >= weight {
+                    state.rotation.rotate_left(1);
+                    state.served = 0;
+                }
+                return Some(job);
+            }
+            if state.closed {
+                return None;
+            }
+            guard = self.ready.wait(guard).unwrap();
+        }
+    }
+}
+
+/// Sending side of the write queue, the writer threads stop once it is dropped and the
+/// queued writes have run.
+pub(crate) struct WriteQueue {
+    queue: Arc<FairQueue>,
+}
+
+impl Drop for WriteQueue {
+    fn drop(&mut self) {
+        let mut state = self.queue.state.lock().unwrap();
+        state.closed = true;
+        for tenant in state.tenants.values() {
+            tenant.room.close();
+        }
+        self.queue.ready.notify_all();
+    }
+}
+
+impl<S> Db<S>
+where
+    S: for<'s> Storage<'s> + 'static,
+{
+    /// Start `writers` threads running the writes submitted with
+    /// [`submit_write`](Self::submit_write), which may queue up to `capacity` writes per
+    /// tenant. A previously started queue is stopped first, its queued writes still run,
+    /// and its weights are kept.
+    ///
+    /// The writers take the queued writes of the tenants in turn, see
+    /// [`set_write_weight`](Self::set_write_weight), so that a tenant queuing many
+    /// writes, such as a bulk import, does not hold up the writes of the others.
+    ///
+    /// The threads hold a reference to the database, so they keep running until
+    /// [`stop_write_queue`](Self::stop_write_queue) or [`close`](Self::close) is called.
+    pub fn start_write_queue(&self, capacity: usize, writers: usize) {
+        let mut current = self.write_queue.lock().unwrap();
+        let weights = match &*current {
+            None => Default::default(),
+            Some(q) => q.queue.state.lock().unwrap().weights.clone(),
+        };
+        let queue = Arc::new(FairQueue {
+            state: Mutex::new(FairState {
+                tenants: Default::default(),
+                rotation: Default::default(),
+                served: 0,
+                weights,
+                closed: false,
+            }),
+            ready: Condvar::new(),
+            capacity: capacity.max(1),
+        });
+        for _ in 0..writers.max(1) {
+            let db = self.clone();
+            let queue = queue.clone();
+            thread::spawn(move || {
+                while let Some(job) = queue.pop() {
+                    let started_at = seconds_since_the_epoch().unwrap_or_default();
+                    let res = db
+                        .run_script(&job.payload, job.params, ScriptMutability::Mutable)
+                        .map(|rows| TxReport {
+                            rows,
+                            queued: started_at - job.submitted_at,
+                            took: seconds_since_the_epoch().unwrap_or_default() - started_at,
+                        });
+                    if job.result.send(res).is_err() {
+                        error!("the result of a queued write was not awaited");
+                    }
+                }
+            });
+        }
+        *current = Some(WriteQueue { queue });
+    }
+
+    /// Stop accepting writes into the write queue. The queued writes still run.
+    /// Returns `false` if the queue was not started.
+    pub fn stop_write_queue(&self) -> bool {
+        self.write_queue.lock().unwrap().take().is_some()
+    }
+
+    /// Let the writers take up to `weight` queued writes of `tenant` in a row before
+    /// turning to the next tenant, instead of one. Returns `false` if the queue was not
+    /// started.
+    pub fn set_write_weight(&self, tenant: &str, weight: u32) -> bool {
+        match &*self.write_queue.lock().unwrap() {
+            None => false,
+            Some(q) => {
+                q.queue
+                    .state
+                    .lock()
+                    .unwrap()
+                    .weights
+                    .insert(tenant.to_string(), weight.max(1));
+                true
+            }
+        }
+    }
+
+    /// Queue the mutable script `payload` to be run by the writer threads started with
+    /// [`start_write_queue`](Self::start_write_queue), resolving once it has run. While the
+    /// queue is full the future waits for room, so that producers are slowed down instead
+    /// of queuing without bound. The write is queued for the default tenant, see
+    /// [`submit_write_for`](Self::submit_write_for).
+    pub fn submit_write(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> impl Future<Output = Result<TxReport>> + Send + 'static {
+        self.submit_write_for("", payload, params)
+    }
+
+    /// Like [`submit_write`](Self::submit_write), queuing the write for `tenant`, such as a
+    /// session or a namespace. Each tenant has the room given by `start_write_queue` of
+    /// its own, and the writers take the writes of the tenants in turn.
+    pub fn submit_write_for(
+        &self,
+        tenant: &str,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> impl Future<Output = Result<TxReport>> + Send + 'static {
+        let queue = self
+            .write_queue
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|q| q.queue.clone());
+        let tenant = tenant.to_string();
+        let payload = payload.to_string();
+        async move {
+            let queue = queue.ok_or(WriteQueueNotStarted)?;
+            let room = queue.room(&tenant).ok_or(WriteQueueStopped)?;
+            let permit = room.acquire_owned().await.map_err(|_| WriteQueueStopped)?;
+            let (job, receiver) = Self::write_job(payload, params);
+            queue.push(&tenant, job, permit)?;
+            receiver.await.map_err(|_| WriteQueueStopped)?
+        }
+    }
+
+    /// Like [`submit_write`](Self::submit_write), but fail right away if the queue is full.
+    pub fn try_submit_write(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<impl Future<Output = Result<TxReport>> + Send + 'static> {
+        self.try_submit_write_for("", payload, params)
+    }
+
+    /// Like [`submit_write_for`](Self::submit_write_for), but fail right away if the queue
+    /// of `tenant` is full.
+    pub fn try_submit_write_for(
+        &self,
+        tenant: &str,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<impl Future<Output = Result<TxReport>> + Send + 'static> {
+        let receiver = match &*self.write_queue.lock().unwrap() {
+            None => return Err(WriteQueueNotStarted.into()),
+            Some(q) => {
+                let room = q.queue.room(tenant).ok_or(WriteQueueStopped)?;
+                let permit = room.try_acquire_owned().map_err(|err| match err {
+                    TryAcquireError::NoPermits => miette::Report::from(WriteQueueFull),
+                    TryAcquireError::Closed => WriteQueueStopped.into(),
+                })?;
+                let (job, receiver) = Self::write_job(payload.to_string(), params);
+                q.queue.push(tenant, job, permit)?;
+                receiver
+            }
+        };
+        Ok(async move { receiver.await.map_err(|_| WriteQueueStopped)? })
+    }
+
+    fn write_job(
+        payload: String,
+        params: BTreeMap<String, DataValue>,
+    ) -> (WriteJob, oneshot::Receiver<Result<TxReport>>) {
+        let (result, receiver) = oneshot::channel();
+        let job = WriteJob {
+            payload,
+            params,
+            submitted_at: seconds_since_the_epoch().unwrap_or_default(),
+            result,
+        };
+        (job, receiver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(capacity: usize) -> WriteQueue {
+        WriteQueue {
+            queue: Arc::new(FairQueue {
+                state: Mutex::new(FairState {
+                    tenants: Default::default(),
+                    rotation: Default::default(),
+                    served: 0,
+                    weights: Default::default(),
+                    closed: false,
+                }),
+                ready: Condvar::new(),
+                capacity,
+            }),
+        }
+    }
+
+    fn push(queue: &FairQueue, tenant: &str, payload: &str) -> Result<()> {
+        let permit = queue
+            .room(tenant)
+            .unwrap()
+            .try_acquire_owned()
+            .map_err(|_| WriteQueueFull)?;
+        let (result, _) = oneshot::channel();
+        let job = WriteJob {
+            payload: payload.to_string(),
+            params: Default::default(),
+            submitted_at: 0.,
+            result,
+        };
+        queue.push(tenant, job, permit)
+    }
+
+    #[test]
+    fn tenants_take_turns() {
+        let q = queue(10);
+        for i in 0..5 {
+            push(&q.queue, "bulk", &format!("b{i}")).unwrap();
+        }
+        push(&q.queue, "user", "u0").unwrap();
+        push(&q.queue, "user", "u1").unwrap();
+        push(&q.queue, "other", "o0").unwrap();
+        q.queue
+            .state
+            .lock()
+            .unwrap()
+            .weights
+            .insert("bulk".to_string(), 2);
+        let order = (0..8)
+            .map(|_| q.queue.pop().unwrap().payload)
+            .collect::<Vec<_>>();
+        assert_eq!(order, ["b0", "b1", "u0", "o0", "b2", "b3", "u1", "b4"]);
+        assert!(q.queue.state.lock().unwrap().tenants.is_empty());
+
+        // the room of a tenant is its own
+        for i in 0..10 {
+            push(&q.queue, "bulk", &format!("b{i}")).unwrap();
+        }
+        assert!(push(&q.queue, "bulk", "b10").is_err());
+        push(&q.queue, "user", "u0").unwrap();
+        q.queue.pop().unwrap();
+        push(&q.queue, "bulk", "b10").unwrap();
+
+        // queued writes are still taken once the queue is stopped
+        let inner = q.queue.clone();
+        drop(q);
+        assert!(inner.room("user").is_none());
+        assert_eq!((0..11).filter_map(|_| inner.pop()).count(), 11);
+        assert!(inner.pop().is_none());
+    }
+}
@@ -1155,6 +1155,35 @@ impl NormalAggrObj for AggrBitXor {
     }
 }
 
+/// The names of the aggregations resolved by [`parse_aggr`].
+pub(crate) const BUILTIN_AGGREGATIONS: &[&str] = &[
+    "and",
+    "or",
+    "unique",
+    "group_count",
+    "union",
+    "intersection",
+    "count",
+    "count_unique",
+    "variance",
+    "std_dev",
+    "sum",
+    "product",
+    "min",
+    "max",
+    "mean",
+    "choice",
+    "collect",
+    "shortest",
+    "min_cost",
+    "bit_and",
+    "bit_or",
+    "bit_xor",
+    "latest_by",
+    "smallest_by",
+    "choice_rand",
+];
+
 pub(crate) fn parse_aggr(name: &str) -> Option<&'static Aggregation> {
     Some(match name {
         "and" => &AGGR_AND,
@@ -0,0 +1,135 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use miette::{bail, Diagnostic, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::data::json::JsonValue;
+use crate::{DataValue, NamedRows};
+
+/// A type built from a row of a query result, see [`NamedRows::into_entities`].
+///
+/// With the `derive` feature this can be derived for structs with named fields, which
+/// read each field from the column of the same name, or from the column given by
+/// `#[cozo(rename = "...")]`.
+pub trait FromEntity: Sized {
+    /// Build a value from `row`, whose columns are named by `headers`.
+    fn from_entity(headers: &[String], row: &[DataValue]) -> Result<Self>;
+}
+
+/// A type written as a row of a stored relation, see [`NamedRows::from_entities`].
+///
+/// With the `derive` feature this can be derived for structs with named fields, in the
+/// same manner as [`FromEntity`].
+pub trait IntoTx {
+    /// The columns the values are written to.
+    fn columns() -> Vec<String>;
+    /// The values of the columns, in the order of [`columns`](Self::columns).
+    fn into_row(self) -> Result<Vec<DataValue>>;
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot convert column '{0}': {1}")]
+#[diagnostic(code(entity::bad_column))]
+struct BadColumn(String, String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot convert a value to or from a Rust type: {0}")]
+#[diagnostic(code(entity::bad_value))]
+struct BadValue(String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot deserialize row {row} of the result: {message}")]
+#[diagnostic(code(db::bad_query_row))]
+struct BadQueryRow {
+    row: usize,
+    message: String,
+    #[help]
+    columns: String,
+}
+
+/// Convert a serializable value to a [`DataValue`], by way of its JSON form.
+/// Structs and maps become JSON values, sequences become lists.
+pub fn to_data_value<T: Serialize + ?Sized>(value: &T) -> Result<DataValue> {
+    match serde_json::to_value(value) {
+        Ok(json) => Ok(DataValue::from(json)),
+        Err(err) => bail!(BadValue(err.to_string())),
+    }
+}
+
+/// Convert a [`DataValue`] to a deserializable value, by way of its JSON form.
+pub fn from_data_value<T: DeserializeOwned>(value: DataValue) -> Result<T> {
+    match serde_json::from_value(JsonValue::from(value)) {
+        Ok(v) => Ok(v),
+        Err(err) => bail!(BadValue(err.to_string())),
+    }
+}
+
+/// Read the column `column` of `row`. A missing column is read as null, so that it is
+/// accepted by `Option` fields only. Used by the derived [`FromEntity`].
+pub fn entity_column<T: DeserializeOwned>(
+    headers: &[String],
+    row: &[DataValue],
+    column: &str,
+) -> Result<T> {
+    let value = headers
+        .iter()
+        .position(|h| h == column)
+        .and_then(|i| row.get(i))
+        .cloned()
+        .unwrap_or(DataValue::Null);
+    match serde_json::from_value(JsonValue::from(value)) {
+        Ok(v) => Ok(v),
+        Err(err) => bail!(BadColumn(column.to_string(), err.to_string())),
+    }
+}
+
+impl NamedRows {
+    /// Build a value from each row.
+    pub fn into_entities<T: FromEntity>(self) -> Result<Vec<T>> {
+        self.rows
+            .iter()
+            .map(|row| T::from_entity(&self.headers, row))
+            .collect()
+    }
+    /// Deserialize each row as a map from the column names to the values, so that the
+    /// columns are matched to the fields of structs by name. Missing columns are only
+    /// accepted by `Option` fields, and extra columns are ignored unless the type denies
+    /// unknown fields.
+    pub fn deserialize_rows<T: DeserializeOwned>(self) -> Result<Vec<T>> {
+        let mut ret = Vec::with_capacity(self.rows.len());
+        for (i, row) in self.rows.into_iter().enumerate() {
+            let obj: serde_json::Map<_, _> = self
+                .headers
+                .iter()
+                .cloned()
+                .zip(row.into_iter().map(JsonValue::from))
+                .collect();
+            match serde_json::from_value(JsonValue::Object(obj)) {
+                Ok(v) => ret.push(v),
+                Err(err) => bail!(BadQueryRow {
+                    row: i,
+                    message: err.to_string(),
+                    columns: format!("The columns of the result are: {}", self.headers.join(", ")),
+                }),
+            }
+        }
+        Ok(ret)
+    }
+    /// Rows holding `entities`, as written to a relation by
+    /// [`import_relations`](crate::Db::import_relations).
+    pub fn from_entities<T: IntoTx>(entities: impl IntoIterator<Item = T>) -> Result<Self> {
+        let rows = entities
+            .into_iter()
+            .map(|entity| entity.into_row())
+            .collect::<Result<_>>()?;
+        Ok(NamedRows::new(T::columns(), rows))
+    }
+}
@@ -790,6 +790,149 @@ impl Debug for Op {
     }
 }
 
+/// The names of the functions resolved by [`get_op`].
+pub(crate) const BUILTIN_FUNCTIONS: &[&str] = &[
+    "coalesce",
+    "list",
+    "json",
+    "set_json_path",
+    "remove_json_path",
+    "parse_json",
+    "dump_json",
+    "json_object",
+    "is_json",
+    "json_to_scalar",
+    "counter_value",
+    "register_value",
+    "add",
+    "sub",
+    "mul",
+    "div",
+    "minus",
+    "abs",
+    "signum",
+    "floor",
+    "ceil",
+    "round",
+    "mod",
+    "max",
+    "min",
+    "pow",
+    "sqrt",
+    "exp",
+    "exp2",
+    "ln",
+    "log2",
+    "log10",
+    "sin",
+    "cos",
+    "tan",
+    "asin",
+    "acos",
+    "atan",
+    "atan2",
+    "sinh",
+    "cosh",
+    "tanh",
+    "asinh",
+    "acosh",
+    "atanh",
+    "eq",
+    "neq",
+    "gt",
+    "ge",
+    "lt",
+    "le",
+    "or",
+    "and",
+    "negate",
+    "bit_and",
+    "bit_or",
+    "bit_not",
+    "bit_xor",
+    "pack_bits",
+    "unpack_bits",
+    "concat",
+    "str_includes",
+    "lowercase",
+    "uppercase",
+    "trim",
+    "trim_start",
+    "trim_end",
+    "starts_with",
+    "ends_with",
+    "is_null",
+    "is_int",
+    "is_float",
+    "is_num",
+    "is_string",
+    "is_list",
+    "is_bytes",
+    "is_in",
+    "is_finite",
+    "is_infinite",
+    "is_nan",
+    "is_uuid",
+    "is_vec",
+    "length",
+    "sorted",
+    "reverse",
+    "append",
+    "prepend",
+    "unicode_normalize",
+    "haversine",
+    "haversine_deg_input",
+    "deg_to_rad",
+    "rad_to_deg",
+    "get",
+    "maybe_get",
+    "chars",
+    "slice_string",
+    "from_substrings",
+    "slice",
+    "regex_matches",
+    "regex_replace",
+    "regex_replace_all",
+    "regex_extract",
+    "regex_extract_first",
+    "t2s",
+    "encode_base64",
+    "decode_base64",
+    "first",
+    "last",
+    "chunks",
+    "chunks_exact",
+    "windows",
+    "to_int",
+    "to_float",
+    "to_string",
+    "l2_dist",
+    "l2_normalize",
+    "ip_dist",
+    "cos_dist",
+    "int_range",
+    "rand_float",
+    "rand_bernoulli",
+    "rand_int",
+    "rand_choose",
+    "assert",
+    "union",
+    "intersection",
+    "difference",
+    "to_uuid",
+    "to_bool",
+    "to_unity",
+    "rand_uuid_v1",
+    "rand_uuid_v4",
+    "uuid_timestamp",
+    "validity",
+    "now",
+    "format_timestamp",
+    "parse_timestamp",
+    "vec",
+    "rand_vec",
+];
+
 pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
     Some(match name {
         "coalesce" => &OP_COALESCE,
@@ -802,6 +945,8 @@ pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
         "json_object" => &OP_JSON_OBJECT,
         "is_json" => &OP_IS_JSON,
         "json_to_scalar" => &OP_JSON_TO_SCALAR,
+        "counter_value" => &OP_COUNTER_VALUE,
+        "register_value" => &OP_REGISTER_VALUE,
         "add" => &OP_ADD,
         "sub" => &OP_SUB,
         "mul" => &OP_MUL,
@@ -33,6 +33,7 @@ use crate::data::relation::VecElementType;
 use crate::data::value::{
     DataValue, JsonData, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs, Vector,
 };
+use crate::runtime::crdt::is_register_state;
 
 macro_rules! define_op {
     ($name:ident, $min_arity:expr, $vararg:expr) => {
@@ -321,6 +322,37 @@ pub(crate) fn op_json_to_scalar(args: &[DataValue]) -> Result<DataValue> {
     })
 }
 
+define_op!(OP_COUNTER_VALUE, 1, false);
+pub(crate) fn op_counter_value(args: &[DataValue]) -> Result<DataValue> {
+    Ok(match &args[0] {
+        DataValue::Json(JsonData(Value::Object(o))) => {
+            let mut total = 0i64;
+            for count in o.values() {
+                let count = count
+                    .as_i64()
+                    .ok_or_else(|| miette!("'counter_value' expects a counter"))?;
+                total = total
+                    .checked_add(count)
+                    .ok_or_else(|| miette!("'counter_value' overflowed"))?;
+            }
+            DataValue::from(total)
+        }
+        d @ (DataValue::Num(Num::Int(_)) | DataValue::Null) => d.clone(),
+        _ => bail!("'counter_value' expects a counter"),
+    })
+}
+
+define_op!(OP_REGISTER_VALUE, 1, false);
+pub(crate) fn op_register_value(args: &[DataValue]) -> Result<DataValue> {
+    Ok(match &args[0] {
+        DataValue::Json(JsonData(Value::Object(o))) if is_register_state(&args[0]) => {
+            json2val(o["value"].clone())
+        }
+        DataValue::Null => DataValue::Null,
+        _ => bail!("'register_value' expects a register"),
+    })
+}
+
 define_op!(OP_IS_IN, 2, false);
 pub(crate) fn op_is_in(args: &[DataValue]) -> Result<DataValue> {
     let left = &args[0];
@@ -24,7 +24,7 @@ const FALSE_TAG: u8 = 0x02;
 const TRUE_TAG: u8 = 0x03;
 const VEC_TAG: u8 = 0x04;
 const NUM_TAG: u8 = 0x05;
-const STR_TAG: u8 = 0x06;
+pub(crate) const STR_TAG: u8 = 0x06;
 const BYTES_TAG: u8 = 0x07;
 const UUID_TAG: u8 = 0x08;
 const REGEX_TAG: u8 = 0x09;
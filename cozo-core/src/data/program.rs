@@ -11,6 +11,7 @@ use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Display, Formatter};
 use std::sync::Arc;
 
+use itertools::Itertools;
 use miette::{bail, ensure, miette, Diagnostic, Result};
 use smallvec::SmallVec;
 use smartstring::{LazyCompact, SmartString};
@@ -21,6 +22,7 @@ use crate::data::expr::Expr;
 use crate::data::relation::StoredRelationMetadata;
 use crate::data::symb::{Symbol, PROG_ENTRY};
 use crate::data::value::{DataValue, ValidityTs};
+use crate::fixed_rule::utilities::constant::Constant;
 use crate::fixed_rule::{FixedRule, FixedRuleHandle};
 use crate::fts::FtsIndexManifest;
 use crate::parse::SourceSpan;
@@ -33,6 +35,7 @@ use crate::runtime::relation::{
 };
 use crate::runtime::temp_store::EpochStore;
 use crate::runtime::transact::SessionTx;
+use crate::NamedRows;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub(crate) enum QueryAssertion {
@@ -55,6 +58,14 @@ pub(crate) struct QueryOutOptions {
     pub(crate) sorters: Vec<(Symbol, SortDir)>,
     pub(crate) store_relation: Option<(InputRelationHandle, RelationOp, ReturnMutation)>,
     pub(crate) assertion: Option<QueryAssertion>,
+    /// With `:debug`, the number of rows kept of each rule in each epoch
+    pub(crate) debug: Option<usize>,
+    /// With `:checksum`, where the option is given: a checksum of the rows is returned
+    /// instead of them
+    pub(crate) checksum: Option<SourceSpan>,
+    /// With `:disable_plan_limits`, the plan is not checked against the limits set by
+    /// `Db::set_plan_limits`
+    pub(crate) disable_plan_limits: bool,
 }
 
 impl Debug for QueryOutOptions {
@@ -115,6 +126,9 @@ impl Display for QueryOutOptions {
                 RelationOp::Rm => {
                     write!(f, ":rm ")?;
                 }
+                RelationOp::Retract => {
+                    write!(f, ":retract ")?;
+                }
                 RelationOp::Delete => {
                     write!(f, ":delete ")?;
                 }
@@ -158,6 +172,15 @@ impl Display for QueryOutOptions {
             writeln!(f, "}};")?;
         }
 
+        if let Some(cap) = self.debug {
+            writeln!(f, ":debug {cap};")?;
+        }
+        if self.checksum.is_some() {
+            writeln!(f, ":checksum;")?;
+        }
+        if self.disable_plan_limits {
+            writeln!(f, ":disable_plan_limits;")?;
+        }
         if let Some(a) = &self.assertion {
             match a {
                 QueryAssertion::AssertNone(_) => {
@@ -197,6 +220,7 @@ pub(crate) enum RelationOp {
     Insert,
     Update,
     Rm,
+    Retract,
     Delete,
     Ensure,
     EnsureNot,
@@ -465,6 +489,7 @@ pub(crate) struct InputProgram {
     pub(crate) prog: BTreeMap<Symbol, InputInlineRulesOrFixed>,
     pub(crate) out_opts: QueryOutOptions,
     pub(crate) disable_magic_rewrite: bool,
+    pub(crate) include_retired: bool,
 }
 
 impl Display for InputProgram {
@@ -473,7 +498,11 @@ impl Display for InputProgram {
             match rules {
                 InputInlineRulesOrFixed::Rules { rules, .. } => {
                     for InputInlineRule {
-                        head, aggr, body, ..
+                        head,
+                        aggr,
+                        body,
+                        hints,
+                        ..
                     } in rules
                     {
                         write!(f, "{name}[")?;
@@ -499,6 +528,9 @@ impl Display for InputProgram {
                             }
                             write!(f, "{atom}")?;
                         }
+                        if !hints.is_empty() {
+                            write!(f, " @hint({hints})")?;
+                        }
                         writeln!(f, ";")?;
                     }
                 }
@@ -554,7 +586,71 @@ struct EntryHeadNotExplicitlyDefinedError(#[label] SourceSpan);
 #[diagnostic(help("You need to have one rule named '?'"))]
 pub(crate) struct NoEntryError;
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot bind the input relation '{0}' as the query defines a rule of that name")]
+#[diagnostic(code(eval::input_relation_conflict))]
+struct InputRelationConflict(String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The input relation '{0}' has no columns")]
+#[diagnostic(code(eval::input_relation_no_columns))]
+struct InputRelationNoColumns(String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The input relation '{0}' has {1} columns but rows of length {2}")]
+#[diagnostic(code(eval::input_relation_arity))]
+struct InputRelationArity(String, usize, usize);
+
 impl InputProgram {
+    /// Bind `data` as the constant rule `name`, as if the query contained
+    /// `name[headers...] <- rows`.
+    pub(crate) fn bind_input(&mut self, name: &str, data: NamedRows) -> Result<()> {
+        let name = Symbol::new(name, Default::default());
+        ensure!(
+            !name.is_prog_entry() && !self.prog.contains_key(&name),
+            InputRelationConflict(name.to_string())
+        );
+        ensure!(
+            !data.headers.is_empty(),
+            InputRelationNoColumns(name.to_string())
+        );
+        let head = data
+            .headers
+            .iter()
+            .map(|h| Symbol::new(h, Default::default()))
+            .collect_vec();
+        let rows = data.rows.into_iter().map(DataValue::List).collect();
+        let mut options = BTreeMap::new();
+        options.insert(
+            SmartString::from("data"),
+            Expr::Const {
+                val: DataValue::List(rows),
+                span: Default::default(),
+            },
+        );
+        let fixed_impl = Box::new(Constant);
+        fixed_impl.init_options(&mut options, Default::default())?;
+        let arity = fixed_impl.arity(&options, &head, Default::default())?;
+        ensure!(
+            arity == head.len(),
+            InputRelationArity(name.to_string(), head.len(), arity)
+        );
+        self.prog.insert(
+            name,
+            InputInlineRulesOrFixed::Fixed {
+                fixed: FixedRuleApply {
+                    fixed_handle: FixedRuleHandle::new("Constant", Default::default()),
+                    rule_args: vec![],
+                    options: Arc::new(options),
+                    head,
+                    arity,
+                    span: Default::default(),
+                    fixed_impl: Arc::new(fixed_impl),
+                },
+            },
+        );
+        Ok(())
+    }
     pub(crate) fn needs_write_lock(&self) -> Option<SmartString<LazyCompact>> {
         if let Some((h, _, _)) = &self.out_opts.store_relation {
             if !h.name.name.starts_with('_') {
@@ -645,7 +741,7 @@ impl InputProgram {
                             inner: rule.body,
                             span: rule.span,
                         }
-                            .disjunctive_normal_form(tx)?;
+                            .disjunctive_normal_form(tx, self.include_retired)?;
                         let mut new_head = Vec::with_capacity(rule.head.len());
                         let mut seen: BTreeMap<&Symbol, Vec<Symbol>> = BTreeMap::default();
                         for symb in rule.head.iter() {
@@ -680,6 +776,7 @@ impl InputProgram {
                                 head: new_head.clone(),
                                 aggr: rule.aggr.clone(),
                                 body,
+                                hints: rule.hints.clone(),
                             };
                             collected_rules.push(normalized_rule.convert_to_well_ordered_rule()?);
                         }
@@ -878,11 +975,65 @@ impl MagicSymbol {
     }
 }
 
+/// Hints overriding the planning of a rule clause, given by `@hint(...)` after the body
+/// of the clause, or by the `:hint` option for all clauses of a query.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct ClauseHints {
+    /// Evaluate the atoms in the order written, instead of moving filters to where
+    /// their variables are bound
+    pub(crate) fixed_order: bool,
+    /// Read stored relations by scanning them, never through their indices
+    pub(crate) no_index: bool,
+    /// Leave the rule of the clause out of the rewrite by magic sets
+    pub(crate) no_magic: bool,
+    /// The index to read each stored relation through, by relation name
+    pub(crate) indices: BTreeMap<SmartString<LazyCompact>, SmartString<LazyCompact>>,
+}
+
+impl ClauseHints {
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+    /// Add the hints of `other`, keeping the indices already chosen.
+    pub(crate) fn merge(&mut self, other: &Self) {
+        self.fixed_order |= other.fixed_order;
+        self.no_index |= other.no_index;
+        self.no_magic |= other.no_magic;
+        for (rel, idx) in &other.indices {
+            self.indices
+                .entry(rel.clone())
+                .or_insert_with(|| idx.clone());
+        }
+    }
+}
+
+impl Display for ClauseHints {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let flags = [
+            (self.fixed_order, "fixed_order"),
+            (self.no_index, "no_index"),
+            (self.no_magic, "no_magic"),
+        ];
+        let hints = flags
+            .iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, name)| name.to_string())
+            .chain(
+                self.indices
+                    .iter()
+                    .map(|(rel, idx)| format!("index({rel}:{idx})")),
+            )
+            .join(", ");
+        write!(f, "{hints}")
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct InputInlineRule {
     pub(crate) head: Vec<Symbol>,
     pub(crate) aggr: Vec<Option<(Aggregation, Vec<DataValue>)>>,
     pub(crate) body: Vec<InputAtom>,
+    pub(crate) hints: ClauseHints,
     pub(crate) span: SourceSpan,
 }
 
@@ -891,6 +1042,7 @@ pub(crate) struct NormalFormInlineRule {
     pub(crate) head: Vec<Symbol>,
     pub(crate) aggr: Vec<Option<(Aggregation, Vec<DataValue>)>>,
     pub(crate) body: Vec<NormalFormAtom>,
+    pub(crate) hints: ClauseHints,
 }
 
 #[derive(Debug)]
@@ -898,6 +1050,7 @@ pub(crate) struct MagicInlineRule {
     pub(crate) head: Vec<Symbol>,
     pub(crate) aggr: Vec<Option<(Aggregation, Vec<DataValue>)>>,
     pub(crate) body: Vec<MagicAtom>,
+    pub(crate) hints: ClauseHints,
 }
 
 impl MagicInlineRule {
@@ -1083,11 +1236,7 @@ impl SearchInput {
         }
 
         if let Some((name, _)) = self.bindings.pop_first() {
-            bail!(NamedFieldNotFound(
-                self.relation.name.to_string(),
-                name.to_string(),
-                self.span
-            ));
+            bail!(NamedFieldNotFound::new(&base_handle, &name, self.span));
         }
 
         #[derive(Debug, Error, Diagnostic)]
@@ -1227,11 +1376,7 @@ impl SearchInput {
         }
 
         if let Some((name, _)) = self.bindings.pop_first() {
-            bail!(NamedFieldNotFound(
-                self.relation.name.to_string(),
-                name.to_string(),
-                self.span
-            ));
+            bail!(NamedFieldNotFound::new(&base_handle, &name, self.span));
         }
 
         #[derive(Debug, Error, Diagnostic)]
@@ -1390,11 +1535,7 @@ impl SearchInput {
         }
 
         if let Some((name, _)) = self.bindings.pop_first() {
-            bail!(NamedFieldNotFound(
-                self.relation.name.to_string(),
-                name.to_string(),
-                self.span
-            ));
+            bail!(NamedFieldNotFound::new(&base_handle, &name, self.span));
         }
 
         #[derive(Debug, Error, Diagnostic)]
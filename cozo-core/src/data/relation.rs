@@ -72,6 +72,8 @@ impl Display for NullableColType {
             ColType::Json => {
                 f.write_str("Json")?;
             }
+            ColType::Counter => f.write_str("Counter")?,
+            ColType::Register => f.write_str("Register")?,
         }
         if self.nullable {
             f.write_str("?")?;
@@ -100,6 +102,8 @@ pub enum ColType {
     Tuple(Vec<NullableColType>),
     Validity,
     Json,
+    Counter,
+    Register,
 }
 
 #[derive(
@@ -452,6 +456,21 @@ impl NullableColType {
                     json!(null)
                 }
             })),
+            // increments, or counter states of other replicas
+            ColType::Counter => match &data {
+                DataValue::Num(Num::Int(i)) if *i >= 0 => data,
+                DataValue::Json(JsonData(serde_json::Value::Object(o)))
+                    if o.values().all(|c| c.as_i64().is_some_and(|c| c >= 0)) =>
+                {
+                    data
+                }
+                _ => bail!(make_err()),
+            },
+            ColType::Register => NullableColType {
+                coltype: ColType::Any,
+                nullable: true,
+            }
+            .coerce(data, cur_vld)?,
         })
     }
 }
@@ -0,0 +1,222 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::fmt::{Debug, Display, Formatter};
+
+use miette::{Diagnostic, Report};
+
+use crate::parse::SourceSpan;
+
+/// Error returned by [`DbInstance`](crate::DbInstance) and
+/// [`MultiTransaction`](crate::MultiTransaction), classified by what went wrong so that
+/// applications can act on it. Every variant carries the full diagnostic report, which
+/// renders with source spans and help texts as before.
+pub enum CozoError {
+    /// The script could not be parsed. See [`CozoError::location`] for the line and column
+    Parse {
+        /// Where in the script the error is, as reported by the parser
+        span: Option<SourceSpan>,
+        /// The full report
+        report: Report,
+    },
+    /// The transaction conflicts with a concurrent one, or writes are frozen by
+    /// [`Db::freeze_writes`](crate::Db::freeze_writes): running it again may succeed
+    Conflict(Report),
+    /// The data breaks a constraint of the database: an `:insert` of an existing key,
+    /// an `:update` of a missing one, a failed assertion or registered constraint, a value
+    /// of the wrong type for its column, or a relation or index that already exists
+    ConstraintViolation(Report),
+    /// The storage engine failed, or the database could not be opened
+    Storage(Report),
+    /// The query or its session was killed, either because it timed out or explicitly
+    Timeout(Report),
+    /// A write was attempted from a read-only script or on a relation that forbids it
+    ReadOnly(Report),
+    /// The role of the session is not granted access to a column, may not write a row outside
+    /// of the row policy, or may not run the operation at all
+    PermissionDenied(Report),
+    /// A script of the principal went over its quota, see [`Quota`](crate::Quota)
+    QuotaExceeded(Report),
+    /// Any other error
+    Other(Report),
+}
+
+impl CozoError {
+    /// The underlying diagnostic report
+    pub fn report(&self) -> &Report {
+        match self {
+            CozoError::Parse { report, .. }
+            | CozoError::Conflict(report)
+            | CozoError::ConstraintViolation(report)
+            | CozoError::Storage(report)
+            | CozoError::Timeout(report)
+            | CozoError::ReadOnly(report)
+            | CozoError::PermissionDenied(report)
+            | CozoError::QuotaExceeded(report)
+            | CozoError::Other(report) => report,
+        }
+    }
+    /// Convert back into the underlying diagnostic report
+    pub fn into_report(self) -> Report {
+        match self {
+            CozoError::Parse { report, .. }
+            | CozoError::Conflict(report)
+            | CozoError::ConstraintViolation(report)
+            | CozoError::Storage(report)
+            | CozoError::Timeout(report)
+            | CozoError::ReadOnly(report)
+            | CozoError::PermissionDenied(report)
+            | CozoError::QuotaExceeded(report)
+            | CozoError::Other(report) => report,
+        }
+    }
+    /// The diagnostic code of the error, e.g. `parser::pest` or `tx::read_only`
+    pub fn code(&self) -> Option<String> {
+        diagnostic_code(self.report().as_ref())
+    }
+    /// Where in the script the error is. Available for errors raised by running a script
+    /// whose diagnostic points into it, which includes every parse error.
+    pub fn location(&self) -> Option<ErrorLocation> {
+        let report = self.report();
+        let span = *report.labels()?.next()?.inner();
+        let source = report.source_code()?;
+        let contents = source.read_span(&span, 0, 0).ok()?;
+        // with a line of context on both sides, the contents cover the whole line of the span
+        let around = source.read_span(&span, 1, 1).ok()?;
+        // the column reported by the contents is in bytes
+        let line_start = span
+            .offset()
+            .checked_sub(around.span().offset() + contents.column())?;
+        let rest = around.data().get(line_start..)?;
+        let line_len = rest.iter().position(|b| *b == b'\n').unwrap_or(rest.len());
+        Some(ErrorLocation {
+            offset: span.offset(),
+            len: span.len(),
+            line: contents.line() + 1,
+            column: String::from_utf8_lossy(rest.get(..contents.column())?)
+                .chars()
+                .count()
+                + 1,
+            snippet: String::from_utf8_lossy(&rest[..line_len])
+                .trim_end()
+                .to_string(),
+        })
+    }
+}
+
+/// Position of an error in the script it was raised for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorLocation {
+    /// Byte offset of the offending part of the script
+    pub offset: usize,
+    /// Length in bytes of the offending part, zero if it is a position
+    pub len: usize,
+    /// Line of the offending part, starting from 1
+    pub line: usize,
+    /// Column of the offending part in characters, starting from 1
+    pub column: usize,
+    /// The line of the script containing the offending part
+    pub snippet: String,
+}
+
+/// The code of a diagnostic, looking through wrapping diagnostics that carry none.
+fn diagnostic_code(mut diagnostic: &dyn Diagnostic) -> Option<String> {
+    loop {
+        if let Some(code) = diagnostic.code() {
+            return Some(code.to_string());
+        }
+        diagnostic = diagnostic.diagnostic_source()?;
+    }
+}
+
+fn is_conflict_code(code: &str) -> bool {
+    code.starts_with("rocksdb::kBusy") || code.starts_with("rocksdb::kTryAgain")
+}
+
+/// Whether the error is a conflict with a concurrent transaction.
+pub(crate) fn is_conflict(report: &Report) -> bool {
+    diagnostic_code(report.as_ref()).is_some_and(|code| is_conflict_code(&code))
+}
+
+fn first_span(diagnostic: &dyn Diagnostic) -> Option<SourceSpan> {
+    let label = diagnostic.labels()?.next()?;
+    Some(SourceSpan(label.offset(), label.len()))
+}
+
+impl From<Report> for CozoError {
+    fn from(report: Report) -> Self {
+        let code = match diagnostic_code(report.as_ref()) {
+            None => return CozoError::Other(report),
+            Some(code) => code,
+        };
+        let is = |prefix: &str| code.starts_with(prefix);
+        if is("parser::") {
+            CozoError::Parse {
+                span: first_span(report.as_ref()),
+                report,
+            }
+        } else if is_conflict_code(&code) || is("db::writes_frozen") {
+            CozoError::Conflict(report)
+        } else if is("eval::killed") || is("db::close_timeout") || is("db::session_killed") {
+            CozoError::Timeout(report)
+        } else if is("tx::read_only") || is("tx::insufficient_access_level") {
+            CozoError::ReadOnly(report)
+        } else if is("tx::column_not_granted")
+            || is("tx::restricted_session")
+            || is("tx::row_policy_violation")
+            || is("tx::row_policy_not_on_index")
+        {
+            CozoError::PermissionDenied(report)
+        } else if is("db::quota_exceeded") {
+            CozoError::QuotaExceeded(report)
+        } else if is("transact::assertion_failure")
+            || is("transact::constraint_violated")
+            || is("eval::assert_")
+            || is("eval::coercion_")
+            || is("eval::col_type_mismatch")
+            || is("eval::required_col_not_provided")
+            || is("eval::stored_relation_conflict")
+            || is("eval::rel_name_conflict")
+            || is("tx::index_already_exists")
+        {
+            CozoError::ConstraintViolation(report)
+        } else if is("rocksdb::")
+            || is("storage::")
+            || is("db::init")
+            || is("db::already_open")
+        {
+            CozoError::Storage(report)
+        } else {
+            CozoError::Other(report)
+        }
+    }
+}
+
+impl From<CozoError> for Report {
+    fn from(err: CozoError) -> Self {
+        err.into_report()
+    }
+}
+
+impl Display for CozoError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self.report(), f)
+    }
+}
+
+impl Debug for CozoError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.report(), f)
+    }
+}
+
+impl std::error::Error for CozoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.report().source()
+    }
+}
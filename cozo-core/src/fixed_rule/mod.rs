@@ -22,7 +22,7 @@ use miette::{bail, ensure, Diagnostic, Report, Result};
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
-use crate::data::expr::Expr;
+use crate::data::expr::{eval_bytecode_pred, Bytecode, Expr};
 use crate::data::program::{
     FixedRuleOptionNotFoundError, MagicFixedRuleApply, MagicFixedRuleRuleArg, MagicSymbol,
     WrongFixedRuleOptionError,
@@ -35,6 +35,7 @@ use crate::fixed_rule::algos::*;
 use crate::fixed_rule::utilities::*;
 use crate::parse::SourceSpan;
 use crate::runtime::db::Poison;
+use crate::runtime::relation::{GrantMode, RelationHandle};
 use crate::runtime::temp_store::{EpochStore, RegularTempStore};
 use crate::runtime::transact::SessionTx;
 use crate::NamedRows;
@@ -43,6 +44,26 @@ use crate::NamedRows;
 pub(crate) mod algos;
 pub(crate) mod utilities;
 
+fn filter_by_policy(
+    it: TupleIter<'_>,
+    policy: Option<Vec<Bytecode>>,
+    span: SourceSpan,
+) -> TupleIter<'_> {
+    let policy = match policy {
+        None => return it,
+        Some(policy) => policy,
+    };
+    let mut stack = vec![];
+    Box::new(it.filter_map(move |tuple| match tuple {
+        Ok(tuple) => match eval_bytecode_pred(&policy, &tuple, &mut stack, span) {
+            Ok(true) => Some(Ok(tuple)),
+            Ok(false) => None,
+            Err(err) => Some(Err(err)),
+        },
+        Err(err) => Some(Err(err)),
+    }))
+}
+
 /// Passed into implementation of fixed rule, can be used to obtain relation inputs and options
 pub struct FixedRulePayload<'a, 'b> {
     pub(crate) manifest: &'a MagicFixedRuleApply,
@@ -92,12 +113,13 @@ impl<'a, 'b> FixedRuleInputRelation<'a, 'b> {
                 Box::new(store.all_iter().map(|t| Ok(t.into_tuple())))
             }
             MagicFixedRuleRuleArg::Stored { name, valid_at, .. } => {
-                let relation = self.tx.get_relation(name, false)?;
-                if let Some(valid_at) = valid_at {
+                let (relation, policy) = self.restricted_relation(name)?;
+                let it: TupleIter<'a> = if let Some(valid_at) = valid_at {
                     Box::new(relation.skip_scan_all(self.tx, *valid_at))
                 } else {
                     Box::new(relation.scan_all(self.tx))
-                }
+                };
+                filter_by_policy(it, policy, self.span())
             }
         })
     }
@@ -112,16 +134,38 @@ impl<'a, 'b> FixedRuleInputRelation<'a, 'b> {
                 Box::new(store.prefix_iter(&t).map(|t| Ok(t.into_tuple())))
             }
             MagicFixedRuleRuleArg::Stored { name, valid_at, .. } => {
-                let relation = self.tx.get_relation(name, false)?;
+                let (relation, policy) = self.restricted_relation(name)?;
                 let t = vec![prefix.clone()];
-                if let Some(valid_at) = valid_at {
+                let it: TupleIter<'_> = if let Some(valid_at) = valid_at {
                     Box::new(relation.skip_scan_prefix(self.tx, &t, *valid_at))
                 } else {
                     Box::new(relation.scan_prefix(self.tx, &t))
-                }
+                };
+                filter_by_policy(it, policy, self.span())
             }
         })
     }
+    /// The stored relation `name`, checking that the session may read all of its columns,
+    /// and its row policy applying to the session.
+    fn restricted_relation(
+        &self,
+        name: &Symbol,
+    ) -> Result<(RelationHandle, Option<Vec<Bytecode>>)> {
+        let relation = self.tx.get_relation(name, false)?;
+        self.tx.ensure_granted(
+            &relation,
+            relation
+                .metadata
+                .keys
+                .iter()
+                .chain(relation.metadata.non_keys.iter())
+                .map(|col| col.name.as_str()),
+            GrantMode::Read,
+            self.span(),
+        )?;
+        let policy = self.tx.row_policy_bytecode(&relation)?;
+        Ok((relation, policy))
+    }
     /// Get the source span of the input relation. Useful for generating informative error messages.
     pub fn span(&self) -> SourceSpan {
         self.arg_manifest.span()
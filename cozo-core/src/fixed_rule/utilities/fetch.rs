@@ -0,0 +1,220 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crossbeam::sync::ShardedLock;
+use itertools::Itertools;
+use miette::{bail, miette, Diagnostic, IntoDiagnostic, Result, WrapErr};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::expr::Expr;
+use crate::data::json::JsonValue;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{CannotDetermineArity, FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// The URLs the `FetchJson` fixed rule may request and how, set by
+/// [`Db::set_fetch_config`](crate::Db::set_fetch_config). By default no URL is allowed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchConfig {
+    /// The prefixes of the URLs allowed, such as `https://api.example.com/v1/`. A URL is
+    /// allowed if it starts with a prefix ending with `/`, or with a prefix followed by
+    /// `/`, `?`, `#` or nothing, so that `https://api.example.com` does not allow
+    /// `https://api.example.com.evil.org`
+    pub allowed_prefixes: Vec<String>,
+    /// Time allowed for each request, rounded up to whole seconds
+    pub timeout: Duration,
+    /// Size above which responses are rejected
+    pub max_response_bytes: usize,
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        Self {
+            allowed_prefixes: vec![],
+            timeout: Duration::from_secs(10),
+            max_response_bytes: 10 << 20,
+        }
+    }
+}
+
+impl FetchConfig {
+    fn allows(&self, url: &str) -> bool {
+        let is_http = url.starts_with("http://") || url.starts_with("https://");
+        is_http
+            && self.allowed_prefixes.iter().any(|prefix| {
+                url.strip_prefix(prefix.as_str()).is_some_and(|rest| {
+                    prefix.ends_with('/') || rest.is_empty() || rest.starts_with(['/', '?', '#'])
+                })
+            })
+    }
+}
+
+#[derive(Error, Diagnostic, Debug)]
+#[error("The URL {0} is not allowed by the fetch configuration")]
+#[diagnostic(code(eval::fetch_not_allowed))]
+#[diagnostic(help("Allow its prefix with `Db::set_fetch_config`"))]
+struct FetchNotAllowed(String, #[label] SourceSpan);
+
+#[derive(Error, Diagnostic, Debug)]
+#[error("The response from {0} is not valid: {1}")]
+#[diagnostic(code(eval::fetch_bad_response))]
+struct BadFetchResponse(String, String, #[label] SourceSpan);
+
+/// Requests a JSON document over HTTP and returns the fields of the objects it holds,
+/// within the limits of a [`FetchConfig`] shared with the database.
+pub(crate) struct FetchJson {
+    pub(crate) config: Arc<ShardedLock<FetchConfig>>,
+}
+
+impl FetchJson {
+    fn fetch(&self, url: &str, span: SourceSpan) -> Result<JsonValue> {
+        let config = self.config.read().unwrap().clone();
+        if !config.allows(url) {
+            bail!(FetchNotAllowed(url.to_string(), span))
+        }
+        let timeout = config.timeout.as_secs() + u64::from(config.timeout.subsec_nanos() > 0);
+        // redirects fail, as they could leave the allowed prefixes
+        let response = minreq::get(url)
+            .with_timeout(timeout.max(1))
+            .with_max_redirects(0)
+            .send_lazy()
+            .map_err(|e| miette!(e))
+            .wrap_err_with(|| format!("when requesting URL {url}"))?;
+        let bad = |message: String| BadFetchResponse(url.to_string(), message, span);
+        if !(200..300).contains(&response.status_code) {
+            bail!(bad(format!(
+                "status {} {}",
+                response.status_code, response.reason_phrase
+            )))
+        }
+        let mut body = vec![];
+        Read::take(response, config.max_response_bytes as u64 + 1)
+            .read_to_end(&mut body)
+            .into_diagnostic()?;
+        if body.len() > config.max_response_bytes {
+            bail!(bad(format!(
+                "larger than {} bytes",
+                config.max_response_bytes
+            )))
+        }
+        serde_json::from_slice(&body).map_err(|e| bad(e.to_string()).into())
+    }
+}
+
+impl FixedRule for FetchJson {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        _poison: Poison,
+    ) -> Result<()> {
+        let url = payload.string_option("url", None)?;
+        let pointer = payload.string_option("pointer", Some(""))?;
+        let null_if_absent = payload.bool_option("null_if_absent", Some(false))?;
+
+        #[derive(Error, Diagnostic, Debug)]
+        #[error("fields specification must be a list of strings")]
+        #[diagnostic(code(eval::algo_bad_fields))]
+        struct BadFields(#[label] SourceSpan);
+
+        let fields_expr = payload.expr_option("fields", None)?;
+        let fields_span = fields_expr.span();
+        let fields: Vec<_> = match fields_expr.eval_to_const()? {
+            DataValue::List(l) => l
+                .into_iter()
+                .map(|d| match d {
+                    DataValue::Str(s) => Ok(s),
+                    _ => Err(BadFields(fields_span)),
+                })
+                .try_collect()?,
+            _ => bail!(BadFields(fields_span)),
+        };
+
+        let span = payload.span();
+        let document = self.fetch(&url, span)?;
+        let bad = |message: String| BadFetchResponse(url.to_string(), message, span);
+        let found = match document.pointer(&pointer) {
+            Some(found) => found,
+            None => bail!(bad(format!("nothing found at the pointer '{pointer}'"))),
+        };
+        let objects = match found {
+            JsonValue::Array(items) => items.iter().collect_vec(),
+            obj => vec![obj],
+        };
+        for obj in objects {
+            let JsonValue::Object(obj) = obj else {
+                bail!(bad(format!("{obj} is not an object")))
+            };
+            let mut row = Vec::with_capacity(fields.len());
+            for field in &fields {
+                row.push(match obj.get(field as &str) {
+                    Some(v) => DataValue::from(v),
+                    None if null_if_absent => DataValue::Null,
+                    None => bail!(bad(format!("the field {field} is absent from {obj:?}"))),
+                });
+            }
+            out.put(row);
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        opts: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        span: SourceSpan,
+    ) -> Result<usize> {
+        let fields = opts.get("fields").ok_or_else(|| {
+            CannotDetermineArity(
+                "FetchJson".to_string(),
+                "option 'fields' not provided".to_string(),
+                span,
+            )
+        })?;
+        Ok(match fields.clone().eval_to_const()? {
+            DataValue::List(l) => l.len(),
+            _ => bail!(CannotDetermineArity(
+                "FetchJson".to_string(),
+                "invalid option 'fields' given, expect a list".to_string(),
+                span
+            )),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowed_prefixes() {
+        let config = FetchConfig {
+            allowed_prefixes: vec![
+                "https://api.example.com".to_string(),
+                "http://internal/v1/".to_string(),
+            ],
+            ..Default::default()
+        };
+        assert!(config.allows("https://api.example.com"));
+        assert!(config.allows("https://api.example.com/users?id=1"));
+        assert!(!config.allows("https://api.example.com.evil.org/users"));
+        assert!(!config.allows("https://api.example.com@evil.org/"));
+        assert!(config.allows("http://internal/v1/items"));
+        assert!(!config.allows("http://internal/v2/items"));
+        assert!(!FetchConfig::default().allows("https://api.example.com/"));
+    }
+}
@@ -8,8 +8,12 @@
 
 pub(crate) mod constant;
 pub(crate) mod csv;
+#[cfg(feature = "fetch")]
+pub(crate) mod fetch;
 pub(crate) mod jlines;
 pub(crate) mod reorder_sort;
+#[cfg(feature = "wasm-plugins")]
+pub(crate) mod wasm_plugin;
 
 pub(crate) use self::csv::CsvReader;
 pub(crate) use constant::Constant;
@@ -0,0 +1,1230 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! A decoder and interpreter for the numeric core of WebAssembly modules.
+//!
+//! Supported are the instructions of the MVP except `call_indirect`, together with the
+//! sign-extension, saturating conversion, `memory.copy` and `memory.fill` instructions that
+//! current compilers emit by default. The only imports allowed are the functions of the
+//! host interface, see [`HostFunc`].
+
+use std::collections::BTreeMap;
+
+use miette::{bail, ensure, Result};
+
+use crate::fixed_rule::utilities::wasm_plugin::WasmLimits;
+use crate::runtime::db::Poison;
+
+const PAGE_SIZE: usize = 65536;
+// bounds the memory taken by decoding hostile modules
+const MAX_LOCALS: usize = 50000;
+const POISON_CHECK_INTERVAL: u64 = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ValType {
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+impl ValType {
+    fn zero(self) -> Val {
+        match self {
+            ValType::I32 => Val::I32(0),
+            ValType::I64 => Val::I64(0),
+            ValType::F32 => Val::F32(0.),
+            ValType::F64 => Val::F64(0.),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Val {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+}
+
+impl Val {
+    fn ty(self) -> ValType {
+        match self {
+            Val::I32(_) => ValType::I32,
+            Val::I64(_) => ValType::I64,
+            Val::F32(_) => ValType::F32,
+            Val::F64(_) => ValType::F64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FuncType {
+    pub(crate) params: Vec<ValType>,
+    pub(crate) results: Vec<ValType>,
+}
+
+/// The functions of the host interface, which modules may import from the module `cozo`.
+/// Nothing else of the host is reachable: there is no access to files, the network, clocks
+/// or the database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum HostFunc {
+    /// `fail(ptr: i32, len: i32)`, aborts the query with the UTF-8 message in memory
+    Fail,
+    /// `log(ptr: i32, len: i32)`, logs the UTF-8 message in memory
+    Log,
+}
+
+impl HostFunc {
+    fn resolve(module: &str, field: &str) -> Option<Self> {
+        match (module, field) {
+            ("cozo", "fail") => Some(HostFunc::Fail),
+            ("cozo", "log") => Some(HostFunc::Log),
+            _ => None,
+        }
+    }
+    fn ty(self) -> FuncType {
+        FuncType {
+            params: vec![ValType::I32, ValType::I32],
+            results: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Op {
+    Unreachable,
+    Nop,
+    Block { params: usize, results: usize, end: usize },
+    Loop { params: usize },
+    If { params: usize, results: usize, else_: usize, end: usize },
+    Else { end: usize },
+    End,
+    Br(u32),
+    BrIf(u32),
+    BrTable(Box<[u32]>, u32),
+    Return,
+    Call(u32),
+    Drop,
+    Select,
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    Load(u8, u32),
+    Store(u8, u32),
+    MemorySize,
+    MemoryGrow,
+    MemoryCopy,
+    MemoryFill,
+    Const(Val),
+    /// The numeric instructions, from `0x45` to `0xc4`, by opcode
+    Numeric(u8),
+    /// The saturating conversions, prefixed by `0xfc`, by sub-opcode
+    TruncSat(u8),
+}
+
+#[derive(Debug)]
+struct Func {
+    ty: u32,
+    locals: Vec<ValType>,
+    code: Vec<Op>,
+}
+
+#[derive(Debug)]
+struct Global {
+    mutable: bool,
+    init: Val,
+}
+
+/// A decoded module.
+#[derive(Debug)]
+pub(crate) struct Module {
+    types: Vec<FuncType>,
+    imports: Vec<(HostFunc, u32)>,
+    funcs: Vec<Func>,
+    memory: Option<(u32, Option<u32>)>,
+    globals: Vec<Global>,
+    exports: BTreeMap<String, u32>,
+    data: Vec<(u32, Vec<u8>)>,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+    fn at_end(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+    fn byte(&mut self) -> Result<u8> {
+        let Some(b) = self.bytes.get(self.pos) else {
+            bail!("unexpected end of module at byte {}", self.pos)
+        };
+        self.pos += 1;
+        Ok(*b)
+    }
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        ensure!(
+            self.bytes.len() - self.pos >= len,
+            "unexpected end of module at byte {}",
+            self.pos
+        );
+        let slice = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+    fn u32(&mut self) -> Result<u32> {
+        let mut result: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let b = self.byte()?;
+            result |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                break;
+            }
+            shift += 7;
+            ensure!(shift < 35, "integer too long at byte {}", self.pos);
+        }
+        u32::try_from(result).map_err(|_| miette::miette!("integer too large at byte {}", self.pos))
+    }
+    fn signed(&mut self, bits: u32) -> Result<i64> {
+        let mut result: i64 = 0;
+        let mut shift = 0;
+        loop {
+            let b = self.byte()?;
+            result |= ((b & 0x7f) as i64) << shift;
+            shift += 7;
+            if b & 0x80 == 0 {
+                if shift < 64 && b & 0x40 != 0 {
+                    result |= -1i64 << shift;
+                }
+                break;
+            }
+            ensure!(shift < bits, "integer too long at byte {}", self.pos);
+        }
+        Ok(result)
+    }
+    fn len(&mut self) -> Result<usize> {
+        let len = self.u32()? as usize;
+        // every item takes at least a byte
+        ensure!(
+            len <= self.bytes.len() - self.pos,
+            "length {len} exceeds the module at byte {}",
+            self.pos
+        );
+        Ok(len)
+    }
+    fn name(&mut self) -> Result<String> {
+        let len = self.len()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| miette::miette!("name is not UTF-8"))
+    }
+    fn val_type(&mut self) -> Result<ValType> {
+        Ok(match self.byte()? {
+            0x7f => ValType::I32,
+            0x7e => ValType::I64,
+            0x7d => ValType::F32,
+            0x7c => ValType::F64,
+            b => bail!("value type 0x{b:02x} is not supported"),
+        })
+    }
+    fn val_types(&mut self) -> Result<Vec<ValType>> {
+        let len = self.len()?;
+        (0..len).map(|_| self.val_type()).collect()
+    }
+    fn limits(&mut self) -> Result<(u32, Option<u32>)> {
+        Ok(match self.byte()? {
+            0x00 => (self.u32()?, None),
+            0x01 => (self.u32()?, Some(self.u32()?)),
+            b => bail!("limits of kind 0x{b:02x} are not supported"),
+        })
+    }
+    fn const_expr(&mut self, globals: &[Global]) -> Result<Val> {
+        let val = match self.byte()? {
+            0x41 => Val::I32(self.signed(32)? as i32),
+            0x42 => Val::I64(self.signed(64)?),
+            0x43 => Val::F32(f32::from_le_bytes(self.take(4)?.try_into().unwrap())),
+            0x44 => Val::F64(f64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+            0x23 => {
+                let idx = self.u32()? as usize;
+                match globals.get(idx) {
+                    Some(g) => g.init,
+                    None => bail!("unknown global {idx} in constant expression"),
+                }
+            }
+            b => bail!("instruction 0x{b:02x} is not allowed in constant expressions"),
+        };
+        ensure!(self.byte()? == 0x0b, "constant expression is not terminated");
+        Ok(val)
+    }
+}
+
+impl Module {
+    /// Decode the binary module `bytes`, rejecting features and imports not supported.
+    pub(crate) fn decode(bytes: &[u8], limits: &WasmLimits) -> Result<Self> {
+        let mut r = Reader::new(bytes);
+        ensure!(
+            r.take(4).ok() == Some(b"\0asm"),
+            "not a WebAssembly binary module"
+        );
+        ensure!(
+            r.take(4)? == [1, 0, 0, 0],
+            "only version 1 of the WebAssembly binary format is supported"
+        );
+        let mut module = Module {
+            types: vec![],
+            imports: vec![],
+            funcs: vec![],
+            memory: None,
+            globals: vec![],
+            exports: Default::default(),
+            data: vec![],
+        };
+        let mut func_types = vec![];
+        while !r.at_end() {
+            let id = r.byte()?;
+            let size = r.len()?;
+            let mut s = Reader::new(r.take(size)?);
+            match id {
+                // custom, table, element and data count sections are not needed without
+                // `call_indirect` and `memory.init`
+                0 | 4 | 9 | 12 => continue,
+                1 => {
+                    for _ in 0..s.len()? {
+                        ensure!(s.byte()? == 0x60, "malformed function type");
+                        let params = s.val_types()?;
+                        let results = s.val_types()?;
+                        module.types.push(FuncType { params, results });
+                    }
+                }
+                2 => {
+                    for _ in 0..s.len()? {
+                        let mod_name = s.name()?;
+                        let field = s.name()?;
+                        ensure!(
+                            s.byte()? == 0x00,
+                            "the import {mod_name}.{field} is not a function, \
+                             only functions of the host interface may be imported"
+                        );
+                        let ty = s.u32()?;
+                        let Some(host) = HostFunc::resolve(&mod_name, &field) else {
+                            bail!(
+                                "the import {mod_name}.{field} is not provided by the host \
+                                 interface, which provides only cozo.fail and cozo.log"
+                            )
+                        };
+                        ensure!(
+                            module.types.get(ty as usize) == Some(&host.ty()),
+                            "the import {mod_name}.{field} must have the type [i32, i32] -> []"
+                        );
+                        module.imports.push((host, ty));
+                    }
+                }
+                3 => {
+                    for _ in 0..s.len()? {
+                        func_types.push(s.u32()?);
+                    }
+                }
+                5 => {
+                    let count = s.len()?;
+                    ensure!(
+                        count <= 1 && module.memory.is_none(),
+                        "at most one memory is supported"
+                    );
+                    if count == 1 {
+                        let (min, max) = s.limits()?;
+                        ensure!(
+                            min <= limits.max_memory_pages,
+                            "the module requires {min} pages of memory, \
+                             more than the limit of {} pages",
+                            limits.max_memory_pages
+                        );
+                        module.memory = Some((min, max));
+                    }
+                }
+                6 => {
+                    for _ in 0..s.len()? {
+                        let ty = s.val_type()?;
+                        let mutable = s.byte()? == 0x01;
+                        let init = s.const_expr(&module.globals)?;
+                        ensure!(init.ty() == ty, "global initialised with the wrong type");
+                        module.globals.push(Global { mutable, init });
+                    }
+                }
+                7 => {
+                    for _ in 0..s.len()? {
+                        let name = s.name()?;
+                        let kind = s.byte()?;
+                        let idx = s.u32()?;
+                        if kind == 0x00 {
+                            module.exports.insert(name, idx);
+                        }
+                    }
+                }
+                8 => bail!("start functions are not supported"),
+                10 => {
+                    let count = s.len()?;
+                    ensure!(
+                        count == func_types.len(),
+                        "the numbers of function declarations and bodies differ"
+                    );
+                    for ty in func_types.iter().copied() {
+                        let size = s.len()?;
+                        let mut body = Reader::new(s.take(size)?);
+                        let mut locals = vec![];
+                        for _ in 0..body.len()? {
+                            let n = body.u32()? as usize;
+                            ensure!(
+                                locals.len() + n <= MAX_LOCALS,
+                                "too many locals in a function"
+                            );
+                            let t = body.val_type()?;
+                            locals.extend(std::iter::repeat_n(t, n));
+                        }
+                        let code = decode_code(&mut body, &module.types)?;
+                        module.funcs.push(Func { ty, locals, code });
+                    }
+                }
+                11 => {
+                    for _ in 0..s.len()? {
+                        match s.u32()? {
+                            0 => {}
+                            // passive segments are only used by `memory.init`
+                            1 => {
+                                let len = s.len()?;
+                                s.take(len)?;
+                                continue;
+                            }
+                            2 => ensure!(s.u32()? == 0, "unknown memory in data segment"),
+                            k => bail!("data segment of kind {k} is not supported"),
+                        }
+                        let Val::I32(offset) = s.const_expr(&module.globals)? else {
+                            bail!("data segment offset is not an i32")
+                        };
+                        let len = s.len()?;
+                        module.data.push((offset as u32, s.take(len)?.to_vec()));
+                    }
+                }
+                id => bail!("unknown section {id}"),
+            }
+        }
+        ensure!(
+            module.funcs.len() == func_types.len(),
+            "the numbers of function declarations and bodies differ"
+        );
+        module.validate_indices()?;
+        Ok(module)
+    }
+
+    fn validate_indices(&self) -> Result<()> {
+        let n_funcs = self.imports.len() + self.funcs.len();
+        for func in &self.funcs {
+            ensure!(
+                (func.ty as usize) < self.types.len(),
+                "unknown type {}",
+                func.ty
+            );
+            for op in &func.code {
+                match op {
+                    Op::Call(idx) => {
+                        ensure!((*idx as usize) < n_funcs, "call to unknown function {idx}")
+                    }
+                    Op::GlobalGet(idx) | Op::GlobalSet(idx) => ensure!(
+                        (*idx as usize) < self.globals.len(),
+                        "unknown global {idx}"
+                    ),
+                    Op::Load(..)
+                    | Op::Store(..)
+                    | Op::MemorySize
+                    | Op::MemoryGrow
+                    | Op::MemoryCopy
+                    | Op::MemoryFill => {
+                        ensure!(self.memory.is_some(), "memory used but not declared")
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for (name, idx) in &self.exports {
+            ensure!(
+                (*idx as usize) < n_funcs,
+                "export {name} refers to unknown function {idx}"
+            );
+        }
+        for (offset, bytes) in &self.data {
+            let min = self.memory.map(|(min, _)| min as usize).unwrap_or(0);
+            ensure!(
+                *offset as usize + bytes.len() <= min * PAGE_SIZE,
+                "data segment out of bounds of memory"
+            );
+        }
+        Ok(())
+    }
+
+    /// The type of the exported function `name`, with its index.
+    pub(crate) fn export(&self, name: &str) -> Option<(u32, &FuncType)> {
+        let idx = *self.exports.get(name)?;
+        Some((idx, self.func_type(idx)))
+    }
+
+    pub(crate) fn exports(&self) -> impl Iterator<Item = &str> {
+        self.exports.keys().map(|k| k.as_str())
+    }
+
+    fn func_type(&self, idx: u32) -> &FuncType {
+        let idx = idx as usize;
+        let ty = match self.imports.get(idx) {
+            Some((_, ty)) => *ty,
+            None => self.funcs[idx - self.imports.len()].ty,
+        };
+        &self.types[ty as usize]
+    }
+}
+
+fn block_type(r: &mut Reader<'_>, types: &[FuncType]) -> Result<(usize, usize)> {
+    if let Some(b) = r.bytes.get(r.pos) {
+        match b {
+            0x40 => {
+                r.pos += 1;
+                return Ok((0, 0));
+            }
+            0x7c..=0x7f => {
+                r.pos += 1;
+                return Ok((0, 1));
+            }
+            _ => {}
+        }
+    }
+    let idx = r.signed(33)?;
+    match usize::try_from(idx).ok().and_then(|idx| types.get(idx)) {
+        Some(ty) => Ok((ty.params.len(), ty.results.len())),
+        None => bail!("unknown block type {idx}"),
+    }
+}
+
+fn decode_code(r: &mut Reader<'_>, types: &[FuncType]) -> Result<Vec<Op>> {
+    let mut code = vec![];
+    // the indices of the open `block`, `loop` and `if` instructions
+    let mut open: Vec<usize> = vec![];
+    loop {
+        let idx = code.len();
+        let opcode = r.byte()?;
+        let op = match opcode {
+            0x00 => Op::Unreachable,
+            0x01 => Op::Nop,
+            0x02 => {
+                let (params, results) = block_type(r, types)?;
+                open.push(idx);
+                Op::Block {
+                    params,
+                    results,
+                    end: 0,
+                }
+            }
+            0x03 => {
+                let (params, _) = block_type(r, types)?;
+                open.push(idx);
+                Op::Loop { params }
+            }
+            0x04 => {
+                let (params, results) = block_type(r, types)?;
+                open.push(idx);
+                Op::If {
+                    params,
+                    results,
+                    else_: 0,
+                    end: 0,
+                }
+            }
+            0x05 => {
+                match open.last().map(|i| &mut code[*i]) {
+                    Some(Op::If { else_, .. }) if *else_ == 0 => *else_ = idx,
+                    _ => bail!("else without if"),
+                }
+                Op::Else { end: 0 }
+            }
+            0x0b => {
+                match open.pop() {
+                    None => {
+                        code.push(Op::End);
+                        ensure!(r.at_end(), "code after the end of a function");
+                        return Ok(code);
+                    }
+                    Some(start) => {
+                        let mut else_at = None;
+                        match &mut code[start] {
+                            Op::Block { end, .. } => *end = idx,
+                            Op::If { else_, end, .. } => {
+                                if *else_ == 0 {
+                                    *else_ = idx;
+                                } else {
+                                    else_at = Some(*else_);
+                                }
+                                *end = idx;
+                            }
+                            _ => {}
+                        }
+                        if let Some(Op::Else { end }) = else_at.map(|i| &mut code[i]) {
+                            *end = idx;
+                        }
+                    }
+                }
+                Op::End
+            }
+            0x0c => Op::Br(r.u32()?),
+            0x0d => Op::BrIf(r.u32()?),
+            0x0e => {
+                let len = r.len()?;
+                let labels: Vec<_> = (0..len).map(|_| r.u32()).collect::<Result<_>>()?;
+                Op::BrTable(labels.into(), r.u32()?)
+            }
+            0x0f => Op::Return,
+            0x10 => Op::Call(r.u32()?),
+            0x11 => bail!("call_indirect is not supported"),
+            0x1a => Op::Drop,
+            0x1b => Op::Select,
+            0x1c => {
+                r.val_types()?;
+                Op::Select
+            }
+            0x20 => Op::LocalGet(r.u32()?),
+            0x21 => Op::LocalSet(r.u32()?),
+            0x22 => Op::LocalTee(r.u32()?),
+            0x23 => Op::GlobalGet(r.u32()?),
+            0x24 => Op::GlobalSet(r.u32()?),
+            0x28..=0x35 => {
+                r.u32()?;
+                Op::Load(opcode, r.u32()?)
+            }
+            0x36..=0x3e => {
+                r.u32()?;
+                Op::Store(opcode, r.u32()?)
+            }
+            0x3f => {
+                r.byte()?;
+                Op::MemorySize
+            }
+            0x40 => {
+                r.byte()?;
+                Op::MemoryGrow
+            }
+            0x41 => Op::Const(Val::I32(r.signed(32)? as i32)),
+            0x42 => Op::Const(Val::I64(r.signed(64)?)),
+            0x43 => Op::Const(Val::F32(f32::from_le_bytes(
+                r.take(4)?.try_into().unwrap(),
+            ))),
+            0x44 => Op::Const(Val::F64(f64::from_le_bytes(
+                r.take(8)?.try_into().unwrap(),
+            ))),
+            0x45..=0xc4 => Op::Numeric(opcode),
+            0xfc => match r.u32()? {
+                sub @ 0..=7 => Op::TruncSat(sub as u8),
+                10 => {
+                    r.byte()?;
+                    r.byte()?;
+                    Op::MemoryCopy
+                }
+                11 => {
+                    r.byte()?;
+                    Op::MemoryFill
+                }
+                sub => bail!("instruction 0xfc {sub} is not supported"),
+            },
+            b => bail!("instruction 0x{b:02x} is not supported"),
+        };
+        code.push(op);
+    }
+}
+
+struct Label {
+    /// The height of the value stack below the values of the block
+    height: usize,
+    /// The number of values a branch to the label carries
+    arity: usize,
+    /// Where a branch to the label continues
+    target: usize,
+    is_loop: bool,
+}
+
+/// An instance of a [`Module`], with its own memory and globals.
+pub(crate) struct Instance<'m> {
+    module: &'m Module,
+    memory: Vec<u8>,
+    max_pages: u32,
+    globals: Vec<Val>,
+    limits: WasmLimits,
+    fuel: u64,
+    executed: u64,
+    poison: Poison,
+    /// Messages passed to `cozo.log`
+    pub(crate) logs: Vec<String>,
+}
+
+fn pop(stack: &mut Vec<Val>) -> Result<Val> {
+    match stack.pop() {
+        Some(v) => Ok(v),
+        None => bail!("value stack underflow"),
+    }
+}
+
+macro_rules! pop_as {
+    ($name:ident, $variant:ident, $t:ty) => {
+        fn $name(stack: &mut Vec<Val>) -> Result<$t> {
+            match pop(stack)? {
+                Val::$variant(v) => Ok(v),
+                v => bail!("type mismatch: expected {}, found {:?}", stringify!($t), v),
+            }
+        }
+    };
+}
+
+pop_as!(pop_i32, I32, i32);
+pop_as!(pop_i64, I64, i64);
+pop_as!(pop_f32, F32, f32);
+pop_as!(pop_f64, F64, f64);
+
+macro_rules! wasm_min {
+    ($a:expr, $b:expr) => {
+        if $a.is_nan() || $b.is_nan() {
+            $a + $b
+        } else if $a == $b {
+            if $a.is_sign_negative() {
+                $a
+            } else {
+                $b
+            }
+        } else {
+            $a.min($b)
+        }
+    };
+}
+
+macro_rules! wasm_max {
+    ($a:expr, $b:expr) => {
+        if $a.is_nan() || $b.is_nan() {
+            $a + $b
+        } else if $a == $b {
+            if $a.is_sign_positive() {
+                $a
+            } else {
+                $b
+            }
+        } else {
+            $a.max($b)
+        }
+    };
+}
+
+/// Truncate `x` towards zero, trapping unless the result is within `[lo, hi)`.
+fn trunc(x: f64, lo: f64, hi: f64) -> Result<f64> {
+    ensure!(!x.is_nan(), "invalid conversion of NaN to integer");
+    let t = x.trunc();
+    ensure!(t >= lo && t < hi, "integer overflow in conversion of {x}");
+    Ok(t)
+}
+
+const I32_MIN: f64 = -2147483648.;
+const I32_END: f64 = 2147483648.;
+const U32_END: f64 = 4294967296.;
+const I64_MIN: f64 = -9223372036854775808.;
+const I64_END: f64 = 9223372036854775808.;
+const U64_END: f64 = 18446744073709551616.;
+
+fn numeric(op: u8, s: &mut Vec<Val>) -> Result<()> {
+    macro_rules! un {
+        ($pop:ident, $wrap:ident, |$a:ident| $e:expr) => {{
+            let $a = $pop(s)?;
+            s.push(Val::$wrap($e));
+        }};
+    }
+    macro_rules! bin {
+        ($pop:ident, $wrap:ident, |$a:ident, $b:ident| $e:expr) => {{
+            let $b = $pop(s)?;
+            let $a = $pop(s)?;
+            s.push(Val::$wrap($e));
+        }};
+    }
+    macro_rules! cmp {
+        ($pop:ident, |$a:ident, $b:ident| $e:expr) => {
+            bin!($pop, I32, |$a, $b| ($e) as i32)
+        };
+    }
+    match op {
+        0x45 => un!(pop_i32, I32, |a| (a == 0) as i32),
+        0x46 => cmp!(pop_i32, |a, b| a == b),
+        0x47 => cmp!(pop_i32, |a, b| a != b),
+        0x48 => cmp!(pop_i32, |a, b| a < b),
+        0x49 => cmp!(pop_i32, |a, b| (a as u32) < (b as u32)),
+        0x4a => cmp!(pop_i32, |a, b| a > b),
+        0x4b => cmp!(pop_i32, |a, b| (a as u32) > (b as u32)),
+        0x4c => cmp!(pop_i32, |a, b| a <= b),
+        0x4d => cmp!(pop_i32, |a, b| (a as u32) <= (b as u32)),
+        0x4e => cmp!(pop_i32, |a, b| a >= b),
+        0x4f => cmp!(pop_i32, |a, b| (a as u32) >= (b as u32)),
+        0x50 => un!(pop_i64, I32, |a| (a == 0) as i32),
+        0x51 => cmp!(pop_i64, |a, b| a == b),
+        0x52 => cmp!(pop_i64, |a, b| a != b),
+        0x53 => cmp!(pop_i64, |a, b| a < b),
+        0x54 => cmp!(pop_i64, |a, b| (a as u64) < (b as u64)),
+        0x55 => cmp!(pop_i64, |a, b| a > b),
+        0x56 => cmp!(pop_i64, |a, b| (a as u64) > (b as u64)),
+        0x57 => cmp!(pop_i64, |a, b| a <= b),
+        0x58 => cmp!(pop_i64, |a, b| (a as u64) <= (b as u64)),
+        0x59 => cmp!(pop_i64, |a, b| a >= b),
+        0x5a => cmp!(pop_i64, |a, b| (a as u64) >= (b as u64)),
+        0x5b => cmp!(pop_f32, |a, b| a == b),
+        0x5c => cmp!(pop_f32, |a, b| a != b),
+        0x5d => cmp!(pop_f32, |a, b| a < b),
+        0x5e => cmp!(pop_f32, |a, b| a > b),
+        0x5f => cmp!(pop_f32, |a, b| a <= b),
+        0x60 => cmp!(pop_f32, |a, b| a >= b),
+        0x61 => cmp!(pop_f64, |a, b| a == b),
+        0x62 => cmp!(pop_f64, |a, b| a != b),
+        0x63 => cmp!(pop_f64, |a, b| a < b),
+        0x64 => cmp!(pop_f64, |a, b| a > b),
+        0x65 => cmp!(pop_f64, |a, b| a <= b),
+        0x66 => cmp!(pop_f64, |a, b| a >= b),
+        0x67 => un!(pop_i32, I32, |a| a.leading_zeros() as i32),
+        0x68 => un!(pop_i32, I32, |a| a.trailing_zeros() as i32),
+        0x69 => un!(pop_i32, I32, |a| a.count_ones() as i32),
+        0x6a => bin!(pop_i32, I32, |a, b| a.wrapping_add(b)),
+        0x6b => bin!(pop_i32, I32, |a, b| a.wrapping_sub(b)),
+        0x6c => bin!(pop_i32, I32, |a, b| a.wrapping_mul(b)),
+        0x6d..=0x70 => {
+            let b = pop_i32(s)?;
+            let a = pop_i32(s)?;
+            ensure!(b != 0, "integer divide by zero");
+            s.push(Val::I32(match op {
+                0x6d => a
+                    .checked_div(b)
+                    .ok_or_else(|| miette::miette!("integer overflow"))?,
+                0x6e => ((a as u32) / (b as u32)) as i32,
+                0x6f => a.wrapping_rem(b),
+                _ => ((a as u32) % (b as u32)) as i32,
+            }));
+        }
+        0x71 => bin!(pop_i32, I32, |a, b| a & b),
+        0x72 => bin!(pop_i32, I32, |a, b| a | b),
+        0x73 => bin!(pop_i32, I32, |a, b| a ^ b),
+        0x74 => bin!(pop_i32, I32, |a, b| a.wrapping_shl(b as u32)),
+        0x75 => bin!(pop_i32, I32, |a, b| a.wrapping_shr(b as u32)),
+        0x76 => bin!(pop_i32, I32, |a, b| (a as u32).wrapping_shr(b as u32) as i32),
+        0x77 => bin!(pop_i32, I32, |a, b| a.rotate_left(b as u32)),
+        0x78 => bin!(pop_i32, I32, |a, b| a.rotate_right(b as u32)),
+        0x79 => un!(pop_i64, I64, |a| a.leading_zeros() as i64),
+        0x7a => un!(pop_i64, I64, |a| a.trailing_zeros() as i64),
+        0x7b => un!(pop_i64, I64, |a| a.count_ones() as i64),
+        0x7c => bin!(pop_i64, I64, |a, b| a.wrapping_add(b)),
+        0x7d => bin!(pop_i64, I64, |a, b| a.wrapping_sub(b)),
+        0x7e => bin!(pop_i64, I64, |a, b| a.wrapping_mul(b)),
+        0x7f..=0x82 => {
+            let b = pop_i64(s)?;
+            let a = pop_i64(s)?;
+            ensure!(b != 0, "integer divide by zero");
+            s.push(Val::I64(match op {
+                0x7f => a
+                    .checked_div(b)
+                    .ok_or_else(|| miette::miette!("integer overflow"))?,
+                0x80 => ((a as u64) / (b as u64)) as i64,
+                0x81 => a.wrapping_rem(b),
+                _ => ((a as u64) % (b as u64)) as i64,
+            }));
+        }
+        0x83 => bin!(pop_i64, I64, |a, b| a & b),
+        0x84 => bin!(pop_i64, I64, |a, b| a | b),
+        0x85 => bin!(pop_i64, I64, |a, b| a ^ b),
+        0x86 => bin!(pop_i64, I64, |a, b| a.wrapping_shl(b as u32)),
+        0x87 => bin!(pop_i64, I64, |a, b| a.wrapping_shr(b as u32)),
+        0x88 => bin!(pop_i64, I64, |a, b| (a as u64).wrapping_shr(b as u32) as i64),
+        0x89 => bin!(pop_i64, I64, |a, b| a.rotate_left(b as u32)),
+        0x8a => bin!(pop_i64, I64, |a, b| a.rotate_right(b as u32)),
+        0x8b => un!(pop_f32, F32, |a| a.abs()),
+        0x8c => un!(pop_f32, F32, |a| -a),
+        0x8d => un!(pop_f32, F32, |a| a.ceil()),
+        0x8e => un!(pop_f32, F32, |a| a.floor()),
+        0x8f => un!(pop_f32, F32, |a| a.trunc()),
+        0x90 => un!(pop_f32, F32, |a| a.round_ties_even()),
+        0x91 => un!(pop_f32, F32, |a| a.sqrt()),
+        0x92 => bin!(pop_f32, F32, |a, b| a + b),
+        0x93 => bin!(pop_f32, F32, |a, b| a - b),
+        0x94 => bin!(pop_f32, F32, |a, b| a * b),
+        0x95 => bin!(pop_f32, F32, |a, b| a / b),
+        0x96 => bin!(pop_f32, F32, |a, b| wasm_min!(a, b)),
+        0x97 => bin!(pop_f32, F32, |a, b| wasm_max!(a, b)),
+        0x98 => bin!(pop_f32, F32, |a, b| a.copysign(b)),
+        0x99 => un!(pop_f64, F64, |a| a.abs()),
+        0x9a => un!(pop_f64, F64, |a| -a),
+        0x9b => un!(pop_f64, F64, |a| a.ceil()),
+        0x9c => un!(pop_f64, F64, |a| a.floor()),
+        0x9d => un!(pop_f64, F64, |a| a.trunc()),
+        0x9e => un!(pop_f64, F64, |a| a.round_ties_even()),
+        0x9f => un!(pop_f64, F64, |a| a.sqrt()),
+        0xa0 => bin!(pop_f64, F64, |a, b| a + b),
+        0xa1 => bin!(pop_f64, F64, |a, b| a - b),
+        0xa2 => bin!(pop_f64, F64, |a, b| a * b),
+        0xa3 => bin!(pop_f64, F64, |a, b| a / b),
+        0xa4 => bin!(pop_f64, F64, |a, b| wasm_min!(a, b)),
+        0xa5 => bin!(pop_f64, F64, |a, b| wasm_max!(a, b)),
+        0xa6 => bin!(pop_f64, F64, |a, b| a.copysign(b)),
+        0xa7 => un!(pop_i64, I32, |a| a as i32),
+        0xa8 => un!(pop_f32, I32, |a| trunc(a as f64, I32_MIN, I32_END)? as i32),
+        0xa9 => un!(pop_f32, I32, |a| trunc(a as f64, 0., U32_END)? as u32 as i32),
+        0xaa => un!(pop_f64, I32, |a| trunc(a, I32_MIN, I32_END)? as i32),
+        0xab => un!(pop_f64, I32, |a| trunc(a, 0., U32_END)? as u32 as i32),
+        0xac => un!(pop_i32, I64, |a| a as i64),
+        0xad => un!(pop_i32, I64, |a| a as u32 as i64),
+        0xae => un!(pop_f32, I64, |a| trunc(a as f64, I64_MIN, I64_END)? as i64),
+        0xaf => un!(pop_f32, I64, |a| trunc(a as f64, 0., U64_END)? as u64 as i64),
+        0xb0 => un!(pop_f64, I64, |a| trunc(a, I64_MIN, I64_END)? as i64),
+        0xb1 => un!(pop_f64, I64, |a| trunc(a, 0., U64_END)? as u64 as i64),
+        0xb2 => un!(pop_i32, F32, |a| a as f32),
+        0xb3 => un!(pop_i32, F32, |a| a as u32 as f32),
+        0xb4 => un!(pop_i64, F32, |a| a as f32),
+        0xb5 => un!(pop_i64, F32, |a| a as u64 as f32),
+        0xb6 => un!(pop_f64, F32, |a| a as f32),
+        0xb7 => un!(pop_i32, F64, |a| a as f64),
+        0xb8 => un!(pop_i32, F64, |a| a as u32 as f64),
+        0xb9 => un!(pop_i64, F64, |a| a as f64),
+        0xba => un!(pop_i64, F64, |a| a as u64 as f64),
+        0xbb => un!(pop_f32, F64, |a| a as f64),
+        0xbc => un!(pop_f32, I32, |a| a.to_bits() as i32),
+        0xbd => un!(pop_f64, I64, |a| a.to_bits() as i64),
+        0xbe => un!(pop_i32, F32, |a| f32::from_bits(a as u32)),
+        0xbf => un!(pop_i64, F64, |a| f64::from_bits(a as u64)),
+        0xc0 => un!(pop_i32, I32, |a| a as i8 as i32),
+        0xc1 => un!(pop_i32, I32, |a| a as i16 as i32),
+        0xc2 => un!(pop_i64, I64, |a| a as i8 as i64),
+        0xc3 => un!(pop_i64, I64, |a| a as i16 as i64),
+        0xc4 => un!(pop_i64, I64, |a| a as i32 as i64),
+        op => bail!("instruction 0x{op:02x} is not supported"),
+    }
+    Ok(())
+}
+
+fn trunc_sat(sub: u8, s: &mut Vec<Val>) -> Result<()> {
+    // `as` casts from floats saturate, and take NaN to zero
+    let val = match sub {
+        0 => Val::I32(pop_f32(s)? as i32),
+        1 => Val::I32(pop_f32(s)? as u32 as i32),
+        2 => Val::I32(pop_f64(s)? as i32),
+        3 => Val::I32(pop_f64(s)? as u32 as i32),
+        4 => Val::I64(pop_f32(s)? as i64),
+        5 => Val::I64(pop_f32(s)? as u64 as i64),
+        6 => Val::I64(pop_f64(s)? as i64),
+        _ => Val::I64(pop_f64(s)? as u64 as i64),
+    };
+    s.push(val);
+    Ok(())
+}
+
+impl<'m> Instance<'m> {
+    /// Instantiate `module`, initialising its memory and globals.
+    pub(crate) fn new(module: &'m Module, limits: WasmLimits, poison: Poison) -> Result<Self> {
+        let (min, max) = module.memory.unwrap_or((0, Some(0)));
+        let mut memory = vec![0; min as usize * PAGE_SIZE];
+        for (offset, bytes) in &module.data {
+            let offset = *offset as usize;
+            memory[offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+        Ok(Self {
+            module,
+            memory,
+            max_pages: max.map_or(limits.max_memory_pages, |max| {
+                max.min(limits.max_memory_pages)
+            }),
+            globals: module.globals.iter().map(|g| g.init).collect(),
+            limits,
+            fuel: 0,
+            executed: 0,
+            poison,
+            logs: vec![],
+        })
+    }
+
+    /// Call the function `func` with `args`, refuelling first.
+    pub(crate) fn invoke(&mut self, func: u32, args: Vec<Val>) -> Result<Vec<Val>> {
+        let ty = self.module.func_type(func);
+        ensure!(
+            args.len() == ty.params.len()
+                && args.iter().zip(&ty.params).all(|(a, t)| a.ty() == *t),
+            "arguments do not match the parameters of the function"
+        );
+        self.fuel = self.limits.fuel;
+        self.call(func, args, 0)
+    }
+
+    fn memory_range(&self, addr: i32, offset: u32, len: usize) -> Result<usize> {
+        let start = addr as u32 as usize + offset as usize;
+        ensure!(
+            start + len <= self.memory.len(),
+            "out of bounds memory access at {start}"
+        );
+        Ok(start)
+    }
+
+    fn load<const N: usize>(&self, addr: i32, offset: u32) -> Result<[u8; N]> {
+        let start = self.memory_range(addr, offset, N)?;
+        Ok(self.memory[start..start + N].try_into().unwrap())
+    }
+
+    fn store(&mut self, addr: i32, offset: u32, bytes: &[u8]) -> Result<()> {
+        let start = self.memory_range(addr, offset, bytes.len())?;
+        self.memory[start..start + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn string(&self, ptr: i32, len: i32) -> Result<String> {
+        let start = self.memory_range(ptr, 0, len as u32 as usize)?;
+        Ok(String::from_utf8_lossy(&self.memory[start..start + len as u32 as usize]).into_owned())
+    }
+
+    fn call_host(&mut self, host: HostFunc, args: &[Val]) -> Result<()> {
+        let [Val::I32(ptr), Val::I32(len)] = args else {
+            bail!("bad arguments to a host function")
+        };
+        let message = self.string(*ptr, *len)?;
+        match host {
+            HostFunc::Fail => bail!("the plugin failed: {message}"),
+            HostFunc::Log => {
+                log::info!("{message}");
+                self.logs.push(message);
+            }
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, func: u32, args: Vec<Val>, depth: usize) -> Result<Vec<Val>> {
+        ensure!(
+            depth < self.limits.max_call_depth,
+            "call depth exceeds the limit of {}",
+            self.limits.max_call_depth
+        );
+        let module = self.module;
+        let n_imports = module.imports.len();
+        if (func as usize) < n_imports {
+            self.call_host(module.imports[func as usize].0, &args)?;
+            return Ok(vec![]);
+        }
+        let f = &module.funcs[func as usize - n_imports];
+        let results = module.types[f.ty as usize].results.len();
+        let mut locals = args;
+        locals.extend(f.locals.iter().map(|t| t.zero()));
+        let mut stack: Vec<Val> = vec![];
+        let mut labels = vec![Label {
+            height: 0,
+            arity: results,
+            target: f.code.len(),
+            is_loop: false,
+        }];
+        let code = &f.code;
+        let mut pc = 0;
+
+        macro_rules! branch {
+            ($depth:expr) => {{
+                let depth = $depth as usize;
+                ensure!(depth < labels.len(), "branch to unknown label");
+                let label = &labels[labels.len() - 1 - depth];
+                ensure!(stack.len() >= label.height + label.arity, "value stack underflow");
+                let kept = stack.split_off(stack.len() - label.arity);
+                stack.truncate(label.height);
+                stack.extend(kept);
+                pc = label.target;
+                let keep = labels.len() - depth - usize::from(!label.is_loop);
+                labels.truncate(keep);
+                continue;
+            }};
+        }
+
+        while pc < code.len() {
+            if self.fuel == 0 {
+                bail!("the plugin ran out of fuel")
+            }
+            self.fuel -= 1;
+            self.executed += 1;
+            if self.executed.is_multiple_of(POISON_CHECK_INTERVAL) {
+                self.poison.check()?;
+            }
+            match &code[pc] {
+                Op::Unreachable => bail!("unreachable executed"),
+                Op::Nop => {}
+                Op::Block {
+                    params,
+                    results,
+                    end,
+                } => {
+                    ensure!(stack.len() >= *params, "value stack underflow");
+                    labels.push(Label {
+                        height: stack.len() - params,
+                        arity: *results,
+                        target: end + 1,
+                        is_loop: false,
+                    });
+                }
+                Op::Loop { params } => {
+                    ensure!(stack.len() >= *params, "value stack underflow");
+                    labels.push(Label {
+                        height: stack.len() - params,
+                        arity: *params,
+                        target: pc + 1,
+                        is_loop: true,
+                    });
+                }
+                Op::If {
+                    params,
+                    results,
+                    else_,
+                    end,
+                } => {
+                    let cond = pop_i32(&mut stack)?;
+                    ensure!(stack.len() >= *params, "value stack underflow");
+                    labels.push(Label {
+                        height: stack.len() - params,
+                        arity: *results,
+                        target: end + 1,
+                        is_loop: false,
+                    });
+                    if cond == 0 {
+                        // to the start of the else branch, or to the end of the block
+                        pc = if else_ == end { *end } else { else_ + 1 };
+                        continue;
+                    }
+                }
+                Op::Else { end } => {
+                    pc = *end;
+                    continue;
+                }
+                Op::End => {
+                    labels.pop();
+                }
+                Op::Br(depth) => branch!(*depth),
+                Op::BrIf(depth) => {
+                    if pop_i32(&mut stack)? != 0 {
+                        branch!(*depth)
+                    }
+                }
+                Op::BrTable(targets, default) => {
+                    let idx = pop_i32(&mut stack)? as u32 as usize;
+                    branch!(*targets.get(idx).unwrap_or(default))
+                }
+                Op::Return => branch!(labels.len() - 1),
+                Op::Call(callee) => {
+                    let n = module.func_type(*callee).params.len();
+                    ensure!(stack.len() >= n, "value stack underflow");
+                    let args = stack.split_off(stack.len() - n);
+                    let ret = self.call(*callee, args, depth + 1)?;
+                    stack.extend(ret);
+                }
+                Op::Drop => {
+                    pop(&mut stack)?;
+                }
+                Op::Select => {
+                    let cond = pop_i32(&mut stack)?;
+                    let b = pop(&mut stack)?;
+                    let a = pop(&mut stack)?;
+                    stack.push(if cond != 0 { a } else { b });
+                }
+                Op::LocalGet(idx) => match locals.get(*idx as usize) {
+                    Some(v) => stack.push(*v),
+                    None => bail!("unknown local {idx}"),
+                },
+                Op::LocalSet(idx) | Op::LocalTee(idx) => {
+                    let v = pop(&mut stack)?;
+                    match locals.get_mut(*idx as usize) {
+                        Some(l) if l.ty() == v.ty() => *l = v,
+                        _ => bail!("bad assignment to local {idx}"),
+                    }
+                    if matches!(code[pc], Op::LocalTee(_)) {
+                        stack.push(v);
+                    }
+                }
+                Op::GlobalGet(idx) => stack.push(self.globals[*idx as usize]),
+                Op::GlobalSet(idx) => {
+                    let v = pop(&mut stack)?;
+                    let idx = *idx as usize;
+                    ensure!(
+                        module.globals[idx].mutable && self.globals[idx].ty() == v.ty(),
+                        "bad assignment to global {idx}"
+                    );
+                    self.globals[idx] = v;
+                }
+                Op::Load(op, offset) => {
+                    let addr = pop_i32(&mut stack)?;
+                    let o = *offset;
+                    stack.push(match op {
+                        0x28 => Val::I32(i32::from_le_bytes(self.load(addr, o)?)),
+                        0x29 => Val::I64(i64::from_le_bytes(self.load(addr, o)?)),
+                        0x2a => Val::F32(f32::from_le_bytes(self.load(addr, o)?)),
+                        0x2b => Val::F64(f64::from_le_bytes(self.load(addr, o)?)),
+                        0x2c => Val::I32(i8::from_le_bytes(self.load(addr, o)?) as i32),
+                        0x2d => Val::I32(u8::from_le_bytes(self.load(addr, o)?) as i32),
+                        0x2e => Val::I32(i16::from_le_bytes(self.load(addr, o)?) as i32),
+                        0x2f => Val::I32(u16::from_le_bytes(self.load(addr, o)?) as i32),
+                        0x30 => Val::I64(i8::from_le_bytes(self.load(addr, o)?) as i64),
+                        0x31 => Val::I64(u8::from_le_bytes(self.load(addr, o)?) as i64),
+                        0x32 => Val::I64(i16::from_le_bytes(self.load(addr, o)?) as i64),
+                        0x33 => Val::I64(u16::from_le_bytes(self.load(addr, o)?) as i64),
+                        0x34 => Val::I64(i32::from_le_bytes(self.load(addr, o)?) as i64),
+                        _ => Val::I64(u32::from_le_bytes(self.load(addr, o)?) as i64),
+                    });
+                }
+                Op::Store(op, offset) => {
+                    let v = pop(&mut stack)?;
+                    let addr = pop_i32(&mut stack)?;
+                    let o = *offset;
+                    match (op, v) {
+                        (0x36, Val::I32(v)) => self.store(addr, o, &v.to_le_bytes())?,
+                        (0x37, Val::I64(v)) => self.store(addr, o, &v.to_le_bytes())?,
+                        (0x38, Val::F32(v)) => self.store(addr, o, &v.to_le_bytes())?,
+                        (0x39, Val::F64(v)) => self.store(addr, o, &v.to_le_bytes())?,
+                        (0x3a, Val::I32(v)) => self.store(addr, o, &v.to_le_bytes()[..1])?,
+                        (0x3b, Val::I32(v)) => self.store(addr, o, &v.to_le_bytes()[..2])?,
+                        (0x3c, Val::I64(v)) => self.store(addr, o, &v.to_le_bytes()[..1])?,
+                        (0x3d, Val::I64(v)) => self.store(addr, o, &v.to_le_bytes()[..2])?,
+                        (0x3e, Val::I64(v)) => self.store(addr, o, &v.to_le_bytes()[..4])?,
+                        (_, v) => bail!("type mismatch: cannot store {v:?}"),
+                    }
+                }
+                Op::MemorySize => stack.push(Val::I32((self.memory.len() / PAGE_SIZE) as i32)),
+                Op::MemoryGrow => {
+                    let delta = pop_i32(&mut stack)? as u32 as usize;
+                    let pages = self.memory.len() / PAGE_SIZE;
+                    if pages + delta <= self.max_pages as usize {
+                        self.memory.resize((pages + delta) * PAGE_SIZE, 0);
+                        stack.push(Val::I32(pages as i32));
+                    } else {
+                        stack.push(Val::I32(-1));
+                    }
+                }
+                Op::MemoryCopy => {
+                    let len = pop_i32(&mut stack)? as u32 as usize;
+                    let src = self.memory_range(pop_i32(&mut stack)?, 0, len)?;
+                    let dst = self.memory_range(pop_i32(&mut stack)?, 0, len)?;
+                    self.memory.copy_within(src..src + len, dst);
+                }
+                Op::MemoryFill => {
+                    let len = pop_i32(&mut stack)? as u32 as usize;
+                    let val = pop_i32(&mut stack)? as u8;
+                    let dst = self.memory_range(pop_i32(&mut stack)?, 0, len)?;
+                    self.memory[dst..dst + len].fill(val);
+                }
+                Op::Const(v) => stack.push(*v),
+                Op::Numeric(op) => numeric(*op, &mut stack)?,
+                Op::TruncSat(sub) => trunc_sat(*sub, &mut stack)?,
+            }
+            pc += 1;
+        }
+        ensure!(stack.len() >= results, "value stack underflow");
+        Ok(stack.split_off(stack.len() - results))
+    }
+}
@@ -0,0 +1,358 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Fixed rules implemented by WebAssembly modules, see [`Db::register_wasm_plugin`].
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Result, WrapErr};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::utilities::wasm_plugin::interp::{FuncType, Instance, Module, Val, ValType};
+use crate::fixed_rule::{CannotDetermineArity, FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+use crate::storage::Storage;
+use crate::Db;
+
+mod interp;
+
+/// The resources a WebAssembly plugin may use, set when it is registered with
+/// [`Db::register_wasm_plugin`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WasmLimits {
+    /// The number of instructions each call of an exported function may execute
+    pub fuel: u64,
+    /// The size the memory of the module may grow to, in pages of 64 KiB
+    pub max_memory_pages: u32,
+    /// The depth of nested calls allowed
+    pub max_call_depth: usize,
+}
+
+impl Default for WasmLimits {
+    fn default() -> Self {
+        Self {
+            fuel: 10_000_000,
+            max_memory_pages: 256,
+            max_call_depth: 256,
+        }
+    }
+}
+
+#[derive(Error, Diagnostic, Debug)]
+#[error("The WebAssembly module does not export the function '{0}'")]
+#[diagnostic(code(eval::wasm_no_function))]
+#[diagnostic(help("The functions exported are: {1}"))]
+struct NoSuchExport(String, String, #[label] SourceSpan);
+
+#[derive(Error, Diagnostic, Debug)]
+#[error("Cannot pass {0} to the plugin as {1:?}")]
+#[diagnostic(code(eval::wasm_bad_arg))]
+struct BadWasmArg(DataValue, ValType, #[label] SourceSpan);
+
+#[derive(Error, Diagnostic, Debug)]
+#[error("The function '{function}' takes {expected} values, but rows have {found}")]
+#[diagnostic(code(eval::wasm_bad_row))]
+struct BadRowLength {
+    function: String,
+    expected: usize,
+    found: usize,
+    #[label]
+    span: SourceSpan,
+}
+
+/// Applies a function exported by a WebAssembly module to each row of its input
+/// relation, or folds the rows with it when the option `init` is given.
+pub(crate) struct WasmPlugin {
+    name: String,
+    module: Arc<Module>,
+    limits: WasmLimits,
+}
+
+impl WasmPlugin {
+    fn function<'a>(
+        &'a self,
+        options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        span: SourceSpan,
+    ) -> Result<(u32, &'a FuncType, Option<Expr>)> {
+        let cannot = |message: &str| CannotDetermineArity(self.name.clone(), message.to_string(), span);
+        let Some(function) = options.get("function") else {
+            bail!(cannot("option 'function' not provided"))
+        };
+        let function = match function.clone().eval_to_const()? {
+            DataValue::Str(s) => s,
+            _ => bail!(cannot("option 'function' must be a string")),
+        };
+        let Some((idx, ty)) = self.module.export(&function) else {
+            bail!(NoSuchExport(
+                function.to_string(),
+                self.module.exports().join(", "),
+                span
+            ))
+        };
+        let init = options.get("init").cloned();
+        let folds = !ty.results.is_empty() && ty.params.starts_with(&ty.results);
+        if init.is_some() && !folds {
+            bail!(cannot(
+                "with option 'init' the function must take the accumulator, \
+                 followed by the row, and return the new accumulator"
+            ))
+        }
+        Ok((idx, ty, init))
+    }
+}
+
+fn to_wasm(value: &DataValue, ty: ValType, span: SourceSpan) -> Result<Val> {
+    let converted = match (ty, value) {
+        (ValType::I32, DataValue::Bool(b)) => Some(Val::I32(*b as i32)),
+        (ValType::I64, DataValue::Bool(b)) => Some(Val::I64(*b as i64)),
+        (ValType::I32, v) => v
+            .get_int()
+            .and_then(|i| i32::try_from(i).ok())
+            .map(Val::I32),
+        (ValType::I64, v) => v.get_int().map(Val::I64),
+        (ValType::F32, v) => v.get_float().map(|f| Val::F32(f as f32)),
+        (ValType::F64, v) => v.get_float().map(Val::F64),
+    };
+    match converted {
+        Some(val) => Ok(val),
+        None => bail!(BadWasmArg(value.clone(), ty, span)),
+    }
+}
+
+fn from_wasm(val: Val) -> DataValue {
+    match val {
+        Val::I32(i) => DataValue::from(i as i64),
+        Val::I64(i) => DataValue::from(i),
+        Val::F32(f) => DataValue::from(f as f64),
+        Val::F64(f) => DataValue::from(f),
+    }
+}
+
+impl FixedRule for WasmPlugin {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let span = payload.span();
+        let (func, ty, init) = self.function(&payload.manifest.options, span)?;
+        let function = payload.string_option("function", None)?;
+        let in_rel = payload.get_input(0)?;
+        let mut instance = Instance::new(&self.module, self.limits, poison)?;
+        let mut call = |args: Vec<Val>| {
+            instance
+                .invoke(func, args)
+                .wrap_err_with(|| format!("when running the WebAssembly plugin {}", self.name))
+        };
+        match init {
+            None => {
+                for tuple in in_rel.iter()? {
+                    let tuple = tuple?;
+                    if tuple.len() != ty.params.len() {
+                        bail!(BadRowLength {
+                            function: function.to_string(),
+                            expected: ty.params.len(),
+                            found: tuple.len(),
+                            span,
+                        })
+                    }
+                    let args = tuple
+                        .iter()
+                        .zip(&ty.params)
+                        .map(|(v, t)| to_wasm(v, *t, span))
+                        .try_collect()?;
+                    let results = call(args)?;
+                    let mut row = tuple;
+                    row.extend(results.into_iter().map(from_wasm));
+                    out.put(row);
+                }
+            }
+            Some(init) => {
+                let n_acc = ty.results.len();
+                let init = match init.eval_to_const()? {
+                    DataValue::List(l) if n_acc > 1 => l,
+                    v => vec![v],
+                };
+                if init.len() != n_acc {
+                    bail!(BadRowLength {
+                        function: function.to_string(),
+                        expected: n_acc,
+                        found: init.len(),
+                        span,
+                    })
+                }
+                let mut acc: Vec<_> = init
+                    .iter()
+                    .zip(&ty.results)
+                    .map(|(v, t)| to_wasm(v, *t, span))
+                    .try_collect()?;
+                let row_types = &ty.params[n_acc..];
+                for tuple in in_rel.iter()? {
+                    let tuple = tuple?;
+                    if tuple.len() != row_types.len() {
+                        bail!(BadRowLength {
+                            function: function.to_string(),
+                            expected: row_types.len(),
+                            found: tuple.len(),
+                            span,
+                        })
+                    }
+                    let mut args = acc;
+                    for (v, t) in tuple.iter().zip(row_types) {
+                        args.push(to_wasm(v, *t, span)?);
+                    }
+                    acc = call(args)?;
+                }
+                out.put(acc.into_iter().map(from_wasm).collect());
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        span: SourceSpan,
+    ) -> Result<usize> {
+        let (_, ty, init) = self.function(options, span)?;
+        Ok(match init {
+            None => ty.params.len() + ty.results.len(),
+            Some(_) => ty.results.len(),
+        })
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Register the WebAssembly binary module `module` as the fixed rule `name`, so that
+    /// custom logic can be deployed to a running database. A function `f` exported by
+    /// the module is applied by
+    ///
+    /// ```text
+    /// ?[a, b, result] <~ name(input[a, b], function: 'f')
+    /// ```
+    ///
+    /// which returns each row of the input followed by the results of `f`, called with
+    /// the values of the row. With the option `init`, the rule is an aggregator instead:
+    /// `f` is called with the accumulator followed by each row and returns the new
+    /// accumulator, starting from `init`, and the rule returns the final accumulator.
+    /// Integers, floats and booleans are passed as the numeric types of the parameters.
+    ///
+    /// Modules run in an interpreter and may only import the host interface, the
+    /// functions `fail` and `log` of the module `cozo`, both taking a pointer to a UTF-8
+    /// message in memory and its length. `fail` aborts the query with the message. So
+    /// plugins cannot reach files, the network or the database, and the resources they
+    /// use are bounded by `limits`. Each application of the rule runs in a fresh
+    /// instance of the module. Tables, `call_indirect` and start functions are not
+    /// supported. Plugins are not persisted, and are unregistered by
+    /// [`unregister_wasm_plugin`](Self::unregister_wasm_plugin).
+    pub fn register_wasm_plugin(&self, name: &str, module: &[u8], limits: WasmLimits) -> Result<()> {
+        let module = Module::decode(module, &limits)
+            .wrap_err_with(|| format!("when decoding the WebAssembly plugin {name}"))?;
+        let mut plugins = self.wasm_plugins.lock().unwrap();
+        self.register_fixed_rule(
+            name.to_string(),
+            WasmPlugin {
+                name: name.to_string(),
+                module: Arc::new(module),
+                limits,
+            },
+        )?;
+        plugins.insert(name.to_string());
+        Ok(())
+    }
+
+    /// Unregister the plugin `name`, returning `false` if no plugin has the name. Fixed
+    /// rules not registered by [`register_wasm_plugin`](Self::register_wasm_plugin)
+    /// are left alone.
+    pub fn unregister_wasm_plugin(&self, name: &str) -> Result<bool> {
+        let mut plugins = self.wasm_plugins.lock().unwrap();
+        if !plugins.remove(name) {
+            return Ok(false);
+        }
+        Ok(self.fixed_rules.write().unwrap().remove(name).is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (func (export "collatz") (param i64) (result i64)
+    //   (local i64)
+    //   (block (loop
+    //     (br_if 1 (i64.le_u (local.get 0) (i64.const 1)))
+    //     (local.set 1 (i64.add (local.get 1) (i64.const 1)))
+    //     (local.set 0 (if (result i64) (i64.eqz (i64.rem_u (local.get 0) (i64.const 2)))
+    //       (then (i64.div_u (local.get 0) (i64.const 2)))
+    //       (else (i64.add (i64.mul (local.get 0) (i64.const 3)) (i64.const 1)))))
+    //     (br 0)))
+    //   (local.get 1))
+    const COLLATZ: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x01, 0x06, 0x01, 0x60, 0x01, 0x7e, 0x01, 0x7e, // type section
+        0x03, 0x02, 0x01, 0x00, // function section
+        0x07, 0x0b, 0x01, 0x07, b'c', b'o', b'l', b'l', b'a', b't', b'z', 0x00, 0x00, // exports
+        0x0a, 0x37, 0x01, 0x35, 0x01, 0x01, 0x7e, // code section, one local i64
+        0x02, 0x40, 0x03, 0x40, // block, loop
+        0x20, 0x00, 0x42, 0x01, 0x58, 0x0d, 0x01, // br_if 1 (n <= 1)
+        0x20, 0x01, 0x42, 0x01, 0x7c, 0x21, 0x01, // steps += 1
+        0x20, 0x00, 0x42, 0x02, 0x82, 0x50, // n % 2 == 0
+        0x04, 0x7e, 0x20, 0x00, 0x42, 0x02, 0x80, // if: n / 2
+        0x05, 0x20, 0x00, 0x42, 0x03, 0x7e, 0x42, 0x01, 0x7c, // else: n * 3 + 1
+        0x0b, 0x21, 0x00, 0x0c, 0x00, // end if, n = .., br 0
+        0x0b, 0x0b, 0x20, 0x01, 0x0b, // end loop, end block, steps
+    ];
+
+    #[test]
+    fn interprets_loops_and_branches() {
+        let module = Module::decode(COLLATZ, &WasmLimits::default()).unwrap();
+        let (idx, ty) = module.export("collatz").unwrap();
+        assert_eq!(ty.params, vec![ValType::I64]);
+        let mut instance = Instance::new(&module, WasmLimits::default(), Poison::default()).unwrap();
+        for (n, steps) in [(1, 0), (6, 8), (27, 111)] {
+            assert_eq!(
+                instance.invoke(idx, vec![Val::I64(n)]).unwrap(),
+                vec![Val::I64(steps)]
+            );
+        }
+    }
+
+    #[test]
+    fn fuel_bounds_execution() {
+        let limits = WasmLimits {
+            fuel: 1000,
+            ..Default::default()
+        };
+        let module = Module::decode(COLLATZ, &limits).unwrap();
+        let mut instance = Instance::new(&module, limits, Poison::default()).unwrap();
+        let err = instance.invoke(0, vec![Val::I64(27)]).unwrap_err();
+        assert!(err.to_string().contains("fuel"));
+    }
+
+    #[test]
+    fn rejects_foreign_imports() {
+        let module = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+            0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type section
+            0x02, 0x0f, 0x01, 0x04, b'w', b'a', b's', b'i', 0x06, b'f', b'd', b'_', b'o', b'p',
+            b'n', 0x00, 0x00, // import wasi.fd_opn
+        ];
+        let err = Module::decode(&module, &WasmLimits::default()).unwrap_err();
+        assert!(err.to_string().contains("not provided by the host interface"));
+    }
+}
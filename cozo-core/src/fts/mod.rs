@@ -20,6 +20,7 @@ use sha2::digest::FixedOutput;
 use sha2::{Digest, Sha256};
 use smartstring::{LazyCompact, SmartString};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
 
 pub(crate) mod ast;
@@ -247,6 +248,8 @@ pub(crate) struct FtsIndexConfig {
 pub(crate) struct TokenizerCache {
     pub(crate) named_cache: RwLock<HashMap<SmartString<LazyCompact>, Arc<TextAnalyzer>>>,
     pub(crate) hashed_cache: RwLock<HashMap<Vec<u8>, Arc<TextAnalyzer>>>,
+    pub(crate) hits: AtomicU64,
+    pub(crate) misses: AtomicU64,
 }
 
 impl TokenizerCache {
@@ -259,6 +262,7 @@ impl TokenizerCache {
         {
             let idx_cache = self.named_cache.read().unwrap();
             if let Some(analyzer) = idx_cache.get(tokenizer_name) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
                 return Ok(analyzer.clone());
             }
         }
@@ -266,12 +270,14 @@ impl TokenizerCache {
         {
             let hashed_cache = self.hashed_cache.read().unwrap();
             if let Some(analyzer) = hashed_cache.get(hash.as_ref()) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
                 let mut idx_cache = self.named_cache.write().unwrap();
                 idx_cache.insert(tokenizer_name.into(), analyzer.clone());
                 return Ok(analyzer.clone());
             }
         }
         {
+            self.misses.fetch_add(1, Ordering::Relaxed);
             let analyzer = Arc::new(tokenizer.build(filters)?);
             let mut hashed_cache = self.hashed_cache.write().unwrap();
             hashed_cache.insert(hash.as_ref().to_vec(), analyzer.clone());
@@ -0,0 +1,117 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Entry points for fuzzing with `cargo fuzz`, whose targets are in `cozo-core/fuzz`.
+//! Only compiled with `--cfg fuzzing`, which `cargo fuzz` sets. Run a target with, for
+//! example, `cargo +nightly fuzz run parse` in `cozo-core`.
+//!
+//! Each entry point takes arbitrary bytes and panics when a property does not hold.
+//! Values are read from bytes in the encoding of the values of stored rows, MessagePack,
+//! which is itself checked by [`fuzz_decode_value`].
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::data::functions::current_validity;
+use crate::data::memcmp::MemCmpEncoder;
+use crate::data::value::DataValue;
+use crate::fixed_rule::DEFAULT_FIXED_RULES;
+use crate::parse::parse_script;
+
+/// Parse `script_bytes`, which must fail with an error rather than panic when they are
+/// not a valid script.
+pub fn fuzz_parse(script_bytes: &[u8]) {
+    if let Ok(script) = std::str::from_utf8(script_bytes) {
+        let _ = parse_script(
+            script,
+            &BTreeMap::new(),
+            &DEFAULT_FIXED_RULES,
+            current_validity(),
+            false,
+        );
+    }
+}
+
+/// Decode a value from `bytes`, then check that its encodings, as stored and as a
+/// key, both give it back.
+pub fn fuzz_decode_value(bytes: &[u8]) {
+    if let Some(value) = decode_value(bytes) {
+        let encoded = encode_value(&value);
+        assert_eq!(decode_value(&encoded).as_ref(), Some(&value));
+        let key = encode_key(&value);
+        let (decoded, rest) = DataValue::decode_from_key(&key);
+        assert!(rest.is_empty());
+        assert_eq!(decoded, value);
+    }
+}
+
+/// Decode two values from `a` and `b`, then check that their ordering is a total order
+/// consistent with equality, and that the ordering of their encodings as keys, by which
+/// the storage sorts them, is the same.
+pub fn fuzz_cmp(a: &[u8], b: &[u8]) {
+    let (Some(a), Some(b)) = (decode_value(a), decode_value(b)) else {
+        return;
+    };
+    let ord = a.cmp(&b);
+    assert_eq!(ord, b.cmp(&a).reverse(), "{a:?} {b:?}");
+    assert_eq!(ord == Ordering::Equal, a == b, "{a:?} {b:?}");
+    assert_eq!(a.cmp(&a), Ordering::Equal);
+    // vectors are not sorted by their encoding, and cannot be keys. JSON values come
+    // before validities in the ordering of values, but after them in the keys.
+    let mixes_json_and_validity =
+        (has(&a, is_json) || has(&b, is_json)) && (has(&a, is_validity) || has(&b, is_validity));
+    if !has(&a, is_vector) && !has(&b, is_vector) && !mixes_json_and_validity {
+        assert_eq!(encode_key(&a).cmp(&encode_key(&b)), ord, "{a:?} {b:?}");
+    }
+}
+
+fn decode_value(bytes: &[u8]) -> Option<DataValue> {
+    let value: DataValue = rmp_serde::from_slice(bytes).ok()?;
+    // values of these kinds are made by the query engine and are never stored
+    (!has(&value, is_transient)).then_some(value)
+}
+
+fn encode_value(value: &DataValue) -> Vec<u8> {
+    let mut ret = vec![];
+    value
+        .serialize(&mut rmp_serde::Serializer::new(&mut ret))
+        .unwrap();
+    ret
+}
+
+fn encode_key(value: &DataValue) -> Vec<u8> {
+    let mut ret = vec![];
+    ret.encode_datavalue(value);
+    ret
+}
+
+fn has(value: &DataValue, pred: fn(&DataValue) -> bool) -> bool {
+    match value {
+        DataValue::List(l) => l.iter().any(|v| has(v, pred)),
+        DataValue::Set(s) => s.iter().any(|v| has(v, pred)),
+        v => pred(v),
+    }
+}
+
+fn is_transient(value: &DataValue) -> bool {
+    matches!(value, DataValue::Bot | DataValue::Regex(_))
+}
+
+fn is_vector(value: &DataValue) -> bool {
+    matches!(value, DataValue::Vec(_))
+}
+
+fn is_json(value: &DataValue) -> bool {
+    matches!(value, DataValue::Json(_))
+}
+
+fn is_validity(value: &DataValue) -> bool {
+    matches!(value, DataValue::Validity(_))
+}
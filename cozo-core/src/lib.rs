@@ -24,6 +24,8 @@
 //! ```
 //! We created an in-memory database above. There are other persistent options:
 //! see [DbInstance::new]. It is perfectly fine to run multiple storage engines in the same process.
+//! Errors are returned as [CozoError], which tells apart parse errors, conflicts, constraint
+//! violations and the like.
 //!
 #![doc = document_features::document_features!()]
 #![warn(rust_2018_idioms, future_incompatible)]
@@ -31,11 +33,16 @@
 #![allow(clippy::type_complexity)]
 #![allow(clippy::too_many_arguments)]
 
+// lets the derived implementations refer to `::cozo` within the tests of this crate
+#[cfg(all(test, feature = "derive"))]
+extern crate self as cozo;
+
 use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
 use std::path::Path;
 use std::thread;
 #[allow(unused_imports)]
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use crossbeam::channel::{bounded, Receiver, Sender};
 use lazy_static::lazy_static;
@@ -46,24 +53,37 @@ use miette::{
     bail, miette, GraphicalReportHandler, GraphicalTheme, IntoDiagnostic, JSONReportHandler,
     Result, ThemeCharacters, ThemeStyles,
 };
+use serde::de::DeserializeOwned;
 use serde_json::json;
 
+pub use data::entity::{entity_column, from_data_value, to_data_value, FromEntity, IntoTx};
 pub use data::value::{DataValue, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs};
+pub use error::{CozoError, ErrorLocation};
+#[cfg(feature = "derive")]
+pub use cozo_derive::{FromEntity, IntoTx};
 pub use fixed_rule::{FixedRule, FixedRuleInputRelation, FixedRulePayload};
 pub use runtime::db::Db;
 pub use runtime::db::NamedRows;
 pub use runtime::relation::decode_tuple_from_kv;
 pub use runtime::temp_store::RegularTempStore;
+#[cfg(feature = "encryption")]
+pub use storage::encrypted::{EncryptedStorage, EncryptionKey};
 pub use storage::mem::{new_cozo_mem, MemStorage};
+#[cfg(any(feature = "storage-rocksdb", feature = "storage-sled"))]
+pub use storage::lock::{force_unlock, AlreadyOpen};
+pub use storage::ns::NsStorage;
 #[cfg(feature = "storage-rocksdb")]
-pub use storage::rocks::{new_cozo_rocksdb, RocksDbStorage};
+pub use storage::rocks::{
+    new_cozo_rocksdb, new_cozo_rocksdb_with_options, DbOptions, Durability, FsyncPolicy,
+    RocksDbCompression, RocksDbStorage,
+};
 #[cfg(feature = "storage-sled")]
 pub use storage::sled::{new_cozo_sled, SledStorage};
 #[cfg(feature = "storage-sqlite")]
 pub use storage::sqlite::{new_cozo_sqlite, SqliteStorage};
 #[cfg(feature = "storage-tikv")]
 pub use storage::tikv::{new_cozo_tikv, TiKvStorage};
-pub use storage::{Storage, StoreTx};
+pub use storage::{Storage, StoreTx, TxDurability};
 
 pub use crate::data::expr::Expr;
 use crate::data::json::JsonValue;
@@ -71,19 +91,71 @@ pub use crate::data::symb::Symbol;
 pub use crate::data::value::{JsonData, Vector};
 pub use crate::fixed_rule::SimpleFixedRule;
 pub use crate::parse::SourceSpan;
+pub use crate::query::ast;
 pub use crate::runtime::callback::CallbackOp;
+pub use crate::runtime::check::{CheckDiagnostic, CheckSeverity};
+pub use crate::runtime::format::{ResultFormat, ResultSerializer};
+pub use crate::runtime::dump::{SnapshotImport, DUMP_VERSION};
+pub use crate::runtime::upgrade::{UpgradeProgress, UpgradeReport};
+pub use crate::runtime::why::{Derivation, DerivationKind};
+pub use crate::runtime::jobs::{JobOptions, JobState, JobStatus, MaintenanceJob};
+pub use crate::runtime::history::{DiffFilter, RowDiff, SchemaDiff};
+pub use crate::runtime::stats::{ColumnHistogram, RelationAnalysis, HISTOGRAM_BUCKETS};
+pub use crate::runtime::describe::{
+    ColumnDescription, DbDescription, FunctionDescription, IndexDescription, RelationDescription,
+};
 pub use crate::runtime::db::evaluate_expressions;
+pub use crate::runtime::db::CompactionProgress;
+pub use crate::runtime::db::{
+    IdAllocationReport, IntegrityFinding, IntegrityReport, Principal, RelationStats, RepairMode,
+    RepairReport, SessionInfo, SnapshotExport, StorageStats, TxPreview, SNAPSHOT_BATCH_ROWS,
+};
 pub use crate::runtime::db::get_variables;
+pub use crate::runtime::metrics::{Histogram, Metrics, LATENCY_BUCKETS};
+pub use crate::runtime::audit::{AuditEntry, AuditEvent, AuditRetention};
+pub use crate::runtime::batch::{BatchOptions, BatchReport, TxBatcher};
+pub use crate::runtime::tx_log::{
+    RelationChanges, TxChanges, TxGroup, TxGroupState, TxLogChunk, TxLogEntry, TxOp,
+};
+#[cfg(feature = "async")]
+pub use crate::async_api::{AsyncMultiTransaction, TxReport};
+pub use crate::runtime::limits::{EvalLimits, EvalProgress, EvalProgressCallback};
+pub use crate::runtime::plan_guard::PlanLimits;
+pub use crate::runtime::procedure::Procedure;
+pub use crate::runtime::property_graph::PropertyGraphImport;
+pub use crate::runtime::quota::Quota;
+pub use crate::runtime::rdf::RdfFormat;
+#[cfg(any(feature = "sink-nats", feature = "sink-kafka"))]
+pub use crate::sink::{CdcSink, SinkConfig, SinkFormat, SinkTarget};
+#[cfg(feature = "fetch")]
+pub use crate::fixed_rule::utilities::fetch::FetchConfig;
+#[cfg(feature = "wasm-plugins")]
+pub use crate::fixed_rule::utilities::wasm_plugin::WasmLimits;
+#[cfg(feature = "scheduler")]
+pub use crate::runtime::scheduler::{ScheduleStatus, ScheduledTask};
+#[cfg(feature = "sql-import")]
+pub use crate::runtime::sql_import::{
+    ColumnNaming, SqlForeignKey, SqlImportOptions, SqlTableImport,
+};
+pub use crate::runtime::slow_log::{SlowQuery, SlowQueryCallback};
+pub use crate::runtime::snapshot::Snapshot;
 pub use crate::runtime::db::Poison;
 pub use crate::runtime::db::ScriptMutability;
 pub use crate::runtime::db::TransactionPayload;
 
+#[cfg(feature = "async")]
+pub(crate) mod async_api;
 pub(crate) mod data;
+pub(crate) mod error;
 pub(crate) mod fixed_rule;
 pub(crate) mod fts;
+#[cfg(any(fuzzing, test))]
+pub mod fuzz;
 pub(crate) mod parse;
 pub(crate) mod query;
 pub(crate) mod runtime;
+#[cfg(any(feature = "sink-nats", feature = "sink-kafka"))]
+pub(crate) mod sink;
 pub(crate) mod storage;
 pub(crate) mod utils;
 
@@ -134,16 +206,20 @@ impl DbInstance {
     /// some of the engines are available. The `mem` engine is always available.
     ///
     /// `path` is ignored for `mem` and `tikv` engines.
-    /// `options` is ignored for every engine except `tikv`.
+    /// `options` is ignored for every engine except `rocksdb`, for which it is
+    /// a JSON-encoded `DbOptions`, and `tikv`.
     #[allow(unused_variables)]
-    pub fn new(engine: &str, path: impl AsRef<Path>, options: &str) -> Result<Self> {
+    pub fn new(engine: &str, path: impl AsRef<Path>, options: &str) -> Result<Self, CozoError> {
         let options = if options.is_empty() { "{}" } else { options };
         Ok(match engine {
             "mem" => Self::Mem(new_cozo_mem()?),
             #[cfg(feature = "storage-sqlite")]
             "sqlite" => Self::Sqlite(new_cozo_sqlite(path)?),
             #[cfg(feature = "storage-rocksdb")]
-            "rocksdb" => Self::RocksDb(new_cozo_rocksdb(path)?),
+            "rocksdb" => {
+                let opts: DbOptions = serde_json::from_str(options).into_diagnostic()?;
+                Self::RocksDb(new_cozo_rocksdb_with_options(path, &opts)?)
+            }
             #[cfg(feature = "storage-sled")]
             "sled" => Self::Sled(new_cozo_sled(path)?),
             #[cfg(feature = "storage-tikv")]
@@ -156,10 +232,13 @@ impl DbInstance {
                 let opts: TiKvOpts = serde_json::from_str(options).into_diagnostic()?;
                 Self::TiKv(new_cozo_tikv(opts.end_points.clone(), opts.optimistic)?)
             }
-            k => bail!(
-                "database engine '{}' not supported (maybe not compiled in)",
-                k
-            ),
+            k => {
+                return Err(miette!(
+                    "database engine '{}' not supported (maybe not compiled in)",
+                    k
+                )
+                .into())
+            }
         })
     }
     /// Same as [Self::new], but inputs and error messages are all in strings
@@ -170,27 +249,304 @@ impl DbInstance {
     ) -> std::result::Result<Self, String> {
         Self::new(engine, path, options).map_err(|err| err.to_string())
     }
+    /// Migrate the database at `path` to the current storage version in place, see
+    /// [crate::Db::upgrade_storage]. `engine` and `options` are as for [Self::new].
+    /// The database must not be open. There is nothing to upgrade for `mem`, and `tikv`
+    /// is not supported.
+    #[allow(unused_variables)]
+    pub fn upgrade_storage(
+        engine: &str,
+        path: impl AsRef<Path>,
+        options: &str,
+        progress: impl FnMut(&UpgradeProgress),
+    ) -> Result<UpgradeReport, CozoError> {
+        let options = if options.is_empty() { "{}" } else { options };
+        Ok(match engine {
+            "mem" => UpgradeReport::default(),
+            #[cfg(feature = "storage-sqlite")]
+            "sqlite" => storage::sqlite::open_cozo_sqlite(path)?.upgrade_storage(progress)?,
+            #[cfg(feature = "storage-rocksdb")]
+            "rocksdb" => {
+                let opts: DbOptions = serde_json::from_str(options).into_diagnostic()?;
+                storage::rocks::open_cozo_rocksdb(path, &opts)?.upgrade_storage(progress)?
+            }
+            #[cfg(feature = "storage-sled")]
+            "sled" => storage::sled::open_cozo_sled(path)?.upgrade_storage(progress)?,
+            k => {
+                return Err(miette!(
+                    "database engine '{}' cannot be upgraded (maybe not compiled in)",
+                    k
+                )
+                .into())
+            }
+        })
+    }
     /// Dispatcher method. See [crate::Db::run_script].
     pub fn run_script(
         &self,
         payload: &str,
         params: BTreeMap<String, DataValue>,
         mutability: ScriptMutability,
-    ) -> Result<NamedRows> {
+    ) -> Result<NamedRows, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.run_script(payload, params, mutability)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_script(payload, params, mutability)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_script(payload, params, mutability)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_script(payload, params, mutability)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_script(payload, params, mutability)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::run_script_as].
+    pub fn run_script_as(
+        &self,
+        principal: &Principal,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Result<NamedRows, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.run_script_as(principal, payload, params, mutability)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_script_as(principal, payload, params, mutability)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_script_as(principal, payload, params, mutability)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_script_as(principal, payload, params, mutability)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_script_as(principal, payload, params, mutability)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::run_script_with_durability].
+    pub fn run_script_with_durability(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        durability: TxDurability,
+    ) -> Result<NamedRows, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.run_script_with_durability(payload, params, durability)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_script_with_durability(payload, params, durability)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => {
+                db.run_script_with_durability(payload, params, durability)?
+            }
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_script_with_durability(payload, params, durability)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_script_with_durability(payload, params, durability)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::run_ast].
+    pub fn run_ast(
+        &self,
+        query: &ast::Query,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Result<NamedRows, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.run_ast(query, params, mutability)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_ast(query, params, mutability)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_ast(query, params, mutability)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_ast(query, params, mutability)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_ast(query, params, mutability)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::check].
+    pub fn check(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Vec<CheckDiagnostic> {
+        match self {
+            DbInstance::Mem(db) => db.check(payload, params),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.check(payload, params),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.check(payload, params),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.check(payload, params),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.check(payload, params),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::attach].
+    pub fn attach(&self, alias: &str, other: DbInstance) -> Result<(), CozoError> {
+        match self {
+            DbInstance::Mem(db) => db.attach(alias, other)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.attach(alias, other)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.attach(alias, other)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.attach(alias, other)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.attach(alias, other)?,
+        }
+        Ok(())
+    }
+    /// Dispatcher method. See [crate::Db::attach_snapshot].
+    pub fn attach_snapshot(&self, alias: &str, reader: impl BufRead) -> Result<(), CozoError> {
+        match self {
+            DbInstance::Mem(db) => db.attach_snapshot(alias, reader)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.attach_snapshot(alias, reader)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.attach_snapshot(alias, reader)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.attach_snapshot(alias, reader)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.attach_snapshot(alias, reader)?,
+        }
+        Ok(())
+    }
+    /// Dispatcher method. See [crate::Db::detach].
+    pub fn detach(&self, alias: &str) -> Result<bool, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.detach(alias)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.detach(alias)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.detach(alias)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.detach(alias)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.detach(alias)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::list_attached].
+    pub fn list_attached(&self) -> Vec<String> {
         match self {
-            DbInstance::Mem(db) => db.run_script(payload, params, mutability),
+            DbInstance::Mem(db) => db.list_attached(),
             #[cfg(feature = "storage-sqlite")]
-            DbInstance::Sqlite(db) => db.run_script(payload, params, mutability),
+            DbInstance::Sqlite(db) => db.list_attached(),
             #[cfg(feature = "storage-rocksdb")]
-            DbInstance::RocksDb(db) => db.run_script(payload, params, mutability),
+            DbInstance::RocksDb(db) => db.list_attached(),
             #[cfg(feature = "storage-sled")]
-            DbInstance::Sled(db) => db.run_script(payload, params, mutability),
+            DbInstance::Sled(db) => db.list_attached(),
             #[cfg(feature = "storage-tikv")]
-            DbInstance::TiKv(db) => db.run_script(payload, params, mutability),
+            DbInstance::TiKv(db) => db.list_attached(),
         }
     }
+    /// Dispatcher method. See [crate::Db::run_script_with_inputs].
+    pub fn run_script_with_inputs(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        inputs: BTreeMap<String, NamedRows>,
+        mutability: ScriptMutability,
+    ) -> Result<NamedRows, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.run_script_with_inputs(payload, params, inputs, mutability)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => {
+                db.run_script_with_inputs(payload, params, inputs, mutability)?
+            }
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => {
+                db.run_script_with_inputs(payload, params, inputs, mutability)?
+            }
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => {
+                db.run_script_with_inputs(payload, params, inputs, mutability)?
+            }
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => {
+                db.run_script_with_inputs(payload, params, inputs, mutability)?
+            }
+        })
+    }
+    /// Dispatcher method. See [crate::Db::why].
+    pub fn why(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        fact: Vec<DataValue>,
+    ) -> Result<Option<Derivation>, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.why(payload, params, fact)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.why(payload, params, fact)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.why(payload, params, fact)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.why(payload, params, fact)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.why(payload, params, fact)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::run_script_serialized].
+    pub fn run_script_serialized(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+        serializer: &dyn ResultSerializer,
+    ) -> Result<Vec<u8>, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => {
+                db.run_script_serialized(payload, params, mutability, serializer)?
+            }
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => {
+                db.run_script_serialized(payload, params, mutability, serializer)?
+            }
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => {
+                db.run_script_serialized(payload, params, mutability, serializer)?
+            }
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => {
+                db.run_script_serialized(payload, params, mutability, serializer)?
+            }
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => {
+                db.run_script_serialized(payload, params, mutability, serializer)?
+            }
+        })
+    }
+    /// Dispatcher method. See [crate::Db::describe].
+    pub fn describe(&self) -> Result<DbDescription, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.describe()?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.describe()?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.describe()?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.describe()?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.describe()?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::run_query_as].
+    pub fn run_query_as<T: DeserializeOwned>(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<Vec<T>, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.run_query_as(payload, params)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_query_as(payload, params)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_query_as(payload, params)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_query_as(payload, params)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_query_as(payload, params)?,
+        })
+    }
     /// `run_script` with mutable script and no parameters
-    pub fn run_default(&self, payload: &str) -> Result<NamedRows> {
+    pub fn run_default(&self, payload: &str) -> Result<NamedRows, CozoError> {
         self.run_script(payload, BTreeMap::new(), ScriptMutability::Mutable)
     }
     /// Run the CozoScript passed in. The `params` argument is a map of parameters.
@@ -201,11 +557,31 @@ impl DbInstance {
         payload: &str,
         params: BTreeMap<String, DataValue>,
         mutability: ScriptMutability,
+    ) -> JsonValue {
+        self.fold_err(payload, || self.run_script(payload, params, mutability))
+    }
+    /// Run the CozoScript passed in for `principal`, folding any error into the returned JSON.
+    /// See [crate::Db::run_script_as].
+    pub fn run_script_as_fold_err(
+        &self,
+        principal: &Principal,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> JsonValue {
+        self.fold_err(payload, || {
+            self.run_script_as(principal, payload, params, mutability)
+        })
+    }
+    fn fold_err(
+        &self,
+        payload: &str,
+        run: impl FnOnce() -> Result<NamedRows, CozoError>,
     ) -> JsonValue {
         #[cfg(not(target_arch = "wasm32"))]
             let start = Instant::now();
 
-        match self.run_script(payload, params, mutability) {
+        match run() {
             Ok(named_rows) => {
                 let mut j_val = named_rows.into_json();
                 #[cfg(not(target_arch = "wasm32"))]
@@ -249,22 +625,22 @@ impl DbInstance {
             .to_string()
     }
     /// Dispatcher method. See [crate::Db::export_relations].
-    pub fn export_relations<I, T>(&self, relations: I) -> Result<BTreeMap<String, NamedRows>>
+    pub fn export_relations<I, T>(&self, relations: I) -> Result<BTreeMap<String, NamedRows>, CozoError>
         where
             T: AsRef<str>,
             I: Iterator<Item=T>,
     {
-        match self {
-            DbInstance::Mem(db) => db.export_relations(relations),
+        Ok(match self {
+            DbInstance::Mem(db) => db.export_relations(relations)?,
             #[cfg(feature = "storage-sqlite")]
-            DbInstance::Sqlite(db) => db.export_relations(relations),
+            DbInstance::Sqlite(db) => db.export_relations(relations)?,
             #[cfg(feature = "storage-rocksdb")]
-            DbInstance::RocksDb(db) => db.export_relations(relations),
+            DbInstance::RocksDb(db) => db.export_relations(relations)?,
             #[cfg(feature = "storage-sled")]
-            DbInstance::Sled(db) => db.export_relations(relations),
+            DbInstance::Sled(db) => db.export_relations(relations)?,
             #[cfg(feature = "storage-tikv")]
-            DbInstance::TiKv(db) => db.export_relations(relations),
-        }
+            DbInstance::TiKv(db) => db.export_relations(relations)?,
+        })
     }
     /// Export relations to JSON-encoded string.
     /// See [crate::Db::export_relations]
@@ -293,18 +669,37 @@ impl DbInstance {
             .collect())
     }
     /// Dispatcher method. See [crate::Db::import_relations].
-    pub fn import_relations(&self, data: BTreeMap<String, NamedRows>) -> Result<()> {
+    pub fn import_relations(&self, data: BTreeMap<String, NamedRows>) -> Result<(), CozoError> {
         match self {
-            DbInstance::Mem(db) => db.import_relations(data),
+            DbInstance::Mem(db) => db.import_relations(data)?,
             #[cfg(feature = "storage-sqlite")]
-            DbInstance::Sqlite(db) => db.import_relations(data),
+            DbInstance::Sqlite(db) => db.import_relations(data)?,
             #[cfg(feature = "storage-rocksdb")]
-            DbInstance::RocksDb(db) => db.import_relations(data),
+            DbInstance::RocksDb(db) => db.import_relations(data)?,
             #[cfg(feature = "storage-sled")]
-            DbInstance::Sled(db) => db.import_relations(data),
+            DbInstance::Sled(db) => db.import_relations(data)?,
             #[cfg(feature = "storage-tikv")]
-            DbInstance::TiKv(db) => db.import_relations(data),
+            DbInstance::TiKv(db) => db.import_relations(data)?,
         }
+        Ok(())
+    }
+    /// Dispatcher method. See [crate::Db::import_relations_chunked].
+    pub fn import_relations_chunked(
+        &self,
+        data: BTreeMap<String, NamedRows>,
+        max_rows: usize,
+    ) -> Result<usize, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.import_relations_chunked(data, max_rows)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.import_relations_chunked(data, max_rows)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.import_relations_chunked(data, max_rows)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.import_relations_chunked(data, max_rows)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.import_relations_chunked(data, max_rows)?,
+        })
     }
     /// Import a relation, the data is given as a JSON string, and the returned result is converted into a string.
     /// See [crate::Db::import_relations].
@@ -320,7 +715,7 @@ impl DbInstance {
     }
     /// Import a relation, the data is given as a JSON string.
     /// See [crate::Db::import_relations].
-    pub fn import_relations_str_with_err(&self, data: &str) -> Result<()> {
+    pub fn import_relations_str_with_err(&self, data: &str) -> Result<(), CozoError> {
         let json_data: JsonValue = serde_json::from_str(data).into_diagnostic()?;
         let json_object = json_data
             .as_object()
@@ -334,18 +729,19 @@ impl DbInstance {
         self.import_relations(mapping)
     }
     /// Dispatcher method. See [crate::Db::backup_db].
-    pub fn backup_db(&self, out_file: impl AsRef<Path>) -> Result<()> {
+    pub fn backup_db(&self, out_file: impl AsRef<Path>) -> Result<(), CozoError> {
         match self {
-            DbInstance::Mem(db) => db.backup_db(out_file),
+            DbInstance::Mem(db) => db.backup_db(out_file)?,
             #[cfg(feature = "storage-sqlite")]
-            DbInstance::Sqlite(db) => db.backup_db(out_file),
+            DbInstance::Sqlite(db) => db.backup_db(out_file)?,
             #[cfg(feature = "storage-rocksdb")]
-            DbInstance::RocksDb(db) => db.backup_db(out_file),
+            DbInstance::RocksDb(db) => db.backup_db(out_file)?,
             #[cfg(feature = "storage-sled")]
-            DbInstance::Sled(db) => db.backup_db(out_file),
+            DbInstance::Sled(db) => db.backup_db(out_file)?,
             #[cfg(feature = "storage-tikv")]
-            DbInstance::TiKv(db) => db.backup_db(out_file),
+            DbInstance::TiKv(db) => db.backup_db(out_file)?,
         }
+        Ok(())
     }
     /// Backup the running database into an Sqlite file, with JSON string return value.
     /// See [crate::Db::backup_db].
@@ -356,18 +752,19 @@ impl DbInstance {
         }
     }
     /// Dispatcher method. See [crate::Db::restore_backup].
-    pub fn restore_backup(&self, in_file: impl AsRef<Path>) -> Result<()> {
+    pub fn restore_backup(&self, in_file: impl AsRef<Path>) -> Result<(), CozoError> {
         match self {
-            DbInstance::Mem(db) => db.restore_backup(in_file),
+            DbInstance::Mem(db) => db.restore_backup(in_file)?,
             #[cfg(feature = "storage-sqlite")]
-            DbInstance::Sqlite(db) => db.restore_backup(in_file),
+            DbInstance::Sqlite(db) => db.restore_backup(in_file)?,
             #[cfg(feature = "storage-rocksdb")]
-            DbInstance::RocksDb(db) => db.restore_backup(in_file),
+            DbInstance::RocksDb(db) => db.restore_backup(in_file)?,
             #[cfg(feature = "storage-sled")]
-            DbInstance::Sled(db) => db.restore_backup(in_file),
+            DbInstance::Sled(db) => db.restore_backup(in_file)?,
             #[cfg(feature = "storage-tikv")]
-            DbInstance::TiKv(db) => db.restore_backup(in_file),
+            DbInstance::TiKv(db) => db.restore_backup(in_file)?,
         }
+        Ok(())
     }
     /// Restore from an Sqlite backup, with JSON string return value.
     /// See [crate::Db::restore_backup].
@@ -382,19 +779,62 @@ impl DbInstance {
         &self,
         in_file: impl AsRef<Path>,
         relations: &[String],
-    ) -> Result<()> {
+    ) -> Result<(), CozoError> {
+        match self {
+            DbInstance::Mem(db) => db.import_from_backup(in_file, relations)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.import_from_backup(in_file, relations)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.import_from_backup(in_file, relations)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.import_from_backup(in_file, relations)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.import_from_backup(in_file, relations)?,
+        }
+        Ok(())
+    }
+    /// Dispatcher method. See [crate::Db::dump_version].
+    pub fn dump_version(&self) -> u32 {
         match self {
-            DbInstance::Mem(db) => db.import_from_backup(in_file, relations),
+            DbInstance::Mem(db) => db.dump_version(),
             #[cfg(feature = "storage-sqlite")]
-            DbInstance::Sqlite(db) => db.import_from_backup(in_file, relations),
+            DbInstance::Sqlite(db) => db.dump_version(),
             #[cfg(feature = "storage-rocksdb")]
-            DbInstance::RocksDb(db) => db.import_from_backup(in_file, relations),
+            DbInstance::RocksDb(db) => db.dump_version(),
             #[cfg(feature = "storage-sled")]
-            DbInstance::Sled(db) => db.import_from_backup(in_file, relations),
+            DbInstance::Sled(db) => db.dump_version(),
             #[cfg(feature = "storage-tikv")]
-            DbInstance::TiKv(db) => db.import_from_backup(in_file, relations),
+            DbInstance::TiKv(db) => db.dump_version(),
         }
     }
+    /// Dispatcher method. See [crate::Db::snapshot_import].
+    pub fn snapshot_import(&self, reader: impl BufRead) -> Result<SnapshotImport, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.snapshot_import(reader)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.snapshot_import(reader)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.snapshot_import(reader)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.snapshot_import(reader)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.snapshot_import(reader)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::snapshot_export].
+    pub fn snapshot_export(&self, writer: impl Write) -> Result<SnapshotExport, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.snapshot_export(writer)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.snapshot_export(writer)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.snapshot_export(writer)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.snapshot_export(writer)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.snapshot_export(writer)?,
+        })
+    }
     /// Import relations from an Sqlite backup, with JSON string return value.
     /// See [crate::Db::import_from_backup].
     pub fn import_from_backup_str(&self, payload: &str) -> String {
@@ -411,7 +851,7 @@ impl DbInstance {
         }
         let json_payload: Payload = serde_json::from_str(payload).into_diagnostic()?;
 
-        self.import_from_backup(&json_payload.path, &json_payload.relations)
+        Ok(self.import_from_backup(&json_payload.path, &json_payload.relations)?)
     }
 
     /// Dispatcher method. See [crate::Db::register_callback].
@@ -449,122 +889,1439 @@ impl DbInstance {
             DbInstance::TiKv(db) => db.unregister_callback(id),
         }
     }
-    /// Dispatcher method. See [crate::Db::register_fixed_rule].
-    pub fn register_fixed_rule<R>(&self, name: String, rule_impl: R) -> Result<()>
-        where
-            R: FixedRule + 'static,
-    {
+    /// Dispatcher method. See [crate::Db::sweep_expired].
+    pub fn sweep_expired(&self, batch_size: usize) -> Result<usize, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.sweep_expired(batch_size)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.sweep_expired(batch_size)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.sweep_expired(batch_size)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.sweep_expired(batch_size)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.sweep_expired(batch_size)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::drop_ns].
+    pub fn drop_ns(&self, name: &str) -> Result<(), CozoError> {
         match self {
-            DbInstance::Mem(db) => db.register_fixed_rule(name, rule_impl),
+            DbInstance::Mem(db) => db.drop_ns(name)?,
             #[cfg(feature = "storage-sqlite")]
-            DbInstance::Sqlite(db) => db.register_fixed_rule(name, rule_impl),
+            DbInstance::Sqlite(db) => db.drop_ns(name)?,
             #[cfg(feature = "storage-rocksdb")]
-            DbInstance::RocksDb(db) => db.register_fixed_rule(name, rule_impl),
+            DbInstance::RocksDb(db) => db.drop_ns(name)?,
             #[cfg(feature = "storage-sled")]
-            DbInstance::Sled(db) => db.register_fixed_rule(name, rule_impl),
+            DbInstance::Sled(db) => db.drop_ns(name)?,
             #[cfg(feature = "storage-tikv")]
-            DbInstance::TiKv(db) => db.register_fixed_rule(name, rule_impl),
+            DbInstance::TiKv(db) => db.drop_ns(name)?,
         }
+        Ok(())
     }
-    /// Dispatcher method. See [crate::Db::unregister_fixed_rule]
-    pub fn unregister_fixed_rule(&self, name: &str) -> Result<bool> {
+    /// Dispatcher method. See [crate::Db::copy_ns].
+    pub fn copy_ns(&self, src: &str, dst: &str) -> Result<(), CozoError> {
         match self {
-            DbInstance::Mem(db) => db.unregister_fixed_rule(name),
+            DbInstance::Mem(db) => db.copy_ns(src, dst)?,
             #[cfg(feature = "storage-sqlite")]
-            DbInstance::Sqlite(db) => db.unregister_fixed_rule(name),
+            DbInstance::Sqlite(db) => db.copy_ns(src, dst)?,
             #[cfg(feature = "storage-rocksdb")]
-            DbInstance::RocksDb(db) => db.unregister_fixed_rule(name),
+            DbInstance::RocksDb(db) => db.copy_ns(src, dst)?,
             #[cfg(feature = "storage-sled")]
-            DbInstance::Sled(db) => db.unregister_fixed_rule(name),
+            DbInstance::Sled(db) => db.copy_ns(src, dst)?,
             #[cfg(feature = "storage-tikv")]
-            DbInstance::TiKv(db) => db.unregister_fixed_rule(name),
+            DbInstance::TiKv(db) => db.copy_ns(src, dst)?,
         }
+        Ok(())
     }
-
-    /// Dispatcher method. See [crate::Db::run_multi_transaction]
-    pub fn run_multi_transaction(
+    /// Dispatcher method. See [crate::Db::compact_range].
+    pub fn compact_range(&self, lower: &[u8], upper: &[u8]) -> Result<(), CozoError> {
+        match self {
+            DbInstance::Mem(db) => db.compact_range(lower, upper)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.compact_range(lower, upper)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.compact_range(lower, upper)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.compact_range(lower, upper)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.compact_range(lower, upper)?,
+        }
+        Ok(())
+    }
+    /// Dispatcher method. See [crate::Db::compact_relations].
+    pub fn compact_relations(
         &self,
-        write: bool,
-        payloads: Receiver<TransactionPayload>,
-        results: Sender<Result<NamedRows>>,
-    ) {
+        relations: &[&str],
+        on_progress: impl FnMut(&CompactionProgress),
+    ) -> Result<(), CozoError> {
         match self {
-            DbInstance::Mem(db) => db.run_multi_transaction(write, payloads, results),
+            DbInstance::Mem(db) => db.compact_relations(relations, on_progress)?,
             #[cfg(feature = "storage-sqlite")]
-            DbInstance::Sqlite(db) => db.run_multi_transaction(write, payloads, results),
+            DbInstance::Sqlite(db) => db.compact_relations(relations, on_progress)?,
             #[cfg(feature = "storage-rocksdb")]
-            DbInstance::RocksDb(db) => db.run_multi_transaction(write, payloads, results),
+            DbInstance::RocksDb(db) => db.compact_relations(relations, on_progress)?,
             #[cfg(feature = "storage-sled")]
-            DbInstance::Sled(db) => db.run_multi_transaction(write, payloads, results),
+            DbInstance::Sled(db) => db.compact_relations(relations, on_progress)?,
             #[cfg(feature = "storage-tikv")]
-            DbInstance::TiKv(db) => db.run_multi_transaction(write, payloads, results),
+            DbInstance::TiKv(db) => db.compact_relations(relations, on_progress)?,
         }
+        Ok(())
     }
-    /// A higher-level, blocking wrapper for [crate::Db::run_multi_transaction]. Runs the transaction on a dedicated thread.
-    /// Write transactions _may_ block other reads, but we guarantee that this does not happen for the RocksDB backend.
-    pub fn multi_transaction(&self, write: bool) -> MultiTransaction {
-        let (app2db_send, app2db_recv) = bounded(1);
-        let (db2app_send, db2app_recv) = bounded(1);
-        let db = self.clone();
-        thread::spawn(move || db.run_multi_transaction(write, app2db_recv, db2app_send));
-        MultiTransaction {
-            sender: app2db_send,
-            receiver: db2app_recv,
+    /// Dispatcher method. See [crate::Db::metrics].
+    pub fn metrics(&self) -> Metrics {
+        match self {
+            DbInstance::Mem(db) => db.metrics(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.metrics(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.metrics(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.metrics(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.metrics(),
         }
     }
-}
-
-/// A multi-transaction handle.
-/// You should use either the fields directly, or the associated functions.
-pub struct MultiTransaction {
-    /// Commands can be sent into the transaction through this channel
-    pub sender: Sender<TransactionPayload>,
-    /// Results can be retrieved from the transaction from this channel
-    pub receiver: Receiver<Result<NamedRows>>,
-}
-
-impl MultiTransaction {
-    /// Runs a single script in the transaction.
-    pub fn run_script(
+    /// Dispatcher method. See [crate::Db::set_slow_query_log].
+    pub fn set_slow_query_log(
         &self,
-        payload: &str,
-        params: BTreeMap<String, DataValue>,
-    ) -> Result<NamedRows> {
-        if let Err(err) = self
-            .sender
-            .send(TransactionPayload::Query((payload.to_string(), params)))
-        {
-            bail!(err);
+        threshold: Duration,
+        capacity: usize,
+        callback: Option<SlowQueryCallback>,
+    ) {
+        match self {
+            DbInstance::Mem(db) => db.set_slow_query_log(threshold, capacity, callback),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_slow_query_log(threshold, capacity, callback),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_slow_query_log(threshold, capacity, callback),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_slow_query_log(threshold, capacity, callback),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_slow_query_log(threshold, capacity, callback),
         }
-        match self.receiver.recv() {
-            Ok(r) => r,
-            Err(err) => bail!(err),
+    }
+    /// Dispatcher method. See [crate::Db::disable_slow_query_log].
+    pub fn disable_slow_query_log(&self) -> bool {
+        match self {
+            DbInstance::Mem(db) => db.disable_slow_query_log(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.disable_slow_query_log(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.disable_slow_query_log(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.disable_slow_query_log(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.disable_slow_query_log(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::slow_queries].
+    pub fn slow_queries(&self) -> Vec<SlowQuery> {
+        match self {
+            DbInstance::Mem(db) => db.slow_queries(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.slow_queries(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.slow_queries(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.slow_queries(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.slow_queries(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::forbid_string_literals].
+    pub fn forbid_string_literals(&self, forbid: bool) {
+        match self {
+            DbInstance::Mem(db) => db.forbid_string_literals(forbid),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.forbid_string_literals(forbid),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.forbid_string_literals(forbid),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.forbid_string_literals(forbid),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.forbid_string_literals(forbid),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_eval_limits].
+    pub fn set_eval_limits(&self, limits: EvalLimits, progress: Option<EvalProgressCallback>) {
+        match self {
+            DbInstance::Mem(db) => db.set_eval_limits(limits, progress),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_eval_limits(limits, progress),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_eval_limits(limits, progress),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_eval_limits(limits, progress),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_eval_limits(limits, progress),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::eval_limits].
+    pub fn eval_limits(&self) -> EvalLimits {
+        match self {
+            DbInstance::Mem(db) => db.eval_limits(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.eval_limits(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.eval_limits(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.eval_limits(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.eval_limits(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_plan_limits].
+    pub fn set_plan_limits(&self, limits: PlanLimits) {
+        match self {
+            DbInstance::Mem(db) => db.set_plan_limits(limits),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_plan_limits(limits),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_plan_limits(limits),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_plan_limits(limits),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_plan_limits(limits),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::plan_limits].
+    pub fn plan_limits(&self) -> PlanLimits {
+        match self {
+            DbInstance::Mem(db) => db.plan_limits(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.plan_limits(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.plan_limits(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.plan_limits(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.plan_limits(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::replica_id].
+    pub fn replica_id(&self) -> String {
+        match self {
+            DbInstance::Mem(db) => db.replica_id(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.replica_id(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.replica_id(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.replica_id(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.replica_id(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_replica_id].
+    pub fn set_replica_id(&self, id: &str) -> Result<(), CozoError> {
+        match self {
+            DbInstance::Mem(db) => db.set_replica_id(id)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_replica_id(id)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_replica_id(id)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_replica_id(id)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_replica_id(id)?,
+        }
+        Ok(())
+    }
+    /// Dispatcher method. See [crate::Db::set_quota].
+    pub fn set_quota(&self, principal: &str, quota: Quota) {
+        match self {
+            DbInstance::Mem(db) => db.set_quota(principal, quota),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_quota(principal, quota),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_quota(principal, quota),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_quota(principal, quota),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_quota(principal, quota),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::remove_quota].
+    pub fn remove_quota(&self, principal: &str) -> bool {
+        match self {
+            DbInstance::Mem(db) => db.remove_quota(principal),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.remove_quota(principal),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.remove_quota(principal),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.remove_quota(principal),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.remove_quota(principal),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::quota].
+    pub fn quota(&self, principal: &str) -> Option<Quota> {
+        match self {
+            DbInstance::Mem(db) => db.quota(principal),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.quota(principal),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.quota(principal),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.quota(principal),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.quota(principal),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::storage_stats].
+    pub fn storage_stats(&self) -> Result<StorageStats, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.storage_stats()?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.storage_stats()?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.storage_stats()?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.storage_stats()?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.storage_stats()?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::analyze].
+    pub fn analyze(&self) -> Result<Vec<RelationAnalysis>, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.analyze()?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.analyze()?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.analyze()?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.analyze()?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.analyze()?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::estimate_count].
+    pub fn estimate_count(&self, relation: &str) -> Result<usize, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.estimate_count(relation)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.estimate_count(relation)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.estimate_count(relation)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.estimate_count(relation)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.estimate_count(relation)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::relation_analysis].
+    pub fn relation_analysis(&self, relation: &str) -> Option<RelationAnalysis> {
+        match self {
+            DbInstance::Mem(db) => db.relation_analysis(relation),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.relation_analysis(relation),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.relation_analysis(relation),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.relation_analysis(relation),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.relation_analysis(relation),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::warm_up].
+    pub fn warm_up(&self, relations: &[&str]) -> Result<usize, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.warm_up(relations)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.warm_up(relations)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.warm_up(relations)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.warm_up(relations)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.warm_up(relations)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::start_job].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn start_job(&self, job: MaintenanceJob, options: JobOptions) -> Result<u64, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.start_job(job, options)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.start_job(job, options)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.start_job(job, options)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.start_job(job, options)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.start_job(job, options)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::pause_job].
+    pub fn pause_job(&self, id: u64) -> bool {
+        match self {
+            DbInstance::Mem(db) => db.pause_job(id),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.pause_job(id),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.pause_job(id),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.pause_job(id),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.pause_job(id),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::resume_job].
+    pub fn resume_job(&self, id: u64) -> bool {
+        match self {
+            DbInstance::Mem(db) => db.resume_job(id),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.resume_job(id),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.resume_job(id),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.resume_job(id),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.resume_job(id),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::cancel_job].
+    pub fn cancel_job(&self, id: u64) -> bool {
+        match self {
+            DbInstance::Mem(db) => db.cancel_job(id),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.cancel_job(id),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.cancel_job(id),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.cancel_job(id),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.cancel_job(id),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::job_status].
+    pub fn job_status(&self, id: u64) -> Option<JobStatus> {
+        match self {
+            DbInstance::Mem(db) => db.job_status(id),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.job_status(id),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.job_status(id),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.job_status(id),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.job_status(id),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::list_jobs].
+    pub fn list_jobs(&self) -> Vec<JobStatus> {
+        match self {
+            DbInstance::Mem(db) => db.list_jobs(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.list_jobs(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.list_jobs(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.list_jobs(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.list_jobs(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::register_constraint].
+    pub fn register_constraint(&self, name: &str, query: &str) -> Result<(), CozoError> {
+        match self {
+            DbInstance::Mem(db) => db.register_constraint(name, query)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.register_constraint(name, query)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.register_constraint(name, query)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.register_constraint(name, query)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.register_constraint(name, query)?,
+        }
+        Ok(())
+    }
+    /// Dispatcher method. See [crate::Db::unregister_constraint].
+    pub fn unregister_constraint(&self, name: &str) -> bool {
+        match self {
+            DbInstance::Mem(db) => db.unregister_constraint(name),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.unregister_constraint(name),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.unregister_constraint(name),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.unregister_constraint(name),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.unregister_constraint(name),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::with_tx_speculative].
+    pub fn with_tx_speculative(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        validations: &[&str],
+    ) -> Result<TxPreview, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.with_tx_speculative(payload, params, validations)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.with_tx_speculative(payload, params, validations)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.with_tx_speculative(payload, params, validations)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.with_tx_speculative(payload, params, validations)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.with_tx_speculative(payload, params, validations)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::batcher].
+    pub fn batcher(
+        &self,
+        relation: &str,
+        options: BatchOptions,
+    ) -> Result<TxBatcher<'_>, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.batcher(relation, options)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.batcher(relation, options)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.batcher(relation, options)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.batcher(relation, options)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.batcher(relation, options)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::run_script_idempotent].
+    pub fn run_script_idempotent(
+        &self,
+        key: &str,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<NamedRows, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.run_script_idempotent(key, payload, params)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_script_idempotent(key, payload, params)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_script_idempotent(key, payload, params)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_script_idempotent(key, payload, params)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_script_idempotent(key, payload, params)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::forget_idempotency_keys].
+    pub fn forget_idempotency_keys(&self, before: f64) -> Result<usize, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.forget_idempotency_keys(before)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.forget_idempotency_keys(before)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.forget_idempotency_keys(before)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.forget_idempotency_keys(before)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.forget_idempotency_keys(before)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::freeze_writes].
+    pub fn freeze_writes(&self, timeout: Duration) -> Result<(), CozoError> {
+        match self {
+            DbInstance::Mem(db) => db.freeze_writes(timeout)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.freeze_writes(timeout)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.freeze_writes(timeout)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.freeze_writes(timeout)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.freeze_writes(timeout)?,
+        }
+        Ok(())
+    }
+    /// Dispatcher method. See [crate::Db::unfreeze_writes].
+    pub fn unfreeze_writes(&self) -> bool {
+        match self {
+            DbInstance::Mem(db) => db.unfreeze_writes(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.unfreeze_writes(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.unfreeze_writes(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.unfreeze_writes(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.unfreeze_writes(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::schema_diff].
+    pub fn schema_diff(&self, from: u64, to: u64) -> Result<SchemaDiff, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.schema_diff(from, to)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.schema_diff(from, to)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.schema_diff(from, to)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.schema_diff(from, to)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.schema_diff(from, to)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::diff].
+    pub fn diff(
+        &self,
+        from: u64,
+        to: u64,
+        filter: &DiffFilter,
+    ) -> Result<Vec<RowDiff>, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.diff(from, to, filter)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.diff(from, to, filter)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.diff(from, to, filter)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.diff(from, to, filter)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.diff(from, to, filter)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::snapshot].
+    pub fn snapshot(&self) -> Result<Snapshot<'_>, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.snapshot()?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.snapshot()?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.snapshot()?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.snapshot()?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.snapshot()?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::bench_dataset].
+    pub fn bench_dataset(&self, n: u64) -> Result<(), CozoError> {
+        match self {
+            DbInstance::Mem(db) => db.bench_dataset(n)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.bench_dataset(n)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.bench_dataset(n)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.bench_dataset(n)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.bench_dataset(n)?,
+        }
+        Ok(())
+    }
+    /// Dispatcher method. See [crate::Db::id_allocation_report].
+    pub fn id_allocation_report(&self) -> Result<IdAllocationReport, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.id_allocation_report()?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.id_allocation_report()?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.id_allocation_report()?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.id_allocation_report()?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.id_allocation_report()?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::compact_id_counter].
+    pub fn compact_id_counter(&self) -> Result<u64, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.compact_id_counter()?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.compact_id_counter()?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.compact_id_counter()?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.compact_id_counter()?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.compact_id_counter()?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::verify_integrity].
+    pub fn verify_integrity(&self) -> Result<IntegrityReport, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.verify_integrity()?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.verify_integrity()?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.verify_integrity()?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.verify_integrity()?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.verify_integrity()?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::repair].
+    pub fn repair(&self, mode: RepairMode) -> Result<RepairReport, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.repair(mode)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.repair(mode)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.repair(mode)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.repair(mode)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.repair(mode)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::close].
+    pub fn close(&self, timeout: Duration) -> Result<(), CozoError> {
+        match self {
+            DbInstance::Mem(db) => db.close(timeout)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.close(timeout)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.close(timeout)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.close(timeout)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.close(timeout)?,
+        }
+        Ok(())
+    }
+    /// Dispatcher method. See [crate::Db::list_sessions].
+    pub fn list_sessions(&self) -> Vec<SessionInfo> {
+        match self {
+            DbInstance::Mem(db) => db.list_sessions(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.list_sessions(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.list_sessions(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.list_sessions(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.list_sessions(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::audit_log].
+    pub fn audit_log(&self) -> Result<Vec<AuditEntry>, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.audit_log()?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.audit_log()?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.audit_log()?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.audit_log()?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.audit_log()?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::enable_tx_log].
+    pub fn enable_tx_log(&self) -> Result<(), CozoError> {
+        match self {
+            DbInstance::Mem(db) => db.enable_tx_log()?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.enable_tx_log()?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.enable_tx_log()?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.enable_tx_log()?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.enable_tx_log()?,
+        }
+        Ok(())
+    }
+    /// Dispatcher method. See [crate::Db::disable_tx_log].
+    pub fn disable_tx_log(&self) -> bool {
+        match self {
+            DbInstance::Mem(db) => db.disable_tx_log(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.disable_tx_log(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.disable_tx_log(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.disable_tx_log(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.disable_tx_log(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::tx_log_chunk].
+    pub fn tx_log_chunk(&self, after: u64, max_entries: usize) -> Result<TxLogChunk, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.tx_log_chunk(after, max_entries)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.tx_log_chunk(after, max_entries)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.tx_log_chunk(after, max_entries)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.tx_log_chunk(after, max_entries)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.tx_log_chunk(after, max_entries)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::truncate_tx_log].
+    pub fn truncate_tx_log(&self, up_to: u64) -> Result<usize, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.truncate_tx_log(up_to)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.truncate_tx_log(up_to)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.truncate_tx_log(up_to)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.truncate_tx_log(up_to)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.truncate_tx_log(up_to)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::restore_to_timestamp].
+    pub fn restore_to_timestamp(
+        &self,
+        base: impl AsRef<Path>,
+        chunks: &[TxLogChunk],
+        ts: f64,
+    ) -> Result<usize, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.restore_to_timestamp(base, chunks, ts)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.restore_to_timestamp(base, chunks, ts)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.restore_to_timestamp(base, chunks, ts)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.restore_to_timestamp(base, chunks, ts)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.restore_to_timestamp(base, chunks, ts)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::kill_session].
+    pub fn kill_session(&self, id: u64) -> bool {
+        match self {
+            DbInstance::Mem(db) => db.kill_session(id),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.kill_session(id),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.kill_session(id),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.kill_session(id),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.kill_session(id),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::start_expiry_sweeper].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn start_expiry_sweeper(&self, interval: Duration, batch_size: usize) {
+        match self {
+            DbInstance::Mem(db) => db.start_expiry_sweeper(interval, batch_size),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.start_expiry_sweeper(interval, batch_size),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.start_expiry_sweeper(interval, batch_size),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.start_expiry_sweeper(interval, batch_size),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.start_expiry_sweeper(interval, batch_size),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::schedule].
+    #[cfg(feature = "scheduler")]
+    pub fn schedule(
+        &self,
+        name: &str,
+        task: ScheduledTask,
+        interval: Duration,
+    ) -> Result<(), CozoError> {
+        match self {
+            DbInstance::Mem(db) => db.schedule(name, task, interval)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.schedule(name, task, interval)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.schedule(name, task, interval)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.schedule(name, task, interval)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.schedule(name, task, interval)?,
+        }
+        Ok(())
+    }
+    /// Dispatcher method. See [crate::Db::unschedule].
+    #[cfg(feature = "scheduler")]
+    pub fn unschedule(&self, name: &str) -> bool {
+        match self {
+            DbInstance::Mem(db) => db.unschedule(name),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.unschedule(name),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.unschedule(name),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.unschedule(name),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.unschedule(name),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::schedules].
+    #[cfg(feature = "scheduler")]
+    pub fn schedules(&self) -> Vec<ScheduleStatus> {
+        match self {
+            DbInstance::Mem(db) => db.schedules(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.schedules(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.schedules(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.schedules(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.schedules(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::import_rdf].
+    pub fn import_rdf(
+        &self,
+        relation: &str,
+        format: RdfFormat,
+        data: &str,
+    ) -> Result<usize, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.import_rdf(relation, format, data)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.import_rdf(relation, format, data)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.import_rdf(relation, format, data)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.import_rdf(relation, format, data)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.import_rdf(relation, format, data)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::export_rdf].
+    pub fn export_rdf(
+        &self,
+        relation: &str,
+        format: RdfFormat,
+        prefixes: &BTreeMap<String, String>,
+    ) -> Result<String, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.export_rdf(relation, format, prefixes)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.export_rdf(relation, format, prefixes)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.export_rdf(relation, format, prefixes)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.export_rdf(relation, format, prefixes)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.export_rdf(relation, format, prefixes)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::import_property_graph].
+    pub fn import_property_graph(
+        &self,
+        nodes_relation: &str,
+        edges_relation: &str,
+        nodes: &[&str],
+        edges: &[&str],
+    ) -> Result<PropertyGraphImport, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => {
+                db.import_property_graph(nodes_relation, edges_relation, nodes, edges)?
+            }
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => {
+                db.import_property_graph(nodes_relation, edges_relation, nodes, edges)?
+            }
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => {
+                db.import_property_graph(nodes_relation, edges_relation, nodes, edges)?
+            }
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => {
+                db.import_property_graph(nodes_relation, edges_relation, nodes, edges)?
+            }
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => {
+                db.import_property_graph(nodes_relation, edges_relation, nodes, edges)?
+            }
+        })
+    }
+    /// Dispatcher method. See [crate::Db::import_sqlite_snapshot].
+    #[cfg(feature = "sql-import")]
+    pub fn import_sqlite_snapshot(
+        &self,
+        path: impl AsRef<Path>,
+        options: &SqlImportOptions,
+    ) -> Result<Vec<SqlTableImport>, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.import_sqlite_snapshot(path, options)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.import_sqlite_snapshot(path, options)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.import_sqlite_snapshot(path, options)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.import_sqlite_snapshot(path, options)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.import_sqlite_snapshot(path, options)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::set_fetch_config].
+    #[cfg(feature = "fetch")]
+    pub fn set_fetch_config(&self, config: FetchConfig) {
+        match self {
+            DbInstance::Mem(db) => db.set_fetch_config(config),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_fetch_config(config),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_fetch_config(config),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_fetch_config(config),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_fetch_config(config),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::fetch_config].
+    #[cfg(feature = "fetch")]
+    pub fn fetch_config(&self) -> FetchConfig {
+        match self {
+            DbInstance::Mem(db) => db.fetch_config(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.fetch_config(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.fetch_config(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.fetch_config(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.fetch_config(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::register_procedure].
+    pub fn register_procedure(
+        &self,
+        name: &str,
+        params: &[&str],
+        script: &str,
+    ) -> Result<(), CozoError> {
+        match self {
+            DbInstance::Mem(db) => db.register_procedure(name, params, script)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.register_procedure(name, params, script)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.register_procedure(name, params, script)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.register_procedure(name, params, script)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.register_procedure(name, params, script)?,
+        }
+        Ok(())
+    }
+    /// Dispatcher method. See [crate::Db::unregister_procedure].
+    pub fn unregister_procedure(&self, name: &str) -> bool {
+        match self {
+            DbInstance::Mem(db) => db.unregister_procedure(name),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.unregister_procedure(name),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.unregister_procedure(name),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.unregister_procedure(name),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.unregister_procedure(name),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::procedures].
+    pub fn procedures(&self) -> Vec<Procedure> {
+        match self {
+            DbInstance::Mem(db) => db.procedures(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.procedures(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.procedures(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.procedures(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.procedures(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::call_procedure].
+    pub fn call_procedure(
+        &self,
+        name: &str,
+        args: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Result<NamedRows, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.call_procedure(name, args, mutability)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.call_procedure(name, args, mutability)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.call_procedure(name, args, mutability)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.call_procedure(name, args, mutability)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.call_procedure(name, args, mutability)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::call_procedure_as].
+    pub fn call_procedure_as(
+        &self,
+        principal: &Principal,
+        name: &str,
+        args: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Result<NamedRows, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.call_procedure_as(principal, name, args, mutability)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.call_procedure_as(principal, name, args, mutability)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.call_procedure_as(principal, name, args, mutability)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.call_procedure_as(principal, name, args, mutability)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.call_procedure_as(principal, name, args, mutability)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::enable_audit_log].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn enable_audit_log(&self, retention: AuditRetention) {
+        match self {
+            DbInstance::Mem(db) => db.enable_audit_log(retention),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.enable_audit_log(retention),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.enable_audit_log(retention),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.enable_audit_log(retention),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.enable_audit_log(retention),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::disable_audit_log].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn disable_audit_log(&self) -> bool {
+        match self {
+            DbInstance::Mem(db) => db.disable_audit_log(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.disable_audit_log(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.disable_audit_log(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.disable_audit_log(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.disable_audit_log(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::subscribe_from].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn subscribe_from(&self, tx_id: u64) -> Result<Receiver<TxChanges>, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.subscribe_from(tx_id)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.subscribe_from(tx_id)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.subscribe_from(tx_id)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.subscribe_from(tx_id)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.subscribe_from(tx_id)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::stop_expiry_sweeper].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn stop_expiry_sweeper(&self) -> bool {
+        match self {
+            DbInstance::Mem(db) => db.stop_expiry_sweeper(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.stop_expiry_sweeper(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.stop_expiry_sweeper(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.stop_expiry_sweeper(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.stop_expiry_sweeper(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::start_compaction_schedule].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn start_compaction_schedule(&self, interval: Duration) {
+        match self {
+            DbInstance::Mem(db) => db.start_compaction_schedule(interval),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.start_compaction_schedule(interval),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.start_compaction_schedule(interval),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.start_compaction_schedule(interval),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.start_compaction_schedule(interval),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::stop_compaction_schedule].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn stop_compaction_schedule(&self) -> bool {
+        match self {
+            DbInstance::Mem(db) => db.stop_compaction_schedule(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.stop_compaction_schedule(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.stop_compaction_schedule(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.stop_compaction_schedule(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.stop_compaction_schedule(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::register_fixed_rule].
+    pub fn register_fixed_rule<R>(&self, name: String, rule_impl: R) -> Result<(), CozoError>
+        where
+            R: FixedRule + 'static,
+    {
+        match self {
+            DbInstance::Mem(db) => db.register_fixed_rule(name, rule_impl)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.register_fixed_rule(name, rule_impl)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.register_fixed_rule(name, rule_impl)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.register_fixed_rule(name, rule_impl)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.register_fixed_rule(name, rule_impl)?,
+        }
+        Ok(())
+    }
+    /// Dispatcher method. See [crate::Db::register_wasm_plugin].
+    #[cfg(feature = "wasm-plugins")]
+    pub fn register_wasm_plugin(
+        &self,
+        name: &str,
+        module: &[u8],
+        limits: WasmLimits,
+    ) -> Result<(), CozoError> {
+        match self {
+            DbInstance::Mem(db) => db.register_wasm_plugin(name, module, limits)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.register_wasm_plugin(name, module, limits)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.register_wasm_plugin(name, module, limits)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.register_wasm_plugin(name, module, limits)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.register_wasm_plugin(name, module, limits)?,
+        }
+        Ok(())
+    }
+    /// Dispatcher method. See [crate::Db::unregister_wasm_plugin].
+    #[cfg(feature = "wasm-plugins")]
+    pub fn unregister_wasm_plugin(&self, name: &str) -> Result<bool, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.unregister_wasm_plugin(name)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.unregister_wasm_plugin(name)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.unregister_wasm_plugin(name)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.unregister_wasm_plugin(name)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.unregister_wasm_plugin(name)?,
+        })
+    }
+    /// Dispatcher method. See [crate::Db::unregister_fixed_rule]
+    pub fn unregister_fixed_rule(&self, name: &str) -> Result<bool, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.unregister_fixed_rule(name)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.unregister_fixed_rule(name)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.unregister_fixed_rule(name)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.unregister_fixed_rule(name)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.unregister_fixed_rule(name)?,
+        })
+    }
+
+    /// Dispatcher method. See [crate::Db::run_multi_transaction]
+    pub fn run_multi_transaction(
+        &self,
+        write: bool,
+        payloads: Receiver<TransactionPayload>,
+        results: Sender<Result<NamedRows>>,
+    ) {
+        match self {
+            DbInstance::Mem(db) => db.run_multi_transaction(write, payloads, results),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_multi_transaction(write, payloads, results),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_multi_transaction(write, payloads, results),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_multi_transaction(write, payloads, results),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_multi_transaction(write, payloads, results),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::run_multi_transaction_as]
+    pub fn run_multi_transaction_as(
+        &self,
+        principal: &Principal,
+        write: bool,
+        payloads: Receiver<TransactionPayload>,
+        results: Sender<Result<NamedRows>>,
+    ) {
+        match self {
+            DbInstance::Mem(db) => {
+                db.run_multi_transaction_as(principal, write, payloads, results)
+            }
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => {
+                db.run_multi_transaction_as(principal, write, payloads, results)
+            }
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => {
+                db.run_multi_transaction_as(principal, write, payloads, results)
+            }
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => {
+                db.run_multi_transaction_as(principal, write, payloads, results)
+            }
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => {
+                db.run_multi_transaction_as(principal, write, payloads, results)
+            }
+        }
+    }
+    /// Dispatcher method. See [crate::Db::run_multi_transaction_with_durability].
+    pub fn run_multi_transaction_with_durability(
+        &self,
+        durability: TxDurability,
+        payloads: Receiver<TransactionPayload>,
+        results: Sender<Result<NamedRows>>,
+    ) {
+        match self {
+            DbInstance::Mem(db) => {
+                db.run_multi_transaction_with_durability(durability, payloads, results)
+            }
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => {
+                db.run_multi_transaction_with_durability(durability, payloads, results)
+            }
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => {
+                db.run_multi_transaction_with_durability(durability, payloads, results)
+            }
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => {
+                db.run_multi_transaction_with_durability(durability, payloads, results)
+            }
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => {
+                db.run_multi_transaction_with_durability(durability, payloads, results)
+            }
+        }
+    }
+    /// A higher-level, blocking wrapper for [crate::Db::run_multi_transaction]. Runs the transaction on a dedicated thread.
+    /// Write transactions _may_ block other reads, but we guarantee that this does not happen for the RocksDB backend.
+    pub fn multi_transaction(&self, write: bool) -> MultiTransaction {
+        let (app2db_send, app2db_recv) = bounded(1);
+        let (db2app_send, db2app_recv) = bounded(1);
+        let db = self.clone();
+        thread::spawn(move || db.run_multi_transaction(write, app2db_recv, db2app_send));
+        MultiTransaction {
+            sender: app2db_send,
+            receiver: db2app_recv,
+        }
+    }
+    /// Like [DbInstance::multi_transaction], on behalf of `principal`.
+    /// See [crate::Db::run_multi_transaction_as].
+    pub fn multi_transaction_as(&self, principal: &Principal, write: bool) -> MultiTransaction {
+        let (app2db_send, app2db_recv) = bounded(1);
+        let (db2app_send, db2app_recv) = bounded(1);
+        let db = self.clone();
+        let principal = principal.clone();
+        thread::spawn(move || {
+            db.run_multi_transaction_as(&principal, write, app2db_recv, db2app_send)
+        });
+        MultiTransaction {
+            sender: app2db_send,
+            receiver: db2app_recv,
+        }
+    }
+    /// Like [DbInstance::multi_transaction] for a write transaction committing with `durability`.
+    /// See [crate::Db::run_multi_transaction_with_durability].
+    pub fn multi_transaction_with_durability(&self, durability: TxDurability) -> MultiTransaction {
+        let (app2db_send, app2db_recv) = bounded(1);
+        let (db2app_send, db2app_recv) = bounded(1);
+        let db = self.clone();
+        thread::spawn(move || {
+            db.run_multi_transaction_with_durability(durability, app2db_recv, db2app_send)
+        });
+        MultiTransaction {
+            sender: app2db_send,
+            receiver: db2app_recv,
+        }
+    }
+}
+
+/// A multi-transaction handle.
+/// You should use either the fields directly, or the associated functions.
+pub struct MultiTransaction {
+    /// Commands can be sent into the transaction through this channel
+    pub sender: Sender<TransactionPayload>,
+    /// Results can be retrieved from the transaction from this channel
+    pub receiver: Receiver<Result<NamedRows>>,
+}
+
+impl MultiTransaction {
+    /// Runs a single script in the transaction.
+    pub fn run_script(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<NamedRows, CozoError> {
+        if let Err(err) = self
+            .sender
+            .send(TransactionPayload::Query((payload.to_string(), params)))
+        {
+            return Err(miette!(err).into());
+        }
+        match self.receiver.recv() {
+            Ok(r) => Ok(r?),
+            Err(err) => Err(miette!(err).into()),
+        }
+    }
+    /// Creates the temporary relation `name` holding `data`, to be queried by the
+    /// scripts run later in the transaction. The name must start with an underscore,
+    /// and all the columns of the relation are keys, so duplicate rows are kept once.
+    /// Like relations created by `:create _name {...}` in a script of the transaction,
+    /// the relation lives in memory only, and is gone when the transaction ends.
+    pub fn create_temp_rel(&self, name: &str, data: NamedRows) -> Result<(), CozoError> {
+        if let Err(err) = self
+            .sender
+            .send(TransactionPayload::CreateTempRel((name.to_string(), data)))
+        {
+            return Err(miette!(err).into());
+        }
+        match self.receiver.recv() {
+            Ok(res) => Ok(res.map(|_| ())?),
+            Err(err) => Err(miette!(err).into()),
         }
     }
     /// Commits the multi-transaction
-    pub fn commit(&self) -> Result<()> {
+    pub fn commit(&self) -> Result<(), CozoError> {
         if let Err(err) = self.sender.send(TransactionPayload::Commit) {
-            bail!(err);
+            return Err(miette!(err).into());
         }
         match self.receiver.recv() {
-            Ok(_) => Ok(()),
-            Err(err) => bail!(err),
+            Ok(res) => Ok(res.map(|_| ())?),
+            Err(err) => Err(miette!(err).into()),
         }
     }
     /// Aborts the multi-transaction
-    pub fn abort(&self) -> Result<()> {
+    pub fn abort(&self) -> Result<(), CozoError> {
         if let Err(err) = self.sender.send(TransactionPayload::Abort) {
-            bail!(err);
+            return Err(miette!(err).into());
         }
         match self.receiver.recv() {
             Ok(_) => Ok(()),
-            Err(err) => bail!(err),
+            Err(err) => Err(miette!(err).into()),
         }
     }
 }
 
 /// Convert error raised by the database into friendly JSON format
-pub fn format_error_as_json(mut err: Report, source: Option<&str>) -> JsonValue {
+pub fn format_error_as_json(err: impl Into<Report>, source: Option<&str>) -> JsonValue {
+    let mut err = err.into();
     if err.source_code().is_none() {
         if let Some(src) = source {
             err = err.with_source_code(format!("{src} "));
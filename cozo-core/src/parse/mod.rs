@@ -13,7 +13,7 @@ use std::sync::Arc;
 
 use either::{Either, Left};
 use miette::{bail, Diagnostic, IntoDiagnostic, Result};
-use pest::error::InputLocation;
+use pest::error::{InputLocation, LineColLocation};
 use pest::Parser;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
@@ -239,11 +239,32 @@ impl From<SourceSpan> for miette::SourceSpan {
 }
 
 #[derive(thiserror::Error, Diagnostic, Debug)]
-#[error("The query parser has encountered unexpected input / end of input at {span}")]
+#[error("The query parser has encountered unexpected input / end of input at line {line}, column {column}: `{snippet}`")]
 #[diagnostic(code(parser::pest))]
 pub(crate) struct ParseError {
     #[label]
     pub(crate) span: SourceSpan,
+    pub(crate) line: usize,
+    pub(crate) column: usize,
+    pub(crate) snippet: String,
+}
+
+impl From<pest::error::Error<Rule>> for ParseError {
+    fn from(err: pest::error::Error<Rule>) -> Self {
+        let span = match err.location {
+            InputLocation::Pos(p) => SourceSpan(p, 0),
+            InputLocation::Span((start, end)) => SourceSpan(start, end - start),
+        };
+        let (line, column) = match err.line_col {
+            LineColLocation::Pos(pos) | LineColLocation::Span(pos, _) => pos,
+        };
+        Self {
+            span,
+            line,
+            column,
+            snippet: err.line().trim_end().to_string(),
+        }
+    }
 }
 
 pub(crate) fn parse_type(src: &str) -> Result<NullableColType> {
@@ -259,35 +280,45 @@ pub(crate) fn parse_expressions(
     param_pool: &BTreeMap<String, DataValue>,
 ) -> Result<Expr> {
     let parsed = CozoScriptParser::parse(Rule::expression_script, src)
-        .map_err(|err| {
-            let span = match err.location {
-                InputLocation::Pos(p) => SourceSpan(p, 0),
-                InputLocation::Span((start, end)) => SourceSpan(start, end - start),
-            };
-            ParseError { span }
-        })?
+        .map_err(ParseError::from)?
         .next()
         .unwrap();
 
     build_expr(parsed.into_inner().next().unwrap(), param_pool)
 }
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("String literal in a script while string literals are forbidden")]
+#[diagnostic(code(parser::string_literal_forbidden))]
+#[diagnostic(help(
+    "Pass the string as a parameter referenced as `$name` instead, \
+    see `Db::forbid_string_literals`"
+))]
+struct StringLiteralForbidden(#[label] SourceSpan);
+
 pub(crate) fn parse_script(
     src: &str,
     param_pool: &BTreeMap<String, DataValue>,
     fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
     cur_vld: ValidityTs,
+    forbid_string_literals: bool,
 ) -> Result<CozoScript> {
     let parsed = CozoScriptParser::parse(Rule::script, src)
-        .map_err(|err| {
-            let span = match err.location {
-                InputLocation::Pos(p) => SourceSpan(p, 0),
-                InputLocation::Span((start, end)) => SourceSpan(start, end - start),
-            };
-            ParseError { span }
-        })?
+        .map_err(ParseError::from)?
         .next()
         .unwrap();
+    // system ops are administrative and take their options, such as tokenizers, as strings
+    if forbid_string_literals && parsed.as_rule() != Rule::sys_script {
+        let literal = parsed.clone().into_inner().flatten().find(|pair| {
+            matches!(
+                pair.as_rule(),
+                Rule::quoted_string | Rule::s_quoted_string | Rule::raw_string
+            )
+        });
+        if let Some(literal) = literal {
+            bail!(StringLiteralForbidden(literal.extract_span()))
+        }
+    }
     Ok(match parsed.as_rule() {
         Rule::query_script => {
             let q = parse_query(parsed.into_inner(), param_pool, fixed_rules, cur_vld)?;
@@ -24,7 +24,7 @@ use crate::data::aggr::{parse_aggr, Aggregation};
 use crate::data::expr::Expr;
 use crate::data::functions::{str2vld, MAX_VALIDITY_TS};
 use crate::data::program::{
-    FixedRuleApply, FixedRuleArg, InputAtom, InputInlineRule, InputInlineRulesOrFixed,
+    ClauseHints, FixedRuleApply, FixedRuleArg, InputAtom, InputInlineRule, InputInlineRulesOrFixed,
     InputNamedFieldRelationApplyAtom, InputProgram, InputRelationApplyAtom, InputRuleApplyAtom,
     QueryAssertion, QueryOutOptions, RelationOp, ReturnMutation, SearchInput, SortDir, Unification,
 };
@@ -67,6 +67,12 @@ struct MultipleRuleDefinitionError(String, Vec<SourceSpan>);
 #[diagnostic(code(parser::multiple_out_assert))]
 struct DuplicateQueryAssertion(#[label] SourceSpan);
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("The checksum of the rows cannot be taken when writing them to a relation")]
+#[diagnostic(code(parser::checksum_with_mutation))]
+#[diagnostic(help("Take the checksum with a query reading the relation once the write is done"))]
+struct ChecksumWithMutation(#[label] SourceSpan);
+
 #[derive(Debug, Error, Diagnostic)]
 #[error("Multiple query yields defined")]
 #[diagnostic(code(parser::multiple_yields))]
@@ -112,6 +118,8 @@ pub(crate) fn parse_query(
     let mut progs: BTreeMap<Symbol, InputInlineRulesOrFixed> = Default::default();
     let mut out_opts: QueryOutOptions = Default::default();
     let mut disable_magic_rewrite = false;
+    let mut include_retired = false;
+    let mut query_hints = ClauseHints::default();
 
     let mut stored_relation = None;
     let mut returning_mutation = ReturnMutation::NotReturning;
@@ -288,6 +296,22 @@ pub(crate) fn parse_query(
                     out_opts.sleep = Some(sleep);
                 }
             }
+            Rule::checksum_option => {
+                out_opts.checksum = Some(pair.extract_span());
+            }
+            Rule::hint_option => {
+                query_hints.merge(&parse_hint_list(pair.into_inner().next().unwrap())?);
+            }
+            Rule::debug_option => {
+                let pair = pair.into_inner().next().unwrap();
+                let span = pair.extract_span();
+                let cap = build_expr(pair, param_pool)?
+                    .eval_to_const()
+                    .map_err(|err| OptionNotConstantError("debug", span, [err]))?
+                    .get_non_neg_int()
+                    .ok_or(OptionNotNonNegIntError("debug", span))?;
+                out_opts.debug = Some(cap as usize);
+            }
             Rule::limit_option => {
                 let pair = pair.into_inner().next().unwrap();
                 let span = pair.extract_span();
@@ -340,6 +364,7 @@ pub(crate) fn parse_query(
                     Rule::relation_insert => RelationOp::Insert,
                     Rule::relation_update => RelationOp::Update,
                     Rule::relation_rm => RelationOp::Rm,
+                    Rule::relation_retract => RelationOp::Retract,
                     Rule::relation_delete => RelationOp::Delete,
                     Rule::relation_ensure => RelationOp::Ensure,
                     Rule::relation_ensure_not => RelationOp::EnsureNot,
@@ -396,15 +421,58 @@ pub(crate) fn parse_query(
                     .ok_or(OptionNotBoolError("disable_magic_rewrite", span))?;
                 disable_magic_rewrite = val;
             }
+            Rule::include_retired_option => {
+                include_retired = match pair.into_inner().next() {
+                    None => true,
+                    Some(pair) => {
+                        let span = pair.extract_span();
+                        build_expr(pair, param_pool)?
+                            .eval_to_const()
+                            .map_err(|err| OptionNotConstantError("include_retired", span, [err]))?
+                            .get_bool()
+                            .ok_or(OptionNotBoolError("include_retired", span))?
+                    }
+                };
+            }
+            Rule::disable_plan_limits_option => {
+                out_opts.disable_plan_limits = match pair.into_inner().next() {
+                    None => true,
+                    Some(pair) => {
+                        let span = pair.extract_span();
+                        build_expr(pair, param_pool)?
+                            .eval_to_const()
+                            .map_err(|err| {
+                                OptionNotConstantError("disable_plan_limits", span, [err])
+                            })?
+                            .get_bool()
+                            .ok_or(OptionNotBoolError("disable_plan_limits", span))?
+                    }
+                };
+            }
             Rule::EOI => break,
             r => unreachable!("{:?}", r),
         }
     }
 
+    if let (Some(span), Some(_)) = (out_opts.checksum, &stored_relation) {
+        bail!(ChecksumWithMutation(span))
+    }
+
+    // hints of the whole query apply to every clause, `no_magic` to the whole program
+    for rules_or_fixed in progs.values_mut() {
+        if let InputInlineRulesOrFixed::Rules { rules } = rules_or_fixed {
+            for rule in rules {
+                rule.hints.merge(&query_hints);
+            }
+        }
+    }
+    disable_magic_rewrite |= query_hints.no_magic;
+
     let mut prog = InputProgram {
         prog: progs,
         out_opts,
         disable_magic_rewrite,
+        include_retired,
     };
 
     if prog.prog.is_empty() {
@@ -559,6 +627,10 @@ fn parse_rule(
             &mut ignored_counter,
         )?)
     }
+    let hints = match src.next() {
+        Some(hints) => parse_hint_list(hints.into_inner().next().unwrap())?,
+        None => Default::default(),
+    };
 
     Ok((
         name,
@@ -566,11 +638,39 @@ fn parse_rule(
             head,
             aggr,
             body: body_clauses,
+            hints,
             span,
         },
     ))
 }
 
+fn parse_hint_list(src: Pair<'_>) -> Result<ClauseHints> {
+    #[derive(Debug, Error, Diagnostic)]
+    #[error("The index hint requires a relation and an index, as in `index(rel:idx)`")]
+    #[diagnostic(code(parser::bad_index_hint))]
+    struct BadIndexHint(#[label] SourceSpan);
+
+    let mut hints = ClauseHints::default();
+    for hint in src.into_inner() {
+        match hint.as_rule() {
+            Rule::hint_fixed_order => hints.fixed_order = true,
+            Rule::hint_no_index => hints.no_index = true,
+            Rule::hint_no_magic => hints.no_magic = true,
+            Rule::hint_index => {
+                let ident = hint.into_inner().next().unwrap();
+                let span = ident.extract_span();
+                let Some((rel, idx)) = ident.as_str().split_once(':') else {
+                    bail!(BadIndexHint(span))
+                };
+                ensure!(!idx.contains(':'), BadIndexHint(span));
+                hints.indices.insert(rel.into(), idx.into());
+            }
+            r => unreachable!("{:?}", r),
+        }
+    }
+    Ok(hints)
+}
+
 fn parse_disjunction(
     pair: Pair<'_>,
     param_pool: &BTreeMap<String, DataValue>,
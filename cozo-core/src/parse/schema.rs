@@ -112,6 +112,8 @@ fn parse_type_inner(pair: Pair<'_>) -> Result<ColType> {
         Rule::bytes_type => ColType::Bytes,
         Rule::uuid_type => ColType::Uuid,
         Rule::json_type => ColType::Json,
+        Rule::counter_type => ColType::Counter,
+        Rule::register_type => ColType::Register,
         Rule::validity_type => ColType::Validity,
         Rule::list_type => {
             let mut inner = pair.into_inner();
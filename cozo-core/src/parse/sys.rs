@@ -23,7 +23,7 @@ use crate::fts::TokenizerConfig;
 use crate::parse::expr::{build_expr, parse_string};
 use crate::parse::query::parse_query;
 use crate::parse::{ExtractSpan, Pairs, Rule, SourceSpan};
-use crate::runtime::relation::AccessLevel;
+use crate::runtime::relation::{AccessLevel, GrantMode};
 use crate::{Expr, FixedRule};
 
 #[derive(Debug)]
@@ -33,6 +33,8 @@ pub(crate) enum SysOp {
     ListIndices(Symbol),
     ListRelations,
     ListRunning,
+    ListSlowQueries,
+    ListSchedules,
     ListFixedRules,
     KillRunning(u64),
     Explain(Box<InputProgram>),
@@ -46,7 +48,13 @@ pub(crate) enum SysOp {
     CreateFtsIndex(FtsIndexConfig),
     CreateMinHashLshIndex(MinHashLshConfig),
     RemoveIndex(Symbol, Symbol),
-    DescribeRelation(Symbol, SmartString<LazyCompact>)
+    DescribeRelation(Symbol, SmartString<LazyCompact>),
+    SetExpiry(Symbol, Option<Symbol>),
+    SetRetired(Symbol, Option<Symbol>),
+    ListGrants(Symbol),
+    SetGrants(Symbol, GrantMode, Vec<Symbol>, Symbol, bool),
+    ShowRowPolicy(Symbol),
+    SetRowPolicy(Symbol, Option<String>),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -111,6 +119,8 @@ pub(crate) fn parse_sys(
     Ok(match inner.as_rule() {
         Rule::compact_op => SysOp::Compact,
         Rule::running_op => SysOp::ListRunning,
+        Rule::slow_queries_op => SysOp::ListSlowQueries,
+        Rule::schedules_op => SysOp::ListSchedules,
         Rule::kill_op => {
             let i_expr = inner.into_inner().next().unwrap();
             let i_val = build_expr(i_expr, param_pool)?;
@@ -188,6 +198,81 @@ pub(crate) fn parse_sys(
             }
             SysOp::SetAccessLevel(rels, access_level)
         }
+        Rule::expiry_op => {
+            let inner = inner.into_inner().next().unwrap();
+            match inner.as_rule() {
+                Rule::expiry_set => {
+                    let mut src = inner.into_inner();
+                    let rels_p = src.next().unwrap();
+                    let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
+                    let col_p = src.next().unwrap();
+                    let col = Symbol::new(col_p.as_str(), col_p.extract_span());
+                    SysOp::SetExpiry(rel, Some(col))
+                }
+                Rule::expiry_drop => {
+                    let rels_p = inner.into_inner().next().unwrap();
+                    let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
+                    SysOp::SetExpiry(rel, None)
+                }
+                r => unreachable!("{:?}", r),
+            }
+        }
+        Rule::retired_op => {
+            let inner = inner.into_inner().next().unwrap();
+            match inner.as_rule() {
+                Rule::retired_set => {
+                    let mut src = inner.into_inner();
+                    let rels_p = src.next().unwrap();
+                    let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
+                    let col_p = src.next().unwrap();
+                    let col = Symbol::new(col_p.as_str(), col_p.extract_span());
+                    SysOp::SetRetired(rel, Some(col))
+                }
+                Rule::retired_drop => {
+                    let rels_p = inner.into_inner().next().unwrap();
+                    let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
+                    SysOp::SetRetired(rel, None)
+                }
+                r => unreachable!("{:?}", r),
+            }
+        }
+        Rule::policy_op => {
+            let inner = inner.into_inner().next().unwrap();
+            let kind = inner.as_rule();
+            let mut src = inner.into_inner();
+            let rels_p = src.next().unwrap();
+            let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
+            match kind {
+                Rule::policy_set => {
+                    let policy = src.next().unwrap().as_str().to_string();
+                    SysOp::SetRowPolicy(rel, Some(policy))
+                }
+                Rule::policy_drop => SysOp::SetRowPolicy(rel, None),
+                Rule::policy_show => SysOp::ShowRowPolicy(rel),
+                r => unreachable!("{:?}", r),
+            }
+        }
+        Rule::grants_op => {
+            let rels_p = inner.into_inner().next().unwrap();
+            let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
+            SysOp::ListGrants(rel)
+        }
+        Rule::grant_op | Rule::revoke_op => {
+            let granted = inner.as_rule() == Rule::grant_op;
+            let mut src = inner.into_inner();
+            let mode = match src.next().unwrap().as_str() {
+                "read" => GrantMode::Read,
+                "write" => GrantMode::Write,
+                _ => unreachable!(),
+            };
+            let rels_p = src.next().unwrap();
+            let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
+            let mut cols = src
+                .map(|p| Symbol::new(p.as_str(), p.extract_span()))
+                .collect_vec();
+            let role = cols.pop().unwrap();
+            SysOp::SetGrants(rel, mode, cols, role, granted)
+        }
         Rule::trigger_relation_show_op => {
             let rels_p = inner.into_inner().next().unwrap();
             let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
@@ -0,0 +1,734 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! A syntax tree of queries for building them in programs rather than by formatting
+//! strings.
+//!
+//! A [`Query`] is rendered to CozoScript by [`Query::to_script`], which checks every
+//! name against the grammar and writes every constant as a properly escaped literal,
+//! so that no part of the tree can change the structure of the script. Values coming
+//! from users are still best passed as parameters, with [`Expr::param`].
+//!
+//! ```
+//! use cozo::ast::{Atom, Expr, Query, Rule};
+//!
+//! let query = Query::new()
+//!     .rule(Rule::new("?", ["to"]).atoms([
+//!         Atom::relation("edge", [Expr::param("fr"), Expr::var("to")]),
+//!         Atom::predicate(Expr::apply("neq", [Expr::var("to"), Expr::val("c")])),
+//!     ]))
+//!     .limit(10);
+//! assert_eq!(
+//!     query.to_script().unwrap(),
+//!     "?[to] := *edge[$fr, to], neq(to, 'c')\n:limit 10"
+//! );
+//! ```
+
+use std::fmt::Write;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use miette::{bail, Diagnostic, Result};
+use thiserror::Error;
+
+use crate::data::value::{DataValue, Num};
+
+/// An expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// A variable bound in the rule body
+    Var(String),
+    /// A parameter passed along with the query, written `$name`
+    Param(String),
+    /// A constant
+    Const(DataValue),
+    /// A list of expressions
+    List(Vec<Expr>),
+    /// The application of a function, such as `add` for `+` or `eq` for `==`
+    Apply {
+        /// Name of the function
+        op: String,
+        /// Arguments of the function
+        args: Vec<Expr>,
+    },
+}
+
+impl Expr {
+    /// A variable.
+    pub fn var(name: impl Into<String>) -> Self {
+        Expr::Var(name.into())
+    }
+    /// A parameter, given without the leading `$`.
+    pub fn param(name: impl Into<String>) -> Self {
+        Expr::Param(name.into())
+    }
+    /// A constant.
+    pub fn val(value: impl Into<DataValue>) -> Self {
+        Expr::Const(value.into())
+    }
+    /// A list.
+    pub fn list(items: impl IntoIterator<Item = Expr>) -> Self {
+        Expr::List(items.into_iter().collect())
+    }
+    /// The application of the function `op` to `args`.
+    pub fn apply(op: impl Into<String>, args: impl IntoIterator<Item = Expr>) -> Self {
+        Expr::Apply {
+            op: op.into(),
+            args: args.into_iter().collect(),
+        }
+    }
+}
+
+/// An atom of a rule body.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Atom {
+    /// An application of a rule of the query, `name[args]`
+    Rule {
+        /// Name of the rule
+        name: String,
+        /// Arguments, by position
+        args: Vec<Expr>,
+    },
+    /// An application of a stored relation with positional arguments, `*name[args]`
+    Relation {
+        /// Name of the relation
+        name: String,
+        /// Arguments, by position
+        args: Vec<Expr>,
+    },
+    /// An application of a stored relation with arguments bound to columns,
+    /// `*name{col: expr}`
+    NamedRelation {
+        /// Name of the relation
+        name: String,
+        /// Columns with the expressions bound to them
+        args: Vec<(String, Expr)>,
+    },
+    /// Binding a variable to the value of an expression, `var = expr`
+    Unify {
+        /// The variable bound
+        var: String,
+        /// Its value
+        expr: Expr,
+    },
+    /// Binding a variable to each element of a list, `var in expr`
+    Member {
+        /// The variable bound
+        var: String,
+        /// The list
+        expr: Expr,
+    },
+    /// A filter keeping the bindings for which the expression is true
+    Predicate(Expr),
+    /// The negation of an atom, `not atom`
+    Negation(Box<Atom>),
+    /// A disjunction of conjunctions, `(a, b) or (c)`
+    Disjunction(Vec<Vec<Atom>>),
+}
+
+impl Atom {
+    /// An application of the rule `name`.
+    pub fn rule(name: impl Into<String>, args: impl IntoIterator<Item = Expr>) -> Self {
+        Atom::Rule {
+            name: name.into(),
+            args: args.into_iter().collect(),
+        }
+    }
+    /// An application of the stored relation `name` with positional arguments.
+    pub fn relation(name: impl Into<String>, args: impl IntoIterator<Item = Expr>) -> Self {
+        Atom::Relation {
+            name: name.into(),
+            args: args.into_iter().collect(),
+        }
+    }
+    /// An application of the stored relation `name` binding `args` to its columns.
+    pub fn named_relation<C: Into<String>>(
+        name: impl Into<String>,
+        args: impl IntoIterator<Item = (C, Expr)>,
+    ) -> Self {
+        Atom::NamedRelation {
+            name: name.into(),
+            args: args.into_iter().map(|(c, e)| (c.into(), e)).collect(),
+        }
+    }
+    /// Binding `var` to the value of `expr`.
+    pub fn unify(var: impl Into<String>, expr: Expr) -> Self {
+        Atom::Unify {
+            var: var.into(),
+            expr,
+        }
+    }
+    /// A filter.
+    pub fn predicate(expr: Expr) -> Self {
+        Atom::Predicate(expr)
+    }
+    /// The negation of `atom`.
+    pub fn negate(atom: Atom) -> Self {
+        Atom::Negation(Box::new(atom))
+    }
+}
+
+/// An argument of a rule head.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeadArg {
+    /// A variable of the body
+    Var(String),
+    /// An aggregation over a variable of the body, such as `count(x)`
+    Aggr {
+        /// Name of the aggregation
+        aggr: String,
+        /// The variable aggregated
+        var: String,
+    },
+}
+
+impl<T: Into<String>> From<T> for HeadArg {
+    fn from(value: T) -> Self {
+        HeadArg::Var(value.into())
+    }
+}
+
+impl HeadArg {
+    /// An aggregation `aggr` over `var`.
+    pub fn aggr(aggr: impl Into<String>, var: impl Into<String>) -> Self {
+        HeadArg::Aggr {
+            aggr: aggr.into(),
+            var: var.into(),
+        }
+    }
+}
+
+/// A relation given as input to a fixed rule.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FixedInput {
+    /// A rule of the query, binding its columns to `vars` by position
+    Rule {
+        /// Name of the rule
+        name: String,
+        /// Variables, by position
+        vars: Vec<String>,
+    },
+    /// A stored relation, binding its columns to `vars` by position
+    Relation {
+        /// Name of the relation
+        name: String,
+        /// Variables, by position
+        vars: Vec<String>,
+    },
+}
+
+/// The body of a rule.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuleBody {
+    /// A conjunction of atoms, `:= a, b`
+    Atoms(Vec<Atom>),
+    /// A constant rule, `<- expr`, whose expression evaluates to a list of rows
+    Const(Expr),
+    /// A fixed rule, `<~ Algo(inputs, options)`
+    Fixed {
+        /// Name of the fixed rule
+        algo: String,
+        /// Input relations
+        inputs: Vec<FixedInput>,
+        /// Options, by name
+        options: Vec<(String, Expr)>,
+    },
+}
+
+/// A rule, the entry rule being named `?`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    /// Name of the rule
+    pub name: String,
+    /// Arguments of the head
+    pub head: Vec<HeadArg>,
+    /// The body
+    pub body: RuleBody,
+}
+
+impl Rule {
+    /// A rule `name` with an empty conjunction as body.
+    pub fn new<H: Into<HeadArg>>(
+        name: impl Into<String>,
+        head: impl IntoIterator<Item = H>,
+    ) -> Self {
+        Rule {
+            name: name.into(),
+            head: head.into_iter().map(Into::into).collect(),
+            body: RuleBody::Atoms(vec![]),
+        }
+    }
+    /// Add `atom` to the conjunction of the body, which replaces any other kind of body.
+    pub fn atom(mut self, atom: Atom) -> Self {
+        match &mut self.body {
+            RuleBody::Atoms(atoms) => atoms.push(atom),
+            body => *body = RuleBody::Atoms(vec![atom]),
+        }
+        self
+    }
+    /// Add `atoms` to the conjunction of the body.
+    pub fn atoms(self, atoms: impl IntoIterator<Item = Atom>) -> Self {
+        atoms.into_iter().fold(self, Rule::atom)
+    }
+    /// Make this a constant rule holding the rows `expr` evaluates to.
+    pub fn constant(mut self, expr: Expr) -> Self {
+        self.body = RuleBody::Const(expr);
+        self
+    }
+}
+
+/// Direction of a sort.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortDir {
+    /// Ascending
+    Asc,
+    /// Descending
+    Dsc,
+}
+
+/// The operation of a query on a stored relation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelationOp {
+    /// `:create`
+    Create,
+    /// `:replace`
+    Replace,
+    /// `:insert`
+    Insert,
+    /// `:put`
+    Put,
+    /// `:update`
+    Update,
+    /// `:rm`
+    Rm,
+    /// `:retract`
+    Retract,
+    /// `:delete`
+    Delete,
+    /// `:ensure`
+    Ensure,
+    /// `:ensure_not`
+    EnsureNot,
+}
+
+/// An option of a query.
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryOption {
+    /// `:limit`
+    Limit(u64),
+    /// `:offset`
+    Offset(u64),
+    /// `:sort`
+    Sort(Vec<(String, SortDir)>),
+    /// `:timeout`, in seconds
+    Timeout(f64),
+    /// An operation on a stored relation, with the key and value columns
+    Relation {
+        /// The operation
+        op: RelationOp,
+        /// Name of the relation
+        name: String,
+        /// Key columns
+        keys: Vec<String>,
+        /// Value columns
+        values: Vec<String>,
+    },
+    /// `:returning`
+    Returning,
+    /// `:assert none`
+    AssertNone,
+    /// `:assert some`
+    AssertSome,
+}
+
+/// A query, made of rules and options.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Query {
+    /// The rules, of which one must be the entry rule `?`
+    pub rules: Vec<Rule>,
+    /// The options
+    pub options: Vec<QueryOption>,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Invalid {0} '{1}' in the query")]
+#[diagnostic(code(ast::invalid_name))]
+struct InvalidName(&'static str, String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The value {0} cannot be written as a literal")]
+#[diagnostic(code(ast::unsupported_literal))]
+#[diagnostic(help("Pass it as a parameter instead"))]
+struct UnsupportedLiteral(String);
+
+const KEYWORDS: [&str; 6] = ["not", "or", "in", "null", "true", "false"];
+
+/// Check `name` against the rule `var` or `ident` of the grammar, also allowing dots if
+/// `dotted`.
+fn check_name(kind: &'static str, name: &str, dotted: bool) -> Result<()> {
+    let mut chars = name.chars();
+    let valid = chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_' || (dotted && c == '.'))
+        && !name.ends_with('.')
+        && !name.contains("..")
+        && !KEYWORDS.contains(&name);
+    if !valid {
+        bail!(InvalidName(kind, name.to_string()))
+    }
+    Ok(())
+}
+
+/// Render a relation name, which may name an index as `rel:idx`.
+fn relation_name(name: &str) -> Result<&str> {
+    let mut parts = name.splitn(2, ':');
+    check_name("relation name", parts.next().unwrap(), true)?;
+    if let Some(index) = parts.next() {
+        check_name("index name", index, false)?;
+    }
+    Ok(name)
+}
+
+fn join<T>(
+    out: &mut String,
+    items: &[T],
+    sep: &str,
+    mut f: impl FnMut(&mut String, &T) -> Result<()>,
+) -> Result<()> {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            out.push_str(sep);
+        }
+        f(out, item)?;
+    }
+    Ok(())
+}
+
+/// Write `s` single-quoted, as the double-quoted strings of the grammar are also matched
+/// by raw strings, which end at the first double quote.
+fn write_string(out: &mut String, s: &str) {
+    out.push('\'');
+    for c in s.chars() {
+        match c {
+            '\'' => out.push_str("\\'"),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() && (c as u32) < 0x10000 => {
+                write!(out, "\\u{:04x}", c as u32).unwrap()
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('\'');
+}
+
+fn write_const(out: &mut String, value: &DataValue) -> Result<()> {
+    match value {
+        DataValue::Null => out.push_str("null"),
+        DataValue::Bool(b) => write!(out, "{b}").unwrap(),
+        // the literal of the smallest integer would not fit once negated
+        DataValue::Num(Num::Int(i64::MIN)) => out.push_str("sub(-9223372036854775807, 1)"),
+        DataValue::Num(Num::Int(i)) => write!(out, "{i}").unwrap(),
+        DataValue::Num(Num::Float(f)) if f.is_finite() => write!(out, "{f:?}").unwrap(),
+        DataValue::Str(s) => write_string(out, s),
+        DataValue::Bytes(b) => {
+            out.push_str("decode_base64(");
+            write_string(out, &STANDARD.encode(b));
+            out.push(')');
+        }
+        DataValue::Uuid(u) => {
+            out.push_str("to_uuid(");
+            write_string(out, &u.0.to_string());
+            out.push(')');
+        }
+        DataValue::List(l) => {
+            out.push('[');
+            join(out, l, ", ", write_const)?;
+            out.push(']');
+        }
+        v => bail!(UnsupportedLiteral(v.to_string())),
+    }
+    Ok(())
+}
+
+fn write_expr(out: &mut String, expr: &Expr) -> Result<()> {
+    match expr {
+        Expr::Var(v) => {
+            check_name("variable", v, true)?;
+            out.push_str(v);
+        }
+        Expr::Param(p) => {
+            let valid = !p.is_empty()
+                && p.chars()
+                    .all(|c| c.is_alphanumeric() || c == '_' || c == '.');
+            if !valid {
+                bail!(InvalidName("parameter", p.to_string()))
+            }
+            write!(out, "${p}").unwrap();
+        }
+        Expr::Const(c) => write_const(out, c)?,
+        Expr::List(l) => {
+            out.push('[');
+            join(out, l, ", ", write_expr)?;
+            out.push(']');
+        }
+        Expr::Apply { op, args } => {
+            check_name("function name", op, false)?;
+            write!(out, "{op}(").unwrap();
+            join(out, args, ", ", write_expr)?;
+            out.push(')');
+        }
+    }
+    Ok(())
+}
+
+fn write_atom(out: &mut String, atom: &Atom) -> Result<()> {
+    match atom {
+        Atom::Rule { name, args } => {
+            check_name("rule name", name, false)?;
+            write!(out, "{name}[").unwrap();
+            join(out, args, ", ", write_expr)?;
+            out.push(']');
+        }
+        Atom::Relation { name, args } => {
+            write!(out, "*{}[", relation_name(name)?).unwrap();
+            join(out, args, ", ", write_expr)?;
+            out.push(']');
+        }
+        Atom::NamedRelation { name, args } => {
+            write!(out, "*{}{{", relation_name(name)?).unwrap();
+            join(out, args, ", ", |out, (col, expr)| {
+                check_name("column name", col, false)?;
+                write!(out, "{col}: ").unwrap();
+                write_expr(out, expr)
+            })?;
+            out.push('}');
+        }
+        Atom::Unify { var, expr } | Atom::Member { var, expr } => {
+            check_name("variable", var, true)?;
+            let op = if matches!(atom, Atom::Unify { .. }) {
+                "="
+            } else {
+                "in"
+            };
+            write!(out, "{var} {op} ").unwrap();
+            write_expr(out, expr)?;
+        }
+        Atom::Predicate(expr) => write_expr(out, expr)?,
+        Atom::Negation(atom) => {
+            out.push_str("not ");
+            write_atom(out, atom)?;
+        }
+        Atom::Disjunction(branches) => {
+            join(out, branches, " or ", |out, atoms| {
+                out.push('(');
+                join(out, atoms, ", ", write_atom)?;
+                out.push(')');
+                Ok(())
+            })?;
+        }
+    }
+    Ok(())
+}
+
+fn write_vars(out: &mut String, vars: &[String]) -> Result<()> {
+    join(out, vars, ", ", |out, var| {
+        check_name("variable", var, true)?;
+        out.push_str(var);
+        Ok(())
+    })
+}
+
+fn write_rule(out: &mut String, rule: &Rule) -> Result<()> {
+    if rule.name != "?" {
+        check_name("rule name", &rule.name, false)?;
+    }
+    write!(out, "{}[", rule.name).unwrap();
+    join(out, &rule.head, ", ", |out, arg| {
+        match arg {
+            HeadArg::Var(v) => {
+                check_name("variable", v, true)?;
+                out.push_str(v);
+            }
+            HeadArg::Aggr { aggr, var } => {
+                check_name("aggregation", aggr, false)?;
+                check_name("variable", var, true)?;
+                write!(out, "{aggr}({var})").unwrap();
+            }
+        }
+        Ok(())
+    })?;
+    out.push(']');
+    match &rule.body {
+        RuleBody::Atoms(atoms) => {
+            out.push_str(" := ");
+            join(out, atoms, ", ", write_atom)?;
+        }
+        RuleBody::Const(expr) => {
+            out.push_str(" <- ");
+            write_expr(out, expr)?;
+        }
+        RuleBody::Fixed {
+            algo,
+            inputs,
+            options,
+        } => {
+            check_name("fixed rule", algo, true)?;
+            write!(out, " <~ {algo}(").unwrap();
+            join(out, inputs, ", ", |out, input| {
+                match input {
+                    FixedInput::Rule { name, vars } => {
+                        check_name("rule name", name, false)?;
+                        write!(out, "{name}[").unwrap();
+                        write_vars(out, vars)?;
+                    }
+                    FixedInput::Relation { name, vars } => {
+                        write!(out, "*{}[", relation_name(name)?).unwrap();
+                        write_vars(out, vars)?;
+                    }
+                }
+                out.push(']');
+                Ok(())
+            })?;
+            if !inputs.is_empty() && !options.is_empty() {
+                out.push_str(", ");
+            }
+            join(out, options, ", ", |out, (name, expr)| {
+                check_name("option", name, false)?;
+                write!(out, "{name}: ").unwrap();
+                write_expr(out, expr)
+            })?;
+            out.push(')');
+        }
+    }
+    Ok(())
+}
+
+fn write_option(out: &mut String, option: &QueryOption) -> Result<()> {
+    match option {
+        QueryOption::Limit(n) => write!(out, ":limit {n}").unwrap(),
+        QueryOption::Offset(n) => write!(out, ":offset {n}").unwrap(),
+        QueryOption::Sort(args) => {
+            out.push_str(":sort ");
+            join(out, args, ", ", |out, (var, dir)| {
+                check_name("variable", var, true)?;
+                let dir = match dir {
+                    SortDir::Asc => "",
+                    SortDir::Dsc => "-",
+                };
+                write!(out, "{dir}{var}").unwrap();
+                Ok(())
+            })?;
+        }
+        QueryOption::Timeout(secs) => {
+            out.push_str(":timeout ");
+            write_const(out, &DataValue::from(*secs))?;
+        }
+        QueryOption::Relation {
+            op,
+            name,
+            keys,
+            values,
+        } => {
+            let op = match op {
+                RelationOp::Create => ":create",
+                RelationOp::Replace => ":replace",
+                RelationOp::Insert => ":insert",
+                RelationOp::Put => ":put",
+                RelationOp::Update => ":update",
+                RelationOp::Rm => ":rm",
+                RelationOp::Retract => ":retract",
+                RelationOp::Delete => ":delete",
+                RelationOp::Ensure => ":ensure",
+                RelationOp::EnsureNot => ":ensure_not",
+            };
+            check_name("relation name", name, true)?;
+            write!(out, "{op} {name} {{").unwrap();
+            let cols = |out: &mut String, cols: &[String]| {
+                join(out, cols, ", ", |out, col| {
+                    check_name("column name", col, false)?;
+                    out.push_str(col);
+                    Ok(())
+                })
+            };
+            cols(out, keys)?;
+            if !values.is_empty() {
+                out.push_str(" => ");
+                cols(out, values)?;
+            }
+            out.push('}');
+        }
+        QueryOption::Returning => out.push_str(":returning"),
+        QueryOption::AssertNone => out.push_str(":assert none"),
+        QueryOption::AssertSome => out.push_str(":assert some"),
+    }
+    Ok(())
+}
+
+impl Query {
+    /// An empty query.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Add a rule.
+    pub fn rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+    /// Add an option.
+    pub fn option(mut self, option: QueryOption) -> Self {
+        self.options.push(option);
+        self
+    }
+    /// Return at most `n` rows.
+    pub fn limit(self, n: u64) -> Self {
+        self.option(QueryOption::Limit(n))
+    }
+    /// Skip the first `n` rows.
+    pub fn offset(self, n: u64) -> Self {
+        self.option(QueryOption::Offset(n))
+    }
+    /// Sort the rows by `vars`.
+    pub fn sort<V: Into<String>>(self, vars: impl IntoIterator<Item = (V, SortDir)>) -> Self {
+        let vars = vars.into_iter().map(|(v, d)| (v.into(), d)).collect();
+        self.option(QueryOption::Sort(vars))
+    }
+    /// Apply `op` to the stored relation `name` with the rows of the entry rule.
+    pub fn relation_op<K: Into<String>, V: Into<String>>(
+        self,
+        op: RelationOp,
+        name: impl Into<String>,
+        keys: impl IntoIterator<Item = K>,
+        values: impl IntoIterator<Item = V>,
+    ) -> Self {
+        self.option(QueryOption::Relation {
+            op,
+            name: name.into(),
+            keys: keys.into_iter().map(Into::into).collect(),
+            values: values.into_iter().map(Into::into).collect(),
+        })
+    }
+    /// Render the query as CozoScript, one rule or option per line.
+    pub fn to_script(&self) -> Result<String> {
+        let mut out = String::new();
+        for rule in &self.rules {
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            write_rule(&mut out, rule)?;
+        }
+        for option in &self.options {
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            write_option(&mut out, option)?;
+        }
+        Ok(out)
+    }
+}
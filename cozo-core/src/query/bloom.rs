@@ -0,0 +1,73 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use crate::data::value::DataValue;
+
+const BITS_PER_KEY: usize = 10;
+const HASHES: u64 = 7;
+
+/// A bloom filter over the join keys of one side of a join, used to skip the rows of
+/// the other side that cannot match. With the sizes used, about 1% of the keys not
+/// inserted are reported as possibly present.
+pub(crate) struct BloomFilter {
+    bits: Vec<u64>,
+}
+
+impl BloomFilter {
+    pub(crate) fn with_capacity(keys: usize) -> Self {
+        let words = (keys.max(1) * BITS_PER_KEY).div_ceil(64);
+        Self {
+            bits: vec![0; words],
+        }
+    }
+    pub(crate) fn insert<'a>(&mut self, key: impl Iterator<Item = &'a DataValue>) {
+        for pos in self.positions(key) {
+            self.bits[pos / 64] |= 1 << (pos % 64);
+        }
+    }
+    pub(crate) fn may_contain<'a>(&self, key: impl Iterator<Item = &'a DataValue>) -> bool {
+        self.positions(key)
+            .all(|pos| self.bits[pos / 64] & (1 << (pos % 64)) != 0)
+    }
+    /// The bits of `key`, derived from a single hash by double hashing.
+    fn positions<'a>(
+        &self,
+        key: impl Iterator<Item = &'a DataValue>,
+    ) -> impl Iterator<Item = usize> {
+        let mut hasher = DefaultHasher::new();
+        for v in key {
+            v.hash(&mut hasher);
+        }
+        let h1 = hasher.finish();
+        let h2 = h1.rotate_left(32) | 1;
+        let n_bits = (self.bits.len() * 64) as u64;
+        (0..HASHES).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % n_bits) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bloom_filter() {
+        let mut filter = BloomFilter::with_capacity(1000);
+        let key = |i: i64| [DataValue::from(i), DataValue::from(format!("k{i}"))];
+        for i in 0..1000 {
+            filter.insert(key(i).iter());
+        }
+        assert!((0..1000).all(|i| filter.may_contain(key(i).iter())));
+        let false_positives = (1000..11000)
+            .filter(|i| filter.may_contain(key(*i).iter()))
+            .count();
+        assert!(false_positives < 300, "{false_positives}");
+    }
+}
@@ -10,6 +10,7 @@ use std::collections::{BTreeMap, BTreeSet};
 
 use itertools::Itertools;
 use miette::{bail, ensure, Context, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
 use crate::data::aggr::Aggregation;
@@ -22,7 +23,7 @@ use crate::data::symb::Symbol;
 use crate::data::value::DataValue;
 use crate::parse::SourceSpan;
 use crate::query::ra::RelAlgebra;
-use crate::runtime::relation::{AccessLevel, InsufficientAccessLevel};
+use crate::runtime::relation::{AccessLevel, GrantMode, InsufficientAccessLevel, RelationHandle};
 use crate::runtime::transact::SessionTx;
 
 pub(crate) type CompiledProgram = BTreeMap<MagicSymbol, CompiledRuleSet>;
@@ -234,6 +235,12 @@ impl<'a> SessionTx<'a> {
                             rel_app.span
                         )
                     );
+                    self.ensure_granted(
+                        &store,
+                        bound_columns(&store, &rel_app.args),
+                        GrantMode::Read,
+                        rel_app.span,
+                    )?;
                     // already existing vars
                     let mut prev_joiner_vars = vec![];
                     // vars introduced by right and joined
@@ -264,18 +271,35 @@ impl<'a> SessionTx<'a> {
                         }
                     }
 
-                    let chosen_index =
-                        store.choose_index(&join_indices, rel_app.valid_at.is_some());
+                    // rows outside of the row policy are filtered out of a full scan
+                    let policy = self
+                        .row_policy(&store)?
+                        .map(|policy| policy_filter(policy, &store, &right_vars));
+                    let chosen_index = if policy.is_some() || rule.hints.no_index {
+                        None
+                    } else if let Some(idx) = rule.hints.indices.get(&store.name) {
+                        Some(store.hinted_index(
+                            idx,
+                            &join_indices,
+                            rel_app.valid_at.is_some(),
+                            rel_app.span,
+                        )?)
+                    } else {
+                        store.choose_index(&join_indices, rel_app.valid_at.is_some())
+                    };
 
                     match chosen_index {
                         None => {
                             // scan original relation
-                            let right = RelAlgebra::relation(
+                            let mut right = RelAlgebra::relation(
                                 right_vars,
                                 store,
                                 rel_app.span,
                                 rel_app.valid_at,
                             )?;
+                            if let Some(policy) = policy {
+                                right = right.filter(policy)?;
+                            }
                             debug_assert_eq!(prev_joiner_vars.len(), right_joiner_vars.len());
                             ret =
                                 ret.join(right, prev_joiner_vars, right_joiner_vars, rel_app.span);
@@ -402,6 +426,12 @@ impl<'a> SessionTx<'a> {
                             rel_app.span
                         )
                     );
+                    self.ensure_granted(
+                        &store,
+                        bound_columns(&store, &rel_app.args),
+                        GrantMode::Read,
+                        rel_app.span,
+                    )?;
 
                     // already existing vars
                     let mut prev_joiner_vars = vec![];
@@ -433,17 +463,34 @@ impl<'a> SessionTx<'a> {
                         }
                     }
 
-                    let chosen_index =
-                        store.choose_index(&join_indices, rel_app.valid_at.is_some());
+                    // rows outside of the row policy are filtered out of a full scan
+                    let policy = self
+                        .row_policy(&store)?
+                        .map(|policy| policy_filter(policy, &store, &right_vars));
+                    let chosen_index = if policy.is_some() || rule.hints.no_index {
+                        None
+                    } else if let Some(idx) = rule.hints.indices.get(&store.name) {
+                        Some(store.hinted_index(
+                            idx,
+                            &join_indices,
+                            rel_app.valid_at.is_some(),
+                            rel_app.span,
+                        )?)
+                    } else {
+                        store.choose_index(&join_indices, rel_app.valid_at.is_some())
+                    };
 
                     match chosen_index {
                         None | Some((_, _, true)) => {
-                            let right = RelAlgebra::relation(
+                            let mut right = RelAlgebra::relation(
                                 right_vars,
                                 store,
                                 rel_app.span,
                                 rel_app.valid_at,
                             )?;
+                            if let Some(policy) = policy {
+                                right = right.filter(policy)?;
+                            }
                             debug_assert_eq!(prev_joiner_vars.len(), right_joiner_vars.len());
                             ret = ret.neg_join(
                                 right,
@@ -506,7 +553,19 @@ impl<'a> SessionTx<'a> {
                             own_bindings.push(var.clone());
                         }
                     }
+                    self.ensure_granted(
+                        &s.base_handle,
+                        bound_columns(&s.base_handle, &s.bindings),
+                        GrantMode::Read,
+                        s.span,
+                    )?;
+                    let policy = self
+                        .row_policy(&s.base_handle)?
+                        .map(|policy| policy_filter(policy, &s.base_handle, &own_bindings));
                     ret = ret.hnsw_search(s.clone(), own_bindings)?;
+                    if let Some(policy) = policy {
+                        ret = ret.filter(policy)?;
+                    }
                     if !post_filters.is_empty() {
                         ret = ret.filter(Expr::build_and(post_filters, s.span))?;
                     }
@@ -540,7 +599,19 @@ impl<'a> SessionTx<'a> {
                             own_bindings.push(var.clone());
                         }
                     }
+                    self.ensure_granted(
+                        &s.base_handle,
+                        bound_columns(&s.base_handle, &s.bindings),
+                        GrantMode::Read,
+                        s.span,
+                    )?;
+                    let policy = self
+                        .row_policy(&s.base_handle)?
+                        .map(|policy| policy_filter(policy, &s.base_handle, &own_bindings));
                     ret = ret.fts_search(s.clone(), own_bindings)?;
+                    if let Some(policy) = policy {
+                        ret = ret.filter(policy)?;
+                    }
                     if !post_filters.is_empty() {
                         ret = ret.filter(Expr::build_and(post_filters, s.span))?;
                     }
@@ -574,7 +645,19 @@ impl<'a> SessionTx<'a> {
                             own_bindings.push(var.clone());
                         }
                     }
+                    self.ensure_granted(
+                        &s.base_handle,
+                        bound_columns(&s.base_handle, &s.bindings),
+                        GrantMode::Read,
+                        s.span,
+                    )?;
+                    let policy = self
+                        .row_policy(&s.base_handle)?
+                        .map(|policy| policy_filter(policy, &s.base_handle, &own_bindings));
                     ret = ret.lsh_search(s.clone(), own_bindings)?;
+                    if let Some(policy) = policy {
+                        ret = ret.filter(policy)?;
+                    }
                     if !post_filters.is_empty() {
                         ret = ret.filter(Expr::build_and(post_filters, s.span))?;
                     }
@@ -643,3 +726,50 @@ impl<'a> SessionTx<'a> {
         Ok(ret)
     }
 }
+
+/// Names of the columns of `handle` that `vars` bind, skipping the ignored positions.
+fn bound_columns<'c>(
+    handle: &'c RelationHandle,
+    vars: &'c [Symbol],
+) -> impl Iterator<Item = &'c str> + 'c {
+    vars.iter()
+        .zip(handle.metadata.keys.iter().chain(handle.metadata.non_keys.iter()))
+        .filter(|(var, _)| !var.is_ignored_symbol() && !var.is_generated_ignored_symbol())
+        .map(|(_, col)| col.name.as_str())
+}
+
+/// The row policy of `handle` as a filter over an atom binding its columns to `vars`.
+fn policy_filter(mut policy: Expr, handle: &RelationHandle, vars: &[Symbol]) -> Expr {
+    let renames: BTreeMap<_, _> = handle
+        .metadata
+        .keys
+        .iter()
+        .chain(handle.metadata.non_keys.iter())
+        .map(|col| &col.name)
+        .zip(vars)
+        .collect();
+    rename_bindings(&mut policy, &renames);
+    policy
+}
+
+fn rename_bindings(expr: &mut Expr, renames: &BTreeMap<&SmartString<LazyCompact>, &Symbol>) {
+    match expr {
+        Expr::Binding { var, .. } => {
+            if let Some(new_var) = renames.get(&var.name) {
+                *var = (*new_var).clone();
+            }
+        }
+        Expr::Const { .. } => {}
+        Expr::Apply { args, .. } | Expr::UnboundApply { args, .. } => {
+            for arg in args.iter_mut() {
+                rename_bindings(arg, renames);
+            }
+        }
+        Expr::Cond { clauses, .. } => {
+            for (cond, val) in clauses {
+                rename_bindings(cond, renames);
+                rename_bindings(val, renames);
+            }
+        }
+    }
+}
@@ -8,6 +8,7 @@
 
 use std::collections::btree_map::Entry;
 use std::collections::BTreeMap;
+use std::mem::size_of;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use itertools::Itertools;
@@ -27,8 +28,11 @@ use crate::query::compile::{
     AggrKind, CompiledProgram, CompiledRule, CompiledRuleSet, ContainedRuleMultiplicity,
 };
 use crate::runtime::db::Poison;
+use crate::runtime::limits::EvalProgress;
 use crate::runtime::temp_store::{EpochStore, MeetAggrStore, RegularTempStore};
 use crate::runtime::transact::SessionTx;
+use crate::utils::trace_span;
+use crate::NamedRows;
 
 pub(crate) struct QueryLimiter {
     total: Option<usize>,
@@ -61,6 +65,45 @@ impl QueryLimiter {
     }
 }
 
+/// The rows derived for each rule in each epoch of an evaluation, returned by queries
+/// with the `:debug` option.
+pub(crate) struct EvalDump {
+    /// Rows kept for each rule in each epoch
+    cap: usize,
+    rows: Vec<Tuple>,
+}
+
+impl EvalDump {
+    pub(crate) fn new(cap: usize) -> Self {
+        Self { cap, rows: vec![] }
+    }
+    /// Record the rows new in `store` after `epoch`. Epochs deriving nothing new are
+    /// recorded only for the first.
+    fn record(&mut self, stratum: usize, rule: &MagicSymbol, epoch: u32, store: &EpochStore) {
+        let new_rows = store.delta_len();
+        if epoch > 0 && new_rows == 0 {
+            return;
+        }
+        let kept = store
+            .delta_all_iter()
+            .take(self.cap)
+            .map(|t| DataValue::List(t.into_tuple()))
+            .collect_vec();
+        self.rows.push(vec![
+            DataValue::from(stratum as i64),
+            DataValue::from(rule.to_string()),
+            DataValue::from(epoch as i64),
+            DataValue::from(new_rows as i64),
+            DataValue::from(store.len() as i64),
+            DataValue::List(kept),
+        ]);
+    }
+    pub(crate) fn into_named_rows(self) -> NamedRows {
+        let headers = ["stratum", "rule", "epoch", "new_rows", "total_rows", "rows"];
+        NamedRows::new(headers.map(|h| h.to_string()).to_vec(), self.rows)
+    }
+}
+
 impl<'a> SessionTx<'a> {
     pub(crate) fn stratified_magic_evaluate(
         &self,
@@ -69,10 +112,13 @@ impl<'a> SessionTx<'a> {
         total_num_to_take: Option<usize>,
         num_to_skip: Option<usize>,
         poison: Poison,
+        mut dump: Option<&mut EvalDump>,
     ) -> Result<(EpochStore, bool)> {
+        let _span = trace_span!("evaluate", tx = self.session.id, strata = strata.len());
         let mut stores: BTreeMap<MagicSymbol, EpochStore> = BTreeMap::new();
         let mut early_return = false;
         for (stratum, cur_prog) in strata.iter().enumerate() {
+            let _span = trace_span!("stratum", stratum, rules = cur_prog.len());
             if stratum > 0 {
                 // remove stores that have outlived their usefulness!
                 stores.retain(|name, _| match store_lifetimes.get(name) {
@@ -101,6 +147,8 @@ impl<'a> SessionTx<'a> {
                 total_num_to_take,
                 num_to_skip,
                 poison.clone(),
+                stratum,
+                dump.as_deref_mut(),
             )?;
         }
         let entry_symbol = MagicSymbol::Muggle {
@@ -117,6 +165,8 @@ impl<'a> SessionTx<'a> {
         total_num_to_take: Option<usize>,
         num_to_skip: Option<usize>,
         poison: Poison,
+        stratum: usize,
+        mut dump: Option<&mut EvalDump>,
     ) -> Result<bool> {
         let limiter = QueryLimiter {
             total: total_num_to_take,
@@ -128,6 +178,7 @@ impl<'a> SessionTx<'a> {
 
         for epoch in 0u32.. {
             debug!("epoch {}", epoch);
+            let epoch_span = trace_span!("epoch", epoch, rows = tracing::field::Empty);
             let mut to_merge = BTreeMap::new();
             let borrowed_stores = stores as &BTreeMap<_, _>;
             if epoch == 0 {
@@ -288,12 +339,36 @@ impl<'a> SessionTx<'a> {
                     }
                 }
             }
+            epoch_span.record(
+                "rows",
+                to_merge.values().map(|s| s.len()).sum::<usize>() as u64,
+            );
             let mut changed = false;
+            let mut delta_rows = 0;
             for (k, new_store) in to_merge {
                 let old_store = stores.get_mut(k).unwrap();
                 old_store.merge_in(new_store)?;
                 trace!("delta for {}: {}", k, old_store.has_delta());
                 changed |= old_store.has_delta();
+                delta_rows += old_store.delta_len();
+                if let Some(dump) = dump.as_deref_mut() {
+                    dump.record(stratum, k, epoch, old_store);
+                }
+            }
+            let progress = EvalProgress {
+                session_id: self.session.id,
+                stratum,
+                iteration: epoch,
+                delta_rows,
+                total_rows: stores.values().map(|s| s.len()).sum(),
+            };
+            self.session.eval_guard().after_iteration(progress, changed)?;
+            if let Some(quota) = &self.session.quota {
+                let held = stores
+                    .values()
+                    .map(|s| s.len() * s.arity.max(1) * size_of::<DataValue>())
+                    .sum();
+                quota.ensure_memory(held)?;
             }
             if !changed {
                 break;
@@ -13,13 +13,18 @@ use miette::{bail, ensure, Diagnostic, Result};
 use thiserror::Error;
 
 use crate::data::expr::Expr;
+use crate::data::functions::{OP_EQ, OP_NEQ};
 use crate::data::program::{
     InputAtom, InputNamedFieldRelationApplyAtom, InputRelationApplyAtom, InputRuleApplyAtom,
     NormalFormAtom, NormalFormRelationApplyAtom, NormalFormRuleApplyAtom, TempSymbGen, Unification,
 };
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
 use crate::parse::SourceSpan;
 use crate::query::reorder::UnsafeNegation;
+use crate::runtime::relation::RelationHandle;
 use crate::runtime::transact::SessionTx;
+use crate::utils::closest_match;
 
 #[derive(Debug)]
 pub(crate) struct Disjunction {
@@ -129,10 +134,14 @@ impl InputAtom {
         })
     }
 
-    pub(crate) fn disjunctive_normal_form(self, tx: &SessionTx<'_>) -> Result<Disjunction> {
+    pub(crate) fn disjunctive_normal_form(
+        self,
+        tx: &SessionTx<'_>,
+        include_retired: bool,
+    ) -> Result<Disjunction> {
         let neg_form = self.negation_normal_form()?;
         let mut gen = TempSymbGen::default();
-        neg_form.do_disjunctive_normal_form(&mut gen, tx)
+        neg_form.do_disjunctive_normal_form(&mut gen, tx, include_retired)
     }
 
     fn convert_named_field_relation(
@@ -154,10 +163,7 @@ impl InputAtom {
             .map(|col| &col.name)
             .collect();
         for k in args.keys() {
-            ensure!(
-                fields.contains(k),
-                NamedFieldNotFound(name.to_string(), k.to_string(), span)
-            );
+            ensure!(fields.contains(k), NamedFieldNotFound::new(&stored, k, span));
         }
         let mut new_args = vec![];
         for col_def in stored
@@ -184,6 +190,7 @@ impl InputAtom {
         self,
         gen: &mut TempSymbGen,
         tx: &SessionTx<'_>,
+        include_retired: bool,
     ) -> Result<Disjunction> {
         // invariants: the input is already in negation normal form
         // the return value is a disjunction of conjunctions, with no nesting
@@ -191,7 +198,10 @@ impl InputAtom {
             InputAtom::Disjunction { inner: args, .. } => {
                 let mut ret = vec![];
                 for arg in args {
-                    for a in arg.do_disjunctive_normal_form(gen, tx)?.inner {
+                    for a in arg
+                        .do_disjunctive_normal_form(gen, tx, include_retired)?
+                        .inner
+                    {
                         ret.push(a);
                     }
                 }
@@ -200,7 +210,7 @@ impl InputAtom {
             InputAtom::Conjunction { inner: args, .. } => {
                 let mut args = args
                     .into_iter()
-                    .map(|a| a.do_disjunctive_normal_form(gen, tx));
+                    .map(|a| a.do_disjunctive_normal_form(gen, tx, include_retired));
                 let mut result = args.next().unwrap()?;
                 for a in args {
                     result = result.conjunctive_to_disjunctive_de_morgen(a?)
@@ -210,19 +220,23 @@ impl InputAtom {
             InputAtom::Rule { inner: r } => r.normalize(false, gen),
             InputAtom::NamedFieldRelation { inner } => {
                 let r = Self::convert_named_field_relation(inner, gen, tx)?;
-                r.normalize(false, gen)
+                r.normalize_hiding_retired(false, gen, tx, include_retired)?
+            }
+            InputAtom::Relation { inner: v } => {
+                v.normalize_hiding_retired(false, gen, tx, include_retired)?
             }
-            InputAtom::Relation { inner: v } => v.normalize(false, gen),
             InputAtom::Predicate { inner: mut p } => {
                 p.partial_eval()?;
                 Disjunction::singlet(NormalFormAtom::Predicate(p))
             }
             InputAtom::Negation { inner: n, .. } => match *n {
                 InputAtom::Rule { inner: r } => r.normalize(true, gen),
-                InputAtom::Relation { inner: v } => v.normalize(true, gen),
+                InputAtom::Relation { inner: v } => {
+                    v.normalize_hiding_retired(true, gen, tx, include_retired)?
+                }
                 InputAtom::NamedFieldRelation { inner } => {
                     let r = Self::convert_named_field_relation(inner, gen, tx)?;
-                    r.normalize(true, gen)
+                    r.normalize_hiding_retired(true, gen, tx, include_retired)?
                 }
                 _ => unreachable!(),
             },
@@ -295,6 +309,141 @@ impl InputRuleApplyAtom {
 }
 
 impl InputRelationApplyAtom {
+    /// Normalizes the atom so that rows whose retired marker (set by `::retired set`)
+    /// is `true` are invisible, unless `include_retired` is set.
+    ///
+    /// The marker is only enforced if its position is bound to a variable: an explicit
+    /// expression for the marker column is taken to mean that the caller knows what it wants.
+    fn normalize_hiding_retired(
+        mut self,
+        is_negated: bool,
+        gen: &mut TempSymbGen,
+        tx: &SessionTx<'_>,
+        include_retired: bool,
+    ) -> Result<Disjunction> {
+        if include_retired || self.name.is_temp_store_name() {
+            return Ok(self.normalize(is_negated, gen));
+        }
+        let stored = tx.get_relation(&self.name, false)?;
+        // an index read directly hides the rows retired in its base relation
+        let retired_column = match tx.base_relation(&self.name)? {
+            None => stored.retired_column.clone(),
+            Some(base) => {
+                let marker = base.retired_column.clone();
+                if let Some(marker) = &marker {
+                    let in_index = stored
+                        .metadata
+                        .keys
+                        .iter()
+                        .chain(stored.metadata.non_keys.iter())
+                        .any(|col| &col.name == marker);
+                    if !in_index {
+                        #[derive(Debug, Error, Diagnostic)]
+                        #[error("index {0} does not hold the retired marker {1} of its relation")]
+                        #[diagnostic(code(eval::retired_index_read))]
+                        #[diagnostic(help(
+                            "Read the relation instead, or add the option `:include_retired`"
+                        ))]
+                        struct RetiredIndexRead(String, String, #[label] SourceSpan);
+                        bail!(RetiredIndexRead(
+                            self.name.to_string(),
+                            marker.to_string(),
+                            self.span
+                        ))
+                    }
+                }
+                marker
+            }
+        };
+        let (pos, nullable) = match retired_column.as_ref().and_then(|marker| {
+            stored
+                .metadata
+                .keys
+                .iter()
+                .chain(stored.metadata.non_keys.iter())
+                .position(|col| &col.name == marker)
+        }) {
+            Some(pos) if pos < self.args.len() => {
+                let nullable = stored
+                    .metadata
+                    .keys
+                    .iter()
+                    .chain(stored.metadata.non_keys.iter())
+                    .nth(pos)
+                    .unwrap()
+                    .typing
+                    .nullable;
+                (pos, nullable)
+            }
+            _ => return Ok(self.normalize(is_negated, gen)),
+        };
+        let marker = match &self.args[pos] {
+            Expr::Binding { var, .. } => var.clone(),
+            _ => return Ok(self.normalize(is_negated, gen)),
+        };
+        let marker_ignored = marker.is_ignored_symbol() || marker.is_generated_ignored_symbol();
+        let span = self.span;
+        let marker_is = |var: Symbol, op, val: bool| {
+            NormalFormAtom::Predicate(Expr::Apply {
+                op,
+                args: [
+                    Expr::Binding {
+                        var,
+                        tuple_pos: None,
+                    },
+                    Expr::Const {
+                        val: DataValue::from(val),
+                        span,
+                    },
+                ]
+                .into(),
+                span,
+            })
+        };
+
+        Ok(if !is_negated {
+            let var = if marker_ignored {
+                let var = gen.next(span);
+                self.args[pos] = Expr::Binding {
+                    var: var.clone(),
+                    tuple_pos: None,
+                };
+                var
+            } else {
+                marker
+            };
+            self.normalize(false, gen)
+                .conjunctive_to_disjunctive_de_morgen(Disjunction::singlet(marker_is(
+                    var, &OP_NEQ, true,
+                )))
+        } else if marker_ignored {
+            // `not *r[.., _]` becomes one negation per non-retired marker value
+            let mut not_retired_vals = vec![DataValue::from(false)];
+            if nullable {
+                not_retired_vals.push(DataValue::Null);
+            }
+            let mut ret = Disjunction::conj(vec![]);
+            for val in not_retired_vals {
+                let mut atom = self.clone();
+                for arg in atom.args.iter_mut() {
+                    if let Expr::Binding { var, .. } = arg {
+                        if var.is_generated_ignored_symbol() {
+                            *var = gen.next_ignored(var.span);
+                        }
+                    }
+                }
+                atom.args[pos] = Expr::Const { val, span };
+                ret = ret.conjunctive_to_disjunctive_de_morgen(atom.normalize(true, gen));
+            }
+            ret
+        } else {
+            // with the marker already bound, a retired match cannot block
+            let mut ret = self.normalize(true, gen);
+            ret.inner
+                .push(Conjunction(vec![marker_is(marker, &OP_EQ, true)]));
+            ret
+        })
+    }
     fn normalize(self, is_negated: bool, gen: &mut TempSymbGen) -> Disjunction {
         let mut ret = Vec::with_capacity(self.args.len() + 1);
         let mut args = Vec::with_capacity(self.args.len());
@@ -363,4 +512,21 @@ pub(crate) struct NamedFieldNotFound(
     pub(crate) String,
     pub(crate) String,
     #[label] pub(crate) SourceSpan,
+    #[help] pub(crate) Option<String>,
 );
+
+impl NamedFieldNotFound {
+    pub(crate) fn new(relation: &RelationHandle, field: &str, span: SourceSpan) -> Self {
+        let suggestion = closest_match(
+            field,
+            relation
+                .metadata
+                .keys
+                .iter()
+                .chain(relation.metadata.non_keys.iter())
+                .map(|col| col.name.as_str()),
+        )
+        .map(|col| format!("Did you mean `{col}`?"));
+        Self(relation.name.to_string(), field.to_string(), span, suggestion)
+    }
+}
@@ -37,6 +37,10 @@ impl NormalFormProgram {
             match rule_set {
                 NormalFormRulesOrFixed::Rules { rules: rule_set } => {
                     'outer: for rule in rule_set.iter() {
+                        if rule.hints.no_magic {
+                            exempt_rules.insert(name.clone());
+                            continue;
+                        }
                         for aggr in rule.aggr.iter() {
                             if aggr.is_some() {
                                 exempt_rules.insert(name.clone());
@@ -149,6 +153,7 @@ fn magic_rewrite_ruleset(
                         head: sup_args.clone(),
                         aggr: sup_aggr,
                         body: sup_body,
+                        hints: Default::default(),
                     }],
                 },
             );
@@ -207,6 +212,7 @@ fn magic_rewrite_ruleset(
                             head: args.clone(),
                             aggr: vec![None; args.len()],
                             body: sup_rule_atoms,
+                            hints: rule.hints.clone(),
                         });
 
                         // add the sup rule application to the collected atoms
@@ -247,6 +253,7 @@ fn magic_rewrite_ruleset(
                             head: inp_args,
                             aggr: inp_aggr,
                             body: vec![sup_rule_app],
+                            hints: Default::default(),
                         });
                     }
                     seen_bindings.extend(r_app.args.iter().cloned());
@@ -265,6 +272,7 @@ fn magic_rewrite_ruleset(
             head: rule.head,
             aggr: rule.aggr,
             body: collected_atoms,
+            hints: rule.hints,
         });
     }
 }
@@ -416,11 +424,7 @@ impl NormalFormProgram {
                                                 for k in bindings.keys() {
                                                     ensure!(
                                                         fields.contains(&k),
-                                                        NamedFieldNotFound(
-                                                            name.to_string(),
-                                                            k.to_string(),
-                                                            *span
-                                                        )
+                                                        NamedFieldNotFound::new(&relation, k, *span)
                                                     );
                                                 }
                                                 let new_bindings = relation
@@ -630,6 +634,7 @@ impl NormalFormInlineRule {
             head: self.head.clone(),
             aggr: self.aggr.clone(),
             body: ret_body,
+            hints: self.hints.clone(),
         }
     }
 }
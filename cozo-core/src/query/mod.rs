@@ -6,6 +6,8 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+pub mod ast;
+pub(crate) mod bloom;
 pub(crate) mod compile;
 pub(crate) mod eval;
 pub(crate) mod graph;
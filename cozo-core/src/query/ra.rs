@@ -24,6 +24,7 @@ use crate::data::symb::Symbol;
 use crate::data::tuple::{Tuple, TupleIter};
 use crate::data::value::{DataValue, ValidityTs};
 use crate::parse::SourceSpan;
+use crate::query::bloom::BloomFilter;
 use crate::runtime::minhash_lsh::LshSearch;
 use crate::runtime::relation::RelationHandle;
 use crate::runtime::temp_store::EpochStore;
@@ -386,6 +387,7 @@ impl RelAlgebra {
             }
             RelAlgebra::NegJoin(r) => {
                 r.left.fill_binding_indices_and_compile()?;
+                r.right.fill_binding_indices_and_compile()?;
             }
             RelAlgebra::Unification(u) => {
                 u.parent.fill_binding_indices_and_compile()?;
@@ -1421,7 +1423,9 @@ impl StoredRA {
                             .map(|i| tuple[*i].clone())
                             .collect_vec();
 
-                        'outer: for found in self.storage.scan_prefix(tx, &prefix) {
+                        let found_iter = self.storage.scan_prefix(tx, &prefix);
+                        'outer: for found in filter_iter(self.filters_bytecodes.clone(), found_iter)
+                        {
                             let found = found?;
                             for (left_idx, right_idx) in
                                 left_join_indices.iter().zip(right_join_indices.iter())
@@ -1455,7 +1459,7 @@ impl StoredRA {
         } else {
             let mut right_join_vals = BTreeSet::new();
 
-            for tuple in self.storage.scan_all(tx) {
+            for tuple in filter_iter(self.filters_bytecodes.clone(), self.storage.scan_all(tx)) {
                 let tuple = tuple?;
                 let to_join: Box<[DataValue]> = right_join_indices
                     .iter()
@@ -2233,13 +2237,36 @@ impl InnerJoin {
             .join_indices(&self.left.bindings_after_eliminate(), &right_bindings)
             .unwrap();
 
+        // a small left side is read whole first, so that only the rows of the right side
+        // whose keys may be among its keys are materialized
         let mut left_iter = self.left.iter(tx, delta_rule, stores)?;
-        let left_cache = match left_iter.next() {
+        let buffered = if left_join_indices.is_empty() {
+            1
+        } else {
+            SEMI_JOIN_MAX_LEFT_ROWS + 1
+        };
+        let mut left_rows = vec![];
+        for item in left_iter.by_ref().take(buffered) {
+            left_rows.push(item?);
+        }
+        let key_filter = (!left_join_indices.is_empty()
+            && left_rows.len() <= SEMI_JOIN_MAX_LEFT_ROWS)
+            .then(|| {
+                debug!("filtering the right side of the join by {} keys", left_rows.len());
+                let mut filter = BloomFilter::with_capacity(left_rows.len());
+                for row in &left_rows {
+                    filter.insert(left_join_indices.iter().map(|i| &row[*i]));
+                }
+                filter
+            });
+        let mut left_rows = left_rows.into_iter();
+        let left_cache = match left_rows.next() {
             None => return Ok(Box::new(iter::empty())),
-            Some(Err(err)) => return Err(err),
-            Some(Ok(data)) => data,
+            Some(data) => data,
         };
+        let left_iter: TupleIter<'a> = Box::new(left_rows.map(Ok).chain(left_iter));
 
+        let n_keys = right_join_indices.len();
         let right_join_indices_set = BTreeSet::from_iter(right_join_indices.iter().cloned());
         let mut right_store_indices = right_join_indices;
         for i in 0..right_bindings.len() {
@@ -2259,6 +2286,12 @@ impl InnerJoin {
             for item in self.right.iter(tx, delta_rule, stores)? {
                 match item {
                     Ok(tuple) => {
+                        if let Some(filter) = &key_filter {
+                            let key = right_store_indices[..n_keys].iter().map(|i| &tuple[*i]);
+                            if !filter.may_contain(key) {
+                                continue;
+                            }
+                        }
                         let stored_tuple = right_store_indices
                             .iter()
                             .map(|i| tuple[*i].clone())
@@ -2288,6 +2321,10 @@ impl InnerJoin {
     }
 }
 
+/// The most rows of the left side of a materialized join for which the rows of its right
+/// side are filtered by their keys before being materialized.
+const SEMI_JOIN_MAX_LEFT_ROWS: usize = 1 << 16;
+
 struct CachedMaterializedIterator<'a> {
     materialized: Vec<Tuple>,
     eliminate_indices: BTreeSet<usize>,
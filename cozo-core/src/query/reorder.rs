@@ -9,7 +9,7 @@
 use std::collections::BTreeSet;
 use std::mem;
 
-use miette::{bail, Diagnostic, Result};
+use miette::{bail, ensure, Diagnostic, Result};
 use thiserror::Error;
 
 use crate::data::program::{NormalFormAtom, NormalFormInlineRule};
@@ -32,6 +32,9 @@ pub(crate) struct UnboundVariable(#[label] pub(crate) SourceSpan);
 
 impl NormalFormInlineRule {
     pub(crate) fn convert_to_well_ordered_rule(self) -> Result<Self> {
+        if self.hints.fixed_order {
+            return self.ensure_evaluable_in_order();
+        }
         let mut seen_variables = BTreeSet::default();
         let mut round_1_collected = vec![];
         let mut pending = vec![];
@@ -237,6 +240,57 @@ impl NormalFormInlineRule {
             head: self.head,
             aggr: self.aggr,
             body: collected,
+            hints: self.hints,
         })
     }
+
+    /// Check that the atoms can be evaluated in the order written, for the
+    /// `fixed_order` hint.
+    fn ensure_evaluable_in_order(self) -> Result<Self> {
+        let mut seen_variables = BTreeSet::default();
+        for atom in &self.body {
+            match atom {
+                NormalFormAtom::Rule(r) => seen_variables.extend(r.args.iter().cloned()),
+                NormalFormAtom::Relation(v) => seen_variables.extend(v.args.iter().cloned()),
+                NormalFormAtom::NegatedRule(r) => {
+                    ensure!(
+                        r.args.iter().any(|a| seen_variables.contains(a)),
+                        UnsafeNegation(r.span)
+                    )
+                }
+                NormalFormAtom::NegatedRelation(v) => {
+                    ensure!(
+                        v.args.iter().any(|a| seen_variables.contains(a)),
+                        UnsafeNegation(v.span)
+                    )
+                }
+                NormalFormAtom::Predicate(p) => {
+                    ensure!(
+                        p.bindings()?.is_subset(&seen_variables),
+                        UnboundVariable(p.span())
+                    )
+                }
+                NormalFormAtom::Unification(u) => {
+                    ensure!(
+                        u.bindings_in_expr()?.is_subset(&seen_variables),
+                        UnboundVariable(u.span)
+                    );
+                    seen_variables.insert(u.binding.clone());
+                }
+                NormalFormAtom::HnswSearch(s) => {
+                    ensure!(seen_variables.contains(&s.query), UnboundVariable(s.span));
+                    seen_variables.extend(s.all_bindings().cloned());
+                }
+                NormalFormAtom::FtsSearch(s) => {
+                    ensure!(seen_variables.contains(&s.query), UnboundVariable(s.span));
+                    seen_variables.extend(s.all_bindings().cloned());
+                }
+                NormalFormAtom::LshSearch(s) => {
+                    ensure!(seen_variables.contains(&s.query), UnboundVariable(s.span));
+                    seen_variables.extend(s.all_bindings().cloned());
+                }
+            }
+        }
+        Ok(self)
+    }
 }
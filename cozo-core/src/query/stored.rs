@@ -6,6 +6,7 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::cmp::Reverse;
 use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Arc;
 
@@ -15,21 +16,24 @@ use pest::Parser;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
-use crate::data::expr::{Bytecode, Expr};
+use crate::data::expr::{eval_bytecode_pred, Bytecode, Expr};
+use crate::data::functions::str2vld;
 use crate::data::program::{FixedRuleApply, InputInlineRulesOrFixed, InputProgram, RelationOp};
-use crate::data::relation::{ColumnDef, NullableColType, StoredRelationMetadata};
+use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
 use crate::data::symb::Symbol;
 use crate::data::tuple::{Tuple, ENCODED_KEY_MIN_LEN};
-use crate::data::value::{DataValue, ValidityTs};
+use crate::data::value::{DataValue, Validity, ValidityTs};
 use crate::fixed_rule::utilities::constant::Constant;
 use crate::fixed_rule::FixedRuleHandle;
 use crate::fts::tokenizer::TextAnalyzer;
 use crate::parse::expr::build_expr;
 use crate::parse::{parse_script, CozoScriptParser, Rule};
 use crate::runtime::callback::{CallbackCollector, CallbackOp};
+use crate::runtime::crdt::merged_columns;
 use crate::runtime::minhash_lsh::HashPermutations;
 use crate::runtime::relation::{
-    extend_tuple_from_v, AccessLevel, InputRelationHandle, InsufficientAccessLevel, RelationHandle,
+    extend_tuple_from_v, AccessLevel, GrantMode, InputRelationHandle, InsufficientAccessLevel,
+    RelationHandle,
 };
 use crate::runtime::transact::SessionTx;
 use crate::storage::Storage;
@@ -43,7 +47,7 @@ struct RelationArityMismatch(String, usize, usize);
 impl<'a> SessionTx<'a> {
     pub(crate) fn execute_relation<'s, S: Storage<'s>>(
         &mut self,
-        db: &Db<S>,
+        db: &'s Db<S>,
         res_iter: impl Iterator<Item = Tuple>,
         op: RelationOp,
         meta: &InputRelationHandle,
@@ -56,6 +60,7 @@ impl<'a> SessionTx<'a> {
     ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
         let mut to_clear = vec![];
         let mut replaced_old_triggers = None;
+        let mut replaced_old_grants = None;
         if op == RelationOp::Replace {
             if !propagate_triggers {
                 #[derive(Debug, Error, Diagnostic)]
@@ -79,6 +84,11 @@ impl<'a> SessionTx<'a> {
                         old_handle.access_level
                     ));
                 }
+                if !old_handle.grants.is_empty() || old_handle.row_policy.is_some() {
+                    self.ensure_unrestricted("replace relations with grants or row policies")?;
+                    replaced_old_grants =
+                        Some((old_handle.grants.clone(), old_handle.row_policy.clone()));
+                }
                 if old_handle.has_triggers() {
                     replaced_old_triggers = Some((old_handle.put_triggers, old_handle.rm_triggers))
                 }
@@ -88,6 +98,7 @@ impl<'a> SessionTx<'a> {
                         &Default::default(),
                         &db.fixed_rules.read().unwrap(),
                         cur_vld,
+                        false,
                     )?
                     .get_single_program()?;
 
@@ -124,6 +135,10 @@ impl<'a> SessionTx<'a> {
             relation_store.put_triggers = old_put;
             relation_store.rm_triggers = old_retract;
         }
+        if let Some((grants, row_policy)) = replaced_old_grants {
+            relation_store.grants = grants;
+            relation_store.row_policy = row_policy;
+        }
         let InputRelationHandle {
             metadata,
             key_bindings,
@@ -132,8 +147,48 @@ impl<'a> SessionTx<'a> {
             ..
         } = meta;
 
+        // updates only touch the given columns, everything else replaces or removes whole rows
+        let (mode, columns) = match op {
+            RelationOp::Ensure | RelationOp::EnsureNot => (
+                GrantMode::Read,
+                metadata.keys.iter().chain(metadata.non_keys.iter()).collect_vec(),
+            ),
+            RelationOp::Update => (
+                GrantMode::Write,
+                metadata
+                    .keys
+                    .iter()
+                    .chain(metadata.non_keys.iter())
+                    .filter(|col| !relation_store.metadata.keys.iter().any(|k| k.name == col.name))
+                    .collect_vec(),
+            ),
+            _ => (
+                GrantMode::Write,
+                relation_store
+                    .metadata
+                    .keys
+                    .iter()
+                    .chain(relation_store.metadata.non_keys.iter())
+                    .collect_vec(),
+            ),
+        };
+        self.ensure_granted(
+            &relation_store,
+            columns.iter().map(|col| col.name.as_str()),
+            mode,
+            *span,
+        )?;
+
+        let is_write = !matches!(op, RelationOp::Ensure | RelationOp::EnsureNot);
+        let written_before = self.rows_written;
+        if is_write {
+            if let Some(quota) = &self.session.quota {
+                quota.ensure_can_write()?;
+            }
+        }
+
         match op {
-            RelationOp::Rm | RelationOp::Delete => self.remove_from_relation(
+            RelationOp::Rm | RelationOp::Retract | RelationOp::Delete => self.remove_from_relation(
                 db,
                 res_iter,
                 headers,
@@ -145,7 +200,7 @@ impl<'a> SessionTx<'a> {
                 &relation_store,
                 metadata,
                 key_bindings,
-                op == RelationOp::Delete,
+                op,
                 force_collect,
                 *span,
             )?,
@@ -202,12 +257,18 @@ impl<'a> SessionTx<'a> {
                 )?,
         };
 
+        if is_write {
+            if let Some(quota) = &self.session.quota {
+                quota.charge_writes(self.rows_written - written_before);
+            }
+        }
+
         Ok(to_clear)
     }
 
     fn put_into_relation<'s, S: Storage<'s>>(
         &mut self,
-        db: &Db<S>,
+        db: &'s Db<S>,
         res_iter: impl Iterator<Item = Tuple>,
         headers: &[Symbol],
         cur_vld: ValidityTs,
@@ -272,15 +333,48 @@ impl<'a> SessionTx<'a> {
         let hnsw_filters = Self::make_hnsw_filters(relation_store)?;
         let fts_lsh_processors = self.make_fts_lsh_processors(relation_store)?;
         let lsh_perms = self.make_lsh_hash_perms(relation_store);
+        let interval_src = validity_interval_source(relation_store, &key_extractors);
+        let row_policy = self.row_policy_bytecode(relation_store)?;
+        let merged = merged_columns(&relation_store.metadata);
 
-        for tuple in res_iter {
-            let extracted: Vec<DataValue> = key_extractors
+        for tuple in res_iter.flat_map(|t| expand_validity_interval(t, interval_src)) {
+            let (tuple, interval) = tuple?;
+            let mut extracted: Vec<DataValue> = key_extractors
                 .iter()
                 .map(|ex| ex.extract_data(&tuple, cur_vld))
                 .try_collect()?;
+            if let Some((from, to)) = interval {
+                let prefix = &extracted[..relation_store.metadata.keys.len() - 1];
+                self.ensure_validity_interval_free(relation_store, prefix, from, to)?;
+            }
 
             let key = relation_store.encode_key_for_store(&extracted, span)?;
 
+            if !merged.is_empty() {
+                let existing = if relation_store.is_temp {
+                    self.temp_store_tx.get(&key, false)?
+                } else {
+                    self.store_tx.get(&key, false)?
+                };
+                let old = existing.map(|existing| {
+                    let mut tup = extracted[0..relation_store.metadata.keys.len()].to_vec();
+                    extend_tuple_from_v(&mut tup, &existing);
+                    tup
+                });
+                db.replica
+                    .merge_row(&merged, old.as_deref(), &mut extracted)?;
+            }
+
+            if let Some(policy) = &row_policy {
+                // neither the new row nor the one it replaces may be outside of the policy
+                self.ensure_row_policy(relation_store, policy, &extracted, &mut stack, span)?;
+                if let Some(existing) = self.store_tx.get(&key, false)? {
+                    let mut tup = extracted[0..relation_store.metadata.keys.len()].to_vec();
+                    extend_tuple_from_v(&mut tup, &existing);
+                    self.ensure_row_policy(relation_store, policy, &tup, &mut stack, span)?;
+                }
+            }
+
             if is_insert {
                 let already_exists = if relation_store.is_temp {
                     self.temp_store_tx.exists(&key, true)?
@@ -348,6 +442,7 @@ impl<'a> SessionTx<'a> {
                 self.temp_store_tx.put(&key, &val)?;
             } else {
                 self.store_tx.put(&key, &val)?;
+                self.rows_written += 1;
             }
         }
 
@@ -519,7 +614,7 @@ impl<'a> SessionTx<'a> {
 
     fn update_in_relation<'s, S: Storage<'s>>(
         &mut self,
-        db: &Db<S>,
+        db: &'s Db<S>,
         res_iter: impl Iterator<Item = Tuple>,
         headers: &[Symbol],
         cur_vld: ValidityTs,
@@ -573,6 +668,8 @@ impl<'a> SessionTx<'a> {
         let hnsw_filters = Self::make_hnsw_filters(relation_store)?;
         let fts_lsh_processors = self.make_fts_lsh_processors(relation_store)?;
         let lsh_perms = self.make_lsh_hash_perms(relation_store);
+        let row_policy = self.row_policy_bytecode(relation_store)?;
+        let merged = merged_columns(&relation_store.metadata);
 
         for tuple in res_iter {
             let mut new_kv: Vec<DataValue> = key_extractors
@@ -611,6 +708,12 @@ impl<'a> SessionTx<'a> {
                     }
                 }
             }
+            db.replica
+                .merge_row(&merged, Some(&old_kv), &mut new_kv)?;
+            if let Some(policy) = &row_policy {
+                self.ensure_row_policy(relation_store, policy, &old_kv, &mut stack, span)?;
+                self.ensure_row_policy(relation_store, policy, &new_kv, &mut stack, span)?;
+            }
             let new_val = relation_store.encode_val_for_store(&new_kv, span)?;
 
             if need_to_collect
@@ -646,6 +749,7 @@ impl<'a> SessionTx<'a> {
                 self.temp_store_tx.put(&key, &new_val)?;
             } else {
                 self.store_tx.put(&key, &new_val)?;
+                self.rows_written += 1;
             }
         }
 
@@ -668,7 +772,7 @@ impl<'a> SessionTx<'a> {
 
     fn collect_mutations<'s, S: Storage<'s>>(
         &mut self,
-        db: &Db<S>,
+        db: &'s Db<S>,
         cur_vld: ValidityTs,
         callback_targets: &BTreeSet<SmartString<LazyCompact>>,
         callback_collector: &mut CallbackCollector,
@@ -700,6 +804,7 @@ impl<'a> SessionTx<'a> {
                     &Default::default(),
                     &db.fixed_rules.read().unwrap(),
                     cur_vld,
+                    false,
                 )?
                 .get_single_program()?;
 
@@ -820,16 +925,27 @@ impl<'a> SessionTx<'a> {
             headers,
         )?;
 
+        let row_policy = self.row_policy_bytecode(relation_store)?;
+        let mut stack = vec![];
+
         for tuple in res_iter {
             let extracted: Vec<DataValue> = key_extractors
                 .iter()
                 .map(|ex| ex.extract_data(&tuple, cur_vld))
                 .try_collect()?;
             let key = relation_store.encode_key_for_store(&extracted, span)?;
-            let already_exists = if relation_store.is_temp {
-                self.temp_store_tx.exists(&key, true)?
-            } else {
-                self.store_tx.exists(&key, true)?
+            let already_exists = match (&row_policy, relation_store.is_temp) {
+                (_, true) => self.temp_store_tx.exists(&key, true)?,
+                (None, false) => self.store_tx.exists(&key, true)?,
+                // a row outside of the row policy of the session is not there for it
+                (Some(policy), false) => match self.store_tx.get(&key, true)? {
+                    None => false,
+                    Some(existing) => {
+                        let mut tup = extracted.clone();
+                        extend_tuple_from_v(&mut tup, &existing);
+                        eval_bytecode_pred(policy, &tup, &mut stack, span)?
+                    }
+                },
             };
             if already_exists {
                 bail!(TransactAssertionFailure {
@@ -874,6 +990,8 @@ impl<'a> SessionTx<'a> {
             headers,
         )?;
         key_extractors.extend(val_extractors);
+        let row_policy = self.row_policy_bytecode(relation_store)?;
+        let mut stack = vec![];
 
         for tuple in res_iter {
             let extracted: Vec<DataValue> = key_extractors
@@ -884,11 +1002,17 @@ impl<'a> SessionTx<'a> {
             let key = relation_store.encode_key_for_store(&extracted, span)?;
             let val = relation_store.encode_val_for_store(&extracted, span)?;
 
-            let existing = if relation_store.is_temp {
+            let mut existing = if relation_store.is_temp {
                 self.temp_store_tx.get(&key, true)?
             } else {
                 self.store_tx.get(&key, true)?
             };
+            // a row outside of the row policy of the session is not there for it
+            if let Some(policy) = &row_policy {
+                if !eval_bytecode_pred(policy, &extracted, &mut stack, span)? {
+                    existing = None;
+                }
+            }
             match existing {
                 None => {
                     bail!(TransactAssertionFailure {
@@ -913,7 +1037,7 @@ impl<'a> SessionTx<'a> {
 
     fn remove_from_relation<'s, S: Storage<'s>>(
         &mut self,
-        db: &Db<S>,
+        db: &'s Db<S>,
         res_iter: impl Iterator<Item = Tuple>,
         headers: &[Symbol],
         cur_vld: ValidityTs,
@@ -924,7 +1048,7 @@ impl<'a> SessionTx<'a> {
         relation_store: &RelationHandle,
         metadata: &StoredRelationMetadata,
         key_bindings: &[Symbol],
-        check_exists: bool,
+        op: RelationOp,
         force_collect: &str,
         span: SourceSpan,
     ) -> Result<()> {
@@ -944,6 +1068,17 @@ impl<'a> SessionTx<'a> {
             key_bindings,
             headers,
         )?;
+        // `:retract` only removes the rows holding the values given for their columns
+        let val_extractors = if op == RelationOp::Retract {
+            make_update_extractors(
+                &relation_store.metadata.non_keys,
+                &metadata.keys,
+                key_bindings,
+                headers,
+            )?
+        } else {
+            vec![]
+        };
 
         let need_to_collect = !force_collect.is_empty()
             || (!relation_store.is_temp
@@ -957,6 +1092,7 @@ impl<'a> SessionTx<'a> {
         let mut new_tuples: Vec<DataValue> = vec![];
         let mut old_tuples: Vec<DataValue> = vec![];
         let mut stack = vec![];
+        let row_policy = self.row_policy_bytecode(relation_store)?;
 
         for tuple in res_iter {
             let extracted: Vec<DataValue> = key_extractors
@@ -964,7 +1100,35 @@ impl<'a> SessionTx<'a> {
                 .map(|ex| ex.extract_data(&tuple, cur_vld))
                 .try_collect()?;
             let key = relation_store.encode_key_for_store(&extracted, span)?;
-            if check_exists {
+            if op == RelationOp::Retract {
+                let existing = if relation_store.is_temp {
+                    self.temp_store_tx.get(&key, false)?
+                } else {
+                    self.store_tx.get(&key, false)?
+                };
+                let Some(existing) = existing else {
+                    continue;
+                };
+                let mut tup = extracted.clone();
+                extend_tuple_from_v(&mut tup, &existing);
+                let mut held = true;
+                for (ex, stored) in val_extractors.iter().zip(&tup[extracted.len()..]) {
+                    if let Some(ex) = ex {
+                        held &= ex.extract_data(&tuple, cur_vld)? == *stored;
+                    }
+                }
+                if !held {
+                    continue;
+                }
+            }
+            if let Some(policy) = &row_policy {
+                if let Some(existing) = self.store_tx.get(&key, false)? {
+                    let mut tup = extracted.clone();
+                    extend_tuple_from_v(&mut tup, &existing);
+                    self.ensure_row_policy(relation_store, policy, &tup, &mut stack, span)?;
+                }
+            }
+            if op == RelationOp::Delete {
                 let exists = if relation_store.is_temp {
                     self.temp_store_tx.exists(&key, false)?
                 } else {
@@ -1009,6 +1173,7 @@ impl<'a> SessionTx<'a> {
                 self.temp_store_tx.del(&key)?;
             } else {
                 self.store_tx.del(&key)?;
+                self.rows_written += 1;
             }
         }
 
@@ -1037,6 +1202,7 @@ impl<'a> SessionTx<'a> {
                         &Default::default(),
                         &db.fixed_rules.read().unwrap(),
                         cur_vld,
+                        false,
                     )?
                     .get_single_program()?;
 
@@ -1135,6 +1301,148 @@ impl DataExtractor {
     }
 }
 
+/// If the last key column of the relation is a validity column fed directly from the input,
+/// returns the position of that column in the input tuples.
+fn validity_interval_source(
+    relation_store: &RelationHandle,
+    extractors: &[DataExtractor],
+) -> Option<usize> {
+    let last_idx = relation_store.metadata.keys.len().checked_sub(1)?;
+    if relation_store.metadata.keys[last_idx].typing.coltype != ColType::Validity {
+        return None;
+    }
+    match &extractors[last_idx] {
+        DataExtractor::IndexExtractor(i, _) => Some(*i),
+        DataExtractor::DefaultExtractor(_, _) => None,
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The validity interval [{from}, {to}) overlaps the history of the key {key:?}")]
+#[diagnostic(code(eval::overlapping_validity_interval))]
+#[diagnostic(help(
+    "The retraction ending an interval would cut short the value it overlaps, \
+    so intervals of a key must not overlap each other or the values already stored"
+))]
+struct OverlappingValidityInterval {
+    key: Vec<DataValue>,
+    from: i64,
+    to: String,
+}
+
+impl SessionTx<'_> {
+    /// Fail if the interval `[from, to)` of the key `prefix` overlaps its stored
+    /// history: a row stored within the interval would change its value or end it, and
+    /// the retraction at `to` would end a value in effect at `from`.
+    fn ensure_validity_interval_free(
+        &self,
+        relation_store: &RelationHandle,
+        prefix: &[DataValue],
+        from: i64,
+        to: Option<i64>,
+    ) -> Result<()> {
+        // the latest rows come first, and at the same time assertions come first
+        for row in relation_store.scan_prefix(self, &prefix.to_vec()) {
+            let row = row?;
+            let DataValue::Validity(vld) = &row[prefix.len()] else {
+                continue;
+            };
+            let ts = vld.timestamp.0 .0;
+            let overlaps = if ts > from {
+                to.is_none_or(|to| ts < to)
+            } else {
+                vld.is_assert.0
+            };
+            if overlaps {
+                bail!(OverlappingValidityInterval {
+                    key: prefix.to_vec(),
+                    from,
+                    to: to.map_or_else(|| "null".to_string(), |to| to.to_string()),
+                })
+            }
+            if ts <= from {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An input validity of the form `[from, to]`, where the endpoints are integer microseconds
+/// or RFC3339 strings (`to` may be `null` for an open interval), is expanded into an assertion
+/// at `from` and a retraction at `to`. Any other value is passed through untouched. The
+/// assertion comes with the interval, to be checked against the history of its key.
+fn expand_validity_interval(
+    tuple: Tuple,
+    src: Option<usize>,
+) -> impl Iterator<Item = Result<(Tuple, Option<(i64, Option<i64>)>)>> {
+    #[derive(Debug, Error, Diagnostic)]
+    #[error("Invalid validity interval {0:?}")]
+    #[diagnostic(code(eval::invalid_validity_interval))]
+    #[diagnostic(help(
+        "Intervals are given as [from, to], with each end an integer timestamp \
+        in microseconds or an RFC3339 string; 'from' must precede 'to', \
+        and neither can be the largest or smallest 64-bit integer"
+    ))]
+    struct InvalidValidityInterval(DataValue);
+
+    fn endpoint(v: &DataValue) -> Option<Result<i64>> {
+        let ts = match v {
+            DataValue::Num(_) => v.get_int()?,
+            DataValue::Str(s) => match str2vld(s) {
+                Ok(ts) => ts.0 .0,
+                Err(err) => return Some(Err(err)),
+            },
+            _ => return None,
+        };
+        // the extremes are reserved, as for validities given directly
+        (ts != i64::MAX && ts != i64::MIN).then_some(Ok(ts))
+    }
+
+    let interval = src.and_then(|i| match &tuple[i] {
+        DataValue::List(l) if l.len() == 2 && l[1].get_bool().is_none() => {
+            Some((i, l[0].clone(), l[1].clone()))
+        }
+        _ => None,
+    });
+    let expanded: Vec<Result<_>> = match interval {
+        None => vec![Ok((tuple, None))],
+        Some((i, from, to)) => {
+            let bad = || InvalidValidityInterval(tuple[i].clone());
+            let from_ts = match endpoint(&from) {
+                Some(Ok(ts)) => ts,
+                Some(Err(err)) => return vec![Err(err)].into_iter(),
+                None => return vec![Err(bad().into())].into_iter(),
+            };
+            let to_ts = match (&to, endpoint(&to)) {
+                (DataValue::Null, _) => None,
+                (_, Some(Ok(ts))) => Some(ts),
+                (_, Some(Err(err))) => return vec![Err(err)].into_iter(),
+                (_, None) => return vec![Err(bad().into())].into_iter(),
+            };
+            if to_ts.is_some_and(|to_ts| to_ts <= from_ts) {
+                return vec![Err(bad().into())].into_iter();
+            }
+            let mut assertion = tuple.clone();
+            assertion[i] = DataValue::Validity(Validity {
+                timestamp: ValidityTs(Reverse(from_ts)),
+                is_assert: Reverse(true),
+            });
+            let mut ret = vec![Ok((assertion, Some((from_ts, to_ts))))];
+            if let Some(to_ts) = to_ts {
+                let mut retraction = tuple;
+                retraction[i] = DataValue::Validity(Validity {
+                    timestamp: ValidityTs(Reverse(to_ts)),
+                    is_assert: Reverse(false),
+                });
+                ret.push(Ok((retraction, None)));
+            }
+            ret
+        }
+    };
+    expanded.into_iter()
+}
+
 fn make_extractors(
     stored: &[ColumnDef],
     input: &[ColumnDef],
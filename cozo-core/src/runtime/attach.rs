@@ -0,0 +1,223 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Other databases attached to a database, their relations read by fixed rules.
+
+use std::collections::BTreeMap;
+use std::io::BufRead;
+use std::sync::Arc;
+
+use itertools::Itertools;
+use miette::{bail, ensure, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::expr::Expr;
+use crate::data::program::WrongFixedRuleOptionError;
+use crate::data::symb::Symbol;
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::dump::DumpReader;
+use crate::runtime::temp_store::RegularTempStore;
+use crate::{DataValue, Db, DbInstance, NamedRows, ScriptMutability, Storage};
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Relation '{0}' does not exist in the attached database '{1}'")]
+#[diagnostic(code(attach::relation_not_found))]
+struct AttachedRelationNotFound(String, String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The columns of relation '{0}' differ between the lines of the snapshot")]
+#[diagnostic(code(attach::headers_changed))]
+struct HeadersChanged(String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot attach a database as '{0}', which is the name of a fixed rule")]
+#[diagnostic(code(attach::name_taken))]
+struct AttachNameTaken(String);
+
+enum Attached {
+    Db(Box<DbInstance>),
+    Snapshot(BTreeMap<String, NamedRows>),
+}
+
+/// The fixed rule registered under the alias of an attached database, yielding the
+/// rows of the relation given by its `relation` option.
+struct AttachedRelation {
+    alias: String,
+    attached: Arc<Attached>,
+}
+
+impl AttachedRelation {
+    fn relation_option(
+        &self,
+        options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        span: SourceSpan,
+    ) -> Result<String> {
+        let wrong = || WrongFixedRuleOptionError {
+            name: "relation".to_string(),
+            span,
+            rule_name: self.alias.clone(),
+            help: "the name of a relation of the attached database is required".to_string(),
+        };
+        match options.get("relation") {
+            // the name is spliced into the scripts run on the attached database
+            Some(ex) => match ex.clone().eval_to_const()? {
+                DataValue::Str(s) if is_relation_name(&s) => Ok(s.to_string()),
+                _ => bail!(wrong()),
+            },
+            None => bail!(wrong()),
+        }
+    }
+    fn columns(&self, relation: &str, span: SourceSpan) -> Result<Vec<String>> {
+        let not_found = || AttachedRelationNotFound(relation.to_string(), self.alias.clone(), span);
+        match self.attached.as_ref() {
+            Attached::Db(db) => {
+                let res = db
+                    .run_script(
+                        &format!("::columns {relation}"),
+                        Default::default(),
+                        ScriptMutability::Immutable,
+                    )
+                    .map_err(|_| not_found())?;
+                Ok(res
+                    .rows
+                    .into_iter()
+                    .filter_map(|row| row.into_iter().next())
+                    .filter_map(|col| col.get_str().map(|s| s.to_string()))
+                    .collect())
+            }
+            Attached::Snapshot(relations) => match relations.get(relation) {
+                Some(rows) => Ok(rows.headers.clone()),
+                None => bail!(not_found()),
+            },
+        }
+    }
+}
+
+impl FixedRule for AttachedRelation {
+    fn init_options(
+        &self,
+        options: &mut BTreeMap<SmartString<LazyCompact>, Expr>,
+        span: SourceSpan,
+    ) -> Result<()> {
+        self.relation_option(options, span)?;
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        span: SourceSpan,
+    ) -> Result<usize> {
+        let relation = self.relation_option(options, span)?;
+        Ok(self.columns(&relation, span)?.len())
+    }
+
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let relation = payload.string_option("relation", None)?;
+        let rows = match self.attached.as_ref() {
+            Attached::Db(db) => {
+                let columns = self.columns(&relation, payload.span())?.join(", ");
+                let script = format!("?[{columns}] := *{relation}{{{columns}}}");
+                db.run_script(&script, Default::default(), ScriptMutability::Immutable)?
+                    .rows
+            }
+            Attached::Snapshot(relations) => match relations.get(relation.as_str()) {
+                Some(rows) => rows.rows.clone(),
+                None => bail!(AttachedRelationNotFound(
+                    relation.to_string(),
+                    self.alias.clone(),
+                    payload.span()
+                )),
+            },
+        };
+        for row in rows {
+            poison.check()?;
+            out.put(row);
+        }
+        Ok(())
+    }
+}
+
+fn is_relation_name(name: &str) -> bool {
+    let mut parts = name.split(['.', ':']);
+    parts.all(|part| {
+        part.chars()
+            .next()
+            .is_some_and(|c| c.is_alphabetic() || c == '_')
+            && part.chars().all(|c| c.is_alphanumeric() || c == '_')
+    })
+}
+
+/// Read a dump written by [`Db::snapshot_export`] into rows by relation.
+fn read_snapshot(reader: impl BufRead) -> Result<BTreeMap<String, NamedRows>> {
+    let mut reader = DumpReader::new(reader)?;
+    let mut relations: BTreeMap<String, NamedRows> = BTreeMap::new();
+    while let Some((name, chunk)) = reader.next_chunk()? {
+        let rows = relations
+            .entry(name.clone())
+            .or_insert_with(|| NamedRows::new(chunk.headers.clone(), vec![]));
+        ensure!(rows.headers == chunk.headers, HeadersChanged(name));
+        rows.rows.extend(chunk.rows);
+    }
+    Ok(relations)
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Attach `other` under `alias`, for its relations to be read in queries by the
+    /// fixed rule named `alias`, whose `relation` option names the relation to read:
+    ///
+    /// ```text
+    /// archived[id, name] <~ archive(relation: 'person')
+    /// ?[id, name] := archived[id, name], not *person{id}
+    /// ```
+    ///
+    /// The fixed rule yields the columns of the relation in order, and reads the whole
+    /// relation from a separate read-only transaction of `other` each time it runs. The
+    /// attached database is never written through this database.
+    pub fn attach(&'s self, alias: &str, other: DbInstance) -> Result<()> {
+        self.register_attached(alias, Attached::Db(Box::new(other)))
+    }
+    /// Attach the dump written by [`snapshot_export`](Self::snapshot_export) read from
+    /// `reader` under `alias`, as [`attach`](Self::attach) does for databases. The dump
+    /// is read into memory once.
+    pub fn attach_snapshot(&'s self, alias: &str, reader: impl BufRead) -> Result<()> {
+        let relations = read_snapshot(reader)?;
+        self.register_attached(alias, Attached::Snapshot(relations))
+    }
+    /// Detach what was attached under `alias`, returning whether anything was.
+    pub fn detach(&'s self, alias: &str) -> Result<bool> {
+        if !self.attached.lock().unwrap().remove(alias) {
+            return Ok(false);
+        }
+        self.unregister_fixed_rule(alias)
+    }
+    /// The aliases of the attached databases and snapshots.
+    pub fn list_attached(&'s self) -> Vec<String> {
+        self.attached.lock().unwrap().iter().cloned().collect_vec()
+    }
+    fn register_attached(&'s self, alias: &str, attached: Attached) -> Result<()> {
+        let mut guard = self.attached.lock().unwrap();
+        let rule = AttachedRelation {
+            alias: alias.to_string(),
+            attached: Arc::new(attached),
+        };
+        self.register_fixed_rule(alias.to_string(), rule)
+            .map_err(|_| AttachNameTaken(alias.to_string()))?;
+        guard.insert(alias.to_string());
+        Ok(())
+    }
+}
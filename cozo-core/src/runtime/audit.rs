@@ -0,0 +1,198 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crossbeam::channel::{bounded, Receiver, Sender};
+use itertools::Itertools;
+use miette::{IntoDiagnostic, Result};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::runtime::db::seconds_since_the_epoch;
+use crate::storage::{Storage, StoreTx};
+
+/// First byte of every key of the audit log. Keys of relations start with zero bytes,
+/// keys of the transaction log with 0xFD and keys of namespaces with 0xFF, so they
+/// never overlap.
+pub(crate) const AUDIT_KEY_MARKER: u8 = 0xFE;
+
+/// An event recorded by the audit log, see
+/// [`Db::enable_audit_log`](crate::Db::enable_audit_log).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// When the event happened, in seconds since the epoch
+    pub timestamp: f64,
+    /// The session the event happened in, as listed by
+    /// [`Db::list_sessions`](crate::Db::list_sessions)
+    pub session_id: u64,
+    /// The principal the session runs for, as passed to
+    /// [`Db::run_script_as`](crate::Db::run_script_as)
+    pub principal: Option<String>,
+    /// What happened
+    pub event: AuditEvent,
+}
+
+/// The kind of an [`AuditEntry`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuditEvent {
+    /// A script started running
+    Query(String),
+    /// The transaction of the session was committed
+    Commit,
+    /// The transaction of the session ended without being committed
+    Abort,
+}
+
+/// How long the audit log keeps its entries. Older entries are removed as new ones are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AuditRetention {
+    /// Remove entries older than this
+    pub max_age: Option<Duration>,
+    /// Keep at most this many entries, removing the oldest ones first
+    pub max_entries: Option<usize>,
+}
+
+pub(crate) enum AuditMsg {
+    Entry(AuditEntry),
+    Flush(Sender<()>),
+}
+
+/// Hands the audited events over to the thread writing them to the storage.
+#[derive(Default)]
+pub(crate) struct AuditLog {
+    sender: Mutex<Option<Sender<AuditMsg>>>,
+    writer: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl AuditLog {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.sender.lock().unwrap().is_some()
+    }
+    pub(crate) fn log(&self, entry: AuditEntry) {
+        if let Some(sender) = &*self.sender.lock().unwrap() {
+            let _ = sender.send(AuditMsg::Entry(entry));
+        }
+    }
+    pub(crate) fn start(&self, sender: Sender<AuditMsg>, writer: JoinHandle<()>) {
+        self.stop();
+        *self.sender.lock().unwrap() = Some(sender);
+        *self.writer.lock().unwrap() = Some(writer);
+    }
+    /// Stop the writer thread once it has written the pending entries.
+    /// Returns `false` if the audit log was not enabled.
+    pub(crate) fn stop(&self) -> bool {
+        let was_enabled = self.sender.lock().unwrap().take().is_some();
+        if let Some(writer) = self.writer.lock().unwrap().take() {
+            let _ = writer.join();
+        }
+        was_enabled
+    }
+    /// Wait until the entries logged so far are written.
+    pub(crate) fn flush(&self) {
+        let sender = self.sender.lock().unwrap().clone();
+        if let Some(sender) = sender {
+            let (done_sender, done_receiver) = bounded(1);
+            if sender.send(AuditMsg::Flush(done_sender)).is_ok() {
+                let _ = done_receiver.recv();
+            }
+        }
+    }
+}
+
+fn audit_key(timestamp: f64, seq: u64) -> Vec<u8> {
+    let mut key = Vec::with_capacity(17);
+    key.push(AUDIT_KEY_MARKER);
+    key.extend_from_slice(&((timestamp * 1000000.) as u64).to_be_bytes());
+    key.extend_from_slice(&seq.to_be_bytes());
+    key
+}
+
+const AUDIT_UPPER: [u8; 1] = [AUDIT_KEY_MARKER + 1];
+
+/// Write the entries received on `receiver` until every sender is dropped,
+/// applying `retention` after each batch.
+pub(crate) fn run_audit_writer<'s, S: Storage<'s>>(
+    storage: &'s S,
+    receiver: Receiver<AuditMsg>,
+    retention: AuditRetention,
+) {
+    let mut seq = 0;
+    while let Ok(msg) = receiver.recv() {
+        let mut entries = vec![];
+        let mut flushed = vec![];
+        for msg in std::iter::once(msg).chain(receiver.try_iter()) {
+            match msg {
+                AuditMsg::Entry(entry) => entries.push(entry),
+                AuditMsg::Flush(done) => flushed.push(done),
+            }
+        }
+        if let Err(err) = write_audit_batch(storage, &entries, &mut seq, &retention) {
+            log::error!("writing the audit log failed: {err:?}");
+        }
+        for done in flushed {
+            let _ = done.send(());
+        }
+    }
+}
+
+fn write_audit_batch<'s, S: Storage<'s>>(
+    storage: &'s S,
+    entries: &[AuditEntry],
+    seq: &mut u64,
+    retention: &AuditRetention,
+) -> Result<()> {
+    let mut tx = storage.transact(true)?;
+    for entry in entries {
+        let val = rmp_serde::to_vec(entry).into_diagnostic()?;
+        tx.put(&audit_key(entry.timestamp, *seq), &val)?;
+        *seq += 1;
+    }
+    let lower = [AUDIT_KEY_MARKER];
+    let mut expired = vec![];
+    if let Some(max_age) = retention.max_age {
+        let cutoff = audit_key(seconds_since_the_epoch()? - max_age.as_secs_f64(), 0);
+        expired = tx
+            .range_scan(&lower, &cutoff)
+            .map_ok(|(k, _)| k)
+            .try_collect()?;
+    }
+    if let Some(max_entries) = retention.max_entries {
+        let excess = tx
+            .range_count(&lower, &AUDIT_UPPER)?
+            .saturating_sub(expired.len() + max_entries);
+        if excess > 0 {
+            // the entries after the expired ones, which are the oldest
+            let mut from = expired.last().cloned().unwrap_or_else(|| lower.to_vec());
+            from.push(0);
+            let oldest: Vec<_> = tx
+                .range_scan(&from, &AUDIT_UPPER)
+                .take(excess)
+                .map_ok(|(k, _)| k)
+                .try_collect()?;
+            expired.extend(oldest);
+        }
+    }
+    for key in expired {
+        tx.del(&key)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// All the entries of the audit log, oldest first.
+pub(crate) fn read_audit_log<'s, S: Storage<'s>>(storage: &'s S) -> Result<Vec<AuditEntry>> {
+    let tx = storage.transact(false)?;
+    tx.range_scan(&[AUDIT_KEY_MARKER], &AUDIT_UPPER)
+        .map(|kv| {
+            let (_, v) = kv?;
+            rmp_serde::from_slice(&v).into_diagnostic()
+        })
+        .try_collect()
+}
@@ -0,0 +1,214 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Writing a stream of rows to a stored relation in transactions of bounded size, see
+//! [`Db::batcher`].
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crossbeam::channel::{Receiver, RecvTimeoutError};
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Result};
+use thiserror::Error;
+
+use crate::data::value::DataValue;
+use crate::runtime::db::seconds_since_the_epoch;
+use crate::storage::Storage;
+use crate::{Db, NamedRows, ScriptMutability};
+
+/// When a [`TxBatcher`] commits the rows it holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchOptions {
+    /// Rows written by each transaction at most
+    pub max_rows: usize,
+    /// The longest a row waits before it is committed. Rows are only committed when a
+    /// row is added, or while waiting on a channel with [`TxBatcher::consume`], so when
+    /// rows stop coming the last of them wait for [`TxBatcher::flush`].
+    pub max_delay: Duration,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self {
+            max_rows: 1000,
+            max_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+/// A transaction committed by a [`TxBatcher`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchReport {
+    /// Position of the batch among those of the batcher, from 1
+    pub batch: u64,
+    /// Rows written by the transaction
+    pub rows: usize,
+    /// Seconds the first row of the batch waited before the transaction committed
+    pub waited: f64,
+    /// Seconds taken to run the transaction
+    pub took: f64,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("A row of {0} values was given to the batcher of relation {1}, which has {2} columns")]
+#[diagnostic(code(eval::batch_row_arity))]
+#[diagnostic(help("Rows hold the keys then the values of the relation, in schema order"))]
+struct BatchRowArity(usize, String, usize);
+
+/// Puts rows into a stored relation, committing them in transactions of at most
+/// [`BatchOptions::max_rows`] rows, or sooner when the oldest row pending has waited
+/// [`BatchOptions::max_delay`]. Created by [`Db::batcher`].
+///
+/// The rows pending when the batcher is dropped are lost: call [`flush`](Self::flush)
+/// first. A failed transaction drops its rows, and the batcher may go on with the next.
+pub struct TxBatcher<'a> {
+    write: Box<dyn Fn(BTreeMap<String, DataValue>) -> Result<NamedRows> + 'a>,
+    relation: String,
+    arity: usize,
+    options: BatchOptions,
+    pending: Vec<DataValue>,
+    /// When the oldest pending row was added
+    pending_since: f64,
+    batches: u64,
+}
+
+impl<'a> TxBatcher<'a> {
+    /// Add `row`, holding the keys then the values of the relation, committing the rows
+    /// pending if the batch is full or has waited long enough.
+    pub fn push(&mut self, row: Vec<DataValue>) -> Result<Option<BatchReport>> {
+        if row.len() != self.arity {
+            bail!(BatchRowArity(row.len(), self.relation.clone(), self.arity))
+        }
+        let now = seconds_since_the_epoch()?;
+        if self.pending.is_empty() {
+            self.pending_since = now;
+        }
+        self.pending.push(DataValue::List(row));
+        if self.pending.len() >= self.options.max_rows || self.is_overdue(now) {
+            self.flush()
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Add every row of `rows`, then commit the rows still pending, returning the reports
+    /// of all the transactions. Stops at the first failure.
+    pub fn extend(
+        &mut self,
+        rows: impl IntoIterator<Item = Vec<DataValue>>,
+    ) -> Result<Vec<BatchReport>> {
+        let mut reports = vec![];
+        for row in rows {
+            reports.extend(self.push(row)?);
+        }
+        reports.extend(self.flush()?);
+        Ok(reports)
+    }
+
+    /// Add the rows received from `rows` until all of its senders are gone, committing
+    /// the rows pending once they have waited [`BatchOptions::max_delay`] even if no row
+    /// comes. Stops at the first failure.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn consume(&mut self, rows: Receiver<Vec<DataValue>>) -> Result<Vec<BatchReport>> {
+        let mut reports = vec![];
+        loop {
+            let received = if self.pending.is_empty() {
+                rows.recv().map_err(|_| RecvTimeoutError::Disconnected)
+            } else {
+                let waited = seconds_since_the_epoch()? - self.pending_since;
+                let left = self.options.max_delay.as_secs_f64() - waited;
+                rows.recv_timeout(Duration::from_secs_f64(left.max(0.)))
+            };
+            match received {
+                Ok(row) => reports.extend(self.push(row)?),
+                Err(RecvTimeoutError::Timeout) => reports.extend(self.flush()?),
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        reports.extend(self.flush()?);
+        Ok(reports)
+    }
+
+    /// Commit the rows pending, if there are any.
+    pub fn flush(&mut self) -> Result<Option<BatchReport>> {
+        if self.pending.is_empty() {
+            return Ok(None);
+        }
+        let rows = std::mem::take(&mut self.pending);
+        let n_rows = rows.len();
+        let started_at = seconds_since_the_epoch()?;
+        (self.write)(BTreeMap::from([(
+            "rows".to_string(),
+            DataValue::List(rows),
+        )]))?;
+        let now = seconds_since_the_epoch()?;
+        self.batches += 1;
+        Ok(Some(BatchReport {
+            batch: self.batches,
+            rows: n_rows,
+            waited: now - self.pending_since,
+            took: now - started_at,
+        }))
+    }
+
+    /// Number of rows added and not committed yet.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn is_overdue(&self, now: f64) -> bool {
+        now - self.pending_since >= self.options.max_delay.as_secs_f64()
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// A [`TxBatcher`] putting rows into the stored relation `relation`, for ingesting a
+    /// stream of rows in transactions of bounded size. The rows go through `:put`, so
+    /// indices, triggers, callbacks and constraints apply as for any write.
+    pub fn batcher(&'s self, relation: &str, options: BatchOptions) -> Result<TxBatcher<'s>> {
+        let handle = self.transact()?.get_relation(relation, false)?;
+        let keys = handle
+            .metadata
+            .keys
+            .iter()
+            .map(|col| col.name.to_string())
+            .collect_vec();
+        let non_keys = handle
+            .metadata
+            .non_keys
+            .iter()
+            .map(|col| col.name.to_string())
+            .collect_vec();
+        let spec = if non_keys.is_empty() {
+            keys.join(", ")
+        } else {
+            format!("{} => {}", keys.join(", "), non_keys.join(", "))
+        };
+        let script = format!(
+            "?[{}] <- $rows :put {} {{{spec}}}",
+            keys.iter().chain(non_keys.iter()).join(", "),
+            handle.name,
+        );
+        Ok(TxBatcher {
+            write: Box::new(move |params| {
+                self.run_script(&script, params, ScriptMutability::Mutable)
+            }),
+            relation: relation.to_string(),
+            arity: keys.len() + non_keys.len(),
+            options: BatchOptions {
+                max_rows: options.max_rows.max(1),
+                ..options
+            },
+            pending: vec![],
+            pending_since: 0.,
+            batches: 0,
+        })
+    }
+}
@@ -0,0 +1,230 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use miette::Report;
+use serde_derive::Serialize;
+
+use crate::data::functions::current_validity;
+use crate::data::symb::Symbol;
+use crate::parse::{parse_script, CozoScript, SourceSpan};
+use crate::query::compile::{CompiledProgram, CompiledRuleSet};
+use crate::query::ra::{InnerJoin, NegJoin, RelAlgebra};
+use crate::runtime::relation::RelationHandle;
+use crate::{DataValue, Db, Storage};
+
+/// How serious a finding of [`Db::check`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckSeverity {
+    /// The script would fail to run
+    Error,
+    /// The script would run, but probably slowly
+    Warning,
+}
+
+/// A finding of [`Db::check`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CheckDiagnostic {
+    /// How serious it is
+    pub severity: CheckSeverity,
+    /// The code of the error, such as `eval::unsafe_negation`, or of the warning
+    pub code: String,
+    /// Description of the problem
+    pub message: String,
+    /// How to fix the problem, if known
+    pub help: Option<String>,
+    /// The parts of the script concerned, as byte offsets and lengths
+    pub spans: Vec<(usize, usize)>,
+    /// The rule concerned, for warnings
+    pub rule: Option<String>,
+}
+
+impl CheckDiagnostic {
+    fn of_error(err: &Report) -> Self {
+        Self {
+            severity: CheckSeverity::Error,
+            code: err
+                .code()
+                .map(|c| c.to_string())
+                .unwrap_or_else(|| "eval::error".to_string()),
+            message: err.to_string(),
+            help: err.help().map(|h| h.to_string()),
+            spans: err
+                .labels()
+                .into_iter()
+                .flatten()
+                .map(|l| (l.offset(), l.len()))
+                .collect(),
+            rule: None,
+        }
+    }
+    fn warning(code: &str, message: String, help: String, span: SourceSpan, rule: String) -> Self {
+        Self {
+            severity: CheckSeverity::Warning,
+            code: code.to_string(),
+            message,
+            help: Some(help),
+            spans: vec![(span.0, span.1)],
+            rule: Some(rule),
+        }
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Parse and plan the CozoScript passed in without running it, reporting the problems
+    /// found, none if the script looks fine. Errors are those the script would fail with,
+    /// such as unknown relations or columns, unbound variables and unsafe negations.
+    /// Warnings point out joins that are cartesian products, and joins on stored
+    /// relations that cannot use the keys of the relation and so scan all of it.
+    ///
+    /// Only queries are planned: for imperative scripts and system ops only the syntax
+    /// is checked.
+    pub fn check(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Vec<CheckDiagnostic> {
+        let script = match parse_script(
+            payload,
+            &params,
+            &self.fixed_rules.read().unwrap(),
+            current_validity(),
+            false,
+        ) {
+            Ok(script) => script,
+            Err(err) => return vec![CheckDiagnostic::of_error(&err)],
+        };
+        let CozoScript::Single(program) = script else {
+            return vec![];
+        };
+        let res = self.transact().and_then(|tx| {
+            let (normalized, _) = program.into_normalized_program(&tx)?;
+            let (stratified, _) = normalized.into_stratified_program()?;
+            let magic = stratified.magic_sets_rewrite(&tx)?;
+            let mut tx = tx;
+            tx.stratified_magic_compile(magic)
+        });
+        match res {
+            Ok(compiled) => plan_warnings(&compiled),
+            Err(err) => vec![CheckDiagnostic::of_error(&err)],
+        }
+    }
+}
+
+fn plan_warnings(strata: &[CompiledProgram]) -> Vec<CheckDiagnostic> {
+    let mut ret = vec![];
+    for stratum in strata {
+        for (name, rule_set) in stratum {
+            if let CompiledRuleSet::Rules(rules) = rule_set {
+                for rule in rules {
+                    let name = name.as_plain_symbol().name.to_string();
+                    relation_warnings(&rule.relation, &name, &mut ret);
+                }
+            }
+        }
+    }
+    ret
+}
+
+fn relation_warnings(rel: &RelAlgebra, rule: &str, ret: &mut Vec<CheckDiagnostic>) {
+    match rel {
+        RelAlgebra::Join(join) => {
+            let InnerJoin {
+                left,
+                right,
+                joiner,
+                span,
+                ..
+            } = join.as_ref();
+            if !left.is_unit() {
+                if joiner.left_keys.is_empty() && !right.is_unit() {
+                    ret.push(CheckDiagnostic::warning(
+                        "check::cartesian_product",
+                        format!("Rule '{rule}' joins atoms sharing no variable"),
+                        "Every row of one side is combined with every row of the other, \
+                        bind a variable in both if this is not intended"
+                            .to_string(),
+                        *span,
+                        rule.to_string(),
+                    ));
+                } else if join.join_type() == "stored_mat_join" {
+                    full_scan_warning(right, &joiner.right_keys, *span, rule, ret);
+                }
+            }
+            relation_warnings(left, rule, ret);
+            relation_warnings(right, rule, ret);
+        }
+        RelAlgebra::NegJoin(join) => {
+            let NegJoin {
+                left,
+                right,
+                joiner,
+                span,
+                ..
+            } = join.as_ref();
+            if join.join_type() == "stored_neg_mat_join" {
+                full_scan_warning(right, &joiner.right_keys, *span, rule, ret);
+            }
+            relation_warnings(left, rule, ret);
+        }
+        RelAlgebra::Reorder(r) => relation_warnings(&r.relation, rule, ret),
+        RelAlgebra::Filter(r) => relation_warnings(&r.parent, rule, ret),
+        RelAlgebra::Unification(r) => relation_warnings(&r.parent, rule, ret),
+        _ => {}
+    }
+}
+
+fn full_scan_warning(
+    right: &RelAlgebra,
+    right_keys: &[Symbol],
+    span: SourceSpan,
+    rule: &str,
+    ret: &mut Vec<CheckDiagnostic>,
+) {
+    let RelAlgebra::Stored(stored) = right else {
+        return;
+    };
+    let columns = joined_columns(&stored.storage, &stored.bindings, right_keys);
+    let relation = &stored.storage.name;
+    ret.push(CheckDiagnostic::warning(
+        "check::missing_index",
+        format!(
+            "Rule '{rule}' joins *{relation} on {}, which does not start its keys, \
+            so all of *{relation} is scanned",
+            columns.join(", ")
+        ),
+        format!(
+            "Create an index of {relation} whose keys start with {}",
+            columns.join(", ")
+        ),
+        span,
+        rule.to_string(),
+    ));
+}
+
+/// The columns of `handle` bound to the variables `keys`, given the variables bound to
+/// all of its columns.
+fn joined_columns(
+    handle: &RelationHandle,
+    bindings: &[Symbol],
+    keys: &[Symbol],
+) -> Vec<String> {
+    let columns = handle
+        .metadata
+        .keys
+        .iter()
+        .chain(handle.metadata.non_keys.iter());
+    bindings
+        .iter()
+        .zip(columns)
+        .filter(|(binding, _)| keys.contains(binding))
+        .map(|(_, col)| col.name.to_string())
+        .collect()
+}
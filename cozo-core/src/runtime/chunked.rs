@@ -0,0 +1,260 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Imports committed in several transactions and rolled back together, see
+//! [`Db::import_relations_chunked`].
+
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use log::error;
+use miette::Result;
+use smartstring::SmartString;
+
+use crate::data::functions::current_validity;
+use crate::data::tuple::Tuple;
+use crate::data::value::ValidityTs;
+use crate::runtime::transact::SessionTx;
+use crate::runtime::tx_log::{TxGroup, TxGroupState};
+use crate::storage::{Storage, TxDurability};
+use crate::{Db, NamedRows};
+
+/// First byte of every key of the journal of chunked imports, which is stored just
+/// before the transaction log.
+pub(crate) const CHUNK_JOURNAL_KEY_MARKER: u8 = 0xFC;
+
+/// Journal entries handled by each transaction clearing the journal of an import.
+const CLEARING_CHUNK: usize = 10000;
+
+/// Values of the header of an import, which is committed with its last chunk.
+const IMPORT_PENDING: u8 = 0;
+const IMPORT_COMMITTED: u8 = 1;
+
+fn header_key(group: u64) -> Vec<u8> {
+    let mut key = Vec::with_capacity(10);
+    key.push(CHUNK_JOURNAL_KEY_MARKER);
+    key.extend_from_slice(&group.to_be_bytes());
+    key.push(0);
+    key
+}
+
+fn undo_key(group: u64, key: &[u8]) -> Vec<u8> {
+    let mut undo = Vec::with_capacity(10 + key.len());
+    undo.push(CHUNK_JOURNAL_KEY_MARKER);
+    undo.extend_from_slice(&group.to_be_bytes());
+    undo.push(1);
+    undo.extend_from_slice(key);
+    undo
+}
+
+fn undo_bounds(group: u64) -> (Vec<u8>, Vec<u8>) {
+    let mut upper = header_key(group);
+    upper[9] = 2;
+    (undo_key(group, &[]), upper)
+}
+
+/// Records the value a key held before an import first writes it, in the transaction
+/// writing it, so that the import can be undone. The default journal records nothing.
+#[derive(Default)]
+pub(crate) struct ImportJournal(Option<u64>);
+
+impl ImportJournal {
+    pub(crate) fn put(&self, tx: &mut SessionTx<'_>, key: &[u8], val: &[u8]) -> Result<()> {
+        self.record(tx, key)?;
+        tx.store_tx.put(key, val)
+    }
+    pub(crate) fn del(&self, tx: &mut SessionTx<'_>, key: &[u8]) -> Result<()> {
+        self.record(tx, key)?;
+        tx.store_tx.del(key)
+    }
+    fn record(&self, tx: &mut SessionTx<'_>, key: &[u8]) -> Result<()> {
+        if let Some(group) = self.0 {
+            let undo = undo_key(group, key);
+            if !tx.store_tx.exists(&undo, true)? {
+                let old = match tx.store_tx.get(key, true)? {
+                    None => vec![0],
+                    Some(val) => [&[1], &val[..]].concat(),
+                };
+                tx.store_tx.put(&undo, &old)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+type ImportChunk<'a> = Vec<(&'a str, &'a [String], &'a [Tuple])>;
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Import `data` as [`import_relations`](Self::import_relations) does, committing at
+    /// most `max_rows` rows per write transaction, so that imports of millions of rows
+    /// need not fit in one transaction of the storage engine. If a transaction fails,
+    /// those already committed are rolled back and the error is returned, so the import
+    /// is written whole or not at all. Returns the number of transactions that wrote rows.
+    ///
+    /// The import is not isolated: other transactions see its rows as they are committed,
+    /// and rolling back restores the values the import overwrote even if others changed
+    /// them since. Each transaction records the previous values of the keys it writes
+    /// first, and an import interrupted by a crash is rolled back when the database is
+    /// next opened, before the transaction log can be enabled again. The entries of the
+    /// transaction log of an import are linked by a [`TxGroup`].
+    pub fn import_relations_chunked(
+        &'s self,
+        data: BTreeMap<String, NamedRows>,
+        max_rows: usize,
+    ) -> Result<usize> {
+        let rel_names = data.keys().map(SmartString::from).collect_vec();
+        let locks = self.obtain_relation_locks(rel_names.iter());
+        let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
+
+        let cur_vld = current_validity();
+        let max_rows = max_rows.max(1);
+        let mut chunks: Vec<ImportChunk<'_>> = vec![vec![]];
+        let mut room = max_rows;
+        for (relation_op, in_data) in &data {
+            let mut rows = &in_data.rows[..];
+            while !rows.is_empty() {
+                if room == 0 {
+                    chunks.push(vec![]);
+                    room = max_rows;
+                }
+                let (piece, rest) = rows.split_at(room.min(rows.len()));
+                let chunk = chunks.last_mut().unwrap();
+                chunk.push((relation_op, &in_data.headers, piece));
+                room -= piece.len();
+                rows = rest;
+            }
+        }
+        if chunks.len() == 1 {
+            self.write_import_chunk(&chunks[0], None, None, cur_vld)?;
+            return Ok(1);
+        }
+
+        let group = rand::random::<u64>();
+        for (i, chunk) in chunks.iter().enumerate() {
+            // the header is committed with the last chunk, which needs no journal as it
+            // is either committed with the header or not at all
+            let header = if i == 0 {
+                Some(IMPORT_PENDING)
+            } else if i + 1 == chunks.len() {
+                Some(IMPORT_COMMITTED)
+            } else {
+                None
+            };
+            if let Err(err) = self.write_import_chunk(chunk, Some(group), header, cur_vld) {
+                if i > 0 {
+                    if let Err(rollback_err) = self.clear_import_journal(group, true) {
+                        error!("Cannot roll back the chunked import {group}: {rollback_err}");
+                    }
+                }
+                return Err(err);
+            }
+        }
+        self.clear_import_journal(group, false)?;
+        Ok(chunks.len())
+    }
+
+    fn write_import_chunk(
+        &'s self,
+        chunk: &ImportChunk<'_>,
+        group: Option<u64>,
+        header: Option<u8>,
+        cur_vld: ValidityTs,
+    ) -> Result<()> {
+        let mut tx = self.transact_write_in_group(
+            TxDurability::Default,
+            group.map(|id| TxGroup {
+                id,
+                state: TxGroupState::Pending,
+            }),
+        )?;
+        let journal = match header {
+            Some(IMPORT_COMMITTED) => ImportJournal::default(),
+            _ => ImportJournal(group),
+        };
+        for (relation_op, headers, rows) in chunk {
+            self.import_rows(&mut tx, relation_op, headers, rows, cur_vld, &journal)?;
+        }
+        if let (Some(group), Some(header)) = (group, header) {
+            tx.store_tx.put(&header_key(group), &[header])?;
+        }
+        tx.commit_tx()
+    }
+
+    /// Remove the journal of the import `group`, first restoring the values it recorded
+    /// if `undo` is set, in transactions of bounded size.
+    fn clear_import_journal(&'s self, group: u64, undo: bool) -> Result<()> {
+        let (lower, upper) = undo_bounds(group);
+        let pending = Some(TxGroup {
+            id: group,
+            state: TxGroupState::Pending,
+        });
+        loop {
+            let mut tx = self.transact_write_in_group(TxDurability::Default, pending)?;
+            let recorded: Vec<_> = tx
+                .store_tx
+                .range_scan(&lower, &upper)
+                .take(CLEARING_CHUNK)
+                .try_collect()?;
+            if recorded.is_empty() {
+                break;
+            }
+            for (key, old) in recorded {
+                if undo {
+                    match old.split_first() {
+                        Some((1, val)) => tx.store_tx.put(&key[lower.len()..], val)?,
+                        _ => tx.store_tx.del(&key[lower.len()..])?,
+                    }
+                }
+                tx.store_tx.del(&key)?;
+            }
+            tx.commit_tx()?;
+        }
+        let state = if undo {
+            TxGroupState::RolledBack
+        } else {
+            TxGroupState::Committed
+        };
+        let mut tx = self
+            .transact_write_in_group(TxDurability::Default, Some(TxGroup { id: group, state }))?;
+        tx.store_tx.del(&header_key(group))?;
+        tx.commit_tx()
+    }
+
+    /// Finish the imports interrupted by a crash: roll back those whose last chunk was
+    /// not committed, and clear the journal of the others.
+    pub(crate) fn recover_chunked_imports(&'s self) -> Result<()> {
+        let mut lower = vec![CHUNK_JOURNAL_KEY_MARKER];
+        loop {
+            let interrupted = {
+                let tx = self.transact()?;
+                let first = tx
+                    .store_tx
+                    .range_scan(&lower, &[CHUNK_JOURNAL_KEY_MARKER + 1])
+                    .next()
+                    .transpose()?;
+                match first {
+                    None => None,
+                    Some((key, _)) => {
+                        let group = u64::from_be_bytes(key[1..9].try_into().unwrap());
+                        let header = tx.store_tx.get(&header_key(group), false)?;
+                        Some((group, header.as_deref() == Some(&[IMPORT_COMMITTED])))
+                    }
+                }
+            };
+            let (group, committed) = match interrupted {
+                None => return Ok(()),
+                Some(found) => found,
+            };
+            self.clear_import_journal(group, !committed)?;
+            match group.checked_add(1) {
+                Some(next) => lower = header_key(next),
+                None => return Ok(()),
+            }
+        }
+    }
+}
@@ -0,0 +1,161 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Named queries that must return no rows once a transaction has written, see
+//! [`Db::register_constraint`].
+
+use std::collections::btree_map::Entry;
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::Ordering;
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Result};
+use thiserror::Error;
+
+use crate::data::functions::current_validity;
+use crate::data::program::InputProgram;
+use crate::data::value::ValidityTs;
+use crate::parse::{parse_script, CozoScript};
+use crate::runtime::transact::SessionTx;
+use crate::storage::Storage;
+use crate::{Db, NamedRows};
+
+/// Rows shown in the message of a violated constraint.
+const SHOWN_ROWS: usize = 10;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Constraint '{constraint}' is violated by {}", ShownRows(.rows))]
+#[diagnostic(code(transact::constraint_violated))]
+#[diagnostic(help("The transaction was aborted as the constraint query returned rows"))]
+pub(crate) struct ConstraintViolation {
+    constraint: String,
+    rows: NamedRows,
+}
+
+struct ShownRows<'a>(&'a NamedRows);
+
+impl Display for ShownRows<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let rows = &self.0.rows;
+        let shown = rows
+            .iter()
+            .take(SHOWN_ROWS)
+            .map(|row| format!("[{}]", row.iter().join(", ")))
+            .join(", ");
+        write!(f, "{} rows: {shown}", rows.len())?;
+        if rows.len() > SHOWN_ROWS {
+            write!(f, ", ...")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The query of constraint '{0}' must be a single query that does not write")]
+#[diagnostic(code(db::invalid_constraint))]
+struct InvalidConstraint(String);
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Register the constraint `name`: `query` must return no rows once any transaction
+    /// writing to stored relations is done, or the transaction is aborted with a
+    /// `transact::constraint_violated` error showing the rows returned. The query sees the writes of
+    /// the transaction, so it can state invariants over several relations, such as
+    /// `?[id] := *orders{id, customer}, not *customers{id: customer}`.
+    ///
+    /// Registering fails if the query is not a single query, if it writes, if a
+    /// constraint of the same name is registered, or if the data already violates the
+    /// constraint. Constraints are not persisted, and are checked with full access to
+    /// the data whatever the role of the session writing.
+    pub fn register_constraint(&'s self, name: &str, query: &str) -> Result<()> {
+        let cur_vld = current_validity();
+        self.parse_constraint(name, query, cur_vld)?;
+        let mut tx = self.transact()?;
+        self.check_constraint(&mut tx, name, query, cur_vld)?;
+        match self.constraints.write().unwrap().entry(name.to_string()) {
+            Entry::Vacant(ent) => {
+                ent.insert(query.to_string());
+                Ok(())
+            }
+            Entry::Occupied(ent) => {
+                bail!(
+                    "A constraint with the name {} is already registered",
+                    ent.key()
+                )
+            }
+        }
+    }
+
+    /// Unregister the constraint `name`, returning `false` if there is no such constraint.
+    pub fn unregister_constraint(&'s self, name: &str) -> bool {
+        self.constraints.write().unwrap().remove(name).is_some()
+    }
+
+    /// Check every registered constraint against the state of `tx`. Write transactions
+    /// run this when they commit, see [`SessionTx::constraints`].
+    pub(crate) fn check_constraints(
+        &'s self,
+        tx: &mut SessionTx<'_>,
+        cur_vld: ValidityTs,
+    ) -> Result<()> {
+        let constraints = self.constraints.read().unwrap().clone();
+        for (name, query) in &constraints {
+            self.check_constraint(tx, name, query, cur_vld)?;
+        }
+        Ok(())
+    }
+
+    fn check_constraint(
+        &'s self,
+        tx: &mut SessionTx<'_>,
+        name: &str,
+        query: &str,
+        cur_vld: ValidityTs,
+    ) -> Result<()> {
+        let program = self.parse_constraint(name, query, cur_vld)?;
+        // the restrictions of the session must not hide violating rows
+        let role = tx.session.role.take();
+        let quota = tx.session.quota.take();
+        let res = self.run_query(
+            tx,
+            program,
+            cur_vld,
+            &Default::default(),
+            &mut Default::default(),
+            false,
+        );
+        tx.session.role = role;
+        tx.session.quota = quota;
+        let (rows, _) = res?;
+        if !rows.rows.is_empty() {
+            bail!(ConstraintViolation {
+                constraint: name.to_string(),
+                rows,
+            })
+        }
+        Ok(())
+    }
+
+    fn parse_constraint(
+        &'s self,
+        name: &str,
+        query: &str,
+        cur_vld: ValidityTs,
+    ) -> Result<InputProgram> {
+        let script = parse_script(
+            query,
+            &Default::default(),
+            &self.fixed_rules.read().unwrap(),
+            cur_vld,
+            self.string_literals_forbidden.load(Ordering::Relaxed),
+        )?;
+        match script {
+            CozoScript::Single(p) if p.needs_write_lock().is_none() => Ok(p),
+            _ => bail!(InvalidConstraint(name.to_string())),
+        }
+    }
+}
@@ -0,0 +1,305 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Columns of the `Counter` and `Register` types, whose values are merged with the ones
+//! they overwrite instead of replacing them, so that the writes of concurrent writers or
+//! of other databases give the same result in any order.
+//!
+//! A counter only grows. It is stored as a JSON object of the total added by each
+//! replica, and writing a non-negative integer adds it to the total of this replica.
+//! A register keeps the last value written. It is stored as
+//! `{"value": ..., "clock": [millis, counter, replica]}`, the clock being read from a
+//! hybrid logical clock, and writing any other value stamps it with the clock.
+//! Writing stored states, such as the ones exported from another database, merges them.
+
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crossbeam::sync::ShardedLock;
+use miette::{bail, Diagnostic, IntoDiagnostic, Result};
+use serde_json::{json, Map, Value as JsonValue};
+use thiserror::Error;
+
+use crate::data::relation::{ColType, StoredRelationMetadata};
+use crate::data::value::{DataValue, JsonData};
+use crate::runtime::transact::replica_id_key;
+use crate::storage::Storage;
+use crate::Db;
+
+/// The replica writes are counted and stamped for, with its hybrid logical clock.
+pub(crate) struct Replica {
+    id: ShardedLock<String>,
+    /// The last clock reading as milliseconds since the epoch and a counter
+    clock: Mutex<(i64, i64)>,
+}
+
+impl Default for Replica {
+    fn default() -> Self {
+        Self {
+            id: ShardedLock::new(uuid::Uuid::new_v4().to_string()),
+            clock: Mutex::new((0, 0)),
+        }
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("column {0} of type {1} cannot be used {2}")]
+#[diagnostic(code(eval::misplaced_merged_column))]
+#[diagnostic(help("Counter and Register columns can only be value columns"))]
+struct MisplacedMergedColumn(String, &'static str, &'static str);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("counter overflow for replica {0}")]
+#[diagnostic(code(eval::counter_overflow))]
+struct CounterOverflow(String);
+
+fn merged_type_name(coltype: &ColType) -> Option<&'static str> {
+    match coltype {
+        ColType::Counter => Some("Counter"),
+        ColType::Register => Some("Register"),
+        _ => None,
+    }
+}
+
+fn contains_merged_type(coltype: &ColType) -> Option<&'static str> {
+    match coltype {
+        ColType::List { eltype, .. } => contains_merged_type(&eltype.coltype),
+        ColType::Tuple(els) => els.iter().find_map(|el| contains_merged_type(&el.coltype)),
+        t => merged_type_name(t),
+    }
+}
+
+/// Fail if a key column, or a type nested in a list or a tuple, is a merged type.
+pub(crate) fn ensure_merged_columns_valid(metadata: &StoredRelationMetadata) -> Result<()> {
+    for col in &metadata.keys {
+        if let Some(name) = contains_merged_type(&col.typing.coltype) {
+            bail!(MisplacedMergedColumn(col.name.to_string(), name, "as a key"))
+        }
+    }
+    for col in &metadata.non_keys {
+        if merged_type_name(&col.typing.coltype).is_none() {
+            if let Some(name) = contains_merged_type(&col.typing.coltype) {
+                bail!(MisplacedMergedColumn(
+                    col.name.to_string(),
+                    name,
+                    "inside a list or a tuple"
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The positions in the rows of the relation of its merged columns.
+pub(crate) fn merged_columns(metadata: &StoredRelationMetadata) -> Vec<(usize, ColType)> {
+    metadata
+        .non_keys
+        .iter()
+        .enumerate()
+        .filter(|(_, col)| merged_type_name(&col.typing.coltype).is_some())
+        .map(|(i, col)| (metadata.keys.len() + i, col.typing.coltype.clone()))
+        .collect()
+}
+
+/// The clock of a stored register state.
+fn register_clock(state: &Map<String, JsonValue>) -> Option<(i64, i64, &str)> {
+    if state.len() != 2 || !state.contains_key("value") {
+        return None;
+    }
+    match state.get("clock")?.as_array()?.as_slice() {
+        [millis, counter, replica] => {
+            Some((millis.as_i64()?, counter.as_i64()?, replica.as_str()?))
+        }
+        _ => None,
+    }
+}
+
+/// Whether `v` is the state of a register, rather than a value to write into one.
+pub(crate) fn is_register_state(v: &DataValue) -> bool {
+    matches!(v, DataValue::Json(JsonData(JsonValue::Object(o))) if register_clock(o).is_some())
+}
+
+impl Replica {
+    fn now(&self) -> (i64, i64) {
+        let wall = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or_default();
+        let mut last = self.clock.lock().unwrap();
+        *last = if wall > last.0 {
+            (wall, 0)
+        } else {
+            (last.0, last.1 + 1)
+        };
+        *last
+    }
+
+    /// Move the clock past a reading of another replica, so that later writes are
+    /// stamped after the writes merged from it.
+    fn observe(&self, millis: i64, counter: i64) {
+        let mut last = self.clock.lock().unwrap();
+        if (millis, counter) > *last {
+            *last = (millis, counter);
+        }
+    }
+
+    fn merge_counter(&self, old: Option<&DataValue>, new: DataValue) -> Result<DataValue> {
+        let mut state = match old {
+            Some(DataValue::Json(JsonData(JsonValue::Object(o)))) => o.clone(),
+            _ => Map::new(),
+        };
+        match new {
+            DataValue::Json(JsonData(JsonValue::Object(other))) => {
+                for (replica, count) in other {
+                    let count = count.as_i64().unwrap_or_default();
+                    let current = state.get(&replica).and_then(|c| c.as_i64());
+                    if current.is_none_or(|c| c < count) {
+                        state.insert(replica, json!(count));
+                    }
+                }
+            }
+            DataValue::Null => {
+                return Ok(match old {
+                    Some(old) => old.clone(),
+                    None => DataValue::Null,
+                })
+            }
+            v => {
+                let increment = v.get_int().unwrap_or_default();
+                let id = self.id.read().unwrap();
+                let current = state.get(id.as_str()).and_then(|c| c.as_i64());
+                let total = current
+                    .unwrap_or_default()
+                    .checked_add(increment)
+                    .ok_or_else(|| CounterOverflow(id.to_string()))?;
+                state.insert(id.to_string(), json!(total));
+            }
+        }
+        Ok(DataValue::Json(JsonData(JsonValue::Object(state))))
+    }
+
+    fn merge_register(&self, old: Option<&DataValue>, new: DataValue) -> Result<DataValue> {
+        let observe = |v: &DataValue| {
+            if let DataValue::Json(JsonData(JsonValue::Object(o))) = v {
+                if let Some((millis, counter, _)) = register_clock(o) {
+                    self.observe(millis, counter);
+                }
+            }
+        };
+        let new = if is_register_state(&new) {
+            observe(&new);
+            new
+        } else {
+            // a value written over another one is stamped after it
+            if let Some(old) = old {
+                observe(old);
+            }
+            let (millis, counter) = self.now();
+            let replica = self.id.read().unwrap().clone();
+            DataValue::Json(JsonData(json!({
+                "value": JsonValue::from(new),
+                "clock": [millis, counter, replica],
+            })))
+        };
+        let clock = |v: &DataValue| match v {
+            DataValue::Json(JsonData(JsonValue::Object(o))) => {
+                register_clock(o).map(|(m, c, r)| (m, c, r.to_string()))
+            }
+            _ => None,
+        };
+        Ok(match old {
+            Some(old) if clock(old) > clock(&new) => old.clone(),
+            _ => new,
+        })
+    }
+
+    /// Merge the values of the merged columns `columns` of the row `new` with those of
+    /// `old`, the stored row with the same key if there is one.
+    pub(crate) fn merge_row(
+        &self,
+        columns: &[(usize, ColType)],
+        old: Option<&[DataValue]>,
+        new: &mut [DataValue],
+    ) -> Result<()> {
+        for (i, coltype) in columns {
+            let old_val = old.map(|o| &o[*i]);
+            let val = std::mem::replace(&mut new[*i], DataValue::Null);
+            new[*i] = match coltype {
+                ColType::Counter => self.merge_counter(old_val, val)?,
+                _ => self.merge_register(old_val, val)?,
+            };
+        }
+        Ok(())
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// The replica that writes into `Counter` columns are counted for, and that stamps
+    /// the writes into `Register` columns. It is a random UUID stored with the data when
+    /// the database is created, unless set with [`set_replica_id`](Self::set_replica_id),
+    /// so that a database keeps its replica id when opened again.
+    pub fn replica_id(&self) -> String {
+        self.replica.id.read().unwrap().clone()
+    }
+
+    /// Count and stamp later writes for the replica `id`, which is stored with the data.
+    /// Databases whose writes are merged together must have different replica ids, so
+    /// a copy of the data files must be given a new one.
+    pub fn set_replica_id(&'s self, id: &str) -> Result<()> {
+        let mut tx = self.transact_write()?;
+        tx.store_tx.put(&replica_id_key(), id.as_bytes())?;
+        tx.commit_tx()?;
+        *self.replica.id.write().unwrap() = id.to_string();
+        Ok(())
+    }
+
+    /// Read the stored replica id, storing the random one of a new database.
+    pub(crate) fn load_replica_id(&'s self) -> Result<()> {
+        let mut tx = self.transact_write()?;
+        match tx.store_tx.get(&replica_id_key(), false)? {
+            Some(id) => {
+                let id = String::from_utf8(id).into_diagnostic()?;
+                *self.replica.id.write().unwrap() = id;
+            }
+            None => {
+                let id = self.replica_id();
+                tx.store_tx.put(&replica_id_key(), id.as_bytes())?;
+                tx.commit_tx()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_commute() {
+        let a = Replica::default();
+        let b = Replica::default();
+        let mut x = a.merge_counter(None, DataValue::from(2)).unwrap();
+        x = a.merge_counter(Some(&x), DataValue::from(3)).unwrap();
+        let y = b.merge_counter(None, DataValue::from(4)).unwrap();
+        let xy = a.merge_counter(Some(&x), y.clone()).unwrap();
+        let yx = b.merge_counter(Some(&y), x.clone()).unwrap();
+        assert_eq!(xy, yx);
+        // merging again changes nothing
+        assert_eq!(a.merge_counter(Some(&xy), x).unwrap(), xy);
+
+        let r1 = a.merge_register(None, DataValue::from("first")).unwrap();
+        let r2 = b.merge_register(Some(&r1), DataValue::from("second")).unwrap();
+        // the clock of a moves past the reading of b, so its next write wins
+        let r3 = a.merge_register(Some(&r1), r2.clone()).unwrap();
+        assert_eq!(r3, r2);
+        assert_eq!(a.merge_register(Some(&r2), r1.clone()).unwrap(), r2);
+        let r4 = a.merge_register(Some(&r3), DataValue::from("third")).unwrap();
+        assert_eq!(b.merge_register(Some(&r2), r4.clone()).unwrap(), r4);
+    }
+}
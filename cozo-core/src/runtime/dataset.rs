@@ -0,0 +1,99 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! A generated dataset for benchmarks, see [`Db::bench_dataset`].
+
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use miette::Result;
+
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::storage::Storage;
+use crate::{Db, NamedRows, ScriptMutability};
+
+/// Rows imported at once, so that large datasets are not held in memory whole.
+const IMPORT_CHUNK: u64 = 10000;
+
+/// Number of values of the `group` column of `bench_node`.
+const GROUPS: u64 = 100;
+
+/// The `dst` of the edge going across the tree from `src`, spreading edges over the ids.
+fn cross_edge(src: u64, n: u64) -> u64 {
+    (src * 31 + 17) % n
+}
+
+fn node_row(id: u64) -> Tuple {
+    vec![
+        DataValue::from(id as i64),
+        DataValue::from(format!("node-{id}")),
+        DataValue::from((id % GROUPS) as i64),
+        DataValue::from((id.wrapping_mul(2654435761) % 1000) as f64 / 10.),
+    ]
+}
+
+fn edge_rows(src: u64, n: u64) -> impl Iterator<Item = Tuple> {
+    [2 * src + 1, 2 * src + 2]
+        .into_iter()
+        .filter(move |dst| *dst < n)
+        .chain((n > 1).then(|| cross_edge(src, n)))
+        .unique()
+        .map(move |dst| {
+            vec![
+                DataValue::from(src as i64),
+                DataValue::from(dst as i64),
+                DataValue::from(((src + dst) % 10) as f64 / 10.),
+            ]
+        })
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Create and fill the relations of a dataset of `n` nodes, the same for a given `n`
+    /// on every release, for benchmarks to run against:
+    ///
+    /// * `bench_node {id: Int => name: String, group: Int, score: Float}`, with the ids
+    ///   `0` to `n - 1` and `group` being `id % 100`;
+    /// * `bench_edge {src: Int, dst: Int => weight: Float}`, where each node has edges to
+    ///   its children `2 * id + 1` and `2 * id + 2` in a binary tree, when they exist, and
+    ///   one edge across the tree, so that every node is reachable from node `0` in about
+    ///   `log2(n)` steps.
+    ///
+    /// Fails if either relation exists.
+    pub fn bench_dataset(&'s self, n: u64) -> Result<()> {
+        self.run_script(
+            r#"
+            {:create bench_node {id: Int => name: String, group: Int, score: Float}}
+            {:create bench_edge {src: Int, dst: Int => weight: Float}}
+            "#,
+            Default::default(),
+            ScriptMutability::Mutable,
+        )?;
+        for start in (0..n).step_by(IMPORT_CHUNK as usize) {
+            let ids = start..(start + IMPORT_CHUNK).min(n);
+            let nodes = NamedRows::new(
+                vec![
+                    "id".to_string(),
+                    "name".to_string(),
+                    "group".to_string(),
+                    "score".to_string(),
+                ],
+                ids.clone().map(node_row).collect_vec(),
+            );
+            let edges = NamedRows::new(
+                vec!["src".to_string(), "dst".to_string(), "weight".to_string()],
+                ids.flat_map(|src| edge_rows(src, n)).collect_vec(),
+            );
+            self.import_relations(BTreeMap::from([
+                ("bench_node".to_string(), nodes),
+                ("bench_edge".to_string(), edges),
+            ]))?;
+        }
+        Ok(())
+    }
+}
@@ -10,27 +10,33 @@ use std::collections::btree_map::Entry;
 use std::collections::{BTreeMap, BTreeSet};
 use std::default::Default;
 use std::fmt::{Debug, Formatter};
+use std::io::{BufWriter, Write};
 use std::iter;
+use std::ops::RangeInclusive;
 use std::path::Path;
 #[allow(unused_imports)]
-use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
 #[allow(unused_imports)]
 use std::thread;
 #[allow(unused_imports)]
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[allow(unused_imports)]
-use crossbeam::channel::{bounded, unbounded, Receiver, Sender};
+use crossbeam::channel::{bounded, unbounded, Receiver, RecvTimeoutError, Sender};
 use crossbeam::sync::ShardedLock;
 use either::{Left, Right};
 use itertools::Itertools;
+#[allow(unused_imports)]
+use log::{debug, error};
 use miette::Report;
 #[allow(unused_imports)]
 use miette::{bail, ensure, miette, Diagnostic, IntoDiagnostic, Result, WrapErr};
+use serde::de::DeserializeOwned;
 use serde_json::json;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
+use twox_hash::xxh3::hash128;
 
 use crate::data::functions::current_validity;
 use crate::data::json::JsonValue;
@@ -38,11 +44,17 @@ use crate::data::program::{InputProgram, QueryAssertion, RelationOp, ReturnMutat
 use crate::data::relation::ColumnDef;
 use crate::data::tuple::{Tuple, TupleT};
 use crate::data::value::{DataValue, ValidityTs, LARGEST_UTF_CHAR};
+#[cfg(feature = "fetch")]
+use crate::fixed_rule::utilities::fetch::{FetchConfig, FetchJson};
 use crate::fixed_rule::DEFAULT_FIXED_RULES;
+use crate::runtime::crdt::{merged_columns, Replica};
+use crate::runtime::plan_guard::PlanLimits;
+use crate::runtime::procedure::Procedure;
 use crate::fts::TokenizerCache;
 use crate::parse::sys::SysOp;
 use crate::parse::{parse_expressions, parse_script, CozoScript, SourceSpan};
 use crate::query::compile::{CompiledProgram, CompiledRule, CompiledRuleSet};
+use crate::query::eval::EvalDump;
 use crate::query::ra::{
     FilteredRA, FtsSearchRA, HnswSearchRA, InnerJoin, LshSearchRA, NegJoin, RelAlgebra, ReorderRA,
     StoredRA, StoredWithValidityRA, TempStoreRA, UnificationRA,
@@ -51,12 +63,36 @@ use crate::query::ra::{
 use crate::runtime::callback::{
     CallbackCollector, CallbackDeclaration, CallbackOp, EventCallbackRegistry,
 };
+#[cfg(feature = "async")]
+use crate::async_api::WriteQueue;
+use crate::runtime::audit::{
+    read_audit_log, run_audit_writer, AuditEntry, AuditEvent, AuditLog, AuditRetention,
+};
+use crate::runtime::chunked::{ImportJournal, CHUNK_JOURNAL_KEY_MARKER};
+use crate::runtime::dump::write_dump_header;
+use crate::runtime::metrics::{Metrics, MetricsRegistry};
 use crate::runtime::relation::{
-    extend_tuple_from_v, AccessLevel, InsufficientAccessLevel, RelationHandle, RelationId,
+    extend_tuple_from_v, AccessLevel, GrantMode, InsufficientAccessLevel, RelationHandle,
+    RelationId,
 };
-use crate::runtime::transact::SessionTx;
+use crate::runtime::jobs::JobRegistry;
+use crate::runtime::limits::{EvalGuard, EvalLimits, EvalProgressCallback};
+use crate::runtime::quota::{Quota, QueryPermit, QuotaUsage, Quotas};
+use crate::runtime::slow_log::{SlowQuery, SlowQueryCallback, SlowQueryLog};
+#[cfg(feature = "scheduler")]
+use crate::runtime::scheduler::Schedules;
+use crate::runtime::schema_cache::{SchemaCache, SchemaView, SchemaWatchTx};
+use crate::runtime::stats::RelationAnalysis;
+use crate::runtime::stripes::{current_stripe, stripe_of, striped_id, Striped};
+use crate::runtime::transact::{idempotency_record_bounds, idempotency_record_key, SessionTx};
+use crate::runtime::tx_log::{
+    last_tx_log_seq, read_tx_log, replay_tx_log_entry, truncate_tx_log, LoggedTx, TxChanges,
+    TxGroup, TxLog, TxLogChunk, TxLogGap, TxLogNotEnabled, TxLogTruncated,
+};
+use crate::storage::ns::{ns_prefix, ns_prefix_upper, NsStorage};
 use crate::storage::temp::TempStorage;
-use crate::storage::Storage;
+use crate::storage::{Storage, StoreTx, TxDurability};
+use crate::utils::trace_span;
 use crate::{decode_tuple_from_kv, FixedRule, Symbol};
 
 pub(crate) struct RunningQueryHandle {
@@ -64,20 +100,422 @@ pub(crate) struct RunningQueryHandle {
     pub(crate) poison: Poison,
 }
 
-pub(crate) struct RunningQueryCleanup {
+/// The queries running, registered in the stripe of their thread, see [`Sessions`].
+#[derive(Default)]
+pub(crate) struct RunningQueries(Striped<RunningQueryStripe>);
+
+#[derive(Default)]
+struct RunningQueryStripe {
+    handles: BTreeMap<u64, RunningQueryHandle>,
+    next_seq: u64,
+}
+
+impl RunningQueries {
+    /// Register a query, to be unregistered by dropping the returned value.
+    pub(crate) fn register(&self, handle: RunningQueryHandle) -> RunningQueryCleanup<'_> {
+        let stripe = current_stripe();
+        let mut queries = self.0.lock(stripe);
+        let id = striped_id(stripe, queries.next_seq);
+        queries.next_seq += 1;
+        queries.handles.insert(id, handle);
+        RunningQueryCleanup {
+            id,
+            running_queries: self,
+        }
+    }
+    /// Returns `false` if there is no such query.
+    fn kill(&self, id: u64) -> bool {
+        match self.0.lock(stripe_of(id)).handles.get(&id) {
+            None => false,
+            Some(handle) => {
+                handle.poison.0.store(true, Ordering::Relaxed);
+                true
+            }
+        }
+    }
+    fn kill_all(&self) {
+        self.0.for_each(|queries| {
+            for handle in queries.handles.values() {
+                handle.poison.0.store(true, Ordering::Relaxed);
+            }
+        });
+    }
+    /// The id and start time of every running query.
+    fn list(&self) -> Vec<(u64, f64)> {
+        let mut ret = vec![];
+        self.0.for_each(|queries| {
+            ret.extend(queries.handles.iter().map(|(id, h)| (*id, h.started_at)));
+        });
+        ret.sort_by_key(|(id, _)| *id);
+        ret
+    }
+}
+
+pub(crate) struct RunningQueryCleanup<'a> {
     pub(crate) id: u64,
-    pub(crate) running_queries: Arc<Mutex<BTreeMap<u64, RunningQueryHandle>>>,
+    running_queries: &'a RunningQueries,
 }
 
-impl Drop for RunningQueryCleanup {
+impl Drop for RunningQueryCleanup<'_> {
     fn drop(&mut self) {
-        let mut map = self.running_queries.lock().unwrap();
-        if let Some(handle) = map.remove(&self.id) {
+        let mut queries = self.running_queries.0.lock(stripe_of(self.id));
+        if let Some(handle) = queries.handles.remove(&self.id) {
             handle.poison.0.store(true, Ordering::Relaxed);
         }
     }
 }
 
+/// The transactions open on a database, which [`Db::close`] waits for.
+///
+/// Sessions are registered in the stripe of the thread opening them, and only read the
+/// flags refusing new sessions, so that sessions opened concurrently on different
+/// threads do not contend. Closing and freezing writes set their flag before looking
+/// for the sessions to wait for, and sessions check the flags once registered, so that
+/// no session slips between the two.
+#[derive(Default)]
+pub(crate) struct Sessions {
+    registry: Striped<SessionStripe>,
+    closed: AtomicBool,
+    /// Set by [`Db::freeze_writes`]
+    writes_frozen: AtomicBool,
+    /// Number of callers waiting for sessions to finish, notified by finishing sessions
+    waiting: AtomicUsize,
+    /// Whether the storage was released by closing, also held while waiting for sessions
+    released: Mutex<bool>,
+    drained: Condvar,
+    pub(crate) audit: AuditLog,
+    pub(crate) quotas: Quotas,
+    pub(crate) eval_guard: EvalGuard,
+    /// Those of the namespaces opened by [`Db::open_ns`], which live in the same storage
+    namespaces: Mutex<Vec<Weak<Sessions>>>,
+}
+
+#[derive(Default)]
+struct SessionStripe {
+    entries: BTreeMap<u64, SessionEntry>,
+    next_seq: u64,
+}
+
+struct SessionEntry {
+    info: SessionInfo,
+    killed: bool,
+    running: Vec<Poison>,
+    // only sessions that ran scripts are audited, not internal ones
+    queried: bool,
+}
+
+/// A transaction open on the database, as listed by [`Db::list_sessions`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionInfo {
+    /// Identifier to pass to [`Db::kill_session`]
+    pub id: u64,
+    /// When the session started, in seconds since the epoch
+    pub started_at: f64,
+    /// Whether the session can write
+    pub write: bool,
+    /// The script the session is running, if any
+    pub query: Option<String>,
+    /// Name of the principal the session runs for, see [`Db::run_script_as`]
+    pub principal: Option<String>,
+    /// Role of the principal the session runs for
+    pub role: Option<String>,
+}
+
+/// The identity a script runs for, supplied by the embedder, see [`Db::run_script_as`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal {
+    /// Name of the principal, shown by [`Db::list_sessions`] and recorded in the audit log
+    pub name: String,
+    /// The role whose grants, set by `::grant`, restrict the columns the principal may
+    /// read and write. The row policies set by `::policy set` also apply to principals with
+    /// a role, with `$current_user` bound to the name. A principal without a role is not
+    /// restricted.
+    pub role: Option<String>,
+}
+
+impl Principal {
+    /// An unrestricted principal.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            role: None,
+        }
+    }
+    /// Restrict the principal to the grants of `role`.
+    pub fn with_role(mut self, role: impl Into<String>) -> Self {
+        self.role = Some(role.into());
+        self
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The database is closed")]
+#[diagnostic(code(db::closed))]
+pub(crate) struct DbClosed;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("{0}")]
+#[diagnostic(code(tx::read_only))]
+pub(crate) struct ReadOnlyViolation(pub(crate) &'static str);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Timed out closing the database: {0} transactions are still active")]
+#[diagnostic(code(db::close_timeout))]
+#[diagnostic(help("Running queries have been killed, the close may be retried"))]
+pub(crate) struct CloseTimedOut(usize);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Writes to the database are frozen")]
+#[diagnostic(code(db::writes_frozen))]
+#[diagnostic(help("Writes were frozen for maintenance with `Db::freeze_writes`, retry later"))]
+pub(crate) struct WritesFrozen;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Timed out freezing writes: {0} write transactions are still active")]
+#[diagnostic(code(db::freeze_timeout))]
+#[diagnostic(help("Writes are not frozen, the freeze may be retried"))]
+pub(crate) struct FreezeTimedOut(usize);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The session has been killed")]
+#[diagnostic(code(db::session_killed))]
+#[diagnostic(help("The transaction is aborted, start a new one"))]
+pub(crate) struct SessionKilled;
+
+impl Sessions {
+    fn enter<'a>(&'a self, write: bool, metrics: &'a MetricsRegistry) -> Result<SessionGuard<'a>> {
+        let started_at = seconds_since_the_epoch()?;
+        let stripe = current_stripe();
+        let id = {
+            let mut registry = self.registry.lock(stripe);
+            let id = striped_id(stripe, registry.next_seq);
+            registry.next_seq += 1;
+            registry.entries.insert(
+                id,
+                SessionEntry {
+                    info: SessionInfo {
+                        id,
+                        started_at,
+                        write,
+                        query: None,
+                        principal: None,
+                        role: None,
+                    },
+                    killed: false,
+                    running: vec![],
+                    queried: false,
+                },
+            );
+            id
+        };
+        let refused = if self.closed.load(Ordering::SeqCst) {
+            Some(DbClosed.into())
+        } else if write && self.writes_frozen.load(Ordering::SeqCst) {
+            Some(WritesFrozen.into())
+        } else {
+            None
+        };
+        if let Some(err) = refused {
+            self.leave(id);
+            return Err(err);
+        }
+        Ok(SessionGuard {
+            sessions: self,
+            id,
+            metrics,
+            committed: false,
+            principal: None,
+            role: None,
+            quota: None,
+            permit: None,
+        })
+    }
+    /// Unregister session `id`, notifying the callers waiting for sessions to finish.
+    fn leave(&self, id: u64) {
+        self.registry.lock(stripe_of(id)).entries.remove(&id);
+        if self.waiting.load(Ordering::SeqCst) > 0 {
+            let _released = self.released.lock().unwrap();
+            self.drained.notify_all();
+        }
+    }
+    /// Wait until no session satisfies `busy`, or `timeout` has passed, returning the
+    /// number of sessions still busy.
+    fn wait_drained(
+        &self,
+        timeout: Duration,
+        busy: impl Fn(&SessionEntry) -> bool,
+    ) -> (MutexGuard<'_, bool>, usize) {
+        let count_busy = || {
+            let mut n = 0;
+            self.registry
+                .for_each(|stripe| n += stripe.entries.values().filter(|e| busy(e)).count());
+            n
+        };
+        self.waiting.fetch_add(1, Ordering::SeqCst);
+        let released = self.released.lock().unwrap();
+        let (released, _) = self
+            .drained
+            .wait_timeout_while(released, timeout, |_| count_busy() > 0)
+            .unwrap();
+        let n_busy = count_busy();
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+        (released, n_busy)
+    }
+    pub(crate) fn ensure_open(&self) -> Result<()> {
+        ensure!(!self.closed.load(Ordering::SeqCst), DbClosed);
+        Ok(())
+    }
+    fn list(&self) -> Vec<SessionInfo> {
+        let mut ret = vec![];
+        self.registry
+            .for_each(|stripe| ret.extend(stripe.entries.values().map(|e| e.info.clone())));
+        ret.sort_by_key(|info| info.id);
+        ret
+    }
+    fn kill(&self, id: u64) -> bool {
+        let mut registry = self.registry.lock(stripe_of(id));
+        match registry.entries.get_mut(&id) {
+            None => false,
+            Some(entry) => {
+                entry.killed = true;
+                for poison in &entry.running {
+                    poison.0.store(true, Ordering::Relaxed);
+                }
+                true
+            }
+        }
+    }
+    /// Refuse new write sessions and wait for the active ones to finish.
+    fn freeze_writes(&self, timeout: Duration) -> Result<()> {
+        self.writes_frozen.store(true, Ordering::SeqCst);
+        let (released, writing) = self.wait_drained(timeout, |e| e.info.write);
+        drop(released);
+        if writing > 0 {
+            self.writes_frozen.store(false, Ordering::SeqCst);
+            bail!(FreezeTimedOut(writing))
+        }
+        Ok(())
+    }
+    /// Returns `false` if writes were not frozen.
+    fn unfreeze_writes(&self) -> bool {
+        self.writes_frozen.swap(false, Ordering::SeqCst)
+    }
+    /// Refuse new sessions and wait for the active ones to finish, first those of the
+    /// namespaces, each for up to `timeout`. Returns whether the caller is the first to
+    /// see all sessions finished, and must release the storage.
+    fn close(&self, timeout: Duration) -> Result<bool> {
+        self.closed.store(true, Ordering::SeqCst);
+        // the storage is shared with the namespaces, whose sessions must finish as well
+        let namespaces = self.namespaces.lock().unwrap().clone();
+        for ns in namespaces.iter().filter_map(Weak::upgrade) {
+            ns.close(timeout)?;
+        }
+        let (mut released, open) = self.wait_drained(timeout, |_| true);
+        ensure!(open == 0, CloseTimedOut(open));
+        Ok(!std::mem::replace(&mut *released, true))
+    }
+}
+
+/// Held by every [`SessionTx`] for as long as it is open, keeping it in the registry.
+pub(crate) struct SessionGuard<'a> {
+    pub(crate) sessions: &'a Sessions,
+    pub(crate) id: u64,
+    pub(crate) metrics: &'a MetricsRegistry,
+    pub(crate) committed: bool,
+    /// Name of the principal of the last script run in the session
+    pub(crate) principal: Option<String>,
+    /// Role of the principal of the last script run in the session
+    pub(crate) role: Option<String>,
+    /// Quota of the principal of the last script run in the session
+    pub(crate) quota: Option<Arc<QuotaUsage>>,
+    permit: Option<QueryPermit>,
+}
+
+impl SessionGuard<'_> {
+    pub(crate) fn eval_guard(&self) -> &EvalGuard {
+        &self.sessions.eval_guard
+    }
+    fn with_entry<T>(&self, f: impl FnOnce(&mut SessionEntry) -> T) -> T {
+        let mut registry = self.sessions.registry.lock(stripe_of(self.id));
+        f(registry.entries.get_mut(&self.id).unwrap())
+    }
+    pub(crate) fn start_query(
+        &mut self,
+        query: &str,
+        principal: Option<&Principal>,
+    ) -> Result<()> {
+        self.permit = None;
+        self.quota = principal.and_then(|p| self.sessions.quotas.usage(&p.name));
+        if let Some(quota) = &self.quota {
+            self.permit = Some(quota.begin_query()?);
+        }
+        self.role = principal.and_then(|p| p.role.clone());
+        let principal = principal.map(|p| p.name.clone());
+        self.principal = principal.clone();
+        self.with_entry(|e| {
+            e.info.query = Some(query.to_string());
+            e.info.principal = principal.clone();
+            e.info.role = self.role.clone();
+            e.queried = true;
+        });
+        if self.sessions.audit.is_enabled() {
+            self.sessions.audit.log(AuditEntry {
+                timestamp: seconds_since_the_epoch().unwrap_or_default(),
+                session_id: self.id,
+                principal,
+                event: AuditEvent::Query(query.to_string()),
+            });
+        }
+        Ok(())
+    }
+    pub(crate) fn end_query(&mut self) {
+        self.permit = None;
+        self.with_entry(|e| e.info.query = None)
+    }
+    /// Make `poison` follow the session, so that killing the session kills the query.
+    pub(crate) fn attach(&self, poison: &Poison) -> Result<()> {
+        self.with_entry(|e| {
+            ensure!(!e.killed, SessionKilled);
+            // finished queries have their poison set
+            e.running.retain(|p| !p.0.load(Ordering::Relaxed));
+            e.running.push(poison.clone());
+            Ok(())
+        })
+    }
+    pub(crate) fn ensure_alive(&self) -> Result<()> {
+        self.with_entry(|e| {
+            ensure!(!e.killed, SessionKilled);
+            Ok(())
+        })
+    }
+}
+
+impl Drop for SessionGuard<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.metrics.transactions_aborted.add(1);
+        }
+        if self.sessions.audit.is_enabled() {
+            if let Some(principal) =
+                self.with_entry(|e| e.queried.then(|| e.info.principal.clone()))
+            {
+                self.sessions.audit.log(AuditEntry {
+                    timestamp: seconds_since_the_epoch().unwrap_or_default(),
+                    session_id: self.id,
+                    principal,
+                    event: if self.committed {
+                        AuditEvent::Commit
+                    } else {
+                        AuditEvent::Abort
+                    },
+                });
+            }
+        }
+        // logged before leaving, as closing stops the audit log once sessions are done
+        self.sessions.leave(self.id);
+    }
+}
+
 #[derive(serde_derive::Serialize, serde_derive::Deserialize)]
 pub struct DbManifest {
     pub storage_version: u64,
@@ -98,15 +536,42 @@ pub struct Db<S> {
     pub(crate) db: S,
     temp_db: TempStorage,
     relation_store_id: Arc<AtomicU64>,
-    pub(crate) queries_count: Arc<AtomicU64>,
-    pub(crate) running_queries: Arc<Mutex<BTreeMap<u64, RunningQueryHandle>>>,
+    pub(crate) running_queries: Arc<RunningQueries>,
     pub(crate) fixed_rules: Arc<ShardedLock<BTreeMap<String, Arc<Box<dyn FixedRule>>>>>,
+    /// The names of the fixed rules registered by [`Db::register_wasm_plugin`]
+    #[cfg(feature = "wasm-plugins")]
+    pub(crate) wasm_plugins: Arc<Mutex<BTreeSet<String>>>,
     pub(crate) tokenizers: Arc<TokenizerCache>,
     #[cfg(not(target_arch = "wasm32"))]
     callback_count: Arc<AtomicU32>,
     #[cfg(not(target_arch = "wasm32"))]
     pub(crate) event_callbacks: Arc<ShardedLock<EventCallbackRegistry>>,
     relation_locks: Arc<ShardedLock<BTreeMap<SmartString<LazyCompact>, Arc<ShardedLock<()>>>>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    expiry_sweeper: Arc<Mutex<Option<Sender<()>>>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    compaction_schedule: Arc<Mutex<Option<Sender<()>>>>,
+    pub(crate) sessions: Arc<Sessions>,
+    pub(crate) metrics: Arc<MetricsRegistry>,
+    slow_queries: Arc<SlowQueryLog>,
+    pub(crate) tx_log: Arc<TxLog>,
+    pub(crate) string_literals_forbidden: Arc<AtomicBool>,
+    pub(crate) attached: Arc<Mutex<BTreeSet<String>>>,
+    pub(crate) analysis: Arc<Mutex<BTreeMap<String, RelationAnalysis>>>,
+    pub(crate) schema_cache: Arc<SchemaCache>,
+    pub(crate) jobs: Arc<JobRegistry>,
+    /// The queries of the constraints, by name
+    pub(crate) constraints: Arc<ShardedLock<BTreeMap<String, String>>>,
+    pub(crate) procedures: Arc<ShardedLock<BTreeMap<String, Procedure>>>,
+    pub(crate) plan_limits: Arc<ShardedLock<PlanLimits>>,
+    pub(crate) replica: Arc<Replica>,
+    #[cfg(feature = "async")]
+    pub(crate) write_queue: Arc<Mutex<Option<WriteQueue>>>,
+    #[cfg(feature = "scheduler")]
+    pub(crate) schedules: Arc<Schedules>,
+    /// Shared with the `FetchJson` fixed rule
+    #[cfg(feature = "fetch")]
+    fetch_config: Arc<ShardedLock<FetchConfig>>,
 }
 
 impl<S> Debug for Db<S> {
@@ -120,12 +585,181 @@ impl<S> Debug for Db<S> {
 #[diagnostic(code(db::init))]
 pub(crate) struct BadDbInit(#[help] pub(crate) String);
 
+/// The condition of the rows of the relation of `handle` that have expired at `$now`.
+/// Rows with a non-numeric expiry never expire.
+pub(crate) fn expiry_condition(handle: &RelationHandle) -> String {
+    let col = handle.expiry_column.as_ref().unwrap();
+    format!("if(is_num({col}), {col} <= $now, false)")
+}
+
+/// How many transactions a subscription of [`Db::subscribe_from`] reads ahead.
+const SUBSCRIPTION_BUFFER: usize = 64;
+
+/// How the relation ids are used, returned by [`Db::id_allocation_report`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdAllocationReport {
+    /// The persisted id counter: the highest id handed out by a committed transaction
+    pub counter: u64,
+    /// The highest id handed out since the database was opened. Ids above `counter`
+    /// belong to transactions that are still running or were aborted.
+    pub allocated: u64,
+    /// Number of stored relations and indices, each using one id
+    pub used: usize,
+    /// Ranges of ids up to `counter` that no relation uses
+    pub gaps: Vec<RangeInclusive<u64>>,
+}
+
+/// Storage statistics returned by [`Db::storage_stats`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct StorageStats {
+    /// Number of keys in the database, including metadata
+    pub total_keys: usize,
+    /// Estimated size of the database in bytes, if the engine supports estimation
+    pub approximate_size: Option<u64>,
+    /// Statistics for every stored relation and index
+    pub relations: Vec<RelationStats>,
+    /// Engine-specific reports, such as the per-level statistics of RocksDB
+    pub engine: BTreeMap<String, String>,
+}
+
+/// The most rows held by a single line of the dump written by [`Db::snapshot_export`].
+pub const SNAPSHOT_BATCH_ROWS: usize = 1024;
+
+/// Summary of a dump written by [`Db::snapshot_export`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SnapshotExport {
+    /// Names of the exported relations
+    pub relations: Vec<String>,
+    /// Number of exported rows, over all relations
+    pub rows: usize,
+    /// Names of the hidden relations, which are not exported
+    pub skipped: Vec<String>,
+}
+
+/// Statistics of a single stored relation or index, see [`StorageStats`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelationStats {
+    /// Name of the relation. Indices are named `relation:index`.
+    pub name: String,
+    /// Number of rows
+    pub rows: usize,
+    /// Estimated size in bytes, if the engine supports estimation
+    pub approximate_size: Option<u64>,
+}
+
+/// Result of [`Db::verify_integrity`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct IntegrityReport {
+    /// Number of stored relations and indices examined
+    pub relations_checked: usize,
+    /// Number of rows of relations and indices examined
+    pub rows_checked: usize,
+    /// Every inconsistency found, empty if the database is sound
+    pub findings: Vec<IntegrityFinding>,
+}
+
+impl IntegrityReport {
+    /// Whether no inconsistency was found.
+    pub fn is_ok(&self) -> bool {
+        self.findings.is_empty()
+    }
+}
+
+/// A single inconsistency reported by [`Db::verify_integrity`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum IntegrityFinding {
+    /// A row of `relation` has no corresponding entry in `index`
+    MissingIndexEntry {
+        /// The base relation
+        relation: String,
+        /// The index, without the relation prefix
+        index: String,
+        /// Keys of the row
+        key: Vec<DataValue>,
+    },
+    /// An entry of `index` does not match any row of `relation`
+    DanglingIndexEntry {
+        /// The base relation
+        relation: String,
+        /// The index, without the relation prefix
+        index: String,
+        /// The index entry
+        entry: Vec<DataValue>,
+    },
+    /// A relation uses an id not yet handed out by the persisted id counter,
+    /// so a relation created later could be given the same id
+    IdAboveCounter {
+        /// The relation or index
+        relation: String,
+        /// Its id
+        id: u64,
+        /// The persisted id counter
+        counter: u64,
+    },
+    /// Keys stored under relation ids not belonging to any relation,
+    /// e.g. leftovers of an interrupted removal
+    OrphanedKeys {
+        /// The smallest such id
+        first_id: u64,
+        /// The largest such id
+        last_id: u64,
+        /// Number of keys found for these ids
+        count: usize,
+    },
+}
+
+/// What [`Db::repair`] should fix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepairMode {
+    /// Fix what [`Db::verify_integrity`] reports: rebuild the inconsistent indices,
+    /// remove orphaned keys and advance the id counter
+    Findings,
+    /// Also rebuild every other index, including the full-text, LSH and HNSW indices
+    /// that the verification does not cover
+    RebuildAll,
+}
+
+/// Result of [`Db::repair`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    /// Indices rebuilt, named `relation:index`
+    pub rebuilt_indices: Vec<String>,
+    /// Number of orphaned keys removed
+    pub removed_keys: usize,
+    /// The new persisted id counter, if it had to be advanced
+    pub new_id_counter: Option<u64>,
+}
+
+/// Progress of [`Db::compact_relations`], reported after every compacted relation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactionProgress {
+    /// The relation or index just compacted
+    pub relation: String,
+    /// Number of relations and indices compacted so far
+    pub done: usize,
+    /// Number of relations and indices to compact
+    pub total: usize,
+}
+
+/// The outcome of a write run by [`Db::with_tx_speculative`] and then discarded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TxPreview {
+    /// Rows returned by the script
+    pub rows: NamedRows,
+    /// Rows the script wrote to stored relations
+    pub rows_written: u64,
+    /// Rows returned by each validation query, in order
+    pub validations: Vec<NamedRows>,
+}
+
 #[derive(Debug, Error, Diagnostic)]
 #[error("Cannot import data into relation {0} as it is an index")]
 #[diagnostic(code(tx::import_into_index))]
 pub(crate) struct ImportIntoIndex(pub(crate) String);
 
-#[derive(serde_derive::Serialize, serde_derive::Deserialize, Debug, Clone, Default)]
+#[derive(
+    serde_derive::Serialize, serde_derive::Deserialize, Debug, Clone, Default, PartialEq, Eq,
+)]
 /// Rows in a relation, together with headers for the fields.
 pub struct NamedRows {
     /// The headers
@@ -236,6 +870,9 @@ pub enum TransactionPayload {
     Abort,
     /// Run a query inside the transaction
     Query((String, BTreeMap<String, DataValue>)),
+    /// Create a temporary relation holding the rows, see
+    /// [`MultiTransaction::create_temp_rel`](crate::MultiTransaction::create_temp_rel)
+    CreateTempRel((String, NamedRows)),
 }
 
 impl<'s, S: Storage<'s>> Db<S> {
@@ -243,13 +880,26 @@ impl<'s, S: Storage<'s>> Db<S> {
     /// You must call [`initialize`](Self::initialize) immediately after creation.
     /// Due to lifetime restrictions we are not able to call that for you automatically.
     pub fn new(storage: S) -> Result<Self> {
+        #[allow(unused_mut)]
+        let mut fixed_rules = DEFAULT_FIXED_RULES.clone();
+        #[cfg(feature = "fetch")]
+        let fetch_config: Arc<ShardedLock<FetchConfig>> = Default::default();
+        #[cfg(feature = "fetch")]
+        fixed_rules.insert(
+            "FetchJson".to_string(),
+            Arc::new(Box::new(FetchJson {
+                config: fetch_config.clone(),
+            })),
+        );
         let ret = Self {
+            schema_cache: Arc::new(SchemaCache::new(storage.storage_kind())),
             db: storage,
             temp_db: Default::default(),
             relation_store_id: Default::default(),
-            queries_count: Default::default(),
             running_queries: Default::default(),
-            fixed_rules: Arc::new(ShardedLock::new(DEFAULT_FIXED_RULES.clone())),
+            fixed_rules: Arc::new(ShardedLock::new(fixed_rules)),
+            #[cfg(feature = "wasm-plugins")]
+            wasm_plugins: Default::default(),
             tokenizers: Arc::new(Default::default()),
             #[cfg(not(target_arch = "wasm32"))]
             callback_count: Default::default(),
@@ -257,6 +907,28 @@ impl<'s, S: Storage<'s>> Db<S> {
             #[cfg(not(target_arch = "wasm32"))]
             event_callbacks: Default::default(),
             relation_locks: Default::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            expiry_sweeper: Default::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            compaction_schedule: Default::default(),
+            sessions: Default::default(),
+            metrics: Default::default(),
+            slow_queries: Default::default(),
+            tx_log: Default::default(),
+            string_literals_forbidden: Default::default(),
+            attached: Default::default(),
+            analysis: Default::default(),
+            jobs: Default::default(),
+            constraints: Default::default(),
+            procedures: Default::default(),
+            plan_limits: Default::default(),
+            replica: Default::default(),
+            #[cfg(feature = "async")]
+            write_queue: Default::default(),
+            #[cfg(feature = "scheduler")]
+            schedules: Default::default(),
+            #[cfg(feature = "fetch")]
+            fetch_config,
         };
         Ok(ret)
     }
@@ -264,6 +936,8 @@ impl<'s, S: Storage<'s>> Db<S> {
     /// Must be called after creation of the database to initialize the runtime state.
     pub fn initialize(&'s self) -> Result<()> {
         self.load_last_ids()?;
+        self.load_replica_id()?;
+        self.recover_chunked_imports()?;
         Ok(())
     }
 
@@ -279,9 +953,49 @@ impl<'s, S: Storage<'s>> Db<S> {
         is_write: bool,
         payloads: Receiver<TransactionPayload>,
         results: Sender<Result<NamedRows>>,
+    ) {
+        self.do_run_multi_transaction(None, is_write, TxDurability::Default, payloads, results)
+    }
+
+    /// Run a multi-transaction on behalf of `principal`, see [`run_script_as`](Self::run_script_as)
+    /// and [`run_multi_transaction`](Self::run_multi_transaction).
+    pub fn run_multi_transaction_as(
+        &'s self,
+        principal: &Principal,
+        is_write: bool,
+        payloads: Receiver<TransactionPayload>,
+        results: Sender<Result<NamedRows>>,
+    ) {
+        self.do_run_multi_transaction(
+            Some(principal),
+            is_write,
+            TxDurability::Default,
+            payloads,
+            results,
+        )
+    }
+
+    /// Run a write multi-transaction whose commit has the given `durability`, see
+    /// [`TxDurability`] and [`run_multi_transaction`](Self::run_multi_transaction).
+    pub fn run_multi_transaction_with_durability(
+        &'s self,
+        durability: TxDurability,
+        payloads: Receiver<TransactionPayload>,
+        results: Sender<Result<NamedRows>>,
+    ) {
+        self.do_run_multi_transaction(None, true, durability, payloads, results)
+    }
+
+    fn do_run_multi_transaction(
+        &'s self,
+        principal: Option<&Principal>,
+        is_write: bool,
+        durability: TxDurability,
+        payloads: Receiver<TransactionPayload>,
+        results: Sender<Result<NamedRows>>,
     ) {
         let tx = if is_write {
-            self.transact_write()
+            self.transact_write_with(durability)
         } else {
             self.transact()
         };
@@ -308,7 +1022,10 @@ impl<'s, S: Storage<'s>> Db<S> {
                         }
                     }
 
-                    let _ = results.send(tx.commit_tx().map(|_| NamedRows::default()));
+                    let committed = tx.commit_tx();
+                    // the session ends before the caller hears of the commit
+                    drop(tx);
+                    let _ = results.send(committed.map(|_| NamedRows::default()));
                     #[cfg(not(target_arch = "wasm32"))]
                     if !callback_collector.is_empty() {
                         self.send_callbacks(callback_collector)
@@ -317,16 +1034,33 @@ impl<'s, S: Storage<'s>> Db<S> {
                     break;
                 }
                 TransactionPayload::Abort => {
+                    drop(tx);
                     let _ = results.send(Ok(NamedRows::default()));
                     break;
                 }
+                TransactionPayload::CreateTempRel((name, data)) => {
+                    let res = tx.create_temp_rel(&name, data).map(|_| {
+                        NamedRows::new(
+                            vec![STATUS_STR.to_string()],
+                            vec![vec![DataValue::from(OK_STR)]],
+                        )
+                    });
+                    if results.send(res).is_err() {
+                        break;
+                    }
+                }
                 TransactionPayload::Query((script, params)) => {
                     let p =
-                        match parse_script(&script, &params, &self.fixed_rules.read().unwrap(), ts)
-                        {
+                        match parse_script(
+                            &script,
+                            &params,
+                            &self.fixed_rules.read().unwrap(),
+                            ts,
+                            self.string_literals_forbidden.load(Ordering::Relaxed),
+                        ) {
                             Ok(p) => p,
                             Err(err) => {
-                                if results.send(Err(err)).is_err() {
+                                if results.send(Err(with_script_source(err, &script))).is_err() {
                                     break;
                                 } else {
                                     continue;
@@ -357,14 +1091,20 @@ impl<'s, S: Storage<'s>> Db<S> {
                         }
                     }
 
-                    let res = self.execute_single_program(
-                        p,
-                        &mut tx,
-                        &mut cleanups,
-                        ts,
-                        &callback_targets,
-                        &mut callback_collector,
-                    );
+                    let started_at = seconds_since_the_epoch().unwrap_or_default();
+                    let res = tx.session.start_query(&script, principal).and_then(|_| {
+                        self.execute_single_program(
+                            p,
+                            &mut tx,
+                            &mut cleanups,
+                            ts,
+                            &callback_targets,
+                            &mut callback_collector,
+                        )
+                    });
+                    tx.session.end_query();
+                    self.observe_query(&script, &params, started_at, &res);
+                    let res = res.map_err(|err| with_script_source(err, &script));
                     if results.send(res).is_err() {
                         break;
                     }
@@ -383,11 +1123,95 @@ impl<'s, S: Storage<'s>> Db<S> {
         let cur_vld = current_validity();
         self.do_run_script(
             payload,
+            None,
             &params,
             cur_vld,
             mutability == ScriptMutability::Immutable,
+            TxDurability::Default,
         )
     }
+    /// Run the CozoScript passed in on behalf of `principal`, an identity supplied by the
+    /// embedder that is shown by [`list_sessions`](Self::list_sessions) and recorded in
+    /// the audit log.
+    pub fn run_script_as(
+        &'s self,
+        principal: &Principal,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Result<NamedRows> {
+        let cur_vld = current_validity();
+        self.do_run_script(
+            payload,
+            Some(principal),
+            &params,
+            cur_vld,
+            mutability == ScriptMutability::Immutable,
+            TxDurability::Default,
+        )
+    }
+    /// Run the CozoScript passed in as a mutable script whose write transactions commit with
+    /// the given `durability`, see [`TxDurability`].
+    pub fn run_script_with_durability(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        durability: TxDurability,
+    ) -> Result<NamedRows> {
+        let cur_vld = current_validity();
+        self.do_run_script(payload, None, &params, cur_vld, false, durability)
+    }
+    /// Run the single query `payload` as a write carrying the idempotency key `key`, such
+    /// as the id of the message it comes from. The rows it returns are recorded under the
+    /// key in the same transaction, and running a write with the same key again returns
+    /// them without running the query, so that a write delivered more than once is only
+    /// applied once. The records are kept in the storage, and in the transaction log if
+    /// it is enabled, until removed by
+    /// [`forget_idempotency_keys`](Self::forget_idempotency_keys).
+    pub fn run_script_idempotent(
+        &'s self,
+        key: &str,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<NamedRows> {
+        let cur_vld = current_validity();
+        let started_at = seconds_since_the_epoch()?;
+        let res = parse_script(
+            payload,
+            &params,
+            &self.fixed_rules.read().unwrap(),
+            cur_vld,
+            self.string_literals_forbidden.load(Ordering::Relaxed),
+        )
+        .and_then(|script| script.get_single_program())
+        .and_then(|p| {
+            let durability = TxDurability::Default;
+            self.execute_single(payload, None, cur_vld, p, false, durability, Some(key))
+        });
+        self.observe_query(payload, &params, started_at, &res);
+        res.map_err(|err| with_script_source(err, payload))
+    }
+    /// Remove the records of the idempotency keys of writes run by
+    /// [`run_script_idempotent`](Self::run_script_idempotent) before `before`, in seconds
+    /// since the epoch, returning how many were removed. Writes with those keys are
+    /// applied again if they come back.
+    pub fn forget_idempotency_keys(&'s self, before: f64) -> Result<usize> {
+        let mut tx = self.transact_write()?;
+        let (lower, upper) = idempotency_record_bounds();
+        let mut expired = vec![];
+        for kv in tx.store_tx.range_scan(&lower, &upper) {
+            let (k, v) = kv?;
+            let (at, _): (f64, NamedRows) = rmp_serde::from_slice(&v).into_diagnostic()?;
+            if at < before {
+                expired.push(k);
+            }
+        }
+        for k in &expired {
+            tx.store_tx.del(k)?;
+        }
+        tx.commit_tx()?;
+        Ok(expired.len())
+    }
     /// Run the CozoScript passed in. The `params` argument is a map of parameters.
     pub fn run_script_read_only(
         &'s self,
@@ -395,7 +1219,133 @@ impl<'s, S: Storage<'s>> Db<S> {
         params: BTreeMap<String, DataValue>,
     ) -> Result<NamedRows> {
         let cur_vld = current_validity();
-        self.do_run_script(payload, &params, cur_vld, true)
+        self.do_run_script(payload, None, &params, cur_vld, true, TxDurability::Default)
+    }
+    /// Run the CozoScript passed in, with each of `inputs` bound as a constant rule named
+    /// by its key, as if the query contained `name[headers...] <- rows`. Data held by
+    /// the client, such as a list of ids, can so be joined with stored relations without
+    /// being written anywhere first. Only single queries take inputs, and the names must
+    /// differ from those of the rules of the query.
+    pub fn run_script_with_inputs(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        inputs: BTreeMap<String, NamedRows>,
+        mutability: ScriptMutability,
+    ) -> Result<NamedRows> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Input relations can only be bound in single queries")]
+        #[diagnostic(code(eval::inputs_need_single_query))]
+        struct InputsNeedSingleQuery;
+
+        let cur_vld = current_validity();
+        let started_at = seconds_since_the_epoch()?;
+        let res = parse_script(
+            payload,
+            &params,
+            &self.fixed_rules.read().unwrap(),
+            cur_vld,
+            self.string_literals_forbidden.load(Ordering::Relaxed),
+        )
+        .and_then(|script| {
+            let CozoScript::Single(mut p) = script else {
+                bail!(InputsNeedSingleQuery)
+            };
+            for (name, data) in inputs {
+                p.bind_input(&name, data)?;
+            }
+            let read_only = mutability == ScriptMutability::Immutable;
+            self.execute_single(
+                payload,
+                None,
+                cur_vld,
+                p,
+                read_only,
+                TxDurability::Default,
+                None,
+            )
+        });
+        self.observe_query(payload, &params, started_at, &res);
+        res.map_err(|err| with_script_source(err, payload))
+    }
+
+    /// Run a query built with the [`ast`](crate::ast) module, rendered to CozoScript by
+    /// [`Query::to_script`](crate::ast::Query::to_script).
+    pub fn run_ast(
+        &'s self,
+        query: &crate::ast::Query,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Result<NamedRows> {
+        self.run_script(&query.to_script()?, params, mutability)
+    }
+    /// Run the single query `payload` in a write transaction, then run each of the
+    /// `validations` queries in the same transaction, seeing the state the write would
+    /// leave, and roll the transaction back. Nothing is committed, callbacks are not
+    /// notified, and the write is not logged. Fails as the commit would if the write
+    /// violates a constraint registered with [`register_constraint`](Self::register_constraint).
+    ///
+    /// The write locks of the relations written are held until the transaction is
+    /// rolled back, so other writes to them wait as for a committing write.
+    pub fn with_tx_speculative(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        validations: &[&str],
+    ) -> Result<TxPreview> {
+        let cur_vld = current_validity();
+        let parse = |script: &str| {
+            parse_script(
+                script,
+                &params,
+                &self.fixed_rules.read().unwrap(),
+                cur_vld,
+                self.string_literals_forbidden.load(Ordering::Relaxed),
+            )
+            .and_then(|p| p.get_single_program())
+            .map_err(|err| with_script_source(err, script))
+        };
+        let p = parse(payload)?;
+        let write_lock_names = p.needs_write_lock();
+        let write_lock = self.obtain_relation_locks(write_lock_names.iter());
+        let _write_lock_guards = write_lock.iter().map(|l| l.read().unwrap()).collect_vec();
+        let mut tx = self.transact_write()?;
+        let run = |tx: &mut SessionTx<'_>, script: &str, p: InputProgram| {
+            tx.session.start_query(script, None)?;
+            self.execute_single_program(
+                p,
+                tx,
+                &mut vec![],
+                cur_vld,
+                &Default::default(),
+                &mut Default::default(),
+            )
+            .map_err(|err| with_script_source(err, script))
+        };
+        let rows = run(&mut tx, payload, p)?;
+        let rows_written = tx.rows_written;
+        self.check_constraints(&mut tx, cur_vld)?;
+        let mut ret = vec![];
+        for script in validations {
+            let p = parse(script)?;
+            ret.push(run(&mut tx, script, p)?);
+        }
+        // dropping the transaction rolls it back
+        Ok(TxPreview {
+            rows,
+            rows_written,
+            validations: ret,
+        })
+    }
+    /// Run the CozoScript passed in as a read-only query and deserialize the rows of the
+    /// result, see [`NamedRows::deserialize_rows`].
+    pub fn run_query_as<T: DeserializeOwned>(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<Vec<T>> {
+        self.run_script_read_only(payload, params)?
+            .deserialize_rows()
     }
 
     /// Export relations to JSON data.
@@ -457,11 +1407,6 @@ impl<'s, S: Storage<'s>> Db<S> {
     /// Note that triggers and callbacks are _not_ run for the relations, if any exists.
     /// If you need to activate triggers or callbacks, use queries with parameters.
     pub fn import_relations(&'s self, data: BTreeMap<String, NamedRows>) -> Result<()> {
-        #[derive(Debug, Diagnostic, Error)]
-        #[error("cannot import data for relation '{0}': {1}")]
-        #[diagnostic(code(import::bad_data))]
-        struct BadDataForRelation(String, JsonValue);
-
         let rel_names = data.keys().map(SmartString::from).collect_vec();
         let locks = self.obtain_relation_locks(rel_names.iter());
         let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
@@ -471,41 +1416,89 @@ impl<'s, S: Storage<'s>> Db<S> {
         let mut tx = self.transact_write()?;
 
         for (relation_op, in_data) in data {
-            let is_delete;
-            let relation: &str = match relation_op.strip_prefix('-') {
-                None => {
-                    is_delete = false;
-                    &relation_op
-                }
-                Some(s) => {
-                    is_delete = true;
-                    s
-                }
-            };
-            if relation.contains(':') {
-                bail!(ImportIntoIndex(relation.to_string()))
-            }
-            let handle = tx.get_relation(relation, false)?;
-            let has_indices = !handle.indices.is_empty();
+            self.import_rows(
+                &mut tx,
+                &relation_op,
+                &in_data.headers,
+                &in_data.rows,
+                cur_vld,
+                &ImportJournal::default(),
+            )?;
+        }
+        tx.commit_tx()?;
+        Ok(())
+    }
+    /// Write `rows` for `relation_op`, the name of a relation prefixed by `-` for
+    /// removing the rows, as [`import_relations`](Self::import_relations) does.
+    pub(crate) fn import_rows(
+        &'s self,
+        tx: &mut SessionTx<'_>,
+        relation_op: &str,
+        headers: &[String],
+        rows: &[Tuple],
+        cur_vld: ValidityTs,
+        journal: &ImportJournal,
+    ) -> Result<()> {
+        #[derive(Debug, Diagnostic, Error)]
+        #[error("cannot import data for relation '{0}': {1}")]
+        #[diagnostic(code(import::bad_data))]
+        struct BadDataForRelation(String, JsonValue);
 
-            if handle.access_level < AccessLevel::Protected {
-                bail!(InsufficientAccessLevel(
-                    handle.name.to_string(),
-                    "data import".to_string(),
-                    handle.access_level
-                ));
+        let is_delete;
+        let relation: &str = match relation_op.strip_prefix('-') {
+            None => {
+                is_delete = false;
+                relation_op
+            }
+            Some(s) => {
+                is_delete = true;
+                s
             }
+        };
+        if relation.contains(':') {
+            bail!(ImportIntoIndex(relation.to_string()))
+        }
+        let handle = tx.get_relation(relation, false)?;
+        let has_indices = !handle.indices.is_empty();
 
-            let header2idx: BTreeMap<_, _> = in_data
-                .headers
-                .iter()
-                .enumerate()
-                .map(|(i, k)| -> Result<(&str, usize)> { Ok((k as &str, i)) })
-                .try_collect()?;
+        if handle.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "data import".to_string(),
+                handle.access_level
+            ));
+        }
+
+        let merged = merged_columns(&handle.metadata);
+
+        let header2idx: BTreeMap<_, _> = headers
+            .iter()
+            .enumerate()
+            .map(|(i, k)| -> Result<(&str, usize)> { Ok((k as &str, i)) })
+            .try_collect()?;
+
+        let key_indices: Vec<_> = handle
+            .metadata
+            .keys
+            .iter()
+            .map(|col| -> Result<(usize, &ColumnDef)> {
+                let idx = header2idx.get(&col.name as &str).ok_or_else(|| {
+                    miette!(
+                        "required header {} not found for relation {}",
+                        col.name,
+                        relation
+                    )
+                })?;
+                Ok((*idx, col))
+            })
+            .try_collect()?;
 
-            let key_indices: Vec<_> = handle
+        let val_indices: Vec<_> = if is_delete {
+            vec![]
+        } else {
+            handle
                 .metadata
-                .keys
+                .non_keys
                 .iter()
                 .map(|col| -> Result<(usize, &ColumnDef)> {
                     let idx = header2idx.get(&col.name as &str).ok_or_else(|| {
@@ -517,30 +1510,38 @@ impl<'s, S: Storage<'s>> Db<S> {
                     })?;
                     Ok((*idx, col))
                 })
-                .try_collect()?;
+                .try_collect()?
+        };
 
-            let val_indices: Vec<_> = if is_delete {
-                vec![]
+        for row in rows {
+            let keys: Vec<_> = key_indices
+                .iter()
+                .map(|(i, col)| -> Result<DataValue> {
+                    let v = row
+                        .get(*i)
+                        .ok_or_else(|| miette!("row too short: {:?}", row))?;
+                    col.typing.coerce(v.clone(), cur_vld)
+                })
+                .try_collect()?;
+            let k_store = handle.encode_key_for_store(&keys, Default::default())?;
+            if has_indices {
+                if let Some(existing) = tx.store_tx.get(&k_store, false)? {
+                    let mut old = keys.clone();
+                    extend_tuple_from_v(&mut old, &existing);
+                    if is_delete || old != *row {
+                        for (idx_rel, extractor) in handle.indices.values() {
+                            let idx_tup = extractor.iter().map(|i| old[*i].clone()).collect_vec();
+                            let encoded =
+                                idx_rel.encode_key_for_store(&idx_tup, Default::default())?;
+                            journal.del(tx, &encoded)?;
+                        }
+                    }
+                }
+            }
+            if is_delete {
+                journal.del(tx, &k_store)?;
             } else {
-                handle
-                    .metadata
-                    .non_keys
-                    .iter()
-                    .map(|col| -> Result<(usize, &ColumnDef)> {
-                        let idx = header2idx.get(&col.name as &str).ok_or_else(|| {
-                            miette!(
-                                "required header {} not found for relation {}",
-                                col.name,
-                                relation
-                            )
-                        })?;
-                        Ok((*idx, col))
-                    })
-                    .try_collect()?
-            };
-
-            for row in in_data.rows {
-                let keys: Vec<_> = key_indices
+                let mut vals: Vec<_> = val_indices
                     .iter()
                     .map(|(i, col)| -> Result<DataValue> {
                         let v = row
@@ -549,50 +1550,32 @@ impl<'s, S: Storage<'s>> Db<S> {
                         col.typing.coerce(v.clone(), cur_vld)
                     })
                     .try_collect()?;
-                let k_store = handle.encode_key_for_store(&keys, Default::default())?;
-                if has_indices {
-                    if let Some(existing) = tx.store_tx.get(&k_store, false)? {
+                if !merged.is_empty() {
+                    // imported counters and registers are merged like written ones
+                    let old = tx.store_tx.get(&k_store, false)?.map(|existing| {
                         let mut old = keys.clone();
                         extend_tuple_from_v(&mut old, &existing);
-                        if is_delete || old != row {
-                            for (idx_rel, extractor) in handle.indices.values() {
-                                let idx_tup =
-                                    extractor.iter().map(|i| old[*i].clone()).collect_vec();
-                                let encoded =
-                                    idx_rel.encode_key_for_store(&idx_tup, Default::default())?;
-                                tx.store_tx.del(&encoded)?;
-                            }
-                        }
-                    }
+                        old
+                    });
+                    let mut kv = keys.clone();
+                    kv.append(&mut vals);
+                    self.replica.merge_row(&merged, old.as_deref(), &mut kv)?;
+                    vals = kv.split_off(keys.len());
                 }
-                if is_delete {
-                    tx.store_tx.del(&k_store)?;
-                } else {
-                    let vals: Vec<_> = val_indices
-                        .iter()
-                        .map(|(i, col)| -> Result<DataValue> {
-                            let v = row
-                                .get(*i)
-                                .ok_or_else(|| miette!("row too short: {:?}", row))?;
-                            col.typing.coerce(v.clone(), cur_vld)
-                        })
-                        .try_collect()?;
-                    let v_store = handle.encode_val_only_for_store(&vals, Default::default())?;
-                    tx.store_tx.put(&k_store, &v_store)?;
-                    if has_indices {
-                        let mut kv = keys;
-                        kv.extend(vals);
-                        for (idx_rel, extractor) in handle.indices.values() {
-                            let idx_tup = extractor.iter().map(|i| kv[*i].clone()).collect_vec();
-                            let encoded =
-                                idx_rel.encode_key_for_store(&idx_tup, Default::default())?;
-                            tx.store_tx.put(&encoded, &[])?;
-                        }
+                let v_store = handle.encode_val_only_for_store(&vals, Default::default())?;
+                journal.put(tx, &k_store, &v_store)?;
+                if has_indices {
+                    let mut kv = keys;
+                    kv.extend(vals);
+                    for (idx_rel, extractor) in handle.indices.values() {
+                        let idx_tup = extractor.iter().map(|i| kv[*i].clone()).collect_vec();
+                        let encoded = idx_rel.encode_key_for_store(&idx_tup, Default::default())?;
+                        journal.put(tx, &encoded, &[])?;
                     }
                 }
             }
         }
-        tx.commit_tx()?;
+        tx.rows_written += rows.len() as u64;
         Ok(())
     }
     /// Backup the running database into an Sqlite file
@@ -634,7 +1617,10 @@ impl<'s, S: Storage<'s>> Db<S> {
             }
             let iter = s_tx.store_tx.total_scan();
             self.db.batch_put(iter)?;
+            self.schema_cache.invalidate();
             s_tx.commit_tx()?;
+            // the rows are written directly to the storage, not by a write transaction
+            self.check_constraints(&mut self.transact()?, current_validity())?;
             Ok(())
         }
         #[cfg(not(feature = "storage-sqlite"))]
@@ -705,6 +1691,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                 for result in data_it {
                     let (key, val) = result?;
                     dst_tx.store_tx.put(&key, &val)?;
+                    dst_tx.rows_written += 1;
                 }
             }
 
@@ -712,6 +1699,64 @@ impl<'s, S: Storage<'s>> Db<S> {
             dst_tx.commit_tx()
         }
     }
+    /// Write a logical dump of all stored relations to `writer`, as read from a single
+    /// snapshot of the database. The first line of the dump is a header giving the
+    /// version of the format, see [`DUMP_VERSION`](crate::DUMP_VERSION). Each following
+    /// line is a JSON object with the fields `relation`, `headers` and `rows`, holding up
+    /// to [`SNAPSHOT_BATCH_ROWS`] rows of the relation, and can be passed to
+    /// [`import_relations`](Self::import_relations) as `{relation: {headers, rows}}`, or
+    /// the whole dump to [`snapshot_import`](Self::snapshot_import). Indices are not
+    /// exported, as importing the relations rebuilds them, and neither are hidden
+    /// relations.
+    ///
+    /// The dump runs in a read transaction, so with the RocksDB backend writes proceed
+    /// while it is written and are not seen by it. With the other backends writes may
+    /// wait for the dump to be done.
+    pub fn snapshot_export(&'s self, writer: impl Write) -> Result<SnapshotExport> {
+        let mut writer = BufWriter::new(writer);
+        let tx = self.transact()?;
+        let mut report = SnapshotExport::default();
+        write_dump_header(&mut writer)?;
+        for handle in self.stored_relations(&tx)? {
+            if handle.name.contains(':') {
+                continue;
+            }
+            if handle.access_level < AccessLevel::ReadOnly {
+                report.skipped.push(handle.name.to_string());
+                continue;
+            }
+            let size_hint = handle.metadata.keys.len() + handle.metadata.non_keys.len();
+            let headers = handle
+                .metadata
+                .keys
+                .iter()
+                .chain(handle.metadata.non_keys.iter())
+                .map(|col| col.name.to_string())
+                .collect_vec();
+            let start = Tuple::default().encode_as_key(handle.id);
+            let end = Tuple::default().encode_as_key(handle.id.next());
+            let chunks = tx.store_tx.range_scan(&start, &end).chunks(SNAPSHOT_BATCH_ROWS);
+            for chunk in &chunks {
+                let rows: Vec<JsonValue> = chunk
+                    .map_ok(|(k, v)| {
+                        let tuple = decode_tuple_from_kv(&k, &v, Some(size_hint));
+                        tuple.into_iter().map(JsonValue::from).collect()
+                    })
+                    .try_collect()?;
+                report.rows += rows.len();
+                let line = json!({
+                    "relation": handle.name.as_str(),
+                    "headers": headers,
+                    "rows": rows,
+                });
+                serde_json::to_writer(&mut writer, &line).into_diagnostic()?;
+                writer.write_all(b"\n").into_diagnostic()?;
+            }
+            report.relations.push(handle.name.to_string());
+        }
+        writer.flush().into_diagnostic()?;
+        Ok(report)
+    }
     /// Register a custom fixed rule implementation.
     pub fn register_fixed_rule<R>(&self, name: String, rule_impl: R) -> Result<()>
     where
@@ -736,7 +1781,10 @@ impl<'s, S: Storage<'s>> Db<S> {
         if DEFAULT_FIXED_RULES.contains_key(name) {
             bail!("Cannot unregister builtin fixed rule {}", name);
         }
-        Ok(self.fixed_rules.write().unwrap().remove(name).is_some())
+        let mut fixed_rules = self.fixed_rules.write().unwrap();
+        #[cfg(feature = "wasm-plugins")]
+        self.wasm_plugins.lock().unwrap().remove(name);
+        Ok(fixed_rules.remove(name).is_some())
     }
 
     /// Register callback channel to receive changes when the requested relation are successfully committed.
@@ -769,46 +1817,746 @@ impl<'s, S: Storage<'s>> Db<S> {
         (new_id, receiver)
     }
 
-    /// Unregister callbacks/channels to run when changes to relations are committed.
-    #[cfg(not(target_arch = "wasm32"))]
-    pub fn unregister_callback(&self, id: u32) -> bool {
-        let mut guard = self.event_callbacks.write().unwrap();
-        let ret = guard.0.remove(&id);
-        if let Some(cb) = &ret {
-            guard.1.get_mut(&cb.dependent).unwrap().remove(&id);
+    /// Unregister callbacks/channels to run when changes to relations are committed.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn unregister_callback(&self, id: u32) -> bool {
+        let mut guard = self.event_callbacks.write().unwrap();
+        let ret = guard.0.remove(&id);
+        if let Some(cb) = &ret {
+            guard.1.get_mut(&cb.dependent).unwrap().remove(&id);
+
+            if guard.1.get(&cb.dependent).unwrap().is_empty() {
+                guard.1.remove(&cb.dependent);
+            }
+        }
+        ret.is_some()
+    }
+
+    /// Remove all rows whose expiry timestamp is in the past, for every stored relation
+    /// with an expiry column set by `::expiry set <rel> <col>`.
+    ///
+    /// The expiry column holds seconds since the UNIX epoch, the same unit as `now()`.
+    /// Rows with a non-numeric expiry (e.g. `null`) never expire.
+    /// Removal goes through `:rm` in write transactions of at most `batch_size` rows each,
+    /// so indices, triggers and callbacks are kept up to date.
+    /// Returns the number of rows removed.
+    pub fn sweep_expired(&'s self, batch_size: usize) -> Result<usize> {
+        let batch_size = batch_size.max(1);
+        let mut total = 0;
+        for handle in self.expiring_relations()? {
+            let condition = expiry_condition(&handle);
+            loop {
+                let params = BTreeMap::from([(
+                    "now".to_string(),
+                    DataValue::from(seconds_since_the_epoch()?),
+                )]);
+                let removed = self
+                    .retract_where(&handle, &condition, params, batch_size)
+                    .wrap_err_with(|| format!("when sweeping expired rows from '{}'", handle.name))?;
+                total += removed;
+                if removed < batch_size {
+                    break;
+                }
+            }
+        }
+        Ok(total)
+    }
+
+    /// The stored relations with an expiry column.
+    pub(crate) fn expiring_relations(&'s self) -> Result<Vec<RelationHandle>> {
+        let tx = self.transact()?;
+        Ok(self
+            .stored_relations(&tx)?
+            .into_iter()
+            .filter(|h| h.expiry_column.is_some() && !h.name.contains(':'))
+            .collect_vec())
+    }
+
+    /// Remove at most `limit` rows of the relation of `handle` for which `condition`, an
+    /// expression over its columns and `params`, is true. Removal goes through `:rm`, so
+    /// indices, triggers and callbacks are kept up to date. Returns the number of rows
+    /// removed.
+    pub(crate) fn retract_where(
+        &'s self,
+        handle: &RelationHandle,
+        condition: &str,
+        mut params: BTreeMap<String, DataValue>,
+        limit: usize,
+    ) -> Result<usize> {
+        let keys = handle
+            .metadata
+            .keys
+            .iter()
+            .map(|col| col.name.to_string())
+            .collect_vec();
+        let bindings = handle
+            .metadata
+            .keys
+            .iter()
+            .chain(handle.metadata.non_keys.iter())
+            .map(|col| col.name.to_string())
+            .collect_vec();
+        let script = format!(
+            "?[{keys}] := *{name}{{{bindings}}}, {condition} \
+            :limit $limit \
+            :returning \
+            :rm {name} {{{keys}}}",
+            keys = keys.join(", "),
+            bindings = bindings.join(", "),
+            name = handle.name,
+        );
+        params.insert("limit".to_string(), DataValue::from(limit as i64));
+        let res = self.run_script(&script, params, ScriptMutability::Mutable)?;
+        Ok(res
+            .rows
+            .iter()
+            .filter(|row| row.first() == Some(&DataValue::from("requested")))
+            .count())
+    }
+
+    pub(crate) fn stored_relations(&'s self, tx: &SessionTx<'_>) -> Result<Vec<RelationHandle>> {
+        let lower = vec![DataValue::from("")].encode_as_key(RelationId::SYSTEM);
+        let upper =
+            vec![DataValue::from(String::from(LARGEST_UTF_CHAR))].encode_as_key(RelationId::SYSTEM);
+        let mut ret = vec![];
+        for kv_res in tx.store_tx.range_scan(&lower, &upper) {
+            let (k_slice, v_slice) = kv_res?;
+            if upper <= k_slice {
+                break;
+            }
+            ret.push(RelationHandle::decode(&v_slice)?);
+        }
+        Ok(ret)
+    }
+
+    pub(crate) fn obtain_relation_locks<'a, T: Iterator<Item = &'a SmartString<LazyCompact>>>(
+        &'s self,
+        rels: T,
+    ) -> Vec<Arc<ShardedLock<()>>> {
+        let mut collected = vec![];
+        let mut pending = vec![];
+        {
+            let locks = self.relation_locks.read().unwrap();
+            for rel in rels {
+                match locks.get(rel) {
+                    None => {
+                        pending.push(rel);
+                    }
+                    Some(lock) => collected.push(lock.clone()),
+                }
+            }
+        }
+        if !pending.is_empty() {
+            let mut locks = self.relation_locks.write().unwrap();
+            for rel in pending {
+                let lock = locks.entry(rel.clone()).or_default().clone();
+                collected.push(lock);
+            }
+        }
+        collected
+    }
+
+    /// Compact the storage in the raw key range from `lower` (inclusive) to `upper` (exclusive).
+    /// A no-op for storage engines without compaction.
+    pub fn compact_range(&'s self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.sessions.ensure_open()?;
+        self.db.range_compact(lower, upper)
+    }
+
+    /// List the transactions currently open on the database, including those of
+    /// running scripts and of [`run_multi_transaction`](Self::run_multi_transaction).
+    pub fn list_sessions(&'s self) -> Vec<SessionInfo> {
+        self.sessions.list()
+    }
+
+    /// All the entries of the audit log, oldest first, including those logged
+    /// by [`enable_audit_log`](Self::enable_audit_log) in previous runs.
+    pub fn audit_log(&'s self) -> Result<Vec<AuditEntry>> {
+        self.sessions.ensure_open()?;
+        self.sessions.audit.flush();
+        read_audit_log(&self.db)
+    }
+
+    /// Record every write transaction committed from now on in the transaction log,
+    /// which is stored in the database itself and so part of its backups. Each entry
+    /// holds the keys written and deleted by a transaction, and can be taken out with
+    /// [`tx_log_chunk`](Self::tx_log_chunk) for archiving and replayed on top of a backup
+    /// by [`restore_to_timestamp`](Self::restore_to_timestamp). Logged transactions are
+    /// numbered as they start committing, and commit concurrently: readers of the log
+    /// only see an entry once those before it are written.
+    ///
+    /// Enable the log before writes start: the log continues from its last entry, and
+    /// transactions committed while it was disabled cannot be replayed.
+    pub fn enable_tx_log(&'s self) -> Result<()> {
+        self.sessions.ensure_open()?;
+        let tx = self.db.transact(false)?;
+        self.tx_log.start(last_tx_log_seq(&tx)?);
+        Ok(())
+    }
+    /// Stop recording the transaction log. The entries so far are kept.
+    /// Returns `false` if the transaction log was not enabled.
+    pub fn disable_tx_log(&'s self) -> bool {
+        self.tx_log.stop()
+    }
+    /// Up to `max_entries` entries of the transaction log following entry `after`, oldest
+    /// first. Pass the [`last_seq`](TxLogChunk::last_seq) of the previous chunk, or zero
+    /// to start from the oldest entry.
+    pub fn tx_log_chunk(&'s self, after: u64, max_entries: usize) -> Result<TxLogChunk> {
+        self.sessions.ensure_open()?;
+        read_tx_log(&self.db, &self.tx_log, after, max_entries)
+    }
+    /// Remove the entries of the transaction log up to and including entry `up_to`,
+    /// typically once they have been archived. Returns the number of removed entries.
+    pub fn truncate_tx_log(&'s self, up_to: u64) -> Result<usize> {
+        self.sessions.ensure_open()?;
+        truncate_tx_log(&self.db, up_to)
+    }
+    /// Restore the Sqlite backup `base`, made by [`backup_db`](Self::backup_db) of a
+    /// database with the transaction log enabled, into this new database, then replay the
+    /// transactions of `chunks` committed at or before `ts`, in seconds since the epoch.
+    /// Transactions already contained in the backup are skipped, and the chunks must
+    /// continue the log of the backup without gaps. Returns the number of replayed
+    /// transactions.
+    pub fn restore_to_timestamp(
+        &'s self,
+        base: impl AsRef<Path>,
+        chunks: &[TxLogChunk],
+        ts: f64,
+    ) -> Result<usize> {
+        self.restore_backup(base)?;
+        let base_seq = last_tx_log_seq(&self.db.transact(false)?)?;
+        let mut last_seq = base_seq;
+        let mut replayed = 0;
+        let entries = chunks.iter().flat_map(|chunk| chunk.entries.iter());
+        for entry in entries.skip_while(|entry| entry.seq <= base_seq) {
+            if entry.timestamp > ts {
+                break;
+            }
+            ensure!(entry.seq == last_seq + 1, TxLogGap(last_seq + 1));
+            let mut tx = self.db.transact(true)?;
+            replay_tx_log_entry(&mut tx, entry)?;
+            tx.commit()?;
+            self.schema_cache.invalidate();
+            last_seq = entry.seq;
+            replayed += 1;
+        }
+        self.load_last_ids()?;
+        Ok(replayed)
+    }
+
+    /// Kill the session `id`: its running queries are stopped and it can no longer
+    /// commit. Returns `false` if no such session is open.
+    pub fn kill_session(&'s self, id: u64) -> bool {
+        self.sessions.kill(id)
+    }
+
+    /// Refuse new write transactions, for maintenance such as migrations or taking a
+    /// consistent snapshot of the files of the storage, and wait for the active ones to
+    /// finish. Writes are then refused with a `db::writes_frozen` error, which
+    /// [`CozoError`](crate::CozoError) classifies as a conflict, as the write may succeed
+    /// once writes are unfrozen. Reading transactions go on as usual. If write
+    /// transactions are still active after `timeout`, writes are unfrozen and an error is
+    /// returned, so no transaction is killed.
+    pub fn freeze_writes(&'s self, timeout: Duration) -> Result<()> {
+        self.sessions.freeze_writes(timeout)
+    }
+
+    /// Accept write transactions again after [`freeze_writes`](Self::freeze_writes).
+    /// Returns `false` if writes were not frozen.
+    pub fn unfreeze_writes(&'s self) -> bool {
+        self.sessions.unfreeze_writes()
+    }
+
+    /// Shut the database down. New transactions are refused from now on, and once the
+    /// running ones have finished, pending writes are flushed and the storage engine is
+    /// released, e.g. RocksDB closes its files and unlocks its directory. If transactions
+    /// are still active after `timeout`, the running queries are killed and an error is
+    /// returned with the storage left open, in which case `close` may be retried.
+    pub fn close(&'s self, timeout: Duration) -> Result<()> {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.expiry_sweeper.lock().unwrap().take();
+            self.compaction_schedule.lock().unwrap().take();
+        }
+        self.jobs.cancel_all();
+        #[cfg(feature = "async")]
+        self.write_queue.lock().unwrap().take();
+        #[cfg(feature = "scheduler")]
+        self.schedules.clear();
+        match self.sessions.close(timeout) {
+            Ok(true) => {
+                self.sessions.audit.stop();
+                self.db.close()
+            }
+            Ok(false) => Ok(()),
+            Err(err) => {
+                self.running_queries.kill_all();
+                Err(err)
+            }
+        }
+    }
+
+    /// Compact the storage of the given stored relations, including their indices,
+    /// or of every stored relation if `relations` is empty. `on_progress` is called
+    /// after each relation or index is compacted.
+    pub fn compact_relations(
+        &'s self,
+        relations: &[&str],
+        mut on_progress: impl FnMut(&CompactionProgress),
+    ) -> Result<()> {
+        let targets = self.relations_and_indices(&self.transact()?, relations)?;
+        let total = targets.len();
+        for (i, handle) in targets.into_iter().enumerate() {
+            let lower = Tuple::default().encode_as_key(handle.id);
+            let upper = Tuple::default().encode_as_key(handle.id.next());
+            self.db.range_compact(&lower, &upper)?;
+            on_progress(&CompactionProgress {
+                relation: handle.name.to_string(),
+                done: i + 1,
+                total,
+            });
+        }
+        Ok(())
+    }
+
+    /// Read the keys of the given stored relations, including their indices, or of every
+    /// stored relation if `relations` is empty, so that the storage engine caches them,
+    /// e.g. RocksDB in its block cache. Call it after opening the database to avoid slow
+    /// first queries. Returns the number of keys read.
+    pub fn warm_up(&'s self, relations: &[&str]) -> Result<usize> {
+        let tx = self.transact()?;
+        let mut keys = 0;
+        for handle in self.relations_and_indices(&tx, relations)? {
+            let lower = Tuple::default().encode_as_key(handle.id);
+            let upper = Tuple::default().encode_as_key(handle.id.next());
+            for kv in tx.store_tx.range_scan(&lower, &upper) {
+                kv?;
+                keys += 1;
+            }
+        }
+        Ok(keys)
+    }
+
+    /// The handles of `relations` and of their indices, or of every stored relation and
+    /// index if `relations` is empty.
+    fn relations_and_indices(
+        &'s self,
+        tx: &SessionTx<'_>,
+        relations: &[&str],
+    ) -> Result<Vec<RelationHandle>> {
+        for name in relations {
+            tx.get_relation(name, false)?;
+        }
+        Ok(self
+            .stored_relations(tx)?
+            .into_iter()
+            .filter(|h| {
+                relations.is_empty()
+                    || relations.iter().any(|name| {
+                        h.name == *name
+                            || h.name
+                                .strip_prefix(*name)
+                                .is_some_and(|rest| rest.starts_with(':'))
+                    })
+            })
+            .collect_vec())
+    }
+
+    /// A snapshot of the counters of the database: transactions, rows written, query
+    /// latencies and cache usage. [`Metrics::to_prometheus`] formats it for scraping.
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.snapshot(
+            self.tokenizers.hits.load(Ordering::Relaxed),
+            self.tokenizers.misses.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Record every script taking at least `threshold` to run, keeping the `capacity` most
+    /// recent ones. They are returned by [`slow_queries`](Self::slow_queries) and by the
+    /// `::slow_queries` system op, and are also passed to `callback` if one is given.
+    /// Calling this again replaces the previous settings but keeps the recorded queries.
+    pub fn set_slow_query_log(
+        &self,
+        threshold: Duration,
+        capacity: usize,
+        callback: Option<SlowQueryCallback>,
+    ) {
+        self.slow_queries.configure(threshold, capacity, callback)
+    }
+
+    /// Stop recording slow queries. Returns `false` if the slow query log was not enabled.
+    pub fn disable_slow_query_log(&self) -> bool {
+        self.slow_queries.disable()
+    }
+
+    /// The slow queries recorded so far, oldest first.
+    pub fn slow_queries(&self) -> Vec<SlowQuery> {
+        self.slow_queries.entries()
+    }
+
+    /// Reject scripts containing string literals, so that strings can only reach queries
+    /// as parameters referenced as `$name` and never by formatting them into the script.
+    /// Such scripts fail to parse with a `parser::string_literal_forbidden` error.
+    /// System ops, which take options such as tokenizers as strings, are exempt.
+    pub fn forbid_string_literals(&self, forbid: bool) {
+        self.string_literals_forbidden
+            .store(forbid, Ordering::Relaxed)
+    }
+
+    /// Limit the resources used by the scripts run for the principal named `principal`,
+    /// see [`run_script_as`](Self::run_script_as). Scripts going over a limit fail with
+    /// a `db::quota_exceeded` error. Setting the quota again replaces the limits but keeps
+    /// the usage, such as the scripts still running.
+    pub fn set_quota(&self, principal: &str, quota: Quota) {
+        self.sessions.quotas.set(principal, quota)
+    }
+
+    /// Remove the quota of a principal. Returns `false` if it had none.
+    pub fn remove_quota(&self, principal: &str) -> bool {
+        self.sessions.quotas.remove(principal)
+    }
+
+    /// The quota of a principal, if one is set.
+    pub fn quota(&self, principal: &str) -> Option<Quota> {
+        self.sessions.quotas.get(principal)
+    }
+
+    /// Limit the evaluation of every query, so that runaway recursive rules fail
+    /// predictably, with an `eval::iteration_limit_exceeded` or an
+    /// `eval::derived_rows_limit_exceeded` error. The limits are checked after each
+    /// iteration of the evaluation, which is also reported to `progress` if one is given,
+    /// on the thread evaluating the query. Calling this again replaces the settings.
+    pub fn set_eval_limits(&self, limits: EvalLimits, progress: Option<EvalProgressCallback>) {
+        self.sessions.eval_guard.configure(limits, progress)
+    }
+
+    /// Set the URLs the `FetchJson` fixed rule may request, and the limits of its
+    /// requests. Calling this again replaces the configuration, which by default allows
+    /// no URL at all.
+    #[cfg(feature = "fetch")]
+    pub fn set_fetch_config(&self, config: FetchConfig) {
+        *self.fetch_config.write().unwrap() = config;
+    }
+
+    /// The configuration set by [`set_fetch_config`](Self::set_fetch_config).
+    #[cfg(feature = "fetch")]
+    pub fn fetch_config(&self) -> FetchConfig {
+        self.fetch_config.read().unwrap().clone()
+    }
+
+    /// The limits set by [`set_eval_limits`](Self::set_eval_limits).
+    pub fn eval_limits(&self) -> EvalLimits {
+        self.sessions.eval_guard.limits()
+    }
+
+    /// Report the number of keys and estimated sizes of the database and of every
+    /// stored relation and index, together with engine-specific statistics.
+    /// Sizes are estimated by the engine from key prefixes without scanning the data,
+    /// and are `None` for engines that cannot estimate them.
+    pub fn storage_stats(&'s self) -> Result<StorageStats> {
+        let tx = self.transact()?;
+        let mut relations = vec![];
+        for handle in self.stored_relations(&tx)? {
+            let lower = Tuple::default().encode_as_key(handle.id);
+            let upper = Tuple::default().encode_as_key(handle.id.next());
+            relations.push(RelationStats {
+                name: handle.name.to_string(),
+                rows: tx.store_tx.range_count(&lower, &upper)?,
+                approximate_size: self.db.approximate_size(&lower, &upper)?,
+            });
+        }
+        let lower = Tuple::default().encode_as_key(RelationId::SYSTEM);
+        // the journal of chunked imports, the transaction log, the audit log and the
+        // namespaces are stored from 0xFC onwards
+        let upper = [CHUNK_JOURNAL_KEY_MARKER];
+        Ok(StorageStats {
+            total_keys: tx.store_tx.range_count(&lower, &upper)?,
+            approximate_size: self.db.approximate_size(&lower, &upper)?,
+            relations,
+            engine: self.db.engine_stats()?,
+        })
+    }
+
+    /// Check the consistency of the stored data: that every index holds exactly
+    /// one entry for each row of its relation, that no relation id exceeds the
+    /// persisted id counter, and that no keys exist outside of the known relations.
+    /// Full-text, LSH and HNSW indices are not checked against their relations.
+    /// This reads the whole database.
+    pub fn verify_integrity(&'s self) -> Result<IntegrityReport> {
+        let tx = self.transact()?;
+        self.verify_integrity_in(&tx)
+    }
+
+    fn verify_integrity_in(&'s self, tx: &SessionTx<'_>) -> Result<IntegrityReport> {
+        let handles = self.stored_relations(tx)?;
+        let counter = tx
+            .store_tx
+            .get(&vec![DataValue::Null].encode_as_key(RelationId::SYSTEM), false)?
+            .map(|v| RelationId::raw_decode(&v))
+            .unwrap_or(RelationId::SYSTEM);
+        let mut report = IntegrityReport {
+            relations_checked: handles.len(),
+            rows_checked: 0,
+            findings: vec![],
+        };
 
-            if guard.1.get(&cb.dependent).unwrap().is_empty() {
-                guard.1.remove(&cb.dependent);
+        for handle in &handles {
+            if handle.id.0 > counter.0 {
+                report.findings.push(IntegrityFinding::IdAboveCounter {
+                    relation: handle.name.to_string(),
+                    id: handle.id.0,
+                    counter: counter.0,
+                });
             }
         }
-        ret.is_some()
-    }
 
-    pub(crate) fn obtain_relation_locks<'a, T: Iterator<Item = &'a SmartString<LazyCompact>>>(
-        &'s self,
-        rels: T,
-    ) -> Vec<Arc<ShardedLock<()>>> {
-        let mut collected = vec![];
-        let mut pending = vec![];
-        {
-            let locks = self.relation_locks.read().unwrap();
-            for rel in rels {
-                match locks.get(rel) {
-                    None => {
-                        pending.push(rel);
+        let mut ids = handles.iter().map(|h| h.id.0).collect_vec();
+        ids.push(RelationId::SYSTEM.0);
+        ids.sort_unstable();
+        ids.dedup();
+        for (i, id) in ids.iter().enumerate() {
+            let lower = Tuple::default().encode_as_key(RelationId(id + 1));
+            let (upper, last_id) = match ids.get(i + 1) {
+                Some(next) => (Tuple::default().encode_as_key(RelationId(*next)), next - 1),
+                // the reserved keys are stored from 0xFC, see `storage_stats`
+                None => (vec![CHUNK_JOURNAL_KEY_MARKER], u64::MAX),
+            };
+            if lower >= upper {
+                continue;
+            }
+            let count = tx.store_tx.range_count(&lower, &upper)?;
+            if count > 0 {
+                report.findings.push(IntegrityFinding::OrphanedKeys {
+                    first_id: id + 1,
+                    last_id,
+                    count,
+                });
+            }
+        }
+
+        for handle in &handles {
+            let n_keys = handle.metadata.keys.len();
+            for (i, (idx_name, (idx_handle, extractor))) in handle.indices.iter().enumerate() {
+                // the rows of the relation are scanned once per index, but counted once
+                for tuple in handle.scan_all(tx) {
+                    let tuple = tuple?;
+                    if i == 0 {
+                        report.rows_checked += 1;
+                    }
+                    let entry = extractor.iter().map(|i| tuple[*i].clone()).collect_vec();
+                    let encoded = idx_handle.encode_key_for_store(&entry, Default::default())?;
+                    if !tx.store_tx.exists(&encoded, false)? {
+                        report.findings.push(IntegrityFinding::MissingIndexEntry {
+                            relation: handle.name.to_string(),
+                            index: idx_name.to_string(),
+                            key: tuple[..n_keys].to_vec(),
+                        });
+                    }
+                }
+
+                // every key of the relation is also a column of its indices
+                let key_positions = (0..n_keys)
+                    .map(|k| extractor.iter().position(|i| *i == k).unwrap())
+                    .collect_vec();
+                for entry in idx_handle.scan_all(tx) {
+                    let entry = entry?;
+                    report.rows_checked += 1;
+                    let mut row = key_positions.iter().map(|i| entry[*i].clone()).collect_vec();
+                    let encoded = handle.encode_key_for_store(&row, Default::default())?;
+                    let matches = match tx.store_tx.get(&encoded, false)? {
+                        None => false,
+                        Some(val) => {
+                            extend_tuple_from_v(&mut row, &val);
+                            extractor.iter().map(|i| &row[*i]).eq(entry.iter())
+                        }
+                    };
+                    if !matches {
+                        report.findings.push(IntegrityFinding::DanglingIndexEntry {
+                            relation: handle.name.to_string(),
+                            index: idx_name.to_string(),
+                            entry,
+                        });
                     }
-                    Some(lock) => collected.push(lock.clone()),
                 }
             }
         }
-        if !pending.is_empty() {
-            let mut locks = self.relation_locks.write().unwrap();
-            for rel in pending {
-                let lock = locks.entry(rel.clone()).or_default().clone();
-                collected.push(lock);
+        Ok(report)
+    }
+
+    /// Repair the inconsistencies found by [`Db::verify_integrity`], rebuilding indices
+    /// from the rows of their relations, which are taken to be correct. With
+    /// [`RepairMode::RebuildAll`], every index is rebuilt, which also recovers from
+    /// corruption the verification cannot detect, at the cost of rewriting all of them.
+    ///
+    /// All relations are locked for writing while the repair runs, and the verification
+    /// runs in the transaction writing the repairs, so that no write lands in between.
+    pub fn repair(&'s self, mode: RepairMode) -> Result<RepairReport> {
+        let rel_names = self
+            .stored_relations(&self.transact()?)?
+            .into_iter()
+            .map(|h| h.name)
+            .collect_vec();
+        let locks = self.obtain_relation_locks(rel_names.iter());
+        let _guards = locks.iter().map(|l| l.write().unwrap()).collect_vec();
+        let mut tx = self.transact_write()?;
+        let findings = self.verify_integrity_in(&tx)?.findings;
+        let mut targets = BTreeSet::new();
+        if mode == RepairMode::RebuildAll {
+            for handle in self.stored_relations(&tx)? {
+                for idx_name in handle
+                    .indices
+                    .keys()
+                    .chain(handle.fts_indices.keys())
+                    .chain(handle.lsh_indices.keys())
+                    .chain(handle.hnsw_indices.keys())
+                {
+                    targets.insert((handle.name.clone(), idx_name.clone()));
+                }
             }
         }
-        collected
+        let mut orphaned = vec![];
+        let mut max_id = None;
+        for finding in findings {
+            match finding {
+                IntegrityFinding::MissingIndexEntry {
+                    relation, index, ..
+                }
+                | IntegrityFinding::DanglingIndexEntry {
+                    relation, index, ..
+                } => {
+                    targets.insert((relation.into(), index.into()));
+                }
+                IntegrityFinding::IdAboveCounter { id, .. } => {
+                    max_id = max_id.max(Some(id));
+                }
+                IntegrityFinding::OrphanedKeys {
+                    first_id, last_id, ..
+                } => {
+                    let lower = Tuple::default().encode_as_key(RelationId(first_id));
+                    let upper = if last_id == u64::MAX {
+                        vec![CHUNK_JOURNAL_KEY_MARKER]
+                    } else {
+                        Tuple::default().encode_as_key(RelationId(last_id + 1))
+                    };
+                    orphaned.push((lower, upper));
+                }
+            }
+        }
+
+        let mut report = RepairReport::default();
+        for (lower, upper) in orphaned {
+            let keys = tx
+                .store_tx
+                .range_scan(&lower, &upper)
+                .map_ok(|(k, _)| k)
+                .collect::<Result<Vec<_>>>()?;
+            report.removed_keys += keys.len();
+            for key in keys {
+                tx.store_tx.del(&key)?;
+            }
+        }
+        for (rel_name, idx_name) in targets {
+            tx.rebuild_index(&rel_name, &idx_name)?;
+            report.rebuilt_indices.push(format!("{rel_name}:{idx_name}"));
+        }
+        if let Some(id) = max_id {
+            tx.store_tx.put(
+                &vec![DataValue::Null].encode_as_key(RelationId::SYSTEM),
+                &RelationId::new(id).raw_encode(),
+            )?;
+            report.new_id_counter = Some(id);
+        }
+        tx.commit_tx()?;
+        if let Some(id) = max_id {
+            self.relation_store_id.fetch_max(id, Ordering::SeqCst);
+        }
+        Ok(report)
+    }
+
+    /// Report how the relation ids handed out so far are used. Ids are never reused:
+    /// those of dropped relations, and those handed out to transactions creating
+    /// relations that were then aborted, are left unused, as gaps below the counter or,
+    /// for ids handed out since the database was opened, above it.
+    pub fn id_allocation_report(&'s self) -> Result<IdAllocationReport> {
+        let tx = self.transact()?;
+        let counter = tx
+            .store_tx
+            .get(&vec![DataValue::Null].encode_as_key(RelationId::SYSTEM), false)?
+            .map(|v| RelationId::raw_decode(&v))
+            .unwrap_or(RelationId::SYSTEM)
+            .0;
+        let mut ids = self
+            .stored_relations(&tx)?
+            .iter()
+            .map(|h| h.id.0)
+            .collect_vec();
+        ids.sort_unstable();
+        let mut gaps = vec![];
+        let mut next = 1;
+        for id in ids.iter().copied().chain(iter::once(counter + 1)) {
+            if id > next && next <= counter {
+                gaps.push(next..=(id - 1).min(counter));
+            }
+            next = next.max(id + 1);
+        }
+        Ok(IdAllocationReport {
+            counter,
+            allocated: self.relation_store_id.load(Ordering::SeqCst),
+            used: ids.len(),
+            gaps,
+        })
+    }
+
+    /// Lower the persisted relation id counter to the highest id in use, so that the
+    /// ids left unused at the top of the range, e.g. by the relations dropped last, are
+    /// handed out again. Ids below the highest one in use are not reused. Returns the
+    /// new counter.
+    ///
+    /// Relations created at the same time keep their ids: the counter is lowered in a
+    /// write transaction, after the ones creating relations have committed, and ids
+    /// handed out while it runs stay above the new counter.
+    pub fn compact_id_counter(&'s self) -> Result<u64> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Cannot compact the relation id counter: keys exist under unused id {0}")]
+        #[diagnostic(code(db::id_counter_orphaned_keys))]
+        #[diagnostic(help("Remove the orphaned keys with `Db::repair` first"))]
+        struct OrphanedKeysAboveIds(u64);
+
+        let allocated = self.relation_store_id.load(Ordering::SeqCst);
+        let mut tx = self.transact_write()?;
+        let counter_key = vec![DataValue::Null].encode_as_key(RelationId::SYSTEM);
+        let counter = tx
+            .store_tx
+            .get(&counter_key, true)?
+            .map(|v| RelationId::raw_decode(&v))
+            .unwrap_or(RelationId::SYSTEM)
+            .0;
+        let highest = self
+            .stored_relations(&tx)?
+            .iter()
+            .map(|h| h.id.0)
+            .max()
+            .unwrap_or(RelationId::SYSTEM.0);
+        if highest >= counter {
+            return Ok(counter);
+        }
+        let lower = Tuple::default().encode_as_key(RelationId(highest + 1));
+        let upper = Tuple::default().encode_as_key(RelationId(counter + 1));
+        if let Some(kv) = tx.store_tx.range_scan(&lower, &upper).next() {
+            let (k, _) = kv?;
+            bail!(OrphanedKeysAboveIds(RelationId::raw_decode(&k).0))
+        }
+        tx.store_tx
+            .put(&counter_key, &RelationId::new(highest).raw_encode())?;
+        tx.commit_tx()?;
+        // fails if an id was handed out meanwhile, which then stays the highest one
+        let _ = self.relation_store_id.compare_exchange(
+            allocated,
+            highest,
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        );
+        Ok(highest)
     }
 
     fn compact_relation(&'s self) -> Result<()> {
@@ -826,22 +2574,72 @@ impl<'s, S: Storage<'s>> Db<S> {
         Ok(())
     }
     pub(crate) fn transact(&'s self) -> Result<SessionTx<'_>> {
+        let session = self.sessions.enter(false, &self.metrics)?;
+        let _span = trace_span!("transact", tx = session.id, write = false);
+        let schema = SchemaView::new(&self.schema_cache);
         let ret = SessionTx {
             store_tx: Box::new(self.db.transact(false)?),
             temp_store_tx: self.temp_db.transact(true)?,
-            relation_store_id: self.relation_store_id.clone(),
+            relation_store_id: &self.relation_store_id,
             temp_store_id: Default::default(),
-            tokenizers: self.tokenizers.clone(),
+            tokenizers: &self.tokenizers,
+            session,
+            rows_written: 0,
+            schema,
+            constraints: None,
         };
         Ok(ret)
     }
     pub(crate) fn transact_write(&'s self) -> Result<SessionTx<'_>> {
+        self.transact_write_with(TxDurability::Default)
+    }
+    pub(crate) fn transact_write_with(
+        &'s self,
+        durability: TxDurability,
+    ) -> Result<SessionTx<'s>> {
+        self.transact_write_in_group(durability, None)
+    }
+    /// A write transaction whose entry in the transaction log is marked with `group`.
+    pub(crate) fn transact_write_in_group(
+        &'s self,
+        durability: TxDurability,
+        group: Option<TxGroup>,
+    ) -> Result<SessionTx<'s>> {
+        let session = self.sessions.enter(true, &self.metrics)?;
+        let _span = trace_span!("transact", tx = session.id, write = true);
+        let schema = SchemaView::new(&self.schema_cache);
+        let mut store_tx: Box<dyn StoreTx<'s> + 's> =
+            Box::new(self.db.transact_write_with(durability)?);
+        if self.tx_log.is_enabled() {
+            let storage = &self.db;
+            store_tx = Box::new(LoggedTx {
+                inner: store_tx,
+                log: self.tx_log.clone(),
+                ops: Default::default(),
+                group,
+                fill_gap: Box::new(move |entry| {
+                    let mut tx = storage.transact(true)?;
+                    replay_tx_log_entry(&mut tx, entry)?;
+                    tx.commit()
+                }),
+            });
+        }
+        store_tx = Box::new(SchemaWatchTx {
+            inner: store_tx,
+            changed: schema.changed.clone(),
+        });
         let ret = SessionTx {
-            store_tx: Box::new(self.db.transact(true)?),
+            store_tx,
             temp_store_tx: self.temp_db.transact(true)?,
-            relation_store_id: self.relation_store_id.clone(),
+            relation_store_id: &self.relation_store_id,
             temp_store_id: Default::default(),
-            tokenizers: self.tokenizers.clone(),
+            tokenizers: &self.tokenizers,
+            session,
+            rows_written: 0,
+            schema,
+            constraints: Some(Box::new(move |tx| {
+                self.check_constraints(tx, current_validity())
+            })),
         };
         Ok(ret)
     }
@@ -870,40 +2668,68 @@ impl<'s, S: Storage<'s>> Db<S> {
     fn do_run_script(
         &'s self,
         payload: &str,
+        principal: Option<&Principal>,
         param_pool: &BTreeMap<String, DataValue>,
         cur_vld: ValidityTs,
         read_only: bool,
+        durability: TxDurability,
     ) -> Result<NamedRows> {
-        match parse_script(
+        let started_at = seconds_since_the_epoch()?;
+        let res = parse_script(
             payload,
             param_pool,
             &self.fixed_rules.read().unwrap(),
             cur_vld,
-        )? {
-            CozoScript::Single(p) => self.execute_single(cur_vld, p, read_only),
-            CozoScript::Imperative(ps) => self.execute_imperative(cur_vld, &ps, read_only),
-            CozoScript::Sys(op) => self.run_sys_op(op, read_only),
+            self.string_literals_forbidden.load(Ordering::Relaxed),
+        )
+        .and_then(|script| match script {
+            CozoScript::Single(p) => {
+                self.execute_single(payload, principal, cur_vld, p, read_only, durability, None)
+            }
+            CozoScript::Imperative(ps) => {
+                self.execute_imperative(payload, principal, cur_vld, &ps, read_only, durability)
+            }
+            CozoScript::Sys(op) => {
+                self.run_sys_op(payload, principal, op, read_only, durability)
+            }
+        });
+        self.observe_query(payload, param_pool, started_at, &res);
+        res.map_err(|err| with_script_source(err, payload))
+    }
+    pub(crate) fn observe_query(
+        &self,
+        script: &str,
+        params: &BTreeMap<String, DataValue>,
+        started_at: f64,
+        res: &Result<NamedRows>,
+    ) {
+        if let Ok(now) = seconds_since_the_epoch() {
+            let duration = now - started_at;
+            self.metrics.observe_query(duration, res.is_ok());
+            let rows = res.as_ref().ok().map(|rows| rows.rows.len());
+            self.slow_queries.record(script, params, started_at, duration, rows);
         }
     }
 
     fn execute_single(
         &'s self,
+        script: &str,
+        principal: Option<&Principal>,
         cur_vld: ValidityTs,
         p: InputProgram,
         read_only: bool,
+        durability: TxDurability,
+        idempotency_key: Option<&str>,
     ) -> Result<NamedRows, Report> {
         let mut callback_collector = BTreeMap::new();
         let write_lock_names = p.needs_write_lock();
-        let is_write = write_lock_names.is_some();
-        if read_only && is_write {
-            bail!("write lock required for read-only query");
+        if read_only && write_lock_names.is_some() {
+            bail!(ReadOnlyViolation("write lock required for read-only query"));
         }
+        // the result of a write with an idempotency key is recorded even if it writes nothing
+        let is_write = write_lock_names.is_some() || idempotency_key.is_some();
         let write_lock = self.obtain_relation_locks(write_lock_names.iter());
-        let _write_lock_guards = if is_write {
-            Some(write_lock[0].read().unwrap())
-        } else {
-            None
-        };
+        let _write_lock_guards = write_lock.first().map(|l| l.read().unwrap());
         let callback_targets = if is_write {
             self.current_callback_targets()
         } else {
@@ -913,10 +2739,18 @@ impl<'s, S: Storage<'s>> Db<S> {
         let res;
         {
             let mut tx = if is_write {
-                self.transact_write()?
+                self.transact_write_with(durability)?
             } else {
                 self.transact()?
             };
+            tx.session.start_query(script, principal)?;
+            if let Some(key) = idempotency_key {
+                if let Some(found) = tx.store_tx.get(&idempotency_record_key(key), true)? {
+                    let (_, rows): (f64, NamedRows) =
+                        rmp_serde::from_slice(&found).into_diagnostic()?;
+                    return Ok(rows);
+                }
+            }
 
             res = self.execute_single_program(
                 p,
@@ -931,6 +2765,11 @@ impl<'s, S: Storage<'s>> Db<S> {
                 tx.store_tx.del_range_from_persisted(&lower, &upper)?;
             }
 
+            if let Some(key) = idempotency_key {
+                let record = (seconds_since_the_epoch()?, &res);
+                let record = rmp_serde::to_vec(&record).into_diagnostic()?;
+                tx.store_tx.put(&idempotency_record_key(key), &record)?;
+            }
             tx.commit_tx()?;
         }
         #[cfg(not(target_arch = "wasm32"))]
@@ -1180,8 +3019,9 @@ impl<'s, S: Storage<'s>> Db<S> {
                 self.explain_compiled(&compiled)
             }
             SysOp::Compact => {
+                tx.ensure_unrestricted("compact")?;
                 if read_only {
-                    bail!("Cannot compact in read-only mode");
+                    bail!(ReadOnlyViolation("Cannot compact in read-only mode"));
                 }
                 self.compact_relation()?;
                 Ok(NamedRows::new(
@@ -1201,8 +3041,9 @@ impl<'s, S: Storage<'s>> Db<S> {
                 ))
             }
             SysOp::RemoveRelation(rel_names) => {
+                tx.ensure_unrestricted("remove relations")?;
                 if read_only {
-                    bail!("Cannot remove relations in read-only mode");
+                    bail!(ReadOnlyViolation("Cannot remove relations in read-only mode"));
                 }
                 let rel_name_strs = rel_names.iter().map(|n| &n.name);
                 let locks = if skip_locking {
@@ -1227,6 +3068,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                 ))
             }
             SysOp::DescribeRelation(rel_name, description) => {
+                tx.ensure_unrestricted("describe relations")?;
                 tx.describe_relation(rel_name, description)?;
                 Ok(NamedRows::new(
                     vec![STATUS_STR.to_string()],
@@ -1234,8 +3076,9 @@ impl<'s, S: Storage<'s>> Db<S> {
                 ))
             }
             SysOp::CreateIndex(rel_name, idx_name, cols) => {
+                tx.ensure_unrestricted("create indices")?;
                 if read_only {
-                    bail!("Cannot create index in read-only mode");
+                    bail!(ReadOnlyViolation("Cannot create index in read-only mode"));
                 }
                 if skip_locking {
                     tx.create_index(rel_name, idx_name, cols)?;
@@ -1253,8 +3096,9 @@ impl<'s, S: Storage<'s>> Db<S> {
                 ))
             }
             SysOp::CreateVectorIndex(config) => {
+                tx.ensure_unrestricted("create indices")?;
                 if read_only {
-                    bail!("Cannot create vector index in read-only mode");
+                    bail!(ReadOnlyViolation("Cannot create vector index in read-only mode"));
                 }
                 if skip_locking {
                     tx.create_hnsw_index(config)?;
@@ -1272,8 +3116,9 @@ impl<'s, S: Storage<'s>> Db<S> {
                 ))
             }
             SysOp::CreateFtsIndex(config) => {
+                tx.ensure_unrestricted("create indices")?;
                 if read_only {
-                    bail!("Cannot create fts index in read-only mode");
+                    bail!(ReadOnlyViolation("Cannot create fts index in read-only mode"));
                 }
                 if skip_locking {
                     tx.create_fts_index(config)?;
@@ -1291,8 +3136,9 @@ impl<'s, S: Storage<'s>> Db<S> {
                 ))
             }
             SysOp::CreateMinHashLshIndex(config) => {
+                tx.ensure_unrestricted("create indices")?;
                 if read_only {
-                    bail!("Cannot create minhash lsh index in read-only mode");
+                    bail!(ReadOnlyViolation("Cannot create minhash lsh index in read-only mode"));
                 }
                 if skip_locking {
                     tx.create_minhash_lsh_index(config)?;
@@ -1311,8 +3157,9 @@ impl<'s, S: Storage<'s>> Db<S> {
                 ))
             }
             SysOp::RemoveIndex(rel_name, idx_name) => {
+                tx.ensure_unrestricted("remove indices")?;
                 if read_only {
-                    bail!("Cannot remove index in read-only mode");
+                    bail!(ReadOnlyViolation("Cannot remove index in read-only mode"));
                 }
                 let bounds = if skip_locking {
                     tx.remove_index(rel_name, idx_name)?
@@ -1336,8 +3183,9 @@ impl<'s, S: Storage<'s>> Db<S> {
             SysOp::ListColumns(rs) => self.list_columns(tx, rs),
             SysOp::ListIndices(rs) => self.list_indices(tx, rs),
             SysOp::RenameRelation(rename_pairs) => {
+                tx.ensure_unrestricted("rename relations")?;
                 if read_only {
-                    bail!("Cannot rename relations in read-only mode");
+                    bail!(ReadOnlyViolation("Cannot rename relations in read-only mode"));
                 }
                 let rel_names = rename_pairs.iter().flat_map(|(f, t)| [&f.name, &t.name]);
                 let locks = if skip_locking {
@@ -1355,21 +3203,23 @@ impl<'s, S: Storage<'s>> Db<S> {
                 ))
             }
             SysOp::ListRunning => self.list_running(),
+            SysOp::ListSlowQueries => Ok(self.slow_queries.as_named_rows()),
+            #[cfg(feature = "scheduler")]
+            SysOp::ListSchedules => Ok(self.schedules.as_named_rows()),
+            #[cfg(not(feature = "scheduler"))]
+            SysOp::ListSchedules => {
+                bail!("listing schedules requires the 'scheduler' feature to be enabled")
+            }
             SysOp::KillRunning(id) => {
-                let queries = self.running_queries.lock().unwrap();
-                Ok(match queries.get(id) {
-                    None => NamedRows::new(
-                        vec![STATUS_STR.to_string()],
-                        vec![vec![DataValue::from("NOT_FOUND")]],
-                    ),
-                    Some(handle) => {
-                        handle.poison.0.store(true, Ordering::Relaxed);
-                        NamedRows::new(
-                            vec![STATUS_STR.to_string()],
-                            vec![vec![DataValue::from("KILLING")]],
-                        )
-                    }
-                })
+                let status = if self.running_queries.kill(*id) {
+                    "KILLING"
+                } else {
+                    "NOT_FOUND"
+                };
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(status)]],
+                ))
             }
             SysOp::ShowTrigger(name) => {
                 let rel = tx.get_relation(name, false)?;
@@ -1393,8 +3243,9 @@ impl<'s, S: Storage<'s>> Db<S> {
                 ))
             }
             SysOp::SetTriggers(name, puts, rms, replaces) => {
+                tx.ensure_unrestricted("set triggers")?;
                 if read_only {
-                    bail!("Cannot set triggers in read-only mode");
+                    bail!(ReadOnlyViolation("Cannot set triggers in read-only mode"));
                 }
                 tx.set_relation_triggers(name, puts, rms, replaces)?;
                 Ok(NamedRows::new(
@@ -1402,9 +3253,87 @@ impl<'s, S: Storage<'s>> Db<S> {
                     vec![vec![DataValue::from(OK_STR)]],
                 ))
             }
+            SysOp::SetExpiry(name, col) => {
+                tx.ensure_unrestricted("set expiry")?;
+                if read_only {
+                    bail!(ReadOnlyViolation("Cannot set expiry in read-only mode"));
+                }
+                tx.set_expiry_column(name, col.as_ref())?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::SetRetired(name, col) => {
+                tx.ensure_unrestricted("set retired markers")?;
+                if read_only {
+                    bail!(ReadOnlyViolation("Cannot set retired marker in read-only mode"));
+                }
+                tx.set_retired_column(name, col.as_ref())?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::ListGrants(name) => {
+                tx.ensure_unrestricted("list grants")?;
+                let rel = tx.get_relation(name, false)?;
+                let mut rows = vec![];
+                for (role, grants) in &rel.grants {
+                    let modes = [
+                        (GrantMode::Read, &grants.read),
+                        (GrantMode::Write, &grants.write),
+                    ];
+                    for (mode, cols) in modes {
+                        for col in cols {
+                            rows.push(vec![
+                                DataValue::from(role.as_str()),
+                                DataValue::from(mode.to_string()),
+                                DataValue::from(col.as_str()),
+                            ]);
+                        }
+                    }
+                }
+                Ok(NamedRows::new(
+                    vec!["role".to_string(), "mode".to_string(), "column".to_string()],
+                    rows,
+                ))
+            }
+            SysOp::SetGrants(name, mode, cols, role, granted) => {
+                if read_only {
+                    bail!(ReadOnlyViolation("Cannot set grants in read-only mode"));
+                }
+                tx.set_grants(name, *mode, cols, &role.name, *granted)?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::ShowRowPolicy(name) => {
+                tx.ensure_unrestricted("show row policies")?;
+                let rel = tx.get_relation(name, false)?;
+                Ok(NamedRows::new(
+                    vec!["policy".to_string()],
+                    vec![vec![match &rel.row_policy {
+                        None => DataValue::Null,
+                        Some(policy) => DataValue::from(policy.as_str()),
+                    }]],
+                ))
+            }
+            SysOp::SetRowPolicy(name, policy) => {
+                if read_only {
+                    bail!(ReadOnlyViolation("Cannot set row policy in read-only mode"));
+                }
+                tx.set_row_policy(name, policy.as_deref())?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
             SysOp::SetAccessLevel(names, level) => {
+                tx.ensure_unrestricted("set access levels")?;
                 if read_only {
-                    bail!("Cannot set access level in read-only mode");
+                    bail!(ReadOnlyViolation("Cannot set access level in read-only mode"));
                 }
                 for name in names {
                     tx.set_access_level(name, *level)?;
@@ -1416,19 +3345,53 @@ impl<'s, S: Storage<'s>> Db<S> {
             }
         }
     }
-    fn run_sys_op(&'s self, op: SysOp, read_only: bool) -> Result<NamedRows> {
+    fn run_sys_op(
+        &'s self,
+        script: &str,
+        principal: Option<&Principal>,
+        op: SysOp,
+        read_only: bool,
+        durability: TxDurability,
+    ) -> Result<NamedRows> {
         let mut tx = if read_only {
             self.transact()?
         } else {
-            self.transact_write()?
+            self.transact_write_with(durability)?
         };
+        tx.session.start_query(script, principal)?;
         let res = self.run_sys_op_with_tx(&mut tx, &op, read_only, false)?;
         tx.commit_tx()?;
         Ok(res)
     }
     /// This is the entry to query evaluation
     pub(crate) fn run_query(
-        &self,
+        &'s self,
+        tx: &mut SessionTx<'_>,
+        input_program: InputProgram,
+        cur_vld: ValidityTs,
+        callback_targets: &BTreeSet<SmartString<LazyCompact>>,
+        callback_collector: &mut CallbackCollector,
+        top_level: bool,
+    ) -> Result<(NamedRows, Vec<(Vec<u8>, Vec<u8>)>)> {
+        let span = trace_span!("query", tx = tx.session.id, rows = tracing::field::Empty);
+        let res = self.do_run_query(
+            tx,
+            input_program,
+            cur_vld,
+            callback_targets,
+            callback_collector,
+            top_level,
+        );
+        if let Ok((rows, _)) = &res {
+            span.record("rows", rows.rows.len() as u64);
+            if let Some(quota) = &tx.session.quota {
+                quota.ensure_rows(rows.rows.len())?;
+            }
+        }
+        res
+    }
+    fn do_run_query(
+        &'s self,
         tx: &mut SessionTx<'_>,
         input_program: InputProgram,
         cur_vld: ValidityTs,
@@ -1466,40 +3429,55 @@ impl<'s, S: Storage<'s>> Db<S> {
 
                 existing.ensure_compatible(
                     meta,
-                    *op == RelationOp::Rm || *op == RelationOp::Delete || *op == RelationOp::Update,
+                    matches!(
+                        op,
+                        RelationOp::Rm
+                            | RelationOp::Retract
+                            | RelationOp::Delete
+                            | RelationOp::Update
+                    ),
                 )?;
             }
         };
 
         // query compilation
-        let entry_head_or_default = input_program.get_entry_out_head_or_default()?;
-        let (normalized_program, out_opts) = input_program.into_normalized_program(tx)?;
-        let (stratified_program, store_lifetimes) = normalized_program.into_stratified_program()?;
-        let program = stratified_program.magic_sets_rewrite(tx)?;
-        let compiled = tx.stratified_magic_compile(program)?;
+        let (entry_head_or_default, out_opts, store_lifetimes, compiled) = {
+            let span = trace_span!("plan", strata = tracing::field::Empty);
+            let entry_head_or_default = input_program.get_entry_out_head_or_default()?;
+            let (normalized_program, out_opts) = input_program.into_normalized_program(tx)?;
+            let (stratified_program, store_lifetimes) =
+                normalized_program.into_stratified_program()?;
+            let program = stratified_program.magic_sets_rewrite(tx)?;
+            let compiled = tx.stratified_magic_compile(program)?;
+            span.record("strata", compiled.len() as u64);
+            (entry_head_or_default, out_opts, store_lifetimes, compiled)
+        };
+        if out_opts.disable_plan_limits {
+            tx.ensure_unrestricted("disable plan limits")?;
+        } else {
+            let returns_rows = match &out_opts.store_relation {
+                None => true,
+                Some((_, _, returning)) => *returning == ReturnMutation::Returning,
+            };
+            self.check_plan_limits(tx, &compiled, returns_rows, out_opts.num_to_take())?;
+        }
 
         // poison is used to terminate queries early
         let poison = Poison::default();
         if let Some(secs) = out_opts.timeout {
             poison.set_timeout(secs)?;
         }
-        // give the query an ID and store it so that it can be queried and cancelled
-        let id = self.queries_count.fetch_add(1, Ordering::AcqRel);
-
         // time the query
         let since_the_epoch = seconds_since_the_epoch()?;
 
+        // give the query an ID and store it so that it can be queried and cancelled, until
+        // the returned RAII guard is dropped
         let handle = RunningQueryHandle {
             started_at: since_the_epoch,
             poison: poison.clone(),
         };
-        self.running_queries.lock().unwrap().insert(id, handle);
-
-        // RAII cleanups of running query handle
-        let _guard = RunningQueryCleanup {
-            id,
-            running_queries: self.running_queries.clone(),
-        };
+        let _guard = self.running_queries.register(handle);
+        tx.session.attach(&poison)?;
 
         let total_num_to_take = if out_opts.sorters.is_empty() {
             out_opts.num_to_take()
@@ -1514,13 +3492,19 @@ impl<'s, S: Storage<'s>> Db<S> {
         };
 
         // the real evaluation
+        let mut dump = out_opts.debug.map(EvalDump::new);
         let (result_store, early_return) = tx.stratified_magic_evaluate(
             &compiled,
             store_lifetimes,
             total_num_to_take,
             num_to_skip,
             poison,
+            dump.as_mut(),
         )?;
+        // with `:debug`, the intermediate rows are returned instead, and nothing is stored
+        if let Some(dump) = dump {
+            return Ok((dump.into_named_rows(), clean_ups));
+        }
 
         // deal with assertions
         if let Some(assertion) = &out_opts.assertion {
@@ -1550,8 +3534,13 @@ impl<'s, S: Storage<'s>> Db<S> {
 
         if !out_opts.sorters.is_empty() {
             // sort outputs if required
-            let sorted_result =
-                tx.sort_and_collect(result_store, &out_opts.sorters, &entry_head_or_default)?;
+            let sorted_result = {
+                let span = trace_span!("sort", rows = tracing::field::Empty);
+                let sorted =
+                    tx.sort_and_collect(result_store, &out_opts.sorters, &entry_head_or_default)?;
+                span.record("rows", sorted.len() as u64);
+                sorted
+            };
             let sorted_iter = if let Some(offset) = out_opts.offset {
                 Left(sorted_result.into_iter().skip(offset))
             } else {
@@ -1563,6 +3552,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                 Right(sorted_iter)
             };
             if let Some((meta, relation_op, returning)) = &out_opts.store_relation {
+                let _span = trace_span!("mutate", relation = %meta.name, op = ?relation_op);
                 let to_clear = tx
                     .execute_relation(
                         self,
@@ -1586,6 +3576,9 @@ impl<'s, S: Storage<'s>> Db<S> {
                     tx.get_returning_rows(callback_collector, &meta.name, returning)?;
                 Ok((returned_rows, clean_ups))
             } else {
+                if out_opts.checksum.is_some() {
+                    return Ok((checksum_rows(sorted_iter), clean_ups));
+                }
                 // not sorting outputs
                 let rows: Vec<Tuple> = sorted_iter.collect_vec();
                 Ok((
@@ -1619,6 +3612,7 @@ impl<'s, S: Storage<'s>> Db<S> {
             };
 
             if let Some((meta, relation_op, returning)) = &out_opts.store_relation {
+                let _span = trace_span!("mutate", relation = %meta.name, op = ?relation_op);
                 let to_clear = tx
                     .execute_relation(
                         self,
@@ -1642,6 +3636,8 @@ impl<'s, S: Storage<'s>> Db<S> {
                     tx.get_returning_rows(callback_collector, &meta.name, returning)?;
 
                 Ok((returned_rows, clean_ups))
+            } else if out_opts.checksum.is_some() {
+                Ok((checksum_rows(scan), clean_ups))
             } else {
                 let rows: Vec<Tuple> = scan.collect_vec();
 
@@ -1661,13 +3657,12 @@ impl<'s, S: Storage<'s>> Db<S> {
     pub(crate) fn list_running(&self) -> Result<NamedRows> {
         let rows = self
             .running_queries
-            .lock()
-            .unwrap()
-            .iter()
-            .map(|(k, v)| {
+            .list()
+            .into_iter()
+            .map(|(id, started_at)| {
                 vec![
-                    DataValue::from(*k as i64),
-                    DataValue::from(format!("{:?}", v.started_at)),
+                    DataValue::from(id as i64),
+                    DataValue::from(format!("{:?}", started_at)),
                 ]
             })
             .collect_vec();
@@ -1752,9 +3747,14 @@ impl<'s, S: Storage<'s>> Db<S> {
     }
     fn list_columns(&'s self, tx: &SessionTx<'_>, name: &str) -> Result<NamedRows> {
         let handle = tx.get_relation(name, false)?;
+        let granting = tx.base_relation(name)?.unwrap_or_else(|| handle.clone());
         let mut rows = vec![];
         let mut idx = 0;
         for col in &handle.metadata.keys {
+            if !granting.is_granted(tx.role(), &col.name, GrantMode::Read) {
+                idx += 1;
+                continue;
+            }
             rows.push(vec![
                 json!(col.name),
                 json!(true),
@@ -1765,6 +3765,10 @@ impl<'s, S: Storage<'s>> Db<S> {
             idx += 1;
         }
         for col in &handle.metadata.non_keys {
+            if !granting.is_granted(tx.role(), &col.name, GrantMode::Read) {
+                idx += 1;
+                continue;
+            }
             rows.push(vec![
                 json!(col.name),
                 json!(false),
@@ -1842,6 +3846,219 @@ impl<'s, S: Storage<'s>> Db<S> {
     }
 }
 
+impl<S> Db<S>
+where
+    S: for<'s> Storage<'s>,
+{
+    /// Open the named logical database living inside the storage of this database.
+    /// Every namespace has its own relations, schema and id counters, and its keys are
+    /// stored under a distinct prefix, so it never sees the data of this database or of
+    /// other namespaces. The namespace is created on first use.
+    ///
+    /// Closing this database waits for the transactions open in its namespaces too,
+    /// and refuses new ones in them.
+    pub fn open_ns(&self, name: &str) -> Result<Db<NsStorage<S>>> {
+        self.sessions.ensure_open()?;
+        let ret = Db::new(NsStorage::new(self.db.clone(), name)?)?;
+        ret.initialize()?;
+        let mut namespaces = self.sessions.namespaces.lock().unwrap();
+        namespaces.retain(|ns| ns.strong_count() > 0);
+        namespaces.push(Arc::downgrade(&ret.sessions));
+        Ok(ret)
+    }
+    /// Delete everything stored in the namespace `name`. Handles previously obtained
+    /// through [`open_ns`](Self::open_ns) for that namespace must not be used afterwards.
+    pub fn drop_ns(&self, name: &str) -> Result<()> {
+        let prefix = ns_prefix(name)?;
+        let upper = ns_prefix_upper(&prefix);
+        let mut tx = self.transact_write()?;
+        tx.store_tx.del_range_from_persisted(&prefix, &upper)?;
+        tx.commit_tx()?;
+        self.db.range_compact(&prefix, &upper)?;
+        Ok(())
+    }
+
+    /// Copy the whole content of the namespace `src` into the namespace `dst`,
+    /// which must be empty.
+    pub fn copy_ns(&self, src: &str, dst: &str) -> Result<()> {
+        let src_prefix = ns_prefix(src)?;
+        let src_upper = ns_prefix_upper(&src_prefix);
+        let dst_prefix = ns_prefix(dst)?;
+        let dst_upper = ns_prefix_upper(&dst_prefix);
+        let mut tx = self.transact_write()?;
+        if tx.store_tx.range_count(&dst_prefix, &dst_upper)? != 0 {
+            bail!("Cannot copy namespace '{}': target namespace '{}' is not empty", src, dst);
+        }
+        let rewrite = |k: Vec<u8>| {
+            let mut new_key = dst_prefix.clone();
+            new_key.extend_from_slice(&k[src_prefix.len()..]);
+            new_key
+        };
+        if tx.store_tx.supports_par_put() {
+            for kv in tx.store_tx.range_scan(&src_prefix, &src_upper) {
+                let (k, v) = kv?;
+                tx.store_tx.par_put(&rewrite(k), &v)?;
+            }
+        } else {
+            let existing: Vec<_> = tx
+                .store_tx
+                .range_scan(&src_prefix, &src_upper)
+                .try_collect()?;
+            for (k, v) in existing {
+                tx.store_tx.put(&rewrite(k), &v)?;
+            }
+        }
+        tx.commit_tx()?;
+        Ok(())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<S> Db<S>
+where
+    S: for<'s> Storage<'s> + 'static,
+{
+    /// Start a background thread calling [`sweep_expired`](Self::sweep_expired)
+    /// every `interval`. Any previously started sweeper is stopped first.
+    ///
+    /// The thread holds a reference to the database, so it keeps running until
+    /// [`stop_expiry_sweeper`](Self::stop_expiry_sweeper) is called.
+    pub fn start_expiry_sweeper(&self, interval: Duration, batch_size: usize) {
+        let (stop_sender, stop_receiver) = bounded::<()>(0);
+        let db = self.clone();
+        thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stop_receiver.recv_timeout(interval) {
+                if let Err(err) = db.sweep_expired(batch_size) {
+                    error!("expiry sweep failed: {err:?}");
+                }
+            }
+        });
+        *self.expiry_sweeper.lock().unwrap() = Some(stop_sender);
+    }
+
+    /// Start recording every script run and every transaction ending, with the session
+    /// and the principal they belong to, in a key range of the storage separate from the
+    /// relations. Entries are written by a background thread, and those falling outside
+    /// `retention` are removed as new ones come in. Any previous audit log settings are
+    /// replaced, the recorded entries are kept.
+    ///
+    /// The thread holds a reference to the database, so it keeps running until
+    /// [`disable_audit_log`](Self::disable_audit_log) or [`close`](Self::close) is called.
+    pub fn enable_audit_log(&self, retention: AuditRetention) {
+        let (sender, receiver) = unbounded();
+        let db = self.clone();
+        let writer = thread::spawn(move || run_audit_writer(&db.db, receiver, retention));
+        self.sessions.audit.start(sender, writer);
+    }
+
+    /// Stop recording the audit log, once the pending entries are written.
+    /// Returns `false` if the audit log was not enabled.
+    pub fn disable_audit_log(&self) -> bool {
+        self.sessions.audit.stop()
+    }
+
+    /// Deliver the changes of every transaction in the transaction log after `tx_id`,
+    /// oldest first, then those of the transactions committed from now on. Changes are
+    /// delivered at least once: a subscriber that records the `tx_id` of the last changes
+    /// it has processed resumes from there with a new subscription after a restart, as
+    /// long as the log is not truncated past it, see
+    /// [`truncate_tx_log`](Self::truncate_tx_log). Pass zero to start from the oldest
+    /// entry still in the log.
+    ///
+    /// A background thread reads the log for as long as the receiver is kept and the
+    /// database is open. It delivers the changes to relations as they are defined when
+    /// each transaction is read, so changes to relations removed since are left out.
+    pub fn subscribe_from(&self, tx_id: u64) -> Result<Receiver<TxChanges>> {
+        ensure!(self.tx_log.is_enabled(), TxLogNotEnabled);
+        if tx_id > 0 {
+            if let Some(first) = read_tx_log(&self.db, &self.tx_log, tx_id, 1)?.entries.first() {
+                ensure!(first.seq == tx_id + 1, TxLogTruncated(tx_id + 1));
+            }
+        }
+        let (sender, receiver) = bounded(SUBSCRIPTION_BUFFER);
+        let db = self.clone();
+        thread::spawn(move || {
+            let mut cursor = tx_id;
+            loop {
+                let res = db.sessions.ensure_open().and_then(|_| {
+                    let chunk = read_tx_log(&db.db, &db.tx_log, cursor, SUBSCRIPTION_BUFFER)?;
+                    let handles = db
+                        .stored_relations(&db.transact()?)?
+                        .into_iter()
+                        .map(|handle| (handle.id.0, handle))
+                        .collect();
+                    Ok((chunk, handles))
+                });
+                let (chunk, handles) = match res {
+                    Ok(res) => res,
+                    Err(err) => {
+                        if db.sessions.ensure_open().is_ok() {
+                            error!("reading the transaction log failed: {err:?}");
+                        }
+                        return;
+                    }
+                };
+                if chunk.entries.is_empty() {
+                    db.tx_log.wait_after(cursor, Duration::from_secs(1));
+                    continue;
+                }
+                for entry in &chunk.entries {
+                    // left by transactions that failed to commit
+                    if !entry.ops.is_empty()
+                        && sender.send(TxChanges::of_entry(entry, &handles)).is_err()
+                    {
+                        return;
+                    }
+                    cursor = entry.seq;
+                }
+            }
+        });
+        Ok(receiver)
+    }
+
+    /// Stop the background expiry sweeper. Returns `false` if none is running.
+    pub fn stop_expiry_sweeper(&self) -> bool {
+        self.expiry_sweeper.lock().unwrap().take().is_some()
+    }
+
+    /// Start a background thread compacting every stored relation every `interval`,
+    /// in the manner of [`compact_relations`](Self::compact_relations).
+    /// Any previously started schedule is stopped first.
+    pub fn start_compaction_schedule(&self, interval: Duration) {
+        let (stop_sender, stop_receiver) = bounded::<()>(0);
+        let db = self.clone();
+        thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stop_receiver.recv_timeout(interval) {
+                let res = db.compact_relations(&[], |progress| {
+                    debug!(
+                        "compacted {} ({}/{})",
+                        progress.relation, progress.done, progress.total
+                    )
+                });
+                if let Err(err) = res {
+                    error!("scheduled compaction failed: {err:?}");
+                }
+            }
+        });
+        *self.compaction_schedule.lock().unwrap() = Some(stop_sender);
+    }
+
+    /// Stop the background compaction schedule. Returns `false` if none is running.
+    pub fn stop_compaction_schedule(&self) -> bool {
+        self.compaction_schedule.lock().unwrap().take().is_some()
+    }
+}
+
+/// Attach the script to an error raised while running it, unless the error already carries
+/// the source it was raised for, so that its labels render against the script.
+pub(crate) fn with_script_source(err: Report, script: &str) -> Report {
+    if err.source_code().is_some() {
+        err
+    } else {
+        err.with_source_code(format!("{script} "))
+    }
+}
+
 /// Evaluate a string expression in the context of a set of parameters and variables
 pub fn evaluate_expressions(
     src: &str,
@@ -1923,6 +4140,25 @@ impl Poison {
     }
 }
 
+/// The result of a query with `:checksum`: the sum of a 128-bit hash of every row, which
+/// does not depend on the order of the rows, and the number of rows. Rows are hashed as
+/// they come, without being collected.
+fn checksum_rows(rows: impl Iterator<Item = Tuple>) -> NamedRows {
+    let mut checksum = 0u128;
+    let mut n_rows = 0i64;
+    for row in rows {
+        checksum = checksum.wrapping_add(hash128(&row.encode_as_key(RelationId::SYSTEM)));
+        n_rows += 1;
+    }
+    NamedRows::new(
+        vec!["checksum".to_string(), "rows".to_string()],
+        vec![vec![
+            DataValue::from(format!("{checksum:032x}")),
+            DataValue::from(n_rows),
+        ]],
+    )
+}
+
 pub(crate) fn seconds_since_the_epoch() -> Result<f64> {
     #[cfg(not(target_arch = "wasm32"))]
     let now = SystemTime::now();
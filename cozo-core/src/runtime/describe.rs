@@ -0,0 +1,185 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use miette::Result;
+use serde_derive::Serialize;
+
+use crate::data::aggr::BUILTIN_AGGREGATIONS;
+use crate::data::expr::{get_op, BUILTIN_FUNCTIONS};
+use crate::data::relation::ColumnDef;
+use crate::data::tuple::TupleT;
+use crate::data::value::LARGEST_UTF_CHAR;
+use crate::runtime::relation::{GrantMode, RelationHandle, RelationId};
+use crate::runtime::transact::SessionTx;
+use crate::{DataValue, Db, Storage};
+
+/// What [`Db::describe`] returns: everything that can be named in a script.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DbDescription {
+    /// The stored relations, by name
+    pub relations: Vec<RelationDescription>,
+    /// The builtin functions, by name
+    pub functions: Vec<FunctionDescription>,
+    /// The names of the builtin aggregations
+    pub aggregations: Vec<String>,
+    /// The names of the fixed rules, including those registered by the user
+    pub fixed_rules: Vec<String>,
+}
+
+/// A stored relation, as described by [`Db::describe`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RelationDescription {
+    /// The name of the relation
+    pub name: String,
+    /// The documentation set by `::describe`, if any
+    pub description: Option<String>,
+    /// The access level, such as `normal` or `read_only`
+    pub access_level: String,
+    /// The key columns
+    pub keys: Vec<ColumnDescription>,
+    /// The non-key columns
+    pub values: Vec<ColumnDescription>,
+    /// The indices of the relation, by name
+    pub indices: Vec<IndexDescription>,
+}
+
+/// A column of a [`RelationDescription`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ColumnDescription {
+    /// The name of the column
+    pub name: String,
+    /// The type of the column, as written in the schema
+    #[serde(rename = "type")]
+    pub col_type: String,
+    /// Whether the column has a default value
+    pub has_default: bool,
+}
+
+/// An index of a [`RelationDescription`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct IndexDescription {
+    /// The name of the index, queried as `*relation:name`
+    pub name: String,
+    /// One of `normal`, `hnsw`, `fts` and `lsh`
+    pub kind: String,
+    /// The columns the index is keyed by, for normal indices only
+    pub columns: Vec<String>,
+}
+
+/// A builtin function, as described by [`Db::describe`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FunctionDescription {
+    /// The name of the function
+    pub name: String,
+    /// The least number of arguments
+    pub min_arity: usize,
+    /// Whether more arguments than `min_arity` are accepted
+    pub vararg: bool,
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Describe the schema of the database and the builtins of CozoScript in a
+    /// machine-readable form, for editor plugins to complete and check scripts with.
+    ///
+    /// Stored relations come with the documentation set by `::describe`. Columns that
+    /// the current role may not read are left out.
+    pub fn describe(&'s self) -> Result<DbDescription> {
+        let mut tx = self.transact()?;
+        let relations = describe_relations(&tx)?;
+        tx.commit_tx()?;
+        let functions = BUILTIN_FUNCTIONS
+            .iter()
+            .filter_map(|name| {
+                get_op(name).map(|op| FunctionDescription {
+                    name: name.to_string(),
+                    min_arity: op.min_arity,
+                    vararg: op.vararg,
+                })
+            })
+            .collect();
+        Ok(DbDescription {
+            relations,
+            functions,
+            aggregations: BUILTIN_AGGREGATIONS.iter().map(|s| s.to_string()).collect(),
+            fixed_rules: self.fixed_rules.read().unwrap().keys().cloned().collect(),
+        })
+    }
+}
+
+fn describe_relations(tx: &SessionTx<'_>) -> Result<Vec<RelationDescription>> {
+    let lower = vec![DataValue::from("")].encode_as_key(RelationId::SYSTEM);
+    let upper =
+        vec![DataValue::from(String::from(LARGEST_UTF_CHAR))].encode_as_key(RelationId::SYSTEM);
+    let mut ret = vec![];
+    for kv_res in tx.store_tx.range_scan(&lower, &upper) {
+        let (k_slice, v_slice) = kv_res?;
+        if upper <= k_slice {
+            break;
+        }
+        let handle = RelationHandle::decode(&v_slice)?;
+        // indices are described with the relations they index
+        if handle.name.contains(':') {
+            continue;
+        }
+        ret.push(describe_relation(tx, &handle));
+    }
+    Ok(ret)
+}
+
+fn describe_relation(tx: &SessionTx<'_>, handle: &RelationHandle) -> RelationDescription {
+    let columns = |cols: &[ColumnDef]| {
+        cols.iter()
+            .filter(|col| handle.is_granted(tx.role(), &col.name, GrantMode::Read))
+            .map(|col| ColumnDescription {
+                name: col.name.to_string(),
+                col_type: col.typing.to_string(),
+                has_default: col.default_gen.is_some(),
+            })
+            .collect()
+    };
+    let all_columns = handle
+        .metadata
+        .keys
+        .iter()
+        .chain(handle.metadata.non_keys.iter())
+        .collect::<Vec<_>>();
+    let mut indices = vec![];
+    for (name, (_, cols)) in &handle.indices {
+        indices.push(IndexDescription {
+            name: name.to_string(),
+            kind: "normal".to_string(),
+            columns: cols
+                .iter()
+                .filter_map(|i| all_columns.get(*i))
+                .map(|col| col.name.to_string())
+                .collect(),
+        });
+    }
+    let others = [
+        ("hnsw", handle.hnsw_indices.keys().collect::<Vec<_>>()),
+        ("fts", handle.fts_indices.keys().collect()),
+        ("lsh", handle.lsh_indices.keys().collect()),
+    ];
+    for (kind, names) in others {
+        for name in names {
+            indices.push(IndexDescription {
+                name: name.to_string(),
+                kind: kind.to_string(),
+                columns: vec![],
+            });
+        }
+    }
+    RelationDescription {
+        name: handle.name.to_string(),
+        description: (!handle.description.is_empty()).then(|| handle.description.to_string()),
+        access_level: handle.access_level.to_string(),
+        keys: columns(&handle.metadata.keys),
+        values: columns(&handle.metadata.non_keys),
+        indices,
+    }
+}
@@ -0,0 +1,169 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Versions of the logical dump written by [`Db::snapshot_export`], and their migration
+//! to the current version when read.
+//!
+//! Since version 2 the first line of a dump is a header `{"cozo_dump": <version>,
+//! "crate_version": <version of the crate>}`. Dumps of version 1 have no header.
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, Lines, Write};
+
+use miette::{bail, ensure, Diagnostic, IntoDiagnostic, Result};
+use serde_json::json;
+use thiserror::Error;
+
+use crate::data::json::JsonValue;
+use crate::{Db, NamedRows, Storage};
+
+/// The version of the dumps written by [`Db::snapshot_export`].
+pub const DUMP_VERSION: u32 = 2;
+
+const HEADER_KEY: &str = "cozo_dump";
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot read a dump of version {0}, the versions known are 1 to {DUMP_VERSION}")]
+#[diagnostic(code(dump::unsupported_version))]
+#[diagnostic(help("The dump may have been written by a newer version of Cozo"))]
+struct UnsupportedDumpVersion(u64);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Bad line {0} in the dump: {1}")]
+#[diagnostic(code(dump::bad_line))]
+struct BadDumpLine(usize, String);
+
+/// Summary of a dump read by [`Db::snapshot_import`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SnapshotImport {
+    /// The version the dump was written with
+    pub version: u32,
+    /// Names of the imported relations
+    pub relations: Vec<String>,
+    /// Number of imported rows, over all relations
+    pub rows: usize,
+}
+
+pub(crate) fn write_dump_header(writer: &mut impl Write) -> Result<()> {
+    let header = json!({
+        HEADER_KEY: DUMP_VERSION,
+        "crate_version": env!("CARGO_PKG_VERSION"),
+    });
+    serde_json::to_writer(&mut *writer, &header).into_diagnostic()?;
+    writer.write_all(b"\n").into_diagnostic()
+}
+
+/// Bring a line of a dump of version `version` to the current version.
+fn migrate_line(version: u32, mut line: JsonValue) -> JsonValue {
+    for from in version..DUMP_VERSION {
+        line = match from {
+            // version 2 only added the header
+            1 => line,
+            _ => unreachable!(),
+        }
+    }
+    line
+}
+
+/// Reads the lines of a dump of any known version as `(relation, rows)` chunks of the
+/// current version.
+pub(crate) struct DumpReader<R> {
+    lines: Lines<R>,
+    line_no: usize,
+    version: u32,
+    pending: Option<JsonValue>,
+}
+
+impl<R: BufRead> DumpReader<R> {
+    pub(crate) fn new(reader: R) -> Result<Self> {
+        let mut ret = Self {
+            lines: reader.lines(),
+            line_no: 0,
+            version: 1,
+            pending: None,
+        };
+        if let Some(first) = ret.next_line()? {
+            match first.get(HEADER_KEY) {
+                None => ret.pending = Some(first),
+                Some(version) => {
+                    let version = version
+                        .as_u64()
+                        .ok_or_else(|| BadDumpLine(ret.line_no, "bad version".to_string()))?;
+                    ensure!(
+                        (1..=DUMP_VERSION as u64).contains(&version),
+                        UnsupportedDumpVersion(version)
+                    );
+                    ret.version = version as u32;
+                }
+            }
+        }
+        Ok(ret)
+    }
+    pub(crate) fn version(&self) -> u32 {
+        self.version
+    }
+    fn next_line(&mut self) -> Result<Option<JsonValue>> {
+        for line in self.lines.by_ref() {
+            self.line_no += 1;
+            let line = line.into_diagnostic()?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            return match serde_json::from_str(&line) {
+                Ok(value) => Ok(Some(value)),
+                Err(err) => bail!(BadDumpLine(self.line_no, err.to_string())),
+            };
+        }
+        Ok(None)
+    }
+    pub(crate) fn next_chunk(&mut self) -> Result<Option<(String, NamedRows)>> {
+        let line = match self.pending.take() {
+            Some(line) => line,
+            None => match self.next_line()? {
+                Some(line) => line,
+                None => return Ok(None),
+            },
+        };
+        let line = migrate_line(self.version, line);
+        let bad = |msg: String| BadDumpLine(self.line_no, msg);
+        let relation = match line.get("relation").and_then(|r| r.as_str()) {
+            Some(relation) => relation.to_string(),
+            None => bail!(bad("no relation name".to_string())),
+        };
+        let rows = NamedRows::from_json(&line).map_err(|err| bad(err.to_string()))?;
+        Ok(Some((relation, rows)))
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// The version of the dumps written by [`snapshot_export`](Self::snapshot_export),
+    /// see [`DUMP_VERSION`].
+    pub fn dump_version(&self) -> u32 {
+        DUMP_VERSION
+    }
+    /// Load a dump written by [`snapshot_export`](Self::snapshot_export), of the current
+    /// version or an older one, into the relations of the database, which must already
+    /// exist. Each line of the dump is imported by
+    /// [`import_relations`](Self::import_relations) in a transaction of its own, so a
+    /// failure leaves the lines before it imported.
+    pub fn snapshot_import(&'s self, reader: impl BufRead) -> Result<SnapshotImport> {
+        let mut reader = DumpReader::new(reader)?;
+        let mut report = SnapshotImport {
+            version: reader.version(),
+            ..Default::default()
+        };
+        while let Some((relation, rows)) = reader.next_chunk()? {
+            report.rows += rows.rows.len();
+            if !report.relations.contains(&relation) {
+                report.relations.push(relation.clone());
+            }
+            self.import_relations(BTreeMap::from([(relation, rows)]))?;
+        }
+        Ok(report)
+    }
+}
@@ -0,0 +1,343 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Binary encodings of query results.
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use miette::{bail, Diagnostic, Result};
+use thiserror::Error;
+
+use crate::data::json::JsonValue;
+use crate::data::value::{DataValue, Num, Vector};
+use crate::runtime::db::ScriptMutability;
+use crate::{Db, NamedRows, Storage};
+
+/// Turns query results into bytes, see [`Db::run_script_serialized`].
+pub trait ResultSerializer: Send + Sync {
+    /// The MIME type of the output, as sent in a `Content-Type` header
+    fn content_type(&self) -> &str;
+    /// Encode `rows`, including the results chained after them
+    fn serialize_rows(&self, rows: &NamedRows) -> Result<Vec<u8>>;
+}
+
+/// The encodings of query results built in.
+///
+/// Results are encoded with the layout of [`NamedRows::into_json`], a map holding
+/// `headers`, `rows` and `next`. The binary formats keep integers, floats and bytes as
+/// such, whereas JSON turns bytes into base64 strings and non-finite floats into
+/// strings or null. UUIDs become strings and validities `[timestamp, is_assert]` in all
+/// formats.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResultFormat {
+    /// JSON text
+    #[default]
+    Json,
+    /// MessagePack
+    MessagePack,
+    /// CBOR, RFC 8949
+    Cbor,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Unknown result format '{0}'")]
+#[diagnostic(code(format::unknown_format))]
+#[diagnostic(help("The formats are 'json', 'msgpack' and 'cbor'"))]
+struct UnknownFormat(String);
+
+impl FromStr for ResultFormat {
+    type Err = miette::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "json" => ResultFormat::Json,
+            "msgpack" | "messagepack" => ResultFormat::MessagePack,
+            "cbor" => ResultFormat::Cbor,
+            s => bail!(UnknownFormat(s.to_string())),
+        })
+    }
+}
+
+impl ResultFormat {
+    /// The format of a MIME type, such as one given in an `Accept` header.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
+        Some(match mime {
+            "application/json" => ResultFormat::Json,
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                ResultFormat::MessagePack
+            }
+            "application/cbor" => ResultFormat::Cbor,
+            _ => return None,
+        })
+    }
+    /// Encode a JSON value, such as a response of the server, in this format.
+    pub fn serialize_json(&self, value: &JsonValue) -> Vec<u8> {
+        match self {
+            ResultFormat::Json => value.to_string().into_bytes(),
+            ResultFormat::MessagePack => {
+                let mut enc = MsgPack(vec![]);
+                enc.json(value);
+                enc.0
+            }
+            ResultFormat::Cbor => {
+                let mut enc = Cbor(vec![]);
+                enc.json(value);
+                enc.0
+            }
+        }
+    }
+}
+
+impl ResultSerializer for ResultFormat {
+    fn content_type(&self) -> &str {
+        match self {
+            ResultFormat::Json => "application/json",
+            ResultFormat::MessagePack => "application/msgpack",
+            ResultFormat::Cbor => "application/cbor",
+        }
+    }
+    fn serialize_rows(&self, rows: &NamedRows) -> Result<Vec<u8>> {
+        Ok(match self {
+            ResultFormat::Json => rows.clone().into_json().to_string().into_bytes(),
+            ResultFormat::MessagePack => {
+                let mut enc = MsgPack(vec![]);
+                enc.rows(rows);
+                enc.0
+            }
+            ResultFormat::Cbor => {
+                let mut enc = Cbor(vec![]);
+                enc.rows(rows);
+                enc.0
+            }
+        })
+    }
+}
+
+impl NamedRows {
+    /// Encode the rows with `serializer`.
+    pub fn serialize(&self, serializer: &dyn ResultSerializer) -> Result<Vec<u8>> {
+        serializer.serialize_rows(self)
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Run the CozoScript passed in like [`run_script`](Self::run_script), returning
+    /// the result encoded by `serializer`, such as a [`ResultFormat`].
+    pub fn run_script_serialized(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+        serializer: &dyn ResultSerializer,
+    ) -> Result<Vec<u8>> {
+        let rows = self.run_script(payload, params, mutability)?;
+        serializer.serialize_rows(&rows)
+    }
+}
+
+/// The self-describing values common to MessagePack and CBOR.
+trait Encoder {
+    fn null(&mut self);
+    fn bool(&mut self, b: bool);
+    fn int(&mut self, i: i64);
+    fn uint(&mut self, u: u64);
+    fn float(&mut self, f: f64);
+    fn str(&mut self, s: &str);
+    fn bytes(&mut self, b: &[u8]);
+    fn array(&mut self, len: usize);
+    fn map(&mut self, len: usize);
+
+    fn rows(&mut self, rows: &NamedRows) {
+        self.map(3);
+        self.str("headers");
+        self.array(rows.headers.len());
+        for header in &rows.headers {
+            self.str(header);
+        }
+        self.str("rows");
+        self.array(rows.rows.len());
+        for row in &rows.rows {
+            self.array(row.len());
+            for value in row {
+                self.value(value);
+            }
+        }
+        self.str("next");
+        match &rows.next {
+            None => self.null(),
+            Some(next) => self.rows(next),
+        }
+    }
+    fn value(&mut self, value: &DataValue) {
+        match value {
+            DataValue::Null | DataValue::Bot => self.null(),
+            DataValue::Bool(b) => self.bool(*b),
+            DataValue::Num(Num::Int(i)) => self.int(*i),
+            DataValue::Num(Num::Float(f)) => self.float(*f),
+            DataValue::Str(s) => self.str(s),
+            DataValue::Bytes(b) => self.bytes(b),
+            DataValue::Uuid(u) => self.str(&u.0.to_string()),
+            DataValue::Regex(r) => self.str(r.0.as_str()),
+            DataValue::List(l) => {
+                self.array(l.len());
+                for v in l {
+                    self.value(v);
+                }
+            }
+            DataValue::Set(s) => {
+                self.array(s.len());
+                for v in s {
+                    self.value(v);
+                }
+            }
+            DataValue::Vec(Vector::F32(a)) => {
+                self.array(a.len());
+                for f in a {
+                    self.float(*f as f64);
+                }
+            }
+            DataValue::Vec(Vector::F64(a)) => {
+                self.array(a.len());
+                for f in a {
+                    self.float(*f);
+                }
+            }
+            DataValue::Json(j) => self.json(&j.0),
+            DataValue::Validity(v) => {
+                self.array(2);
+                self.int(v.timestamp.0 .0);
+                self.bool(v.is_assert.0);
+            }
+        }
+    }
+    fn json(&mut self, value: &JsonValue) {
+        match value {
+            JsonValue::Null => self.null(),
+            JsonValue::Bool(b) => self.bool(*b),
+            JsonValue::Number(n) => {
+                if let Some(i) = n.as_i64() {
+                    self.int(i)
+                } else if let Some(u) = n.as_u64() {
+                    self.uint(u)
+                } else {
+                    self.float(n.as_f64().unwrap_or(f64::NAN))
+                }
+            }
+            JsonValue::String(s) => self.str(s),
+            JsonValue::Array(a) => {
+                self.array(a.len());
+                for v in a {
+                    self.json(v);
+                }
+            }
+            JsonValue::Object(o) => {
+                self.map(o.len());
+                for (k, v) in o {
+                    self.str(k);
+                    self.json(v);
+                }
+            }
+        }
+    }
+}
+
+struct MsgPack(Vec<u8>);
+
+// writing to a `Vec` cannot fail
+impl Encoder for MsgPack {
+    fn null(&mut self) {
+        rmp::encode::write_nil(&mut self.0).unwrap();
+    }
+    fn bool(&mut self, b: bool) {
+        rmp::encode::write_bool(&mut self.0, b).unwrap();
+    }
+    fn int(&mut self, i: i64) {
+        rmp::encode::write_sint(&mut self.0, i).unwrap();
+    }
+    fn uint(&mut self, u: u64) {
+        rmp::encode::write_uint(&mut self.0, u).unwrap();
+    }
+    fn float(&mut self, f: f64) {
+        rmp::encode::write_f64(&mut self.0, f).unwrap();
+    }
+    fn str(&mut self, s: &str) {
+        rmp::encode::write_str(&mut self.0, s).unwrap();
+    }
+    fn bytes(&mut self, b: &[u8]) {
+        rmp::encode::write_bin(&mut self.0, b).unwrap();
+    }
+    fn array(&mut self, len: usize) {
+        rmp::encode::write_array_len(&mut self.0, len as u32).unwrap();
+    }
+    fn map(&mut self, len: usize) {
+        rmp::encode::write_map_len(&mut self.0, len as u32).unwrap();
+    }
+}
+
+struct Cbor(Vec<u8>);
+
+impl Cbor {
+    /// The initial bytes of an item of major type `major` with argument `n`.
+    fn head(&mut self, major: u8, n: u64) {
+        let major = major << 5;
+        if n < 24 {
+            self.0.push(major | n as u8);
+        } else if n <= u8::MAX as u64 {
+            self.0.extend_from_slice(&[major | 24, n as u8]);
+        } else if n <= u16::MAX as u64 {
+            self.0.push(major | 25);
+            self.0.extend_from_slice(&(n as u16).to_be_bytes());
+        } else if n <= u32::MAX as u64 {
+            self.0.push(major | 26);
+            self.0.extend_from_slice(&(n as u32).to_be_bytes());
+        } else {
+            self.0.push(major | 27);
+            self.0.extend_from_slice(&n.to_be_bytes());
+        }
+    }
+}
+
+impl Encoder for Cbor {
+    fn null(&mut self) {
+        self.0.push(0xf6);
+    }
+    fn bool(&mut self, b: bool) {
+        self.0.push(if b { 0xf5 } else { 0xf4 });
+    }
+    fn int(&mut self, i: i64) {
+        if i >= 0 {
+            self.head(0, i as u64);
+        } else {
+            // negative integers are encoded as -1 - n
+            self.head(1, !(i as u64));
+        }
+    }
+    fn uint(&mut self, u: u64) {
+        self.head(0, u);
+    }
+    fn float(&mut self, f: f64) {
+        self.0.push(0xfb);
+        self.0.extend_from_slice(&f.to_be_bytes());
+    }
+    fn str(&mut self, s: &str) {
+        self.head(3, s.len() as u64);
+        self.0.extend_from_slice(s.as_bytes());
+    }
+    fn bytes(&mut self, b: &[u8]) {
+        self.head(2, b.len() as u64);
+        self.0.extend_from_slice(b);
+    }
+    fn array(&mut self, len: usize) {
+        self.head(4, len as u64);
+    }
+    fn map(&mut self, len: usize) {
+        self.head(5, len as u64);
+    }
+}
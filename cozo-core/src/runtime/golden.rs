@@ -0,0 +1,126 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Golden files of query plans: tests write the plans of queries in a canonical text
+//! form and compare them with the files checked in under `tests/plans`, so that changes
+//! of the planner, such as an index no longer used, show up as diffs.
+//!
+//! Run the tests with `COZO_UPDATE_GOLDEN=1` to write the current plans to the files.
+
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use itertools::Itertools;
+
+use crate::data::json::JsonValue;
+use crate::{DataValue, DbInstance, NamedRows};
+
+const UPDATE_VAR: &str = "COZO_UPDATE_GOLDEN";
+
+/// The plan of `::explain`, one line per atom:
+///
+/// ```text
+/// <stratum>.<rule>[<rule index>].<atom index> <op> [<ref>] [on <joins>] [where <filters>]
+///     [= <unified expression>] -> [<out>]
+/// ```
+pub(crate) fn plan_text(explained: &NamedRows) -> String {
+    let col = |name: &str| explained.headers.iter().position(|h| h == name).unwrap();
+    let [stratum, rule_idx, rule, atom_idx, op, ref_name, joins_on, filters, out] = [
+        "stratum",
+        "rule_idx",
+        "rule",
+        "atom_idx",
+        "op",
+        "ref",
+        "joins_on",
+        "filters/expr",
+        "out_relation",
+    ]
+    .map(col);
+    let mut ret = String::new();
+    for row in &explained.rows {
+        let json = |i: usize| JsonValue::from(row[i].clone());
+        let text = |i: usize| match json(i) {
+            JsonValue::String(s) => s,
+            v => v.to_string(),
+        };
+        write!(
+            ret,
+            "{}.{}[{}].{} {}",
+            text(stratum),
+            text(rule),
+            text(rule_idx),
+            text(atom_idx),
+            text(op)
+        )
+        .unwrap();
+        if row[ref_name] != DataValue::Null {
+            write!(ret, " {}", text(ref_name)).unwrap();
+        }
+        if let JsonValue::Object(joins) = json(joins_on) {
+            let joins = joins
+                .iter()
+                .map(|(l, r)| format!("{l}={}", r.as_str().unwrap_or_default()))
+                .join(", ");
+            write!(ret, " on {joins}").unwrap();
+        }
+        match json(filters) {
+            JsonValue::Null => {}
+            JsonValue::Array(fs) if fs.is_empty() => {}
+            JsonValue::Array(fs) => {
+                let fs = fs.iter().map(|f| f.as_str().unwrap_or_default()).join(", ");
+                write!(ret, " where {fs}").unwrap();
+            }
+            // the expression bound by a unification
+            v => write!(ret, " = {}", v.as_str().unwrap_or_default()).unwrap(),
+        }
+        let out = match json(out) {
+            JsonValue::Array(bindings) => bindings
+                .iter()
+                .map(|b| b.as_str().unwrap_or_default().to_string())
+                .join(", "),
+            _ => String::new(),
+        };
+        writeln!(ret, " -> [{out}]").unwrap();
+    }
+    ret
+}
+
+/// Check the plan of `query` against the golden file `tests/plans/<name>.plan`, or
+/// write the file when `COZO_UPDATE_GOLDEN` is set.
+pub(crate) fn assert_golden_plan(db: &DbInstance, name: &str, query: &str) {
+    let explained = db
+        .run_default(&format!("::explain {{ {query} }}"))
+        .unwrap_or_else(|err| panic!("cannot explain the query of plan '{name}': {err}"));
+    let mut actual = query
+        .trim()
+        .lines()
+        .map(|line| format!("# {}\n", line.trim()))
+        .collect::<String>();
+    actual.push_str(&plan_text(&explained));
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/plans")
+        .join(format!("{name}.plan"));
+    if std::env::var_os(UPDATE_VAR).is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|err| {
+        panic!(
+            "cannot read {}: {err}, run with {UPDATE_VAR}=1 to write it",
+            path.display()
+        )
+    });
+    assert!(
+        expected == actual,
+        "the plan '{name}' differs from {}, run with {UPDATE_VAR}=1 to accept it\n\
+        --- expected\n{expected}+++ actual\n{actual}",
+        path.display()
+    );
+}
@@ -0,0 +1,338 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! The database as it was at earlier points of the transaction log, see
+//! [`Db::schema_diff`] and [`Db::diff`].
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use miette::{ensure, Diagnostic, Result};
+use thiserror::Error;
+
+use crate::data::tuple::{decode_tuple_from_key, Tuple, TupleT};
+use crate::data::value::{DataValue, LARGEST_UTF_CHAR};
+use crate::runtime::relation::{extend_tuple_from_v, RelationHandle, RelationId};
+use crate::runtime::chunked::CHUNK_JOURNAL_KEY_MARKER;
+use crate::runtime::tx_log::{read_tx_log, TxLogEntry, TxOp};
+use crate::storage::{Storage, StoreTx};
+use crate::Db;
+
+/// Entries of the transaction log read at a time.
+const HISTORY_CHUNK: usize = 1000;
+
+/// The relations and indices whose definition changed between two points of the
+/// transaction log, by name, see [`Db::schema_diff`]. Indices are named
+/// `relation:index`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SchemaDiff {
+    /// Defined at the second point only
+    pub added: Vec<String>,
+    /// Defined at the first point only
+    pub removed: Vec<String>,
+    /// Defined at both points with different columns
+    pub redefined: Vec<String>,
+    /// Defined at both points with the same columns, but with other parts of the
+    /// definition changed, such as triggers, indices, access level or grants, or
+    /// replaced by a relation of the same columns
+    pub altered: Vec<String>,
+}
+
+impl SchemaDiff {
+    /// Whether the definitions are the same at both points.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.redefined.is_empty()
+            && self.altered.is_empty()
+    }
+}
+
+/// Which rows [`Db::diff`] compares.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DiffFilter {
+    /// Names of the relations compared, all of them if empty
+    pub relations: BTreeSet<String>,
+    /// Only the rows whose keys start with these values are compared, for following a
+    /// single entity
+    pub key_prefix: Vec<DataValue>,
+}
+
+/// A row of a stored relation that differs between two points of the transaction log,
+/// see [`Db::diff`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowDiff {
+    /// Name of the relation
+    pub relation: String,
+    /// The keys of the row
+    pub key: Tuple,
+    /// The row at the first point, with all columns, or `None` if there was no such row
+    pub before: Option<Tuple>,
+    /// The row at the second point, with all columns, or `None` if there is no such row
+    /// any more
+    pub after: Option<Tuple>,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The transaction log no longer holds transaction {0}")]
+#[diagnostic(code(db::history_truncated))]
+#[diagnostic(help("Earlier states of the database are only known from an untruncated log"))]
+pub(crate) struct HistoryTruncated(pub(crate) u64);
+
+fn handle_key_bounds() -> (Vec<u8>, Vec<u8>) {
+    let lower = vec![DataValue::from("")].encode_as_key(RelationId::SYSTEM);
+    let upper =
+        vec![DataValue::from(String::from(LARGEST_UTF_CHAR))].encode_as_key(RelationId::SYSTEM);
+    (lower, upper)
+}
+
+/// The id of the relation holding the row stored under `key`, if it holds a row.
+fn row_relation_id(key: &[u8]) -> Option<u64> {
+    if key.len() < 8 || key[0] >= CHUNK_JOURNAL_KEY_MARKER {
+        return None;
+    }
+    let id = u64::from_be_bytes(key[..8].try_into().unwrap());
+    (id != RelationId::SYSTEM.0).then_some(id)
+}
+
+/// A decoded row of `handle` from its key and value.
+fn decode_row(handle: &RelationHandle, key: &[u8], val: &[u8]) -> Tuple {
+    let mut tuple = decode_tuple_from_key(key, handle.metadata.keys.len());
+    extend_tuple_from_v(&mut tuple, val);
+    tuple
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Compare the definitions of the stored relations and indices after transaction
+    /// `from` of the transaction log with those after transaction `to`, zero meaning
+    /// before the first logged transaction. Passing the current last sequence number
+    /// compares with the database as it is now, and `from` may come after `to` to get the
+    /// changes the other way round, so that two environments can be checked for drift
+    /// from the same history.
+    ///
+    /// The definitions at each point are worked out from the current ones and the changes
+    /// recorded by the log, which must not have been truncated. Definitions written by a
+    /// logged transaction are taken not to exist before it, so the log should have been
+    /// enabled since the database was created.
+    pub fn schema_diff(&'s self, from: u64, to: u64) -> Result<SchemaDiff> {
+        let (lower, upper) = handle_key_bounds();
+        let is_handle_key = |key: &[u8]| lower.as_slice() <= key && key < upper.as_slice();
+        let mut current = BTreeMap::new();
+        {
+            let tx = self.db.transact(false)?;
+            for kv in tx.range_scan(&lower, &upper) {
+                let (k, v) = kv?;
+                current.insert(k, v);
+            }
+        }
+        // the value of each handle key after the last write to it up to each point, and
+        // the keys any logged transaction wrote to
+        let mut at_from: BTreeMap<Vec<u8>, Option<Vec<u8>>> = BTreeMap::new();
+        let mut at_to: BTreeMap<Vec<u8>, Option<Vec<u8>>> = BTreeMap::new();
+        let mut written: BTreeSet<Vec<u8>> = BTreeSet::new();
+        self.for_each_logged(0, u64::MAX, |entry| {
+            let mut changes: Vec<(Vec<u8>, Option<Vec<u8>>)> = vec![];
+            for op in &entry.ops {
+                match op {
+                    TxOp::Put(key, val) if is_handle_key(key) => {
+                        changes.push((key.clone(), Some(val.clone())))
+                    }
+                    TxOp::Del(key) if is_handle_key(key) => changes.push((key.clone(), None)),
+                    TxOp::DelRange(l, u) if *l < upper && lower < *u => {
+                        let keys: BTreeSet<_> = current
+                            .keys()
+                            .chain(written.iter())
+                            .chain(changes.iter().map(|(key, _)| key))
+                            .filter(|key| l <= *key && *key < u)
+                            .cloned()
+                            .collect();
+                        changes.extend(keys.into_iter().map(|key| (key, None)));
+                    }
+                    _ => {}
+                }
+            }
+            for (key, val) in changes {
+                if entry.seq <= from {
+                    at_from.insert(key.clone(), val.clone());
+                }
+                if entry.seq <= to {
+                    at_to.insert(key.clone(), val);
+                }
+                written.insert(key);
+            }
+            Ok(())
+        })?;
+        let state = |at: &BTreeMap<Vec<u8>, Option<Vec<u8>>>, key: &Vec<u8>| -> Result<_> {
+            let val = match at.get(key) {
+                Some(val) => val.as_ref(),
+                None if written.contains(key) => None,
+                None => current.get(key),
+            };
+            val.map(|val| RelationHandle::decode(val)).transpose()
+        };
+        let mut diff = SchemaDiff::default();
+        let keys: BTreeSet<_> = current.keys().chain(written.iter()).collect();
+        for key in keys {
+            let name = match decode_tuple_from_key(key, 1).first() {
+                Some(DataValue::Str(name)) => name.to_string(),
+                _ => continue,
+            };
+            match (state(&at_from, key)?, state(&at_to, key)?) {
+                (None, Some(_)) => diff.added.push(name),
+                (Some(_), None) => diff.removed.push(name),
+                (Some(a), Some(b)) if a.metadata != b.metadata => diff.redefined.push(name),
+                (Some(a), Some(b)) if a != b => diff.altered.push(name),
+                _ => {}
+            }
+        }
+        Ok(diff)
+    }
+
+    /// The rows of stored relations that differ between after transaction `from` of the
+    /// transaction log and after transaction `to`, zero meaning before the first logged
+    /// transaction, restricted to those selected by `filter`. Rows are grouped by
+    /// relation and come in key order within each relation; `from` may come after `to`
+    /// to get the changes the other way round. Rows of indices are left out, and so are
+    /// those of relations whose definition is neither in the log nor in the database.
+    ///
+    /// Only the changes recorded by the log are read, so its size rather than that of
+    /// the database bounds the work done. As for [`schema_diff`](Self::schema_diff), the
+    /// log must not have been truncated, and rows written by a logged transaction are
+    /// taken not to exist before it.
+    pub fn diff(&'s self, from: u64, to: u64, filter: &DiffFilter) -> Result<Vec<RowDiff>> {
+        let (lo, hi) = (from.min(to), from.max(to));
+        let (lower, upper) = handle_key_bounds();
+        let is_handle_key = |key: &[u8]| lower.as_slice() <= key && key < upper.as_slice();
+        let mut handles: BTreeMap<u64, RelationHandle> = BTreeMap::new();
+        // the rows written after `lo`, with their values at `hi`, and the key ranges
+        // deleted after `lo`
+        let mut at_hi: BTreeMap<Vec<u8>, Option<Vec<u8>>> = BTreeMap::new();
+        let mut ranges: Vec<(Vec<u8>, Vec<u8>)> = vec![];
+        self.for_each_logged(lo, hi, |entry| {
+            for op in &entry.ops {
+                match op {
+                    // creating a relation first puts its id under its name
+                    TxOp::Put(key, val) if is_handle_key(key) => {
+                        if let Ok(handle) = RelationHandle::decode(val) {
+                            handles.insert(handle.id.0, handle);
+                        }
+                    }
+                    TxOp::Put(key, val) if row_relation_id(key).is_some() => {
+                        at_hi.insert(key.clone(), Some(val.clone()));
+                    }
+                    TxOp::Del(key) if row_relation_id(key).is_some() => {
+                        at_hi.insert(key.clone(), None);
+                    }
+                    TxOp::DelRange(l, u) if l < u => {
+                        for (_, val) in at_hi.range_mut(l.clone()..u.clone()) {
+                            *val = None;
+                        }
+                        ranges.push((l.clone(), u.clone()));
+                    }
+                    _ => {}
+                }
+            }
+            Ok(())
+        })?;
+        // the values at `lo` of the rows written after it, and of those deleted by ranges
+        let deleted_by_range = |key: &Vec<u8>| ranges.iter().any(|(l, u)| l <= key && key < u);
+        let mut at_lo: BTreeMap<Vec<u8>, Option<Vec<u8>>> = BTreeMap::new();
+        self.for_each_logged(0, lo, |entry| {
+            for op in &entry.ops {
+                match op {
+                    TxOp::Put(key, val) if is_handle_key(key) => {
+                        if let Ok(handle) = RelationHandle::decode(val) {
+                            handles.entry(handle.id.0).or_insert(handle);
+                        }
+                    }
+                    TxOp::Put(key, val) if at_hi.contains_key(key) || deleted_by_range(key) => {
+                        at_lo.insert(key.clone(), Some(val.clone()));
+                    }
+                    TxOp::Del(key) if at_lo.contains_key(key) => {
+                        at_lo.insert(key.clone(), None);
+                    }
+                    TxOp::DelRange(l, u) if l < u => {
+                        for (_, val) in at_lo.range_mut(l.clone()..u.clone()) {
+                            *val = None;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Ok(())
+        })?;
+        for handle in self.stored_relations(&self.transact()?)? {
+            handles.entry(handle.id.0).or_insert(handle);
+        }
+
+        let keys: BTreeSet<_> = at_hi
+            .keys()
+            .chain(
+                at_lo
+                    .iter()
+                    .filter(|(_, val)| val.is_some())
+                    .map(|(key, _)| key),
+            )
+            .collect();
+        let mut ret = vec![];
+        for key in keys {
+            let handle = match row_relation_id(key).and_then(|id| handles.get(&id)) {
+                Some(handle) if !handle.name.contains(':') => handle,
+                _ => continue,
+            };
+            if !filter.relations.is_empty() && !filter.relations.contains(handle.name.as_str()) {
+                continue;
+            }
+            let row_key = decode_tuple_from_key(key, handle.metadata.keys.len());
+            if !row_key.starts_with(&filter.key_prefix) {
+                continue;
+            }
+            let mut before = at_lo.get(key).cloned().flatten();
+            let mut after = at_hi.get(key).cloned().flatten();
+            if before == after {
+                continue;
+            }
+            if from > to {
+                std::mem::swap(&mut before, &mut after);
+            }
+            ret.push(RowDiff {
+                relation: handle.name.to_string(),
+                key: row_key,
+                before: before.map(|val| decode_row(handle, key, &val)),
+                after: after.map(|val| decode_row(handle, key, &val)),
+            });
+        }
+        Ok(ret)
+    }
+
+    /// Call `f` on the entries of the transaction log after `after` up to and including
+    /// `up_to`, oldest first, failing if the log no longer holds the first of them.
+    fn for_each_logged(
+        &'s self,
+        after: u64,
+        up_to: u64,
+        mut f: impl FnMut(&TxLogEntry) -> Result<()>,
+    ) -> Result<()> {
+        let mut cursor = after;
+        while cursor < up_to {
+            let chunk = read_tx_log(&self.db, &self.tx_log, cursor, HISTORY_CHUNK)?;
+            if cursor == after {
+                if let Some(first) = chunk.entries.first() {
+                    ensure!(first.seq == after + 1, HistoryTruncated(after + 1));
+                }
+            }
+            let Some(last) = chunk.last_seq() else {
+                break;
+            };
+            for entry in chunk.entries.iter().take_while(|entry| entry.seq <= up_to) {
+                f(entry)?;
+            }
+            cursor = last;
+        }
+        Ok(())
+    }
+}
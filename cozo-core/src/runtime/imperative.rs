@@ -7,7 +7,6 @@
  */
 
 use std::collections::{BTreeMap, BTreeSet};
-use std::sync::atomic::Ordering;
 
 use either::{Either, Left, Right};
 use itertools::Itertools;
@@ -20,10 +19,12 @@ use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationM
 use crate::data::symb::Symbol;
 use crate::parse::{ImperativeCondition, ImperativeProgram, ImperativeStmt, SourceSpan};
 use crate::runtime::callback::CallbackCollector;
-use crate::runtime::db::{seconds_since_the_epoch, RunningQueryCleanup, RunningQueryHandle};
+use crate::runtime::db::{
+    seconds_since_the_epoch, Principal, ReadOnlyViolation, RunningQueryHandle,
+};
 use crate::runtime::relation::InputRelationHandle;
 use crate::runtime::transact::SessionTx;
-use crate::{DataValue, Db, NamedRows, Poison, Storage, ValidityTs};
+use crate::{DataValue, Db, NamedRows, Poison, Storage, TxDurability, ValidityTs};
 
 enum ControlCode {
     Termination(NamedRows),
@@ -254,9 +255,12 @@ impl<'s, S: Storage<'s>> Db<S> {
     }
     pub(crate) fn execute_imperative(
         &'s self,
+        script: &str,
+        principal: Option<&Principal>,
         cur_vld: ValidityTs,
         ps: &ImperativeProgram,
         readonly: bool,
+        durability: TxDurability,
     ) -> Result<NamedRows, Report> {
         let mut callback_collector = BTreeMap::new();
         let mut write_lock_names = BTreeSet::new();
@@ -264,7 +268,9 @@ impl<'s, S: Storage<'s>> Db<S> {
             p.needs_write_locks(&mut write_lock_names);
         }
         if readonly && !write_lock_names.is_empty() {
-            bail!("Read-only imperative program attempted to acquire write locks");
+            bail!(ReadOnlyViolation(
+                "Read-only imperative program attempted to acquire write locks"
+            ));
         }
         let is_write = !write_lock_names.is_empty();
         let write_lock = self.obtain_relation_locks(write_lock_names.iter());
@@ -279,24 +285,21 @@ impl<'s, S: Storage<'s>> Db<S> {
         let ret;
         {
             let mut tx = if is_write {
-                self.transact_write()?
+                self.transact_write_with(durability)?
             } else {
                 self.transact()?
             };
+            tx.session.start_query(script, principal)?;
 
             let poison = Poison::default();
-            let qid = self.queries_count.fetch_add(1, Ordering::AcqRel);
             let since_the_epoch = seconds_since_the_epoch()?;
 
             let q_handle = RunningQueryHandle {
                 started_at: since_the_epoch,
                 poison: poison.clone(),
             };
-            self.running_queries.lock().unwrap().insert(qid, q_handle);
-            let _guard = RunningQueryCleanup {
-                id: qid,
-                running_queries: self.running_queries.clone(),
-            };
+            let _guard = self.running_queries.register(q_handle);
+            tx.session.attach(&poison)?;
 
             match self.execute_imperative_stmts(
                 ps,
@@ -342,7 +345,7 @@ impl<'s, S: Storage<'s>> Db<S> {
 impl SessionTx<'_> {
     fn script_store_as_relation<'s, S: Storage<'s>>(
         &mut self,
-        db: &Db<S>,
+        db: &'s Db<S>,
         name: &str,
         rels: &NamedRows,
         cur_vld: ValidityTs,
@@ -0,0 +1,459 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Maintenance jobs run in the background in small transactions, see
+//! [`Db::start_job`](crate::Db::start_job).
+
+use std::collections::{BTreeMap, VecDeque};
+use std::iter;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use itertools::Itertools;
+use miette::{bail, Result};
+use smartstring::SmartString;
+
+use crate::data::tuple::{Tuple, TupleT};
+use crate::data::value::DataValue;
+use crate::runtime::db::{expiry_condition, seconds_since_the_epoch};
+use crate::runtime::relation::{
+    decode_tuple_from_kv, extend_tuple_from_v, IndexNotFound, RelationHandle,
+};
+use crate::storage::Storage;
+use crate::Db;
+
+/// A maintenance operation run by [`Db::start_job`](crate::Db::start_job).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MaintenanceJob {
+    /// Remove the rows of `relation` for which `condition` is true, an expression over
+    /// the columns of the relation such as `at < 1700000000`
+    Retract {
+        /// The stored relation
+        relation: String,
+        /// The condition of the rows to remove
+        condition: String,
+    },
+    /// Remove the expired rows of every relation with an expiry column, as
+    /// [`Db::sweep_expired`](crate::Db::sweep_expired) does
+    SweepExpired,
+    /// Bring the index `index` of `relation` up to date with the rows of the relation,
+    /// as rebuilding it would: the missing entries are added, then the entries of rows
+    /// that are gone are removed. Only indices created by `::index create` are supported.
+    Reindex {
+        /// The stored relation
+        relation: String,
+        /// The name of the index, without the name of the relation
+        index: String,
+    },
+}
+
+/// How a job started by [`Db::start_job`](crate::Db::start_job) is run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JobOptions {
+    /// Rows handled by each transaction of the job
+    pub batch_size: usize,
+    /// The pause between two transactions, limiting the share of the database the job
+    /// takes from other work
+    pub interval: Duration,
+}
+
+impl Default for JobOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: 1000,
+            interval: Duration::from_millis(10),
+        }
+    }
+}
+
+/// The state of a job, see [`JobStatus`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobState {
+    /// The job runs a transaction or waits for the next one
+    Running,
+    /// The job waits for [`Db::resume_job`](crate::Db::resume_job)
+    Paused,
+    /// The job has done all its work
+    Done,
+    /// The job was stopped by [`Db::cancel_job`](crate::Db::cancel_job) or by closing
+    /// the database
+    Cancelled,
+    /// A transaction of the job failed, with the error given. The transactions before
+    /// it stay committed.
+    Failed(String),
+}
+
+/// The status of a job, returned by [`Db::job_status`](crate::Db::job_status).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobStatus {
+    /// The id of the job, returned by [`Db::start_job`](crate::Db::start_job)
+    pub id: u64,
+    /// What the job does
+    pub job: MaintenanceJob,
+    /// Where the job is at
+    pub state: JobState,
+    /// Transactions committed so far
+    pub batches: u64,
+    /// Rows handled so far: removed by retractions, read from the relation or from the
+    /// index when reindexing
+    pub rows: u64,
+}
+
+pub(crate) struct JobControl {
+    status: Mutex<JobStatus>,
+    /// Notified when the state of the job is changed from outside
+    changed: Condvar,
+}
+
+/// The jobs started on a database, finished ones included.
+#[derive(Default)]
+pub(crate) struct JobRegistry {
+    last_id: AtomicU64,
+    jobs: Mutex<BTreeMap<u64, Arc<JobControl>>>,
+}
+
+impl JobRegistry {
+    fn add(&self, job: MaintenanceJob) -> Arc<JobControl> {
+        let id = self.last_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let control = Arc::new(JobControl {
+            status: Mutex::new(JobStatus {
+                id,
+                job,
+                state: JobState::Running,
+                batches: 0,
+                rows: 0,
+            }),
+            changed: Condvar::new(),
+        });
+        self.jobs.lock().unwrap().insert(id, control.clone());
+        control
+    }
+    pub(crate) fn status(&self, id: u64) -> Option<JobStatus> {
+        let control = self.jobs.lock().unwrap().get(&id).cloned()?;
+        let status = control.status.lock().unwrap().clone();
+        Some(status)
+    }
+    pub(crate) fn list(&self) -> Vec<JobStatus> {
+        let jobs = self.jobs.lock().unwrap().values().cloned().collect_vec();
+        jobs.iter()
+            .map(|control| control.status.lock().unwrap().clone())
+            .collect()
+    }
+    /// Move job `id` to `to` if it is in one of the states `from`.
+    pub(crate) fn transition(&self, id: u64, from: &[JobState], to: JobState) -> bool {
+        let Some(control) = self.jobs.lock().unwrap().get(&id).cloned() else {
+            return false;
+        };
+        let mut status = control.status.lock().unwrap();
+        if !from.contains(&status.state) {
+            return false;
+        }
+        status.state = to;
+        control.changed.notify_all();
+        true
+    }
+    pub(crate) fn cancel_all(&self) {
+        let ids = self.jobs.lock().unwrap().keys().copied().collect_vec();
+        for id in ids {
+            self.transition(
+                id,
+                &[JobState::Running, JobState::Paused],
+                JobState::Cancelled,
+            );
+        }
+    }
+}
+
+enum ReindexPhase {
+    /// Adding the entries of the rows after the cursor
+    Rows,
+    /// Removing the entries after the cursor that match no row
+    Entries,
+}
+
+/// The work left of a job.
+enum JobRun {
+    Retract {
+        handle: Box<RelationHandle>,
+        condition: String,
+        finished: bool,
+    },
+    SweepExpired {
+        relations: VecDeque<RelationHandle>,
+    },
+    Reindex {
+        relation: String,
+        index: String,
+        phase: ReindexPhase,
+        /// The smallest key not yet handled
+        cursor: Option<Vec<u8>>,
+    },
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    fn plan_job(&'s self, job: &MaintenanceJob) -> Result<JobRun> {
+        Ok(match job {
+            MaintenanceJob::Retract {
+                relation,
+                condition,
+            } => JobRun::Retract {
+                handle: Box::new(self.transact()?.get_relation(relation, false)?),
+                condition: condition.clone(),
+                finished: false,
+            },
+            MaintenanceJob::SweepExpired => JobRun::SweepExpired {
+                relations: self.expiring_relations()?.into(),
+            },
+            MaintenanceJob::Reindex { relation, index } => {
+                let handle = self.transact()?.get_relation(relation, false)?;
+                if !handle.indices.contains_key(index.as_str()) {
+                    bail!(IndexNotFound(index.clone(), relation.clone()))
+                }
+                JobRun::Reindex {
+                    relation: relation.clone(),
+                    index: index.clone(),
+                    phase: ReindexPhase::Rows,
+                    cursor: Some(vec![]),
+                }
+            }
+        })
+    }
+
+    /// Run the next transaction of `run`, returning the rows it handled, or `None` if
+    /// there is no work left.
+    fn run_job_batch(&'s self, run: &mut JobRun, batch_size: usize) -> Result<Option<usize>> {
+        match run {
+            JobRun::Retract {
+                handle,
+                condition,
+                finished,
+            } => {
+                if *finished {
+                    return Ok(None);
+                }
+                let removed =
+                    self.retract_where(handle, condition, Default::default(), batch_size)?;
+                *finished = removed < batch_size;
+                Ok(Some(removed))
+            }
+            JobRun::SweepExpired { relations } => {
+                let Some(handle) = relations.front() else {
+                    return Ok(None);
+                };
+                let params = BTreeMap::from([(
+                    "now".to_string(),
+                    DataValue::from(seconds_since_the_epoch()?),
+                )]);
+                let removed =
+                    self.retract_where(handle, &expiry_condition(handle), params, batch_size)?;
+                if removed < batch_size {
+                    relations.pop_front();
+                }
+                Ok(Some(removed))
+            }
+            JobRun::Reindex {
+                relation,
+                index,
+                phase,
+                cursor,
+            } => {
+                let Some(lower) = cursor.take() else {
+                    return Ok(None);
+                };
+                let (handled, next) =
+                    self.reindex_batch(relation, index, phase, lower, batch_size)?;
+                *cursor = next;
+                Ok(Some(handled))
+            }
+        }
+    }
+
+    /// Handle the rows or entries from `lower` onwards, returning how many were handled
+    /// and where to continue from, if anything is left.
+    fn reindex_batch(
+        &'s self,
+        relation: &str,
+        index: &str,
+        phase: &mut ReindexPhase,
+        lower: Vec<u8>,
+        batch_size: usize,
+    ) -> Result<(usize, Option<Vec<u8>>)> {
+        let name = SmartString::from(relation);
+        let locks = self.obtain_relation_locks(iter::once(&name));
+        let _guards = locks.iter().map(|l| l.write().unwrap()).collect_vec();
+        let mut tx = self.transact_write()?;
+        let handle = tx.get_relation(relation, false)?;
+        let Some((idx_handle, extractor)) = handle.indices.get(index) else {
+            bail!(IndexNotFound(index.to_string(), relation.to_string()))
+        };
+        let scanned = match phase {
+            ReindexPhase::Rows => &handle,
+            ReindexPhase::Entries => idx_handle,
+        };
+        let lower = lower.max(Tuple::default().encode_as_key(scanned.id));
+        let upper = Tuple::default().encode_as_key(scanned.id.next());
+        let batch = tx
+            .store_tx
+            .range_scan(&lower, &upper)
+            .take(batch_size)
+            .collect::<Result<Vec<_>>>()?;
+        match phase {
+            ReindexPhase::Rows => {
+                for (k, v) in &batch {
+                    let row = decode_tuple_from_kv(k, v, Some(handle.arity()));
+                    let entry = extractor.iter().map(|i| row[*i].clone()).collect_vec();
+                    let key = idx_handle.encode_key_for_store(&entry, Default::default())?;
+                    if !tx.store_tx.exists(&key, false)? {
+                        tx.store_tx.put(&key, &[])?;
+                    }
+                }
+            }
+            ReindexPhase::Entries => {
+                let n_keys = handle.metadata.keys.len();
+                let key_positions = (0..n_keys)
+                    .map(|k| extractor.iter().position(|i| *i == k).unwrap())
+                    .collect_vec();
+                for (k, v) in &batch {
+                    let entry = decode_tuple_from_kv(k, v, Some(extractor.len()));
+                    let mut row = key_positions
+                        .iter()
+                        .map(|i| entry[*i].clone())
+                        .collect_vec();
+                    let encoded = handle.encode_key_for_store(&row, Default::default())?;
+                    let matches = match tx.store_tx.get(&encoded, false)? {
+                        None => false,
+                        Some(val) => {
+                            extend_tuple_from_v(&mut row, &val);
+                            extractor.iter().map(|i| &row[*i]).eq(entry.iter())
+                        }
+                    };
+                    if !matches {
+                        tx.store_tx.del(k)?;
+                    }
+                }
+            }
+        }
+        tx.commit_tx()?;
+        let next = match batch.last() {
+            Some((last, _)) if batch.len() == batch_size => {
+                let mut next = last.clone();
+                next.push(0);
+                Some(next)
+            }
+            // the phase is done
+            _ => match phase {
+                ReindexPhase::Rows => {
+                    *phase = ReindexPhase::Entries;
+                    Some(vec![])
+                }
+                ReindexPhase::Entries => None,
+            },
+        };
+        Ok((batch.len(), next))
+    }
+
+    /// Pause the job `id`, once its current transaction is done. Returns `false` if the
+    /// job is not running.
+    pub fn pause_job(&'s self, id: u64) -> bool {
+        self.jobs
+            .transition(id, &[JobState::Running], JobState::Paused)
+    }
+
+    /// Resume the job `id` paused by [`pause_job`](Self::pause_job). Returns `false` if
+    /// the job is not paused.
+    pub fn resume_job(&'s self, id: u64) -> bool {
+        self.jobs
+            .transition(id, &[JobState::Paused], JobState::Running)
+    }
+
+    /// Stop the job `id`, once its current transaction is done. The transactions it has
+    /// committed are kept. Returns `false` if the job is not running or paused.
+    pub fn cancel_job(&'s self, id: u64) -> bool {
+        self.jobs.transition(
+            id,
+            &[JobState::Running, JobState::Paused],
+            JobState::Cancelled,
+        )
+    }
+
+    /// The status of the job `id`, `None` if there is no such job.
+    pub fn job_status(&'s self, id: u64) -> Option<JobStatus> {
+        self.jobs.status(id)
+    }
+
+    /// The status of every job started since the database was opened, oldest first.
+    pub fn list_jobs(&'s self) -> Vec<JobStatus> {
+        self.jobs.list()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<S> Db<S>
+where
+    S: for<'s> Storage<'s> + 'static,
+{
+    /// Start `job` on a background thread, which runs it in transactions handling at
+    /// most `options.batch_size` rows each, pausing `options.interval` between two of
+    /// them, so that the job never blocks other transactions for long. Returns the id
+    /// of the job, to follow it with [`job_status`](Self::job_status), and to pause,
+    /// resume or cancel it. Fails if the relations or the index of the job do not exist.
+    ///
+    /// The rows removed by a job go through `:rm`, so indices, triggers and callbacks
+    /// are kept up to date.
+    pub fn start_job(&self, job: MaintenanceJob, options: JobOptions) -> Result<u64> {
+        self.sessions.ensure_open()?;
+        let mut run = self.plan_job(&job)?;
+        let control = self.jobs.add(job);
+        let id = control.status.lock().unwrap().id;
+        let batch_size = options.batch_size.max(1);
+        let db = self.clone();
+        std::thread::spawn(move || loop {
+            {
+                let mut status = control.status.lock().unwrap();
+                while status.state == JobState::Paused {
+                    status = control.changed.wait(status).unwrap();
+                }
+                if status.state != JobState::Running {
+                    return;
+                }
+            }
+            let res = db
+                .sessions
+                .ensure_open()
+                .and_then(|_| db.run_job_batch(&mut run, batch_size));
+            let mut status = control.status.lock().unwrap();
+            match res {
+                Ok(Some(rows)) => {
+                    status.batches += 1;
+                    status.rows += rows as u64;
+                }
+                Ok(None) => {
+                    if matches!(status.state, JobState::Running | JobState::Paused) {
+                        status.state = JobState::Done;
+                    }
+                    return;
+                }
+                Err(err) => {
+                    if matches!(status.state, JobState::Running | JobState::Paused) {
+                        status.state = JobState::Failed(err.to_string());
+                    }
+                    return;
+                }
+            }
+            // pausing or cancelling the job ends the wait
+            let _ = control
+                .changed
+                .wait_timeout_while(status, options.interval, |status| {
+                    status.state == JobState::Running
+                })
+                .unwrap();
+        });
+        Ok(id)
+    }
+}
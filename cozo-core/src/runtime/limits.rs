@@ -0,0 +1,97 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::sync::{Arc, RwLock};
+
+use miette::{bail, Diagnostic, Result};
+use thiserror::Error;
+
+/// Called after every iteration of the evaluation of a query, see
+/// [`Db::set_eval_limits`](crate::Db::set_eval_limits).
+pub type EvalProgressCallback = Box<dyn Fn(&EvalProgress) + Send + Sync>;
+
+/// Limits on the evaluation of every query of a database, see
+/// [`Db::set_eval_limits`](crate::Db::set_eval_limits). Limits left at `None` are not
+/// enforced.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EvalLimits {
+    /// Iterations deriving new rows in the fixed-point evaluation of a stratum of a
+    /// query. Rules that are not recursive derive all their rows in the first one.
+    pub max_iterations: Option<u32>,
+    /// Rows the rules of a single query may hold while it is evaluated
+    pub max_derived_rows: Option<usize>,
+}
+
+/// The progress of the evaluation of a query after an iteration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalProgress {
+    /// The session evaluating the query, as listed by
+    /// [`Db::list_sessions`](crate::Db::list_sessions)
+    pub session_id: u64,
+    /// The stratum evaluated, counting from zero
+    pub stratum: usize,
+    /// The iteration of the stratum just done, counting from zero
+    pub iteration: u32,
+    /// Rows derived in the iteration that were not derived before
+    pub delta_rows: usize,
+    /// Rows held by the rules of the query
+    pub total_rows: usize,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Stratum {stratum} of the query did not reach a fixed point in {limit} iterations")]
+#[diagnostic(code(eval::iteration_limit_exceeded))]
+#[diagnostic(help("The limit is set by the embedder with `Db::set_eval_limits`"))]
+pub(crate) struct IterationLimitExceeded {
+    stratum: usize,
+    limit: u32,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The rules of the query derived more than {0} rows")]
+#[diagnostic(code(eval::derived_rows_limit_exceeded))]
+#[diagnostic(help("The limit is set by the embedder with `Db::set_eval_limits`"))]
+pub(crate) struct DerivedRowsLimitExceeded(usize);
+
+/// The evaluation limits of a database and its progress callback.
+#[derive(Default)]
+pub(crate) struct EvalGuard {
+    settings: RwLock<(EvalLimits, Option<Arc<EvalProgressCallback>>)>,
+}
+
+impl EvalGuard {
+    pub(crate) fn configure(&self, limits: EvalLimits, progress: Option<EvalProgressCallback>) {
+        *self.settings.write().unwrap() = (limits, progress.map(Arc::new));
+    }
+    pub(crate) fn limits(&self) -> EvalLimits {
+        self.settings.read().unwrap().0
+    }
+    /// Report `progress`, then check it against the limits given that another iteration
+    /// is needed if `changed`.
+    pub(crate) fn after_iteration(&self, progress: EvalProgress, changed: bool) -> Result<()> {
+        let (limits, callback) = self.settings.read().unwrap().clone();
+        if let Some(callback) = callback {
+            callback(&progress);
+        }
+        if let Some(max) = limits.max_derived_rows {
+            if progress.total_rows > max {
+                bail!(DerivedRowsLimitExceeded(max))
+            }
+        }
+        if let Some(max) = limits.max_iterations {
+            // the iteration after the last one allowed may only find that nothing is new
+            if changed && progress.iteration >= max {
+                bail!(IterationLimitExceeded {
+                    stratum: progress.stratum,
+                    limit: max
+                })
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::fmt::Write;
+
+use crate::runtime::stripes::{current_stripe, Striped, StripedCounter};
+
+/// Upper bounds, in seconds, of the buckets of the query latency histogram.
+pub const LATENCY_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0,
+];
+
+/// Counters updated by the running database, snapshotted by [`Db::metrics`](crate::Db::metrics).
+/// They are striped, as every transaction updates them.
+#[derive(Default)]
+pub(crate) struct MetricsRegistry {
+    pub(crate) transactions_committed: StripedCounter,
+    pub(crate) transactions_aborted: StripedCounter,
+    pub(crate) commit_conflicts: StripedCounter,
+    pub(crate) rows_written: StripedCounter,
+    pub(crate) queries_failed: StripedCounter,
+    query_latency: Striped<Histogram>,
+}
+
+impl MetricsRegistry {
+    pub(crate) fn observe_query(&self, secs: f64, ok: bool) {
+        if !ok {
+            self.queries_failed.add(1);
+        }
+        self.query_latency.lock(current_stripe()).observe(secs);
+    }
+    pub(crate) fn snapshot(
+        &self,
+        tokenizer_cache_hits: u64,
+        tokenizer_cache_misses: u64,
+    ) -> Metrics {
+        let mut query_latency = Histogram::default();
+        self.query_latency
+            .for_each(|stripe| query_latency.merge(stripe));
+        Metrics {
+            transactions_committed: self.transactions_committed.get(),
+            transactions_aborted: self.transactions_aborted.get(),
+            commit_conflicts: self.commit_conflicts.get(),
+            rows_written: self.rows_written.get(),
+            queries_failed: self.queries_failed.get(),
+            query_latency,
+            tokenizer_cache_hits,
+            tokenizer_cache_misses,
+        }
+    }
+}
+
+/// A snapshot of the counters of a database, returned by [`Db::metrics`](crate::Db::metrics).
+/// All counters start at zero when the database is opened.
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize)]
+pub struct Metrics {
+    /// Transactions committed, including read-only ones
+    pub transactions_committed: u64,
+    /// Transactions that ended without being committed, by an error or an explicit abort
+    pub transactions_aborted: u64,
+    /// Commits that failed because of a conflict with a concurrent transaction. Cozo does not
+    /// retry them by itself, so this is the number of retries left to the application.
+    pub commit_conflicts: u64,
+    /// Rows put into, updated in or removed from stored relations by committed transactions
+    pub rows_written: u64,
+    /// Scripts that returned an error, also counted in `query_latency`
+    pub queries_failed: u64,
+    /// Time taken to run scripts, in seconds
+    pub query_latency: Histogram,
+    /// Full-text search tokenizers found in the cache
+    pub tokenizer_cache_hits: u64,
+    /// Full-text search tokenizers that had to be built
+    pub tokenizer_cache_misses: u64,
+}
+
+/// A histogram of observed values, see [`LATENCY_BUCKETS`].
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize)]
+pub struct Histogram {
+    /// Number of observations up to each bucket bound, not cumulative. The last entry counts
+    /// the observations above the last bound.
+    pub buckets: Vec<u64>,
+    /// Sum of all observed values
+    pub sum: f64,
+    /// Number of observations
+    pub count: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: vec![0; LATENCY_BUCKETS.len() + 1],
+            sum: 0.,
+            count: 0,
+        }
+    }
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        let idx = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[idx] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+    fn merge(&mut self, other: &Histogram) {
+        for (n, m) in self.buckets.iter_mut().zip(&other.buckets) {
+            *n += m;
+        }
+        self.sum += other.sum;
+        self.count += other.count;
+    }
+}
+
+impl Metrics {
+    /// Format the metrics in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let counters = [
+            (
+                "cozo_transactions_committed_total",
+                "Transactions committed",
+                self.transactions_committed,
+            ),
+            (
+                "cozo_transactions_aborted_total",
+                "Transactions ended without a commit",
+                self.transactions_aborted,
+            ),
+            (
+                "cozo_commit_conflicts_total",
+                "Commits failed by a conflict with a concurrent transaction",
+                self.commit_conflicts,
+            ),
+            (
+                "cozo_rows_written_total",
+                "Rows written to stored relations",
+                self.rows_written,
+            ),
+            (
+                "cozo_queries_failed_total",
+                "Scripts that returned an error",
+                self.queries_failed,
+            ),
+            (
+                "cozo_tokenizer_cache_hits_total",
+                "Tokenizers found in the cache",
+                self.tokenizer_cache_hits,
+            ),
+            (
+                "cozo_tokenizer_cache_misses_total",
+                "Tokenizers built on a cache miss",
+                self.tokenizer_cache_misses,
+            ),
+        ];
+        for (name, help, value) in counters {
+            let _ = writeln!(
+                out,
+                "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}"
+            );
+        }
+        let name = "cozo_query_duration_seconds";
+        let _ = writeln!(
+            out,
+            "# HELP {name} Time taken to run scripts\n# TYPE {name} histogram"
+        );
+        let mut cumulative = 0;
+        for (bound, n) in LATENCY_BUCKETS.iter().zip(&self.query_latency.buckets) {
+            cumulative += n;
+            let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+        }
+        let _ = writeln!(
+            out,
+            "{name}_bucket{{le=\"+Inf\"}} {}\n{name}_sum {}\n{name}_count {}",
+            self.query_latency.count, self.query_latency.sum, self.query_latency.count
+        );
+        out
+    }
+}
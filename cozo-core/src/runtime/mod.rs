@@ -6,13 +6,47 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+pub(crate) mod attach;
+pub(crate) mod audit;
+pub(crate) mod batch;
 pub(crate) mod callback;
+pub(crate) mod check;
+pub(crate) mod chunked;
+pub(crate) mod constraint;
+pub(crate) mod crdt;
+pub(crate) mod dataset;
 pub(crate) mod db;
+pub(crate) mod describe;
+pub(crate) mod dump;
+pub(crate) mod format;
+pub(crate) mod history;
 pub(crate) mod imperative;
+pub(crate) mod jobs;
+pub(crate) mod limits;
+pub(crate) mod metrics;
+pub(crate) mod plan_guard;
+pub(crate) mod procedure;
+pub(crate) mod property_graph;
+pub(crate) mod quota;
+pub(crate) mod rdf;
 pub(crate) mod relation;
+#[cfg(feature = "scheduler")]
+pub(crate) mod scheduler;
+pub(crate) mod schema_cache;
+pub(crate) mod slow_log;
+pub(crate) mod snapshot;
+#[cfg(feature = "sql-import")]
+pub(crate) mod sql_import;
+pub(crate) mod stats;
+pub(crate) mod stripes;
 pub(crate) mod temp_store;
 pub(crate) mod transact;
+pub(crate) mod tx_log;
+pub(crate) mod upgrade;
+pub(crate) mod why;
 pub(crate) mod hnsw;
 pub(crate) mod minhash_lsh;
 #[cfg(test)]
+pub(crate) mod golden;
+#[cfg(test)]
 mod tests;
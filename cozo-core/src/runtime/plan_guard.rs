@@ -0,0 +1,320 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Rejecting query plans estimated to be too costly before they are evaluated, see
+//! [`Db::set_plan_limits`].
+
+use std::collections::BTreeMap;
+
+use miette::{bail, Diagnostic, Result};
+use thiserror::Error;
+
+use crate::data::program::{MagicFixedRuleRuleArg, MagicSymbol};
+use crate::data::symb::{Symbol, PROG_ENTRY};
+use crate::parse::SourceSpan;
+use crate::query::compile::{CompiledProgram, CompiledRuleSet};
+use crate::query::ra::RelAlgebra;
+use crate::runtime::relation::RelationHandle;
+use crate::runtime::stats::RelationAnalysis;
+use crate::runtime::transact::SessionTx;
+use crate::storage::Storage;
+use crate::Db;
+
+/// The fraction of rows a filter is taken to keep.
+const FILTER_SELECTIVITY: f64 = 0.5;
+
+/// Limits on the estimated cost and output of the plan of every query, see
+/// [`Db::set_plan_limits`]. Limits left at `None` are not enforced.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PlanLimits {
+    /// Rows the scans and joins of the plan may produce in total
+    pub max_cost: Option<f64>,
+    /// Rows the query may return
+    pub max_rows: Option<f64>,
+}
+
+impl PlanLimits {
+    /// The limits the standalone server enforces by default: a million rows returned,
+    /// and a hundred million rows produced.
+    pub fn server_default() -> Self {
+        Self {
+            max_cost: Some(1e8),
+            max_rows: Some(1e6),
+        }
+    }
+}
+
+/// The estimates of a plan checked against the [`PlanLimits`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct PlanEstimate {
+    /// Rows the scans and joins of the plan are estimated to produce in total
+    pub(crate) cost: f64,
+    /// Rows the query is estimated to return
+    pub(crate) rows: f64,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The plan of the query is estimated to {what} {estimate:.0} rows, more than the limit of {limit:.0}")]
+#[diagnostic(code(eval::plan_limit_exceeded))]
+#[diagnostic(help(
+    "Check for atoms sharing no variable, whose rows are all combined. \
+    To run the query anyway, add the option `:disable_plan_limits`, \
+    which sessions restricted to a role cannot use"
+))]
+struct PlanLimitExceeded {
+    what: &'static str,
+    estimate: f64,
+    limit: f64,
+}
+
+struct Estimator<'a, 't> {
+    tx: &'a SessionTx<'t>,
+    analyzed: &'a BTreeMap<String, RelationAnalysis>,
+    /// The rows of the analyzed relation of a handle, scaled as the engine estimates
+    scaled_rows: &'a dyn Fn(&RelationHandle, &RelationAnalysis) -> Result<Option<f64>>,
+    stored: BTreeMap<String, f64>,
+    rules: BTreeMap<MagicSymbol, f64>,
+    cost: f64,
+}
+
+impl<'a, 't> Estimator<'a, 't> {
+    /// The rows of a stored relation, without reading them. Relations that were not
+    /// analyzed are unknown and taken to be empty, so they never make a plan exceed
+    /// the limits.
+    fn stored_rows(&mut self, handle: &RelationHandle) -> Result<f64> {
+        let name = handle.name.to_string();
+        if let Some(rows) = self.stored.get(&name) {
+            return Ok(*rows);
+        }
+        let rows = match self.analyzed.get(&name) {
+            Some(analysis) => {
+                (self.scaled_rows)(handle, analysis)?.unwrap_or(analysis.rows as f64)
+            }
+            None => 0.,
+        };
+        self.stored.insert(name, rows);
+        Ok(rows)
+    }
+
+    /// The rows found by looking up a value of the first key column of `rel`: those of
+    /// a value if the relation was analyzed, otherwise one.
+    fn fan_out(&mut self, rel: &RelAlgebra) -> Result<f64> {
+        let handle = match rel {
+            RelAlgebra::Stored(r) => &r.storage,
+            RelAlgebra::StoredWithValidity(r) => &r.storage,
+            _ => return Ok(1.),
+        };
+        let Some(analysis) = self.analyzed.get(handle.name.as_str()) else {
+            return Ok(1.);
+        };
+        let first = handle.metadata.keys[0].name.as_str();
+        Ok(match analysis.histograms.get(first) {
+            Some(h) if h.distinct > 0 => (h.rows as f64 / h.distinct as f64).max(1.),
+            _ => 1.,
+        })
+    }
+
+    fn filtered(rows: f64, filters: usize) -> f64 {
+        if filters == 0 {
+            rows
+        } else {
+            rows * FILTER_SELECTIVITY
+        }
+    }
+
+    /// The rows `rel` produces, adding them to the cost unless `looked_up`, when only the
+    /// rows joined are read.
+    fn relation(&mut self, rel: &RelAlgebra, looked_up: bool) -> Result<f64> {
+        let rows = match rel {
+            RelAlgebra::Fixed(r) => r.data.len() as f64,
+            RelAlgebra::TempStore(r) => Self::filtered(
+                self.rules.get(&r.storage_key).copied().unwrap_or(1.),
+                r.filters.len(),
+            ),
+            RelAlgebra::Stored(r) => {
+                Self::filtered(self.stored_rows(&r.storage)?, r.filters.len())
+            }
+            RelAlgebra::StoredWithValidity(r) => {
+                Self::filtered(self.stored_rows(&r.storage)?, r.filters.len())
+            }
+            RelAlgebra::Join(join) => {
+                let left = self.relation(&join.left, false)?;
+                if join.left.is_unit() {
+                    return self.relation(&join.right, looked_up);
+                }
+                let prefix = join.join_type().ends_with("prefix_join");
+                let right = self.relation(&join.right, prefix)?;
+                let rows = if join.joiner.left_keys.is_empty() {
+                    left * right
+                } else if prefix {
+                    left * self.fan_out(&join.right)?
+                } else {
+                    left.max(right)
+                };
+                self.cost += rows;
+                return Ok(rows);
+            }
+            RelAlgebra::NegJoin(join) => {
+                let left = self.relation(&join.left, false)?;
+                let prefix = join.join_type().ends_with("prefix_join");
+                self.relation(&join.right, prefix)?;
+                return Ok(left);
+            }
+            RelAlgebra::Reorder(r) => return self.relation(&r.relation, looked_up),
+            RelAlgebra::Filter(r) => {
+                let rows = self.relation(&r.parent, looked_up)?;
+                return Ok(Self::filtered(rows, r.filters.len()));
+            }
+            RelAlgebra::Unification(r) => return self.relation(&r.parent, looked_up),
+            RelAlgebra::HnswSearch(r) => return self.relation(&r.parent, looked_up),
+            RelAlgebra::FtsSearch(r) => return self.relation(&r.parent, looked_up),
+            RelAlgebra::LshSearch(r) => return self.relation(&r.parent, looked_up),
+        };
+        if !looked_up {
+            self.cost += rows;
+        }
+        Ok(rows)
+    }
+
+    fn rule_set(&mut self, rule_set: &CompiledRuleSet) -> Result<f64> {
+        match rule_set {
+            CompiledRuleSet::Rules(rules) => {
+                let mut rows = 0.;
+                for rule in rules {
+                    rows += self.relation(&rule.relation, false)?;
+                }
+                Ok(rows)
+            }
+            // the output of fixed rules is taken to be as large as their inputs
+            CompiledRuleSet::Fixed(fixed) => {
+                let mut rows = 0.;
+                for arg in &fixed.rule_args {
+                    rows += match arg {
+                        MagicFixedRuleRuleArg::InMem { name, .. } => {
+                            self.rules.get(name).copied().unwrap_or(1.)
+                        }
+                        MagicFixedRuleRuleArg::Stored { name, .. } => {
+                            let handle = self.tx.get_relation(name, false)?;
+                            self.stored_rows(&handle)?
+                        }
+                    };
+                }
+                Ok(rows.max(1.))
+            }
+        }
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Reject the plans of queries estimated to return more rows than
+    /// `limits.max_rows`, or to produce more rows in their scans and joins than
+    /// `limits.max_cost`, with an `eval::plan_limit_exceeded` error, before they are
+    /// evaluated. This protects shared databases from queries that accidentally combine
+    /// all rows of relations sharing no variable. A query with the option
+    /// `:disable_plan_limits` is not checked, but the option fails in sessions
+    /// restricted to a role.
+    ///
+    /// Estimates take the rows of stored relations from [`Db::analyze`], scaled by how
+    /// much their sizes estimated by the engine have changed since, and never read the
+    /// data. Relations that were not analyzed are taken to be empty, so only the
+    /// relations analyzed can make a plan exceed the limits. Estimates assume that joins
+    /// on shared variables produce as many rows as their larger side, and filters keep half
+    /// of the rows. Joins looking up the keys of a stored relation find as many rows per
+    /// lookup as the analysis of the relation finds per value of its first key, or one if
+    /// it was not analyzed. Recursive rules are not expanded. The limit on returned rows does not
+    /// apply to queries writing to a stored relation without `:returning`. The limits are
+    /// off by default; calling this again replaces them.
+    pub fn set_plan_limits(&self, limits: PlanLimits) {
+        *self.plan_limits.write().unwrap() = limits;
+    }
+
+    /// The limits set by [`set_plan_limits`](Self::set_plan_limits).
+    pub fn plan_limits(&self) -> PlanLimits {
+        *self.plan_limits.read().unwrap()
+    }
+
+    pub(crate) fn estimate_plan(
+        &'s self,
+        tx: &SessionTx<'_>,
+        strata: &[CompiledProgram],
+    ) -> Result<PlanEstimate> {
+        let analyzed = self.analysis.lock().unwrap().clone();
+        let scaled_rows = |handle: &RelationHandle, analysis: &RelationAnalysis| {
+            self.scaled_rows(handle, analysis)
+        };
+        let mut estimator = Estimator {
+            tx,
+            analyzed: &analyzed,
+            scaled_rows: &scaled_rows,
+            stored: Default::default(),
+            rules: Default::default(),
+            cost: 0.,
+        };
+        let mut cost = 0.;
+        for stratum in strata {
+            // a first pass estimates the rules referred to before they are estimated,
+            // and only the cost of the second one is counted
+            for _ in 0..2 {
+                estimator.cost = 0.;
+                for (name, rule_set) in stratum {
+                    let rows = estimator.rule_set(rule_set)?;
+                    estimator.rules.insert(name.clone(), rows);
+                }
+            }
+            cost += estimator.cost;
+        }
+        let entry = MagicSymbol::Muggle {
+            inner: Symbol::new(PROG_ENTRY, SourceSpan(0, 0)),
+        };
+        Ok(PlanEstimate {
+            cost,
+            rows: estimator.rules.get(&entry).copied().unwrap_or(0.),
+        })
+    }
+
+    /// Check the plan `strata` against the limits set by
+    /// [`set_plan_limits`](Self::set_plan_limits). `returns_rows` is false for queries
+    /// writing to stored relations without returning the rows, and `limit` is the
+    /// number of rows the query takes at most.
+    pub(crate) fn check_plan_limits(
+        &'s self,
+        tx: &SessionTx<'_>,
+        strata: &[CompiledProgram],
+        returns_rows: bool,
+        limit: Option<usize>,
+    ) -> Result<()> {
+        let limits = self.plan_limits();
+        if limits.max_cost.is_none() && limits.max_rows.is_none() {
+            return Ok(());
+        }
+        let estimate = self.estimate_plan(tx, strata)?;
+        if let Some(max) = limits.max_cost {
+            if estimate.cost > max {
+                bail!(PlanLimitExceeded {
+                    what: "produce",
+                    estimate: estimate.cost,
+                    limit: max,
+                })
+            }
+        }
+        if let (Some(max), true) = (limits.max_rows, returns_rows) {
+            let rows = match limit {
+                Some(limit) => estimate.rows.min(limit as f64),
+                None => estimate.rows,
+            };
+            if rows > max {
+                bail!(PlanLimitExceeded {
+                    what: "return",
+                    estimate: rows,
+                    limit: max,
+                })
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Named scripts taking declared parameters, run in one transaction, see
+//! [`Db::register_procedure`].
+
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Result};
+use thiserror::Error;
+
+use crate::data::functions::current_validity;
+use crate::data::value::DataValue;
+use crate::parse::parse_script;
+use crate::runtime::db::Principal;
+use crate::storage::Storage;
+use crate::{Db, NamedRows, ScriptMutability};
+
+/// A procedure registered with [`Db::register_procedure`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Procedure {
+    /// The name the procedure is called by
+    pub name: String,
+    /// The parameters of the script, all of which must be passed to calls
+    pub params: Vec<String>,
+    /// The script, referring to the parameters as `$name`
+    pub script: String,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("No procedure named '{0}' is registered")]
+#[diagnostic(code(db::procedure_not_found))]
+struct ProcedureNotFound(String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Procedure '{name}' takes the parameters [{}], but was called with [{}]",
+    .params.join(", "), .args.join(", "))]
+#[diagnostic(code(db::procedure_bad_args))]
+struct BadProcedureArgs {
+    name: String,
+    params: Vec<String>,
+    args: Vec<String>,
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Register the procedure `name`: a script taking the parameters `params`, which
+    /// [`call_procedure`](Self::call_procedure) runs server-side with the arguments of the
+    /// call. The script is CozoScript, so it combines queries, writes and the control flow
+    /// of imperative scripts, such as `%if` and `%loop`, and all of it runs in a single
+    /// transaction that is committed only if the whole script succeeds. It is sandboxed as
+    /// any script is: it cannot reach the host other than through the fixed rules, and is
+    /// bounded by the limits of [`set_eval_limits`](Self::set_eval_limits).
+    ///
+    /// Registering fails if the script does not parse with every parameter bound, or if
+    /// a procedure of the same name is registered. Procedures are not persisted.
+    pub fn register_procedure(&'s self, name: &str, params: &[&str], script: &str) -> Result<()> {
+        if let Some(dup) = params.iter().duplicates().next() {
+            bail!("The parameter '{dup}' of procedure '{name}' is declared twice")
+        }
+        let bound = params
+            .iter()
+            .map(|p| (p.to_string(), DataValue::Null))
+            .collect();
+        parse_script(
+            script,
+            &bound,
+            &self.fixed_rules.read().unwrap(),
+            current_validity(),
+            self.string_literals_forbidden.load(Ordering::Relaxed),
+        )?;
+        match self.procedures.write().unwrap().entry(name.to_string()) {
+            Entry::Vacant(ent) => {
+                ent.insert(Procedure {
+                    name: name.to_string(),
+                    params: params.iter().map(|p| p.to_string()).collect(),
+                    script: script.to_string(),
+                });
+                Ok(())
+            }
+            Entry::Occupied(ent) => {
+                bail!(
+                    "A procedure with the name {} is already registered",
+                    ent.key()
+                )
+            }
+        }
+    }
+
+    /// Unregister the procedure `name`, returning `false` if there is no such procedure.
+    pub fn unregister_procedure(&'s self, name: &str) -> bool {
+        self.procedures.write().unwrap().remove(name).is_some()
+    }
+
+    /// The registered procedures, ordered by name.
+    pub fn procedures(&'s self) -> Vec<Procedure> {
+        self.procedures.read().unwrap().values().cloned().collect()
+    }
+
+    /// Run the procedure `name` with `args`, which must bind exactly its parameters, as
+    /// [`run_script`](Self::run_script) runs a script. Returns the result of the script.
+    pub fn call_procedure(
+        &'s self,
+        name: &str,
+        args: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Result<NamedRows> {
+        let script = self.procedure_script(name, &args)?;
+        self.run_script(&script, args, mutability)
+    }
+
+    /// Run the procedure `name` on behalf of `principal`, as
+    /// [`run_script_as`](Self::run_script_as) runs a script, see
+    /// [`call_procedure`](Self::call_procedure).
+    pub fn call_procedure_as(
+        &'s self,
+        principal: &Principal,
+        name: &str,
+        args: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Result<NamedRows> {
+        let script = self.procedure_script(name, &args)?;
+        self.run_script_as(principal, &script, args, mutability)
+    }
+
+    fn procedure_script(
+        &'s self,
+        name: &str,
+        args: &BTreeMap<String, DataValue>,
+    ) -> Result<String> {
+        let procedures = self.procedures.read().unwrap();
+        let Some(procedure) = procedures.get(name) else {
+            bail!(ProcedureNotFound(name.to_string()))
+        };
+        let matches = args.len() == procedure.params.len()
+            && procedure.params.iter().all(|p| args.contains_key(p));
+        if !matches {
+            bail!(BadProcedureArgs {
+                name: name.to_string(),
+                params: procedure.params.clone(),
+                args: args.keys().cloned().collect(),
+            })
+        }
+        Ok(procedure.script.clone())
+    }
+}
@@ -0,0 +1,454 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Import of the node and relationship CSV files of property-graph stores, see
+//! [`Db::import_property_graph`].
+
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Result};
+use thiserror::Error;
+
+use crate::data::value::DataValue;
+use crate::storage::Storage;
+use crate::{Db, ScriptMutability};
+
+/// Separator of the values of labels and array properties.
+const ARRAY_DELIMITER: char = ';';
+
+/// Columns of the relations of nodes and edges not holding properties.
+const NODE_COLUMNS: [&str; 2] = ["id", "labels"];
+const EDGE_COLUMNS: [&str; 3] = ["start", "type", "end"];
+
+/// The numbers of rows written by [`Db::import_property_graph`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PropertyGraphImport {
+    /// Rows of the node files
+    pub nodes: usize,
+    /// Rows of the relationship files
+    pub edges: usize,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Bad {kind} file {file} at line {line}: {message}")]
+#[diagnostic(code(import::property_graph))]
+struct PropertyGraphError {
+    kind: &'static str,
+    file: usize,
+    line: u64,
+    message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PropType {
+    Int,
+    Float,
+    Bool,
+    String,
+}
+
+impl PropType {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "int" | "long" | "short" | "byte" => PropType::Int,
+            "float" | "double" => PropType::Float,
+            "boolean" => PropType::Bool,
+            // temporal and spatial values are kept in their textual form
+            "string" | "char" | "date" | "localtime" | "time" | "localdatetime" | "datetime"
+            | "duration" | "point" => PropType::String,
+            _ => return None,
+        })
+    }
+    fn coerce(self, s: &str) -> Option<DataValue> {
+        Some(match self {
+            PropType::Int => DataValue::from(s.trim().parse::<i64>().ok()?),
+            PropType::Float => DataValue::from(s.trim().parse::<f64>().ok()?),
+            PropType::Bool => match s.trim().to_ascii_lowercase().as_str() {
+                "true" => DataValue::from(true),
+                "false" => DataValue::from(false),
+                _ => return None,
+            },
+            PropType::String => DataValue::from(s),
+        })
+    }
+    fn column_type(self, array: bool) -> String {
+        let name = match self {
+            PropType::Int => "Int",
+            PropType::Float => "Float",
+            PropType::Bool => "Bool",
+            PropType::String => "String",
+        };
+        if array {
+            format!("[{name}]?")
+        } else {
+            format!("{name}?")
+        }
+    }
+}
+
+/// A field of the header of a CSV file, as `name:type`.
+#[derive(Debug, Clone, PartialEq)]
+enum Field {
+    /// The id of the node, also stored as the property `name` if there is one other
+    /// than `id`
+    Id(Option<String>),
+    Labels,
+    StartId,
+    EndId,
+    Type,
+    Ignore,
+    Property {
+        name: String,
+        typ: PropType,
+        array: bool,
+    },
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_field(field: &str) -> std::result::Result<Field, String> {
+    let (name, typ) = match field.rfind(':') {
+        Some(i) => (&field[..i], &field[i + 1..]),
+        None => (field, "string"),
+    };
+    // the group of an id space, as in `:ID(Person)`, is not kept
+    let typ = match typ.find('(') {
+        Some(i) if typ.ends_with(')') => &typ[..i],
+        _ => typ,
+    };
+    let field = match typ.to_ascii_uppercase().as_str() {
+        "ID" => Field::Id((!name.is_empty()).then(|| name.to_string())),
+        "LABEL" => Field::Labels,
+        "START_ID" => Field::StartId,
+        "END_ID" => Field::EndId,
+        "TYPE" => Field::Type,
+        "IGNORE" => Field::Ignore,
+        _ => {
+            let (typ, array) = match typ.strip_suffix("[]") {
+                Some(typ) => (typ, true),
+                None => (typ, false),
+            };
+            let typ = PropType::parse(typ).ok_or_else(|| format!("unknown type '{typ}'"))?;
+            Field::Property {
+                name: name.to_string(),
+                typ,
+                array,
+            }
+        }
+    };
+    match &field {
+        Field::Id(Some(name)) | Field::Property { name, .. } if !is_identifier(name) => {
+            Err(format!("'{name}' is not a valid column name"))
+        }
+        _ => Ok(field),
+    }
+}
+
+/// The rows of the files of nodes or of relationships, and the columns of their
+/// properties.
+struct Parsed {
+    kind: &'static str,
+    reserved: &'static [&'static str],
+    properties: BTreeMap<String, (PropType, bool)>,
+    rows: Vec<(Vec<DataValue>, BTreeMap<String, DataValue>)>,
+}
+
+impl Parsed {
+    fn new(kind: &'static str, reserved: &'static [&'static str]) -> Self {
+        Self {
+            kind,
+            reserved,
+            properties: BTreeMap::new(),
+            rows: vec![],
+        }
+    }
+
+    fn error<T>(&self, file: usize, line: u64, message: impl Into<String>) -> Result<T> {
+        bail!(PropertyGraphError {
+            kind: self.kind,
+            file: file + 1,
+            line,
+            message: message.into(),
+        })
+    }
+
+    fn add_property(&mut self, file: usize, name: &str, typ: PropType, array: bool) -> Result<()> {
+        if self.reserved.contains(&name) {
+            return self.error(
+                file,
+                1,
+                format!("the property '{name}' is a reserved column"),
+            );
+        }
+        match self.properties.get(name) {
+            Some(existing) if *existing != (typ, array) => self.error(
+                file,
+                1,
+                format!("the property '{name}' has a different type in another file"),
+            ),
+            _ => {
+                self.properties.insert(name.to_string(), (typ, array));
+                Ok(())
+            }
+        }
+    }
+
+    /// Parse a file, whose fixed columns are those of `fixed`, in this order.
+    fn parse_file(&mut self, file: usize, data: &str, fixed: &[Field]) -> Result<()> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .from_reader(data.as_bytes());
+        let headers = match reader.headers() {
+            Ok(headers) => headers.clone(),
+            Err(err) => return self.error(file, 1, err.to_string()),
+        };
+        let mut fields = vec![];
+        for header in headers.iter() {
+            match parse_field(header) {
+                Ok(field) => fields.push(field),
+                Err(err) => return self.error(file, 1, err),
+            }
+        }
+        for field in &fields {
+            match field {
+                Field::Property { name, typ, array } => {
+                    self.add_property(file, name, *typ, *array)?
+                }
+                Field::Id(Some(name)) if name != "id" && fixed.contains(&Field::Id(None)) => {
+                    self.add_property(file, name, PropType::String, false)?
+                }
+                Field::Ignore => {}
+                Field::Id(_) if !fixed.contains(&Field::Id(None)) => {
+                    return self.error(file, 1, "relationships have no :ID")
+                }
+                Field::Id(_) => {}
+                field if !fixed.contains(field) => {
+                    return self.error(file, 1, format!("unexpected field {field:?}"))
+                }
+                _ => {}
+            }
+        }
+        for required in fixed {
+            let present = fields.iter().any(|field| match (field, required) {
+                (Field::Id(_), Field::Id(_)) => true,
+                (field, required) => field == required,
+            });
+            if !present && *required != Field::Labels {
+                return self.error(file, 1, format!("missing field {required:?}"));
+            }
+        }
+
+        for record in reader.records() {
+            let record = match record {
+                Ok(record) => record,
+                Err(err) => {
+                    let line = err.position().map_or(0, |pos| pos.line());
+                    return self.error(file, line, err.to_string());
+                }
+            };
+            let line = record.position().map_or(0, |pos| pos.line());
+            let mut keys = fixed
+                .iter()
+                .map(|field| match field {
+                    Field::Labels => DataValue::List(vec![]),
+                    _ => DataValue::Null,
+                })
+                .collect_vec();
+            let mut properties = BTreeMap::new();
+            for (field, value) in fields.iter().zip(record.iter()) {
+                let slot = fixed.iter().position(|f| match (f, field) {
+                    (Field::Id(_), Field::Id(_)) => true,
+                    (f, field) => f == field,
+                });
+                if let Some(slot) = slot {
+                    keys[slot] = match field {
+                        Field::Labels => DataValue::List(
+                            value
+                                .split(ARRAY_DELIMITER)
+                                .filter(|label| !label.is_empty())
+                                .map(DataValue::from)
+                                .collect(),
+                        ),
+                        _ if value.is_empty() => {
+                            return self.error(file, line, format!("empty {field:?}"))
+                        }
+                        _ => DataValue::from(value),
+                    };
+                }
+                // empty fields are absent properties
+                if value.is_empty() {
+                    continue;
+                }
+                let (name, typ, array) = match field {
+                    Field::Property { name, typ, array } => (name, *typ, *array),
+                    Field::Id(Some(name)) if name != "id" => (name, PropType::String, false),
+                    _ => continue,
+                };
+                let coerced = if array {
+                    value
+                        .split(ARRAY_DELIMITER)
+                        .map(|item| typ.coerce(item))
+                        .collect::<Option<Vec<_>>>()
+                        .map(DataValue::List)
+                } else {
+                    typ.coerce(value)
+                };
+                match coerced {
+                    Some(coerced) => properties.insert(name.clone(), coerced),
+                    None => {
+                        let message = format!("'{value}' is not a value of the type of '{name}'");
+                        return self.error(file, line, message);
+                    }
+                };
+            }
+            self.rows.push((keys, properties));
+        }
+        Ok(())
+    }
+
+    fn columns(&self) -> Vec<&str> {
+        self.reserved
+            .iter()
+            .copied()
+            .chain(self.properties.keys().map(|name| name.as_str()))
+            .collect()
+    }
+
+    fn schema(&self) -> String {
+        let keys = match self.kind {
+            "node" => "id: String",
+            _ => "start: String, type: String, end: String",
+        };
+        let mut values = self
+            .properties
+            .iter()
+            .map(|(name, (typ, array))| format!("{name}: {}", typ.column_type(*array)))
+            .collect_vec();
+        if self.kind == "node" {
+            values.insert(0, "labels: [String]".to_string());
+        }
+        if values.is_empty() {
+            format!("{{{keys}}}")
+        } else {
+            format!("{{{keys} => {}}}", values.join(", "))
+        }
+    }
+
+    fn into_param(self) -> DataValue {
+        let names = self.properties.keys().cloned().collect_vec();
+        DataValue::List(
+            self.rows
+                .into_iter()
+                .map(|(mut row, mut properties)| {
+                    for name in &names {
+                        row.push(properties.remove(name).unwrap_or(DataValue::Null));
+                    }
+                    DataValue::List(row)
+                })
+                .collect(),
+        )
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Import the nodes and relationships of a property graph, in the CSV format of the
+    /// Neo4j admin import and export tools, into `nodes_relation` and `edges_relation`,
+    /// in one transaction. Each of `nodes` and `edges` holds the contents of CSV files,
+    /// with a header of `name:type` fields: `:ID`, `:LABEL` and properties for nodes,
+    /// `:START_ID`, `:END_ID`, `:TYPE` and properties for relationships. Properties have
+    /// the types `int`, `long`, `float`, `double`, `boolean` or `string`, `[]` making
+    /// them arrays separated by `;`. Temporal and spatial properties are kept as strings,
+    /// and empty fields are absent properties.
+    ///
+    /// The relations are created if they do not exist, as
+    ///
+    /// ```text
+    /// nodes_relation {id: String => labels: [String], <property>: <type>?, ...}
+    /// edges_relation {start: String, type: String, end: String => <property>: <type>?, ...}
+    /// ```
+    ///
+    /// with a column for each property found in the files. Existing relations must have a
+    /// column for each property of the files, others are given their default values.
+    ///
+    /// The ends of the relationships must be among the nodes, imported or already stored.
+    /// Since `start`, `type` and `end` are the keys of relationships, those of the same
+    /// type between the same nodes are merged, and id spaces are not kept: ids must be
+    /// unique across the files of nodes.
+    pub fn import_property_graph(
+        &'s self,
+        nodes_relation: &str,
+        edges_relation: &str,
+        nodes: &[&str],
+        edges: &[&str],
+    ) -> Result<PropertyGraphImport> {
+        let mut parsed_nodes = Parsed::new("node", &NODE_COLUMNS);
+        for (i, data) in nodes.iter().enumerate() {
+            parsed_nodes.parse_file(i, data, &[Field::Id(None), Field::Labels])?;
+        }
+        let mut parsed_edges = Parsed::new("relationship", &EDGE_COLUMNS);
+        for (i, data) in edges.iter().enumerate() {
+            parsed_edges.parse_file(i, data, &[Field::StartId, Field::Type, Field::EndId])?;
+        }
+        let report = PropertyGraphImport {
+            nodes: parsed_nodes.rows.len(),
+            edges: parsed_edges.rows.len(),
+        };
+
+        let mut script = String::new();
+        let tx = self.transact()?;
+        for (relation, parsed) in [
+            (nodes_relation, &parsed_nodes),
+            (edges_relation, &parsed_edges),
+        ] {
+            if !tx.relation_exists(relation)? {
+                script += &format!("{{:create {relation} {}}}\n", parsed.schema());
+            }
+        }
+        drop(tx);
+        for (relation, param, parsed, n_keys) in [
+            (nodes_relation, "nodes", &parsed_nodes, 1),
+            (edges_relation, "edges", &parsed_edges, 3),
+        ] {
+            let columns = parsed.columns();
+            let (keys, values) = columns.split_at(n_keys);
+            let spec = if values.is_empty() {
+                keys.join(", ")
+            } else {
+                format!("{} => {}", keys.join(", "), values.join(", "))
+            };
+            script += &format!(
+                "{{?[{}] <- ${param} :put {relation} {{{spec}}}}}\n",
+                columns.join(", ")
+            );
+        }
+        script += &format!(
+            "{{edges[start, end] <- $ends \
+             ?[node] := edges[node, _] or edges[_, node], not *{nodes_relation}{{id: node}} \
+             :assert none}}"
+        );
+        let ends = DataValue::List(
+            parsed_edges
+                .rows
+                .iter()
+                .map(|(keys, _)| DataValue::List(vec![keys[0].clone(), keys[2].clone()]))
+                .collect(),
+        );
+        let params = BTreeMap::from([
+            ("nodes".to_string(), parsed_nodes.into_param()),
+            ("edges".to_string(), parsed_edges.into_param()),
+            ("ends".to_string(), ends),
+        ]);
+        self.run_script(&script, params, ScriptMutability::Mutable)?;
+        Ok(report)
+    }
+}
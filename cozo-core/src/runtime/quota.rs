@@ -0,0 +1,171 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Quotas limiting the rows, memory, concurrent queries and write rate of the sessions
+//! of a principal. The usage of each principal is shared by all of its sessions, and
+//! checked as queries start, as rules are evaluated and as rows are written.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use miette::{bail, Diagnostic, Result};
+use thiserror::Error;
+
+use crate::runtime::db::seconds_since_the_epoch;
+
+/// Limits on the resources used by the sessions of a principal, see
+/// [`Db::set_quota`](crate::Db::set_quota). Limits left at `None` are not enforced.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Quota {
+    /// Rows a single query may return
+    pub max_rows: Option<usize>,
+    /// Bytes the rules of a single query may hold while it is evaluated, estimated from
+    /// the number of rows and their arity. Heap data of strings, lists and the like are
+    /// not counted.
+    pub max_memory: Option<usize>,
+    /// Scripts that may run at the same time, over all sessions of the principal
+    pub max_concurrent_queries: Option<usize>,
+    /// Rows that may be written to stored relations per second, over all sessions of
+    /// the principal. A write is refused only once the allowance is used up, so a single
+    /// large write succeeds and delays the following ones.
+    pub max_writes_per_second: Option<f64>,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Principal '{principal}' exceeded its quota of {limit} {resource}")]
+#[diagnostic(code(db::quota_exceeded))]
+#[diagnostic(help("The quota is set by the embedder with `Db::set_quota`"))]
+pub(crate) struct QuotaExceeded {
+    principal: String,
+    resource: &'static str,
+    limit: String,
+}
+
+/// The quota of a principal together with its usage, shared by all of its sessions.
+pub(crate) struct QuotaUsage {
+    principal: String,
+    quota: Mutex<Quota>,
+    running: Mutex<usize>,
+    /// The rows that may still be written, which may be negative, and when it was computed
+    write_allowance: Mutex<(f64, f64)>,
+}
+
+impl QuotaUsage {
+    fn exceeded(&self, resource: &'static str, limit: impl ToString) -> QuotaExceeded {
+        QuotaExceeded {
+            principal: self.principal.clone(),
+            resource,
+            limit: limit.to_string(),
+        }
+    }
+    fn quota(&self) -> Quota {
+        *self.quota.lock().unwrap()
+    }
+    /// Count a script as running until the returned permit is dropped.
+    pub(crate) fn begin_query(self: &Arc<Self>) -> Result<QueryPermit> {
+        let mut running = self.running.lock().unwrap();
+        if let Some(max) = self.quota().max_concurrent_queries {
+            if *running >= max {
+                bail!(self.exceeded("concurrent queries", max))
+            }
+        }
+        *running += 1;
+        Ok(QueryPermit {
+            usage: self.clone(),
+        })
+    }
+    pub(crate) fn ensure_rows(&self, rows: usize) -> Result<()> {
+        if let Some(max) = self.quota().max_rows {
+            if rows > max {
+                bail!(self.exceeded("rows returned by a query", max))
+            }
+        }
+        Ok(())
+    }
+    pub(crate) fn ensure_memory(&self, bytes: usize) -> Result<()> {
+        if let Some(max) = self.quota().max_memory {
+            if bytes > max {
+                bail!(self.exceeded("bytes held by a query", max))
+            }
+        }
+        Ok(())
+    }
+    /// Refuse writes while the write allowance is used up.
+    pub(crate) fn ensure_can_write(&self) -> Result<()> {
+        if let Some(rate) = self.quota().max_writes_per_second {
+            if self.refill(rate) <= 0. {
+                bail!(self.exceeded("rows written per second", rate))
+            }
+        }
+        Ok(())
+    }
+    /// Take `rows` written rows off the write allowance.
+    pub(crate) fn charge_writes(&self, rows: u64) {
+        if let Some(rate) = self.quota().max_writes_per_second {
+            self.refill(rate);
+            self.write_allowance.lock().unwrap().0 -= rows as f64;
+        }
+    }
+    fn refill(&self, rate: f64) -> f64 {
+        let now = seconds_since_the_epoch().unwrap_or_default();
+        let mut allowance = self.write_allowance.lock().unwrap();
+        let (left, at) = *allowance;
+        // the allowance never grows above a second worth of writes
+        *allowance = ((left + (now - at) * rate).min(rate), now);
+        allowance.0
+    }
+}
+
+/// Held for as long as a script of a principal with a quota runs.
+pub(crate) struct QueryPermit {
+    usage: Arc<QuotaUsage>,
+}
+
+impl Drop for QueryPermit {
+    fn drop(&mut self) {
+        *self.usage.running.lock().unwrap() -= 1;
+    }
+}
+
+/// The quotas set on a database, by principal name.
+#[derive(Default)]
+pub(crate) struct Quotas {
+    by_principal: Mutex<BTreeMap<String, Arc<QuotaUsage>>>,
+}
+
+impl Quotas {
+    pub(crate) fn set(&self, principal: &str, quota: Quota) {
+        let mut by_principal = self.by_principal.lock().unwrap();
+        match by_principal.get(principal) {
+            // keep the usage, so that running scripts stay counted
+            Some(usage) => *usage.quota.lock().unwrap() = quota,
+            None => {
+                let now = seconds_since_the_epoch().unwrap_or_default();
+                let allowance = quota.max_writes_per_second.unwrap_or_default();
+                by_principal.insert(
+                    principal.to_string(),
+                    Arc::new(QuotaUsage {
+                        principal: principal.to_string(),
+                        quota: Mutex::new(quota),
+                        running: Mutex::new(0),
+                        write_allowance: Mutex::new((allowance, now)),
+                    }),
+                );
+            }
+        }
+    }
+    pub(crate) fn remove(&self, principal: &str) -> bool {
+        self.by_principal.lock().unwrap().remove(principal).is_some()
+    }
+    pub(crate) fn get(&self, principal: &str) -> Option<Quota> {
+        self.usage(principal).map(|usage| usage.quota())
+    }
+    pub(crate) fn usage(&self, principal: &str) -> Option<Arc<QuotaUsage>> {
+        self.by_principal.lock().unwrap().get(principal).cloned()
+    }
+}
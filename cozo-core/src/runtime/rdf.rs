@@ -0,0 +1,795 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Import and export of RDF triples in the N-Triples and Turtle formats, see
+//! [`Db::import_rdf`].
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use itertools::Itertools;
+use miette::{bail, ensure, Diagnostic, Result};
+use thiserror::Error;
+
+use crate::data::json::JsonValue;
+use crate::data::value::{DataValue, Num};
+use crate::runtime::db::NamedRows;
+use crate::storage::Storage;
+use crate::{Db, ScriptMutability};
+
+const XSD: &str = "http://www.w3.org/2001/XMLSchema#";
+const RDF: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+
+/// The columns of a relation holding triples, see [`Db::import_rdf`].
+const TRIPLE_COLUMNS: [&str; 6] = ["s", "p", "o", "iri", "lang", "datatype"];
+
+/// The syntax of RDF data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RdfFormat {
+    /// [N-Triples](https://www.w3.org/TR/n-triples/), one triple per line
+    NTriples,
+    /// [Turtle](https://www.w3.org/TR/turtle/), of which N-Triples is a subset
+    Turtle,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Bad RDF at line {line}: {message}")]
+#[diagnostic(code(rdf::parse))]
+struct RdfParseError {
+    line: usize,
+    message: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Term {
+    Iri(String),
+    /// A blank node, with the label given to it by the import
+    Blank(String),
+    Literal {
+        value: DataValue,
+        lang: Option<String>,
+        /// Only for datatypes not converted to a value of their own
+        datatype: Option<String>,
+    },
+}
+
+impl Term {
+    /// The subject or the predicate as stored: IRIs as they are, blank nodes as `_:label`.
+    fn into_node(self) -> String {
+        match self {
+            Term::Iri(iri) => iri,
+            Term::Blank(label) => format!("_:{label}"),
+            Term::Literal { .. } => unreachable!(),
+        }
+    }
+    fn into_row(self, s: String, p: String) -> Vec<DataValue> {
+        let (o, iri, lang, datatype) = match self {
+            Term::Literal {
+                value,
+                lang,
+                datatype,
+            } => (value, false, lang, datatype),
+            node => (DataValue::from(node.into_node()), true, None, None),
+        };
+        vec![
+            DataValue::from(s),
+            DataValue::from(p),
+            o,
+            DataValue::from(iri),
+            lang.map_or(DataValue::Null, DataValue::from),
+            datatype.map_or(DataValue::Null, DataValue::from),
+        ]
+    }
+}
+
+/// The value of a literal, converting the numbers and booleans of XML Schema.
+fn typed_literal(lexical: String, datatype: String) -> Term {
+    let value = match datatype.strip_prefix(XSD) {
+        Some("string") => Some(DataValue::from(lexical.as_str())),
+        Some("integer" | "int" | "long" | "short" | "byte" | "nonNegativeInteger") => {
+            lexical.trim().parse::<i64>().ok().map(DataValue::from)
+        }
+        Some("double" | "float" | "decimal") => match lexical.trim() {
+            "INF" => Some(DataValue::from(f64::INFINITY)),
+            "-INF" => Some(DataValue::from(f64::NEG_INFINITY)),
+            "NaN" => Some(DataValue::from(f64::NAN)),
+            l => l.parse::<f64>().ok().map(DataValue::from),
+        },
+        Some("boolean") => match lexical.trim() {
+            "true" | "1" => Some(DataValue::from(true)),
+            "false" | "0" => Some(DataValue::from(false)),
+            _ => None,
+        },
+        _ => None,
+    };
+    match value {
+        Some(value) => Term::Literal {
+            value,
+            lang: None,
+            datatype: None,
+        },
+        None => Term::Literal {
+            value: DataValue::from(lexical),
+            lang: None,
+            datatype: Some(datatype),
+        },
+    }
+}
+
+struct TurtleParser<'a> {
+    src: &'a str,
+    pos: usize,
+    base: String,
+    prefixes: BTreeMap<String, String>,
+    /// The labels given to the blank nodes of the source, so that they do not merge
+    /// with the blank nodes of other imports
+    blank_labels: BTreeMap<String, String>,
+    import_tag: u64,
+    blank_count: usize,
+    rows: Vec<Vec<DataValue>>,
+}
+
+impl<'a> TurtleParser<'a> {
+    fn new(src: &'a str) -> Self {
+        Self {
+            src,
+            pos: 0,
+            base: String::new(),
+            prefixes: BTreeMap::new(),
+            blank_labels: BTreeMap::new(),
+            import_tag: rand::random(),
+            blank_count: 0,
+            rows: vec![],
+        }
+    }
+    fn error<T>(&self, message: impl Into<String>) -> Result<T> {
+        let line = self.src[..self.pos].matches('\n').count() + 1;
+        bail!(RdfParseError {
+            line,
+            message: message.into(),
+        })
+    }
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+    /// Skip whitespace and comments.
+    fn skip(&mut self) {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+            if trimmed.starts_with('#') {
+                self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else {
+                return;
+            }
+        }
+    }
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip();
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+    fn expect(&mut self, token: &str) -> Result<()> {
+        if !self.eat(token) {
+            let found = self.rest().chars().take(20).collect::<String>();
+            return self.error(format!("expected '{token}', found '{found}'"));
+        }
+        Ok(())
+    }
+    /// Whether the source continues with the keyword `word`, which is then consumed.
+    fn keyword(&mut self, word: &str, case_insensitive: bool) -> bool {
+        self.skip();
+        let rest = self.rest();
+        let matches = match rest.get(..word.len()) {
+            Some(start) if case_insensitive => start.eq_ignore_ascii_case(word),
+            Some(start) => start == word,
+            None => false,
+        };
+        if matches && !rest[word.len()..].starts_with(|c: char| c.is_alphanumeric() || c == ':') {
+            self.pos += word.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse(mut self, format: RdfFormat) -> Result<Vec<Vec<DataValue>>> {
+        loop {
+            self.skip();
+            if self.peek().is_none() {
+                return Ok(self.rows);
+            }
+            if format == RdfFormat::NTriples {
+                self.triple()?;
+                self.expect(".")?;
+            } else if self.eat("@prefix") {
+                self.prefix_decl()?;
+                self.expect(".")?;
+            } else if self.eat("@base") {
+                self.base = self.iri_ref()?;
+                self.expect(".")?;
+            } else if self.keyword("PREFIX", true) {
+                self.prefix_decl()?;
+            } else if self.keyword("BASE", true) {
+                self.base = self.iri_ref()?;
+            } else {
+                self.triples()?;
+                self.expect(".")?;
+            }
+        }
+    }
+    /// A triple of N-Triples, without prefixed names or abbreviations.
+    fn triple(&mut self) -> Result<()> {
+        let subject = self.node()?;
+        let predicate = Term::Iri(self.iri_ref()?);
+        self.skip();
+        let object = match self.peek() {
+            Some('"') => self.literal()?,
+            _ => self.node()?,
+        };
+        self.add(&subject, &predicate, object);
+        Ok(())
+    }
+    fn prefix_decl(&mut self) -> Result<()> {
+        self.skip();
+        let end = match self.rest().find(':') {
+            Some(end) => end,
+            None => return self.error("expected a prefix"),
+        };
+        let prefix = self.rest()[..end].trim().to_string();
+        self.pos += end + 1;
+        let iri = self.iri_ref()?;
+        self.prefixes.insert(prefix, iri);
+        Ok(())
+    }
+    fn triples(&mut self) -> Result<()> {
+        self.skip();
+        if self.peek() == Some('[') {
+            let subject = self.blank_node_property_list()?;
+            self.skip();
+            if self.peek() != Some('.') {
+                self.predicate_object_list(&subject)?;
+            }
+            return Ok(());
+        }
+        let subject = match self.peek() {
+            Some('(') => self.collection()?,
+            _ => self.node()?,
+        };
+        self.predicate_object_list(&subject)
+    }
+    fn predicate_object_list(&mut self, subject: &Term) -> Result<()> {
+        loop {
+            let predicate = if self.keyword("a", false) {
+                Term::Iri(format!("{RDF}type"))
+            } else {
+                self.iri()?
+            };
+            loop {
+                let object = self.object()?;
+                self.add(subject, &predicate, object);
+                if !self.eat(",") {
+                    break;
+                }
+            }
+            if !self.eat(";") {
+                return Ok(());
+            }
+            while self.eat(";") {}
+            self.skip();
+            if matches!(self.peek(), Some('.' | ']') | None) {
+                return Ok(());
+            }
+        }
+    }
+    fn add(&mut self, subject: &Term, predicate: &Term, object: Term) {
+        let row = object.into_row(subject.clone().into_node(), predicate.clone().into_node());
+        self.rows.push(row);
+    }
+    fn fresh_blank(&mut self) -> Term {
+        self.blank_count += 1;
+        Term::Blank(format!("b{:016x}_{}", self.import_tag, self.blank_count))
+    }
+    fn blank_node_property_list(&mut self) -> Result<Term> {
+        self.expect("[")?;
+        let node = self.fresh_blank();
+        if !self.eat("]") {
+            self.predicate_object_list(&node)?;
+            self.expect("]")?;
+        }
+        Ok(node)
+    }
+    /// A collection, as the first node of its `rdf:first` and `rdf:rest` list.
+    fn collection(&mut self) -> Result<Term> {
+        self.expect("(")?;
+        let mut items = vec![];
+        while !self.eat(")") {
+            if self.peek().is_none() {
+                return self.error("unterminated collection");
+            }
+            items.push(self.object()?);
+        }
+        let mut list = Term::Iri(format!("{RDF}nil"));
+        for item in items.into_iter().rev() {
+            let node = self.fresh_blank();
+            self.add(&node, &Term::Iri(format!("{RDF}first")), item);
+            self.add(&node, &Term::Iri(format!("{RDF}rest")), list);
+            list = node;
+        }
+        Ok(list)
+    }
+    fn object(&mut self) -> Result<Term> {
+        self.skip();
+        match self.peek() {
+            Some('[') => self.blank_node_property_list(),
+            Some('(') => self.collection(),
+            Some('"' | '\'') => self.literal(),
+            Some(c) if c.is_ascii_digit() || c == '+' || c == '-' || c == '.' => self.number(),
+            _ => {
+                for (word, value) in [("true", true), ("false", false)] {
+                    if self.keyword(word, false) {
+                        return Ok(Term::Literal {
+                            value: DataValue::from(value),
+                            lang: None,
+                            datatype: None,
+                        });
+                    }
+                }
+                self.node()
+            }
+        }
+    }
+    /// An IRI or a blank node.
+    fn node(&mut self) -> Result<Term> {
+        self.skip();
+        if self.rest().starts_with("_:") {
+            self.pos += 2;
+            let label = self.name_chars();
+            if label.is_empty() {
+                return self.error("expected the label of a blank node");
+            }
+            let next = self.blank_labels.len() + 1;
+            let import_tag = self.import_tag;
+            let label = self
+                .blank_labels
+                .entry(label)
+                .or_insert_with(|| format!("n{import_tag:016x}_{next}"));
+            return Ok(Term::Blank(label.clone()));
+        }
+        self.iri()
+    }
+    fn iri(&mut self) -> Result<Term> {
+        self.skip();
+        if self.peek() == Some('<') {
+            return Ok(Term::Iri(self.iri_ref()?));
+        }
+        let prefix = self.name_chars();
+        if self.peek() != Some(':') {
+            let found = self.rest().chars().take(20).collect::<String>();
+            return self.error(format!("expected an IRI, found '{prefix}{found}'"));
+        }
+        self.pos += 1;
+        let Some(namespace) = self.prefixes.get(&prefix).cloned() else {
+            return self.error(format!("undefined prefix '{prefix}:'"));
+        };
+        let mut local = String::new();
+        loop {
+            match self.peek() {
+                Some('\\') => {
+                    self.pos += 1;
+                    match self.bump() {
+                        Some(c) => local.push(c),
+                        None => return self.error("unterminated escape"),
+                    }
+                }
+                Some(c) if c.is_alphanumeric() || "_-:%".contains(c) => {
+                    local.push(c);
+                    self.pos += c.len_utf8();
+                }
+                // a dot ends the statement unless a name character follows
+                Some('.')
+                    if self.rest()[1..]
+                        .starts_with(|c: char| c.is_alphanumeric() || "_-:%".contains(c)) =>
+                {
+                    local.push('.');
+                    self.pos += 1;
+                }
+                _ => break,
+            }
+        }
+        Ok(Term::Iri(format!("{namespace}{local}")))
+    }
+    fn name_chars(&mut self) -> String {
+        let rest = self.rest();
+        let mut end = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-' || c == '.'))
+            .unwrap_or(rest.len());
+        // a name does not end with a dot
+        while rest[..end].ends_with('.') {
+            end -= 1;
+        }
+        self.pos += end;
+        rest[..end].to_string()
+    }
+    fn iri_ref(&mut self) -> Result<String> {
+        self.expect("<")?;
+        let Some(end) = self.rest().find('>') else {
+            return self.error("unterminated IRI");
+        };
+        let iri = unescape_iri(&self.rest()[..end]);
+        self.pos += end + 1;
+        Ok(self.resolve(iri))
+    }
+    /// Resolve `iri` against the base IRI, if it is relative.
+    fn resolve(&self, iri: String) -> String {
+        let scheme_end = iri.find(':');
+        let is_absolute = scheme_end.is_some_and(|end| {
+            end > 0
+                && iri[..end]
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        });
+        if is_absolute || self.base.is_empty() {
+            iri
+        } else if iri.is_empty() {
+            self.base.clone()
+        } else if iri.starts_with('#') {
+            let base = self.base.split('#').next().unwrap();
+            format!("{base}{iri}")
+        } else {
+            let base = match self.base.rfind('/') {
+                Some(end) => &self.base[..end + 1],
+                None => &self.base,
+            };
+            format!("{base}{iri}")
+        }
+    }
+    fn literal(&mut self) -> Result<Term> {
+        let quote = self.bump().unwrap();
+        let long = [quote; 2].iter().collect::<String>();
+        let long = self.rest().starts_with(&long);
+        if long {
+            self.pos += 2;
+        }
+        let mut value = String::new();
+        loop {
+            let Some(c) = self.bump() else {
+                return self.error("unterminated string");
+            };
+            match c {
+                c if c == quote => {
+                    if !long {
+                        break;
+                    }
+                    let closing = [quote; 2].iter().collect::<String>();
+                    if self.rest().starts_with(&closing) {
+                        self.pos += 2;
+                        break;
+                    }
+                    value.push(c);
+                }
+                '\\' => {
+                    let escaped = match self.bump() {
+                        Some('t') => '\t',
+                        Some('b') => '\u{8}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('f') => '\u{c}',
+                        Some(c @ ('"' | '\'' | '\\')) => c,
+                        Some(u @ ('u' | 'U')) => {
+                            let len = if u == 'u' { 4 } else { 8 };
+                            let code = self.rest().get(..len).unwrap_or_default();
+                            let c = u32::from_str_radix(code, 16).ok().and_then(char::from_u32);
+                            self.pos += code.len();
+                            match c {
+                                Some(c) => c,
+                                None => return self.error(format!("bad escape \\{u}{code}")),
+                            }
+                        }
+                        c => return self.error(format!("bad escape {c:?}")),
+                    };
+                    value.push(escaped);
+                }
+                '\n' | '\r' if !long => return self.error("line break in a string"),
+                c => value.push(c),
+            }
+        }
+        if self.peek() == Some('@') {
+            self.pos += 1;
+            let rest = self.rest();
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+                .unwrap_or(rest.len());
+            ensure!(end > 0, "expected a language tag");
+            let lang = rest[..end].to_string();
+            self.pos += end;
+            return Ok(Term::Literal {
+                value: DataValue::from(value),
+                lang: Some(lang),
+                datatype: None,
+            });
+        }
+        if self.rest().starts_with("^^") {
+            self.pos += 2;
+            let Term::Iri(datatype) = self.iri()? else {
+                unreachable!()
+            };
+            return Ok(typed_literal(value, datatype));
+        }
+        Ok(Term::Literal {
+            value: DataValue::from(value),
+            lang: None,
+            datatype: None,
+        })
+    }
+    fn number(&mut self) -> Result<Term> {
+        let rest = self.rest();
+        let bytes = rest.as_bytes();
+        let mut end = 0;
+        if matches!(bytes.first(), Some(b'+' | b'-')) {
+            end += 1;
+        }
+        let digits = |from: usize| {
+            from + bytes[from..]
+                .iter()
+                .take_while(|b| b.is_ascii_digit())
+                .count()
+        };
+        end = digits(end);
+        let mut is_integer = true;
+        if bytes.get(end) == Some(&b'.') && bytes.get(end + 1).is_some_and(u8::is_ascii_digit) {
+            end = digits(end + 1);
+            is_integer = false;
+        }
+        if matches!(bytes.get(end), Some(b'e' | b'E')) {
+            let mut exp = end + 1;
+            if matches!(bytes.get(exp), Some(b'+' | b'-')) {
+                exp += 1;
+            }
+            if bytes.get(exp).is_some_and(u8::is_ascii_digit) {
+                end = digits(exp);
+                is_integer = false;
+            }
+        }
+        let lexical = rest[..end].to_string();
+        self.pos += end;
+        let datatype = if is_integer { "integer" } else { "double" };
+        match typed_literal(lexical.clone(), format!("{XSD}{datatype}")) {
+            term @ Term::Literal { datatype: None, .. } => Ok(term),
+            _ => self.error(format!("bad number '{lexical}'")),
+        }
+    }
+}
+
+fn unescape_iri(iri: &str) -> String {
+    let mut out = String::with_capacity(iri.len());
+    let mut chars = iri.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        let len = match chars.next() {
+            Some('u') => 4,
+            Some('U') => 8,
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+                continue;
+            }
+            None => {
+                out.push('\\');
+                continue;
+            }
+        };
+        let code = chars.by_ref().take(len).collect::<String>();
+        match u32::from_str_radix(&code, 16).ok().and_then(char::from_u32) {
+            Some(c) => out.push(c),
+            None => out.push_str(&code),
+        }
+    }
+    out
+}
+
+fn write_escaped(out: &mut String, s: &str) {
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+}
+
+/// Writes nodes and literals, abbreviating the IRIs given prefixes for in Turtle.
+struct TermWriter<'a> {
+    prefixes: &'a BTreeMap<String, String>,
+}
+
+impl TermWriter<'_> {
+    fn node(&self, out: &mut String, node: &str) {
+        if node.starts_with("_:") {
+            out.push_str(node);
+            return;
+        }
+        for (prefix, namespace) in self.prefixes {
+            if let Some(local) = node.strip_prefix(namespace.as_str()) {
+                let is_name = local
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+                    && !local.starts_with('-');
+                if is_name {
+                    write!(out, "{prefix}:{local}").unwrap();
+                    return;
+                }
+            }
+        }
+        out.push('<');
+        out.push_str(node);
+        out.push('>');
+    }
+    fn object(&self, out: &mut String, row: &[DataValue]) {
+        let (o, iri, lang, datatype) = (&row[0], &row[1], &row[2], &row[3]);
+        if let (DataValue::Str(s), DataValue::Bool(true)) = (o, iri) {
+            return self.node(out, s);
+        }
+        let (lexical, datatype) = match (o, datatype) {
+            (DataValue::Str(s), DataValue::Str(datatype)) => {
+                (s.to_string(), Some(datatype.to_string()))
+            }
+            (DataValue::Str(s), _) => (s.to_string(), None),
+            (DataValue::Bool(b), _) => (b.to_string(), Some(format!("{XSD}boolean"))),
+            (DataValue::Num(Num::Int(i)), _) => (i.to_string(), Some(format!("{XSD}integer"))),
+            (DataValue::Num(Num::Float(f)), _) => {
+                let lexical = if f.is_nan() {
+                    "NaN".to_string()
+                } else if f.is_infinite() {
+                    if *f > 0. { "INF" } else { "-INF" }.to_string()
+                } else {
+                    format!("{f:?}")
+                };
+                (lexical, Some(format!("{XSD}double")))
+            }
+            (v, _) => (JsonValue::from(v.clone()).to_string(), None),
+        };
+        out.push('"');
+        write_escaped(out, &lexical);
+        out.push('"');
+        if let DataValue::Str(lang) = lang {
+            write!(out, "@{lang}").unwrap();
+        } else if let Some(datatype) = datatype {
+            out.push_str("^^");
+            self.node(out, &datatype);
+        }
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Import the triples of `data`, in the syntax of `format`, into `relation`, which is
+    /// created if it does not exist with the schema
+    ///
+    /// ```text
+    /// {s: String, p: String, o: Any, iri: Bool => lang: String?, datatype: String?}
+    /// ```
+    ///
+    /// `s` and `p` hold the IRIs of the subject and the predicate, or `_:label` for blank
+    /// nodes. `o` holds the object: the IRI or the blank node, with `iri` set, or the
+    /// value of the literal. Literals of the numeric and boolean types of XML Schema are
+    /// converted to numbers and booleans, those of other datatypes are kept as strings
+    /// with their datatype. Blank nodes are given new labels, unique to the import, so
+    /// that importing the same data twice does not merge them. Relative IRIs are
+    /// resolved against the base IRI, if any, by simple concatenation.
+    ///
+    /// The triples are written in a single transaction. Returns the number of triples.
+    pub fn import_rdf(&'s self, relation: &str, format: RdfFormat, data: &str) -> Result<usize> {
+        let rows = TurtleParser::new(data).parse(format)?;
+        let n = rows.len();
+        let exists = self.transact()?.relation_exists(relation)?;
+        let create = if exists {
+            String::new()
+        } else {
+            format!(
+                "{{:create {relation} {{s: String, p: String, o: Any, iri: Bool => \
+                 lang: String?, datatype: String?}}}}"
+            )
+        };
+        let columns = TRIPLE_COLUMNS.join(", ");
+        let script = format!(
+            "{create} {{?[{columns}] <- $rows :put {relation} {{s, p, o, iri => lang, datatype}}}}"
+        );
+        let params = BTreeMap::from([(
+            "rows".to_string(),
+            DataValue::List(rows.into_iter().map(DataValue::List).collect()),
+        )]);
+        self.run_script(&script, params, ScriptMutability::Mutable)?;
+        Ok(n)
+    }
+
+    /// Write the triples of `relation`, with the schema of those imported by
+    /// [`import_rdf`](Self::import_rdf), in the syntax of `format`. In Turtle, the IRIs
+    /// starting with one of the namespaces of `prefixes` are abbreviated with its prefix,
+    /// and the triples are grouped by subject.
+    pub fn export_rdf(
+        &'s self,
+        relation: &str,
+        format: RdfFormat,
+        prefixes: &BTreeMap<String, String>,
+    ) -> Result<String> {
+        let columns = TRIPLE_COLUMNS.join(", ");
+        let NamedRows { rows, .. } = self.run_script(
+            &format!("?[{columns}] := *{relation}{{{columns}}}"),
+            Default::default(),
+            ScriptMutability::Immutable,
+        )?;
+        let no_prefixes = BTreeMap::new();
+        let writer = TermWriter {
+            prefixes: match format {
+                RdfFormat::NTriples => &no_prefixes,
+                RdfFormat::Turtle => prefixes,
+            },
+        };
+        let mut out = String::new();
+        if format == RdfFormat::Turtle {
+            for (prefix, namespace) in prefixes {
+                writeln!(out, "@prefix {prefix}: <{namespace}> .").unwrap();
+            }
+            if !prefixes.is_empty() {
+                out.push('\n');
+            }
+        }
+        for (subject, triples) in &rows.iter().group_by(|row| row[0].clone()) {
+            let Some(subject) = subject.get_str() else {
+                bail!("Subject {subject:?} of relation '{relation}' is not a string")
+            };
+            for (i, row) in triples.enumerate() {
+                let Some(predicate) = row[1].get_str() else {
+                    bail!(
+                        "Predicate {:?} of relation '{relation}' is not a string",
+                        row[1]
+                    )
+                };
+                match format {
+                    RdfFormat::NTriples => {
+                        writer.node(&mut out, subject);
+                        out.push(' ');
+                    }
+                    RdfFormat::Turtle if i == 0 => {
+                        writer.node(&mut out, subject);
+                        out.push(' ');
+                    }
+                    RdfFormat::Turtle => out.push_str(" ;\n    "),
+                }
+                writer.node(&mut out, predicate);
+                out.push(' ');
+                writer.object(&mut out, &row[2..]);
+                if format == RdfFormat::NTriples {
+                    out.push_str(" .\n");
+                }
+            }
+            if format == RdfFormat::Turtle {
+                out.push_str(" .\n");
+            }
+        }
+        Ok(out)
+    }
+}
@@ -6,7 +6,7 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Display, Formatter};
 use std::sync::atomic::Ordering;
 
@@ -19,6 +19,7 @@ use serde::Serialize;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
+use crate::data::expr::{Bytecode, Expr};
 use crate::data::memcmp::MemCmpEncoder;
 use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
 use crate::data::symb::Symbol;
@@ -29,6 +30,8 @@ use crate::parse::expr::build_expr;
 use crate::parse::sys::{FtsIndexConfig, HnswIndexConfig, MinHashLshConfig};
 use crate::parse::{CozoScriptParser, Rule, SourceSpan};
 use crate::query::compile::IndexPositionUse;
+use crate::query::logical::NamedFieldNotFound;
+use crate::runtime::crdt::ensure_merged_columns_valid;
 use crate::runtime::hnsw::HnswIndexManifest;
 use crate::runtime::minhash_lsh::{HashPermutations, LshParams, MinHashLshIndexManifest, Weights};
 use crate::runtime::transact::SessionTx;
@@ -90,6 +93,98 @@ pub(crate) struct RelationHandle {
         (RelationHandle, RelationHandle, MinHashLshIndexManifest),
     >,
     pub(crate) description: SmartString<LazyCompact>,
+    /// Column holding the expiry timestamp (seconds since the epoch) of each row, if any
+    #[serde(default)]
+    pub(crate) expiry_column: Option<SmartString<LazyCompact>>,
+    /// Boolean column marking soft-deleted rows, which queries skip by default
+    #[serde(default)]
+    pub(crate) retired_column: Option<SmartString<LazyCompact>>,
+    /// Columns each role may access, set by `::grant`. Sessions with a role can only
+    /// access the granted columns of relations having grants.
+    #[serde(default)]
+    pub(crate) grants: BTreeMap<SmartString<LazyCompact>, RoleGrants>,
+    /// Predicate over the columns, set by `::policy set`, that every row seen or written by
+    /// sessions with a role must satisfy
+    #[serde(default)]
+    pub(crate) row_policy: Option<String>,
+}
+
+/// Columns of a relation that a role may read and write.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct RoleGrants {
+    pub(crate) read: BTreeSet<SmartString<LazyCompact>>,
+    pub(crate) write: BTreeSet<SmartString<LazyCompact>>,
+}
+
+/// Whether a grant allows reading or writing columns.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum GrantMode {
+    Read,
+    Write,
+}
+
+impl Display for GrantMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GrantMode::Read => write!(f, "read"),
+            GrantMode::Write => write!(f, "write"),
+        }
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Role '{role}' may not {mode} column '{column}' of relation '{relation}'")]
+#[diagnostic(code(tx::column_not_granted))]
+#[diagnostic(help("Grants are listed by `::grants` and managed with `::grant` and `::revoke`"))]
+pub(crate) struct ColumnNotGranted {
+    pub(crate) relation: String,
+    pub(crate) column: String,
+    pub(crate) role: String,
+    pub(crate) mode: GrantMode,
+    #[label]
+    pub(crate) span: SourceSpan,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Sessions with role '{0}' cannot {1}")]
+#[diagnostic(code(tx::restricted_session))]
+#[diagnostic(help("Only sessions without a role can change the schema and the grants"))]
+pub(crate) struct RestrictedSession(pub(crate) String, pub(crate) String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Role '{role}' may not write the row {row:?} of relation '{relation}'")]
+#[diagnostic(code(tx::row_policy_violation))]
+#[diagnostic(help("The row is outside of the row policy of the relation, set by `::policy set`"))]
+pub(crate) struct RowPolicyViolation {
+    pub(crate) relation: String,
+    pub(crate) role: String,
+    pub(crate) row: Vec<DataValue>,
+    #[label]
+    pub(crate) span: SourceSpan,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The row policy of relation '{relation}' cannot be checked on its index '{index}'")]
+#[diagnostic(code(tx::row_policy_not_on_index))]
+#[diagnostic(help("The policy uses the column '{column}', which the index does not have"))]
+pub(crate) struct RowPolicyNotOnIndex {
+    pub(crate) relation: String,
+    pub(crate) index: String,
+    pub(crate) column: String,
+}
+
+/// Parse the row policy `code`, substituting `$current_user` and `$current_role`.
+pub(crate) fn parse_row_policy(code: &str, user: Option<&str>, role: Option<&str>) -> Result<Expr> {
+    let as_value = |s: Option<&str>| s.map(DataValue::from).unwrap_or(DataValue::Null);
+    let params = BTreeMap::from([
+        ("current_user".to_string(), as_value(user)),
+        ("current_role".to_string(), as_value(role)),
+    ]);
+    let parsed = CozoScriptParser::parse(Rule::expr, code)
+        .into_diagnostic()?
+        .next()
+        .unwrap();
+    build_expr(parsed, &params)
 }
 
 impl RelationHandle {
@@ -99,6 +194,23 @@ impl RelationHandle {
             || self.fts_indices.contains_key(index_name)
             || self.lsh_indices.contains_key(index_name)
     }
+    /// Whether a session with `role` may access `column` in the manner of `mode`.
+    pub(crate) fn is_granted(&self, role: Option<&str>, column: &str, mode: GrantMode) -> bool {
+        let role = match role {
+            None => return true,
+            Some(role) => role,
+        };
+        if self.grants.is_empty() {
+            return true;
+        }
+        match self.grants.get(role) {
+            None => false,
+            Some(grants) => match mode {
+                GrantMode::Read => grants.read.contains(column),
+                GrantMode::Write => grants.write.contains(column),
+            },
+        }
+    }
     pub(crate) fn has_no_index(&self) -> bool {
         self.indices.is_empty()
             && self.hnsw_indices.is_empty()
@@ -193,6 +305,34 @@ impl RelationHandle {
         let prefix_bytes = self.id.0.to_be_bytes();
         data[0..8].copy_from_slice(&prefix_bytes);
     }
+    /// The index `name` to read the relation through, as given by an `index` hint, in
+    /// the form returned by [`choose_index`](Self::choose_index).
+    pub(crate) fn hinted_index(
+        &self,
+        name: &str,
+        arg_uses: &[IndexPositionUse],
+        validity_query: bool,
+        span: SourceSpan,
+    ) -> Result<(RelationHandle, Vec<usize>, bool)> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Index {0} of the hint cannot be used to read relation {1} at a validity")]
+        #[diagnostic(code(eval::hinted_index_unusable))]
+        #[diagnostic(help("Only indices ending with the validity column can be used"))]
+        struct HintedIndexUnusable(String, String, #[label] SourceSpan);
+
+        let Some((manifest, mapper)) = self.indices.get(name) else {
+            bail!(IndexNotFound(name.to_string(), self.name.to_string()))
+        };
+        ensure!(
+            !validity_query || *mapper.last().unwrap() == self.metadata.keys.len() - 1,
+            HintedIndexUnusable(name.to_string(), self.name.to_string(), span)
+        );
+        let need_join = arg_uses
+            .iter()
+            .enumerate()
+            .any(|(i, pos_use)| *pos_use != IndexPositionUse::Ignored && !mapper.contains(&i));
+        Ok((manifest.clone(), mapper.clone(), need_join))
+    }
     pub(crate) fn choose_index(
         &self,
         arg_uses: &[IndexPositionUse],
@@ -535,6 +675,22 @@ pub fn extend_tuple_from_v(key: &mut Tuple, val: &[u8]) {
 #[diagnostic(code(tx::index_already_exists))]
 pub(crate) struct IndexAlreadyExists(String, String);
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("index {0} for relation {1} not found")]
+#[diagnostic(code(tx::idx_not_found))]
+pub(crate) struct IndexNotFound(pub(crate) String, pub(crate) String);
+
+/// Compile the expression `code` of an index manifest against the columns of `rel_handle`.
+fn compile_relation_expr(rel_handle: &RelationHandle, code: &str) -> Result<Vec<Bytecode>> {
+    let parsed = CozoScriptParser::parse(Rule::expr, code)
+        .into_diagnostic()?
+        .next()
+        .unwrap();
+    let mut code_expr = build_expr(parsed, &Default::default())?;
+    code_expr.fill_binding_indices(&rel_handle.raw_binding_map())?;
+    code_expr.compile()
+}
+
 #[derive(Debug, Diagnostic, Error)]
 #[error("Cannot create relation {0} as one with the same name already exists")]
 #[diagnostic(code(eval::rel_name_conflict))]
@@ -600,6 +756,7 @@ impl<'a> SessionTx<'a> {
             bail!(RelNameConflictError(input_meta.name.to_string()))
         }
 
+        ensure_merged_columns_valid(&input_meta.metadata)?;
         let metadata = input_meta.metadata.clone();
         let last_id = if is_temp {
             self.temp_store_id.fetch_add(1, Ordering::Relaxed) as u64
@@ -620,6 +777,10 @@ impl<'a> SessionTx<'a> {
             fts_indices: Default::default(),
             lsh_indices: Default::default(),
             description: Default::default(),
+            expiry_column: None,
+            retired_column: None,
+            grants: Default::default(),
+            row_policy: None,
         };
 
         let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
@@ -641,12 +802,86 @@ impl<'a> SessionTx<'a> {
 
         Ok(meta)
     }
+    /// Create the temporary relation `name` holding `data`, for the scripts run later in
+    /// the same transaction to query. All columns are keys of type `Any?`.
+    pub(crate) fn create_temp_rel(&mut self, name: &str, data: NamedRows) -> Result<()> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Cannot create '{0}' as a temporary relation")]
+        #[diagnostic(code(tx::temp_rel_name))]
+        #[diagnostic(help("The names of temporary relations start with an underscore"))]
+        struct TempRelNameRequired(String);
+
+        let name = Symbol::new(name, Default::default());
+        ensure!(
+            name.is_temp_store_name(),
+            TempRelNameRequired(name.to_string())
+        );
+        ensure!(
+            !self.relation_exists(&name)?,
+            RelNameConflictError(name.to_string())
+        );
+        let keys = data
+            .headers
+            .iter()
+            .map(|header| ColumnDef {
+                name: SmartString::from(header.as_str()),
+                typing: NullableColType {
+                    coltype: ColType::Any,
+                    nullable: true,
+                },
+                default_gen: None,
+            })
+            .collect();
+        let handle = self.create_relation(InputRelationHandle {
+            name,
+            metadata: StoredRelationMetadata {
+                keys,
+                non_keys: vec![],
+            },
+            key_bindings: vec![],
+            dep_bindings: vec![],
+            span: Default::default(),
+        })?;
+        for row in &data.rows {
+            ensure!(
+                row.len() == data.headers.len(),
+                StoredRelArityMismatch {
+                    name: handle.name.to_string(),
+                    expect_arity: data.headers.len(),
+                    actual_arity: row.len(),
+                    span: Default::default(),
+                }
+            );
+            let key = handle.encode_key_for_store(row, Default::default())?;
+            let val = handle.encode_val_for_store(row, Default::default())?;
+            self.temp_store_tx.put(&key, &val)?;
+        }
+        Ok(())
+    }
+    /// The relation that the index `name` is built on, `None` if `name` is not an index.
+    pub(crate) fn base_relation(&self, name: &str) -> Result<Option<RelationHandle>> {
+        match name.split_once(':') {
+            None => Ok(None),
+            Some((base, _)) => Ok(Some(self.get_relation(base, false)?)),
+        }
+    }
     pub(crate) fn get_relation(&self, name: &str, lock: bool) -> Result<RelationHandle> {
         #[derive(Error, Diagnostic, Debug)]
         #[error("Cannot find requested stored relation '{0}'")]
         #[diagnostic(code(query::relation_not_found))]
         struct StoredRelationNotFoundError(String);
 
+        // handles read to change them, and those of temporary relations, are not cached
+        let epoch = match self.schema.epoch() {
+            Some(epoch) if !lock && !name.starts_with('_') => Some(epoch),
+            _ => None,
+        };
+        if let Some(epoch) = epoch {
+            if let Some(handle) = self.schema.cache.get(epoch, name) {
+                return Ok(handle);
+            }
+        }
+
         let key = DataValue::from(name);
         let encoded = vec![key].encode_as_key(RelationId::SYSTEM);
 
@@ -660,6 +895,9 @@ impl<'a> SessionTx<'a> {
                 .ok_or_else(|| StoredRelationNotFoundError(name.to_string()))?
         };
         let metadata = RelationHandle::decode(&found)?;
+        if let Some(epoch) = epoch {
+            self.schema.cache.insert(epoch, &metadata);
+        }
         Ok(metadata)
     }
     pub(crate) fn describe_relation(&mut self, name: &str, description: &str) -> Result<()> {
@@ -722,6 +960,175 @@ impl<'a> SessionTx<'a> {
         to_clean.push((lower_bound, upper_bound));
         Ok(to_clean)
     }
+    pub(crate) fn set_expiry_column(&mut self, rel: &Symbol, col: Option<&Symbol>) -> Result<()> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Column {0} not found in relation {1}")]
+        #[diagnostic(code(eval::expiry_col_not_found))]
+        struct ExpiryColumnNotFound(String, String, #[label] SourceSpan);
+
+        if rel.is_temp_store_name() {
+            bail!("Cannot set expiry for temp store")
+        }
+        let mut meta = self.get_relation(rel, true)?;
+        if meta.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                meta.name.to_string(),
+                "set expiry".to_string(),
+                meta.access_level
+            ))
+        }
+        meta.expiry_column = match col {
+            None => None,
+            Some(col) => {
+                ensure!(
+                    meta.metadata
+                        .keys
+                        .iter()
+                        .chain(meta.metadata.non_keys.iter())
+                        .any(|c| c.name == col.name),
+                    ExpiryColumnNotFound(col.name.to_string(), meta.name.to_string(), col.span)
+                );
+                Some(col.name.clone())
+            }
+        };
+
+        let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
+
+        let mut meta_val = vec![];
+        meta.serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&name_key, &meta_val)?;
+
+        Ok(())
+    }
+    pub(crate) fn set_retired_column(&mut self, rel: &Symbol, col: Option<&Symbol>) -> Result<()> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Column {0} of relation {1} cannot be used as a retired marker")]
+        #[diagnostic(code(eval::bad_retired_col))]
+        #[diagnostic(help("The marker must be an existing column of type `Bool` or `Bool?`"))]
+        struct BadRetiredColumn(String, String, #[label] SourceSpan);
+
+        if rel.is_temp_store_name() {
+            bail!("Cannot set retired marker for temp store")
+        }
+        let mut meta = self.get_relation(rel, true)?;
+        if meta.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                meta.name.to_string(),
+                "set retired marker".to_string(),
+                meta.access_level
+            ))
+        }
+        meta.retired_column = match col {
+            None => None,
+            Some(col) => {
+                ensure!(
+                    meta.metadata
+                        .keys
+                        .iter()
+                        .chain(meta.metadata.non_keys.iter())
+                        .any(|c| c.name == col.name && c.typing.coltype == ColType::Bool),
+                    BadRetiredColumn(col.name.to_string(), meta.name.to_string(), col.span)
+                );
+                Some(col.name.clone())
+            }
+        };
+
+        let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
+
+        let mut meta_val = vec![];
+        meta.serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&name_key, &meta_val)?;
+
+        Ok(())
+    }
+    pub(crate) fn set_row_policy(&mut self, rel: &Symbol, policy: Option<&str>) -> Result<()> {
+        self.ensure_unrestricted("set row policies")?;
+        if rel.is_temp_store_name() {
+            bail!("Cannot set row policy for temp store")
+        }
+        let mut meta = self.get_relation(rel, true)?;
+        if meta.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                meta.name.to_string(),
+                "set row policy".to_string(),
+                meta.access_level
+            ))
+        }
+        if let Some(code) = policy {
+            // only columns and the two parameters may appear in the policy
+            let mut expr = parse_row_policy(code, None, None)?;
+            expr.fill_binding_indices(&meta.raw_binding_map())?;
+            expr.compile()?;
+        }
+        meta.row_policy = policy.map(|code| code.to_string());
+
+        let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
+
+        let mut meta_val = vec![];
+        meta.serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&name_key, &meta_val)?;
+
+        Ok(())
+    }
+    /// Grant (or revoke, if `granted` is false) access to `columns` of `rel` to `role`.
+    pub(crate) fn set_grants(
+        &mut self,
+        rel: &Symbol,
+        mode: GrantMode,
+        columns: &[Symbol],
+        role: &str,
+        granted: bool,
+    ) -> Result<()> {
+        self.ensure_unrestricted("manage grants")?;
+        if rel.is_temp_store_name() {
+            bail!("Cannot set grants for temp store")
+        }
+        let mut meta = self.get_relation(rel, true)?;
+        if meta.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                meta.name.to_string(),
+                "setting grants".to_string(),
+                meta.access_level
+            ))
+        }
+        for col in columns {
+            ensure!(
+                meta.metadata
+                    .keys
+                    .iter()
+                    .chain(meta.metadata.non_keys.iter())
+                    .any(|c| c.name == col.name),
+                NamedFieldNotFound::new(&meta, &col.name, col.span)
+            );
+        }
+        let grants = meta.grants.entry(SmartString::from(role)).or_default();
+        let target = match mode {
+            GrantMode::Read => &mut grants.read,
+            GrantMode::Write => &mut grants.write,
+        };
+        for col in columns {
+            if granted {
+                target.insert(col.name.clone());
+            } else {
+                target.remove(&col.name);
+            }
+        }
+        if grants.read.is_empty() && grants.write.is_empty() {
+            meta.grants.remove(role);
+        }
+
+        let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
+
+        let mut meta_val = vec![];
+        meta.serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&name_key, &meta_val)?;
+
+        Ok(())
+    }
     pub(crate) fn set_access_level(&mut self, rel: &Symbol, level: AccessLevel) -> Result<()> {
         let mut meta = self.get_relation(rel, true)?;
         meta.access_level = level;
@@ -1384,11 +1791,6 @@ impl<'a> SessionTx<'a> {
             && rel.lsh_indices.remove(&idx_name.name).is_none()
             && rel.fts_indices.remove(&idx_name.name).is_none()
         {
-            #[derive(Debug, Error, Diagnostic)]
-            #[error("index {0} for relation {1} not found")]
-            #[diagnostic(code(tx::idx_not_found))]
-            pub(crate) struct IndexNotFound(String, String);
-
             bail!(IndexNotFound(idx_name.to_string(), rel_name.to_string()));
         }
 
@@ -1409,6 +1811,102 @@ impl<'a> SessionTx<'a> {
         Ok(to_clean)
     }
 
+    /// Discard the content of the index `idx_name` of `rel_name` and build it again
+    /// from the rows of the relation.
+    pub(crate) fn rebuild_index(&mut self, rel_name: &str, idx_name: &str) -> Result<()> {
+        let rel_handle = self.get_relation(rel_name, true)?;
+        let mut existing = TempCollector::default();
+        for tuple in rel_handle.scan_all(self) {
+            existing.push(tuple?);
+        }
+        let mut stack = vec![];
+        if let Some((idx_handle, extractor)) = rel_handle.indices.get(idx_name) {
+            self.clear_relation(idx_handle)?;
+            for tuple in existing.into_iter() {
+                let extracted = extractor.iter().map(|i| tuple[*i].clone()).collect_vec();
+                let key = idx_handle.encode_key_for_store(&extracted, Default::default())?;
+                self.store_tx.put(&key, &[])?;
+            }
+        } else if let Some((idx_handle, manifest)) = rel_handle.fts_indices.get(idx_name) {
+            self.clear_relation(idx_handle)?;
+            let tokenizer =
+                self.tokenizers
+                    .get(&idx_handle.name, &manifest.tokenizer, &manifest.filters)?;
+            let extractor = compile_relation_expr(&rel_handle, &manifest.extractor)?;
+            for tuple in existing.into_iter() {
+                self.put_fts_index_item(
+                    &tuple,
+                    &extractor,
+                    &mut stack,
+                    &tokenizer,
+                    &rel_handle,
+                    idx_handle,
+                )?;
+            }
+        } else if let Some((idx_handle, inv_idx_handle, manifest)) =
+            rel_handle.lsh_indices.get(idx_name)
+        {
+            self.clear_relation(idx_handle)?;
+            self.clear_relation(inv_idx_handle)?;
+            let tokenizer =
+                self.tokenizers
+                    .get(&idx_handle.name, &manifest.tokenizer, &manifest.filters)?;
+            let extractor = compile_relation_expr(&rel_handle, &manifest.extractor)?;
+            let hash_perms = manifest.get_hash_perms();
+            for tuple in existing.into_iter() {
+                self.put_lsh_index_item(
+                    &tuple,
+                    &extractor,
+                    &mut stack,
+                    &tokenizer,
+                    &rel_handle,
+                    idx_handle,
+                    inv_idx_handle,
+                    manifest,
+                    &hash_perms,
+                )?;
+            }
+        } else if let Some((idx_handle, manifest)) = rel_handle.hnsw_indices.get(idx_name) {
+            self.clear_relation(idx_handle)?;
+            let filter = match &manifest.index_filter {
+                Some(code) => compile_relation_expr(&rel_handle, code)?,
+                None => vec![],
+            };
+            let filter = if filter.is_empty() {
+                None
+            } else {
+                Some(&filter)
+            };
+            for tuple in existing.into_iter() {
+                self.hnsw_put(
+                    manifest,
+                    &rel_handle,
+                    idx_handle,
+                    filter,
+                    &mut stack,
+                    &tuple,
+                )?;
+            }
+        } else {
+            bail!(IndexNotFound(idx_name.to_string(), rel_name.to_string()));
+        }
+        Ok(())
+    }
+
+    fn clear_relation(&mut self, handle: &RelationHandle) -> Result<()> {
+        let lower = Tuple::default().encode_as_key(handle.id);
+        let upper = Tuple::default().encode_as_key(handle.id.next());
+        let keys = self
+            .store_tx
+            .range_scan(&lower, &upper)
+            .map_ok(|(k, _)| k)
+            .collect::<Result<Vec<_>>>()?;
+        for key in keys {
+            self.store_tx.del(&key)?;
+        }
+        Ok(())
+    }
+
     pub(crate) fn rename_relation(&mut self, old: &Symbol, new: &Symbol) -> Result<()> {
         if old.name.starts_with('_') || new.name.starts_with('_') {
             bail!("Bad name given");
@@ -0,0 +1,227 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Tasks run at fixed intervals on background threads, enabled by the `scheduler`
+//! feature, see [`Db::schedule`](crate::Db::schedule).
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam::channel::{bounded, RecvTimeoutError, Sender};
+use itertools::Itertools;
+use log::error;
+use miette::Result;
+
+use crate::data::value::DataValue;
+use crate::runtime::db::{seconds_since_the_epoch, NamedRows};
+use crate::storage::Storage;
+use crate::{Db, ScriptMutability};
+
+/// What a schedule runs, see [`Db::schedule`](crate::Db::schedule).
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScheduledTask {
+    /// Run `script` as a mutable script, so that its writes are committed together,
+    /// e.g. to recompute a relation holding the results of a query
+    Script {
+        /// The script to run
+        script: String,
+        /// The parameters passed to the script
+        params: BTreeMap<String, DataValue>,
+    },
+    /// Remove the expired rows of every relation with an expiry column, as
+    /// [`Db::sweep_expired`](crate::Db::sweep_expired) does with `batch_size`
+    SweepExpired {
+        /// Rows removed by each transaction
+        batch_size: usize,
+    },
+}
+
+impl ScheduledTask {
+    fn kind(&self) -> &'static str {
+        match self {
+            ScheduledTask::Script { .. } => "script",
+            ScheduledTask::SweepExpired { .. } => "sweep_expired",
+        }
+    }
+}
+
+/// The status of a schedule, returned by [`Db::schedules`](crate::Db::schedules) and,
+/// as rows, by the `::schedules` system op.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScheduleStatus {
+    /// The name the task was scheduled under
+    pub name: String,
+    /// What the schedule runs
+    pub task: ScheduledTask,
+    /// The time between the end of a run and the start of the next
+    pub interval: Duration,
+    /// Runs finished so far, failed ones included
+    pub runs: u64,
+    /// Runs that failed so far
+    pub failures: u64,
+    /// When the last run started, in seconds since the epoch, `None` before the first
+    pub last_run: Option<f64>,
+    /// Time taken by the last run, in seconds
+    pub last_duration: Option<f64>,
+    /// The error of the last run, `None` if it succeeded
+    pub last_error: Option<String>,
+}
+
+struct Schedule {
+    status: Arc<Mutex<ScheduleStatus>>,
+    /// Dropping it stops the thread running the schedule
+    _stop: Sender<()>,
+}
+
+/// The schedules of a database, by name.
+#[derive(Default)]
+pub(crate) struct Schedules {
+    schedules: Mutex<BTreeMap<String, Schedule>>,
+}
+
+impl Schedules {
+    pub(crate) fn list(&self) -> Vec<ScheduleStatus> {
+        let statuses = self
+            .schedules
+            .lock()
+            .unwrap()
+            .values()
+            .map(|schedule| schedule.status.clone())
+            .collect_vec();
+        statuses
+            .iter()
+            .map(|status| status.lock().unwrap().clone())
+            .collect()
+    }
+    pub(crate) fn remove(&self, name: &str) -> bool {
+        self.schedules.lock().unwrap().remove(name).is_some()
+    }
+    pub(crate) fn clear(&self) {
+        self.schedules.lock().unwrap().clear();
+    }
+    pub(crate) fn as_named_rows(&self) -> NamedRows {
+        let rows = self
+            .list()
+            .into_iter()
+            .map(|status| {
+                vec![
+                    DataValue::from(status.name),
+                    DataValue::from(status.task.kind()),
+                    DataValue::from(status.interval.as_secs_f64()),
+                    DataValue::from(status.runs as i64),
+                    DataValue::from(status.failures as i64),
+                    status.last_run.map_or(DataValue::Null, DataValue::from),
+                    status
+                        .last_duration
+                        .map_or(DataValue::Null, DataValue::from),
+                    status.last_error.map_or(DataValue::Null, DataValue::from),
+                ]
+            })
+            .collect_vec();
+        NamedRows::new(
+            vec![
+                "name".to_string(),
+                "task".to_string(),
+                "interval".to_string(),
+                "runs".to_string(),
+                "failures".to_string(),
+                "last_run".to_string(),
+                "last_duration".to_string(),
+                "last_error".to_string(),
+            ],
+            rows,
+        )
+    }
+}
+
+impl<S> Db<S>
+where
+    S: for<'s> Storage<'s> + 'static,
+{
+    /// Run `task` on a background thread every `interval`, the first time after
+    /// `interval` has passed, under `name`. Any task already scheduled under `name` is
+    /// unscheduled first. A run that fails is logged and recorded in the status of the
+    /// schedule, the next run happening as usual. The runs of a schedule never overlap,
+    /// the interval being counted from the end of the previous one.
+    ///
+    /// The schedule runs until [`unschedule`](Self::unschedule) is called or the
+    /// database is closed. Its status is returned by [`schedules`](Self::schedules) and
+    /// by the `::schedules` system op.
+    pub fn schedule(&self, name: &str, task: ScheduledTask, interval: Duration) -> Result<()> {
+        self.sessions.ensure_open()?;
+        let (stop_sender, stop_receiver) = bounded::<()>(0);
+        let status = Arc::new(Mutex::new(ScheduleStatus {
+            name: name.to_string(),
+            task: task.clone(),
+            interval,
+            runs: 0,
+            failures: 0,
+            last_run: None,
+            last_duration: None,
+            last_error: None,
+        }));
+        let db = self.clone();
+        let run_status = status.clone();
+        let name = name.to_string();
+        thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stop_receiver.recv_timeout(interval) {
+                if db.sessions.ensure_open().is_err() {
+                    return;
+                }
+                let started_at = seconds_since_the_epoch().ok();
+                let started = Instant::now();
+                let res = db.run_scheduled(&task);
+                let mut status = run_status.lock().unwrap();
+                status.runs += 1;
+                status.last_run = started_at;
+                status.last_duration = Some(started.elapsed().as_secs_f64());
+                status.last_error = match res {
+                    Ok(()) => None,
+                    Err(err) => {
+                        error!("scheduled task {} failed: {err:?}", status.name);
+                        status.failures += 1;
+                        Some(err.to_string())
+                    }
+                };
+            }
+        });
+        self.schedules.schedules.lock().unwrap().insert(
+            name,
+            Schedule {
+                status,
+                _stop: stop_sender,
+            },
+        );
+        Ok(())
+    }
+
+    fn run_scheduled(&self, task: &ScheduledTask) -> Result<()> {
+        match task {
+            ScheduledTask::Script { script, params } => {
+                self.run_script(script, params.clone(), ScriptMutability::Mutable)?;
+            }
+            ScheduledTask::SweepExpired { batch_size } => {
+                self.sweep_expired(*batch_size)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Stop running the task scheduled under `name`, once its current run, if any, is
+    /// done. Returns `false` if there is no such schedule.
+    pub fn unschedule(&self, name: &str) -> bool {
+        self.schedules.remove(name)
+    }
+
+    /// The status of every schedule, ordered by name.
+    pub fn schedules(&self) -> Vec<ScheduleStatus> {
+        self.schedules.list()
+    }
+}
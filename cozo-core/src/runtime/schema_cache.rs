@@ -0,0 +1,237 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! The relation handles read by queries, cached for all the sessions of a database so
+//! that they are not decoded from the storage by every transaction.
+//!
+//! The cache holds the handles of one schema epoch. The epoch changes when a transaction
+//! writing handles commits, and a transaction only uses the cache if the epoch has not
+//! changed since it started, so that it never sees handles newer than its snapshot.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crossbeam::sync::ShardedLock;
+use miette::Result;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::memcmp::STR_TAG;
+use crate::data::tuple::Tuple;
+use crate::data::value::ValidityTs;
+use crate::runtime::relation::{RelationHandle, RelationId};
+use crate::storage::StoreTx;
+
+#[derive(Default)]
+struct SchemaState {
+    epoch: u64,
+    /// Number of transactions with schema changes committing
+    committing: usize,
+    handles: BTreeMap<SmartString<LazyCompact>, RelationHandle>,
+}
+
+pub(crate) struct SchemaCache {
+    /// `false` for storages other processes may change the schema of
+    enabled: bool,
+    /// Read by every transaction, and written when the schema changes or a handle is
+    /// first read in an epoch
+    state: ShardedLock<SchemaState>,
+}
+
+impl SchemaCache {
+    pub(crate) fn new(storage_kind: &str) -> Self {
+        Self {
+            // only these storages are opened by a single process at a time
+            enabled: matches!(storage_kind, "mem" | "rocksdb" | "sled"),
+            state: Default::default(),
+        }
+    }
+    /// The epoch of a transaction starting now, `None` if it cannot use the cache.
+    pub(crate) fn epoch(&self) -> Option<u64> {
+        let state = self.state.read().unwrap();
+        (self.enabled && state.committing == 0).then_some(state.epoch)
+    }
+    pub(crate) fn get(&self, epoch: u64, name: &str) -> Option<RelationHandle> {
+        let state = self.state.read().unwrap();
+        if state.epoch != epoch || state.committing != 0 {
+            return None;
+        }
+        state.handles.get(name).cloned()
+    }
+    /// Cache `handle`, read by a transaction of `epoch`.
+    pub(crate) fn insert(&self, epoch: u64, handle: &RelationHandle) {
+        let mut state = self.state.write().unwrap();
+        if state.epoch == epoch && state.committing == 0 {
+            state.handles.insert(handle.name.clone(), handle.clone());
+        }
+    }
+    /// Called before a transaction changing the schema commits, which must be followed
+    /// by [`end_change`](Self::end_change) whether the commit succeeds or not.
+    pub(crate) fn begin_change(&self) {
+        let mut state = self.state.write().unwrap();
+        state.committing += 1;
+        state.epoch += 1;
+        state.handles.clear();
+    }
+    pub(crate) fn end_change(&self) {
+        self.state.write().unwrap().committing -= 1;
+    }
+    /// Discard the cache after the storage was written to outside of transactions.
+    pub(crate) fn invalidate(&self) {
+        self.begin_change();
+        self.end_change();
+    }
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.state.read().unwrap().handles.len()
+    }
+}
+
+/// The schema cache as seen by a transaction.
+pub(crate) struct SchemaView<'a> {
+    pub(crate) cache: &'a SchemaCache,
+    /// The epoch the transaction started at
+    pub(crate) epoch: Option<u64>,
+    /// Set once the transaction writes a relation handle
+    pub(crate) changed: Arc<AtomicBool>,
+}
+
+impl<'a> SchemaView<'a> {
+    pub(crate) fn new(cache: &'a SchemaCache) -> Self {
+        Self {
+            epoch: cache.epoch(),
+            cache,
+            changed: Default::default(),
+        }
+    }
+    /// The epoch to use the cache at, `None` if the transaction changed the schema.
+    pub(crate) fn epoch(&self) -> Option<u64> {
+        if self.changed.load(Ordering::Relaxed) {
+            None
+        } else {
+            self.epoch
+        }
+    }
+    pub(crate) fn changed(&self) -> bool {
+        self.changed.load(Ordering::Relaxed)
+    }
+}
+
+/// Relation handles are stored under their names in the system relation.
+fn is_handle_key(key: &[u8]) -> bool {
+    key.len() > 8 && key[..8] == RelationId::SYSTEM.raw_encode() && key[8] == STR_TAG
+}
+
+/// Wraps the storage transaction of a session, noting whether it writes relation
+/// handles.
+pub(crate) struct SchemaWatchTx<'s> {
+    pub(crate) inner: Box<dyn StoreTx<'s> + 's>,
+    pub(crate) changed: Arc<AtomicBool>,
+}
+
+impl<'s> SchemaWatchTx<'s> {
+    fn watch(&self, key: &[u8]) {
+        if is_handle_key(key) {
+            self.changed.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<'s> StoreTx<'s> for SchemaWatchTx<'s> {
+    fn get(&self, key: &[u8], for_update: bool) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key, for_update)
+    }
+
+    fn multi_get(&self, keys: &[Vec<u8>], for_update: bool) -> Result<Vec<Option<Vec<u8>>>> {
+        self.inner.multi_get(keys, for_update)
+    }
+
+    fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.watch(key);
+        self.inner.put(key, val)
+    }
+
+    fn supports_par_put(&self) -> bool {
+        self.inner.supports_par_put()
+    }
+
+    fn par_put(&self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.watch(key);
+        self.inner.par_put(key, val)
+    }
+
+    fn del(&mut self, key: &[u8]) -> Result<()> {
+        self.watch(key);
+        self.inner.del(key)
+    }
+
+    fn par_del(&self, key: &[u8]) -> Result<()> {
+        self.watch(key);
+        self.inner.par_del(key)
+    }
+
+    fn del_range_from_persisted(&mut self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        if lower < &RelationId::SYSTEM.next().raw_encode()[..] {
+            self.changed.store(true, Ordering::Relaxed);
+        }
+        self.inner.del_range_from_persisted(lower, upper)
+    }
+
+    fn exists(&self, key: &[u8], for_update: bool) -> Result<bool> {
+        self.inner.exists(key, for_update)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.inner.commit()
+    }
+
+    fn range_skip_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        valid_at: ValidityTs,
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
+        self.inner.range_skip_scan_tuple(lower, upper, valid_at)
+    }
+
+    fn range_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.range_scan_tuple(lower, upper)
+    }
+
+    fn range_scan<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.range_scan(lower, upper)
+    }
+
+    fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
+    where
+        's: 'a,
+    {
+        self.inner.range_count(lower, upper)
+    }
+
+    fn total_scan<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.total_scan()
+    }
+}
@@ -0,0 +1,240 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, VecDeque};
+use std::iter::Peekable;
+use std::str::Chars;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use itertools::Itertools;
+
+use crate::data::json::JsonValue;
+use crate::data::value::{DataValue, JsonData};
+use crate::runtime::db::NamedRows;
+
+/// Called with every query recorded by the slow query log.
+pub type SlowQueryCallback = Box<dyn Fn(&SlowQuery) + Send + Sync>;
+
+/// A script that ran for longer than the threshold of the slow query log,
+/// see [`Db::set_slow_query_log`](crate::Db::set_slow_query_log).
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlowQuery {
+    /// The script with comments removed and whitespace collapsed, so that runs of
+    /// the same query compare equal
+    pub script: String,
+    /// Parameters passed to the script
+    pub params: BTreeMap<String, DataValue>,
+    /// When the script started, in seconds since the epoch
+    pub started_at: f64,
+    /// Time taken by the script, in seconds
+    pub duration: f64,
+    /// Number of rows returned, `None` if the script failed
+    pub rows: Option<usize>,
+}
+
+struct SlowLogConfig {
+    threshold: f64,
+    capacity: usize,
+    callback: Option<Arc<SlowQueryCallback>>,
+}
+
+/// The most recent slow queries, oldest first.
+#[derive(Default)]
+pub(crate) struct SlowQueryLog {
+    config: Mutex<Option<SlowLogConfig>>,
+    entries: Mutex<VecDeque<SlowQuery>>,
+}
+
+impl SlowQueryLog {
+    pub(crate) fn configure(
+        &self,
+        threshold: Duration,
+        capacity: usize,
+        callback: Option<SlowQueryCallback>,
+    ) {
+        *self.config.lock().unwrap() = Some(SlowLogConfig {
+            threshold: threshold.as_secs_f64(),
+            capacity,
+            callback: callback.map(Arc::new),
+        });
+        let mut entries = self.entries.lock().unwrap();
+        while entries.len() > capacity {
+            entries.pop_front();
+        }
+    }
+    pub(crate) fn disable(&self) -> bool {
+        self.config.lock().unwrap().take().is_some()
+    }
+    pub(crate) fn record(
+        &self,
+        script: &str,
+        params: &BTreeMap<String, DataValue>,
+        started_at: f64,
+        duration: f64,
+        rows: Option<usize>,
+    ) {
+        let (capacity, callback) = match &*self.config.lock().unwrap() {
+            Some(config) if duration >= config.threshold => {
+                (config.capacity, config.callback.clone())
+            }
+            _ => return,
+        };
+        let entry = SlowQuery {
+            script: normalize_script(script),
+            params: params.clone(),
+            started_at,
+            duration,
+            rows,
+        };
+        // the callback runs without any lock held, so that it may use the database
+        if let Some(callback) = callback {
+            callback(&entry);
+        }
+        if capacity > 0 {
+            let mut entries = self.entries.lock().unwrap();
+            if entries.len() >= capacity {
+                entries.pop_front();
+            }
+            entries.push_back(entry);
+        }
+    }
+    pub(crate) fn entries(&self) -> Vec<SlowQuery> {
+        self.entries.lock().unwrap().iter().cloned().collect()
+    }
+    pub(crate) fn as_named_rows(&self) -> NamedRows {
+        let rows = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|entry| {
+                let params: JsonValue = entry
+                    .params
+                    .iter()
+                    .map(|(k, v)| (k.clone(), JsonValue::from(v.clone())))
+                    .collect::<serde_json::Map<_, _>>()
+                    .into();
+                vec![
+                    DataValue::from(entry.started_at),
+                    DataValue::from(entry.duration),
+                    match entry.rows {
+                        None => DataValue::Null,
+                        Some(n) => DataValue::from(n as i64),
+                    },
+                    DataValue::from(entry.script.as_str()),
+                    DataValue::Json(JsonData(params)),
+                ]
+            })
+            .collect_vec();
+        NamedRows::new(
+            ["started_at", "duration", "rows", "script", "params"]
+                .into_iter()
+                .map(|s| s.to_string())
+                .collect(),
+            rows,
+        )
+    }
+}
+
+/// Remove comments and collapse whitespace outside of string literals.
+pub(crate) fn normalize_script(script: &str) -> String {
+    let mut out = String::with_capacity(script.len());
+    let mut chars = script.chars().peekable();
+    let mut pending_space = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '#' => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+                pending_space = true;
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                skip_block_comment(&mut chars);
+                pending_space = true;
+            }
+            c if c.is_whitespace() => pending_space = true,
+            c => {
+                if pending_space && !out.is_empty() {
+                    out.push(' ');
+                }
+                pending_space = false;
+                if c == '"' {
+                    // raw strings are opened by underscores directly followed by the quote
+                    let underscores = out.len() - out.trim_end_matches('_').len();
+                    out.push(c);
+                    if underscores > 0 {
+                        copy_raw_string(&mut chars, &mut out, underscores);
+                    } else {
+                        copy_quoted_string(&mut chars, &mut out, '"');
+                    }
+                } else {
+                    out.push(c);
+                    if c == '\'' {
+                        copy_quoted_string(&mut chars, &mut out, '\'');
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+fn skip_block_comment(chars: &mut Peekable<Chars<'_>>) {
+    let mut depth = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                depth += 1;
+            }
+            '*' if chars.peek() == Some(&'/') => {
+                chars.next();
+                depth -= 1;
+                if depth == 0 {
+                    return;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn copy_quoted_string(chars: &mut Peekable<Chars<'_>>, out: &mut String, quote: char) {
+    while let Some(c) = chars.next() {
+        out.push(c);
+        if c == '\\' {
+            if let Some(escaped) = chars.next() {
+                out.push(escaped);
+            }
+        } else if c == quote {
+            return;
+        }
+    }
+}
+
+fn copy_raw_string(chars: &mut Peekable<Chars<'_>>, out: &mut String, underscores: usize) {
+    while let Some(c) = chars.next() {
+        out.push(c);
+        if c == '"' {
+            let mut closing = 0;
+            while closing < underscores && chars.peek() == Some(&'_') {
+                chars.next();
+                out.push('_');
+                closing += 1;
+            }
+            if closing == underscores {
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,115 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Read-only views of the database kept across queries, see [`Db::snapshot`].
+
+use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
+
+use miette::{bail, Result};
+
+use crate::data::functions::current_validity;
+use crate::data::value::{DataValue, ValidityTs};
+use crate::parse::parse_script;
+use crate::runtime::db::{seconds_since_the_epoch, with_script_source, ReadOnlyViolation};
+use crate::runtime::transact::SessionTx;
+use crate::runtime::tx_log::last_tx_log_seq;
+use crate::storage::Storage;
+use crate::{Db, NamedRows};
+
+type SnapshotQuery<'s> = dyn FnMut(&str, &BTreeMap<String, DataValue>) -> Result<NamedRows> + 's;
+
+/// The database as it was when [`Db::snapshot`] was called: every query run on the
+/// snapshot sees the same data, whatever is committed meanwhile, until it is dropped.
+pub struct Snapshot<'s> {
+    tx_id: u64,
+    validity: ValidityTs,
+    run: Box<SnapshotQuery<'s>>,
+}
+
+impl<'s> Snapshot<'s> {
+    /// Run the read-only query `payload` against the snapshot. Scripts that write, and
+    /// system ops, are refused.
+    pub fn run_script(
+        &mut self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<NamedRows> {
+        (self.run)(payload, &params)
+    }
+
+    /// The number of the last transaction of the transaction log the snapshot sees, see
+    /// [`TxLogEntry::seq`](crate::TxLogEntry::seq), or zero if the log is empty.
+    pub fn tx_id(&self) -> u64 {
+        self.tx_id
+    }
+
+    /// The validity used as `NOW` by the time-travel queries run on the snapshot, fixed
+    /// when it was taken so that they see the same rows as well.
+    pub fn validity(&self) -> ValidityTs {
+        self.validity
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Take a snapshot of the database to run several queries against, all seeing the
+    /// same data, instead of each query seeing the data committed when it starts.
+    ///
+    /// The snapshot keeps a read transaction open for as long as it lives. With RocksDB
+    /// and TiKV this only keeps the old versions of the data around, but with the in-memory
+    /// and SQLite engines, as for any read transaction, writers wait until it is dropped.
+    /// Sled does not isolate transactions, so its snapshots see the data as it changes.
+    pub fn snapshot(&'s self) -> Result<Snapshot<'s>> {
+        let mut tx = self.transact()?;
+        // reading through the transaction also pins the view of engines taking it lazily
+        let tx_id = last_tx_log_seq(&*tx.store_tx)?;
+        let validity = current_validity();
+        Ok(Snapshot {
+            tx_id,
+            validity,
+            run: Box::new(move |payload, params| {
+                let started_at = seconds_since_the_epoch()?;
+                let res = self.run_in_snapshot(&mut tx, payload, params, validity);
+                self.observe_query(payload, params, started_at, &res);
+                res.map_err(|err| with_script_source(err, payload))
+            }),
+        })
+    }
+
+    fn run_in_snapshot(
+        &'s self,
+        tx: &mut SessionTx<'_>,
+        payload: &str,
+        params: &BTreeMap<String, DataValue>,
+        validity: ValidityTs,
+    ) -> Result<NamedRows> {
+        let script = parse_script(
+            payload,
+            params,
+            &self.fixed_rules.read().unwrap(),
+            validity,
+            self.string_literals_forbidden.load(Ordering::Relaxed),
+        )?;
+        let p = script.get_single_program()?;
+        if p.needs_write_lock().is_some() {
+            bail!(ReadOnlyViolation("snapshots cannot be written to"));
+        }
+        let res = tx.session.start_query(payload, None).and_then(|_| {
+            self.execute_single_program(
+                p,
+                tx,
+                &mut vec![],
+                validity,
+                &Default::default(),
+                &mut Default::default(),
+            )
+        });
+        tx.session.end_query();
+        res
+    }
+}
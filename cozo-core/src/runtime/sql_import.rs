@@ -0,0 +1,431 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Import of the tables of SQL databases into relations, enabled by the `sql-import`
+//! feature, see [`Db::import_sqlite_snapshot`](crate::Db::import_sqlite_snapshot).
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use itertools::Itertools;
+use log::error;
+use miette::{bail, ensure, IntoDiagnostic, Result};
+use sqlite::{Connection, OpenFlags, State, Value};
+
+use crate::data::value::DataValue;
+use crate::runtime::db::NamedRows;
+use crate::storage::Storage;
+use crate::{Db, ScriptMutability};
+
+/// How the names of tables and columns become those of relations and columns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColumnNaming {
+    /// Keep the names, failing on those that are not identifiers
+    #[default]
+    AsIs,
+    /// Convert the names to `snake_case`, replacing the characters not allowed in
+    /// identifiers with `_`
+    SnakeCase,
+    /// As [`SnakeCase`](Self::SnakeCase), and prefix the names of columns with the name
+    /// of their table, as in `customer_name`
+    TablePrefixed,
+}
+
+/// Options of [`Db::import_sqlite_snapshot`](crate::Db::import_sqlite_snapshot).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SqlImportOptions {
+    /// The tables to import, all of them if `None`
+    pub tables: Option<Vec<String>>,
+    /// Prepended to the name of each relation
+    pub relation_prefix: String,
+    /// How the names of tables and columns are converted
+    pub naming: ColumnNaming,
+    /// Rows written by each transaction, as in
+    /// [`Db::import_relations_chunked`](crate::Db::import_relations_chunked)
+    pub batch_rows: usize,
+}
+
+impl Default for SqlImportOptions {
+    fn default() -> Self {
+        Self {
+            tables: None,
+            relation_prefix: String::new(),
+            naming: ColumnNaming::default(),
+            batch_rows: 100000,
+        }
+    }
+}
+
+/// A foreign key of an imported table, in the names of the relations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SqlForeignKey {
+    /// The columns holding the key
+    pub columns: Vec<String>,
+    /// The relation of the referenced table
+    pub relation: String,
+    /// The referenced columns, in the same order
+    pub ref_columns: Vec<String>,
+}
+
+/// A table imported by [`Db::import_sqlite_snapshot`](crate::Db::import_sqlite_snapshot).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SqlTableImport {
+    /// The name of the table
+    pub table: String,
+    /// The relation the rows were imported into
+    pub relation: String,
+    /// The number of rows imported
+    pub rows: usize,
+    /// The foreign keys of the table
+    pub foreign_keys: Vec<SqlForeignKey>,
+}
+
+struct SqlColumn {
+    name: String,
+    column: String,
+    typ: &'static str,
+    nullable: bool,
+}
+
+struct SqlTable {
+    name: String,
+    relation: String,
+    keys: Vec<SqlColumn>,
+    values: Vec<SqlColumn>,
+    foreign_keys: Vec<SqlForeignKey>,
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn snake_case(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    let mut prev_lower = false;
+    for c in name.chars() {
+        if c.is_ascii_uppercase() {
+            if prev_lower {
+                out.push('_');
+            }
+            out.push(c.to_ascii_lowercase());
+            prev_lower = false;
+        } else if c.is_ascii_alphanumeric() {
+            out.push(c);
+            prev_lower = true;
+        } else {
+            out.push('_');
+            prev_lower = false;
+        }
+    }
+    if !out.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        out.insert(0, 't');
+    }
+    out
+}
+
+impl ColumnNaming {
+    fn relation(self, prefix: &str, table: &str) -> Result<String> {
+        let name = match self {
+            ColumnNaming::AsIs => format!("{prefix}{table}"),
+            _ => format!("{prefix}{}", snake_case(table)),
+        };
+        ensure!(
+            is_identifier(&name),
+            "The table '{table}' cannot be imported as the relation '{name}'"
+        );
+        Ok(name)
+    }
+    fn column(self, table: &str, column: &str) -> Result<String> {
+        let name = match self {
+            ColumnNaming::AsIs => column.to_string(),
+            ColumnNaming::SnakeCase => snake_case(column),
+            ColumnNaming::TablePrefixed => {
+                format!("{}_{}", snake_case(table), snake_case(column))
+            }
+        };
+        ensure!(
+            is_identifier(&name),
+            "The column '{column}' of the table '{table}' cannot be imported as '{name}'"
+        );
+        Ok(name)
+    }
+}
+
+/// The type of the column of a relation for a column declared as `declared`, following
+/// the rules SQLite uses to give columns their affinity.
+fn column_type(declared: &str) -> &'static str {
+    let declared = declared.to_ascii_uppercase();
+    if declared.contains("INT") {
+        "Int"
+    } else if ["CHAR", "CLOB", "TEXT"]
+        .iter()
+        .any(|t| declared.contains(t))
+    {
+        "String"
+    } else if declared.contains("BLOB") {
+        "Bytes"
+    } else if ["REAL", "FLOA", "DOUB"]
+        .iter()
+        .any(|t| declared.contains(t))
+    {
+        "Float"
+    } else {
+        "Any"
+    }
+}
+
+fn quote_sql(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Run `query` and collect its rows.
+fn sql_rows(conn: &Connection, query: &str) -> Result<Vec<Vec<Value>>> {
+    let mut statement = conn.prepare(query).into_diagnostic()?;
+    let n = statement.column_count();
+    let mut rows = vec![];
+    while statement.next().into_diagnostic()? == State::Row {
+        let row: Vec<Value> = (0..n)
+            .map(|i| statement.read::<Value, _>(i))
+            .try_collect()
+            .into_diagnostic()?;
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+fn sql_str(value: &Value) -> Option<&str> {
+    match value {
+        Value::String(s) => Some(s),
+        _ => None,
+    }
+}
+
+fn sql_int(value: &Value) -> i64 {
+    match value {
+        Value::Integer(i) => *i,
+        _ => 0,
+    }
+}
+
+fn sql_value(value: Value) -> DataValue {
+    match value {
+        Value::Binary(b) => DataValue::Bytes(b),
+        Value::Float(f) => DataValue::from(f),
+        Value::Integer(i) => DataValue::from(i),
+        Value::String(s) => DataValue::from(s),
+        Value::Null => DataValue::Null,
+    }
+}
+
+/// The rows of `PRAGMA table_info`: the name, the declared type, whether the column is
+/// `NOT NULL`, and its position in the primary key, from `1`, or `0`.
+fn table_info(conn: &Connection, table: &str) -> Result<Vec<(String, String, bool, i64)>> {
+    let rows = sql_rows(conn, &format!("PRAGMA table_info({})", quote_sql(table)))?;
+    ensure!(!rows.is_empty(), "The table '{table}' does not exist");
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let name = sql_str(&row[1]).unwrap_or_default().to_string();
+            let declared = sql_str(&row[2]).unwrap_or_default().to_string();
+            (name, declared, sql_int(&row[3]) != 0, sql_int(&row[5]))
+        })
+        .collect())
+}
+
+fn introspect(conn: &Connection, options: &SqlImportOptions) -> Result<Vec<SqlTable>> {
+    let names = match &options.tables {
+        Some(tables) => tables.clone(),
+        None => sql_rows(
+            conn,
+            "SELECT name FROM sqlite_master WHERE type = 'table' \
+             AND name NOT LIKE 'sqlite_%' ORDER BY name",
+        )?
+        .iter()
+        .filter_map(|row| sql_str(&row[0]).map(str::to_string))
+        .collect(),
+    };
+    let naming = options.naming;
+    let mut tables = vec![];
+    for name in names {
+        let relation = naming.relation(&options.relation_prefix, &name)?;
+        let info = table_info(conn, &name)?;
+        let mut keys = vec![];
+        let mut values = vec![];
+        for (column, declared, not_null, pk) in &info {
+            let col = SqlColumn {
+                name: column.clone(),
+                column: naming.column(&name, column)?,
+                typ: column_type(declared),
+                nullable: !not_null && *pk == 0,
+            };
+            if *pk > 0 {
+                keys.push((*pk, col));
+            } else {
+                values.push(col);
+            }
+        }
+        keys.sort_by_key(|(pk, _)| *pk);
+        let mut keys = keys.into_iter().map(|(_, col)| col).collect_vec();
+        // tables without primary key are keyed by their rowid
+        if keys.is_empty() {
+            keys.push(SqlColumn {
+                name: "rowid".to_string(),
+                column: naming.column(&name, "rowid")?,
+                typ: "Int",
+                nullable: false,
+            });
+        }
+        let columns = keys.iter().chain(values.iter()).map(|c| &c.column);
+        if let Some(dup) = columns.duplicates().next() {
+            bail!("The table '{name}' has several columns imported as '{dup}'")
+        }
+
+        let mut fks: BTreeMap<i64, (String, Vec<String>, Vec<Option<String>>)> = BTreeMap::new();
+        let fk_rows = sql_rows(
+            conn,
+            &format!("PRAGMA foreign_key_list({})", quote_sql(&name)),
+        )?;
+        for row in fk_rows {
+            let fk = fks.entry(sql_int(&row[0])).or_default();
+            fk.0 = sql_str(&row[2]).unwrap_or_default().to_string();
+            fk.1.push(sql_str(&row[3]).unwrap_or_default().to_string());
+            fk.2.push(sql_str(&row[4]).map(str::to_string));
+        }
+        let mut foreign_keys = vec![];
+        for (ref_table, from, to) in fks.into_values() {
+            // the referenced columns are omitted when they are the primary key
+            let to = if to.iter().any(Option::is_none) {
+                let mut pk = table_info(conn, &ref_table)?
+                    .into_iter()
+                    .filter(|(_, _, _, pk)| *pk > 0)
+                    .collect_vec();
+                pk.sort_by_key(|(_, _, _, pk)| *pk);
+                pk.into_iter().map(|(name, _, _, _)| name).collect_vec()
+            } else {
+                to.into_iter().flatten().collect()
+            };
+            foreign_keys.push(SqlForeignKey {
+                columns: from.iter().map(|c| naming.column(&name, c)).try_collect()?,
+                relation: naming.relation(&options.relation_prefix, &ref_table)?,
+                ref_columns: to
+                    .iter()
+                    .map(|c| naming.column(&ref_table, c))
+                    .try_collect()?,
+            });
+        }
+        tables.push(SqlTable {
+            name,
+            relation,
+            keys,
+            values,
+            foreign_keys,
+        });
+    }
+    Ok(tables)
+}
+
+impl SqlTable {
+    fn schema(&self) -> String {
+        let spec = |cols: &[SqlColumn]| {
+            cols.iter()
+                .map(|c| {
+                    let nullable = if c.nullable { "?" } else { "" };
+                    format!("{}: {}{nullable}", c.column, c.typ)
+                })
+                .join(", ")
+        };
+        if self.values.is_empty() {
+            format!("{{{}}}", spec(&self.keys))
+        } else {
+            format!("{{{} => {}}}", spec(&self.keys), spec(&self.values))
+        }
+    }
+    fn rows(&self, conn: &Connection) -> Result<NamedRows> {
+        let columns = self.keys.iter().chain(self.values.iter()).collect_vec();
+        let query = format!(
+            "SELECT {} FROM {}",
+            columns.iter().map(|c| quote_sql(&c.name)).join(", "),
+            quote_sql(&self.name)
+        );
+        let rows = sql_rows(conn, &query)?
+            .into_iter()
+            .map(|row| row.into_iter().map(sql_value).collect_vec())
+            .collect_vec();
+        Ok(NamedRows::new(
+            columns.iter().map(|c| c.column.clone()).collect(),
+            rows,
+        ))
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Import the tables of the SQLite database at `path`, opened read-only, each into a
+    /// relation of its own, for migrations from SQL databases. The relations are created,
+    /// and must not exist. Their keys are the columns of the primary key of the table, or
+    /// a `rowid` column for tables without one, and their types are given by the affinity
+    /// of the declared types: `Int`, `Float`, `String`, `Bytes`, or `Any` for `NUMERIC`
+    /// and undeclared types. Columns without `NOT NULL` are nullable.
+    ///
+    /// Foreign keys become plain columns holding the key of the referenced row, which
+    /// queries join on, and are listed in the returned report. The rows are imported as
+    /// [`import_relations_chunked`](Self::import_relations_chunked) does, with
+    /// `options.batch_rows` rows per transaction; if the import fails, the relations it
+    /// created are removed. The rows of each table are read in memory at once.
+    pub fn import_sqlite_snapshot(
+        &'s self,
+        path: impl AsRef<Path>,
+        options: &SqlImportOptions,
+    ) -> Result<Vec<SqlTableImport>> {
+        let conn = Connection::open_with_flags(path, OpenFlags::new().set_read_only())
+            .into_diagnostic()?;
+        let tables = introspect(&conn, options)?;
+        if let Some(dup) = tables.iter().map(|t| &t.relation).duplicates().next() {
+            bail!("Several tables would be imported into the relation '{dup}'")
+        }
+        let script = tables
+            .iter()
+            .map(|t| format!("{{:create {} {}}}", t.relation, t.schema()))
+            .join("\n");
+        if script.is_empty() {
+            return Ok(vec![]);
+        }
+        self.run_script(&script, Default::default(), ScriptMutability::Mutable)?;
+
+        let import = || -> Result<Vec<SqlTableImport>> {
+            let mut data = BTreeMap::new();
+            let mut report = vec![];
+            for table in &tables {
+                let rows = table.rows(&conn)?;
+                report.push(SqlTableImport {
+                    table: table.name.clone(),
+                    relation: table.relation.clone(),
+                    rows: rows.rows.len(),
+                    foreign_keys: table.foreign_keys.clone(),
+                });
+                data.insert(table.relation.clone(), rows);
+            }
+            self.import_relations_chunked(data, options.batch_rows)?;
+            Ok(report)
+        };
+        let res = import();
+        if res.is_err() {
+            let relations = tables.iter().map(|t| &t.relation).join(", ");
+            let removed = self.run_script(
+                &format!("::remove {relations}"),
+                Default::default(),
+                ScriptMutability::Mutable,
+            );
+            if let Err(err) = removed {
+                error!("Cannot remove the relations of a failed SQL import: {err}");
+            }
+        }
+        res
+    }
+}
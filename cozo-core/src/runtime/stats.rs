@@ -0,0 +1,246 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Value statistics of stored relations gathered by [`Db::analyze`], for estimating how
+//! many rows range predicates select.
+
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use miette::Result;
+
+use crate::data::tuple::{Tuple, TupleT};
+use crate::data::value::DataValue;
+use crate::runtime::relation::RelationHandle;
+use crate::storage::Storage;
+use crate::Db;
+
+/// Number of buckets of the histograms built by [`Db::analyze`].
+pub const HISTOGRAM_BUCKETS: usize = 32;
+
+/// The statistics of a stored relation, see [`Db::analyze`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelationAnalysis {
+    /// Name of the relation
+    pub relation: String,
+    /// Number of rows
+    pub rows: usize,
+    /// Estimated size of the relation in bytes, if the engine supports estimation
+    pub approximate_size: Option<u64>,
+    /// Histograms of the columns leading the key of the relation or of one of its
+    /// indices, by column name
+    pub histograms: BTreeMap<String, ColumnHistogram>,
+}
+
+/// An equi-depth histogram of the values of a column: every bucket holds about the same
+/// number of rows, so that buckets are narrow where values are dense.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnHistogram {
+    /// Number of rows
+    pub rows: usize,
+    /// Number of distinct values
+    pub distinct: usize,
+    /// The bounds of the buckets, in ascending order: the first is the smallest value
+    /// and the last the largest, and bucket `i` holds the values between `bounds[i]` and
+    /// `bounds[i + 1]`. Empty if there are no rows.
+    pub bounds: Vec<DataValue>,
+}
+
+impl ColumnHistogram {
+    fn build(mut values: Vec<DataValue>, buckets: usize) -> Self {
+        values.sort();
+        let rows = values.len();
+        let distinct = values.iter().dedup().count();
+        let bounds = if rows == 0 {
+            vec![]
+        } else {
+            let buckets = buckets.min(rows);
+            (0..=buckets)
+                .map(|i| values[i * (rows - 1) / buckets].clone())
+                .collect_vec()
+        };
+        Self {
+            rows,
+            distinct,
+            bounds,
+        }
+    }
+
+    /// Estimate the fraction of rows whose value is between `lower` and `upper`, both
+    /// inclusive, with `None` leaving that side unbounded. Within a bucket, numbers are
+    /// taken to be spread evenly, and half of a bucket of other values is taken to be in
+    /// the range if its bounds straddle the range.
+    pub fn range_selectivity(&self, lower: Option<&DataValue>, upper: Option<&DataValue>) -> f64 {
+        if self.bounds.is_empty() {
+            return 0.;
+        }
+        let buckets = self.bounds.len() - 1;
+        self.bounds
+            .iter()
+            .tuple_windows()
+            .map(|(lo, hi)| self.bucket_fraction(lo, hi, lower, upper))
+            .sum::<f64>()
+            / buckets as f64
+    }
+
+    fn bucket_fraction(
+        &self,
+        lo: &DataValue,
+        hi: &DataValue,
+        lower: Option<&DataValue>,
+        upper: Option<&DataValue>,
+    ) -> f64 {
+        let from = match lower {
+            Some(l) if l > hi => return 0.,
+            Some(l) if l > lo => l,
+            _ => lo,
+        };
+        let to = match upper {
+            Some(u) if u < lo => return 0.,
+            Some(u) if u < hi => u,
+            _ => hi,
+        };
+        if from == lo && to == hi {
+            return 1.;
+        }
+        match (
+            lo.get_float(),
+            hi.get_float(),
+            from.get_float(),
+            to.get_float(),
+        ) {
+            (Some(lo), Some(hi), Some(from), Some(to)) if hi > lo => {
+                ((to - from) / (hi - lo)).clamp(0., 1.)
+            }
+            _ => 0.5,
+        }
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Gather the statistics of every stored relation, building histograms of the
+    /// columns leading the key of the relation or of one of its indices, as those are
+    /// the columns range scans are done on. The statistics replace those of the previous
+    /// call and are kept by the database, see [`Db::relation_analysis`]; they are not
+    /// updated by later writes. This reads the whole database.
+    pub fn analyze(&'s self) -> Result<Vec<RelationAnalysis>> {
+        let tx = self.transact()?;
+        let mut ret = vec![];
+        for handle in self.stored_relations(&tx)? {
+            if handle.name.contains(':') {
+                continue;
+            }
+            let mut columns = vec![0];
+            columns.extend(handle.indices.values().map(|(_, extractor)| extractor[0]));
+            columns.sort_unstable();
+            columns.dedup();
+            let mut values = vec![vec![]; columns.len()];
+            let mut rows = 0;
+            for tuple in handle.scan_all(&tx) {
+                let tuple = tuple?;
+                rows += 1;
+                for (col, values) in columns.iter().zip(values.iter_mut()) {
+                    values.push(tuple[*col].clone());
+                }
+            }
+            let names = handle
+                .metadata
+                .keys
+                .iter()
+                .chain(handle.metadata.non_keys.iter())
+                .map(|col| col.name.to_string())
+                .collect_vec();
+            let histograms = columns
+                .iter()
+                .zip(values)
+                .map(|(col, values)| {
+                    (
+                        names[*col].clone(),
+                        ColumnHistogram::build(values, HISTOGRAM_BUCKETS),
+                    )
+                })
+                .collect();
+            let lower = Tuple::default().encode_as_key(handle.id);
+            let upper = Tuple::default().encode_as_key(handle.id.next());
+            ret.push(RelationAnalysis {
+                relation: handle.name.to_string(),
+                rows,
+                approximate_size: self.db.approximate_size(&lower, &upper)?,
+                histograms,
+            });
+        }
+        *self.analysis.lock().unwrap() = ret
+            .iter()
+            .map(|analysis| (analysis.relation.clone(), analysis.clone()))
+            .collect();
+        Ok(ret)
+    }
+
+    /// Estimate the number of rows of `relation` without reading them, from the rows
+    /// counted by the last call of [`Db::analyze`], scaled by how much the size of the
+    /// relation estimated by the engine has changed since. When the relation was not
+    /// analyzed, or the engine cannot estimate sizes, the rows are counted instead.
+    pub fn estimate_count(&'s self, relation: &str) -> Result<usize> {
+        let tx = self.transact()?;
+        let handle = tx.get_relation(relation, false)?;
+        if let Some(analysis) = self.relation_analysis(relation) {
+            if let Some(rows) = self.scaled_rows(&handle, &analysis)? {
+                return Ok(rows.round() as usize);
+            }
+        }
+        let lower = Tuple::default().encode_as_key(handle.id);
+        let upper = Tuple::default().encode_as_key(handle.id.next());
+        tx.store_tx.range_count(&lower, &upper)
+    }
+
+    /// The rows counted by `analysis` scaled by how much the size of the relation of
+    /// `handle` estimated by the engine has changed since, `None` if the engine cannot
+    /// estimate sizes.
+    pub(crate) fn scaled_rows(
+        &'s self,
+        handle: &RelationHandle,
+        analysis: &RelationAnalysis,
+    ) -> Result<Option<f64>> {
+        let lower = Tuple::default().encode_as_key(handle.id);
+        let upper = Tuple::default().encode_as_key(handle.id.next());
+        let size = self.db.approximate_size(&lower, &upper)?;
+        Ok(match (analysis.approximate_size, size) {
+            (Some(then), Some(now)) if then > 0 => {
+                Some(analysis.rows as f64 * now as f64 / then as f64)
+            }
+            _ => None,
+        })
+    }
+
+    /// The statistics of `relation` gathered by the last call of [`Db::analyze`].
+    pub fn relation_analysis(&'s self, relation: &str) -> Option<RelationAnalysis> {
+        self.analysis.lock().unwrap().get(relation).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_selectivity() {
+        let values = (0..1000).map(|i| DataValue::from(i * i)).collect_vec();
+        let hist = ColumnHistogram::build(values, HISTOGRAM_BUCKETS);
+        assert_eq!(hist.bounds.len(), HISTOGRAM_BUCKETS + 1);
+        let estimate = |lo: i64, hi: i64| {
+            hist.range_selectivity(Some(&DataValue::from(lo)), Some(&DataValue::from(hi)))
+        };
+        // a tenth of the rows, spread over the first hundredth of the values
+        assert!((estimate(0, 10000) - 0.1).abs() < 0.02);
+        assert!((estimate(250000, 1000000) - 0.5).abs() < 0.02);
+        assert_eq!(hist.range_selectivity(None, None), 1.);
+        assert_eq!(estimate(-10, -1), 0.);
+        let empty = ColumnHistogram::build(vec![], HISTOGRAM_BUCKETS);
+        assert_eq!(empty.range_selectivity(None, None), 0.);
+    }
+}
@@ -0,0 +1,67 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! State updated by every session, striped by thread so that sessions running on
+//! different threads do not write to the same locks and cache lines.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use crossbeam::utils::CachePadded;
+
+/// Number of stripes. Threads are spread over them in the order they first use one.
+pub(crate) const STRIPES: usize = 16;
+
+/// The stripe of the current thread.
+pub(crate) fn current_stripe() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    thread_local! {
+        static STRIPE: usize = NEXT.fetch_add(1, Ordering::Relaxed) % STRIPES;
+    }
+    STRIPE.with(|stripe| *stripe)
+}
+
+/// Identifiers allocated in a stripe end with the number of the stripe, so that they are
+/// unique across stripes and the stripe can be found from the identifier.
+pub(crate) fn striped_id(stripe: usize, seq: u64) -> u64 {
+    seq * STRIPES as u64 + stripe as u64
+}
+
+/// The stripe an identifier made by [`striped_id`] was allocated in.
+pub(crate) fn stripe_of(id: u64) -> usize {
+    (id % STRIPES as u64) as usize
+}
+
+/// A counter whose stripes are incremented by their threads, summed when read.
+#[derive(Default)]
+pub(crate) struct StripedCounter([CachePadded<AtomicU64>; STRIPES]);
+
+impl StripedCounter {
+    pub(crate) fn add(&self, n: u64) {
+        self.0[current_stripe()].fetch_add(n, Ordering::Relaxed);
+    }
+    pub(crate) fn get(&self) -> u64 {
+        self.0.iter().map(|c| c.load(Ordering::Relaxed)).sum()
+    }
+}
+
+/// A value per stripe, each behind its own lock.
+#[derive(Default)]
+pub(crate) struct Striped<T>([CachePadded<Mutex<T>>; STRIPES]);
+
+impl<T> Striped<T> {
+    pub(crate) fn lock(&self, stripe: usize) -> MutexGuard<'_, T> {
+        self.0[stripe].lock().unwrap()
+    }
+    /// Lock the stripes one after the other, not together.
+    pub(crate) fn for_each(&self, mut f: impl FnMut(&mut T)) {
+        for stripe in &self.0 {
+            f(&mut stripe.lock().unwrap());
+        }
+    }
+}
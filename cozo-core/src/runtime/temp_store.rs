@@ -237,6 +237,12 @@ impl TempStore {
             TempStore::MeetAggr(m) => Right(m.range_iter(lower, upper, upper_inclusive)),
         }
     }
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            TempStore::Normal(n) => n.inner.len(),
+            TempStore::MeetAggr(m) => m.inner.len(),
+        }
+    }
     fn is_empty(&self) -> bool {
         match self {
             TempStore::Normal(n) => n.inner.is_empty(),
@@ -285,6 +291,18 @@ impl EpochStore {
         }
         Ok(())
     }
+    /// Number of rows held, not counting the delta of the last epoch
+    pub(crate) fn len(&self) -> usize {
+        self.total.len()
+    }
+    /// Number of rows new in the last epoch
+    pub(crate) fn delta_len(&self) -> usize {
+        if self.use_total_for_delta {
+            self.total.len()
+        } else {
+            self.delta.len()
+        }
+    }
     pub(crate) fn has_delta(&self) -> bool {
         if self.use_total_for_delta {
             !self.total.is_empty()
@@ -23,7 +23,9 @@ use crate::fts::{TokenizerCache, TokenizerConfig};
 use crate::parse::SourceSpan;
 use crate::runtime::callback::CallbackOp;
 use crate::runtime::db::Poison;
-use crate::{DbInstance, FixedRule, RegularTempStore, ScriptMutability};
+use crate::{
+    DbInstance, FixedRule, NamedRows, RegularTempStore, ScriptMutability, TxDurability, TxLogChunk,
+};
 
 #[test]
 fn test_limit_offset() {
@@ -1553,4 +1555,4296 @@ fn fts_drop() {
     db.run_default(r#"
         ::fts drop entity:fts_index
     "#).unwrap();
-}
\ No newline at end of file
+}
+#[test]
+fn expiry_sweep() {
+    let db = DbInstance::default();
+    db.run_default(r"?[k, exp] <- [[1, 0], [2, 1.5], [3, null], [4, 1e20]] :create ttl {k => exp}")
+        .unwrap();
+    db.run_default("::index create ttl:by_exp {exp}").unwrap();
+    assert!(db.run_default("::expiry set ttl nope").is_err());
+    db.run_default("::expiry set ttl exp").unwrap();
+
+    assert_eq!(db.sweep_expired(1).unwrap(), 2);
+    let res = db.run_default("?[k] := *ttl{k}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[3], [4]]));
+    let res = db.run_default("?[k] := *ttl:by_exp{k}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[3], [4]]));
+    assert_eq!(db.sweep_expired(100).unwrap(), 0);
+
+    db.run_default("::expiry drop ttl").unwrap();
+    db.run_default("?[k, exp] <- [[5, 0]] :put ttl {k => exp}")
+        .unwrap();
+    assert_eq!(db.sweep_expired(100).unwrap(), 0);
+
+    db.run_default("::expiry set ttl exp").unwrap();
+    db.start_expiry_sweeper(Duration::from_millis(10), 100);
+    std::thread::sleep(Duration::from_millis(200));
+    assert!(db.stop_expiry_sweeper());
+    assert!(!db.stop_expiry_sweeper());
+    let res = db.run_default("?[k] := *ttl{k}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[3], [4]]));
+}
+
+#[test]
+fn retired_rows_are_hidden() {
+    let db = DbInstance::default();
+    db.run_default(
+        r"?[k, v, gone] <- [[1, 'a', false], [2, 'b', true], [3, 'c', null]]
+        :create doc {k => v, gone: Bool?}",
+    )
+    .unwrap();
+    assert!(db.run_default("::retired set doc v").is_err());
+    db.run_default("::retired set doc gone").unwrap();
+
+    let res = db.run_default("?[k] := *doc{k}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1], [3]]));
+    let res = db.run_default("?[k, v] := *doc[k, v, _]").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, "a"], [3, "c"]]));
+    let res = db
+        .run_default("?[k] := k in [1, 2, 3, 4], not *doc{k}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2], [4]]));
+    let res = db
+        .run_default("?[k, g] := k in [1, 2], g in [true, false], not *doc{k, gone: g}")
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([[1, true], [2, false], [2, true]])
+    );
+    let res = db.run_default("?[k] := *doc{k} :include_retired").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1], [2], [3]]));
+    let res = db
+        .run_default("?[k] := *doc{k} :include_retired false")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1], [3]]));
+
+    // indices read directly hide the same rows
+    db.run_default("::index create doc:by_v {v}").unwrap();
+    db.run_default("::index create doc:by_v_gone {v, gone}").unwrap();
+    let err = db.run_default("?[v, k] := *doc:by_v{v, k}").unwrap_err();
+    assert_eq!(err.code().as_deref(), Some("eval::retired_index_read"));
+    let res = db
+        .run_default("?[v, k] := *doc:by_v{v, k} :include_retired")
+        .unwrap();
+    assert_eq!(res.rows.len(), 3);
+    let res = db.run_default("?[v, k] := *doc:by_v_gone{v, k}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["a", 1], ["c", 3]]));
+    let res = db
+        .run_default("?[k] := k in [1, 2], not *doc:by_v_gone{k}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2]]));
+
+    db.run_default("::retired drop doc").unwrap();
+    let res = db.run_default("?[k] := *doc{k}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1], [2], [3]]));
+}
+
+#[test]
+fn validity_intervals() {
+    let db = DbInstance::default();
+    db.run_default(":create role {name: String, at: Validity => title: String}")
+        .unwrap();
+    db.run_default(
+        r"?[name, at, title] <- [['alice', [10, 20], 'engineer'],
+                                  ['alice', [20, null], 'manager'],
+                                  ['bob', ['2001-01-01T00:00:00Z', '2002-01-01T00:00:00Z'], 'intern']]
+        :put role {name, at => title}",
+    )
+    .unwrap();
+
+    let res = db
+        .run_default("?[name, title] := *role{name, title @ 15}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["alice", "engineer"]]));
+    let res = db
+        .run_default("?[name, title] := *role{name, title @ 25}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["alice", "manager"]]));
+    let res = db
+        .run_default("?[name, title] := *role{name, title @ '2001-06-01T00:00:00Z'}")
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["alice", "manager"], ["bob", "intern"]])
+    );
+    let res = db
+        .run_default("?[name, title] := *role{name, title @ 'NOW'}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["alice", "manager"]]));
+
+    assert!(db
+        .run_default(
+            "?[name, at, title] <- [['carol', [20, 10], 'x']] :put role {name, at => title}"
+        )
+        .is_err());
+    assert!(db
+        .run_default(
+            "?[name, at, title] <- [['carol', [true, 10], 'x']] :put role {name, at => title}"
+        )
+        .is_err());
+    // overlapping intervals would end each other's values
+    for intervals in [
+        "['dave', [10, 30], 'engineer'], ['dave', [20, null], 'manager']",
+        "['dave', [10, 30], 'engineer'], ['dave', [15, 20], 'manager']",
+        "['dave', [10, null], 'engineer'], ['dave', [10, 20], 'manager']",
+    ] {
+        let err = db
+            .run_default(&format!(
+                "?[name, at, title] <- [{intervals}] :put role {{name, at => title}}"
+            ))
+            .unwrap_err();
+        assert_eq!(err.code().as_deref(), Some("eval::overlapping_validity_interval"));
+    }
+    assert!(db
+        .run_default("?[name, title] := *role{name, title @ 25}, name = 'dave'")
+        .unwrap()
+        .rows
+        .is_empty());
+    // also with the intervals already stored
+    let put = |row: &str| {
+        db.run_default(&format!(
+            "?[name, at, title] <- [{row}] :put role {{name, at => title}}"
+        ))
+    };
+    let err = put("['alice', [5, 15], 'x']").unwrap_err();
+    assert_eq!(err.code().as_deref(), Some("eval::overlapping_validity_interval"));
+    put("['alice', [5, 10], 'intern']").unwrap();
+    let res = db
+        .run_default("?[t] := *role{name: 'alice', title: t @ 7}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["intern"]]));
+
+    // the extremes are reserved
+    for interval in ["[0, 9223372036854775807]", "[-9223372036854775807 - 1, 0]"] {
+        let err = db
+            .run_default(&format!(
+                "?[name, at, title] := name = 'carol', at = {interval}, title = 'x' \
+                 :put role {{name, at => title}}"
+            ))
+            .unwrap_err();
+        assert_eq!(err.code().as_deref(), Some("eval::invalid_validity_interval"));
+    }
+}
+
+#[test]
+fn namespaces() {
+    fn run<S: for<'s> crate::Storage<'s>>(
+        db: &crate::Db<S>,
+        script: &str,
+    ) -> miette::Result<crate::NamedRows> {
+        db.run_script(script, Default::default(), ScriptMutability::Mutable)
+    }
+
+    let db = crate::new_cozo_mem().unwrap();
+    run(&db, ":create rel {a => b}").unwrap();
+    run(&db, "?[a, b] <- [[1, 'root']] :put rel {a => b}").unwrap();
+
+    let t1 = db.open_ns("tenant_1").unwrap();
+    let t2 = db.open_ns("tenant_2").unwrap();
+    assert!(run(&t1, "?[a, b] := *rel[a, b]").is_err());
+    run(&t1, ":create rel {a => b}").unwrap();
+    run(&t2, ":create rel {x, y}").unwrap();
+    run(&t1, "?[a, b] <- [[1, 'one'], [2, 'two']] :put rel {a => b}").unwrap();
+    run(&t2, ":create hist {k: Int, at: Validity => v}").unwrap();
+    run(
+        &t2,
+        "?[k, at, v] <- [[1, [10, 20], 'a'], [1, [20, null], 'b']] :put hist {k, at => v}",
+    )
+    .unwrap();
+
+    let res = run(&db, "?[a, b] := *rel[a, b]").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, "root"]]));
+    let res = run(&t1, "?[a, b] := *rel[a, b]").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, "one"], [2, "two"]]));
+    let res = run(&t2, "?[x, y] := *rel[x, y]").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([]));
+    let res = run(&t2, "?[v] := *hist{k: 1, v @ 15}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["a"]]));
+    let res = run(&db, "::relations").unwrap();
+    assert_eq!(res.rows.len(), 1);
+
+    let t1_again = db.open_ns("tenant_1").unwrap();
+    let res = run(&t1_again, "?[a] := *rel[a, _]").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1], [2]]));
+    assert!(db.open_ns("").is_err());
+}
+
+#[test]
+fn namespace_drop_and_copy() {
+    let db = DbInstance::default();
+    db.run_default(":create rel {a => b}").unwrap();
+    let DbInstance::Mem(mem) = &db else {
+        unreachable!()
+    };
+    let t1 = mem.open_ns("tenant_1").unwrap();
+    t1.run_script(
+        r"?[a, b] <- [[1, 'one'], [2, 'two']]
+        :create rel {a => b}",
+        Default::default(),
+        ScriptMutability::Mutable,
+    )
+    .unwrap();
+
+    db.copy_ns("tenant_1", "tenant_2").unwrap();
+    assert!(db.copy_ns("tenant_1", "tenant_2").is_err());
+    let t2 = mem.open_ns("tenant_2").unwrap();
+    let res = t2
+        .run_script(
+            "?[a, b] := *rel[a, b]",
+            Default::default(),
+            ScriptMutability::Immutable,
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, "one"], [2, "two"]]));
+
+    db.drop_ns("tenant_1").unwrap();
+    let t1 = mem.open_ns("tenant_1").unwrap();
+    assert!(t1
+        .run_script(
+            "?[a, b] := *rel[a, b]",
+            Default::default(),
+            ScriptMutability::Immutable
+        )
+        .is_err());
+    let res = t2
+        .run_script(
+            "?[a] := *rel[a, _]",
+            Default::default(),
+            ScriptMutability::Immutable,
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1], [2]]));
+    db.run_default("?[a, b] := *rel[a, b]").unwrap();
+}
+
+#[cfg(feature = "encryption")]
+#[test]
+fn encryption_at_rest() {
+    use crate::{EncryptionKey, MemStorage, Storage, StoreTx};
+
+    let run = |db: &crate::Db<crate::EncryptedStorage<MemStorage>>, script: &str| {
+        db.run_script(script, Default::default(), ScriptMutability::Mutable)
+    };
+    let key_1 = EncryptionKey {
+        id: 1,
+        key: [1; 32],
+    };
+    let key_2 = EncryptionKey {
+        id: 2,
+        key: [2; 32],
+    };
+
+    let plain = crate::new_cozo_mem().unwrap();
+    let raw = plain.db.clone();
+    plain
+        .run_script(
+            "?[a, b] <- [[1, 'existing']] :create rel {a => b}",
+            Default::default(),
+            ScriptMutability::Mutable,
+        )
+        .unwrap();
+    let db = plain.with_encryption(key_1.clone(), vec![]).unwrap();
+    run(&db, "?[a, b] <- [[2, 'secret']] :put rel {a => b}").unwrap();
+    let res = run(&db, "?[a, b] := *rel[a, b]").unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([[1, "existing"], [2, "secret"]])
+    );
+
+    let tx = raw.transact(false).unwrap();
+    for kv in tx.total_scan() {
+        let (_, v) = kv.unwrap();
+        assert!(!v.windows(6).any(|w| w == b"secret"));
+    }
+    drop(tx);
+
+    let wrong = EncryptionKey {
+        id: 1,
+        key: [9; 32],
+    };
+    assert!(crate::Db::new(raw.clone())
+        .unwrap()
+        .with_encryption(wrong, vec![])
+        .is_err());
+    let rotated = crate::Db::new(raw.clone())
+        .unwrap()
+        .with_encryption(key_2.clone(), vec![key_1])
+        .unwrap();
+    assert!(rotated.reencrypt(1).unwrap() > 0);
+    assert_eq!(rotated.reencrypt(1).unwrap(), 0);
+
+    let reopened = crate::Db::new(raw)
+        .unwrap()
+        .with_encryption(key_2, vec![])
+        .unwrap();
+    let res = run(&reopened, "?[a, b] := *rel[a, b]").unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([[1, "existing"], [2, "secret"]])
+    );
+}
+
+#[test]
+fn compaction_progress() {
+    let db = DbInstance::default();
+    db.run_default(":create a {k => v}").unwrap();
+    db.run_default(":create b {k => v}").unwrap();
+    db.run_default("::index create a:by_v {v}").unwrap();
+
+    let mut seen = vec![];
+    db.compact_relations(&["a"], |p| seen.push((p.relation.clone(), p.done, p.total)))
+        .unwrap();
+    assert_eq!(
+        seen,
+        vec![("a".to_string(), 1, 2), ("a:by_v".to_string(), 2, 2)]
+    );
+    let mut count = 0;
+    db.compact_relations(&[], |_| count += 1).unwrap();
+    assert_eq!(count, 3);
+    assert!(db.compact_relations(&["c"], |_| {}).is_err());
+
+    db.start_compaction_schedule(Duration::from_millis(10));
+    std::thread::sleep(Duration::from_millis(30));
+    assert!(db.stop_compaction_schedule());
+    assert!(!db.stop_compaction_schedule());
+}
+
+#[test]
+fn storage_stats() {
+    let db = DbInstance::default();
+    db.run_default("?[k, v] <- [[1, 'a'], [2, 'b'], [3, 'c']] :create a {k => v}")
+        .unwrap();
+    db.run_default("::index create a:by_v {v}").unwrap();
+    db.run_default(":create b {k}").unwrap();
+
+    let stats = db.storage_stats().unwrap();
+    let rows = stats
+        .relations
+        .iter()
+        .map(|r| (r.name.as_str(), r.rows))
+        .collect_vec();
+    assert_eq!(rows, vec![("a", 3), ("a:by_v", 3), ("b", 0)]);
+    assert!(stats.total_keys >= 6);
+    assert_eq!(stats.approximate_size, None);
+    assert!(stats.engine.is_empty());
+}
+
+#[test]
+fn verify_integrity() {
+    use crate::data::tuple::TupleT;
+    use crate::runtime::relation::RelationId;
+    use crate::storage::{Storage, StoreTx};
+    use crate::IntegrityFinding;
+
+    let db = crate::new_cozo_mem().unwrap();
+    db.run_script(
+        "?[k, v] <- [[1, 'a'], [2, 'b'], [3, 'c']] :create a {k => v}",
+        Default::default(),
+        ScriptMutability::Mutable,
+    )
+    .unwrap();
+    db.run_script(
+        "::index create a:by_v {v}",
+        Default::default(),
+        ScriptMutability::Mutable,
+    )
+    .unwrap();
+    let report = db.verify_integrity().unwrap();
+    assert!(report.is_ok(), "{:?}", report.findings);
+    assert_eq!(report.relations_checked, 2);
+    assert_eq!(report.rows_checked, 6);
+    db.run_script(
+        "::index create a:by_v_k {v, k}",
+        Default::default(),
+        ScriptMutability::Mutable,
+    )
+    .unwrap();
+    let report = db.verify_integrity().unwrap();
+    assert_eq!(report.relations_checked, 3);
+    assert_eq!(report.rows_checked, 9);
+    db.run_script(
+        "::index drop a:by_v_k",
+        Default::default(),
+        ScriptMutability::Mutable,
+    )
+    .unwrap();
+
+    let idx = db
+        .transact()
+        .unwrap()
+        .get_relation("a:by_v", false)
+        .unwrap();
+    let removed = idx
+        .encode_key_for_store(
+            &[DataValue::from("b"), DataValue::from(2)],
+            Default::default(),
+        )
+        .unwrap();
+    let added = idx
+        .encode_key_for_store(
+            &[DataValue::from("z"), DataValue::from(9)],
+            Default::default(),
+        )
+        .unwrap();
+    let mut tx = db.db.transact(true).unwrap();
+    tx.del(&removed).unwrap();
+    tx.put(&added, &[]).unwrap();
+    tx.put(
+        &vec![DataValue::from(1)].encode_as_key(RelationId::new(1000)),
+        &[],
+    )
+    .unwrap();
+    tx.commit().unwrap();
+    drop(tx);
+
+    let report = db.verify_integrity().unwrap();
+    assert_eq!(
+        report.findings,
+        vec![
+            IntegrityFinding::OrphanedKeys {
+                first_id: idx.id.0 + 1,
+                last_id: u64::MAX,
+                count: 1
+            },
+            IntegrityFinding::MissingIndexEntry {
+                relation: "a".to_string(),
+                index: "by_v".to_string(),
+                key: vec![DataValue::from(2)]
+            },
+            IntegrityFinding::DanglingIndexEntry {
+                relation: "a".to_string(),
+                index: "by_v".to_string(),
+                entry: vec![DataValue::from("z"), DataValue::from(9)]
+            },
+        ]
+    );
+}
+
+#[test]
+fn repair() {
+    use crate::data::tuple::{Tuple, TupleT};
+    use crate::runtime::relation::RelationId;
+    use crate::storage::{Storage, StoreTx};
+    use crate::{RepairMode, RepairReport};
+
+    let db = crate::new_cozo_mem().unwrap();
+    let run = |script: &str| {
+        db.run_script(script, Default::default(), ScriptMutability::Mutable)
+            .unwrap()
+    };
+    run("?[k, v] <- [[1, 'hello world'], [2, 'goodbye world']] :create a {k => v}");
+    run("::index create a:by_v {v}");
+    run("::fts create a:fts {extractor: v, tokenizer: Simple}");
+
+    let (idx, fts) = {
+        let tx = db.transact().unwrap();
+        (
+            tx.get_relation("a:by_v", false).unwrap(),
+            tx.get_relation("a:fts", false).unwrap(),
+        )
+    };
+    let mut tx = db.db.transact(true).unwrap();
+    for handle in [&idx, &fts] {
+        let keys = tx
+            .range_scan(
+                &Tuple::default().encode_as_key(handle.id),
+                &Tuple::default().encode_as_key(handle.id.next()),
+            )
+            .map(|kv| kv.unwrap().0)
+            .collect_vec();
+        for key in keys {
+            tx.del(&key).unwrap();
+        }
+    }
+    tx.put(
+        &vec![DataValue::from(1)].encode_as_key(RelationId::new(1000)),
+        &[],
+    )
+    .unwrap();
+    tx.commit().unwrap();
+    drop(tx);
+
+    let report = db.repair(RepairMode::Findings).unwrap();
+    assert_eq!(
+        report,
+        RepairReport {
+            rebuilt_indices: vec!["a:by_v".to_string()],
+            removed_keys: 1,
+            new_id_counter: None,
+        }
+    );
+    assert!(db.verify_integrity().unwrap().is_ok());
+    let search = "?[k] := ~a:fts{k | query: 'hello', k: 2}";
+    assert!(run(search).rows.is_empty());
+
+    let report = db.repair(RepairMode::RebuildAll).unwrap();
+    assert_eq!(report.rebuilt_indices, vec!["a:by_v", "a:fts"]);
+    assert_eq!(run(search).rows, vec![vec![DataValue::from(1)]]);
+    assert_eq!(run("?[v, k] := *a:by_v{v, k}").rows.len(), 2);
+}
+
+#[test]
+fn close_waits_for_sessions() {
+    let db = crate::new_cozo_mem().unwrap();
+    db.run_script(
+        "?[k] <- [[1]] :create a {k}",
+        Default::default(),
+        ScriptMutability::Mutable,
+    )
+    .unwrap();
+
+    let tx = db.transact().unwrap();
+    assert!(db.close(Duration::from_millis(10)).is_err());
+    // no new transactions after the first attempt, even though it failed
+    assert!(db
+        .run_script(
+            "?[k] := *a{k}",
+            Default::default(),
+            ScriptMutability::Immutable
+        )
+        .is_err());
+    drop(tx);
+    db.close(Duration::from_millis(10)).unwrap();
+    db.close(Duration::from_millis(10)).unwrap();
+    assert!(db.transact_write().is_err());
+}
+
+#[test]
+fn close_waits_for_namespace_sessions() {
+    let db = crate::new_cozo_mem().unwrap();
+    let ns = db.open_ns("tenant").unwrap();
+    ns.run_script(
+        "?[k] <- [[1]] :create a {k}",
+        Default::default(),
+        ScriptMutability::Mutable,
+    )
+    .unwrap();
+
+    let tx = ns.transact().unwrap();
+    let err = db.close(Duration::from_millis(10)).unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "db::close_timeout");
+    assert!(ns.transact().is_err());
+    assert!(db.open_ns("other").is_err());
+    drop(tx);
+    db.close(Duration::from_millis(10)).unwrap();
+}
+
+#[test]
+fn session_registry() {
+    let db = DbInstance::default();
+    db.run_default(":create a {k}").unwrap();
+    assert!(db.list_sessions().is_empty());
+
+    let tx = db.multi_transaction(true);
+    tx.run_script("?[k] <- [[1]] :put a {k}", Default::default())
+        .unwrap();
+    let sessions = db.list_sessions();
+    assert_eq!(sessions.len(), 1);
+    assert!(sessions[0].write);
+    assert_eq!(sessions[0].query, None);
+
+    assert!(db.kill_session(sessions[0].id));
+    assert!(!db.kill_session(sessions[0].id + 1));
+    assert!(tx.run_script("?[k] := *a{k}", Default::default()).is_err());
+    assert!(tx.commit().is_err());
+    assert!(db.run_default("?[k] := *a{k}").unwrap().rows.is_empty());
+}
+
+#[test]
+#[cfg(feature = "storage-sled")]
+fn lock_file() {
+    let dir = std::env::temp_dir().join(format!("cozo_lock_file_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let db = crate::new_cozo_sled(&dir).unwrap();
+    let err = crate::new_cozo_sled(&dir).unwrap_err();
+    let already_open = err.downcast_ref::<crate::AlreadyOpen>().unwrap();
+    assert_eq!(already_open.pid, std::process::id());
+    db.close(Duration::from_secs(1)).unwrap();
+    // released by the close, before the database object is dropped, and the handle is
+    // gone with the lock, so the directory can be opened again at once
+    let reopened = crate::new_cozo_sled(&dir).unwrap();
+    drop(db);
+    drop(reopened);
+    // a lock file left behind by a process that no longer holds the lock
+    std::fs::write(
+        dir.join("cozo.lock"),
+        r#"{"pid": 4294967295, "since": 0.0}"#,
+    )
+    .unwrap();
+    let db = crate::new_cozo_sled(&dir).unwrap();
+    let err = crate::new_cozo_sled(&dir).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<crate::AlreadyOpen>().unwrap().pid,
+        std::process::id()
+    );
+    drop(db);
+    assert!(crate::force_unlock(&dir).unwrap());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn error_kinds() {
+    use crate::CozoError;
+
+    let db = DbInstance::new("mem", "", "").unwrap();
+    db.run_default(":create a {k: Int => v: String}").unwrap();
+    db.run_default("?[k, v] <- [[1, 'x']] :put a {k => v}")
+        .unwrap();
+
+    match db.run_default("?[k] := *a{k}, k >").unwrap_err() {
+        CozoError::Parse { span, .. } => assert!(span.is_some()),
+        err => panic!("expected a parse error, got {err:?}"),
+    }
+    let err = db
+        .run_default("?[k, v] <- [[1, 'y']] :insert a {k => v}")
+        .unwrap_err();
+    assert!(matches!(err, CozoError::ConstraintViolation(_)));
+    assert_eq!(err.code().as_deref(), Some("transact::assertion_failure"));
+    let err = db
+        .run_default("?[k, v] <- [[2, 3]] :put a {k => v}")
+        .unwrap_err();
+    assert!(matches!(err, CozoError::ConstraintViolation(_)));
+    let err = db
+        .run_script(
+            "?[k, v] <- [[2, 'y']] :put a {k => v}",
+            Default::default(),
+            ScriptMutability::Immutable,
+        )
+        .unwrap_err();
+    assert!(matches!(err, CozoError::ReadOnly(_)));
+    let err = db
+        .run_default(
+            r#"
+            r[x] := x = 0
+            r[y] := r[x], y = x + 1
+            ?[x] := r[x]
+            :timeout 0.1
+            "#,
+        )
+        .unwrap_err();
+    assert!(matches!(err, CozoError::Timeout(_)));
+
+    // converts back into the report for code using miette
+    let report: miette::Report = db.run_default("?[k] := *nope{k}").unwrap_err().into();
+    assert!(report.to_string().contains("nope"));
+}
+
+#[test]
+fn parse_error_location() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    let err = db
+        .run_default("?[a] := a in [1, 2]\n?[b] := b = ,")
+        .unwrap_err();
+    let loc = err.location().unwrap();
+    assert_eq!((loc.offset, loc.line, loc.column), (32, 2, 13));
+    assert_eq!(loc.snippet, "?[b] := b = ,");
+    assert!(err.to_string().contains("line 2, column 13"));
+    // the report carries the script, so it renders with the offending line
+    assert!(format!("{err:?}").contains("?[b] := b = ,"));
+
+    db.run_default(":create a {k: Int => value: String}")
+        .unwrap();
+    let err = db
+        .run_default("?[v] := v = 'é', *a{k, valeu: v}")
+        .unwrap_err();
+    let loc = err.location().unwrap();
+    // columns count characters, not bytes
+    assert_eq!((loc.offset, loc.line, loc.column), (18, 1, 18));
+    assert_eq!(
+        err.report().help().unwrap().to_string(),
+        "Did you mean `value`?"
+    );
+    let err = db.run_default("?[v] := *a{k, other: v}").unwrap_err();
+    assert!(err.report().help().is_none());
+}
+
+#[test]
+#[cfg(feature = "tracing")]
+fn tracing_spans() {
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    struct Fields<'a>(&'a mut Vec<String>);
+    impl Visit for Fields<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0.push(format!("{}={:?}", field.name(), value));
+        }
+    }
+    #[derive(Default)]
+    struct Collector {
+        // span names and the fields recorded for them, by span id
+        spans: Arc<Mutex<Vec<(&'static str, Vec<String>)>>>,
+    }
+    impl Subscriber for Collector {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut fields = vec![];
+            span.record(&mut Fields(&mut fields));
+            let mut spans = self.spans.lock().unwrap();
+            spans.push((span.metadata().name(), fields));
+            Id::from_u64(spans.len() as u64)
+        }
+        fn record(&self, span: &Id, values: &Record<'_>) {
+            let mut spans = self.spans.lock().unwrap();
+            values.record(&mut Fields(&mut spans[span.into_u64() as usize - 1].1));
+        }
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, _: &Event<'_>) {}
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    let db = DbInstance::new("mem", "", "").unwrap();
+    let collector = Collector::default();
+    let spans = collector.spans.clone();
+    tracing::subscriber::with_default(collector, || {
+        db.run_default("?[x] := x in [3, 1, 2] :order x").unwrap();
+    });
+    let spans = spans.lock().unwrap();
+    let names = spans.iter().map(|(name, _)| *name).collect_vec();
+    for name in [
+        "transact", "query", "plan", "evaluate", "stratum", "epoch", "sort",
+    ] {
+        assert!(names.contains(&name), "no {name} span in {names:?}");
+    }
+    let (_, query_fields) = spans.iter().find(|(name, _)| *name == "query").unwrap();
+    assert!(query_fields.iter().any(|f| f.starts_with("tx=")));
+    assert!(query_fields.contains(&"rows=3".to_string()));
+}
+
+#[test]
+fn metrics() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    db.run_default(":create m {k: Int => v: Int}").unwrap();
+    let before = db.metrics();
+    db.run_default("?[k, v] <- [[1, 1], [2, 2], [3, 3]] :put m {k => v}")
+        .unwrap();
+    db.run_default("?[k] <- [[1]] :rm m {k}").unwrap();
+    assert!(db.run_default("?[x] := y = 1").is_err());
+    {
+        let tx = db.multi_transaction(true);
+        tx.run_script("?[k, v] <- [[4, 4]] :put m {k => v}", Default::default())
+            .unwrap();
+        tx.abort().unwrap();
+    }
+    let after = db.metrics();
+    assert_eq!(after.rows_written - before.rows_written, 4);
+    assert_eq!(after.queries_failed - before.queries_failed, 1);
+    assert_eq!(after.query_latency.count - before.query_latency.count, 4);
+    assert_eq!(
+        after.query_latency.buckets.iter().sum::<u64>(),
+        after.query_latency.count
+    );
+    assert!(after.transactions_committed - before.transactions_committed >= 2);
+    assert!(after.transactions_aborted - before.transactions_aborted >= 1);
+    let text = after.to_prometheus();
+    assert!(text.contains(&format!("cozo_rows_written_total {}", after.rows_written)));
+    assert!(text.contains(&format!(
+        "cozo_query_duration_seconds_count {}",
+        after.query_latency.count
+    )));
+}
+
+#[test]
+fn slow_query_log() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    let db = DbInstance::new("mem", "", "").unwrap();
+    let seen = Arc::new(AtomicUsize::new(0));
+    let seen_in_callback = seen.clone();
+    db.set_slow_query_log(
+        Duration::ZERO,
+        2,
+        Some(Box::new(move |_| {
+            seen_in_callback.fetch_add(1, Ordering::Relaxed);
+        })),
+    );
+    db.run_default("?[x] <- [[1]]").unwrap();
+    db.run_script(
+        "# find them\n?[x]   :=  x in [1, 2, 3], x > $min, /* a comment */ y = 'a  # b'",
+        BTreeMap::from([("min".to_string(), DataValue::from(1))]),
+        ScriptMutability::Immutable,
+    )
+    .unwrap();
+    assert!(db.run_default("?[x] := y = 1").is_err());
+    assert_eq!(seen.load(Ordering::Relaxed), 3);
+
+    let logged = db.slow_queries();
+    assert_eq!(logged.len(), 2);
+    assert_eq!(
+        logged[0].script,
+        "?[x] := x in [1, 2, 3], x > $min, y = 'a  # b'"
+    );
+    assert_eq!(logged[0].params["min"], DataValue::from(1));
+    assert_eq!(logged[0].rows, Some(2));
+    assert!(logged[0].duration >= 0.);
+    assert_eq!(logged[1].rows, None);
+
+    // the script reading the log is only recorded once it has returned
+    let res = db.run_default("::slow_queries").unwrap();
+    assert_eq!(
+        res.headers,
+        ["started_at", "duration", "rows", "script", "params"]
+    );
+    assert_eq!(res.rows.len(), 2);
+    assert_eq!(res.rows[1][2], DataValue::Null);
+
+    assert!(db.disable_slow_query_log());
+    db.run_default("?[x] <- [[1]]").unwrap();
+    assert_eq!(seen.load(Ordering::Relaxed), 4);
+    assert!(!db.disable_slow_query_log());
+}
+
+#[test]
+fn audit_log() {
+    use crate::{AuditEvent, AuditRetention, Principal};
+
+    let db = DbInstance::new("mem", "", "").unwrap();
+    db.run_default(":create a {x: Int}").unwrap();
+    db.enable_audit_log(AuditRetention::default());
+    db.run_script_as(
+        &Principal::new("alice"),
+        "?[x] <- [[1]] :put a {x}",
+        Default::default(),
+        ScriptMutability::Mutable,
+    )
+    .unwrap();
+    assert!(db
+        .run_script_as(
+            &Principal::new("bob"),
+            "?[x] := y = 1",
+            Default::default(),
+            ScriptMutability::Mutable
+        )
+        .is_err());
+    {
+        let tx = db.multi_transaction_as(&Principal::new("carol"), true);
+        tx.run_script("?[x] <- [[2]] :put a {x}", Default::default())
+            .unwrap();
+        tx.commit().unwrap();
+    }
+    // stored relations and the storage statistics do not see the log
+    db.run_default("::relations").unwrap();
+    assert!(db.verify_integrity().unwrap().findings.is_empty());
+
+    let entries = db.audit_log().unwrap();
+    let events = entries
+        .iter()
+        .map(|e| (e.principal.as_deref(), e.event.clone()))
+        .collect_vec();
+    assert_eq!(
+        events[..7],
+        [
+            (
+                Some("alice"),
+                AuditEvent::Query("?[x] <- [[1]] :put a {x}".to_string())
+            ),
+            (Some("alice"), AuditEvent::Commit),
+            (Some("bob"), AuditEvent::Query("?[x] := y = 1".to_string())),
+            (Some("bob"), AuditEvent::Abort),
+            (
+                Some("carol"),
+                AuditEvent::Query("?[x] <- [[2]] :put a {x}".to_string())
+            ),
+            (Some("carol"), AuditEvent::Commit),
+            (None, AuditEvent::Query("::relations".to_string())),
+        ]
+    );
+    assert_eq!(entries[0].session_id, entries[1].session_id);
+    assert_ne!(entries[0].session_id, entries[2].session_id);
+
+    db.enable_audit_log(AuditRetention {
+        max_age: None,
+        max_entries: Some(3),
+    });
+    db.run_default("?[x] <- [[1]]").unwrap();
+    let entries = db.audit_log().unwrap();
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[2].event, AuditEvent::Commit);
+    assert!(db.disable_audit_log());
+    db.run_default("?[x] <- [[1]]").unwrap();
+    assert_eq!(db.audit_log().unwrap().len(), 3);
+    assert!(!db.disable_audit_log());
+}
+
+#[test]
+fn column_grants() {
+    use crate::{CozoError, Principal};
+
+    let db = DbInstance::new("mem", "", "").unwrap();
+    db.run_default(":create r {k: Int => name: String, salary: Int}")
+        .unwrap();
+    db.run_default(":create open {k: Int}").unwrap();
+    db.run_default("?[k, name, salary] <- [[1, 'a', 100]] :put r {k => name, salary}")
+        .unwrap();
+    db.run_default("::grant read r {k, name} to analyst")
+        .unwrap();
+    db.run_default("::grant write r {k, name} to analyst")
+        .unwrap();
+    let grants = db.run_default("::grants r").unwrap();
+    assert_eq!(grants.rows.len(), 4);
+
+    let analyst = Principal::new("ann").with_role("analyst");
+    let run = |script: &str| {
+        db.run_script_as(
+            &analyst,
+            script,
+            Default::default(),
+            ScriptMutability::Mutable,
+        )
+    };
+    let res = run("?[k, name] := *r{k, name}").unwrap();
+    assert_eq!(
+        res.rows,
+        vec![vec![DataValue::from(1), DataValue::from("a")]]
+    );
+    let err = run("?[k, salary] := *r{k, salary}").unwrap_err();
+    assert!(matches!(err, CozoError::PermissionDenied(_)));
+    assert_eq!(err.code().as_deref(), Some("tx::column_not_granted"));
+    // the columns of an index are granted by its relation
+    db.run_default("::index create r:by_salary {salary}")
+        .unwrap();
+    let err = run("?[k, salary] := *r:by_salary{salary, k}").unwrap_err();
+    assert_eq!(err.code().as_deref(), Some("tx::column_not_granted"));
+    db.run_default("::index create r:by_name {name}").unwrap();
+    assert_eq!(run("?[k] := *r:by_name{name: 'a', k}").unwrap().rows.len(), 1);
+    assert_eq!(run("::columns r:by_salary").unwrap().rows.len(), 1);
+    // a row is replaced as a whole, so a put needs every column
+    assert!(run("?[k, name, salary] <- [[2, 'b', 1]] :put r {k => name, salary}").is_err());
+    run("?[k, name] <- [[1, 'b']] :update r {k => name}").unwrap();
+    assert!(run("?[k, salary] <- [[1, 1]] :update r {k => salary}").is_err());
+    let cols = run("::columns r").unwrap();
+    assert_eq!(cols.rows.len(), 2);
+    assert!(matches!(
+        run("::grant read r {salary} to analyst").unwrap_err(),
+        CozoError::PermissionDenied(_)
+    ));
+    assert!(run("::remove r").is_err());
+    // relations without grants are open to every role
+    run("?[k] <- [[1]] :put open {k}").unwrap();
+    assert!(run("?[other] := *r{k}, other = k").is_ok());
+    // sessions without a role see everything
+    let res = db.run_default("?[salary] := *r{salary}").unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(100)]]);
+
+    db.run_default("::revoke read r {name} from analyst")
+        .unwrap();
+    assert!(run("?[name] := *r{name}").is_err());
+    db.run_default("::revoke read r {k} from analyst").unwrap();
+    db.run_default("::revoke write r {k, name} from analyst")
+        .unwrap();
+    assert!(db.run_default("::grants r").unwrap().rows.is_empty());
+    assert!(run("?[salary] := *r{salary}").is_ok());
+}
+
+#[test]
+fn row_policy() {
+    use crate::{CozoError, Principal};
+
+    let db = DbInstance::new("mem", "", "").unwrap();
+    db.run_default(":create docs {id: Int => owner: String, body: String}")
+        .unwrap();
+    db.run_default(
+        "?[id, owner, body] <- [[1, 'alice', 'a'], [2, 'bob', 'b']] :put docs {id => owner, body}",
+    )
+    .unwrap();
+    db.run_default("::index create docs:by_owner {owner}")
+        .unwrap();
+    assert!(db.run_default("::policy set docs nope == 1").is_err());
+    db.run_default("::policy set docs owner == $current_user")
+        .unwrap();
+    assert_eq!(
+        db.run_default("::policy show docs").unwrap().rows,
+        vec![vec![DataValue::from("owner == $current_user")]]
+    );
+
+    let alice = Principal::new("alice").with_role("user");
+    let run = |script: &str| {
+        db.run_script_as(
+            &alice,
+            script,
+            Default::default(),
+            ScriptMutability::Mutable,
+        )
+    };
+    let ids = |script: &str| {
+        run(script)
+            .unwrap()
+            .rows
+            .into_iter()
+            .map(|row| row[0].get_int().unwrap())
+            .collect_vec()
+    };
+    assert_eq!(ids("?[id] := *docs{id}"), [1]);
+    assert_eq!(ids("?[id] := id in [1, 2, 3], not *docs{id}"), [2, 3]);
+    // the index of the relation does not skip the policy
+    assert!(ids("?[id] := *docs{id, owner: 'bob'}").is_empty());
+    // nor does reading the index directly, or through a fixed rule
+    assert!(ids("?[id] := *docs:by_owner{owner: 'bob', id}").is_empty());
+    assert_eq!(ids("?[id] := *docs:by_owner{id}"), [1]);
+    assert_eq!(
+        run("?[rank, id] <~ ReorderSort(*docs:by_owner[owner, id], out: [id], take: 10)")
+            .unwrap()
+            .rows,
+        vec![vec![DataValue::from(1), DataValue::from(1)]]
+    );
+    db.run_default("::policy set docs body != 'secret'").unwrap();
+    let err = run("?[id] := *docs:by_owner{id}").unwrap_err();
+    assert!(matches!(err, CozoError::PermissionDenied(_)));
+    assert_eq!(err.code().as_deref(), Some("tx::row_policy_not_on_index"));
+    db.run_default("::policy set docs owner == $current_user")
+        .unwrap();
+
+    run("?[id, owner, body] <- [[3, 'alice', 'c']] :put docs {id => owner, body}").unwrap();
+    let err =
+        run("?[id, owner, body] <- [[4, 'bob', 'd']] :put docs {id => owner, body}").unwrap_err();
+    assert!(matches!(err, CozoError::PermissionDenied(_)));
+    assert_eq!(err.code().as_deref(), Some("tx::row_policy_violation"));
+    assert!(run("?[id, body] <- [[2, 'x']] :update docs {id => body}").is_err());
+    assert!(run("?[id] <- [[2]] :rm docs {id}").is_err());
+    // rows outside of the policy are not there for the session
+    run("?[id, owner, body] <- [[2, 'bob', 'b']] :ensure_not docs {id => owner, body}").unwrap();
+    assert!(run("::policy drop docs").is_err());
+
+    // sessions without a role are not restricted
+    let all = db.run_default("?[id] := *docs{id}").unwrap();
+    assert_eq!(all.rows.len(), 3);
+    db.run_default("::policy drop docs").unwrap();
+    assert_eq!(ids("?[id] := *docs{id}"), [1, 2, 3]);
+}
+
+#[test]
+fn quotas() {
+    use crate::{CozoError, Principal, Quota};
+
+    let db = DbInstance::new("mem", "", "").unwrap();
+    db.run_default(":create t {a: Int}").unwrap();
+    let tenant = Principal::new("tenant");
+    let run = |script: &str| {
+        db.run_script_as(
+            &tenant,
+            script,
+            Default::default(),
+            ScriptMutability::Mutable,
+        )
+    };
+    db.set_quota(
+        "tenant",
+        Quota {
+            max_rows: Some(3),
+            max_memory: Some(10_000),
+            max_writes_per_second: Some(1.),
+            ..Default::default()
+        },
+    );
+    assert_eq!(db.quota("tenant").unwrap().max_rows, Some(3));
+
+    assert_eq!(run("?[a] := a in [1, 2, 3]").unwrap().rows.len(), 3);
+    let err = run("?[a] := a in [1, 2, 3, 4]").unwrap_err();
+    assert!(matches!(err, CozoError::QuotaExceeded(_)));
+    assert_eq!(err.code().as_deref(), Some("db::quota_exceeded"));
+    // the rows held while evaluating count even if few are returned
+    let err = run("r[a] := a in int_range(10000) ?[count(a)] := r[a]").unwrap_err();
+    assert!(matches!(err, CozoError::QuotaExceeded(_)));
+
+    // a write may go over the allowance, the following ones wait for it to refill
+    run("?[a] <- [[1], [2], [3]] :put t {a}").unwrap();
+    let err = run("?[a] <- [[4]] :put t {a}").unwrap_err();
+    assert!(matches!(err, CozoError::QuotaExceeded(_)));
+    run("?[a] := *t{a}").unwrap();
+
+    db.set_quota(
+        "tenant",
+        Quota {
+            max_concurrent_queries: Some(0),
+            ..Default::default()
+        },
+    );
+    assert!(run("?[a] := a = 1").is_err());
+    // other principals and sessions without one are not limited
+    db.run_script_as(
+        &Principal::new("other"),
+        "?[a] := a in [1, 2, 3, 4]",
+        Default::default(),
+        ScriptMutability::Immutable,
+    )
+    .unwrap();
+    db.run_default("?[a] <- [[4]] :put t {a}").unwrap();
+    assert!(db.remove_quota("tenant"));
+    assert!(!db.remove_quota("tenant"));
+    run("?[a] := a in [1, 2, 3, 4]").unwrap();
+}
+
+#[cfg(feature = "async")]
+fn block_on<F: std::future::Future>(fut: F) -> F::Output {
+    use std::pin::pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake};
+    use std::thread::{self, Thread};
+
+    struct Unpark(Thread);
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark()
+        }
+    }
+    let mut fut = pin!(fut);
+    let waker = Arc::new(Unpark(thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(res) = fut.as_mut().poll(&mut cx) {
+            return res;
+        }
+        thread::park();
+    }
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn async_api() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    block_on(db.run_default_async(":create a {x: Int}")).unwrap();
+    let tx = db.transact_async(true);
+    block_on(tx.run_script("?[x] <- [[1], [2]] :put a {x}", Default::default())).unwrap();
+    block_on(tx.commit()).unwrap();
+    let res = block_on(db.run_script_async(
+        "?[count(x)] := *a{x}",
+        Default::default(),
+        ScriptMutability::Immutable,
+    ))
+    .unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(2)]]);
+    let err = block_on(db.run_default_async("?[x] := *nope{x}")).unwrap_err();
+    assert!(err.code().is_some());
+
+    let exported = block_on(db.export_relations_async(["a"].iter())).unwrap();
+    assert_eq!(exported["a"].rows.len(), 2);
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn write_queue() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    db.run_default(":create a {x: Int}").unwrap();
+    let err = block_on(db.submit_write("?[x] <- [[0]] :put a {x}", Default::default()));
+    assert_eq!(
+        err.unwrap_err().code().as_deref(),
+        Some("db::write_queue_not_started")
+    );
+
+    db.start_write_queue(2, 1);
+    let futs = (1..=10)
+        .map(|i| {
+            db.submit_write(
+                "?[x] <- [[$x]] :put a {x}",
+                BTreeMap::from([("x".to_string(), DataValue::from(i))]),
+            )
+        })
+        .collect_vec();
+    for fut in futs {
+        let report = block_on(fut).unwrap();
+        assert!(report.queued >= 0.);
+    }
+    let count = db.run_default("?[count(x)] := *a{x}").unwrap();
+    assert_eq!(count.rows, vec![vec![DataValue::from(10)]]);
+
+    // a failed write reports its error to the submitter only
+    let err = block_on(db.submit_write("?[x] <- [['a']] :put a {x}", Default::default()));
+    assert!(err.is_err());
+    block_on(
+        db.try_submit_write("?[x] <- [[11]] :put a {x}", Default::default())
+            .unwrap(),
+    )
+    .unwrap();
+
+    assert!(db.set_write_weight("bulk", 4));
+    let futs = (20..30)
+        .map(|i| {
+            let tenant = if i % 2 == 0 { "bulk" } else { "user" };
+            db.submit_write_for(
+                tenant,
+                "?[x] <- [[$x]] :put a {x}",
+                BTreeMap::from([("x".to_string(), DataValue::from(i))]),
+            )
+        })
+        .collect_vec();
+    for fut in futs {
+        block_on(fut).unwrap();
+    }
+    let count = db.run_default("?[count(x)] := *a{x}").unwrap();
+    assert_eq!(count.rows, vec![vec![DataValue::from(21)]]);
+
+    assert!(db.stop_write_queue());
+    assert!(!db.stop_write_queue());
+    assert!(!db.set_write_weight("bulk", 4));
+    assert!(db
+        .try_submit_write("?[x] <- [[12]] :put a {x}", Default::default())
+        .is_err());
+}
+
+#[test]
+fn per_transaction_durability() {
+    let db = DbInstance::default();
+    db.run_default(":create a {x}").unwrap();
+    for (i, durability) in [
+        TxDurability::Default,
+        TxDurability::NoSync,
+        TxDurability::Async,
+        TxDurability::Sync,
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        db.run_script_with_durability(
+            "?[x] <- [[$x]] :put a {x}",
+            BTreeMap::from([("x".to_string(), DataValue::from(i as i64))]),
+            durability,
+        )
+        .unwrap();
+    }
+    let tx = db.multi_transaction_with_durability(TxDurability::NoSync);
+    tx.run_script("?[x] <- [[10]] :put a {x}", Default::default())
+        .unwrap();
+    tx.commit().unwrap();
+    let count = db.run_default("?[count(x)] := *a{x}").unwrap();
+    assert_eq!(count.rows, vec![vec![DataValue::from(5)]]);
+}
+
+#[test]
+fn snapshot_export() {
+    let db = DbInstance::default();
+    db.run_default(":create a {x => y}").unwrap();
+    db.run_default(":create h {x}").unwrap();
+    db.run_default("::index create a:y {y}").unwrap();
+    db.run_default("?[x, y] := x in int_range(2500), y = x * 2 :put a {x => y}")
+        .unwrap();
+    db.run_default("::access_level hidden h").unwrap();
+
+    let mut out = vec![];
+    let report = db.snapshot_export(&mut out).unwrap();
+    assert_eq!(report.relations, vec!["a".to_string()]);
+    assert_eq!(report.skipped, vec!["h".to_string()]);
+    assert_eq!(report.rows, 2500);
+    let lines = String::from_utf8(out).unwrap();
+    let lines = lines.lines().collect_vec();
+    assert_eq!(lines.len(), 4);
+    let header: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+    assert_eq!(header["cozo_dump"], json!(crate::DUMP_VERSION));
+
+    let restored = DbInstance::default();
+    restored.run_default(":create a {x => y}").unwrap();
+    restored.run_default("::index create a:y {y}").unwrap();
+    for line in &lines[1..] {
+        let line: serde_json::Value = serde_json::from_str(line).unwrap();
+        let relation = line["relation"].as_str().unwrap().to_string();
+        let rows = NamedRows::from_json(&line).unwrap();
+        restored
+            .import_relations(BTreeMap::from([(relation, rows)]))
+            .unwrap();
+    }
+    let res = restored
+        .run_default("?[count(x), sum(y)] := *a:y{x, y}")
+        .unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(2500));
+}
+
+#[test]
+#[cfg(feature = "storage-sqlite")]
+fn restore_to_timestamp() {
+    let backup = std::env::temp_dir().join(format!("cozo_pitr_{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&backup);
+
+    let db = DbInstance::default();
+    db.enable_tx_log().unwrap();
+    db.run_default(":create a {x}").unwrap();
+    db.run_default("?[x] <- [[1]] :put a {x}").unwrap();
+    db.backup_db(&backup).unwrap();
+    for x in 2..=4 {
+        std::thread::sleep(Duration::from_millis(10));
+        db.run_default(&format!("?[x] <- [[{x}]] :put a {{x}}"))
+            .unwrap();
+    }
+    db.run_default("?[x] <- [[1]] :rm a {x}").unwrap();
+    // read-only scripts are not logged
+    db.run_default("?[x] := *a{x}").unwrap();
+
+    let first = db.tx_log_chunk(0, 3).unwrap();
+    let rest = db.tx_log_chunk(first.last_seq().unwrap(), 100).unwrap();
+    assert_eq!(first.entries.len() + rest.entries.len(), 6);
+    let archived = [
+        TxLogChunk::from_bytes(&first.to_bytes().unwrap()).unwrap(),
+        rest.clone(),
+    ];
+    // the entry adding 3
+    let ts = rest.entries[0].timestamp;
+
+    let restored = DbInstance::default();
+    assert_eq!(
+        restored
+            .restore_to_timestamp(&backup, &archived, ts)
+            .unwrap(),
+        2
+    );
+    let res = restored.run_default("?[x] := *a{x}").unwrap();
+    assert_eq!(
+        res.rows,
+        vec![
+            vec![DataValue::from(1)],
+            vec![DataValue::from(2)],
+            vec![DataValue::from(3)]
+        ]
+    );
+    // relations created after the restore get new ids
+    restored.run_default(":create b {x}").unwrap();
+
+    let restored = DbInstance::default();
+    let err = restored
+        .restore_to_timestamp(&backup, &archived[1..], f64::MAX)
+        .unwrap_err();
+    assert_eq!(err.code().as_deref(), Some("restore::tx_log_gap"));
+
+    assert_eq!(db.truncate_tx_log(2).unwrap(), 2);
+    assert_eq!(db.tx_log_chunk(0, 100).unwrap().entries[0].seq, 3);
+    assert!(db.disable_tx_log());
+    std::fs::remove_file(&backup).unwrap();
+}
+
+#[test]
+fn id_allocation() {
+    let db = DbInstance::default();
+    for name in ["a", "b", "c", "d"] {
+        db.run_default(&format!(":create {name} {{x}}")).unwrap();
+    }
+    db.run_default("::remove b").unwrap();
+    db.run_default("::remove d").unwrap();
+    // the id handed out to the aborted creation is never committed
+    let tx = db.multi_transaction(true);
+    tx.run_script(":create e {x}", Default::default()).unwrap();
+    tx.abort().unwrap();
+
+    let report = db.id_allocation_report().unwrap();
+    assert_eq!(report.counter, 4);
+    assert_eq!(report.allocated, 5);
+    assert_eq!(report.used, 2);
+    assert_eq!(report.gaps, vec![2..=2, 4..=4]);
+
+    assert_eq!(db.compact_id_counter().unwrap(), 3);
+    let report = db.id_allocation_report().unwrap();
+    assert_eq!(report.counter, 3);
+    assert_eq!(report.gaps, vec![2..=2]);
+    // the ids above the new counter are handed out again
+    db.run_default(":create f {x}").unwrap();
+    db.run_default("?[x] <- [[1]] :put f {x}").unwrap();
+    assert_eq!(db.id_allocation_report().unwrap().counter, 4);
+
+    // the id of the relation removed last is freed
+    db.run_default(":create g {x}").unwrap();
+    db.run_default("::remove g").unwrap();
+    assert_eq!(db.compact_id_counter().unwrap(), 4);
+}
+
+#[test]
+fn subscribe_from() {
+    let db = DbInstance::default();
+    let err = db.subscribe_from(0).unwrap_err();
+    assert_eq!(err.code().as_deref(), Some("db::tx_log_not_enabled"));
+
+    db.enable_tx_log().unwrap();
+    db.run_default(":create a {k => v}").unwrap();
+    db.run_default("?[k, v] <- [[1, 'x'], [2, 'y']] :put a {k => v}")
+        .unwrap();
+    let receiver = db.subscribe_from(0).unwrap();
+    let created = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(created.tx_id, 1);
+    assert!(created.relations.is_empty());
+    let put = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(put.tx_id, 2);
+    assert_eq!(put.relations["a"].put.headers, vec!["k", "v"]);
+    assert_eq!(
+        put.relations["a"].put.rows,
+        vec![
+            vec![DataValue::from(1), DataValue::from("x")],
+            vec![DataValue::from(2), DataValue::from("y")]
+        ]
+    );
+    // committed after the subscription
+    db.run_default("?[k] <- [[1]] :rm a {k}").unwrap();
+    let rm = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(rm.tx_id, 3);
+    assert_eq!(rm.relations["a"].rm.rows, vec![vec![DataValue::from(1)]]);
+    assert!(rm.relations["a"].put.rows.is_empty());
+
+    // resuming after the last acknowledged transaction
+    let receiver = db.subscribe_from(2).unwrap();
+    let next = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(next.tx_id, 3);
+
+    db.truncate_tx_log(2).unwrap();
+    let err = db.subscribe_from(1).unwrap_err();
+    assert_eq!(err.code().as_deref(), Some("db::tx_log_truncated"));
+    assert!(db.subscribe_from(2).is_ok());
+    // zero starts from the oldest entry left
+    let receiver = db.subscribe_from(0).unwrap();
+    let oldest = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(oldest.tx_id, 3);
+}
+
+#[cfg(feature = "sink-nats")]
+#[test]
+fn nats_sink() {
+    use crate::{SinkConfig, SinkFormat, SinkTarget};
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let (sender, published) = crossbeam::channel::unbounded();
+    std::thread::spawn(move || {
+        for (i, stream) in listener.incoming().enumerate() {
+            let mut stream = stream.unwrap();
+            stream.write_all(b"INFO {}\r\n").unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            assert!(line.starts_with("CONNECT "));
+            loop {
+                line.clear();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                let len = line.strip_prefix("PUB cozo.changes ").unwrap();
+                let len: usize = len.trim_end().parse().unwrap();
+                let mut payload = vec![0; len + 2];
+                reader.read_exact(&mut payload).unwrap();
+                payload.truncate(len);
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+                assert_eq!(line, "PING\r\n");
+                if i == 0 {
+                    // the first connection refuses to publish, the message is sent again
+                    stream
+                        .write_all(b"-ERR 'Permissions Violation'\r\n")
+                        .unwrap();
+                    break;
+                }
+                sender.send(payload).unwrap();
+                stream.write_all(b"PONG\r\n").unwrap();
+            }
+        }
+    });
+
+    let db = DbInstance::default();
+    db.enable_tx_log().unwrap();
+    db.run_default(":create a {k => v}").unwrap();
+    db.run_default(":create b {k => v}").unwrap();
+    let config = SinkConfig {
+        target: SinkTarget::Nats {
+            addr,
+            subject: "cozo.changes".to_string(),
+        },
+        format: SinkFormat::Json,
+        relations: Some(vec!["a".to_string()]),
+    };
+    let sink = db.start_sink(config, 0).unwrap();
+    db.run_default("?[k, v] <- [[1, 'x']] :put a {k => v}")
+        .unwrap();
+    db.run_default("?[k, v] <- [[1, 'x']] :put b {k => v}")
+        .unwrap();
+    db.run_default("?[k] <- [[1]] :rm a {k}").unwrap();
+
+    let first = published.recv_timeout(Duration::from_secs(10)).unwrap();
+    let first: serde_json::Value = serde_json::from_slice(&first).unwrap();
+    assert_eq!(first["tx_id"], json!(3));
+    assert_eq!(first["relations"]["a"]["put"]["rows"], json!([[1, "x"]]));
+    assert_eq!(first["relations"]["a"]["rm"]["rows"], json!([]));
+    // the transaction writing to `b` only is skipped
+    let second = published.recv_timeout(Duration::from_secs(10)).unwrap();
+    let second: serde_json::Value = serde_json::from_slice(&second).unwrap();
+    assert_eq!(second["tx_id"], json!(5));
+    assert_eq!(second["relations"]["a"]["rm"]["rows"], json!([[1]]));
+    assert!(second["relations"].get("b").is_none());
+
+    for _ in 0..100 {
+        if sink.delivered() == 5 {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    assert!(sink.is_running());
+    assert_eq!(sink.stop(), 5);
+}
+
+#[cfg(feature = "sink-kafka")]
+#[test]
+fn kafka_sink() {
+    use crate::{SinkConfig, SinkFormat, SinkTarget};
+    use std::io::{Read, Write};
+
+    fn varint(buf: &[u8], at: &mut usize) -> i64 {
+        let mut z = 0u64;
+        let mut shift = 0;
+        loop {
+            let b = buf[*at];
+            *at += 1;
+            z |= ((b & 0x7F) as u64) << shift;
+            shift += 7;
+            if b < 0x80 {
+                return (z >> 1) as i64 ^ -((z & 1) as i64);
+            }
+        }
+    }
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let (sender, produced) = crossbeam::channel::unbounded();
+    std::thread::spawn(move || {
+        let mut requests = 0;
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut size = [0; 4];
+            while stream.read_exact(&mut size).is_ok() {
+                let mut req = vec![0; i32::from_be_bytes(size) as usize];
+                stream.read_exact(&mut req).unwrap();
+                // api key and version
+                assert_eq!(req[..4], [0, 0, 0, 3]);
+                let correlation_id = req[4..8].to_vec();
+                let client_id_len = i16::from_be_bytes([req[8], req[9]]) as usize;
+                let mut at = 10 + client_id_len;
+                // transactional id, acks and timeout
+                assert_eq!(req[at..at + 4], [0xFF, 0xFF, 0xFF, 0xFF]);
+                at += 8;
+                assert_eq!(req[at..at + 4], [0, 0, 0, 1]);
+                let topic_len = i16::from_be_bytes([req[at + 4], req[at + 5]]) as usize;
+                let topic = req[at + 6..at + 6 + topic_len].to_vec();
+                assert_eq!(topic, b"changes");
+                at += 6 + topic_len;
+                // a single partition, number 2
+                assert_eq!(req[at..at + 8], [0, 0, 0, 1, 0, 0, 0, 2]);
+                let batch = &req[at + 12..];
+                assert_eq!(batch[16], 2);
+                let crc = u32::from_be_bytes(batch[17..21].try_into().unwrap());
+                assert_eq!(crc, crate::sink::kafka::crc32c(&batch[21..]));
+                let mut at = 61;
+                varint(batch, &mut at);
+                at += 1;
+                varint(batch, &mut at);
+                varint(batch, &mut at);
+                let key_len = varint(batch, &mut at) as usize;
+                let key = String::from_utf8(batch[at..at + key_len].to_vec()).unwrap();
+                at += key_len;
+                let value_len = varint(batch, &mut at) as usize;
+                let value = batch[at..at + value_len].to_vec();
+
+                // the first record is refused as if the broker did not lead the partition
+                let error_code: i16 = if requests == 0 { 6 } else { 0 };
+                requests += 1;
+                let mut resp = correlation_id;
+                resp.extend_from_slice(&[0, 0, 0, 1, 0, 7]);
+                resp.extend_from_slice(b"changes");
+                resp.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 2]);
+                resp.extend_from_slice(&error_code.to_be_bytes());
+                resp.extend_from_slice(&[0; 20]);
+                let mut framed = (resp.len() as i32).to_be_bytes().to_vec();
+                framed.extend_from_slice(&resp);
+                stream.write_all(&framed).unwrap();
+                if error_code == 0 {
+                    sender.send((key, value)).unwrap();
+                }
+            }
+        }
+    });
+
+    let db = DbInstance::default();
+    db.enable_tx_log().unwrap();
+    db.run_default(":create a {k => v}").unwrap();
+    let config = SinkConfig {
+        target: SinkTarget::Kafka {
+            addr,
+            topic: "changes".to_string(),
+            partition: 2,
+        },
+        format: SinkFormat::MessagePack,
+        relations: None,
+    };
+    let sink = db.start_sink(config, 0).unwrap();
+    db.run_default("?[k, v] <- [[1, 'x']] :put a {k => v}")
+        .unwrap();
+
+    // the transaction creating the relation changes no rows and is not published
+    let (key, value) = produced.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert_eq!(key, "2");
+    let value: serde_json::Value = rmp_serde::from_slice(&value).unwrap();
+    assert_eq!(value["relations"]["a"]["put"]["headers"], json!(["k", "v"]));
+    assert_eq!(value["relations"]["a"]["put"]["rows"], json!([[1, "x"]]));
+    assert_eq!(sink.stop(), 2);
+}
+
+#[cfg(feature = "derive")]
+#[test]
+fn entity_mapping() {
+    use crate::{FromEntity, IntoTx};
+
+    #[derive(Debug, PartialEq, FromEntity, IntoTx)]
+    struct Person {
+        id: i64,
+        #[cozo(rename = "full_name")]
+        name: String,
+        tags: Vec<String>,
+        email: Option<String>,
+    }
+
+    let db = DbInstance::default();
+    db.run_default(":create person {id: Int => full_name: String, tags: [String], email: String?}")
+        .unwrap();
+    let people = vec![
+        Person {
+            id: 1,
+            name: "Ada".to_string(),
+            tags: vec!["math".to_string()],
+            email: None,
+        },
+        Person {
+            id: 2,
+            name: "Alan".to_string(),
+            tags: vec![],
+            email: Some("alan@example.com".to_string()),
+        },
+    ];
+    let rows = NamedRows::from_entities(people).unwrap();
+    assert_eq!(rows.headers, vec!["id", "full_name", "tags", "email"]);
+    db.import_relations(BTreeMap::from([("person".to_string(), rows)]))
+        .unwrap();
+
+    let res = db
+        .run_default("?[id, full_name, tags, email] := *person{id, full_name, tags, email}")
+        .unwrap();
+    let read: Vec<Person> = res.into_entities().unwrap();
+    assert_eq!(read[0].name, "Ada");
+    assert_eq!(read[0].email, None);
+    assert_eq!(read[1].email.as_deref(), Some("alan@example.com"));
+
+    // columns that are missing are read as null, which only optional fields accept
+    let res = db
+        .run_default("?[id, full_name] := *person{id, full_name}")
+        .unwrap();
+    let err = res.into_entities::<Person>().unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "entity::bad_column");
+}
+
+#[test]
+fn run_query_as() {
+    #[derive(serde_derive::Deserialize, Debug, PartialEq)]
+    struct Edge {
+        fr: String,
+        to: String,
+        weight: f64,
+        label: Option<String>,
+    }
+
+    let db = DbInstance::default();
+    db.run_default(":create edge {fr: String, to: String => weight: Float}")
+        .unwrap();
+    db.run_default(
+        "?[fr, to, weight] <- [['a', 'b', 1.5], ['b', 'c', 2]] :put edge {fr, to => weight}",
+    )
+    .unwrap();
+    let edges: Vec<Edge> = db
+        .run_query_as(
+            "?[fr, to, weight] := *edge{fr, to, weight}, fr = $fr",
+            BTreeMap::from([("fr".to_string(), DataValue::from("a"))]),
+        )
+        .unwrap();
+    assert_eq!(
+        edges,
+        vec![Edge {
+            fr: "a".to_string(),
+            to: "b".to_string(),
+            weight: 1.5,
+            label: None,
+        }]
+    );
+
+    let err = db
+        .run_query_as::<Edge>(
+            "?[fr, to, weight] := *edge{fr, to}, weight = 'heavy'",
+            Default::default(),
+        )
+        .unwrap_err();
+    assert_eq!(err.code().as_deref(), Some("db::bad_query_row"));
+    assert!(err.to_string().contains("row 0"));
+    // queries run read-only
+    assert!(db
+        .run_query_as::<Edge>(
+            "?[fr, to, weight] <- [['x', 'y', 1]] :put edge {fr, to => weight}",
+            Default::default()
+        )
+        .is_err());
+}
+
+#[test]
+fn query_ast() {
+    use crate::ast::{Atom, Expr, HeadArg, Query, RelationOp, Rule, SortDir};
+
+    let db = DbInstance::default();
+    let create = Query::new()
+        .rule(
+            Rule::new("?", ["fr", "to", "weight"]).constant(Expr::val(DataValue::List(vec![
+                DataValue::List(vec!["a".into(), "b".into(), 1.5.into()]),
+                DataValue::List(vec!["a".into(), "c'\"]".into(), 2.into()]),
+                DataValue::List(vec!["b".into(), "c'\"]".into(), 3.into()]),
+            ]))),
+        )
+        .relation_op(RelationOp::Create, "edge", ["fr", "to"], ["weight"]);
+    assert_eq!(
+        create.to_script().unwrap(),
+        "?[fr, to, weight] <- [['a', 'b', 1.5], ['a', 'c\\'\"]', 2], ['b', 'c\\'\"]', 3]]\n\
+        :create edge {fr, to => weight}"
+    );
+    db.run_ast(&create, Default::default(), ScriptMutability::Mutable)
+        .unwrap();
+
+    let query = Query::new()
+        .rule(Rule::new("out", ["to"]).atom(Atom::named_relation(
+            "edge",
+            [("fr", Expr::param("fr")), ("to", Expr::var("to"))],
+        )))
+        .rule(
+            Rule::new("?", [HeadArg::from("to"), HeadArg::aggr("count", "fr")]).atoms([
+                Atom::rule("out", [Expr::var("to")]),
+                Atom::relation("edge", [Expr::var("fr"), Expr::var("to"), Expr::var("w")]),
+                Atom::predicate(Expr::apply("gt", [Expr::var("w"), Expr::val(1)])),
+                Atom::negate(Atom::predicate(Expr::apply(
+                    "eq",
+                    [Expr::var("fr"), Expr::val("z")],
+                ))),
+            ]),
+        )
+        .sort([("to", SortDir::Dsc)])
+        .limit(5);
+    assert_eq!(
+        query.to_script().unwrap(),
+        "out[to] := *edge{fr: $fr, to: to}\n\
+        ?[to, count(fr)] := out[to], *edge[fr, to, w], gt(w, 1), not eq(fr, 'z')\n\
+        :sort -to\n\
+        :limit 5"
+    );
+    let res = db
+        .run_ast(
+            &query,
+            BTreeMap::from([("fr".to_string(), DataValue::from("a"))]),
+            ScriptMutability::Immutable,
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["c'\"]", 2], ["b", 1]]));
+
+    // names are checked against the grammar instead of being spliced in
+    let bad = Query::new().rule(
+        Rule::new("?", ["x"]).atom(Atom::relation("edge[x]; ::remove edge", [Expr::var("x")])),
+    );
+    let err = bad.to_script().unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "ast::invalid_name");
+    let bad = Query::new().rule(Rule::new("?", ["not"]).atom(Atom::unify("not", Expr::val(1))));
+    assert!(bad.to_script().is_err());
+}
+
+#[test]
+fn forbid_string_literals() {
+    let db = DbInstance::default();
+    db.run_default(":create user {name: String => role: String}")
+        .unwrap();
+    db.run_default("?[name, role] <- [['alice', 'admin']] :put user {name => role}")
+        .unwrap();
+    db.forbid_string_literals(true);
+
+    for script in [
+        "?[role] := *user{name: 'alice', role}",
+        "?[role] := *user{name: \"alice\", role}",
+        "?[role] := *user{name: ___\"alice\"___, role}",
+        "{?[role] := *user{name: 'alice', role}}",
+    ] {
+        let err = db.run_default(script).unwrap_err();
+        assert_eq!(
+            err.code().as_deref(),
+            Some("parser::string_literal_forbidden")
+        );
+    }
+    let res = db
+        .run_script(
+            "?[role] := *user{name: $name, role}",
+            BTreeMap::from([("name".to_string(), DataValue::from("alice"))]),
+            ScriptMutability::Immutable,
+        )
+        .unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from("admin")]]);
+    let err = db
+        .run_script(
+            "?[role] := *user{name: $name, role}",
+            Default::default(),
+            ScriptMutability::Immutable,
+        )
+        .unwrap_err();
+    assert_eq!(err.code().as_deref(), Some("parser::param_not_found"));
+    // system ops are exempt, and numbers are no strings
+    db.run_default("::columns user").unwrap();
+    db.run_default("?[x] <- [[1]] :limit 1").unwrap();
+
+    db.forbid_string_literals(false);
+    db.run_default("?[role] := *user{name: 'alice', role}")
+        .unwrap();
+}
+
+#[test]
+fn check_script() {
+    use crate::CheckSeverity;
+
+    let db = DbInstance::default();
+    db.run_default(":create a {k => v}").unwrap();
+    db.run_default(":create b {k => v}").unwrap();
+    let check = |script: &str| db.check(script, Default::default());
+
+    assert!(check("?[k, v] := *a{k, v}").is_empty());
+    assert!(check("?[k, w] := *a{k, v}, *b{k: v, v: w}").is_empty());
+    // nothing is run
+    assert!(check("?[k, v] <- [[1, 2]] :put a {k => v}").is_empty());
+    assert!(db.run_default("?[k] := *a{k}").unwrap().rows.is_empty());
+
+    let syntax = check("?[k] := *a{k");
+    assert_eq!(syntax.len(), 1);
+    assert_eq!(syntax[0].severity, CheckSeverity::Error);
+    assert_eq!(syntax[0].code, "parser::pest");
+    assert_eq!(syntax[0].spans.len(), 1);
+
+    for script in [
+        "?[k] := *nope{k}",
+        "?[k] := *a{k, nope}",
+        "?[k, x] := *a{k}",
+        "?[k] := *a{k}, not *b{k: x}",
+    ] {
+        let found = check(script);
+        assert_eq!(found.len(), 1, "{script}");
+        assert_eq!(found[0].severity, CheckSeverity::Error, "{script}");
+    }
+
+    let cartesian = check("?[x, y] := *a{k: x}, *b{k: y}");
+    assert_eq!(cartesian.len(), 1);
+    assert_eq!(cartesian[0].severity, CheckSeverity::Warning);
+    assert_eq!(cartesian[0].code, "check::cartesian_product");
+    assert_eq!(cartesian[0].rule.as_deref(), Some("?"));
+
+    let scan = check("?[x, k] := *b{k: x}, *a{k, v: x}");
+    assert_eq!(scan.len(), 1);
+    assert_eq!(scan[0].code, "check::missing_index");
+    assert!(scan[0].message.contains("*a on v"), "{}", scan[0].message);
+    db.run_default("::index create a:by_v {v}").unwrap();
+    assert!(check("?[x, k] := *b{k: x}, *a{k, v: x}").is_empty());
+}
+
+#[test]
+fn describe_db() {
+    let db = DbInstance::default();
+    db.run_default(":create a {k: Int => v: String default '', w: Any?}")
+        .unwrap();
+    db.run_default("::describe a 'Things by key'").unwrap();
+    db.run_default("::index create a:by_v {v}").unwrap();
+    db.run_default(":create b {k}").unwrap();
+
+    let desc = db.describe().unwrap();
+    let names = desc.relations.iter().map(|r| r.name.as_str()).collect_vec();
+    assert_eq!(names, ["a", "b"]);
+    let a = &desc.relations[0];
+    assert_eq!(a.description.as_deref(), Some("Things by key"));
+    assert_eq!(a.access_level, "normal");
+    assert_eq!(a.keys.len(), 1);
+    assert_eq!(a.keys[0].col_type, "Int");
+    assert_eq!(
+        a.values
+            .iter()
+            .map(|c| (c.name.as_str(), c.has_default))
+            .collect_vec(),
+        [("v", true), ("w", false)]
+    );
+    assert_eq!(a.indices.len(), 1);
+    assert_eq!(a.indices[0].name, "by_v");
+    assert_eq!(a.indices[0].kind, "normal");
+    assert_eq!(a.indices[0].columns[0], "v");
+    assert_eq!(desc.relations[1].description, None);
+
+    assert_eq!(
+        desc.functions.len(),
+        crate::data::expr::BUILTIN_FUNCTIONS.len()
+    );
+    let concat = desc.functions.iter().find(|f| f.name == "concat").unwrap();
+    assert!(concat.vararg);
+    let add = desc.functions.iter().find(|f| f.name == "add").unwrap();
+    assert_eq!(add.min_arity, 0);
+    for name in crate::data::aggr::BUILTIN_AGGREGATIONS {
+        assert!(crate::data::aggr::parse_aggr(name).is_some(), "{name}");
+    }
+    assert!(desc.aggregations.iter().any(|a| a == "count"));
+    assert!(desc.fixed_rules.iter().any(|r| r == "PageRank"));
+    let json = serde_json::to_value(&desc).unwrap();
+    assert_eq!(json["relations"][0]["keys"][0]["type"], "Int");
+}
+
+#[test]
+fn result_formats() {
+    use crate::{ResultFormat, ResultSerializer};
+
+    let db = DbInstance::default();
+    let script = "?[a, b, c] <- [[1, -300, 'x'], [2.5, null, true]]";
+    let run = |format: ResultFormat| {
+        db.run_script_serialized(
+            script,
+            Default::default(),
+            ScriptMutability::Immutable,
+            &format,
+        )
+        .unwrap()
+    };
+    let json: serde_json::Value = serde_json::from_slice(&run(ResultFormat::Json)).unwrap();
+    let msgpack: serde_json::Value =
+        rmp_serde::from_slice(&run(ResultFormat::MessagePack)).unwrap();
+    assert_eq!(json, msgpack);
+    assert_eq!(json["rows"].as_array().unwrap().len(), 2);
+
+    let rows = NamedRows::new(
+        vec!["k".to_string()],
+        vec![
+            vec![DataValue::from(-1)],
+            vec![DataValue::Bytes(vec![7; 24])],
+        ],
+    );
+    let cbor = rows.serialize(&ResultFormat::Cbor).unwrap();
+    let mut expected = vec![0xa3, 0x67];
+    expected.extend_from_slice(b"headers");
+    expected.extend_from_slice(&[0x81, 0x61, b'k', 0x64]);
+    expected.extend_from_slice(b"rows");
+    expected.extend_from_slice(&[0x82, 0x81, 0x20, 0x81, 0x58, 24]);
+    expected.extend_from_slice(&[7; 24]);
+    expected.push(0x64);
+    expected.extend_from_slice(b"next");
+    expected.push(0xf6);
+    assert_eq!(cbor, expected);
+
+    let msgpack = rows.serialize(&ResultFormat::MessagePack).unwrap();
+    let value: rmpv::Value = rmpv::decode::read_value(&mut msgpack.as_slice()).unwrap();
+    assert_eq!(value["rows"][1][0], rmpv::Value::Binary(vec![7; 24]));
+
+    assert_eq!(ResultFormat::Cbor.content_type(), "application/cbor");
+    assert_eq!(
+        ResultFormat::from_content_type("application/msgpack; q=0.9"),
+        Some(ResultFormat::MessagePack)
+    );
+    assert_eq!("cbor".parse::<ResultFormat>().unwrap(), ResultFormat::Cbor);
+    assert!("xml".parse::<ResultFormat>().is_err());
+}
+
+#[test]
+fn temp_rels_in_multi_transaction() {
+    let db = DbInstance::default();
+    db.run_default(":create person {id => name}").unwrap();
+    db.run_default("?[id, name] <- [[1, 'a'], [2, 'b'], [3, 'c']] :put person {id => name}")
+        .unwrap();
+
+    let tx = db.multi_transaction(true);
+    let wanted = NamedRows::new(
+        vec!["id".to_string()],
+        vec![
+            vec![DataValue::from(1)],
+            vec![DataValue::from(3)],
+            vec![DataValue::from(3)],
+        ],
+    );
+    tx.create_temp_rel("_wanted", wanted).unwrap();
+    let res = tx
+        .run_script(
+            "?[name] := *_wanted{id}, *person{id, name}",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["a"], ["c"]]));
+    // temporary relations created by a script are also kept for the next ones
+    tx.run_script(
+        "?[name] := *_wanted{id}, *person{id, name} :create _names {name}",
+        Default::default(),
+    )
+    .unwrap();
+    let res = tx
+        .run_script("?[count(name)] := *_names{name}", Default::default())
+        .unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(2));
+
+    assert!(tx.create_temp_rel("wanted", NamedRows::default()).is_err());
+    assert!(tx.create_temp_rel("_wanted", NamedRows::default()).is_err());
+    let ragged = NamedRows::new(
+        vec!["a".to_string(), "b".to_string()],
+        vec![vec![DataValue::from(1)]],
+    );
+    assert!(tx.create_temp_rel("_ragged", ragged).is_err());
+    tx.commit().unwrap();
+
+    assert!(db.run_default("?[id] := *_wanted{id}").is_err());
+    let relations = db.run_default("::relations").unwrap();
+    assert_eq!(relations.rows.len(), 1);
+}
+
+#[test]
+fn input_relations() {
+    let db = DbInstance::default();
+    db.run_default(":create person {id => name}").unwrap();
+    db.run_default("?[id, name] <- [[1, 'a'], [2, 'b'], [3, 'c']] :put person {id => name}")
+        .unwrap();
+    let ids = |ids: &[i64]| {
+        NamedRows::new(
+            vec!["id".to_string()],
+            ids.iter().map(|id| vec![DataValue::from(*id)]).collect(),
+        )
+    };
+    let run = |script: &str, inputs: BTreeMap<String, NamedRows>| {
+        db.run_script_with_inputs(
+            script,
+            Default::default(),
+            inputs,
+            ScriptMutability::Immutable,
+        )
+    };
+
+    let inputs = BTreeMap::from([("wanted".to_string(), ids(&[3, 1, 4]))]);
+    let res = run("?[name] := wanted[id], *person{id, name}", inputs).unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["a"], ["c"]]));
+    // an empty input still has the arity given by its headers
+    let inputs = BTreeMap::from([("wanted".to_string(), ids(&[]))]);
+    let res = run("?[name] := wanted[id], *person{id, name}", inputs).unwrap();
+    assert!(res.rows.is_empty());
+
+    let inputs = BTreeMap::from([("wanted".to_string(), ids(&[1]))]);
+    assert!(run("wanted[id] <- [[2]] ?[id] := wanted[id]", inputs).is_err());
+    let inputs = BTreeMap::from([("?".to_string(), ids(&[1]))]);
+    assert!(run("?[id] := *person{id}", inputs).is_err());
+    let ragged = NamedRows::new(
+        vec!["a".to_string(), "b".to_string()],
+        vec![vec![DataValue::from(1)]],
+    );
+    let inputs = BTreeMap::from([("ragged".to_string(), ragged)]);
+    assert!(run("?[a] := ragged[a, _]", inputs).is_err());
+    let inputs = BTreeMap::from([("wanted".to_string(), ids(&[1]))]);
+    assert!(run("::relations", inputs).is_err());
+}
+
+#[test]
+fn attached_databases() {
+    let live = DbInstance::default();
+    let archive = DbInstance::default();
+    for db in [&live, &archive] {
+        db.run_default(":create person {id => name}").unwrap();
+    }
+    live.run_default("?[id, name] <- [[1, 'a'], [2, 'b']] :put person {id => name}")
+        .unwrap();
+    archive
+        .run_default("?[id, name] <- [[2, 'b'], [3, 'c']] :put person {id => name}")
+        .unwrap();
+
+    live.attach("archive", archive.clone()).unwrap();
+    assert!(live.attach("archive", archive.clone()).is_err());
+    assert!(live.attach("PageRank", archive.clone()).is_err());
+    assert_eq!(live.list_attached(), ["archive"]);
+    let gone = "archived[id, name] <~ archive(relation: 'person') \
+                ?[id, name] := archived[id, name], not *person{id}";
+    let res = live.run_default(gone).unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[3, "c"]]));
+    // the attached database is read live
+    archive
+        .run_default("?[id, name] <- [[4, 'd']] :put person {id => name}")
+        .unwrap();
+    assert_eq!(live.run_default(gone).unwrap().rows.len(), 2);
+    assert!(live
+        .run_default("r[a, b] <~ archive(relation: 'nope') ?[a] := r[a, b]")
+        .is_err());
+    assert!(live
+        .run_default("r[a, b] <~ archive(relation: 'person{id}, x') ?[a] := r[a, b]")
+        .is_err());
+
+    let mut dump = vec![];
+    archive.snapshot_export(&mut dump).unwrap();
+    live.attach_snapshot("frozen", dump.as_slice()).unwrap();
+    archive
+        .run_default("?[id] <- [[4]] :rm person {id}")
+        .unwrap();
+    let res = live
+        .run_default("f[id, name] <~ frozen(relation: 'person') ?[count(id)] := f[id, name]")
+        .unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(3));
+
+    assert!(live.detach("archive").unwrap());
+    assert!(!live.detach("archive").unwrap());
+    assert!(!live.detach("PageRank").unwrap());
+    assert!(live.run_default(gone).is_err());
+    assert_eq!(live.list_attached(), ["frozen"]);
+}
+
+#[test]
+fn dump_versions() {
+    let db = DbInstance::default();
+    db.run_default(":create a {x => y}").unwrap();
+    db.run_default("?[x, y] <- [[1, 2], [3, 4]] :put a {x => y}")
+        .unwrap();
+    assert_eq!(db.dump_version(), crate::DUMP_VERSION);
+    let mut dump = vec![];
+    db.snapshot_export(&mut dump).unwrap();
+
+    let restored = DbInstance::default();
+    restored.run_default(":create a {x => y}").unwrap();
+    let report = restored.snapshot_import(dump.as_slice()).unwrap();
+    assert_eq!(report.version, crate::DUMP_VERSION);
+    assert_eq!(report.relations, ["a"]);
+    assert_eq!(report.rows, 2);
+
+    // dumps of version 1 have no header
+    let v1 = r#"{"relation": "a", "headers": ["x", "y"], "rows": [[5, 6]]}"#;
+    let report = restored.snapshot_import(v1.as_bytes()).unwrap();
+    assert_eq!(report.version, 1);
+    let res = restored.run_default("?[count(x)] := *a{x}").unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(3));
+
+    let future = format!(r#"{{"cozo_dump": {}}}"#, crate::DUMP_VERSION + 1);
+    let err = restored.snapshot_import(future.as_bytes()).unwrap_err();
+    assert_eq!(err.code().as_deref(), Some("dump::unsupported_version"));
+    let err = restored
+        .snapshot_import("{\"cozo_dump\": 2}\nnope".as_bytes())
+        .unwrap_err();
+    assert_eq!(err.code().as_deref(), Some("dump::bad_line"));
+}
+
+#[test]
+fn storage_version_check() {
+    let db = crate::new_cozo_mem().unwrap();
+    db.run_script(
+        ":create a {x}",
+        Default::default(),
+        ScriptMutability::Mutable,
+    )
+    .unwrap();
+    db.initialize().unwrap();
+
+    {
+        let mut tx = db.transact_write().unwrap();
+        tx.store_tx
+            .put(&crate::runtime::transact::storage_version_key(), &[0x7f])
+            .unwrap();
+        tx.commit_tx().unwrap();
+    }
+    let err = db.initialize().unwrap_err();
+    assert_eq!(
+        err.code().map(|c| c.to_string()).as_deref(),
+        Some("storage::incompatible_version")
+    );
+    assert!(err
+        .help()
+        .unwrap()
+        .to_string()
+        .contains(env!("CARGO_PKG_VERSION")));
+}
+
+#[test]
+fn storage_upgrade() {
+    use crate::runtime::transact::{storage_version_key, CURRENT_STORAGE_VERSION};
+    use crate::runtime::upgrade::StorageMigration;
+
+    let db = crate::new_cozo_mem().unwrap();
+    db.run_script(
+        "?[x] <- [[1], [2]] :create a {x}",
+        Default::default(),
+        ScriptMutability::Mutable,
+    )
+    .unwrap();
+    let report = db
+        .upgrade_storage(|_| panic!("nothing to rewrite"))
+        .unwrap();
+    assert_eq!(report.from_version, Some(CURRENT_STORAGE_VERSION.to_vec()));
+    assert_eq!(report.steps, 0);
+
+    {
+        let mut tx = db.transact_write().unwrap();
+        tx.store_tx.put(&storage_version_key(), &[0x7f]).unwrap();
+        tx.commit_tx().unwrap();
+    }
+    let err = db.upgrade_storage(|_| {}).unwrap_err();
+    assert_eq!(
+        err.code().map(|c| c.to_string()).as_deref(),
+        Some("storage::incompatible_version")
+    );
+
+    let migrations = [StorageMigration {
+        from: &[0x7f],
+        to: &CURRENT_STORAGE_VERSION,
+        rewrite: |k, v| Ok(Some((k.to_vec(), v.to_vec()))),
+    }];
+    let mut batches = vec![];
+    let report = db
+        .upgrade_storage_with(&migrations, |p| batches.push(p.clone()))
+        .unwrap();
+    assert_eq!(report.steps, 1);
+    assert!(report.pairs_rewritten > 2);
+    assert_eq!(batches.len(), 1);
+    assert_eq!(batches[0].pairs_read, batches[0].pairs_rewritten + 1);
+    db.initialize().unwrap();
+    let res = db
+        .run_script(
+            "?[x] := *a{x}",
+            Default::default(),
+            ScriptMutability::Immutable,
+        )
+        .unwrap();
+    assert_eq!(res.rows.len(), 2);
+
+    let report = DbInstance::upgrade_storage("mem", "", "", |_| {}).unwrap();
+    assert_eq!(report, Default::default());
+}
+
+#[test]
+fn crash_simulation() {
+    use crate::storage::sim::{sweep_faults, Fault, SimDb};
+    use miette::ensure;
+
+    let run = |db: &SimDb, script: &str| {
+        db.run_script(script, Default::default(), ScriptMutability::Mutable)
+    };
+    let setup = |db: &SimDb| {
+        run(
+            db,
+            "?[id, balance] <- [[0, 100], [1, 100], [2, 100]] :create acct {id => balance}",
+        )?;
+        run(db, "::index create acct:by_balance {balance, id}")?;
+        Ok(())
+    };
+    let workload = |db: &SimDb| {
+        for (from, to) in [(0, 1), (1, 2), (2, 0), (0, 2)] {
+            run(
+                db,
+                &format!(
+                    r#"
+                    ?[id, balance] := *acct{{id, balance: b}}, id == {from}, balance = b - 10
+                    ?[id, balance] := *acct{{id, balance: b}}, id == {to}, balance = b + 10
+                    :put acct {{id => balance}}
+                    "#
+                ),
+            )?;
+        }
+        Ok(())
+    };
+    let check = |db: &SimDb| {
+        let res = db.run_script(
+            "?[count(id), sum(balance)] := *acct{id, balance}",
+            Default::default(),
+            ScriptMutability::Immutable,
+        )?;
+        ensure!(
+            res.rows[0] == vec![DataValue::from(3), DataValue::from(300.)],
+            "balances no longer add up: {:?}",
+            res.rows[0]
+        );
+        Ok(())
+    };
+
+    let faults = [Fault::WriteError, Fault::Crash, Fault::TornCommit];
+    let runs = sweep_faults(&faults, setup, workload, check).unwrap();
+    assert!(runs.len() > 3 * 4 * 3);
+    for run in &runs {
+        if run.fault != Fault::TornCommit {
+            assert!(run.report.is_ok(), "{:?} at {}", run.fault, run.at);
+            run.check.as_ref().unwrap();
+        }
+    }
+    // commits torn by the storage break atomicity, and are detected
+    assert!(runs
+        .iter()
+        .any(|run| run.fault == Fault::TornCommit && (!run.report.is_ok() || run.check.is_err())));
+}
+
+#[test]
+fn fuzz_entry_points() {
+    use crate::fuzz::{fuzz_cmp, fuzz_decode_value, fuzz_parse};
+
+    for script in [
+        "?[x] := x = 1",
+        "?[x] <- [[1, 'a']] :create a {x}",
+        "{ ?[a] <- [[1]] } %if _ %then { ?[b] := b = 2 }",
+        "?[x] := x = 1 +",
+        "::index create a:b {",
+        "\u{0}[]",
+    ] {
+        fuzz_parse(script.as_bytes());
+    }
+    fuzz_parse(&[0xff, 0xfe]);
+
+    let values = [
+        DataValue::Null,
+        DataValue::from(false),
+        DataValue::from(true),
+        DataValue::from(-1),
+        DataValue::from(0),
+        DataValue::from(1),
+        DataValue::from(1.0),
+        DataValue::from(-0.5),
+        DataValue::from(f64::INFINITY),
+        DataValue::from(i64::MAX),
+        DataValue::from(""),
+        DataValue::from("a"),
+        DataValue::from("ab"),
+        DataValue::Bytes(vec![0, 255]),
+        DataValue::List(vec![DataValue::from(1), DataValue::from("a")]),
+        DataValue::List(vec![]),
+        DataValue::Set([DataValue::from(2)].into()),
+        DataValue::Json(crate::JsonData(json!({"a": [1, null]}))),
+        DataValue::Uuid(crate::UuidWrapper(uuid::Uuid::from_u128(1 << 100))),
+        DataValue::Validity(crate::Validity::from((3, true))),
+        DataValue::Vec(crate::Vector::F32(ndarray::array![1., -2.])),
+    ];
+    let encoded = values
+        .iter()
+        .map(|v| rmp_serde::to_vec(v).unwrap())
+        .collect_vec();
+    for a in &encoded {
+        fuzz_decode_value(a);
+        for b in &encoded {
+            fuzz_cmp(a, b);
+        }
+    }
+    fuzz_decode_value(&[0xc1, 0x00]);
+}
+
+#[test]
+fn query_plan_goldens() {
+    use crate::runtime::golden::assert_golden_plan;
+
+    let db = DbInstance::default();
+    db.run_default(":create friends {fr: Int, to: Int => data: Any}")
+        .unwrap();
+    db.run_default("::index create friends:rev {to, fr}")
+        .unwrap();
+    db.run_default(":create person {id: Int => name: String, age: Int}")
+        .unwrap();
+
+    assert_golden_plan(
+        &db,
+        "index_lookup",
+        "?[fr, data] := *friends{to: 2, fr, data}",
+    );
+    assert_golden_plan(
+        &db,
+        "prefix_scan",
+        "?[to, data] := *friends{fr: 1, to, data}",
+    );
+    assert_golden_plan(
+        &db,
+        "join_filter",
+        "?[name, to] := *person{id, name, age}, age > 30, *friends{fr: id, to}",
+    );
+    assert_golden_plan(
+        &db,
+        "negation",
+        "?[id] := *person{id}, not *friends{fr: id}",
+    );
+    assert_golden_plan(
+        &db,
+        "recursion_aggregation",
+        r#"
+        reach[a, b] := *friends{fr: a, to: b}
+        reach[a, c] := reach[a, b], *friends{fr: b, to: c}
+        ?[a, count(b)] := reach[a, b]
+        "#,
+    );
+}
+
+#[test]
+fn why_provenance() {
+    use crate::{DerivationKind, ScriptMutability};
+
+    let db = DbInstance::default();
+    db.run_default(":create edge {fr: Int, to: Int}").unwrap();
+    db.run_default(":create blocked {id: Int}").unwrap();
+    db.run_default("?[fr, to] <- [[1, 2], [2, 3], [3, 1], [3, 4]] :put edge {fr, to}")
+        .unwrap();
+    db.run_default("?[id] <- [[4]] :put blocked {id}").unwrap();
+
+    let reach = r#"
+        reach[a, b] := *edge{fr: a, to: b}
+        reach[a, c] := reach[a, b], *edge{fr: b, to: c}
+        ?[a, b] := reach[a, b], not *blocked{id: b}
+    "#;
+    let why = |query: &str, fact: Vec<DataValue>| db.why(query, Default::default(), fact).unwrap();
+
+    let derivation = why(reach, vec![DataValue::from(1), DataValue::from(1)]).unwrap();
+    assert_eq!(derivation.relation, "?");
+    assert_eq!(derivation.kind, DerivationKind::Rule { clause: 0 });
+    let [reached, not_blocked] = &derivation.premises[..] else {
+        panic!("{derivation}")
+    };
+    assert_eq!(reached.relation, "reach");
+    assert_eq!(reached.kind, DerivationKind::Rule { clause: 1 });
+    assert_eq!(not_blocked.kind, DerivationKind::Absent);
+    assert_eq!(not_blocked.fact, vec![DataValue::from(1)]);
+    // the cycle through 1 is explained by all three edges, not by itself
+    let mut stored = derivation
+        .stored_facts()
+        .into_iter()
+        .map(|(rel, fact)| (rel.to_string(), fact.clone()))
+        .collect_vec();
+    stored.sort();
+    let edge = |a: i64, b: i64| {
+        (
+            "edge".to_string(),
+            vec![DataValue::from(a), DataValue::from(b)],
+        )
+    };
+    assert_eq!(stored, vec![edge(1, 2), edge(2, 3), edge(3, 1)]);
+    assert!(derivation.to_string().contains("*edge[3, 1]"));
+
+    // blocked, and not derivable at all
+    assert!(why(reach, vec![DataValue::from(1), DataValue::from(4)]).is_none());
+    assert!(why(reach, vec![DataValue::from(4), DataValue::from(1)]).is_none());
+
+    let counted = why(
+        "?[a, count(b)] := *edge{fr: a, to: b}",
+        vec![DataValue::from(3), DataValue::from(2)],
+    )
+    .unwrap();
+    assert_eq!(counted.kind, DerivationKind::Aggregation { clause: 0 });
+    assert_eq!(counted.premises.len(), 2);
+    assert!(why(
+        "?[a, count(b)] := *edge{fr: a, to: b}",
+        vec![DataValue::from(3), DataValue::from(5)],
+    )
+    .is_none());
+
+    let err = db
+        .why(reach, Default::default(), vec![DataValue::from(1)])
+        .unwrap_err();
+    assert_eq!(
+        err.code().map(|c| c.to_string()).as_deref(),
+        Some("eval::why_arity_mismatch")
+    );
+    // the explained rows are the rows of the query
+    let rows = db
+        .run_script(reach, Default::default(), ScriptMutability::Immutable)
+        .unwrap();
+    for row in rows.rows {
+        assert!(why(reach, row).is_some());
+    }
+}
+
+#[test]
+fn debug_dump_intermediate_relations() {
+    let db = DbInstance::default();
+    db.run_default("?[fr, to] <- [[1, 2], [2, 3], [3, 4]] :create edge {fr, to}")
+        .unwrap();
+    let res = db
+        .run_default(
+            r#"
+            reach[a, b] := *edge{fr: a, to: b}
+            reach[a, c] := reach[a, b], *edge{fr: b, to: c}
+            ?[a, b] := reach[a, b]
+            :debug 2
+            "#,
+        )
+        .unwrap();
+    assert_eq!(
+        res.headers,
+        ["stratum", "rule", "epoch", "new_rows", "total_rows", "rows"]
+    );
+    let reach = res
+        .rows
+        .iter()
+        // named as in `::explain`, after the rewrite by magic sets
+        .filter(|row| row[1].get_str().unwrap().starts_with("reach"))
+        .map(|row| {
+            let kept = row[5].get_slice().unwrap().len();
+            (row[2].get_int().unwrap(), row[3].get_int().unwrap(), kept)
+        })
+        .collect_vec();
+    // paths of one edge, then of two, then of three
+    assert_eq!(reach, vec![(0, 3, 2), (1, 2, 2), (2, 1, 1)]);
+    let entry = res.rows.last().unwrap();
+    assert_eq!(entry[1], DataValue::from("?"));
+    assert_eq!(entry[4], DataValue::from(6));
+
+    // nothing is stored
+    db.run_default("?[fr, to] <- [[5, 6]] :put edge {fr, to} :debug 10")
+        .unwrap();
+    let res = db.run_default("?[count(fr)] := *edge{fr}").unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(3)]]);
+    assert!(db.run_default("?[a] <- [[1]] :debug -1").is_err());
+}
+
+#[test]
+fn eval_limits_and_progress() {
+    use crate::{EvalLimits, EvalProgress};
+    use std::sync::{Arc, Mutex};
+
+    let db = DbInstance::default();
+    db.run_default("?[fr, to] <- [[1, 2], [2, 3], [3, 4], [4, 5]] :create edge {fr, to}")
+        .unwrap();
+    let reach = r#"
+        reach[a, b] := *edge{fr: a, to: b}
+        reach[a, c] := reach[a, b], *edge{fr: b, to: c}
+        ?[a, b] := reach[a, b]
+    "#;
+    let seen: Arc<Mutex<Vec<EvalProgress>>> = Default::default();
+    let collect = seen.clone();
+    db.set_eval_limits(
+        EvalLimits::default(),
+        Some(Box::new(move |p: &EvalProgress| {
+            collect.lock().unwrap().push(p.clone())
+        })),
+    );
+    assert_eq!(db.run_default(reach).unwrap().rows.len(), 10);
+    let deltas = seen
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|p| (p.iteration, p.delta_rows))
+        .collect_vec();
+    // the entry rule copies the rows of `reach` one iteration later
+    assert_eq!(deltas, vec![(0, 4), (1, 7), (2, 5), (3, 3), (4, 1), (5, 0)]);
+    assert_eq!(seen.lock().unwrap().last().unwrap().total_rows, 20);
+
+    let limits = EvalLimits {
+        max_iterations: Some(3),
+        max_derived_rows: None,
+    };
+    db.set_eval_limits(limits, None);
+    assert_eq!(db.eval_limits(), limits);
+    let err = db.run_default(reach).unwrap_err();
+    assert_eq!(
+        err.code().as_deref(),
+        Some("eval::iteration_limit_exceeded")
+    );
+    // rules that are not recursive take a single iteration
+    db.set_eval_limits(
+        EvalLimits {
+            max_iterations: Some(1),
+            max_derived_rows: None,
+        },
+        None,
+    );
+    assert_eq!(
+        db.run_default("?[a, b] := *edge{fr: a, to: b}")
+            .unwrap()
+            .rows
+            .len(),
+        4
+    );
+
+    db.set_eval_limits(
+        EvalLimits {
+            max_iterations: None,
+            max_derived_rows: Some(12),
+        },
+        None,
+    );
+    let err = db.run_default(reach).unwrap_err();
+    assert_eq!(
+        err.code().as_deref(),
+        Some("eval::derived_rows_limit_exceeded")
+    );
+    db.set_eval_limits(EvalLimits::default(), None);
+    assert_eq!(db.run_default(reach).unwrap().rows.len(), 10);
+}
+
+#[test]
+fn query_hints() {
+    let db = DbInstance::default();
+    db.run_default(":create friends {fr: Int, to: Int => data: Any}")
+        .unwrap();
+    db.run_default("::index create friends:rev {to, fr}")
+        .unwrap();
+    db.run_default(
+        "?[fr, to, data] <- [[1, 2, 'a'], [2, 3, 'b'], [3, 2, 'c']] :put friends {fr, to => data}",
+    )
+    .unwrap();
+    let refs = |query: &str| {
+        let explained = db.run_default(&format!("::explain {{ {query} }}")).unwrap();
+        let col = |name: &str| explained.headers.iter().position(|h| h == name).unwrap();
+        let (rule, ref_name) = (col("rule"), col("ref"));
+        explained
+            .rows
+            .iter()
+            .map(|row| {
+                (
+                    row[rule].get_str().unwrap().to_string(),
+                    row[ref_name].get_str().unwrap_or_default().to_string(),
+                )
+            })
+            // stored relations, not the temporary ones of rules
+            .filter(|(_, r)| r.starts_with(':'))
+            .collect_vec()
+    };
+    let rows = |query: &str| db.run_default(query).unwrap().rows;
+
+    let by_to = "?[fr, data] := *friends{to: 2, fr, data}";
+    assert!(refs(by_to).iter().any(|(_, r)| r == ":friends:rev"));
+    let scanned = format!("{by_to} @hint(no_index)");
+    assert!(refs(&scanned).iter().all(|(_, r)| r == ":friends"));
+    assert_eq!(rows(&scanned), rows(by_to));
+    let by_fr = "?[to] := *friends{fr: 1, to}";
+    assert!(refs(by_fr).iter().all(|(_, r)| r == ":friends"));
+    let forced = format!("{by_fr} :hint index(friends:rev)");
+    assert!(refs(&forced).iter().any(|(_, r)| r == ":friends:rev"));
+    assert_eq!(rows(&forced), rows(by_fr));
+
+    // filters are not moved before the atoms binding their variables
+    let filter_first = "?[fr] := fr > 1, *friends{fr}";
+    assert_eq!(rows(filter_first).len(), 2);
+    let err = db
+        .run_default(&format!("{filter_first} @hint(fixed_order)"))
+        .unwrap_err();
+    assert_eq!(err.code().as_deref(), Some("eval::unbound_variable"));
+
+    let reach = r#"
+        reach[a, b] := *friends{fr: a, to: b} HINT
+        reach[a, c] := reach[a, b], *friends{fr: b, to: c}
+        ?[b] := reach[1, b]
+    "#;
+    let rules = |query: &str| {
+        refs(query)
+            .into_iter()
+            .map(|(r, _)| r)
+            .collect::<std::collections::BTreeSet<_>>()
+    };
+    assert!(rules(&reach.replace("HINT", ""))
+        .iter()
+        .any(|r| r.starts_with("reach|")));
+    let unrewritten = reach.replace("HINT", "@hint(no_magic)");
+    assert!(rules(&unrewritten).iter().all(|r| !r.contains('|')));
+    assert_eq!(rows(&unrewritten), rows(&reach.replace("HINT", "")));
+
+    let err = db
+        .run_default(&format!("{by_fr} :hint index(friends:nope)"))
+        .unwrap_err();
+    assert_eq!(err.code().as_deref(), Some("tx::idx_not_found"));
+    let err = db
+        .run_default(&format!("{by_fr} @hint(no_index, index(friends))"))
+        .unwrap_err();
+    assert_eq!(err.code().as_deref(), Some("parser::bad_index_hint"));
+}
+
+#[test]
+fn semi_join_filters_materialized_side() {
+    let db = DbInstance::default();
+    db.run_default(":create big {k: Int => v: Int}").unwrap();
+    db.run_default("?[k, v] := k in int_range(1000), v = k % 100 :put big {k => v}")
+        .unwrap();
+    let query = "?[a, k] := a in [3, 5, 200], *big{k, v: a}";
+    let explained = db.run_default(&format!("::explain {{ {query} }}")).unwrap();
+    let op = explained.headers.iter().position(|h| h == "op").unwrap();
+    assert!(explained
+        .rows
+        .iter()
+        .any(|row| row[op] == DataValue::from("stored_mat_join")));
+    let rows = db.run_default(query).unwrap().rows;
+    assert_eq!(rows.len(), 20);
+    assert!(rows
+        .iter()
+        .all(|row| row[1].get_int().unwrap() % 100 == row[0].get_int().unwrap()));
+    // the left side is too large to filter by, and is joined as before
+    let query = "?[count(k)] := a in int_range(70000), *big{k, v: a}";
+    assert_eq!(
+        db.run_default(query).unwrap().rows,
+        vec![vec![DataValue::from(1000)]]
+    );
+}
+
+#[test]
+fn analyze_histograms() {
+    let db = DbInstance::default();
+    db.run_default(":create events {id: Int => at: Float, kind: String}")
+        .unwrap();
+    db.run_default("::index create events:by_at {at}").unwrap();
+    // nine tenths of the events happen in the last tenth of the time
+    db.run_default(
+        r#"?[id, at, kind] := id in int_range(1000),
+            at = if(id < 100, id * 9.0, 900.0 + (id - 100) / 9.0),
+            kind = 'k'
+           :put events {id => at, kind}"#,
+    )
+    .unwrap();
+    assert_eq!(db.relation_analysis("events"), None);
+    let analyzed = db.analyze().unwrap();
+    assert_eq!(analyzed.len(), 1);
+    let analysis = db.relation_analysis("events").unwrap();
+    assert_eq!(analysis.rows, 1000);
+    assert_eq!(analysis.histograms.keys().collect_vec(), vec!["at", "id"]);
+    let at = &analysis.histograms["at"];
+    assert_eq!(at.distinct, 1000);
+    let late = at.range_selectivity(Some(&DataValue::from(900.0)), None);
+    assert!((late - 0.9).abs() < 0.04, "{late}");
+    let early = at.range_selectivity(None, Some(&DataValue::from(450.0)));
+    assert!((early - 0.05).abs() < 0.04, "{early}");
+}
+
+#[test]
+fn schema_cache_invalidation() {
+    let db = DbInstance::default();
+    let DbInstance::Mem(mem) = &db else {
+        unreachable!()
+    };
+    db.run_default(":create people {name: String => age: Int}")
+        .unwrap();
+    db.run_default("?[name, age] <- [['a', 30], ['b', 40]] :put people {name => age}")
+        .unwrap();
+    let by_age = "?[name] := *people{name, age: 40}";
+    let rows = db.run_default(by_age).unwrap().rows;
+    assert_eq!(rows, vec![vec![DataValue::from("b")]]);
+    assert_eq!(mem.schema_cache.len(), 1);
+
+    // the new index is seen by the next query
+    db.run_default("::index create people:by_age {age}")
+        .unwrap();
+    assert_eq!(mem.schema_cache.len(), 0);
+    let explained = db
+        .run_default(&format!("::explain {{ {by_age} }}"))
+        .unwrap();
+    let ref_col = explained.headers.iter().position(|h| h == "ref").unwrap();
+    assert!(explained
+        .rows
+        .iter()
+        .any(|row| row[ref_col] == DataValue::from(":people:by_age")));
+
+    db.run_default("::index drop people:by_age").unwrap();
+    db.run_default("::remove people").unwrap();
+    assert!(db.run_default(by_age).is_err());
+    db.run_default(":create people {name: String => height: Float}")
+        .unwrap();
+    db.run_default("?[name, height] <- [['c', 1.8]] :put people {name => height}")
+        .unwrap();
+    let rows = db
+        .run_default("?[name, height] := *people{name, height}")
+        .unwrap()
+        .rows;
+    assert_eq!(rows, vec![vec![DataValue::from("c"), DataValue::from(1.8)]]);
+    assert!(db.run_default(by_age).is_err());
+}
+
+#[test]
+fn warm_up_reads_relations() {
+    let db = DbInstance::default();
+    db.run_default(":create a {k: Int => v: Int}").unwrap();
+    db.run_default(":create b {k: Int}").unwrap();
+    db.run_default("::index create a:by_v {v}").unwrap();
+    db.run_default("?[k, v] := k in int_range(10), v = k * 2 :put a {k => v}")
+        .unwrap();
+    db.run_default("?[k] := k in int_range(5) :put b {k}")
+        .unwrap();
+    assert_eq!(db.warm_up(&["a"]).unwrap(), 20);
+    assert_eq!(db.warm_up(&[]).unwrap(), 25);
+    assert!(db.warm_up(&["c"]).is_err());
+}
+
+#[test]
+fn estimate_count_without_size_estimates() {
+    let db = DbInstance::default();
+    db.run_default(":create nums {n: Int}").unwrap();
+    db.run_default("?[n] := n in int_range(100) :put nums {n}")
+        .unwrap();
+    assert_eq!(db.estimate_count("nums").unwrap(), 100);
+    db.analyze().unwrap();
+    db.run_default("?[n] := n in int_range(100, 150) :put nums {n}")
+        .unwrap();
+    // the in-memory storage cannot estimate sizes, so the rows are counted
+    assert_eq!(db.estimate_count("nums").unwrap(), 150);
+    assert!(db.estimate_count("nope").is_err());
+}
+
+#[test]
+fn background_jobs() {
+    use crate::data::tuple::{Tuple, TupleT};
+    use crate::storage::{Storage, StoreTx};
+    use crate::{JobOptions, JobState, MaintenanceJob};
+
+    let db = DbInstance::default();
+    let DbInstance::Mem(mem) = &db else {
+        unreachable!()
+    };
+    db.run_default(":create a {k: Int => v: Int}").unwrap();
+    db.run_default("::index create a:by_v {v}").unwrap();
+    db.run_default("?[k, v] := k in int_range(100), v = k * 2 :put a {k => v}")
+        .unwrap();
+    let count = |script: &str| {
+        db.run_default(script).unwrap().rows[0][0]
+            .get_int()
+            .unwrap()
+    };
+    let wait = |id: u64| loop {
+        let status = db.job_status(id).unwrap();
+        if status.state != JobState::Running {
+            return status;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    };
+    let options = JobOptions {
+        batch_size: 10,
+        interval: Duration::ZERO,
+    };
+
+    let retract = MaintenanceJob::Retract {
+        relation: "a".to_string(),
+        condition: "v >= 100".to_string(),
+    };
+    let status = wait(db.start_job(retract, options).unwrap());
+    assert_eq!(status.state, JobState::Done);
+    assert_eq!((status.batches, status.rows), (6, 50));
+    assert_eq!(count("?[count(k)] := *a{k}"), 50);
+    assert_eq!(count("?[count(k)] := *a:by_v{k}"), 50);
+
+    // drop the entries of the index and add one of a row that does not exist
+    let idx = mem
+        .transact()
+        .unwrap()
+        .get_relation("a:by_v", false)
+        .unwrap();
+    let mut tx = mem.db.transact(true).unwrap();
+    let keys = tx
+        .range_scan(
+            &Tuple::default().encode_as_key(idx.id),
+            &Tuple::default().encode_as_key(idx.id.next()),
+        )
+        .map(|kv| kv.unwrap().0)
+        .collect_vec();
+    for key in keys {
+        tx.del(&key).unwrap();
+    }
+    let stale = vec![DataValue::from(999), DataValue::from(999)];
+    tx.put(&stale.encode_as_key(idx.id), &[]).unwrap();
+    tx.commit().unwrap();
+    drop(tx);
+    let reindex = MaintenanceJob::Reindex {
+        relation: "a".to_string(),
+        index: "by_v".to_string(),
+    };
+    let status = wait(db.start_job(reindex, options).unwrap());
+    assert_eq!(status.state, JobState::Done);
+    // the rows, then the entries with the stale one
+    assert_eq!(status.rows, 101);
+    assert_eq!(count("?[count(k)] := *a:by_v{k}"), 50);
+    assert_eq!(count("?[count(k)] := *a:by_v{k}, k >= 50"), 0);
+
+    let slow = JobOptions {
+        batch_size: 1,
+        interval: Duration::from_secs(3600),
+    };
+    let all = MaintenanceJob::Retract {
+        relation: "a".to_string(),
+        condition: "true".to_string(),
+    };
+    let id = db.start_job(all, slow).unwrap();
+    assert!(!db.resume_job(id));
+    assert!(db.pause_job(id));
+    assert_eq!(db.job_status(id).unwrap().state, JobState::Paused);
+    assert!(db.resume_job(id));
+    assert!(db.cancel_job(id));
+    assert!(!db.pause_job(id));
+    assert_eq!(db.job_status(id).unwrap().state, JobState::Cancelled);
+    assert!(count("?[count(k)] := *a{k}") >= 49);
+
+    let bad = MaintenanceJob::Retract {
+        relation: "a".to_string(),
+        condition: "w > 1".to_string(),
+    };
+    let status = wait(db.start_job(bad, options).unwrap());
+    assert!(matches!(status.state, JobState::Failed(_)));
+    assert_eq!(db.list_jobs().len(), 4);
+    assert!(db.job_status(5).is_none());
+
+    let missing = MaintenanceJob::Retract {
+        relation: "nope".to_string(),
+        condition: "true".to_string(),
+    };
+    assert!(db.start_job(missing, options).is_err());
+    let missing = MaintenanceJob::Reindex {
+        relation: "a".to_string(),
+        index: "nope".to_string(),
+    };
+    let err = db.start_job(missing, options).unwrap_err();
+    assert_eq!(err.code().as_deref(), Some("tx::idx_not_found"));
+}
+
+#[test]
+fn registered_constraints() {
+    use crate::CozoError;
+
+    let db = DbInstance::default();
+    db.run_default(":create customers {id: Int}").unwrap();
+    db.run_default(":create orders {id: Int => customer: Int}")
+        .unwrap();
+    db.run_default("?[id] <- [[1]] :put customers {id}")
+        .unwrap();
+    let dangling = "?[id, customer] := *orders{id, customer}, not *customers{id: customer}";
+    db.register_constraint("orders_have_customers", dangling)
+        .unwrap();
+    assert!(db
+        .register_constraint("orders_have_customers", dangling)
+        .is_err());
+    let err = db
+        .register_constraint("writes", "?[id] <- [[2]] :put customers {id}")
+        .unwrap_err();
+    assert_eq!(err.code().as_deref(), Some("db::invalid_constraint"));
+
+    db.run_default("?[id, customer] <- [[1, 1]] :put orders {id => customer}")
+        .unwrap();
+    let err = db
+        .run_default("?[id, customer] <- [[2, 5], [3, 1]] :put orders {id => customer}")
+        .unwrap_err();
+    assert!(matches!(err, CozoError::ConstraintViolation(_)));
+    assert_eq!(err.code().as_deref(), Some("transact::constraint_violated"));
+    assert!(err.to_string().contains("1 rows: [2, 5]"), "{err}");
+    let count = |script: &str| db.run_default(script).unwrap().rows.len();
+    assert_eq!(count("?[id] := *orders{id}"), 1);
+
+    // the writes of the whole transaction are checked together
+    db.run_default(
+        "{?[id] <- [[5]] :put customers {id}} \
+        {?[id, customer] <- [[2, 5]] :put orders {id => customer}}",
+    )
+    .unwrap();
+    assert!(db.run_default("?[id] <- [[5]] :rm customers {id}").is_err());
+    let tx = db.multi_transaction(true);
+    tx.run_script("?[id] <- [[5]] :rm customers {id}", Default::default())
+        .unwrap();
+    tx.run_script("?[id] <- [[2]] :rm orders {id}", Default::default())
+        .unwrap();
+    tx.commit().unwrap();
+    assert_eq!(count("?[id] := *customers{id}"), 1);
+
+    // imports are checked when they commit like scripts are
+    let orders = NamedRows::new(
+        vec!["id".to_string(), "customer".to_string()],
+        vec![vec![DataValue::from(7), DataValue::from(9)]],
+    );
+    let err = db
+        .import_relations(BTreeMap::from([("orders".to_string(), orders)]))
+        .unwrap_err();
+    assert!(matches!(err, CozoError::ConstraintViolation(_)));
+    assert_eq!(count("?[id] := *orders{id}"), 1);
+    db.run_default("?[id, customer] <- [[8, 1]] :put orders {id => customer}")
+        .unwrap();
+
+    let err = db
+        .register_constraint("few_orders", "?[id] := *orders{id}")
+        .unwrap_err();
+    assert_eq!(err.code().as_deref(), Some("transact::constraint_violated"));
+    assert!(db.unregister_constraint("orders_have_customers"));
+    assert!(!db.unregister_constraint("orders_have_customers"));
+    db.run_default("?[id] <- [[1]] :rm customers {id}").unwrap();
+}
+
+#[test]
+fn speculative_transactions() {
+    let db = DbInstance::default();
+    db.run_default(":create stock {item: String => count: Int}")
+        .unwrap();
+    db.run_default("?[item, count] <- [['apple', 3], ['pear', 1]] :put stock {item => count}")
+        .unwrap();
+    let (sender, receiver) = db.register_callback("stock", None);
+    let preview = db
+        .with_tx_speculative(
+            "?[item, count] := *stock{item, count: c}, count = c - $n :put stock {item => count}",
+            BTreeMap::from([("n".to_string(), DataValue::from(2))]),
+            &[
+                "?[item] := *stock{item, count}, count < 0",
+                "?[sum(count)] := *stock{count}",
+            ],
+        )
+        .unwrap();
+    assert_eq!(preview.rows_written, 2);
+    assert_eq!(
+        preview.validations[0].rows,
+        vec![vec![DataValue::from("pear")]]
+    );
+    assert_eq!(preview.validations[1].rows, vec![vec![DataValue::from(0.)]]);
+    let rows = db
+        .run_default("?[sum(count)] := *stock{count}")
+        .unwrap()
+        .rows;
+    assert_eq!(rows, vec![vec![DataValue::from(4.)]]);
+    assert!(receiver.try_recv().is_err());
+    db.unregister_callback(sender);
+
+    db.register_constraint("non_negative", "?[item] := *stock{item, count}, count < 0")
+        .unwrap();
+    let err = db
+        .with_tx_speculative(
+            "?[item, count] <- [['fig', -1]] :put stock {item => count}",
+            Default::default(),
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(err.code().as_deref(), Some("transact::constraint_violated"));
+    assert!(db
+        .with_tx_speculative("?[x] := x = ", Default::default(), &[])
+        .is_err());
+    // the write locks are released
+    db.run_default("?[item, count] <- [['fig', 1]] :put stock {item => count}")
+        .unwrap();
+}
+
+#[test]
+fn batched_ingestion() {
+    use crate::BatchOptions;
+
+    let db = DbInstance::default();
+    db.run_default(":create events {id: Int => v: String}")
+        .unwrap();
+    let row = |i: i64| vec![DataValue::from(i), DataValue::from(format!("e{i}"))];
+    let count = || db.run_default("?[count(id)] := *events{id}").unwrap().rows[0][0].clone();
+
+    let options = BatchOptions {
+        max_rows: 4,
+        max_delay: Duration::from_secs(3600),
+    };
+    let mut batcher = db.batcher("events", options).unwrap();
+    let reports = batcher.extend((0..10).map(row)).unwrap();
+    assert_eq!(
+        reports.iter().map(|r| (r.batch, r.rows)).collect_vec(),
+        vec![(1, 4), (2, 4), (3, 2)]
+    );
+    assert_eq!(count(), DataValue::from(10));
+    assert!(batcher.push(vec![DataValue::from(1)]).is_err());
+    assert!(batcher.push(row(10)).unwrap().is_none());
+    assert_eq!(batcher.pending(), 1);
+    assert_eq!(batcher.flush().unwrap().unwrap().rows, 1);
+    assert!(batcher.flush().unwrap().is_none());
+
+    let options = BatchOptions {
+        max_rows: 100,
+        max_delay: Duration::from_millis(20),
+    };
+    let mut batcher = db.batcher("events", options).unwrap();
+    let (sender, receiver) = crossbeam::channel::unbounded();
+    let producer = std::thread::spawn(move || {
+        sender.send(row(20)).unwrap();
+        sender.send(row(21)).unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        sender.send(row(22)).unwrap();
+    });
+    let reports = batcher.consume(receiver).unwrap();
+    producer.join().unwrap();
+    // the first two rows are committed once they have waited long enough
+    assert_eq!(reports.iter().map(|r| r.rows).collect_vec(), vec![2, 1]);
+    assert_eq!(count(), DataValue::from(14));
+    assert!(db.batcher("nope", options).is_err());
+}
+
+#[test]
+fn idempotent_writes() {
+    let db = DbInstance::default();
+    db.run_default(":create counter {k: Int => n: Int}")
+        .unwrap();
+    db.run_default("?[k, n] <- [[0, 0]] :put counter {k => n}")
+        .unwrap();
+    let bump = "?[k, n] := *counter{k, n: m}, n = m + $by :returning :put counter {k => n}";
+    let params = || BTreeMap::from([("by".to_string(), DataValue::from(1))]);
+    let first = db.run_script_idempotent("msg-1", bump, params()).unwrap();
+    let again = db.run_script_idempotent("msg-1", bump, params()).unwrap();
+    assert_eq!(first, again);
+    db.run_script_idempotent("msg-2", bump, params()).unwrap();
+    let n = || db.run_default("?[n] := *counter{n}").unwrap().rows[0][0].clone();
+    assert_eq!(n(), DataValue::from(2));
+
+    // a failed write records nothing
+    assert!(db
+        .run_script_idempotent(
+            "msg-3",
+            "?[k, n] <- [[0, 'x']] :put counter {k => n}",
+            params()
+        )
+        .is_err());
+    db.run_script_idempotent("msg-3", bump, params()).unwrap();
+    assert_eq!(n(), DataValue::from(3));
+
+    assert_eq!(db.forget_idempotency_keys(0.).unwrap(), 0);
+    assert_eq!(db.forget_idempotency_keys(f64::MAX).unwrap(), 3);
+    db.run_script_idempotent("msg-1", bump, params()).unwrap();
+    assert_eq!(n(), DataValue::from(4));
+}
+
+#[test]
+fn frozen_writes() {
+    use crate::CozoError;
+
+    let db = DbInstance::default();
+    db.run_default(":create a {k: Int}").unwrap();
+    db.freeze_writes(Duration::from_secs(1)).unwrap();
+    let err = db.run_default("?[k] <- [[1]] :put a {k}").unwrap_err();
+    assert_eq!(err.code().as_deref(), Some("db::writes_frozen"));
+    assert!(matches!(err, CozoError::Conflict(_)));
+    db.run_default("?[k] := *a{k}").unwrap();
+    assert!(db.unfreeze_writes());
+    assert!(!db.unfreeze_writes());
+    db.run_default("?[k] <- [[1]] :put a {k}").unwrap();
+
+    let tx = db.multi_transaction(true);
+    tx.run_script("?[k] <- [[2]] :put a {k}", Default::default())
+        .unwrap();
+    let err = db.freeze_writes(Duration::from_millis(10)).unwrap_err();
+    assert_eq!(err.code().as_deref(), Some("db::freeze_timeout"));
+    assert!(!db.unfreeze_writes());
+    // freezing waits for the write transactions to finish
+    let committer = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        tx.commit().unwrap();
+    });
+    db.freeze_writes(Duration::from_secs(10)).unwrap();
+    committer.join().unwrap();
+    assert_eq!(db.run_default("?[k] := *a{k}").unwrap().rows.len(), 2);
+    assert!(db.unfreeze_writes());
+}
+
+#[test]
+fn schema_diff() {
+    use crate::SchemaDiff;
+
+    let db = DbInstance::default();
+    db.run_default(":create kept {k: Int}").unwrap();
+    db.enable_tx_log().unwrap();
+    let last_seq = || {
+        db.tx_log_chunk(0, usize::MAX)
+            .unwrap()
+            .last_seq()
+            .unwrap_or(0)
+    };
+    let names = |names: &[&str]| names.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+    db.run_default(":create a {k: Int => v: Int}").unwrap();
+    db.run_default(":create b {k: Int}").unwrap();
+    let first = last_seq();
+    db.run_default("::remove b").unwrap();
+    db.run_default(":create c {k: Int}").unwrap();
+    db.run_default("::index create a:by_v {v}").unwrap();
+    db.run_default("?[k, v] <- [[1, 2]] :put a {k => v}")
+        .unwrap();
+    let second = last_seq();
+    db.run_default("::remove c").unwrap();
+    db.run_default(":create c {k: String}").unwrap();
+    let third = last_seq();
+
+    assert_eq!(
+        db.schema_diff(0, first).unwrap(),
+        SchemaDiff {
+            added: names(&["a", "b"]),
+            ..Default::default()
+        }
+    );
+    assert_eq!(
+        db.schema_diff(first, second).unwrap(),
+        SchemaDiff {
+            added: names(&["a:by_v", "c"]),
+            removed: names(&["b"]),
+            altered: names(&["a"]),
+            ..Default::default()
+        }
+    );
+    assert_eq!(
+        db.schema_diff(second, first).unwrap(),
+        SchemaDiff {
+            added: names(&["b"]),
+            removed: names(&["a:by_v", "c"]),
+            altered: names(&["a"]),
+            ..Default::default()
+        }
+    );
+    assert_eq!(
+        db.schema_diff(second, third).unwrap(),
+        SchemaDiff {
+            redefined: names(&["c"]),
+            ..Default::default()
+        }
+    );
+    assert!(db.schema_diff(third, u64::MAX).unwrap().is_empty());
+    assert!(!db
+        .schema_diff(0, third)
+        .unwrap()
+        .removed
+        .contains(&"kept".to_string()));
+
+    db.truncate_tx_log(1).unwrap();
+    let err = db.schema_diff(0, third).unwrap_err();
+    assert_eq!(err.code().as_deref(), Some("db::history_truncated"));
+}
+
+#[test]
+fn data_diff() {
+    use crate::DiffFilter;
+
+    let db = DbInstance::default();
+    db.enable_tx_log().unwrap();
+    let last_seq = || {
+        db.tx_log_chunk(0, usize::MAX)
+            .unwrap()
+            .last_seq()
+            .unwrap_or(0)
+    };
+    db.run_default(":create a {k: Int => v: Int}").unwrap();
+    db.run_default(":create gone {k: Int}").unwrap();
+    db.run_default("?[k, v] <- [[1, 1], [2, 2], [3, 3]] :put a {k => v}")
+        .unwrap();
+    db.run_default("?[k] <- [[1], [2]] :put gone {k}").unwrap();
+    let first = last_seq();
+    db.run_default("?[k, v] <- [[1, 10], [4, 4]] :put a {k => v}")
+        .unwrap();
+    db.run_default("?[k] <- [[2]] :rm a {k}").unwrap();
+    db.run_default("?[k, v] <- [[3, 30]] :put a {k => v}")
+        .unwrap();
+    db.run_default("?[k, v] <- [[3, 3]] :put a {k => v}")
+        .unwrap();
+    db.run_default("::remove gone").unwrap();
+    let second = last_seq();
+
+    let all = DiffFilter::default();
+    let summary = |from, to, filter: &DiffFilter| {
+        db.diff(from, to, filter)
+            .unwrap()
+            .into_iter()
+            .map(|row| {
+                let show = |row: Option<Vec<DataValue>>| row.map(|row| row[row.len() - 1].clone());
+                (
+                    row.relation,
+                    row.key[0].clone(),
+                    show(row.before),
+                    show(row.after),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    let int = |i: i64| Some(DataValue::from(i));
+    let a = |k: i64, before, after| ("a".to_string(), DataValue::from(k), before, after);
+    let gone = |k: i64| ("gone".to_string(), DataValue::from(k), int(k), None);
+    // the row put back to its old value does not differ
+    assert_eq!(
+        summary(first, second, &all),
+        vec![
+            a(1, int(1), int(10)),
+            a(2, int(2), None),
+            a(4, None, int(4)),
+            gone(1),
+            gone(2),
+        ]
+    );
+    assert_eq!(summary(second, first, &all)[0], a(1, int(10), int(1)));
+    assert_eq!(summary(0, first, &all).len(), 5);
+    assert!(summary(second, second, &all).is_empty());
+
+    let only_a = DiffFilter {
+        relations: ["a".to_string()].into(),
+        key_prefix: vec![DataValue::from(4)],
+    };
+    assert_eq!(summary(first, second, &only_a), vec![a(4, None, int(4))]);
+
+    // the rows before the diffed transactions are read from the log too
+    db.truncate_tx_log(1).unwrap();
+    let err = db.diff(first, second, &all).unwrap_err();
+    assert_eq!(err.code().as_deref(), Some("db::history_truncated"));
+}
+
+#[test]
+fn result_checksums() {
+    let db = DbInstance::default();
+    db.run_default(":create a {k: Int => v: String}").unwrap();
+    db.run_default("?[k, v] <- [[1, 'x'], [2, 'y'], [3, 'z']] :put a {k => v}")
+        .unwrap();
+    let checksum = |script: &str| {
+        let res = db.run_default(script).unwrap();
+        assert_eq!(res.headers, vec!["checksum", "rows"]);
+        (res.rows[0][0].clone(), res.rows[0][1].clone())
+    };
+    let (stored, rows) = checksum("?[k, v] := *a{k, v} :checksum");
+    assert_eq!(rows, DataValue::from(3));
+    // the checksum does not depend on the order of the rows
+    assert_eq!(
+        checksum("?[k, v] <- [[3, 'z'], [1, 'x'], [2, 'y']] :order -k :checksum").0,
+        stored
+    );
+    assert_ne!(
+        checksum("?[k, v] <- [[1, 'x'], [2, 'y'], [3, 'w']] :checksum").0,
+        stored
+    );
+    assert_ne!(checksum("?[k, v] := *a{k, v} :limit 2 :checksum").0, stored);
+    assert_eq!(
+        checksum("?[k] := k = 1, k = 2 :checksum").1,
+        DataValue::from(0)
+    );
+
+    for script in [
+        "?[k, v] <- [[4, 'w']] :put a {k => v} :checksum",
+        "?[k] <- [[1]] :checksum :rm a {k}",
+    ] {
+        let err = db.run_default(script).unwrap_err();
+        assert_eq!(
+            err.code().as_deref(),
+            Some("parser::checksum_with_mutation")
+        );
+    }
+    // nothing was written
+    assert_eq!(checksum("?[k, v] := *a{k, v} :checksum").0, stored);
+}
+
+#[test]
+fn read_snapshots() {
+    let db = DbInstance::default();
+    db.enable_tx_log().unwrap();
+    db.run_default(":create a {k: Int}").unwrap();
+    db.run_default("?[k] <- [[1], [2]] :put a {k}").unwrap();
+    let last_seq = || db.tx_log_chunk(0, usize::MAX).unwrap().last_seq().unwrap();
+    let seq = last_seq();
+
+    let mut snapshot = db.snapshot().unwrap();
+    assert_eq!(snapshot.tx_id(), seq);
+    let count = "?[count(k)] := *a{k}";
+    for _ in 0..2 {
+        let res = snapshot.run_script(count, Default::default()).unwrap();
+        assert_eq!(res.rows, vec![vec![DataValue::from(2)]]);
+    }
+    let res = snapshot
+        .run_script(
+            "?[x] := x = $x",
+            BTreeMap::from([("x".to_string(), DataValue::from(7))]),
+        )
+        .unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(7)]]);
+    let err = snapshot
+        .run_script("?[k] <- [[3]] :put a {k}", Default::default())
+        .unwrap_err();
+    assert_eq!(
+        err.code().map(|c| c.to_string()).as_deref(),
+        Some("tx::read_only")
+    );
+    assert!(snapshot
+        .run_script("::relations", Default::default())
+        .is_err());
+    drop(snapshot);
+
+    db.run_default("?[k] <- [[3]] :put a {k}").unwrap();
+    let mut snapshot = db.snapshot().unwrap();
+    assert_eq!(snapshot.tx_id(), seq + 1);
+    let res = snapshot.run_script(count, Default::default()).unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(3)]]);
+}
+
+#[test]
+fn sessions_across_threads() {
+    use crate::Principal;
+    use std::collections::BTreeSet;
+
+    let db = DbInstance::default();
+    db.run_default(":create a {k: Int}").unwrap();
+    db.run_default("?[k] <- [[1], [2]] :put a {k}").unwrap();
+    let committed = db.metrics().transactions_committed;
+
+    let (sender, receiver) = crossbeam::channel::bounded(0);
+    let local = db.multi_transaction(false);
+    local
+        .run_script("?[k] := *a{k}", Default::default())
+        .unwrap();
+    std::thread::scope(|s| {
+        for _ in 0..8 {
+            s.spawn(|| {
+                for _ in 0..50 {
+                    assert_eq!(db.run_default("?[k] := *a{k}").unwrap().rows.len(), 2);
+                }
+            });
+        }
+        s.spawn(|| {
+            let tx = db.multi_transaction_as(&Principal::new("other"), false);
+            tx.run_script("?[k] := *a{k}", Default::default()).unwrap();
+            sender.send(()).unwrap();
+            sender.send(()).unwrap();
+            assert!(tx.run_script("?[k] := *a{k}", Default::default()).is_err());
+        });
+        receiver.recv().unwrap();
+        // sessions opened on other threads are listed and killed like the local ones
+        let sessions = db.list_sessions();
+        let ids = sessions.iter().map(|s| s.id).collect::<BTreeSet<_>>();
+        assert_eq!(ids.len(), sessions.len());
+        let other = sessions
+            .iter()
+            .find(|s| s.principal.as_deref() == Some("other"))
+            .unwrap();
+        assert!(db.kill_session(other.id));
+        receiver.recv().unwrap();
+    });
+    assert!(local
+        .run_script("?[k] := *a{k}", Default::default())
+        .is_ok());
+    drop(local);
+    assert_eq!(db.metrics().transactions_committed, committed + 400);
+}
+
+#[test]
+fn bench_dataset() {
+    let db = DbInstance::default();
+    db.bench_dataset(1000).unwrap();
+    let res = db
+        .run_default("?[count(id), max(id)] := *bench_node{id}")
+        .unwrap();
+    assert_eq!(
+        res.rows,
+        vec![vec![DataValue::from(1000), DataValue::from(999)]]
+    );
+    let res = db
+        .run_default(
+            r#"
+            reach[d] := d = 0
+            reach[d] := reach[s], *bench_edge{src: s, dst: d}
+            ?[count(d)] := reach[d]
+            "#,
+        )
+        .unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(1000)]]);
+    assert!(db.bench_dataset(10).is_err());
+
+    let other = DbInstance::default();
+    other.bench_dataset(1000).unwrap();
+    let query = "?[id, name, group, score] := *bench_node{id, name, group, score}";
+    assert_eq!(
+        db.run_default(query).unwrap().rows,
+        other.run_default(query).unwrap().rows
+    );
+}
+
+#[test]
+fn chunked_imports() {
+    use crate::storage::{Storage, StoreTx};
+    use crate::{TxGroupState, TxOp};
+
+    let db = DbInstance::default();
+    let DbInstance::Mem(mem) = &db else {
+        unreachable!()
+    };
+    db.run_default(":create a {k: Int => v: Int}").unwrap();
+    db.run_default("::index create a:by_v {v}").unwrap();
+    db.run_default("?[k, v] <- [[0, 100]] :put a {k => v}")
+        .unwrap();
+    db.enable_tx_log().unwrap();
+    let rows = |range: std::ops::Range<i64>, v: fn(i64) -> DataValue| {
+        let rows = range.map(|k| vec![DataValue::from(k), v(k)]).collect_vec();
+        BTreeMap::from([(
+            "a".to_string(),
+            NamedRows::new(vec!["k".to_string(), "v".to_string()], rows),
+        )])
+    };
+    let count = |script: &str| {
+        db.run_default(script).unwrap().rows[0][0]
+            .get_int()
+            .unwrap()
+    };
+
+    assert_eq!(
+        db.import_relations_chunked(rows(0..25, |k| DataValue::from(k * 2)), 10)
+            .unwrap(),
+        3
+    );
+    assert_eq!(count("?[count(k)] := *a{k}"), 25);
+    assert_eq!(count("?[count(k)] := *a:by_v{k}"), 25);
+    let entries = db.tx_log_chunk(0, 100).unwrap().entries;
+    let group = entries[0].group.unwrap();
+    assert!(entries
+        .iter()
+        .all(|e| e.group.map(|g| g.id) == Some(group.id)));
+    assert_eq!(
+        entries.last().unwrap().group.unwrap().state,
+        TxGroupState::Committed
+    );
+    assert!(db.verify_integrity().unwrap().findings.is_empty());
+
+    // a failing chunk rolls back those committed before it
+    let mut failing = rows(20..45, |k| DataValue::from(k * 3));
+    failing.get_mut("a").unwrap().rows[22][1] = DataValue::from("not an int");
+    assert!(db.import_relations_chunked(failing, 10).is_err());
+    assert_eq!(count("?[count(k)] := *a{k}"), 25);
+    assert_eq!(count("?[count(k)] := *a{k, v}, v == k * 2"), 25);
+    assert_eq!(count("?[count(k)] := *a:by_v{k, v}, v == k * 2"), 25);
+    let last = db.tx_log_chunk(0, 100).unwrap().entries.pop().unwrap();
+    assert_eq!(last.group.unwrap().state, TxGroupState::RolledBack);
+    assert!(db.verify_integrity().unwrap().findings.is_empty());
+
+    // an import interrupted before its last chunk is rolled back at the next opening
+    let seq = db.tx_log_chunk(0, 100).unwrap().last_seq().unwrap();
+    db.run_default("?[k, v] <- [[100, 1]] :put a {k => v}")
+        .unwrap();
+    let written = db.tx_log_chunk(seq, 1).unwrap().entries[0]
+        .ops
+        .iter()
+        .filter_map(|op| match op {
+            TxOp::Put(key, _) => Some(key.clone()),
+            _ => None,
+        })
+        .collect_vec();
+    let group = 7u64.to_be_bytes();
+    let mut tx = mem.db.transact(true).unwrap();
+    tx.put(&[&[0xFC], &group[..], &[0]].concat(), &[0]).unwrap();
+    for key in &written {
+        tx.put(&[&[0xFC], &group[..], &[1], &key[..]].concat(), &[0])
+            .unwrap();
+    }
+    tx.commit().unwrap();
+    drop(tx);
+    mem.recover_chunked_imports().unwrap();
+    assert_eq!(count("?[count(k)] := *a{k}"), 25);
+    assert_eq!(count("?[count(k)] := *a:by_v{k}"), 25);
+    let tx = mem.db.transact(false).unwrap();
+    assert_eq!(tx.range_count(&[0xFC], &[0xFD]).unwrap(), 0);
+    drop(tx);
+}
+
+#[test]
+fn retract_by_query() {
+    let db = DbInstance::default();
+    db.run_default(":create eav {e: Int, a: String => v: Any}")
+        .unwrap();
+    db.run_default("::index create eav:by_v {v}").unwrap();
+    db.run_default(
+        r#"?[e, a, v] <- [[1, 'colour', 'red'], [2, 'colour', 'blue'], [3, 'size', 10],
+                          [4, 'colour', 'red'], [4, 'size', 12]]
+           :put eav {e, a => v}"#,
+    )
+    .unwrap();
+
+    // only the rows still holding the given values are removed
+    let res = db
+        .run_default(
+            r#"?[e, a, v] <- [[1, 'colour', 'red'], [2, 'colour', 'red'], [5, 'size', 1]]
+               :returning
+               :retract eav {e, a => v}"#,
+        )
+        .unwrap();
+    let removed = res
+        .rows
+        .iter()
+        .filter(|row| row[0] == DataValue::from("requested"))
+        .map(|row| row[1].clone())
+        .collect_vec();
+    assert_eq!(removed, vec![DataValue::from(1)]);
+
+    // the retracted rows are given by a query over the data, in the same transaction
+    db.run_default("?[e, a, v] := *eav{e, a, v}, e == 4, a == 'colour' :retract eav {e, a => v}")
+        .unwrap();
+    // without values, rows are retracted by key like `:rm`
+    db.run_default("?[e, a] <- [[3, 'size']] :retract eav {e, a}")
+        .unwrap();
+    let rows = db.run_default("?[e, a, v] := *eav{e, a, v}").unwrap().rows;
+    assert_eq!(
+        rows,
+        vec![
+            vec![
+                DataValue::from(2),
+                DataValue::from("colour"),
+                DataValue::from("blue")
+            ],
+            vec![
+                DataValue::from(4),
+                DataValue::from("size"),
+                DataValue::from(12)
+            ],
+        ]
+    );
+    assert_eq!(
+        db.run_default("?[count(e)] := *eav:by_v{e}").unwrap().rows[0][0],
+        DataValue::from(2)
+    );
+}
+
+#[test]
+fn derived_writes_in_transaction() {
+    let db = DbInstance::default();
+    db.run_default(
+        r#"
+        {:create customer {id: Int => name: String, total: Float default 0.}}
+        {:create order {id: Int => customer: Int, amount: Float}}
+        "#,
+    )
+    .unwrap();
+    db.run_default("?[id, name] <- [[1, 'a'], [2, 'b']] :put customer {id => name}")
+        .unwrap();
+
+    // the orders and the totals derived from them are written in one transaction,
+    // the query deriving the totals seeing the orders written before it
+    let tx = db.multi_transaction(true);
+    tx.run_script(
+        "?[id, customer, amount] <- [[1, 1, 2.5], [2, 1, 1.5], [3, 2, 4.]] \
+         :put order {id => customer, amount}",
+        Default::default(),
+    )
+    .unwrap();
+    tx.run_script(
+        r#"
+        total[id, sum(amount)] := *order{customer: id, amount}
+        ?[id, total] := total[id, total]
+        :update customer {id => total}
+        "#,
+        Default::default(),
+    )
+    .unwrap();
+    tx.commit().unwrap();
+    let totals = "?[id, total] := *customer{id, total}";
+    assert_eq!(
+        db.run_default(totals).unwrap().rows,
+        vec![
+            vec![DataValue::from(1), DataValue::from(4.)],
+            vec![DataValue::from(2), DataValue::from(4.)],
+        ]
+    );
+
+    // a failing derivation aborts the writes made before it
+    let res = db.run_default(
+        r#"
+        {?[id, customer, amount] <- [[4, 2, 1.]] :put order {id => customer, amount}}
+        {?[id, total] := *order{customer: id, amount}, total = amount / 'x'
+         :update customer {id => total}}
+        "#,
+    );
+    assert!(res.is_err());
+    assert_eq!(
+        db.run_default("?[count(id)] := *order{id}").unwrap().rows[0][0],
+        DataValue::from(3)
+    );
+}
+
+#[cfg(feature = "scheduler")]
+#[test]
+fn scheduled_tasks() {
+    use crate::ScheduledTask;
+    use std::time::{Duration, Instant};
+
+    let db = DbInstance::default();
+    db.run_default(":create counter {k: Int => n: Int}")
+        .unwrap();
+    db.run_default("?[k, n] <- [[0, 0]] :put counter {k => n}")
+        .unwrap();
+    let script = |script: &str| ScheduledTask::Script {
+        script: script.to_string(),
+        params: Default::default(),
+    };
+    let interval = Duration::from_millis(10);
+    db.schedule(
+        "count",
+        script("?[k, n] := *counter{k, n: m}, n = m + 1 :put counter {k => n}"),
+        interval,
+    )
+    .unwrap();
+    db.schedule("fail", script("?[x] := x = 1 / 'a'"), interval)
+        .unwrap();
+    let count = || {
+        db.run_default("?[n] := *counter{n}").unwrap().rows[0][0]
+            .get_int()
+            .unwrap()
+    };
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while count() < 2 || db.schedules().iter().any(|status| status.runs == 0) {
+        assert!(Instant::now() < deadline);
+        std::thread::sleep(interval);
+    }
+
+    let statuses = db.run_default("::schedules").unwrap();
+    assert_eq!(statuses.headers[0], "name");
+    let names = statuses.rows.iter().map(|row| row[0].clone()).collect_vec();
+    assert_eq!(
+        names,
+        vec![DataValue::from("count"), DataValue::from("fail")]
+    );
+    let error = statuses
+        .headers
+        .iter()
+        .position(|h| h == "last_error")
+        .unwrap();
+    assert_eq!(statuses.rows[0][error], DataValue::Null);
+    assert!(statuses.rows[1][error].get_str().is_some());
+    let failing = &db.schedules()[1];
+    assert_eq!(failing.failures, failing.runs);
+
+    assert!(db.unschedule("count"));
+    assert!(!db.unschedule("count"));
+    std::thread::sleep(Duration::from_millis(100));
+    let stopped_at = count();
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(count(), stopped_at);
+    assert_eq!(db.schedules().len(), 1);
+}
+
+#[test]
+fn rdf_import_export() {
+    use crate::RdfFormat;
+
+    let db = DbInstance::default();
+    let turtle = r#"
+        @prefix ex: <http://example.org/> .
+        @prefix xsd: <http://www.w3.org/2001/XMLSchema#> .
+        # people
+        ex:alice a ex:Person ;
+            ex:name "Alice", "Alicia"@es ;
+            ex:age 42 ;
+            ex:height "1.7"^^xsd:double ;
+            ex:knows [ ex:name "Bob" ] ;
+            ex:born "1980-01-01"^^xsd:date .
+    "#;
+    assert_eq!(
+        db.import_rdf("triples", RdfFormat::Turtle, turtle).unwrap(),
+        8
+    );
+    let ages = db
+        .run_default(
+            "?[o] := *triples{s: 'http://example.org/alice', p: 'http://example.org/age', o}",
+        )
+        .unwrap();
+    assert_eq!(ages.rows, vec![vec![DataValue::from(42)]]);
+    let dates = db
+        .run_default("?[o, datatype] := *triples{p: 'http://example.org/born', o, datatype}")
+        .unwrap();
+    assert_eq!(
+        dates.rows,
+        vec![vec![
+            DataValue::from("1980-01-01"),
+            DataValue::from("http://www.w3.org/2001/XMLSchema#date")
+        ]]
+    );
+
+    let ntriples = db
+        .export_rdf("triples", RdfFormat::NTriples, &BTreeMap::new())
+        .unwrap();
+    assert_eq!(ntriples.lines().count(), 8);
+    assert!(
+        ntriples.contains("<http://example.org/alice> <http://example.org/name> \"Alicia\"@es .\n")
+    );
+    db.import_rdf("copy", RdfFormat::NTriples, &ntriples)
+        .unwrap();
+    let query = "?[s, p, o, iri, lang, datatype] := *REL{s, p, o, iri, lang, datatype}, \
+                 not starts_with(s, '_:'), p != 'http://example.org/knows'";
+    assert_eq!(
+        db.run_default(&query.replace("REL", "triples"))
+            .unwrap()
+            .rows,
+        db.run_default(&query.replace("REL", "copy")).unwrap().rows
+    );
+    assert!(db
+        .import_rdf(
+            "copy",
+            RdfFormat::NTriples,
+            "@prefix ex: <http://example.org/> ."
+        )
+        .is_err());
+
+    let prefixes = BTreeMap::from([("ex".to_string(), "http://example.org/".to_string())]);
+    let turtle = db
+        .export_rdf("triples", RdfFormat::Turtle, &prefixes)
+        .unwrap();
+    assert!(turtle.starts_with("@prefix ex: <http://example.org/> .\n"));
+    assert!(turtle.contains("ex:alice "));
+    db.import_rdf("again", RdfFormat::Turtle, &turtle).unwrap();
+    assert_eq!(
+        db.run_default("?[count(s)] := *again{s}").unwrap().rows,
+        vec![vec![DataValue::from(8)]]
+    );
+}
+
+#[test]
+fn property_graph_import() {
+    let db = DbInstance::default();
+    let people = "personId:ID(Person),name,age:int,tags:string[],:LABEL\n\
+                  p1,Alice,42,a;b,Person;Admin\n\
+                  p2,\"Bob, Jr.\",,,Person\n";
+    let companies = "id:ID(Company),name,founded:int\nc1,Acme,1990\n";
+    let edges = ":START_ID(Person),:END_ID,:TYPE,since:int,weight:double\n\
+                 p1,p2,KNOWS,2010,0.5\n\
+                 p2,c1,WORKS_AT,,\n";
+    let report = db
+        .import_property_graph("nodes", "edges", &[people, companies], &[edges])
+        .unwrap();
+    assert_eq!(report.nodes, 3);
+    assert_eq!(report.edges, 2);
+
+    let nodes = db
+        .run_default(
+            "?[id, labels, name, age, tags, founded, personId] := \
+             *nodes{id, labels, name, age, tags, founded, personId}",
+        )
+        .unwrap();
+    assert_eq!(
+        nodes.into_json()["rows"],
+        json!([
+            ["c1", [], "Acme", null, null, 1990, null],
+            [
+                "p1",
+                ["Person", "Admin"],
+                "Alice",
+                42,
+                ["a", "b"],
+                null,
+                "p1"
+            ],
+            ["p2", ["Person"], "Bob, Jr.", null, null, null, "p2"]
+        ])
+    );
+    let edges = db
+        .run_default(
+            "?[start, type, end, since, weight] := *edges{start, type, end, since, weight}",
+        )
+        .unwrap();
+    assert_eq!(
+        edges.into_json()["rows"],
+        json!([
+            ["p1", "KNOWS", "p2", 2010, 0.5],
+            ["p2", "WORKS_AT", "c1", null, null]
+        ])
+    );
+
+    let dangling = ":START_ID,:END_ID,:TYPE\np1,p9,KNOWS\n";
+    assert!(db
+        .import_property_graph("nodes", "edges", &[], &[dangling])
+        .is_err());
+    let bad = "id:ID,age:int\np3,old\n";
+    let err = db
+        .import_property_graph("nodes", "edges", &[bad], &[])
+        .unwrap_err();
+    assert!(err.to_string().contains("line 2"));
+    assert_eq!(
+        db.run_default("?[count(id)] := *nodes{id}").unwrap().rows,
+        vec![vec![DataValue::from(3)]]
+    );
+}
+
+#[test]
+#[cfg(feature = "sql-import")]
+fn sqlite_snapshot_import() {
+    use crate::{ColumnNaming, SqlImportOptions};
+
+    let path = std::env::temp_dir().join(format!("cozo_sql_import_{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let conn = sqlite::open(&path).unwrap();
+    conn.execute(
+        "CREATE TABLE Customer (CustomerId INTEGER PRIMARY KEY, FullName TEXT NOT NULL);
+         CREATE TABLE \"Order\" (
+             OrderId INTEGER PRIMARY KEY,
+             CustomerId INTEGER REFERENCES Customer,
+             Amount REAL
+         );
+         CREATE TABLE Note (Body TEXT);
+         INSERT INTO Customer VALUES (1, 'Alice'), (2, 'Bob');
+         INSERT INTO \"Order\" VALUES (10, 1, 9.5), (11, 2, NULL);
+         INSERT INTO Note VALUES ('hello');",
+    )
+    .unwrap();
+    drop(conn);
+
+    let db = DbInstance::default();
+    let options = SqlImportOptions {
+        relation_prefix: "shop_".to_string(),
+        naming: ColumnNaming::SnakeCase,
+        batch_rows: 2,
+        ..Default::default()
+    };
+    let report = db.import_sqlite_snapshot(&path, &options).unwrap();
+    let relations = report.iter().map(|t| t.relation.as_str()).collect_vec();
+    assert_eq!(relations, vec!["shop_customer", "shop_note", "shop_order"]);
+    assert_eq!(report[2].rows, 2);
+    assert_eq!(report[2].foreign_keys[0].columns, vec!["customer_id"]);
+    assert_eq!(report[2].foreign_keys[0].relation, "shop_customer");
+    assert_eq!(report[2].foreign_keys[0].ref_columns, vec!["customer_id"]);
+
+    let res = db
+        .run_default(
+            "?[order_id, full_name, amount] := *shop_order{order_id, customer_id, amount}, \
+             *shop_customer{customer_id, full_name}",
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([[10, "Alice", 9.5], [11, "Bob", null]])
+    );
+    let notes = db
+        .run_default("?[rowid, body] := *shop_note{rowid, body}")
+        .unwrap();
+    assert_eq!(notes.into_json()["rows"], json!([[1, "hello"]]));
+
+    // the relations exist now, so a second import fails without touching them
+    assert!(db.import_sqlite_snapshot(&path, &options).is_err());
+    assert_eq!(
+        db.run_default("?[count(id)] := *shop_order{order_id: id}")
+            .unwrap()
+            .rows,
+        vec![vec![DataValue::from(2)]]
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+#[cfg(feature = "fetch")]
+fn fetch_json_fixed_rule() {
+    use std::io::{Read, Write};
+
+    use crate::FetchConfig;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            let body = r#"{"data": [{"code": "FR", "name": "France"}, {"code": "JP"}]}"#;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    });
+
+    let db = DbInstance::default();
+    db.run_default(
+        r"
+        ?[code, population] <- [['FR', 68], ['JP', 125], ['DE', 84]]
+        :create country {code => population}
+    ",
+    )
+    .unwrap();
+    let query = "countries[code, name] <~ FetchJson(url: $url, fields: ['code', 'name'], \
+                 pointer: '/data', null_if_absent: true)
+                 ?[code, name, population] := countries[code, name], *country{code, population}";
+    let params = BTreeMap::from([(
+        "url".to_string(),
+        DataValue::from(format!("{base}/countries")),
+    )]);
+    let err = db
+        .run_script(query, params.clone(), ScriptMutability::Immutable)
+        .unwrap_err();
+    assert!(err.to_string().contains("not allowed"));
+
+    db.set_fetch_config(FetchConfig {
+        allowed_prefixes: vec![base.clone()],
+        ..Default::default()
+    });
+    let res = db
+        .run_script(query, params, ScriptMutability::Immutable)
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["FR", "France", 68], ["JP", null, 125]])
+    );
+    let elsewhere = BTreeMap::from([(
+        "url".to_string(),
+        DataValue::from("http://127.0.0.2/countries"),
+    )]);
+    assert!(db
+        .run_script(query, elsewhere, ScriptMutability::Immutable)
+        .is_err());
+}
+
+#[test]
+fn stored_procedures() {
+    let db = DbInstance::default();
+    db.run_default(
+        r"
+        {?[id, balance] <- [['a', 100], ['b', 0]] :create account {id => balance}}
+        {:create transfer {from: String, to: String, amount: Int}}
+    ",
+    )
+    .unwrap();
+    let script = r"
+        {
+            ?[balance] := *account{id: $from, balance}, balance >= $amount
+            :assert some
+        }
+        {
+            ?[id, balance] := *account{id: $from, balance: old}, id = $from,
+                balance = old - $amount
+            ?[id, balance] := *account{id: $to, balance: old}, id = $to,
+                balance = old + $amount
+            :put account {id => balance}
+        }
+        {
+            ?[from, to, amount] <- [[$from, $to, $amount]]
+            :put transfer {from, to, amount}
+        }
+        {
+            ?[id, balance] := *account{id, balance}
+        }
+    ";
+    db.register_procedure("transfer", &["from", "to", "amount"], script)
+        .unwrap();
+    assert!(db
+        .register_procedure("transfer", &[], "?[a] := a = 1")
+        .is_err());
+    assert!(db
+        .register_procedure("broken", &["x"], "?[a] := a = $y")
+        .is_err());
+    assert_eq!(db.procedures()[0].params, vec!["from", "to", "amount"]);
+
+    let args = |amount: i64| {
+        BTreeMap::from([
+            ("from".to_string(), DataValue::from("a")),
+            ("to".to_string(), DataValue::from("b")),
+            ("amount".to_string(), DataValue::from(amount)),
+        ])
+    };
+    let res = db
+        .call_procedure("transfer", args(30), ScriptMutability::Mutable)
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["a", 70], ["b", 30]]));
+
+    // the failed assertion aborts the transaction with the writes before it
+    assert!(db
+        .call_procedure("transfer", args(500), ScriptMutability::Mutable)
+        .is_err());
+    let balances = db
+        .run_default("?[id, balance] := *account{id, balance}")
+        .unwrap();
+    assert_eq!(balances.into_json()["rows"], json!([["a", 70], ["b", 30]]));
+    assert_eq!(
+        db.run_default("?[count(from)] := *transfer{from}")
+            .unwrap()
+            .rows,
+        vec![vec![DataValue::from(1)]]
+    );
+
+    let mut missing = args(1);
+    missing.remove("to");
+    assert!(db
+        .call_procedure("transfer", missing, ScriptMutability::Mutable)
+        .is_err());
+    assert!(db
+        .call_procedure("transfer", args(1), ScriptMutability::Immutable)
+        .is_err());
+    assert!(db.unregister_procedure("transfer"));
+    assert!(db
+        .call_procedure("transfer", args(1), ScriptMutability::Mutable)
+        .is_err());
+}
+
+#[test]
+#[cfg(feature = "wasm-plugins")]
+fn wasm_plugin_fixed_rule() {
+    use crate::{SimpleFixedRule, WasmLimits};
+
+    // (import "cozo" "fail" (func $fail (param i32 i32)))
+    // (memory 1)
+    // (func (export "total") (param f64 f64) (result f64)
+    //   (if (f64.lt (local.get 1) (f64.const 0)) (then (call $fail (i32.const 0) (i32.const 8))))
+    //   (f64.add (local.get 0) (local.get 1)))
+    // (func (export "square") (param f64) (result f64)
+    //   (f64.mul (local.get 0) (local.get 0)))
+    // (data (i32.const 0) "negative")
+    let module = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x01, 0x11, 0x03, 0x60, 0x02, 0x7f, 0x7f, 0x00, 0x60, 0x02, 0x7c, 0x7c, 0x01, 0x7c, 0x60,
+        0x01, 0x7c, 0x01, 0x7c, // types
+        0x02, 0x0d, 0x01, 0x04, b'c', b'o', b'z', b'o', 0x04, b'f', b'a', b'i', b'l', 0x00,
+        0x00, // imports
+        0x03, 0x03, 0x02, 0x01, 0x02, // functions
+        0x05, 0x03, 0x01, 0x00, 0x01, // memory
+        0x07, 0x12, 0x02, 0x05, b't', b'o', b't', b'a', b'l', 0x00, 0x01, 0x06, b's', b'q',
+        b'u', b'a', b'r', b'e', 0x00, 0x02, // exports
+        0x0a, 0x26, 0x02, 0x1c, 0x00, 0x20, 0x01, 0x44, 0, 0, 0, 0, 0, 0, 0, 0, 0x63, 0x04,
+        0x40, 0x41, 0x00, 0x41, 0x08, 0x10, 0x00, 0x0b, 0x20, 0x00, 0x20, 0x01, 0xa0, 0x0b,
+        0x07, 0x00, 0x20, 0x00, 0x20, 0x00, 0xa2, 0x0b, // code
+        0x0b, 0x0e, 0x01, 0x00, 0x41, 0x00, 0x0b, 0x08, b'n', b'e', b'g', b'a', b't', b'i',
+        b'v', b'e', // data
+    ];
+    let db = DbInstance::default();
+    db.register_wasm_plugin("Stats", &module, WasmLimits::default())
+        .unwrap();
+    assert!(db
+        .register_wasm_plugin("Stats", &module, WasmLimits::default())
+        .is_err());
+
+    let res = db
+        .run_default(
+            r"
+        xs[x] <- [[1.5], [2.0]]
+        ?[x, sq] <~ Stats(xs[], function: 'square')
+    ",
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1.5, 2.25], [2.0, 4.0]]));
+    let res = db
+        .run_default(
+            r"
+        xs[x] <- [[1.5], [2.0]]
+        ?[total] <~ Stats(xs[], function: 'total', init: 0.0)
+    ",
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[3.5]]));
+
+    let err = db
+        .run_default(
+            r"
+        xs[x] <- [[1.5], [-2.0]]
+        ?[total] <~ Stats(xs[], function: 'total', init: 0.0)
+    ",
+        )
+        .unwrap_err();
+    assert!(err
+        .report()
+        .chain()
+        .any(|e| e.to_string().contains("failed: negative")));
+    assert!(db
+        .run_default("xs[x] <- [[1]] ?[x, y] <~ Stats(xs[], function: 'cube')")
+        .is_err());
+
+    // only plugins are unregistered as plugins
+    db.register_fixed_rule(
+        "Custom".to_string(),
+        SimpleFixedRule::new(1, |_, _| Ok(NamedRows::default())),
+    )
+    .unwrap();
+    assert!(!db.unregister_wasm_plugin("Custom").unwrap());
+    assert!(!db.unregister_wasm_plugin("ReorderSort").unwrap());
+    assert!(db.run_default("?[x] <~ Custom()").is_ok());
+    assert!(db.unregister_wasm_plugin("Stats").unwrap());
+    assert!(!db.unregister_wasm_plugin("Stats").unwrap());
+    assert!(db
+        .run_default("xs[x] <- [[1.5]] ?[x, sq] <~ Stats(xs[], function: 'square')")
+        .is_err());
+    // a plugin unregistered as a fixed rule is forgotten
+    db.register_wasm_plugin("Stats", &module, WasmLimits::default())
+        .unwrap();
+    assert!(db.unregister_fixed_rule("Stats").unwrap());
+    db.register_fixed_rule(
+        "Stats".to_string(),
+        SimpleFixedRule::new(1, |_, _| Ok(NamedRows::default())),
+    )
+    .unwrap();
+    assert!(!db.unregister_wasm_plugin("Stats").unwrap());
+    assert!(db.register_wasm_plugin("Bad", b"\0asm", WasmLimits::default()).is_err());
+}
+
+#[test]
+fn plan_limits() {
+    let db = DbInstance::default();
+    db.run_default(":create a {k: Int => v: Int}").unwrap();
+    db.run_default(":create b {k: Int => v: Int}").unwrap();
+    db.run_default("?[k, v] := k in int_range(300), v = k % 7 :put a {k => v}")
+        .unwrap();
+    db.run_default("?[k, v] := k in int_range(300), v = k % 11 :put b {k => v}")
+        .unwrap();
+    db.set_plan_limits(crate::PlanLimits {
+        max_cost: Some(50_000.),
+        max_rows: Some(10_000.),
+    });
+
+    // relations are not read when planning, so without an analysis nothing is known
+    let cross = "?[x, y] := *a{k: x}, *b{k: y}";
+    assert_eq!(db.run_default(cross).unwrap().rows.len(), 90_000);
+    db.analyze().unwrap();
+    let err = db.run_default(cross).unwrap_err();
+    assert_eq!(err.code().as_deref(), Some("eval::plan_limit_exceeded"));
+    let res = db
+        .run_default(&format!("{cross} :disable_plan_limits"))
+        .unwrap();
+    assert_eq!(res.rows.len(), 90_000);
+    // only unrestricted sessions can override the limits
+    db.run_default("::grant read a {k, v} to analyst").unwrap();
+    db.run_default("::grant read b {k, v} to analyst").unwrap();
+    let err = db
+        .run_script_as(
+            &crate::Principal::new("ann").with_role("analyst"),
+            &format!("{cross} :disable_plan_limits"),
+            Default::default(),
+            ScriptMutability::Immutable,
+        )
+        .unwrap_err();
+    assert_eq!(err.code().as_deref(), Some("tx::restricted_session"));
+
+    let res = db
+        .run_default("?[x, v, w] := *a{k: x, v}, *b{k: x, v: w}")
+        .unwrap();
+    assert_eq!(res.rows.len(), 300);
+    let res = db.run_default("?[v] := *a{k: 42, v}").unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(0)]]);
+
+    db.set_plan_limits(Default::default());
+    assert_eq!(db.run_default(cross).unwrap().rows.len(), 90_000);
+}
+
+#[test]
+#[cfg(feature = "storage-sqlite")]
+fn replica_id_persisted() {
+    let path = std::env::temp_dir().join(format!("cozo_replica_id_{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let db = crate::new_cozo_sqlite(&path).unwrap();
+    let id = db.replica_id();
+    assert_ne!(id, DbInstance::default().replica_id());
+    drop(db);
+    let db = crate::new_cozo_sqlite(&path).unwrap();
+    assert_eq!(db.replica_id(), id);
+    db.set_replica_id("a").unwrap();
+    drop(db);
+    assert_eq!(crate::new_cozo_sqlite(&path).unwrap().replica_id(), "a");
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn merged_column_types() {
+    let a = DbInstance::default();
+    let b = DbInstance::default();
+    a.set_replica_id("a").unwrap();
+    b.set_replica_id("b").unwrap();
+    assert_eq!(a.replica_id(), "a");
+    for db in [&a, &b] {
+        db.run_default(":create page {id: Int => views: Counter default 0, title: Register}")
+            .unwrap();
+    }
+    assert_eq!(
+        a.run_default(":create bad {c: Counter}")
+            .unwrap_err()
+            .code()
+            .as_deref(),
+        Some("eval::misplaced_merged_column")
+    );
+    assert!(a.run_default(":create bad {k => c: [Counter]}").is_err());
+    assert!(a
+        .run_default("?[id, views, title] <- [[1, -1, 'x']] :put page {id => views, title}")
+        .is_err());
+
+    a.run_default("?[id, views, title] <- [[1, 2, 'a1']] :put page {id => views, title}")
+        .unwrap();
+    a.run_default("?[id, views] <- [[1, 3]] :update page {id => views}")
+        .unwrap();
+    b.run_default("?[id, views, title] <- [[1, 4, 'b1']] :put page {id => views, title}")
+        .unwrap();
+    let read = "?[v, t] := *page{id: 1, views, title}, v = counter_value(views), \
+                t = register_value(title)";
+    assert_eq!(
+        a.run_default(read).unwrap().into_json()["rows"],
+        json!([[5, "a1"]])
+    );
+
+    // exchanging the rows in both directions merges them the same way
+    let from_a = a.export_relations(["page"].iter()).unwrap();
+    let from_b = b.export_relations(["page"].iter()).unwrap();
+    a.import_relations(from_b.clone()).unwrap();
+    b.import_relations(from_a).unwrap();
+    let merged = a.run_default(read).unwrap().into_json()["rows"].clone();
+    assert_eq!(merged[0][0], json!(9));
+    assert_eq!(b.run_default(read).unwrap().into_json()["rows"], merged);
+    // importing the same rows again changes nothing
+    a.import_relations(from_b).unwrap();
+    assert_eq!(a.run_default(read).unwrap().into_json()["rows"], merged);
+
+    // a write after the merge wins on both replicas
+    a.run_default("?[id, title] <- [[1, 'a2']] :update page {id => title}")
+        .unwrap();
+    b.import_relations(a.export_relations(["page"].iter()).unwrap())
+        .unwrap();
+    assert_eq!(
+        b.run_default(read).unwrap().into_json()["rows"],
+        json!([[9, "a2"]])
+    );
+}
@@ -7,39 +7,225 @@
  */
 
 use std::sync::atomic::{AtomicU32, AtomicU64};
-use std::sync::Arc;
 
-use miette::{bail, Result};
+use miette::{bail, Diagnostic, Result};
+use thiserror::Error;
 use crate::data::program::ReturnMutation;
 
+use crate::data::expr::{eval_bytecode, Bytecode, Expr};
 use crate::data::tuple::TupleT;
 use crate::data::value::DataValue;
+use crate::error::is_conflict;
 use crate::fts::TokenizerCache;
 use crate::{CallbackOp, NamedRows};
 use crate::runtime::callback::CallbackCollector;
-use crate::runtime::relation::RelationId;
+use crate::runtime::db::SessionGuard;
+use crate::runtime::schema_cache::SchemaView;
+use crate::parse::SourceSpan;
+use crate::runtime::relation::{
+    parse_row_policy, ColumnNotGranted, GrantMode, RelationHandle, RelationId, RestrictedSession,
+    RowPolicyNotOnIndex, RowPolicyViolation,
+};
 use crate::storage::temp::TempTx;
 use crate::storage::StoreTx;
+use crate::utils::trace_span;
 
 pub struct SessionTx<'a> {
     pub(crate) store_tx: Box<dyn StoreTx<'a> + 'a>,
     pub(crate) temp_store_tx: TempTx,
-    pub(crate) relation_store_id: Arc<AtomicU64>,
+    pub(crate) relation_store_id: &'a AtomicU64,
     pub(crate) temp_store_id: AtomicU32,
-    pub(crate) tokenizers: Arc<TokenizerCache>,
+    pub(crate) tokenizers: &'a TokenizerCache,
+    pub(crate) session: SessionGuard<'a>,
+    /// Rows written to stored relations, counted into the metrics on commit
+    pub(crate) rows_written: u64,
+    pub(crate) schema: SchemaView<'a>,
+    /// Checks the constraints of the database before a commit that wrote rows, set for
+    /// write transactions
+    pub(crate) constraints: Option<ConstraintCheck<'a>>,
 }
 
+/// See [`SessionTx::constraints`].
+pub(crate) type ConstraintCheck<'a> =
+    Box<dyn Fn(&mut SessionTx<'a>) -> Result<()> + Send + Sync + 'a>;
+
 pub const CURRENT_STORAGE_VERSION: [u8; 1] = [0x00];
 
-fn storage_version_key() -> Vec<u8> {
+pub(crate) fn storage_version_key() -> Vec<u8> {
     let storage_version_tuple = vec![DataValue::Null, DataValue::from("STORAGE_VERSION")];
     storage_version_tuple.encode_as_key(RelationId::SYSTEM)
 }
 
+/// The key holding the version of the crate that created the storage, for error messages.
+pub(crate) fn storage_created_by_key() -> Vec<u8> {
+    let created_by_tuple = vec![DataValue::Null, DataValue::from("CREATED_BY")];
+    created_by_tuple.encode_as_key(RelationId::SYSTEM)
+}
+
+/// The key holding the replica id of the database, see [`Db::replica_id`](crate::Db::replica_id).
+pub(crate) fn replica_id_key() -> Vec<u8> {
+    let replica_tuple = vec![DataValue::Null, DataValue::from("REPLICA_ID")];
+    replica_tuple.encode_as_key(RelationId::SYSTEM)
+}
+
+const IDEMPOTENCY_KEY_STR: &str = "IDEMPOTENCY_KEY";
+
+/// The key recording the result of the write run with idempotency key `key`, see
+/// [`Db::run_script_idempotent`](crate::Db::run_script_idempotent).
+pub(crate) fn idempotency_record_key(key: &str) -> Vec<u8> {
+    let tuple = vec![
+        DataValue::Null,
+        DataValue::from(IDEMPOTENCY_KEY_STR),
+        DataValue::from(key),
+    ];
+    tuple.encode_as_key(RelationId::SYSTEM)
+}
+
+/// The bounds of the keys of [`idempotency_record_key`].
+pub(crate) fn idempotency_record_bounds() -> (Vec<u8>, Vec<u8>) {
+    let prefix = vec![DataValue::Null, DataValue::from(IDEMPOTENCY_KEY_STR)];
+    let mut upper = prefix.clone();
+    upper.push(DataValue::Bot);
+    (
+        prefix.encode_as_key(RelationId::SYSTEM),
+        upper.encode_as_key(RelationId::SYSTEM),
+    )
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error(
+    "Incompatible storage: the data has storage version {found}, \
+    but this release of Cozo ({}) reads version {CURRENT_STORAGE_VERSION:?}",
+    env!("CARGO_PKG_VERSION")
+)]
+#[diagnostic(code(storage::incompatible_version))]
+#[diagnostic(help(
+    "{written_by}. To migrate, try `upgrade_storage`, or open the data with that release, \
+    write it out with `snapshot_export` or `backup_db`, then load it with `snapshot_import` \
+    or `restore_backup` into a new database created by this release"
+))]
+pub(crate) struct IncompatibleStorageVersion {
+    found: String,
+    written_by: String,
+}
+
+impl IncompatibleStorageVersion {
+    pub(crate) fn new<'s>(tx: &(impl StoreTx<'s> + ?Sized), found: Option<&[u8]>) -> Result<Self> {
+        let written_by = match tx.get(&storage_created_by_key(), false)? {
+            Some(v) => format!("The data was written by Cozo {}", String::from_utf8_lossy(&v)),
+            None => "The data was written by another release of Cozo".to_string(),
+        };
+        Ok(Self {
+            found: match found {
+                None => "none, probably written by an ancient release".to_string(),
+                Some(v) => format!("{v:?}"),
+            },
+            written_by,
+        })
+    }
+}
+
 const STATUS_STR: &str = "status";
 const OK_STR: &str = "OK";
 
 impl<'a> SessionTx<'a> {
+    /// The role restricting the session, if any.
+    pub(crate) fn role(&self) -> Option<&str> {
+        self.session.role.as_deref()
+    }
+    pub(crate) fn ensure_unrestricted(&self, action: &str) -> Result<()> {
+        if let Some(role) = self.role() {
+            bail!(RestrictedSession(role.to_string(), action.to_string()))
+        }
+        Ok(())
+    }
+    /// Check that the session may access `columns` of `handle` in the manner of `mode`.
+    /// The columns of an index are granted by the relation it is built on.
+    pub(crate) fn ensure_granted<'c>(
+        &self,
+        handle: &RelationHandle,
+        columns: impl IntoIterator<Item = &'c str>,
+        mode: GrantMode,
+        span: SourceSpan,
+    ) -> Result<()> {
+        if self.role().is_none() {
+            return Ok(());
+        }
+        let base = self.base_relation(&handle.name)?;
+        let granting = base.as_ref().unwrap_or(handle);
+        for column in columns {
+            if !granting.is_granted(self.role(), column, mode) {
+                bail!(ColumnNotGranted {
+                    relation: handle.name.to_string(),
+                    column: column.to_string(),
+                    role: self.role().unwrap_or_default().to_string(),
+                    mode,
+                    span,
+                })
+            }
+        }
+        Ok(())
+    }
+    /// The row policy of `handle` if it applies to the session, with `$current_user` and
+    /// `$current_role` bound to the principal. Bindings are the names of the columns.
+    /// An index is restricted by the policy of the relation it is built on.
+    pub(crate) fn row_policy(&self, handle: &RelationHandle) -> Result<Option<Expr>> {
+        let role = match self.role() {
+            None => return Ok(None),
+            Some(role) => role,
+        };
+        let base = self.base_relation(&handle.name)?;
+        let code = match &base.as_ref().unwrap_or(handle).row_policy {
+            None => return Ok(None),
+            Some(code) => code,
+        };
+        let policy = parse_row_policy(code, self.session.principal.as_deref(), Some(role))?;
+        if let Some(base) = &base {
+            let columns = handle.raw_binding_map();
+            for binding in policy.bindings()? {
+                if !columns.contains_key(&binding) {
+                    bail!(RowPolicyNotOnIndex {
+                        relation: base.name.to_string(),
+                        index: handle.name.to_string(),
+                        column: binding.name.to_string(),
+                    })
+                }
+            }
+        }
+        Ok(Some(policy))
+    }
+    /// [`SessionTx::row_policy`] compiled against full rows of `handle`.
+    pub(crate) fn row_policy_bytecode(
+        &self,
+        handle: &RelationHandle,
+    ) -> Result<Option<Vec<Bytecode>>> {
+        match self.row_policy(handle)? {
+            None => Ok(None),
+            Some(mut expr) => {
+                expr.fill_binding_indices(&handle.raw_binding_map())?;
+                Ok(Some(expr.compile()?))
+            }
+        }
+    }
+    /// Check that `row`, a full row of `handle`, satisfies the compiled row policy.
+    pub(crate) fn ensure_row_policy(
+        &self,
+        handle: &RelationHandle,
+        policy: &[Bytecode],
+        row: &[DataValue],
+        stack: &mut Vec<DataValue>,
+        span: SourceSpan,
+    ) -> Result<()> {
+        if eval_bytecode(policy, row, stack)? != DataValue::from(true) {
+            bail!(RowPolicyViolation {
+                relation: handle.name.to_string(),
+                role: self.role().unwrap_or_default().to_string(),
+                row: row.to_vec(),
+                span,
+            })
+        }
+        Ok(())
+    }
     pub(crate) fn get_returning_rows(&self, callback_collector: &mut CallbackCollector, rel: &str, returning: &ReturnMutation) -> Result<NamedRows> {
         let returned_rows = {
             match returning {
@@ -103,25 +289,19 @@ impl<'a> SessionTx<'a> {
             None => {
                 self.store_tx
                     .put(&storage_version_key, &CURRENT_STORAGE_VERSION)?;
+                self.store_tx.put(
+                    &storage_created_by_key(),
+                    env!("CARGO_PKG_VERSION").as_bytes(),
+                )?;
                 self.store_tx
                     .put(&t_encoded, &RelationId::new(0).raw_encode())?;
                 RelationId::SYSTEM
             }
             Some(slice) => {
                 let version_found = self.store_tx.get(&storage_version_key, false)?;
-                match version_found {
-                    None => {
-                        bail!("Storage is used but un-versioned, probably created by an ancient version of Cozo.")
-                    }
-                    Some(v) => {
-                        if v != CURRENT_STORAGE_VERSION {
-                            bail!(
-                                "Version mismatch: expect storage version {:?}, got {:?}",
-                                CURRENT_STORAGE_VERSION,
-                                v
-                            )
-                        }
-                    }
+                if version_found.as_deref() != Some(&CURRENT_STORAGE_VERSION[..]) {
+                    let store_tx = self.store_tx.as_ref();
+                    bail!(IncompatibleStorageVersion::new(store_tx, version_found.as_deref())?)
                 }
                 RelationId::raw_decode(&slice)
             }
@@ -130,7 +310,31 @@ impl<'a> SessionTx<'a> {
     }
 
     pub fn commit_tx(&mut self) -> Result<()> {
-        self.store_tx.commit()?;
+        let _span = trace_span!("commit", tx = self.session.id);
+        self.session.ensure_alive()?;
+        if self.rows_written > 0 {
+            if let Some(check) = self.constraints.take() {
+                check(self)?;
+            }
+        }
+        let schema_changed = self.schema.changed();
+        if schema_changed {
+            self.schema.cache.begin_change();
+        }
+        let committed = self.store_tx.commit();
+        if schema_changed {
+            self.schema.cache.end_change();
+        }
+        if let Err(err) = committed {
+            if is_conflict(&err) {
+                self.session.metrics.commit_conflicts.add(1);
+            }
+            return Err(err);
+        }
+        self.session.committed = true;
+        let metrics = &self.session.metrics;
+        metrics.transactions_committed.add(1);
+        metrics.rows_written.add(self.rows_written);
         Ok(())
     }
 }
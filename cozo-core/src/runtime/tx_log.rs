@@ -0,0 +1,504 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use itertools::Itertools;
+use miette::{Diagnostic, IntoDiagnostic, Result};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use thiserror::Error;
+
+use crate::data::json::JsonValue;
+use crate::data::tuple::{decode_tuple_from_key, Tuple};
+use crate::data::value::ValidityTs;
+use crate::runtime::chunked::CHUNK_JOURNAL_KEY_MARKER;
+use crate::runtime::db::seconds_since_the_epoch;
+use crate::runtime::relation::{extend_tuple_from_v, RelationHandle, RelationId};
+use crate::storage::{Storage, StoreTx};
+use crate::NamedRows;
+
+/// First byte of every key of the transaction log, which is stored just before the
+/// audit log.
+pub(crate) const TX_LOG_KEY_MARKER: u8 = 0xFD;
+
+const TX_LOG_UPPER: [u8; 1] = [TX_LOG_KEY_MARKER + 1];
+
+/// A change made to the storage by a logged transaction, see [`TxLogEntry`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TxOp {
+    /// A key was written with a value
+    Put(
+        #[serde(with = "serde_bytes")] Vec<u8>,
+        #[serde(with = "serde_bytes")] Vec<u8>,
+    ),
+    /// A key was deleted
+    Del(#[serde(with = "serde_bytes")] Vec<u8>),
+    /// The keys from the first one inclusive to the second one exclusive were deleted
+    DelRange(
+        #[serde(with = "serde_bytes")] Vec<u8>,
+        #[serde(with = "serde_bytes")] Vec<u8>,
+    ),
+}
+
+/// A committed write transaction recorded by the transaction log, see
+/// [`Db::enable_tx_log`](crate::Db::enable_tx_log).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TxLogEntry {
+    /// Position of the transaction in the log. Transactions are numbered from 1 in the
+    /// order they start committing, without gaps: a transaction that fails to commit
+    /// leaves an entry without changes.
+    pub seq: u64,
+    /// When the transaction was committed, in seconds since the epoch
+    pub timestamp: f64,
+    /// The changes made by the transaction, in the order they were made
+    pub ops: Vec<TxOp>,
+    /// The group of the transaction, if it is one of those committing a chunked import
+    #[serde(default)]
+    pub group: Option<TxGroup>,
+}
+
+/// Links the entries of the transactions committing a chunked import, see
+/// [`Db::import_relations_chunked`](crate::Db::import_relations_chunked). To see the
+/// import as a single transaction, hold the entries of a group until its last one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxGroup {
+    /// Identifies the group. Other transactions may be logged between its entries.
+    pub id: u64,
+    /// Where the group stands once the entry is applied
+    pub state: TxGroupState,
+}
+
+/// See [`TxGroup`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TxGroupState {
+    /// Entries of the group follow
+    Pending,
+    /// The entry is the last of the group, and the import is committed
+    Committed,
+    /// The entry is the last of the group, and the changes of the import were undone
+    RolledBack,
+}
+
+/// Consecutive entries of the transaction log, as returned by
+/// [`Db::tx_log_chunk`](crate::Db::tx_log_chunk) for archiving.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TxLogChunk {
+    /// The entries, oldest first
+    pub entries: Vec<TxLogEntry>,
+}
+
+impl TxLogChunk {
+    /// The sequence number of the last entry, if there is any.
+    pub fn last_seq(&self) -> Option<u64> {
+        self.entries.last().map(|entry| entry.seq)
+    }
+    /// Encode the chunk into bytes for archiving.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        rmp_serde::to_vec(self).into_diagnostic()
+    }
+    /// Decode a chunk encoded by [`to_bytes`](Self::to_bytes).
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        rmp_serde::from_slice(bytes).into_diagnostic()
+    }
+}
+
+/// The rows changed in stored relations by a committed transaction, delivered by
+/// [`Db::subscribe_from`](crate::Db::subscribe_from).
+#[derive(Debug, Clone)]
+pub struct TxChanges {
+    /// The number of the transaction in the transaction log, see [`TxLogEntry::seq`]
+    pub tx_id: u64,
+    /// When the transaction was committed, in seconds since the epoch
+    pub timestamp: f64,
+    /// The changes, by relation name. Indices are not included.
+    pub relations: BTreeMap<String, RelationChanges>,
+}
+
+/// The rows of a relation changed by a transaction, see [`TxChanges`].
+#[derive(Debug, Clone)]
+pub struct RelationChanges {
+    /// The rows put, with all columns
+    pub put: NamedRows,
+    /// The keys of the rows removed. A key put and then removed by the same
+    /// transaction appears in both.
+    pub rm: NamedRows,
+}
+
+impl TxChanges {
+    /// Convert to JSON, as `{"tx_id", "timestamp", "relations"}` with the changes of
+    /// each relation given as `{"put", "rm"}` in the format of [`NamedRows::into_json`].
+    pub fn into_json(self) -> JsonValue {
+        let relations: serde_json::Map<_, _> = self
+            .relations
+            .into_iter()
+            .map(|(name, rows)| {
+                let rows = json!({"put": rows.put.into_json(), "rm": rows.rm.into_json()});
+                (name, rows)
+            })
+            .collect();
+        json!({
+            "tx_id": self.tx_id,
+            "timestamp": self.timestamp,
+            "relations": relations,
+        })
+    }
+    /// The changes recorded by `entry` to the relations of `handles`, by id. Changes to
+    /// relations no longer existing are left out.
+    pub(crate) fn of_entry(entry: &TxLogEntry, handles: &BTreeMap<u64, RelationHandle>) -> Self {
+        let mut relations: BTreeMap<String, RelationChanges> = BTreeMap::new();
+        for op in &entry.ops {
+            let (key, val) = match op {
+                TxOp::Put(key, val) => (key, Some(val)),
+                TxOp::Del(key) => (key, None),
+                TxOp::DelRange(..) => continue,
+            };
+            if key.len() < 8 || key[0] >= CHUNK_JOURNAL_KEY_MARKER {
+                continue;
+            }
+            let handle = match handles.get(&RelationId::raw_decode(key).0) {
+                Some(handle) if !handle.name.contains(':') => handle,
+                _ => continue,
+            };
+            let changes = relations
+                .entry(handle.name.to_string())
+                .or_insert_with(|| {
+                    let keys = handle.metadata.keys.iter().map(|c| c.name.to_string());
+                    let non_keys = handle.metadata.non_keys.iter().map(|c| c.name.to_string());
+                    RelationChanges {
+                        put: NamedRows::new(keys.clone().chain(non_keys).collect(), vec![]),
+                        rm: NamedRows::new(keys.collect(), vec![]),
+                    }
+                });
+            let mut tuple = decode_tuple_from_key(key, changes.put.headers.len());
+            match val {
+                Some(val) => {
+                    extend_tuple_from_v(&mut tuple, val);
+                    changes.put.rows.push(tuple);
+                }
+                None => changes.rm.rows.push(tuple),
+            }
+        }
+        Self {
+            tx_id: entry.seq,
+            timestamp: entry.timestamp,
+            relations,
+        }
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The transaction log is not enabled")]
+#[diagnostic(code(db::tx_log_not_enabled))]
+#[diagnostic(help("Enable it with `Db::enable_tx_log`"))]
+pub(crate) struct TxLogNotEnabled;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The transaction log no longer holds transaction {0}")]
+#[diagnostic(code(db::tx_log_truncated))]
+#[diagnostic(help("The log was truncated past the last acknowledged transaction"))]
+pub(crate) struct TxLogTruncated(pub(crate) u64);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The transaction log has no entry {0}: the chunks do not continue the base backup")]
+#[diagnostic(code(restore::tx_log_gap))]
+#[diagnostic(help(
+    "Pass every chunk archived since the base backup was taken, in order, without gaps"
+))]
+pub(crate) struct TxLogGap(pub(crate) u64);
+
+/// Whether write transactions are logged, and the numbers given to them.
+#[derive(Default)]
+pub(crate) struct TxLog {
+    enabled: AtomicBool,
+    seqs: Mutex<TxLogSeqs>,
+    /// Notified after a logged transaction has finished committing
+    committed: Condvar,
+}
+
+#[derive(Default)]
+struct TxLogSeqs {
+    /// The number given to the last logged transaction
+    last: u64,
+    /// The numbers of the transactions still committing
+    committing: BTreeSet<u64>,
+}
+
+impl TxLogSeqs {
+    fn settled(&self) -> u64 {
+        match self.committing.first() {
+            None => self.last,
+            Some(seq) => seq - 1,
+        }
+    }
+}
+
+impl TxLog {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Acquire)
+    }
+    pub(crate) fn start(&self, last_seq: u64) {
+        self.seqs.lock().unwrap().last = last_seq;
+        self.enabled.store(true, Ordering::Release);
+    }
+    /// Returns `false` if the log was not enabled.
+    pub(crate) fn stop(&self) -> bool {
+        self.enabled.swap(false, Ordering::AcqRel)
+    }
+    /// The number for a transaction starting to commit, to be passed to
+    /// [`finish`](Self::finish) once it is done.
+    fn assign(&self) -> u64 {
+        let mut seqs = self.seqs.lock().unwrap();
+        seqs.last += 1;
+        let seq = seqs.last;
+        seqs.committing.insert(seq);
+        seq
+    }
+    fn finish(&self, seq: u64) {
+        self.seqs.lock().unwrap().committing.remove(&seq);
+        self.committed.notify_all();
+    }
+    /// The number of the last entry such that the entries up to it are all in the
+    /// storage. The entries after it may still be written in any order, so readers
+    /// stop there to not skip any of them. No entries are written while the log is
+    /// disabled, so all of them are settled.
+    pub(crate) fn settled(&self) -> u64 {
+        if !self.is_enabled() {
+            return u64::MAX;
+        }
+        self.seqs.lock().unwrap().settled()
+    }
+    /// Wait until a transaction after `seq` has been settled, or `timeout` has passed.
+    pub(crate) fn wait_after(&self, seq: u64, timeout: Duration) {
+        let seqs = self.seqs.lock().unwrap();
+        let _ = self
+            .committed
+            .wait_timeout_while(seqs, timeout, |seqs| seqs.settled() <= seq)
+            .unwrap();
+    }
+}
+
+pub(crate) fn tx_log_key(seq: u64) -> Vec<u8> {
+    let mut key = Vec::with_capacity(9);
+    key.push(TX_LOG_KEY_MARKER);
+    key.extend_from_slice(&seq.to_be_bytes());
+    key
+}
+
+/// The number of the last entry in the transaction log of `tx`, or zero if it is empty.
+pub(crate) fn last_tx_log_seq<'s>(tx: &(impl StoreTx<'s> + ?Sized)) -> Result<u64> {
+    Ok(match tx.range_scan(&[TX_LOG_KEY_MARKER], &TX_LOG_UPPER).last() {
+        None => 0,
+        Some(kv) => {
+            let (_, v) = kv?;
+            rmp_serde::from_slice::<TxLogEntry>(&v).into_diagnostic()?.seq
+        }
+    })
+}
+
+/// Up to `max_entries` entries of the log following entry `after`, stopping at the
+/// [settled](TxLog::settled) entries of `log`.
+pub(crate) fn read_tx_log<'s, S: Storage<'s>>(
+    storage: &'s S,
+    log: &TxLog,
+    after: u64,
+    max_entries: usize,
+) -> Result<TxLogChunk> {
+    let settled = log.settled();
+    let tx = storage.transact(false)?;
+    let upper = match settled.checked_add(1) {
+        Some(upper) if upper <= after => return Ok(TxLogChunk::default()),
+        Some(upper) => tx_log_key(upper),
+        None => TX_LOG_UPPER.to_vec(),
+    };
+    let entries = tx
+        .range_scan(&tx_log_key(after.saturating_add(1)), &upper)
+        .take(max_entries)
+        .map(|kv| {
+            let (_, v) = kv?;
+            rmp_serde::from_slice(&v).into_diagnostic()
+        })
+        .try_collect()?;
+    Ok(TxLogChunk { entries })
+}
+
+/// Remove the entries of the log up to and including entry `up_to`, returning how many
+/// were removed.
+pub(crate) fn truncate_tx_log<'s, S: Storage<'s>>(storage: &'s S, up_to: u64) -> Result<usize> {
+    let mut tx = storage.transact(true)?;
+    let upper = tx_log_key(up_to.saturating_add(1));
+    let count = tx.range_count(&[TX_LOG_KEY_MARKER], &upper)?;
+    tx.del_range_from_persisted(&[TX_LOG_KEY_MARKER], &upper)?;
+    tx.commit()?;
+    Ok(count)
+}
+
+/// Apply the changes of `entry` to `tx`, recording the entry in its log as well.
+pub(crate) fn replay_tx_log_entry<'s>(
+    tx: &mut impl StoreTx<'s>,
+    entry: &TxLogEntry,
+) -> Result<()> {
+    for op in &entry.ops {
+        match op {
+            TxOp::Put(key, val) => tx.put(key, val)?,
+            TxOp::Del(key) => tx.del(key)?,
+            TxOp::DelRange(lower, upper) => tx.del_range_from_persisted(lower, upper)?,
+        }
+    }
+    let val = rmp_serde::to_vec(entry).into_diagnostic()?;
+    tx.put(&tx_log_key(entry.seq), &val)
+}
+
+/// Writes the entry of a logged transaction that failed to commit in a transaction of
+/// its own, see [`LoggedTx`].
+pub(crate) type FillTxLogGap<'s> = Box<dyn Fn(&TxLogEntry) -> Result<()> + Sync + 's>;
+
+/// Wraps the storage transaction of a session, recording its changes and writing them
+/// to the transaction log together with the changes themselves when it commits.
+pub(crate) struct LoggedTx<'s> {
+    pub(crate) inner: Box<dyn StoreTx<'s> + 's>,
+    pub(crate) log: Arc<TxLog>,
+    pub(crate) ops: Mutex<Vec<TxOp>>,
+    pub(crate) group: Option<TxGroup>,
+    pub(crate) fill_gap: FillTxLogGap<'s>,
+}
+
+impl<'s> LoggedTx<'s> {
+    fn record(&self, op: TxOp) {
+        self.ops.lock().unwrap().push(op);
+    }
+}
+
+impl<'s> StoreTx<'s> for LoggedTx<'s> {
+    fn get(&self, key: &[u8], for_update: bool) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key, for_update)
+    }
+
+    fn multi_get(&self, keys: &[Vec<u8>], for_update: bool) -> Result<Vec<Option<Vec<u8>>>> {
+        self.inner.multi_get(keys, for_update)
+    }
+
+    fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.inner.put(key, val)?;
+        self.record(TxOp::Put(key.to_vec(), val.to_vec()));
+        Ok(())
+    }
+
+    fn supports_par_put(&self) -> bool {
+        self.inner.supports_par_put()
+    }
+
+    fn par_put(&self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.inner.par_put(key, val)?;
+        self.record(TxOp::Put(key.to_vec(), val.to_vec()));
+        Ok(())
+    }
+
+    fn del(&mut self, key: &[u8]) -> Result<()> {
+        self.inner.del(key)?;
+        self.record(TxOp::Del(key.to_vec()));
+        Ok(())
+    }
+
+    fn par_del(&self, key: &[u8]) -> Result<()> {
+        self.inner.par_del(key)?;
+        self.record(TxOp::Del(key.to_vec()));
+        Ok(())
+    }
+
+    fn del_range_from_persisted(&mut self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.inner.del_range_from_persisted(lower, upper)?;
+        self.record(TxOp::DelRange(lower.to_vec(), upper.to_vec()));
+        Ok(())
+    }
+
+    fn exists(&self, key: &[u8], for_update: bool) -> Result<bool> {
+        self.inner.exists(key, for_update)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        let ops = std::mem::take(&mut *self.ops.lock().unwrap());
+        if ops.is_empty() || !self.log.is_enabled() {
+            return self.inner.commit();
+        }
+        let timestamp = seconds_since_the_epoch()?;
+        // the number is taken under the lock, the commit happens outside of it
+        let seq = self.log.assign();
+        let entry = TxLogEntry {
+            seq,
+            timestamp,
+            ops,
+            group: self.group,
+        };
+        let committed = rmp_serde::to_vec(&entry)
+            .into_diagnostic()
+            .and_then(|val| self.inner.put(&tx_log_key(seq), &val))
+            .and_then(|_| self.inner.commit());
+        if committed.is_err() {
+            let empty = TxLogEntry {
+                seq,
+                timestamp,
+                ops: vec![],
+                group: None,
+            };
+            if let Err(err) = (self.fill_gap)(&empty) {
+                log::error!("cannot fill the entry {seq} of the transaction log: {err:?}");
+            }
+        }
+        self.log.finish(seq);
+        committed
+    }
+
+    fn range_skip_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        valid_at: ValidityTs,
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
+        self.inner.range_skip_scan_tuple(lower, upper, valid_at)
+    }
+
+    fn range_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.range_scan_tuple(lower, upper)
+    }
+
+    fn range_scan<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.range_scan(lower, upper)
+    }
+
+    fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
+    where
+        's: 'a,
+    {
+        self.inner.range_count(lower, upper)
+    }
+
+    fn total_scan<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.total_scan()
+    }
+}
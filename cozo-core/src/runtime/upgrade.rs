@@ -0,0 +1,177 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! In-place migration of the key-value encoding of a storage to the current storage
+//! version, see [`Db::upgrade_storage`].
+
+use miette::{bail, Result};
+
+use crate::runtime::transact::{
+    storage_version_key, IncompatibleStorageVersion, CURRENT_STORAGE_VERSION,
+};
+use crate::storage::{Storage, StoreTx};
+use crate::Db;
+
+/// Rewrites a key-value pair from the encoding of one storage version to that of the
+/// next, returning `None` to keep the pair as it is. A rewrite must also return `None`
+/// for pairs already in the new encoding: an interrupted upgrade starts over, and pairs
+/// moved past the scan position are read again.
+pub(crate) type RewriteFn = fn(&[u8], &[u8]) -> Result<Option<(Vec<u8>, Vec<u8>)>>;
+
+/// A step of the upgrade, from one storage version to the next.
+pub(crate) struct StorageMigration {
+    pub(crate) from: &'static [u8],
+    pub(crate) to: &'static [u8],
+    pub(crate) rewrite: RewriteFn,
+}
+
+/// The migrations between the storage versions, in order. Version `[0]` is the first
+/// versioned encoding, so there is nothing to upgrade from yet.
+const STORAGE_MIGRATIONS: &[StorageMigration] = &[];
+
+/// Number of pairs read and rewritten in each transaction of an upgrade.
+const UPGRADE_BATCH_SIZE: usize = 10000;
+
+/// Larger than all keys of the storage: keys start with a relation id, or with
+/// `0xff` followed by the UTF-8 name of a namespace, which contains no `0xff` byte.
+const UPPER_KEY: [u8; 9] = [0xff; 9];
+
+/// Progress of [`Db::upgrade_storage`], reported after each batch of pairs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UpgradeProgress {
+    /// The storage version migrated from in the current step
+    pub from_version: Vec<u8>,
+    /// The storage version migrated to in the current step
+    pub to_version: Vec<u8>,
+    /// Pairs read so far in the current step
+    pub pairs_read: usize,
+    /// Pairs rewritten so far in the current step
+    pub pairs_rewritten: usize,
+}
+
+/// Summary of [`Db::upgrade_storage`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UpgradeReport {
+    /// The storage version found, or `None` for a new storage
+    pub from_version: Option<Vec<u8>>,
+    /// Number of migration steps run, zero when the storage is already current
+    pub steps: usize,
+    /// Pairs rewritten over all steps
+    pub pairs_rewritten: usize,
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Migrate the storage to the current storage version in place, so that it can be
+    /// opened by this release. Storages that are new or already current are left as
+    /// they are.
+    ///
+    /// This must be called on a database that has not been initialized, and so is not
+    /// in use: use [`DbInstance::upgrade_storage`](crate::DbInstance::upgrade_storage)
+    /// to upgrade a database by its path. Each step of the upgrade rewrites all pairs
+    /// of the storage in batches, calling `progress` after each batch. The storage
+    /// version is only updated when all steps are done, and an interrupted upgrade is
+    /// resumed by calling this again.
+    pub fn upgrade_storage(
+        &'s self,
+        progress: impl FnMut(&UpgradeProgress),
+    ) -> Result<UpgradeReport> {
+        self.upgrade_storage_with(STORAGE_MIGRATIONS, progress)
+    }
+
+    pub(crate) fn upgrade_storage_with(
+        &'s self,
+        migrations: &[StorageMigration],
+        mut progress: impl FnMut(&UpgradeProgress),
+    ) -> Result<UpgradeReport> {
+        let (found, is_empty) = {
+            let tx = self.db.transact(false)?;
+            let found = tx.get(&storage_version_key(), false)?;
+            let is_empty = tx.range_scan(&[], &UPPER_KEY).next().is_none();
+            (found, is_empty)
+        };
+        let mut report = UpgradeReport {
+            from_version: found.clone(),
+            ..Default::default()
+        };
+        let mut version = match found {
+            Some(v) => v,
+            None if is_empty => return Ok(report),
+            None => bail!(self.incompatible_version(None)?),
+        };
+        // find all steps before rewriting anything
+        let mut steps = vec![];
+        while version != CURRENT_STORAGE_VERSION {
+            match migrations.iter().find(|m| m.from == version) {
+                Some(m) => {
+                    steps.push(m);
+                    version = m.to.to_vec();
+                }
+                None => bail!(self.incompatible_version(Some(&version))?),
+            }
+        }
+        for migration in steps {
+            report.pairs_rewritten += self.run_migration(migration, &mut progress)?;
+            report.steps += 1;
+        }
+        if report.steps > 0 {
+            let mut tx = self.db.transact(true)?;
+            tx.put(&storage_version_key(), &CURRENT_STORAGE_VERSION)?;
+            tx.commit()?;
+            self.schema_cache.invalidate();
+        }
+        Ok(report)
+    }
+
+    fn incompatible_version(&'s self, found: Option<&[u8]>) -> Result<IncompatibleStorageVersion> {
+        let tx = self.db.transact(false)?;
+        IncompatibleStorageVersion::new(&tx, found)
+    }
+
+    fn run_migration(
+        &'s self,
+        migration: &StorageMigration,
+        progress: &mut impl FnMut(&UpgradeProgress),
+    ) -> Result<usize> {
+        let mut status = UpgradeProgress {
+            from_version: migration.from.to_vec(),
+            to_version: migration.to.to_vec(),
+            ..Default::default()
+        };
+        let version_key = storage_version_key();
+        let mut lower = vec![];
+        loop {
+            let mut tx = self.db.transact(true)?;
+            let batch = tx
+                .range_scan(&lower, &UPPER_KEY)
+                .take(UPGRADE_BATCH_SIZE)
+                .collect::<Result<Vec<_>>>()?;
+            let Some((last, _)) = batch.last() else {
+                break;
+            };
+            // the smallest key after the batch
+            lower = last.clone();
+            lower.push(0);
+            status.pairs_read += batch.len();
+            for (k, v) in &batch {
+                if *k == version_key {
+                    continue;
+                }
+                if let Some((new_k, new_v)) = (migration.rewrite)(k, v)? {
+                    if new_k != *k {
+                        tx.del(k)?;
+                    }
+                    tx.put(&new_k, &new_v)?;
+                    status.pairs_rewritten += 1;
+                }
+            }
+            tx.commit()?;
+            progress(&status);
+        }
+        Ok(status.pairs_rewritten)
+    }
+}
@@ -0,0 +1,432 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Explanations of the rows of queries by the rules and stored rows they are derived
+//! from, see [`Db::why`].
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::Ordering;
+
+use itertools::Itertools;
+use miette::{bail, ensure, Diagnostic, Result};
+use thiserror::Error;
+
+use crate::data::expr::Expr;
+use crate::data::functions::current_validity;
+use crate::data::program::{
+    InputProgram, NormalFormAtom, NormalFormInlineRule, NormalFormProgram, NormalFormRulesOrFixed,
+    Unification,
+};
+use crate::data::symb::{Symbol, PROG_ENTRY};
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::parse::{parse_script, CozoScript, SourceSpan};
+use crate::runtime::db::{with_script_source, Poison};
+use crate::runtime::transact::SessionTx;
+use crate::{Db, Storage};
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Only the rows of single queries can be explained")]
+#[diagnostic(code(eval::why_needs_single_query))]
+struct WhyNeedsSingleQuery;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The query returns rows of {0} columns, but the row to explain has {1}")]
+#[diagnostic(code(eval::why_arity_mismatch))]
+struct WhyArityMismatch(usize, usize);
+
+/// How the fact of a [`Derivation`] holds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DerivationKind {
+    /// Derived by a clause of a rule from the premises. Clauses are numbered from zero
+    /// in their order in the query, after disjunctions are expanded into clauses.
+    Rule {
+        /// The clause deriving the fact
+        clause: usize,
+    },
+    /// Derived by a clause of a rule aggregating the rows whose premises are given
+    Aggregation {
+        /// The clause deriving the fact
+        clause: usize,
+    },
+    /// A row of a stored relation
+    Stored,
+    /// A row produced by a fixed rule
+    Fixed,
+    /// A row required by a negation not to be in a rule or stored relation. Columns
+    /// left unbound by the negation hold [`DataValue::Bot`].
+    Absent,
+}
+
+/// A derivation tree explaining a fact, returned by [`Db::why`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Derivation {
+    /// The rule, or the stored relation, of the fact. The rule of the rows of the
+    /// query is named `?`.
+    pub relation: String,
+    /// The fact
+    pub fact: Tuple,
+    /// How the fact holds
+    pub kind: DerivationKind,
+    /// The facts the fact is derived from
+    pub premises: Vec<Derivation>,
+}
+
+impl Derivation {
+    /// The rows of stored relations the fact is ultimately derived from, without
+    /// duplicates.
+    pub fn stored_facts(&self) -> Vec<(&str, &Tuple)> {
+        let mut ret = vec![];
+        self.collect_stored(&mut ret);
+        ret.into_iter().unique().collect()
+    }
+    fn collect_stored<'a>(&'a self, coll: &mut Vec<(&'a str, &'a Tuple)>) {
+        if self.kind == DerivationKind::Stored {
+            coll.push((&self.relation, &self.fact));
+        }
+        for premise in &self.premises {
+            premise.collect_stored(coll);
+        }
+    }
+    fn fmt_indented(&self, f: &mut Formatter<'_>, depth: usize) -> std::fmt::Result {
+        let values = self.fact.iter().map(|v| v.to_string()).join(", ");
+        write!(f, "{:indent$}", "", indent = depth * 2)?;
+        match self.kind {
+            DerivationKind::Rule { clause } => {
+                writeln!(f, "{}[{values}] by clause {clause}", self.relation)?
+            }
+            DerivationKind::Aggregation { clause } => writeln!(
+                f,
+                "{}[{values}] aggregated by clause {clause}",
+                self.relation
+            )?,
+            DerivationKind::Stored => writeln!(f, "*{}[{values}]", self.relation)?,
+            DerivationKind::Fixed => writeln!(f, "{}[{values}] by fixed rule", self.relation)?,
+            DerivationKind::Absent => writeln!(f, "not {}[{values}]", self.relation)?,
+        }
+        for premise in &self.premises {
+            premise.fmt_indented(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Derivation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.fmt_indented(f, 0)
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Explain why `fact` is a row of the query `payload`: returns the derivation of
+    /// the row by the rules of the query down to the stored rows it comes from, or
+    /// `None` if the query does not return the row. Options of the query such as
+    /// `:limit` or `:put` are ignored.
+    ///
+    /// The derivation is found after the fact, by evaluating for each fact to explain
+    /// the clauses of its rule restricted to that fact, and explaining in turn the
+    /// premises of the first restricted row found. Facts are never explained by
+    /// themselves, so the derivations through recursive rules are finite. Each fact
+    /// explained takes an evaluation of the query, so this is meant for debugging.
+    pub fn why(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        fact: Vec<DataValue>,
+    ) -> Result<Option<Derivation>> {
+        let cur_vld = current_validity();
+        parse_script(
+            payload,
+            &params,
+            &self.fixed_rules.read().unwrap(),
+            cur_vld,
+            self.string_literals_forbidden.load(Ordering::Relaxed),
+        )
+        .and_then(|script| {
+            let CozoScript::Single(input) = script else {
+                bail!(WhyNeedsSingleQuery)
+            };
+            let arity = input.get_entry_out_head_or_default()?.len();
+            ensure!(arity == fact.len(), WhyArityMismatch(arity, fact.len()));
+            let mut tx = self.transact()?;
+            let mut explainer = Explainer {
+                tx: &mut tx,
+                input: &input,
+                proved: Default::default(),
+                path: Default::default(),
+            };
+            explainer.explain(&entry_symbol(), &fact, true)
+        })
+        .map_err(|err| with_script_source(err, payload))
+    }
+}
+
+fn entry_symbol() -> Symbol {
+    Symbol::new(PROG_ENTRY, SourceSpan(0, 0))
+}
+
+struct Explainer<'a, 't> {
+    tx: &'a mut SessionTx<'t>,
+    input: &'a InputProgram,
+    /// Facts explained so far, by rule
+    proved: BTreeMap<(Symbol, Tuple), Derivation>,
+    /// Facts being explained
+    path: BTreeSet<(Symbol, Tuple)>,
+}
+
+impl Explainer<'_, '_> {
+    fn program(&self) -> Result<NormalFormProgram> {
+        Ok(self.input.clone().into_normalized_program(self.tx)?.0)
+    }
+
+    /// The rows of the entry of `prog`.
+    fn evaluate(&mut self, prog: NormalFormProgram) -> Result<Vec<Tuple>> {
+        let (stratified, store_lifetimes) = prog.into_stratified_program()?;
+        let magic = stratified.magic_sets_rewrite(self.tx)?;
+        let compiled = self.tx.stratified_magic_compile(magic)?;
+        let (store, _) = self.tx.stratified_magic_evaluate(
+            &compiled,
+            store_lifetimes,
+            None,
+            None,
+            Poison::default(),
+            None,
+        )?;
+        Ok(store.all_iter().map(|t| t.into_tuple()).collect())
+    }
+
+    /// Explain `fact` of `rule`, which is known to hold unless `unverified`.
+    fn explain(
+        &mut self,
+        rule: &Symbol,
+        fact: &Tuple,
+        unverified: bool,
+    ) -> Result<Option<Derivation>> {
+        let key = (rule.clone(), fact.clone());
+        if let Some(found) = self.proved.get(&key) {
+            return Ok(Some(found.clone()));
+        }
+        if self.path.contains(&key) {
+            return Ok(None);
+        }
+        let prog = self.program()?;
+        let num_clauses = match prog.prog.get(rule) {
+            Some(NormalFormRulesOrFixed::Rules { rules }) => rules.len(),
+            _ => {
+                if unverified && !self.evaluate(prog)?.contains(fact) {
+                    return Ok(None);
+                }
+                return Ok(Some(Derivation {
+                    relation: rule.name.to_string(),
+                    fact: fact.clone(),
+                    kind: DerivationKind::Fixed,
+                    premises: vec![],
+                }));
+            }
+        };
+        self.path.insert(key.clone());
+        let mut found = None;
+        for clause in 0..num_clauses {
+            found = self.explain_by_clause(rule, clause, fact)?;
+            if found.is_some() {
+                break;
+            }
+        }
+        self.path.remove(&key);
+        if let Some(derivation) = &found {
+            self.proved.insert(key, derivation.clone());
+        }
+        Ok(found)
+    }
+
+    /// The program with `rule` replaced by its `clause`, restricted to the rows
+    /// agreeing with `fact` on its columns not aggregated, as entry. With `bindings`,
+    /// the entry returns the values of all variables of the clause instead, and does
+    /// not aggregate.
+    fn restricted_program(
+        &self,
+        rule: &Symbol,
+        clause: usize,
+        fact: &Tuple,
+        bindings: bool,
+    ) -> Result<(NormalFormProgram, NormalFormInlineRule)> {
+        let mut prog = self.program()?;
+        let rules = match prog.prog.get(rule) {
+            Some(NormalFormRulesOrFixed::Rules { rules }) => rules,
+            _ => unreachable!(),
+        };
+        let original = &rules[clause];
+        let span = SourceSpan(0, 0);
+        let mut body = original
+            .head
+            .iter()
+            .zip(&original.aggr)
+            .zip(fact)
+            .filter(|((_, aggr), _)| aggr.is_none())
+            .map(|((symb, _), val)| {
+                NormalFormAtom::Unification(Unification {
+                    binding: symb.clone(),
+                    expr: Expr::Const {
+                        val: val.clone(),
+                        span,
+                    },
+                    one_many_unif: false,
+                    span,
+                })
+            })
+            .collect_vec();
+        body.extend(original.body.iter().cloned());
+        let clause_rule = NormalFormInlineRule {
+            head: original.head.clone(),
+            aggr: original.aggr.clone(),
+            body: original.body.clone(),
+            hints: original.hints.clone(),
+        };
+        let entry = if bindings {
+            let vars = bound_variables(&original.body);
+            NormalFormInlineRule {
+                aggr: vec![None; vars.len()],
+                head: vars,
+                body,
+                hints: original.hints.clone(),
+            }
+        } else {
+            NormalFormInlineRule {
+                head: original.head.clone(),
+                aggr: original.aggr.clone(),
+                body,
+                hints: original.hints.clone(),
+            }
+        };
+        prog.prog.insert(
+            entry_symbol(),
+            NormalFormRulesOrFixed::Rules {
+                rules: vec![entry.convert_to_well_ordered_rule()?],
+            },
+        );
+        Ok((prog, clause_rule))
+    }
+
+    fn explain_by_clause(
+        &mut self,
+        rule: &Symbol,
+        clause: usize,
+        fact: &Tuple,
+    ) -> Result<Option<Derivation>> {
+        let (prog, clause_rule) = self.restricted_program(rule, clause, fact, false)?;
+        let aggregated = clause_rule.aggr.iter().any(|a| a.is_some());
+        // the restriction to the columns not aggregated does not check the others
+        if aggregated && !self.evaluate(prog)?.contains(fact) {
+            return Ok(None);
+        }
+        let (prog, _) = self.restricted_program(rule, clause, fact, true)?;
+        let vars = match prog.prog.get(&entry_symbol()) {
+            Some(NormalFormRulesOrFixed::Rules { rules }) => rules[0].head.clone(),
+            _ => unreachable!(),
+        };
+        let rows = self.evaluate(prog)?;
+        let mut premises: Vec<Derivation> = vec![];
+        for row in rows {
+            let env: BTreeMap<&Symbol, &DataValue> = vars.iter().zip(row.iter()).collect();
+            let Some(row_premises) = self.explain_row(&clause_rule.body, &env)? else {
+                continue;
+            };
+            if !aggregated {
+                return Ok(Some(Derivation {
+                    relation: rule.name.to_string(),
+                    fact: fact.clone(),
+                    kind: DerivationKind::Rule { clause },
+                    premises: row_premises,
+                }));
+            }
+            for premise in row_premises {
+                if !premises.contains(&premise) {
+                    premises.push(premise);
+                }
+            }
+        }
+        Ok(aggregated.then(|| Derivation {
+            relation: rule.name.to_string(),
+            fact: fact.clone(),
+            kind: DerivationKind::Aggregation { clause },
+            premises,
+        }))
+    }
+
+    /// Explain the atoms of `body` under the bindings `env`, or `None` if some
+    /// premise can only be explained by a fact being explained.
+    fn explain_row(
+        &mut self,
+        body: &[NormalFormAtom],
+        env: &BTreeMap<&Symbol, &DataValue>,
+    ) -> Result<Option<Vec<Derivation>>> {
+        let instantiate = |args: &[Symbol]| -> Tuple {
+            args.iter()
+                .map(|arg| env.get(arg).map_or(DataValue::Bot, |v| (*v).clone()))
+                .collect()
+        };
+        let mut premises = vec![];
+        for atom in body {
+            match atom {
+                NormalFormAtom::Rule(r) => {
+                    match self.explain(&r.name, &instantiate(&r.args), false)? {
+                        Some(derivation) => premises.push(derivation),
+                        None => return Ok(None),
+                    }
+                }
+                NormalFormAtom::Relation(r) => premises.push(Derivation {
+                    relation: r.name.name.to_string(),
+                    fact: instantiate(&r.args),
+                    kind: DerivationKind::Stored,
+                    premises: vec![],
+                }),
+                NormalFormAtom::NegatedRule(r) => premises.push(Derivation {
+                    relation: r.name.name.to_string(),
+                    fact: instantiate(&r.args),
+                    kind: DerivationKind::Absent,
+                    premises: vec![],
+                }),
+                NormalFormAtom::NegatedRelation(r) => premises.push(Derivation {
+                    relation: r.name.name.to_string(),
+                    fact: instantiate(&r.args),
+                    kind: DerivationKind::Absent,
+                    premises: vec![],
+                }),
+                // predicates, unifications and searches hold for the row by construction
+                NormalFormAtom::Predicate(_)
+                | NormalFormAtom::Unification(_)
+                | NormalFormAtom::HnswSearch(_)
+                | NormalFormAtom::FtsSearch(_)
+                | NormalFormAtom::LshSearch(_) => {}
+            }
+        }
+        Ok(Some(premises))
+    }
+}
+
+/// The variables bound by the atoms of `body`, in order.
+fn bound_variables(body: &[NormalFormAtom]) -> Vec<Symbol> {
+    let mut ret: BTreeSet<Symbol> = BTreeSet::new();
+    for atom in body {
+        match atom {
+            NormalFormAtom::Rule(r) => ret.extend(r.args.iter().cloned()),
+            NormalFormAtom::Relation(r) => ret.extend(r.args.iter().cloned()),
+            NormalFormAtom::Unification(u) => {
+                ret.insert(u.binding.clone());
+            }
+            NormalFormAtom::HnswSearch(s) => ret.extend(s.all_bindings().cloned()),
+            NormalFormAtom::FtsSearch(s) => ret.extend(s.all_bindings().cloned()),
+            NormalFormAtom::LshSearch(s) => ret.extend(s.all_bindings().cloned()),
+            NormalFormAtom::NegatedRule(_)
+            | NormalFormAtom::NegatedRelation(_)
+            | NormalFormAtom::Predicate(_) => {}
+        }
+    }
+    ret.into_iter().collect()
+}
@@ -0,0 +1,244 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Producing to Kafka with version 3 of the produce request, which carries record
+//! batches of the v2 format understood by brokers since 0.11.
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+use miette::{ensure, Diagnostic, IntoDiagnostic, Result};
+use thiserror::Error;
+
+use crate::sink::{Publisher, SinkMessage, IO_TIMEOUT};
+
+const PRODUCE_API_KEY: i16 = 0;
+const PRODUCE_API_VERSION: i16 = 3;
+const CLIENT_ID: &str = "cozo";
+/// Wait for all in-sync replicas
+const ACKS_ALL: i16 = -1;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The Kafka broker refused the record with error code {0}")]
+#[diagnostic(code(sink::kafka_error))]
+#[diagnostic(help(
+    "See the error codes of the Kafka protocol, code 6 means that the broker does not \
+    lead the partition"
+))]
+struct KafkaError(i16);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Malformed produce response from the Kafka broker")]
+#[diagnostic(code(sink::kafka_bad_response))]
+struct KafkaBadResponse;
+
+pub(crate) struct KafkaPublisher {
+    addr: String,
+    topic: String,
+    partition: i32,
+    conn: Option<TcpStream>,
+    correlation_id: i32,
+}
+
+impl KafkaPublisher {
+    pub(crate) fn new(addr: &str, topic: &str, partition: i32) -> Self {
+        Self {
+            addr: addr.to_string(),
+            topic: topic.to_string(),
+            partition,
+            conn: None,
+            correlation_id: 0,
+        }
+    }
+    fn connect(&self) -> Result<TcpStream> {
+        let stream = TcpStream::connect(&self.addr).into_diagnostic()?;
+        stream.set_read_timeout(Some(IO_TIMEOUT)).into_diagnostic()?;
+        stream.set_write_timeout(Some(IO_TIMEOUT)).into_diagnostic()?;
+        Ok(stream)
+    }
+    fn produce_request(&self, message: &SinkMessage) -> Vec<u8> {
+        let batch = record_batch(message);
+        let mut req = vec![0; 4];
+        req.extend_from_slice(&PRODUCE_API_KEY.to_be_bytes());
+        req.extend_from_slice(&PRODUCE_API_VERSION.to_be_bytes());
+        req.extend_from_slice(&self.correlation_id.to_be_bytes());
+        put_string(&mut req, CLIENT_ID);
+        // no transactional id
+        req.extend_from_slice(&(-1i16).to_be_bytes());
+        req.extend_from_slice(&ACKS_ALL.to_be_bytes());
+        req.extend_from_slice(&(IO_TIMEOUT.as_millis() as i32).to_be_bytes());
+        req.extend_from_slice(&1i32.to_be_bytes());
+        put_string(&mut req, &self.topic);
+        req.extend_from_slice(&1i32.to_be_bytes());
+        req.extend_from_slice(&self.partition.to_be_bytes());
+        req.extend_from_slice(&(batch.len() as i32).to_be_bytes());
+        req.extend_from_slice(&batch);
+        let size = (req.len() - 4) as i32;
+        req[..4].copy_from_slice(&size.to_be_bytes());
+        req
+    }
+}
+
+impl Publisher for KafkaPublisher {
+    fn publish(&mut self, message: &SinkMessage) -> Result<()> {
+        self.correlation_id = self.correlation_id.wrapping_add(1);
+        let req = self.produce_request(message);
+        let conn = match &mut self.conn {
+            Some(conn) => conn,
+            None => self.conn.insert(self.connect()?),
+        };
+        let res = produce_on(conn, &req, self.correlation_id);
+        if res.is_err() {
+            self.conn = None;
+        }
+        res
+    }
+}
+
+fn produce_on(conn: &mut TcpStream, req: &[u8], correlation_id: i32) -> Result<()> {
+    conn.write_all(req).into_diagnostic()?;
+    let mut size = [0; 4];
+    conn.read_exact(&mut size).into_diagnostic()?;
+    let mut resp = vec![0; i32::from_be_bytes(size).max(0) as usize];
+    conn.read_exact(&mut resp).into_diagnostic()?;
+    let mut reader = Reader(&resp);
+    ensure!(reader.i32()? == correlation_id, KafkaBadResponse);
+    // a single topic with a single partition was sent
+    ensure!(reader.i32()? == 1, KafkaBadResponse);
+    let topic_len = reader.i16()?.max(0) as usize;
+    reader.skip(topic_len)?;
+    ensure!(reader.i32()? == 1, KafkaBadResponse);
+    reader.i32()?;
+    let error_code = reader.i16()?;
+    ensure!(error_code == 0, KafkaError(error_code));
+    Ok(())
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn skip(&mut self, n: usize) -> Result<&'a [u8]> {
+        ensure!(self.0.len() >= n, KafkaBadResponse);
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+    fn i16(&mut self) -> Result<i16> {
+        Ok(i16::from_be_bytes(self.skip(2)?.try_into().unwrap()))
+    }
+    fn i32(&mut self) -> Result<i32> {
+        Ok(i32::from_be_bytes(self.skip(4)?.try_into().unwrap()))
+    }
+}
+
+/// A record batch holding a single record for `message`.
+fn record_batch(message: &SinkMessage) -> Vec<u8> {
+    let timestamp = (message.timestamp * 1000.) as i64;
+    let key = message.tx_id.to_string();
+    let mut record = vec![0];
+    // timestamp and offset deltas
+    put_varint(&mut record, 0);
+    put_varint(&mut record, 0);
+    put_varint(&mut record, key.len() as i64);
+    record.extend_from_slice(key.as_bytes());
+    put_varint(&mut record, message.payload.len() as i64);
+    record.extend_from_slice(&message.payload);
+    // no headers
+    put_varint(&mut record, 0);
+
+    // the part of the batch covered by the checksum
+    let mut checked = vec![];
+    // attributes: no compression, create time
+    checked.extend_from_slice(&0i16.to_be_bytes());
+    // last offset delta
+    checked.extend_from_slice(&0i32.to_be_bytes());
+    checked.extend_from_slice(&timestamp.to_be_bytes());
+    checked.extend_from_slice(&timestamp.to_be_bytes());
+    // producer id, producer epoch and base sequence of a non-idempotent producer
+    checked.extend_from_slice(&(-1i64).to_be_bytes());
+    checked.extend_from_slice(&(-1i16).to_be_bytes());
+    checked.extend_from_slice(&(-1i32).to_be_bytes());
+    checked.extend_from_slice(&1i32.to_be_bytes());
+    put_varint(&mut checked, record.len() as i64);
+    checked.extend_from_slice(&record);
+
+    let mut batch = vec![];
+    // base offset, assigned by the broker
+    batch.extend_from_slice(&0i64.to_be_bytes());
+    // the batch length counts the bytes after it: leader epoch, magic, crc and the rest
+    batch.extend_from_slice(&((4 + 1 + 4 + checked.len()) as i32).to_be_bytes());
+    batch.extend_from_slice(&(-1i32).to_be_bytes());
+    batch.push(2);
+    batch.extend_from_slice(&crc32c(&checked).to_be_bytes());
+    batch.extend_from_slice(&checked);
+    batch
+}
+
+fn put_string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as i16).to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+/// Zigzag encoded variable length integer, as used within records.
+fn put_varint(buf: &mut Vec<u8>, v: i64) {
+    let mut z = ((v << 1) ^ (v >> 63)) as u64;
+    while z >= 0x80 {
+        buf.push((z as u8) | 0x80);
+        z >>= 7;
+    }
+    buf.push(z as u8);
+}
+
+const CRC32C_TABLE: [u32; 256] = crc32c_table();
+
+const fn crc32c_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0x82F63B78
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// The Castagnoli CRC checksumming record batches.
+pub(crate) fn crc32c(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, b| {
+        CRC32C_TABLE[((crc ^ *b as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32c_check_value() {
+        assert_eq!(crc32c(b"123456789"), 0xE3069283);
+        assert_eq!(crc32c(b""), 0);
+    }
+
+    #[test]
+    fn zigzag_varints() {
+        let mut buf = vec![];
+        for v in [0, -1, 1, 63, -64, 64, 300] {
+            put_varint(&mut buf, v);
+        }
+        assert_eq!(buf, [0, 1, 2, 126, 127, 0x80, 1, 0xD8, 4]);
+    }
+}
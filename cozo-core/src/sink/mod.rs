@@ -0,0 +1,279 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Connectors publishing the committed transactions to message brokers, enabled by the
+//! `sink-nats` and `sink-kafka` features.
+//!
+//! A sink reads the transaction log with [`Db::subscribe_from`] and publishes one message
+//! per transaction, holding the changes in the shape of [`TxChanges::into_json`] serialized
+//! as JSON or MessagePack. A message the broker does not accept is retried until it is, so
+//! every transaction is published at least once and in the order of commit.
+
+#[cfg(feature = "sink-kafka")]
+pub(crate) mod kafka;
+#[cfg(feature = "sink-nats")]
+pub(crate) mod nats;
+
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crossbeam::channel::RecvTimeoutError;
+use log::{error, warn};
+use miette::{IntoDiagnostic, Result};
+
+use crate::{CozoError, Db, DbInstance, Storage, TxChanges};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Timeout of each read and write on the connection to the broker
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How a transaction is serialized in the published message.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SinkFormat {
+    /// The JSON of [`TxChanges::into_json`]
+    #[default]
+    Json,
+    /// The same value encoded as MessagePack, with maps keyed by strings
+    MessagePack,
+}
+
+impl SinkFormat {
+    fn encode(self, changes: TxChanges) -> Result<Vec<u8>> {
+        let json = changes.into_json();
+        Ok(match self {
+            SinkFormat::Json => json.to_string().into_bytes(),
+            SinkFormat::MessagePack => rmp_serde::to_vec(&json).into_diagnostic()?,
+        })
+    }
+}
+
+/// The broker a sink publishes to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SinkTarget {
+    /// Publish on a subject of a NATS server.
+    #[cfg(feature = "sink-nats")]
+    Nats {
+        /// Address of the server, as `host:port`
+        addr: String,
+        /// Subject the messages are published on
+        subject: String,
+    },
+    /// Produce to a partition of a Kafka topic. The records are keyed by the
+    /// transaction id in decimal and written with `acks=all`.
+    #[cfg(feature = "sink-kafka")]
+    Kafka {
+        /// Address of the broker leading the partition, as `host:port`
+        addr: String,
+        /// Topic the records are produced to
+        topic: String,
+        /// Partition of the topic the records are produced to
+        partition: i32,
+    },
+}
+
+impl SinkTarget {
+    fn publisher(&self) -> Box<dyn Publisher> {
+        match self {
+            #[cfg(feature = "sink-nats")]
+            SinkTarget::Nats { addr, subject } => {
+                Box::new(nats::NatsPublisher::new(addr, subject))
+            }
+            #[cfg(feature = "sink-kafka")]
+            SinkTarget::Kafka {
+                addr,
+                topic,
+                partition,
+            } => Box::new(kafka::KafkaPublisher::new(addr, topic, *partition)),
+        }
+    }
+}
+
+impl Display for SinkTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            #[cfg(feature = "sink-nats")]
+            SinkTarget::Nats { addr, subject } => write!(f, "NATS subject {subject} at {addr}"),
+            #[cfg(feature = "sink-kafka")]
+            SinkTarget::Kafka {
+                addr,
+                topic,
+                partition,
+            } => write!(f, "Kafka topic {topic}, partition {partition} at {addr}"),
+        }
+    }
+}
+
+/// The configuration of a sink, see [`Db::start_sink`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SinkConfig {
+    /// Where the transactions are published
+    pub target: SinkTarget,
+    /// How each transaction is serialized
+    pub format: SinkFormat,
+    /// Only changes to these relations are published, all of them if `None`.
+    /// Transactions changing none of them are not published.
+    pub relations: Option<Vec<String>>,
+}
+
+/// A message for a single transaction.
+pub(crate) struct SinkMessage {
+    pub(crate) tx_id: u64,
+    /// Seconds since the epoch of the commit, only Kafka records carry it
+    #[cfg_attr(not(feature = "sink-kafka"), allow(dead_code))]
+    pub(crate) timestamp: f64,
+    pub(crate) payload: Vec<u8>,
+}
+
+/// A connection to a broker, established on first use and again after each failure.
+pub(crate) trait Publisher: Send {
+    /// Publish `message`, returning once the broker has accepted it.
+    fn publish(&mut self, message: &SinkMessage) -> Result<()>;
+}
+
+/// A running sink, which stops when dropped.
+pub struct CdcSink {
+    stopped: Arc<AtomicBool>,
+    delivered: Arc<AtomicU64>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl CdcSink {
+    /// The id of the last transaction published or skipped. Transactions up to it may be
+    /// truncated off the log as far as this sink is concerned, and a sink started from it
+    /// resumes where this one stopped.
+    pub fn delivered(&self) -> u64 {
+        self.delivered.load(Ordering::SeqCst)
+    }
+    /// Whether the sink is still publishing. It stops on its own once the database is
+    /// closed.
+    pub fn is_running(&self) -> bool {
+        self.handle.as_ref().is_some_and(|handle| !handle.is_finished())
+    }
+    /// Stop the sink, waiting for the message being published, if any, to be accepted
+    /// or given up on. Returns the id of the last transaction delivered.
+    pub fn stop(mut self) -> u64 {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+        self.delivered()
+    }
+}
+
+impl Drop for CdcSink {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+impl<S> Db<S>
+where
+    S: for<'s> Storage<'s> + 'static,
+{
+    /// Start publishing the transactions committed after `tx_id` to a broker, in a
+    /// background thread running as long as the returned sink is kept and the database
+    /// is open. The transaction log must be enabled, see
+    /// [`subscribe_from`](Self::subscribe_from).
+    pub fn start_sink(&self, config: SinkConfig, tx_id: u64) -> Result<CdcSink> {
+        let receiver = self.subscribe_from(tx_id)?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let delivered = Arc::new(AtomicU64::new(tx_id));
+        let handle = {
+            let stopped = stopped.clone();
+            let delivered = delivered.clone();
+            thread::spawn(move || {
+                let mut publisher = config.target.publisher();
+                while !stopped.load(Ordering::SeqCst) {
+                    let mut changes = match receiver.recv_timeout(POLL_INTERVAL) {
+                        Ok(changes) => changes,
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    };
+                    let tx_id = changes.tx_id;
+                    if let Some(relations) = &config.relations {
+                        changes.relations.retain(|name, _| relations.contains(name));
+                    }
+                    if !changes.relations.is_empty() {
+                        let message = SinkMessage {
+                            tx_id,
+                            timestamp: changes.timestamp,
+                            payload: match config.format.encode(changes) {
+                                Ok(payload) => payload,
+                                Err(err) => {
+                                    error!("encoding transaction {tx_id} failed: {err:?}");
+                                    return;
+                                }
+                            },
+                        };
+                        if !publish(&mut *publisher, &message, &config.target, &stopped) {
+                            return;
+                        }
+                    }
+                    delivered.store(tx_id, Ordering::SeqCst);
+                }
+            })
+        };
+        Ok(CdcSink {
+            stopped,
+            delivered,
+            handle: Some(handle),
+        })
+    }
+}
+
+/// Publish `message` until the broker accepts it, backing off between the attempts.
+/// Returns `false` if the sink was stopped first.
+fn publish(
+    publisher: &mut dyn Publisher,
+    message: &SinkMessage,
+    target: &SinkTarget,
+    stopped: &AtomicBool,
+) -> bool {
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        let err = match publisher.publish(message) {
+            Ok(()) => return true,
+            Err(err) => err,
+        };
+        warn!(
+            "publishing transaction {} to {target} failed, retrying in {backoff:?}: {err}",
+            message.tx_id
+        );
+        let retry_at = Instant::now() + backoff;
+        while Instant::now() < retry_at {
+            if stopped.load(Ordering::SeqCst) {
+                return false;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+impl DbInstance {
+    /// Dispatcher method. See [crate::Db::start_sink].
+    pub fn start_sink(&self, config: SinkConfig, tx_id: u64) -> Result<CdcSink, CozoError> {
+        Ok(match self {
+            DbInstance::Mem(db) => db.start_sink(config, tx_id)?,
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.start_sink(config, tx_id)?,
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.start_sink(config, tx_id)?,
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.start_sink(config, tx_id)?,
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.start_sink(config, tx_id)?,
+        })
+    }
+}
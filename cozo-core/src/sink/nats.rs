@@ -0,0 +1,89 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Publishing over the text protocol of NATS core.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+
+use miette::{bail, ensure, Diagnostic, IntoDiagnostic, Result};
+use thiserror::Error;
+
+use crate::sink::{Publisher, SinkMessage, IO_TIMEOUT};
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The NATS server answered '{0}'")]
+#[diagnostic(code(sink::nats_error))]
+struct NatsError(String);
+
+pub(crate) struct NatsPublisher {
+    addr: String,
+    subject: String,
+    conn: Option<BufReader<TcpStream>>,
+}
+
+impl NatsPublisher {
+    pub(crate) fn new(addr: &str, subject: &str) -> Self {
+        Self {
+            addr: addr.to_string(),
+            subject: subject.to_string(),
+            conn: None,
+        }
+    }
+    fn connect(&self) -> Result<BufReader<TcpStream>> {
+        let stream = TcpStream::connect(&self.addr).into_diagnostic()?;
+        stream.set_read_timeout(Some(IO_TIMEOUT)).into_diagnostic()?;
+        stream.set_write_timeout(Some(IO_TIMEOUT)).into_diagnostic()?;
+        let mut conn = BufReader::new(stream);
+        let info = read_line(&mut conn)?;
+        ensure!(info.starts_with("INFO"), NatsError(info));
+        conn.get_mut()
+            .write_all(b"CONNECT {\"verbose\":false,\"pedantic\":false,\"name\":\"cozo\"}\r\n")
+            .into_diagnostic()?;
+        Ok(conn)
+    }
+}
+
+impl Publisher for NatsPublisher {
+    fn publish(&mut self, message: &SinkMessage) -> Result<()> {
+        let conn = match &mut self.conn {
+            Some(conn) => conn,
+            None => self.conn.insert(self.connect()?),
+        };
+        let res = publish_on(conn, &self.subject, &message.payload);
+        if res.is_err() {
+            self.conn = None;
+        }
+        res
+    }
+}
+
+fn publish_on(conn: &mut BufReader<TcpStream>, subject: &str, payload: &[u8]) -> Result<()> {
+    let mut bytes = format!("PUB {subject} {}\r\n", payload.len()).into_bytes();
+    bytes.extend_from_slice(payload);
+    // the server answers the ping once it has processed the message before it
+    bytes.extend_from_slice(b"\r\nPING\r\n");
+    conn.get_mut().write_all(&bytes).into_diagnostic()?;
+    loop {
+        let line = read_line(conn)?;
+        match line.as_str() {
+            "PONG" => return Ok(()),
+            "PING" => conn.get_mut().write_all(b"PONG\r\n").into_diagnostic()?,
+            "+OK" => {}
+            l if l.starts_with("INFO") => {}
+            _ => bail!(NatsError(line)),
+        }
+    }
+}
+
+fn read_line(conn: &mut BufReader<TcpStream>) -> Result<String> {
+    let mut line = String::new();
+    let n = conn.read_line(&mut line).into_diagnostic()?;
+    ensure!(n > 0, NatsError("<connection closed>".to_string()));
+    Ok(line.trim_end().to_string())
+}
@@ -0,0 +1,439 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread;
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread::JoinHandle;
+
+use itertools::Itertools;
+use miette::{bail, miette, Diagnostic, IntoDiagnostic, Result};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use thiserror::Error;
+
+use crate::data::tuple::Tuple;
+use crate::data::value::ValidityTs;
+use crate::runtime::relation::decode_tuple_from_kv;
+use crate::storage::{SeekingSkipIterator, Storage, StoreTx, TxDurability};
+use crate::Db;
+
+/// Stored unencrypted in the underlying storage. The key lies in the namespace range
+/// of the empty name, which can never be opened.
+const HEADER_KEY: &[u8] = b"\xff\x00encryption";
+const KEY_ID_LEN: usize = 4;
+const KEY_CHECK_PLAINTEXT: &[u8] = b"cozo";
+
+/// A key for [`Db::with_encryption`].
+#[derive(Clone)]
+pub struct EncryptionKey {
+    /// Identifies the key. Every stored value records the id of the key that encrypted it.
+    pub id: u32,
+    /// The AES-256 key
+    pub key: [u8; 32],
+}
+
+#[derive(Debug, Default, serde_derive::Serialize, serde_derive::Deserialize)]
+struct EncryptionHeader {
+    current_key_id: u32,
+    /// Encryptions of a fixed plaintext, used to detect wrong keys on opening
+    key_checks: BTreeMap<u32, Vec<u8>>,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Value encrypted with key {0}, which was not supplied")]
+#[diagnostic(code(storage::missing_encryption_key))]
+#[diagnostic(help(
+    "Supply every key that data may still be encrypted with, until re-encryption finishes"
+))]
+struct MissingEncryptionKey(u32);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Encryption key {0} does not match the one the database was encrypted with")]
+#[diagnostic(code(storage::wrong_encryption_key))]
+struct WrongEncryptionKey(u32);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot decrypt stored value: data corrupted or tampered with")]
+#[diagnostic(code(storage::decryption_failure))]
+struct DecryptionFailure;
+
+struct Keyring {
+    current_id: u32,
+    keys: BTreeMap<u32, LessSafeKey>,
+    rng: SystemRandom,
+}
+
+impl Keyring {
+    fn new(current: &EncryptionKey, previous: &[EncryptionKey]) -> Result<Self> {
+        let mut keys = BTreeMap::new();
+        for k in previous.iter().chain([current]) {
+            let unbound = UnboundKey::new(&AES_256_GCM, &k.key)
+                .map_err(|_| miette!("invalid AES-256 key"))?;
+            keys.insert(k.id, LessSafeKey::new(unbound));
+        }
+        Ok(Self {
+            current_id: current.id,
+            keys,
+            rng: SystemRandom::new(),
+        })
+    }
+
+    fn encrypt(&self, aad: &[u8], val: &[u8]) -> Result<Vec<u8>> {
+        let key_id = self.current_id;
+        let key = &self.keys[&key_id];
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng
+            .fill(&mut nonce)
+            .map_err(|_| miette!("failed to generate nonce"))?;
+        let mut ret = Vec::with_capacity(KEY_ID_LEN + NONCE_LEN + val.len() + 16);
+        ret.extend_from_slice(&key_id.to_be_bytes());
+        ret.extend_from_slice(&nonce);
+        let mut sealed = val.to_vec();
+        key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(aad),
+            &mut sealed,
+        )
+        .map_err(|_| miette!("encryption failed"))?;
+        ret.extend_from_slice(&sealed);
+        Ok(ret)
+    }
+
+    fn decrypt(&self, aad: &[u8], stored: &[u8]) -> Result<Vec<u8>> {
+        if stored.len() < KEY_ID_LEN + NONCE_LEN {
+            bail!(DecryptionFailure)
+        }
+        let key_id = stored_key_id(stored);
+        let key = self.keys.get(&key_id).ok_or(MissingEncryptionKey(key_id))?;
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce.copy_from_slice(&stored[KEY_ID_LEN..KEY_ID_LEN + NONCE_LEN]);
+        let mut opened = stored[KEY_ID_LEN + NONCE_LEN..].to_vec();
+        let len = key
+            .open_in_place(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(aad),
+                &mut opened,
+            )
+            .map_err(|_| DecryptionFailure)?
+            .len();
+        opened.truncate(len);
+        Ok(opened)
+    }
+}
+
+fn stored_key_id(stored: &[u8]) -> u32 {
+    u32::from_be_bytes([stored[0], stored[1], stored[2], stored[3]])
+}
+
+/// A storage whose values are all encrypted with AES-256-GCM before reaching the
+/// wrapped storage. Keys are stored in the clear, as their order must be preserved.
+/// Obtained through [`Db::with_encryption`].
+#[derive(Clone)]
+pub struct EncryptedStorage<S> {
+    inner: S,
+    keyring: Arc<Keyring>,
+}
+
+impl<'s, S: Storage<'s>> Storage<'s> for EncryptedStorage<S> {
+    type Tx = EncryptedTx<'s, S::Tx>;
+
+    fn storage_kind(&self) -> &'static str {
+        self.inner.storage_kind()
+    }
+
+    fn transact(&'s self, write: bool) -> Result<Self::Tx> {
+        Ok(EncryptedTx {
+            inner: self.inner.transact(write)?,
+            keyring: self.keyring.clone(),
+            _marker: PhantomData,
+        })
+    }
+
+    fn transact_write_with(&'s self, durability: TxDurability) -> Result<Self::Tx> {
+        Ok(EncryptedTx {
+            inner: self.inner.transact_write_with(durability)?,
+            keyring: self.keyring.clone(),
+            _marker: PhantomData,
+        })
+    }
+
+    fn range_compact(&'s self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.inner.range_compact(lower, upper)
+    }
+
+    fn batch_put<'a>(
+        &'a self,
+        data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
+    ) -> Result<()> {
+        let keyring = self.keyring.clone();
+        self.inner.batch_put(Box::new(data.map(move |kv| {
+            let (k, v) = kv?;
+            let encrypted = keyring.encrypt(&k, &v)?;
+            Ok((k, encrypted))
+        })))
+    }
+    fn approximate_size(&'s self, lower: &[u8], upper: &[u8]) -> Result<Option<u64>> {
+        self.inner.approximate_size(lower, upper)
+    }
+
+    fn engine_stats(&'s self) -> Result<BTreeMap<String, String>> {
+        self.inner.engine_stats()
+    }
+
+    fn close(&'s self) -> Result<()> {
+        self.inner.close()
+    }
+}
+
+/// Transaction type of [`EncryptedStorage`].
+pub struct EncryptedTx<'s, T> {
+    inner: T,
+    keyring: Arc<Keyring>,
+    _marker: PhantomData<&'s ()>,
+}
+
+impl<'s, T: StoreTx<'s>> StoreTx<'s> for EncryptedTx<'s, T> {
+    fn get(&self, key: &[u8], for_update: bool) -> Result<Option<Vec<u8>>> {
+        match self.inner.get(key, for_update)? {
+            None => Ok(None),
+            Some(stored) => Ok(Some(self.keyring.decrypt(key, &stored)?)),
+        }
+    }
+
+    fn multi_get(&self, keys: &[Vec<u8>], for_update: bool) -> Result<Vec<Option<Vec<u8>>>> {
+        self.inner
+            .multi_get(keys, for_update)?
+            .into_iter()
+            .zip(keys)
+            .map(|(stored, key)| match stored {
+                None => Ok(None),
+                Some(stored) => Ok(Some(self.keyring.decrypt(key, &stored)?)),
+            })
+            .collect()
+    }
+
+    fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        let encrypted = self.keyring.encrypt(key, val)?;
+        self.inner.put(key, &encrypted)
+    }
+
+    fn supports_par_put(&self) -> bool {
+        self.inner.supports_par_put()
+    }
+
+    fn par_put(&self, key: &[u8], val: &[u8]) -> Result<()> {
+        let encrypted = self.keyring.encrypt(key, val)?;
+        self.inner.par_put(key, &encrypted)
+    }
+
+    fn del(&mut self, key: &[u8]) -> Result<()> {
+        self.inner.del(key)
+    }
+
+    fn par_del(&self, key: &[u8]) -> Result<()> {
+        self.inner.par_del(key)
+    }
+
+    fn del_range_from_persisted(&mut self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.inner.del_range_from_persisted(lower, upper)
+    }
+
+    fn exists(&self, key: &[u8], for_update: bool) -> Result<bool> {
+        self.inner.exists(key, for_update)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.inner.commit()
+    }
+
+    fn range_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a>
+    where
+        's: 'a,
+    {
+        Box::new(
+            self.range_scan(lower, upper)
+                .map_ok(|(k, v)| decode_tuple_from_kv(&k, &v, None)),
+        )
+    }
+
+    fn range_skip_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        valid_at: ValidityTs,
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
+        // the skip scan of the wrapped storage would decode the encrypted values
+        Box::new(SeekingSkipIterator::new(self, lower, upper, valid_at))
+    }
+
+    fn range_scan<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        Box::new(
+            self.inner
+                .range_scan(lower, upper)
+                .filter_ok(|(k, _)| k != HEADER_KEY)
+                .map(|kv| {
+                    let (k, v) = kv?;
+                    let decrypted = self.keyring.decrypt(&k, &v)?;
+                    Ok((k, decrypted))
+                }),
+        )
+    }
+
+    fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
+    where
+        's: 'a,
+    {
+        self.inner.range_count(lower, upper)
+    }
+
+    fn total_scan<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        Box::new(
+            self.inner
+                .total_scan()
+                .filter_ok(|(k, _)| k != HEADER_KEY)
+                .map(|kv| {
+                    let (k, v) = kv?;
+                    let decrypted = self.keyring.decrypt(&k, &v)?;
+                    Ok((k, decrypted))
+                }),
+        )
+    }
+}
+
+impl<S> Db<S>
+where
+    S: for<'s> Storage<'s>,
+{
+    /// Turn this database into one whose stored values are transparently encrypted
+    /// with `current`. The first time this is done for a storage, all existing values
+    /// are encrypted in place. The id of the current key is recorded in a header.
+    ///
+    /// To rotate keys, pass the new key as `current` and the keys used so far as
+    /// `previous`, then call [`reencrypt`](Db::reencrypt) (or
+    /// [`start_reencryption`](Db::start_reencryption)). Values not yet re-encrypted remain
+    /// readable as long as their key is supplied.
+    ///
+    /// This must be called on a freshly opened database, before any query is run.
+    pub fn with_encryption(
+        self,
+        current: EncryptionKey,
+        previous: Vec<EncryptionKey>,
+    ) -> Result<Db<EncryptedStorage<S>>> {
+        let keyring = Keyring::new(&current, &previous)?;
+        let inner = self.db;
+        {
+            let mut tx = inner.transact(true)?;
+            let mut header: EncryptionHeader = match tx.get(HEADER_KEY, true)? {
+                None => {
+                    // encrypt everything written in the clear so far
+                    let existing: Vec<_> = tx.total_scan().try_collect()?;
+                    for (k, v) in existing {
+                        tx.put(&k, &keyring.encrypt(&k, &v)?)?;
+                    }
+                    EncryptionHeader::default()
+                }
+                Some(data) => rmp_serde::from_slice(&data).into_diagnostic()?,
+            };
+            for (id, check) in header.key_checks.iter() {
+                if keyring.keys.contains_key(id) && keyring.decrypt(HEADER_KEY, check).is_err() {
+                    bail!(WrongEncryptionKey(*id))
+                }
+            }
+            header.current_key_id = current.id;
+            if let Entry::Vacant(entry) = header.key_checks.entry(current.id) {
+                entry.insert(keyring.encrypt(HEADER_KEY, KEY_CHECK_PLAINTEXT)?);
+            }
+            let encoded = rmp_serde::to_vec_named(&header).into_diagnostic()?;
+            tx.put(HEADER_KEY, &encoded)?;
+            tx.commit()?;
+        }
+        let ret = Db::new(EncryptedStorage {
+            inner,
+            keyring: Arc::new(keyring),
+        })?;
+        ret.initialize()?;
+        Ok(ret)
+    }
+}
+
+impl<S> Db<EncryptedStorage<S>>
+where
+    S: for<'s> Storage<'s>,
+{
+    /// Re-encrypt with the current key every value that was encrypted with a previous key,
+    /// `batch_size` values per transaction. Returns the number of values re-encrypted.
+    pub fn reencrypt(&self, batch_size: usize) -> Result<usize> {
+        let storage = &self.db;
+        let keyring = &storage.keyring;
+        let mut count = 0;
+        let mut lower = vec![];
+        loop {
+            let batch = {
+                let tx = storage.inner.transact(false)?;
+                let batch: Vec<_> = tx
+                    .range_scan(&lower, &[0xFF, 0xFF])
+                    .filter_ok(|(k, v)| {
+                        k != HEADER_KEY
+                            && (v.len() < KEY_ID_LEN || stored_key_id(v) != keyring.current_id)
+                    })
+                    .map_ok(|(k, _)| k)
+                    .take(batch_size)
+                    .try_collect()?;
+                batch
+            };
+            if batch.is_empty() {
+                return Ok(count);
+            }
+            let mut tx = storage.inner.transact(true)?;
+            for k in batch.iter() {
+                // re-read under the write transaction, the value may have been overwritten
+                if let Some(stored) = tx.get(k, true)? {
+                    if stored.len() < KEY_ID_LEN || stored_key_id(&stored) != keyring.current_id {
+                        let val = keyring.decrypt(k, &stored)?;
+                        tx.put(k, &keyring.encrypt(k, &val)?)?;
+                        count += 1;
+                    }
+                }
+            }
+            tx.commit()?;
+            lower = batch.last().unwrap().clone();
+            lower.push(0);
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<S> Db<EncryptedStorage<S>>
+where
+    S: for<'s> Storage<'s> + 'static,
+{
+    /// Run [`reencrypt`](Self::reencrypt) in a background thread.
+    pub fn start_reencryption(&self, batch_size: usize) -> JoinHandle<Result<usize>> {
+        let db = self.clone();
+        thread::spawn(move || db.reencrypt(batch_size))
+    }
+}
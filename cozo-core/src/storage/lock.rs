@@ -0,0 +1,118 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::fs;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use miette::{Diagnostic, IntoDiagnostic, Result, WrapErr};
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::runtime::db::seconds_since_the_epoch;
+
+const LOCK_FILE_NAME: &str = "cozo.lock";
+
+/// Returned when opening a database directory that another database object,
+/// in this process or another one, already holds open.
+#[derive(Debug, Error, Diagnostic)]
+#[error("The database at {path} is already open in process {pid}, since {since} (seconds since the epoch)")]
+#[diagnostic(code(db::already_open))]
+#[diagnostic(help("Close the other database first"))]
+pub struct AlreadyOpen {
+    /// The database directory
+    pub path: String,
+    /// Id of the process holding the database, 0 if it has not written it yet
+    pub pid: u32,
+    /// When the database was opened, in seconds since the epoch
+    pub since: f64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct LockHolder {
+    pid: u32,
+    since: f64,
+}
+
+/// An advisory lock of the system on the lock file of a database directory, held until
+/// released or dropped. The system releases it when the process exits, so a crash
+/// never leaves the directory locked. The file records the holder, for the message of
+/// [`AlreadyOpen`], and is left in place: removing it would let an opener lock a new
+/// file while another one still waits on the old one.
+pub(crate) struct DirLock {
+    file: Mutex<Option<File>>,
+}
+
+impl DirLock {
+    pub(crate) fn acquire(dir: &Path) -> Result<Self> {
+        let path = dir.join(LOCK_FILE_NAME);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .into_diagnostic()
+            .wrap_err_with(|| "when opening lock file")?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let holder = serde_json::from_slice::<LockHolder>(
+                    &fs::read(&path).unwrap_or_default(),
+                )
+                .unwrap_or_default();
+                return Err(AlreadyOpen {
+                    path: dir.to_string_lossy().to_string(),
+                    pid: holder.pid,
+                    since: holder.since,
+                }
+                .into());
+            }
+            Err(TryLockError::Error(err)) => {
+                return Err(err)
+                    .into_diagnostic()
+                    .wrap_err_with(|| "when locking lock file")
+            }
+        }
+        let holder = LockHolder {
+            pid: std::process::id(),
+            since: seconds_since_the_epoch()?,
+        };
+        file.set_len(0)
+            .and_then(|_| file.write_all(&serde_json::to_vec(&holder).unwrap()))
+            .and_then(|_| file.flush())
+            .into_diagnostic()
+            .wrap_err_with(|| "when writing lock file")?;
+        Ok(Self {
+            file: Mutex::new(Some(file)),
+        })
+    }
+
+    /// Release the lock. Idempotent.
+    pub(crate) fn release(&self) {
+        // closing the file releases the lock
+        self.file.lock().unwrap().take();
+    }
+}
+
+/// Remove the lock file of the database directory at `path`. The lock is released when
+/// the process holding it exits, even without closing the database, so this is never
+/// needed to open a directory again. Only use this when no other process has the
+/// database open, otherwise the data can get corrupted, as a new opener locks a new
+/// file. Returns whether a lock file was found.
+pub fn force_unlock(path: impl AsRef<Path>) -> Result<bool> {
+    match fs::remove_file(path.as_ref().join(LOCK_FILE_NAME)) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err)
+            .into_diagnostic()
+            .wrap_err_with(|| "when removing lock file"),
+    }
+}
@@ -6,16 +6,27 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+
 use itertools::Itertools;
 use miette::Result;
 
-use crate::data::tuple::Tuple;
+use crate::data::tuple::{check_key_for_validity, Tuple};
 use crate::data::value::ValidityTs;
 use crate::decode_tuple_from_kv;
+use crate::runtime::relation::extend_tuple_from_v;
 
+#[cfg(feature = "encryption")]
+pub(crate) mod encrypted;
+#[cfg(any(feature = "storage-rocksdb", feature = "storage-sled"))]
+pub(crate) mod lock;
 pub(crate) mod mem;
+pub(crate) mod ns;
 #[cfg(feature = "storage-rocksdb")]
 pub(crate) mod rocks;
+#[cfg(test)]
+pub(crate) mod sim;
 #[cfg(feature = "storage-sled")]
 pub(crate) mod sled;
 #[cfg(feature = "storage-sqlite")]
@@ -25,6 +36,24 @@ pub(crate) mod temp;
 pub(crate) mod tikv;
 // pub(crate) mod re;
 
+/// How durable the commit of a single write transaction is, see
+/// [`Db::run_script_with_durability`](crate::Db::run_script_with_durability).
+/// Only the RocksDB engine distinguishes the levels.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TxDurability {
+    /// As set by the options of the database
+    #[default]
+    Default,
+    /// Skip the write-ahead log. The writes are lost if the process crashes before
+    /// they are flushed to the data files.
+    NoSync,
+    /// Write to the write-ahead log without syncing it, so that the writes survive a crash
+    /// of the process but not necessarily of the machine
+    Async,
+    /// Sync the write-ahead log before the commit returns
+    Sync,
+}
+
 /// Swappable storage trait for Cozo's storage engine
 pub trait Storage<'s>: Send + Sync + Clone {
     /// The associated transaction type used by this engine
@@ -36,6 +65,13 @@ pub trait Storage<'s>: Send + Sync + Clone {
     /// Create a transaction object. Write ops will only be called when `write == true`.
     fn transact(&'s self, write: bool) -> Result<Self::Tx>;
 
+    /// Create a write transaction whose commit is as durable as `durability` asks.
+    /// Engines without durability settings ignore it.
+    fn transact_write_with(&'s self, durability: TxDurability) -> Result<Self::Tx> {
+        let _ = durability;
+        self.transact(true)
+    }
+
     /// Compact the key range. Can be a no-op if the storage engine does not
     /// have the concept of compaction.
     fn range_compact(&'s self, lower: &[u8], upper: &[u8]) -> Result<()>;
@@ -47,6 +83,27 @@ pub trait Storage<'s>: Send + Sync + Clone {
         &'a self,
         data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
     ) -> Result<()>;
+
+    /// Estimate the number of bytes the key range takes in the storage.
+    /// The default implementation returns `None`, meaning that the engine cannot
+    /// estimate sizes cheaply.
+    fn approximate_size(&'s self, _lower: &[u8], _upper: &[u8]) -> Result<Option<u64>> {
+        Ok(None)
+    }
+
+    /// Engine-specific statistics, as named textual reports.
+    /// The default implementation returns nothing.
+    fn engine_stats(&'s self) -> Result<BTreeMap<String, String>> {
+        Ok(BTreeMap::new())
+    }
+
+    /// Flush pending writes to durable storage and release the resources held by the
+    /// engine, such as file handles and locks. Called by [`Db::close`](crate::Db::close)
+    /// once no transaction is active: the storage is not used afterwards.
+    /// The default implementation does nothing.
+    fn close(&'s self) -> Result<()> {
+        Ok(())
+    }
 }
 
 /// Trait for the associated transaction type of a storage engine.
@@ -161,3 +218,49 @@ pub trait StoreTx<'s>: Sync {
     where
         's: 'a;
 }
+
+/// A skip scan built on top of [`StoreTx::range_scan`] of `tx`, seeking one candidate at a time.
+/// Used by storage wrappers that transform keys or values, for which the skip scan of the
+/// wrapped storage cannot be used directly.
+pub(crate) struct SeekingSkipIterator<'a, 's, T> {
+    tx: &'a T,
+    upper: Vec<u8>,
+    valid_at: ValidityTs,
+    next_bound: Vec<u8>,
+    _marker: PhantomData<&'s ()>,
+}
+
+impl<'a, 's, T> SeekingSkipIterator<'a, 's, T> {
+    pub(crate) fn new(tx: &'a T, lower: &[u8], upper: &[u8], valid_at: ValidityTs) -> Self {
+        Self {
+            tx,
+            upper: upper.to_vec(),
+            valid_at,
+            next_bound: lower.to_vec(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, 's: 'a, T: StoreTx<'s>> Iterator for SeekingSkipIterator<'a, 's, T> {
+    type Item = Result<Tuple>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let nxt = self.tx.range_scan(&self.next_bound, &self.upper).next();
+            match nxt {
+                None => return None,
+                Some(Err(err)) => return Some(Err(err)),
+                Some(Ok((candidate_key, candidate_val))) => {
+                    let (ret, nxt_bound) =
+                        check_key_for_validity(&candidate_key, self.valid_at, None);
+                    self.next_bound = nxt_bound;
+                    if let Some(mut nk) = ret {
+                        extend_tuple_from_v(&mut nk, &candidate_val);
+                        return Some(Ok(nk));
+                    }
+                }
+            }
+        }
+    }
+}
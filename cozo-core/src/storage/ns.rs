@@ -0,0 +1,243 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use itertools::Itertools;
+use miette::{Diagnostic, Result};
+use thiserror::Error;
+
+use crate::data::tuple::Tuple;
+use crate::data::value::ValidityTs;
+use crate::runtime::relation::decode_tuple_from_kv;
+use crate::storage::{SeekingSkipIterator, Storage, StoreTx, TxDurability};
+
+/// First byte of every namespaced key. Keys of the default namespace always start with
+/// a relation id, whose leading bytes are zero, so the two never overlap.
+pub(crate) const NS_KEY_MARKER: u8 = 0xFF;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Invalid namespace name '{0}'")]
+#[diagnostic(code(db::invalid_namespace))]
+#[diagnostic(help("Namespace names must be non-empty and must not contain NUL characters"))]
+pub(crate) struct InvalidNamespace(pub(crate) String);
+
+/// Key prefix shared by everything stored in the namespace `name`.
+pub(crate) fn ns_prefix(name: &str) -> Result<Vec<u8>> {
+    if name.is_empty() || name.contains('\0') {
+        return Err(InvalidNamespace(name.to_string()).into());
+    }
+    let mut prefix = Vec::with_capacity(name.len() + 2);
+    prefix.push(NS_KEY_MARKER);
+    prefix.extend_from_slice(name.as_bytes());
+    prefix.push(0);
+    Ok(prefix)
+}
+
+/// A logical database living inside another storage, with all its keys stored under
+/// a distinct prefix. Obtained through [`Db::open_ns`](crate::Db::open_ns).
+#[derive(Clone)]
+pub struct NsStorage<S> {
+    inner: S,
+    prefix: Arc<Vec<u8>>,
+}
+
+impl<S> NsStorage<S> {
+    pub(crate) fn new(inner: S, name: &str) -> Result<Self> {
+        Ok(Self {
+            inner,
+            prefix: Arc::new(ns_prefix(name)?),
+        })
+    }
+}
+
+fn prefixed(prefix: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(prefix.len() + key.len());
+    ret.extend_from_slice(prefix);
+    ret.extend_from_slice(key);
+    ret
+}
+
+/// The exclusive upper bound of all keys carrying `prefix`.
+pub(crate) fn ns_prefix_upper(prefix: &[u8]) -> Vec<u8> {
+    let mut ret = prefix.to_vec();
+    // the prefix always ends with the NUL terminator
+    *ret.last_mut().unwrap() = 1;
+    ret
+}
+
+impl<'s, S: Storage<'s>> Storage<'s> for NsStorage<S> {
+    type Tx = NsTx<'s, S::Tx>;
+
+    fn storage_kind(&self) -> &'static str {
+        self.inner.storage_kind()
+    }
+
+    fn transact(&'s self, write: bool) -> Result<Self::Tx> {
+        Ok(NsTx {
+            inner: self.inner.transact(write)?,
+            prefix: self.prefix.clone(),
+            _marker: PhantomData,
+        })
+    }
+
+    fn transact_write_with(&'s self, durability: TxDurability) -> Result<Self::Tx> {
+        Ok(NsTx {
+            inner: self.inner.transact_write_with(durability)?,
+            prefix: self.prefix.clone(),
+            _marker: PhantomData,
+        })
+    }
+
+    fn range_compact(&'s self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.inner.range_compact(
+            &prefixed(&self.prefix, lower),
+            &prefixed(&self.prefix, upper),
+        )
+    }
+
+    fn batch_put<'a>(
+        &'a self,
+        data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
+    ) -> Result<()> {
+        let prefix = self.prefix.clone();
+        self.inner.batch_put(Box::new(
+            data.map_ok(move |(k, v)| (prefixed(&prefix, &k), v)),
+        ))
+    }
+    fn approximate_size(&'s self, lower: &[u8], upper: &[u8]) -> Result<Option<u64>> {
+        self.inner.approximate_size(
+            &prefixed(&self.prefix, lower),
+            &prefixed(&self.prefix, upper),
+        )
+    }
+
+    fn engine_stats(&'s self) -> Result<BTreeMap<String, String>> {
+        self.inner.engine_stats()
+    }
+}
+
+/// Transaction type of [`NsStorage`].
+pub struct NsTx<'s, T> {
+    inner: T,
+    prefix: Arc<Vec<u8>>,
+    _marker: PhantomData<&'s ()>,
+}
+
+impl<'s, T: StoreTx<'s>> StoreTx<'s> for NsTx<'s, T> {
+    fn get(&self, key: &[u8], for_update: bool) -> Result<Option<Vec<u8>>> {
+        self.inner.get(&prefixed(&self.prefix, key), for_update)
+    }
+
+    fn multi_get(&self, keys: &[Vec<u8>], for_update: bool) -> Result<Vec<Option<Vec<u8>>>> {
+        let keys = keys.iter().map(|k| prefixed(&self.prefix, k)).collect_vec();
+        self.inner.multi_get(&keys, for_update)
+    }
+
+    fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.inner.put(&prefixed(&self.prefix, key), val)
+    }
+
+    fn supports_par_put(&self) -> bool {
+        self.inner.supports_par_put()
+    }
+
+    fn par_put(&self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.inner.par_put(&prefixed(&self.prefix, key), val)
+    }
+
+    fn del(&mut self, key: &[u8]) -> Result<()> {
+        self.inner.del(&prefixed(&self.prefix, key))
+    }
+
+    fn par_del(&self, key: &[u8]) -> Result<()> {
+        self.inner.par_del(&prefixed(&self.prefix, key))
+    }
+
+    fn del_range_from_persisted(&mut self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.inner.del_range_from_persisted(
+            &prefixed(&self.prefix, lower),
+            &prefixed(&self.prefix, upper),
+        )
+    }
+
+    fn exists(&self, key: &[u8], for_update: bool) -> Result<bool> {
+        self.inner.exists(&prefixed(&self.prefix, key), for_update)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.inner.commit()
+    }
+
+    fn range_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a>
+    where
+        's: 'a,
+    {
+        Box::new(
+            self.range_scan(lower, upper)
+                .map_ok(|(k, v)| decode_tuple_from_kv(&k, &v, None)),
+        )
+    }
+
+    fn range_skip_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        valid_at: ValidityTs,
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
+        // the skip scan of the wrapped storage would decode the keys with the prefix attached
+        Box::new(SeekingSkipIterator::new(self, lower, upper, valid_at))
+    }
+
+    fn range_scan<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        let prefix_len = self.prefix.len();
+        Box::new(
+            self.inner
+                .range_scan(
+                    &prefixed(&self.prefix, lower),
+                    &prefixed(&self.prefix, upper),
+                )
+                .map_ok(move |(k, v)| (k[prefix_len..].to_vec(), v)),
+        )
+    }
+
+    fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
+    where
+        's: 'a,
+    {
+        self.inner.range_count(
+            &prefixed(&self.prefix, lower),
+            &prefixed(&self.prefix, upper),
+        )
+    }
+
+    fn total_scan<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        let prefix_len = self.prefix.len();
+        Box::new(
+            self.inner
+                .range_scan(&self.prefix, &ns_prefix_upper(&self.prefix))
+                .map_ok(move |(k, v)| (k[prefix_len..].to_vec(), v)),
+        )
+    }
+}
@@ -6,30 +6,133 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
 
 use log::info;
-use miette::{miette, IntoDiagnostic, Result, WrapErr};
+use miette::{bail, miette, Diagnostic, IntoDiagnostic, Result, WrapErr};
+use thiserror::Error;
 
-use cozorocks::{DbBuilder, DbIter, RocksDb, Tx};
+use cozorocks::{DbBuilder, DbCompressionType, DbIter, RocksDb, Tx};
 
 use crate::data::tuple::{check_key_for_validity, Tuple};
 use crate::data::value::ValidityTs;
 use crate::runtime::db::{BadDbInit, DbManifest};
 use crate::runtime::relation::{decode_tuple_from_kv, extend_tuple_from_v};
-use crate::storage::{Storage, StoreTx};
+use crate::storage::lock::DirLock;
+use crate::storage::{Storage, StoreTx, TxDurability};
 use crate::utils::swap_option_result;
 use crate::Db;
 
 const KEY_PREFIX_LEN: usize = 9;
 const CURRENT_STORAGE_VERSION: u64 = 3;
 
+/// Compression algorithm used by the RocksDB storage engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde_derive::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RocksDbCompression {
+    /// No compression
+    None,
+    /// LZ4 compression. The bottommost level is always compressed with zstd.
+    Lz4,
+    /// Zstd compression
+    Zstd,
+}
+
+/// When the RocksDB storage engine makes writes durable on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde_derive::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FsyncPolicy {
+    /// Leave syncing the write-ahead log to RocksDB and the OS. A committed transaction
+    /// survives a crash of the process, but not necessarily a crash of the machine.
+    Background,
+    /// Sync the write-ahead log with `fsync` when every transaction commits.
+    EveryCommit,
+    /// Sync the write-ahead log before every commit returns, like `EveryCommit`, but let
+    /// the transactions committing concurrently share a single sync: while one sync runs,
+    /// the commits arriving meanwhile wait and are all synced by the next one. This makes
+    /// durable small writes much faster under concurrency.
+    GroupCommit,
+}
+
+/// Whether the RocksDB storage engine keeps a write-ahead log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde_derive::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Durability {
+    /// Write every transaction to the write-ahead log, synced as given by [`FsyncPolicy`]
+    Durable,
+    /// Write transactions to the memtables only, for caches and scratch data. Writes are
+    /// much faster, but everything not yet flushed to the data files is lost if the process
+    /// crashes. Closing the database with [`Db::close`] flushes it. The `fsync` option is
+    /// ignored.
+    Ephemeral,
+}
+
+/// Tuning options for the RocksDB storage engine, used by [`new_cozo_rocksdb_with_options`].
+/// When a RocksDB `options` file exists in the database directory, it takes precedence
+/// over everything here except the bloom filter and the block cache.
+#[derive(Debug, Clone, serde_derive::Deserialize)]
+#[serde(default)]
+pub struct DbOptions {
+    /// Size of the block cache in bytes. Zero means the RocksDB default.
+    pub block_cache_size: usize,
+    /// Size of a single memtable in bytes. Zero means the RocksDB default.
+    pub write_buffer_size: usize,
+    /// Compression algorithm for the data files
+    pub compression: RocksDbCompression,
+    /// Compression level, if the algorithm supports one
+    pub compression_level: Option<i32>,
+    /// Maximum number of concurrent flush and compaction jobs
+    pub max_background_jobs: usize,
+    /// Bits per key of the bloom filter, or `None` to disable the filter
+    pub bloom_filter_bits_per_key: Option<f64>,
+    /// When writes are synced to disk
+    pub fsync: FsyncPolicy,
+    /// Whether writes go through the write-ahead log
+    pub durability: Durability,
+}
+
+impl Default for DbOptions {
+    fn default() -> Self {
+        Self {
+            block_cache_size: 0,
+            write_buffer_size: 0,
+            compression: RocksDbCompression::Lz4,
+            compression_level: None,
+            max_background_jobs: 6,
+            bloom_filter_bits_per_key: Some(9.9),
+            fsync: FsyncPolicy::Background,
+            durability: Durability::Durable,
+        }
+    }
+}
+
 /// Creates a RocksDB database object.
 /// This is currently the fastest persistent storage and it can
 /// sustain huge concurrency.
 /// Supports concurrent readers and writers.
 pub fn new_cozo_rocksdb(path: impl AsRef<Path>) -> Result<Db<RocksDbStorage>> {
+    new_cozo_rocksdb_with_options(path, &DbOptions::default())
+}
+
+/// Creates a RocksDB database object with the given tuning options.
+/// See [`new_cozo_rocksdb`].
+pub fn new_cozo_rocksdb_with_options(
+    path: impl AsRef<Path>,
+    options: &DbOptions,
+) -> Result<Db<RocksDbStorage>> {
+    let ret = open_cozo_rocksdb(path, options)?;
+    ret.initialize()?;
+    Ok(ret)
+}
+
+/// Open the storage without initializing it, see [`new_cozo_rocksdb_with_options`].
+pub(crate) fn open_cozo_rocksdb(
+    path: impl AsRef<Path>,
+    options: &DbOptions,
+) -> Result<Db<RocksDbStorage>> {
     let builder = DbBuilder::default().path(path.as_ref());
     fs::create_dir_all(path.as_ref()).map_err(|err| {
         BadDbInit(format!(
@@ -38,6 +141,7 @@ pub fn new_cozo_rocksdb(path: impl AsRef<Path>) -> Result<Db<RocksDbStorage>> {
             err
         ))
     })?;
+    let lock = DirLock::acquire(path.as_ref())?;
     let path_buf = PathBuf::from(path.as_ref());
 
     let is_new = {
@@ -96,29 +200,145 @@ pub fn new_cozo_rocksdb(path: impl AsRef<Path>) -> Result<Db<RocksDbStorage>> {
         ""
     };
 
+    let compression = match options.compression {
+        RocksDbCompression::None => DbCompressionType::kNoCompression,
+        RocksDbCompression::Lz4 => DbCompressionType::kLZ4Compression,
+        RocksDbCompression::Zstd => DbCompressionType::kZSTD,
+    };
+    let ephemeral = options.durability == Durability::Ephemeral;
+    // without a log there is nothing to sync
+    let fsync = if ephemeral {
+        FsyncPolicy::Background
+    } else {
+        options.fsync
+    };
+    let sync_on_commit = fsync == FsyncPolicy::EveryCommit;
+    let use_fsync = fsync != FsyncPolicy::Background;
+
+    // the prefix extractor is tied to the key layout and is not configurable
     let db_builder = builder
         .create_if_missing(is_new)
         .use_capped_prefix_extractor(true, KEY_PREFIX_LEN)
-        .use_bloom_filter(true, 9.9, true)
+        .use_bloom_filter(
+            options.bloom_filter_bits_per_key.is_some(),
+            options.bloom_filter_bits_per_key.unwrap_or_default(),
+            true,
+        )
+        .block_cache_size(options.block_cache_size)
+        .write_buffer_size(options.write_buffer_size)
+        .compression(compression, options.compression_level)
+        .max_background_jobs(options.max_background_jobs as i32)
+        .use_fsync(use_fsync)
         .path(store_path)
         .options_path(options_path);
 
     let db = db_builder.build()?;
 
-    let ret = Db::new(RocksDbStorage::new(db))?;
-    ret.initialize()?;
-    Ok(ret)
+    let group_commit =
+        (fsync == FsyncPolicy::GroupCommit).then(|| Arc::new(GroupCommit::new(db.clone())));
+    Db::new(RocksDbStorage::new(
+        db,
+        lock,
+        sync_on_commit,
+        ephemeral,
+        group_commit,
+    ))
 }
 
 /// RocksDB storage engine
 #[derive(Clone)]
 pub struct RocksDbStorage {
     db: RocksDb,
+    lock: Arc<DirLock>,
+    sync_on_commit: bool,
+    disable_wal: bool,
+    group_commit: Option<Arc<GroupCommit>>,
 }
 
 impl RocksDbStorage {
-    pub(crate) fn new(db: RocksDb) -> Self {
-        Self { db }
+    pub(crate) fn new(
+        db: RocksDb,
+        lock: DirLock,
+        sync_on_commit: bool,
+        disable_wal: bool,
+        group_commit: Option<Arc<GroupCommit>>,
+    ) -> Self {
+        Self {
+            db,
+            lock: Arc::new(lock),
+            sync_on_commit,
+            disable_wal,
+            group_commit,
+        }
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Syncing the write-ahead log failed: {0}")]
+#[diagnostic(code(rocksdb::group_commit))]
+pub(crate) struct GroupSyncFailed(String);
+
+/// Lets concurrently committing transactions share one sync of the write-ahead log,
+/// see [`FsyncPolicy::GroupCommit`].
+pub(crate) struct GroupCommit {
+    db: RocksDb,
+    state: Mutex<GroupCommitState>,
+    synced: Condvar,
+}
+
+#[derive(Default)]
+struct GroupCommitState {
+    /// Number of commits written to the log so far
+    written: u64,
+    /// Number of the first commits known to be synced
+    synced: u64,
+    /// Whether a commit is running a sync on behalf of the others
+    syncing: bool,
+    /// The commits covered by the last failed sync, and its error
+    failed: Option<(u64, String)>,
+}
+
+impl GroupCommit {
+    fn new(db: RocksDb) -> Self {
+        Self {
+            db,
+            state: Default::default(),
+            synced: Default::default(),
+        }
+    }
+    /// Run `commit`, which writes to the log without syncing, and return once the log is
+    /// synced up to it. The first commit to find no sync running syncs for everyone.
+    fn commit(&self, commit: impl FnOnce() -> Result<()>) -> Result<()> {
+        commit()?;
+        let mut state = self.state.lock().unwrap();
+        state.written += 1;
+        let seq = state.written;
+        loop {
+            if state.synced >= seq {
+                return Ok(());
+            }
+            if let Some((upto, err)) = &state.failed {
+                if *upto >= seq {
+                    bail!(GroupSyncFailed(err.clone()))
+                }
+            }
+            if state.syncing {
+                state = self.synced.wait(state).unwrap();
+            } else {
+                // everything counted as written so far is in the log and covered by the sync
+                let target = state.written;
+                state.syncing = true;
+                drop(state);
+                let res = self.db.sync_wal();
+                state = self.state.lock().unwrap();
+                state.syncing = false;
+                match res {
+                    Ok(()) => state.synced = target,
+                    Err(err) => state.failed = Some((target, err.to_string())),
+                }
+                self.synced.notify_all();
+            }
+        }
     }
 }
 
@@ -129,9 +349,47 @@ impl Storage<'_> for RocksDbStorage {
         "rocksdb"
     }
 
-    fn transact(&self, _write: bool) -> Result<Self::Tx> {
-        let db_tx = self.db.transact().set_snapshot(true).start();
-        Ok(RocksDbTx { db_tx })
+    fn transact(&self, write: bool) -> Result<Self::Tx> {
+        let db_tx = self
+            .db
+            .transact()
+            .set_snapshot(true)
+            .sync(self.sync_on_commit)
+            .disable_wal(self.disable_wal)
+            .start();
+        let group_commit = if write {
+            self.group_commit.clone()
+        } else {
+            None
+        };
+        Ok(RocksDbTx {
+            db_tx,
+            group_commit,
+        })
+    }
+
+    fn transact_write_with(&self, durability: TxDurability) -> Result<Self::Tx> {
+        let (disable_wal, sync, group_commit) = match durability {
+            TxDurability::Default => return self.transact(true),
+            TxDurability::NoSync => (true, false, None),
+            TxDurability::Async => (false, false, None),
+            // a group commit syncs the log after the commit, which itself must not sync
+            TxDurability::Sync => match &self.group_commit {
+                None => (false, true, None),
+                Some(group_commit) => (false, false, Some(group_commit.clone())),
+            },
+        };
+        let db_tx = self
+            .db
+            .transact()
+            .set_snapshot(true)
+            .sync(sync)
+            .disable_wal(disable_wal)
+            .start();
+        Ok(RocksDbTx {
+            db_tx,
+            group_commit,
+        })
     }
 
     fn range_compact(&self, lower: &[u8], upper: &[u8]) -> Result<()> {
@@ -148,10 +406,38 @@ impl Storage<'_> for RocksDbStorage {
         }
         Ok(())
     }
+
+    fn approximate_size(&self, lower: &[u8], upper: &[u8]) -> Result<Option<u64>> {
+        Ok(Some(self.db.approximate_size(lower, upper)))
+    }
+
+    fn engine_stats(&self) -> Result<BTreeMap<String, String>> {
+        let mut ret = BTreeMap::new();
+        for name in [
+            "rocksdb.levelstats",
+            "rocksdb.estimate-num-keys",
+            "rocksdb.total-sst-files-size",
+            "rocksdb.cur-size-all-mem-tables",
+            "rocksdb.block-cache-usage",
+        ] {
+            if let Some(value) = self.db.property(name) {
+                ret.insert(name.to_string(), value);
+            }
+        }
+        Ok(ret)
+    }
+
+    fn close(&self) -> Result<()> {
+        self.db.flush().into_diagnostic()?;
+        self.db.close().into_diagnostic()?;
+        self.lock.release();
+        Ok(())
+    }
 }
 
 pub struct RocksDbTx {
     db_tx: Tx,
+    group_commit: Option<Arc<GroupCommit>>,
 }
 
 unsafe impl Sync for RocksDbTx {}
@@ -205,7 +491,10 @@ impl<'s> StoreTx<'s> for RocksDbTx {
     }
 
     fn commit(&mut self) -> Result<()> {
-        Ok(self.db_tx.commit()?)
+        match &self.group_commit {
+            None => Ok(self.db_tx.commit()?),
+            Some(group_commit) => group_commit.commit(|| Ok(self.db_tx.commit()?)),
+        }
     }
 
     fn range_scan_tuple<'a>(
@@ -0,0 +1,351 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Deterministic crash testing: a storage injecting faults at chosen operations, and a
+//! harness re-opening the database after each fault and verifying it.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use miette::{bail, ensure, Diagnostic, Result, WrapErr};
+use thiserror::Error;
+
+use crate::data::tuple::Tuple;
+use crate::data::value::ValidityTs;
+use crate::storage::mem::MemStorage;
+use crate::storage::{Storage, StoreTx, TxDurability};
+use crate::{Db, IntegrityReport};
+
+/// A fault injected by [`SimStorage`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Fault {
+    /// The operation fails, but the storage goes on
+    WriteError,
+    /// A commit applies only the first half of the writes of its transaction, then the
+    /// storage crashes. Other operations crash the storage.
+    TornCommit,
+    /// The operation and all later ones fail until [`SimStorage::restart`]
+    Crash,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Injected fault {0:?} at operation {1}")]
+#[diagnostic(code(sim::injected_fault))]
+pub(crate) struct InjectedFault(Fault, u64);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The simulated storage has crashed")]
+#[diagnostic(code(sim::crashed))]
+pub(crate) struct StorageCrashed;
+
+#[derive(Default)]
+struct SimState {
+    /// Number of operations so far: transaction starts, writes and commits
+    ops: u64,
+    /// The faults to inject, by operation number
+    faults: BTreeMap<u64, Fault>,
+    crashed: bool,
+}
+
+/// Wraps a storage, counting the operations on it and failing those chosen by
+/// [`inject`](Self::inject). Reads are not counted, but fail once the storage crashed.
+/// What was committed before a crash survives it, as in a durable storage.
+#[derive(Clone)]
+pub(crate) struct SimStorage<S> {
+    inner: S,
+    state: Arc<Mutex<SimState>>,
+}
+
+impl<S> SimStorage<S> {
+    pub(crate) fn new(inner: S) -> Self {
+        Self {
+            inner,
+            state: Default::default(),
+        }
+    }
+    /// Inject `fault` at the operation numbered `at`.
+    pub(crate) fn inject(&self, at: u64, fault: Fault) {
+        self.state.lock().unwrap().faults.insert(at, fault);
+    }
+    /// The number of operations so far.
+    pub(crate) fn ops(&self) -> u64 {
+        self.state.lock().unwrap().ops
+    }
+    /// Bring the storage back after a crash, discarding the faults not yet injected.
+    pub(crate) fn restart(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.crashed = false;
+        state.faults.clear();
+    }
+    fn check_alive(&self) -> Result<()> {
+        ensure!(!self.state.lock().unwrap().crashed, StorageCrashed);
+        Ok(())
+    }
+    /// Count an operation, returning whether it is a commit to tear.
+    fn step(&self, is_commit: bool) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        ensure!(!state.crashed, StorageCrashed);
+        let n = state.ops;
+        state.ops += 1;
+        match state.faults.remove(&n) {
+            None => Ok(false),
+            Some(Fault::TornCommit) if is_commit => Ok(true),
+            Some(Fault::WriteError) => bail!(InjectedFault(Fault::WriteError, n)),
+            Some(fault) => {
+                state.crashed = true;
+                bail!(InjectedFault(fault, n))
+            }
+        }
+    }
+    fn crash(&self) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.crashed = true;
+        state.ops - 1
+    }
+}
+
+impl<'s, S: Storage<'s> + 's> Storage<'s> for SimStorage<S> {
+    type Tx = SimTx<'s, S>;
+
+    fn storage_kind(&self) -> &'static str {
+        self.inner.storage_kind()
+    }
+
+    fn transact(&'s self, write: bool) -> Result<Self::Tx> {
+        if write {
+            self.step(false)?;
+        } else {
+            self.check_alive()?;
+        }
+        Ok(SimTx {
+            inner: Some(self.inner.transact(write)?),
+            storage: self,
+            writes: vec![],
+        })
+    }
+
+    fn transact_write_with(&'s self, durability: TxDurability) -> Result<Self::Tx> {
+        self.step(false)?;
+        Ok(SimTx {
+            inner: Some(self.inner.transact_write_with(durability)?),
+            storage: self,
+            writes: vec![],
+        })
+    }
+
+    fn range_compact(&'s self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.check_alive()?;
+        self.inner.range_compact(lower, upper)
+    }
+
+    fn batch_put<'a>(
+        &'a self,
+        data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
+    ) -> Result<()> {
+        self.step(false)?;
+        self.inner.batch_put(data)
+    }
+}
+
+enum Write {
+    Put(Vec<u8>, Vec<u8>),
+    Del(Vec<u8>),
+}
+
+/// Transaction type of [`SimStorage`], remembering its writes to tear its commit.
+pub(crate) struct SimTx<'s, S: Storage<'s>> {
+    /// Only `None` after a torn commit, which crashes the storage
+    inner: Option<S::Tx>,
+    storage: &'s SimStorage<S>,
+    writes: Vec<Write>,
+}
+
+impl<'s, S: Storage<'s> + 's> SimTx<'s, S> {
+    fn inner(&self) -> Result<&S::Tx> {
+        self.storage.check_alive()?;
+        Ok(self.inner.as_ref().unwrap())
+    }
+    fn inner_mut(&mut self, is_commit: bool) -> Result<(bool, &mut S::Tx)> {
+        let tear = self.storage.step(is_commit)?;
+        Ok((tear, self.inner.as_mut().unwrap()))
+    }
+}
+
+impl<'s, S: Storage<'s> + 's> StoreTx<'s> for SimTx<'s, S> {
+    fn get(&self, key: &[u8], for_update: bool) -> Result<Option<Vec<u8>>> {
+        self.inner()?.get(key, for_update)
+    }
+
+    fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.inner_mut(false)?.1.put(key, val)?;
+        self.writes.push(Write::Put(key.to_vec(), val.to_vec()));
+        Ok(())
+    }
+
+    fn supports_par_put(&self) -> bool {
+        // parallel writes cannot be remembered in order
+        false
+    }
+
+    fn del(&mut self, key: &[u8]) -> Result<()> {
+        self.inner_mut(false)?.1.del(key)?;
+        self.writes.push(Write::Del(key.to_vec()));
+        Ok(())
+    }
+
+    fn del_range_from_persisted(&mut self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.inner_mut(false)?
+            .1
+            .del_range_from_persisted(lower, upper)
+    }
+
+    fn exists(&self, key: &[u8], for_update: bool) -> Result<bool> {
+        self.inner()?.exists(key, for_update)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        let (tear, inner) = self.inner_mut(true)?;
+        if !tear {
+            return inner.commit();
+        }
+        // abandon the transaction, and write part of it in another one
+        self.inner = None;
+        let torn = self.storage.inner.transact(true).and_then(|mut tx| {
+            for write in &self.writes[..self.writes.len() / 2] {
+                match write {
+                    Write::Put(k, v) => tx.put(k, v)?,
+                    Write::Del(k) => tx.del(k)?,
+                }
+            }
+            tx.commit()
+        });
+        let n = self.storage.crash();
+        torn?;
+        bail!(InjectedFault(Fault::TornCommit, n))
+    }
+
+    fn range_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a>
+    where
+        's: 'a,
+    {
+        match self.inner() {
+            Ok(inner) => inner.range_scan_tuple(lower, upper),
+            Err(err) => Box::new(std::iter::once(Err(err))),
+        }
+    }
+
+    fn range_skip_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        valid_at: ValidityTs,
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
+        match self.inner() {
+            Ok(inner) => inner.range_skip_scan_tuple(lower, upper, valid_at),
+            Err(err) => Box::new(std::iter::once(Err(err))),
+        }
+    }
+
+    fn range_scan<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        match self.inner() {
+            Ok(inner) => inner.range_scan(lower, upper),
+            Err(err) => Box::new(std::iter::once(Err(err))),
+        }
+    }
+
+    fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
+    where
+        's: 'a,
+    {
+        self.inner()?.range_count(lower, upper)
+    }
+
+    fn total_scan<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        match self.inner() {
+            Ok(inner) => inner.total_scan(),
+            Err(err) => Box::new(std::iter::once(Err(err))),
+        }
+    }
+}
+
+pub(crate) type SimDb = Db<SimStorage<MemStorage>>;
+
+/// Re-open the database over `storage`, as a restarted process would after a crash.
+pub(crate) fn reopen(storage: &SimStorage<MemStorage>) -> Result<SimDb> {
+    storage.restart();
+    let db = Db::new(storage.clone())?;
+    db.initialize()?;
+    Ok(db)
+}
+
+/// The outcome of a run of [`sweep_faults`].
+pub(crate) struct SimRun {
+    pub(crate) fault: Fault,
+    /// The operation of the workload the fault was injected at
+    pub(crate) at: u64,
+    /// The integrity of the re-opened database
+    pub(crate) report: IntegrityReport,
+    /// The invariants of the workload on the re-opened database
+    pub(crate) check: Result<()>,
+}
+
+/// Run `workload` once with each of `faults` injected at each of the operations it
+/// performs, every time on a fresh database prepared by `setup`. After each run the
+/// database is re-opened, its integrity verified, and the invariants of the workload
+/// validated by `check`. The workload may fail, but must not panic, and the database
+/// must re-open.
+pub(crate) fn sweep_faults(
+    faults: &[Fault],
+    setup: impl Fn(&SimDb) -> Result<()>,
+    workload: impl Fn(&SimDb) -> Result<()>,
+    check: impl Fn(&SimDb) -> Result<()>,
+) -> Result<Vec<SimRun>> {
+    let fresh = || -> Result<(SimStorage<MemStorage>, SimDb)> {
+        let storage = SimStorage::new(MemStorage::default());
+        let db = reopen(&storage)?;
+        setup(&db)?;
+        Ok((storage, db))
+    };
+    let (storage, db) = fresh()?;
+    let start = storage.ops();
+    workload(&db)?;
+    let total = storage.ops() - start;
+
+    let mut runs = vec![];
+    for at in 0..total {
+        for &fault in faults {
+            let (storage, db) = fresh()?;
+            storage.inject(storage.ops() + at, fault);
+            let _ = workload(&db);
+            drop(db);
+            let context = || format!("after {fault:?} at operation {at} of the workload");
+            let db = reopen(&storage).wrap_err_with(context)?;
+            runs.push(SimRun {
+                fault,
+                at,
+                report: db.verify_integrity().wrap_err_with(context)?,
+                check: check(&db).wrap_err_with(context),
+            });
+        }
+    }
+    Ok(runs)
+}
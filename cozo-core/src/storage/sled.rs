@@ -7,9 +7,11 @@
  */
 
 use std::cmp::Ordering;
+use std::fs;
 use std::iter;
 use std::iter::Fuse;
 use std::path::Path;
+use std::sync::{Arc, Mutex};
 
 use itertools::Itertools;
 use miette::{miette, IntoDiagnostic, Result};
@@ -18,6 +20,7 @@ use sled::{Batch, Config, Db, IVec, Iter, Mode};
 use crate::data::tuple::Tuple;
 use crate::data::value::ValidityTs;
 use crate::runtime::relation::decode_tuple_from_kv;
+use crate::storage::lock::DirLock;
 use crate::storage::{Storage, StoreTx};
 use crate::utils::{swap_option_result, TempCollector};
 
@@ -25,17 +28,29 @@ use crate::utils::{swap_option_result, TempCollector};
 /// You should use [`new_cozo_rocksdb`](crate::new_cozo_rocksdb) or
 /// [`new_cozo_sqlite`](crate::new_cozo_sqlite) instead.
 pub fn new_cozo_sled(path: impl AsRef<Path>) -> Result<crate::Db<SledStorage>> {
-    let db = sled::open(path).into_diagnostic()?;
-    let ret = crate::Db::new(SledStorage { db })?;
-
+    let ret = open_cozo_sled(path)?;
     ret.initialize()?;
     Ok(ret)
 }
 
+/// Open the storage without initializing it, see [`new_cozo_sled`].
+pub(crate) fn open_cozo_sled(path: impl AsRef<Path>) -> Result<crate::Db<SledStorage>> {
+    fs::create_dir_all(path.as_ref()).into_diagnostic()?;
+    let lock = Arc::new(DirLock::acquire(path.as_ref())?);
+    let db = sled::open(path).into_diagnostic()?;
+    crate::Db::new(SledStorage {
+        db: Arc::new(Mutex::new(Some(db))),
+        lock,
+    })
+}
+
 /// Storage engine using Sled
 #[derive(Clone)]
 pub struct SledStorage {
-    db: Db,
+    /// Taken out when the storage is closed, so that the handle is dropped before the
+    /// directory lock is released
+    db: Arc<Mutex<Option<Db>>>,
+    lock: Arc<DirLock>,
 }
 
 const PUT_MARKER: u8 = 1;
@@ -49,8 +64,14 @@ impl Storage<'_> for SledStorage {
     }
 
     fn transact(&self, _write: bool) -> Result<Self::Tx> {
+        let db = self
+            .db
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| miette!("sled storage is closed"))?;
         Ok(SledTx {
-            db: self.db.clone(),
+            db,
             changes: Default::default(),
         })
     }
@@ -71,6 +92,14 @@ impl Storage<'_> for SledStorage {
         tx.commit()?;
         Ok(())
     }
+
+    fn close(&self) -> Result<()> {
+        if let Some(db) = self.db.lock().unwrap().take() {
+            db.flush().into_diagnostic()?;
+        }
+        self.lock.release();
+        Ok(())
+    }
 }
 
 pub struct SledTx {
@@ -35,6 +35,13 @@ pub struct SqliteStorage {
 /// You must provide a disk-based path: `:memory:` is not OK.
 /// If you want a pure memory storage, use [`new_cozo_mem`](crate::new_cozo_mem).
 pub fn new_cozo_sqlite(path: impl AsRef<Path>) -> Result<crate::Db<SqliteStorage>> {
+    let ret = open_cozo_sqlite(path)?;
+    ret.initialize()?;
+    Ok(ret)
+}
+
+/// Open the storage without initializing it, see [`new_cozo_sqlite`].
+pub(crate) fn open_cozo_sqlite(path: impl AsRef<Path>) -> Result<crate::Db<SqliteStorage>> {
     if path.as_ref().to_str() == Some("") {
         bail!("empty path for sqlite storage")
     }
@@ -49,14 +56,11 @@ pub fn new_cozo_sqlite(path: impl AsRef<Path>) -> Result<crate::Db<SqliteStorage
     let mut statement = conn.prepare(query).unwrap();
     while statement.next().into_diagnostic()? != State::Done {}
 
-    let ret = crate::Db::new(SqliteStorage {
+    crate::Db::new(SqliteStorage {
         lock: Default::default(),
         name: PathBuf::from(path.as_ref()),
         pool: Default::default(),
-    })?;
-
-    ret.initialize()?;
-    Ok(ret)
+    })
 }
 
 impl<'s> Storage<'s> for SqliteStorage {
@@ -111,6 +115,11 @@ impl<'s> Storage<'s> for SqliteStorage {
         Ok(())
     }
 
+    fn close(&'_ self) -> Result<()> {
+        self.pool.lock().unwrap().clear();
+        Ok(())
+    }
+
     fn storage_kind(&self) -> &'static str {
         "sqlite"
     }
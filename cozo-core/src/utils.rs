@@ -15,6 +15,33 @@ pub(crate) fn swap_option_result<T, E>(d: Result<Option<T>, E>) -> Option<Result
     }
 }
 
+/// Guard of a `tracing` span entered with [`trace_span!`], exiting it on drop. Without the
+/// `tracing` feature it does nothing.
+pub(crate) struct TraceSpan(#[cfg(feature = "tracing")] pub(crate) tracing::span::EnteredSpan);
+
+impl TraceSpan {
+    /// Record a field declared as `tracing::field::Empty` when the span was entered.
+    #[allow(unused_variables)]
+    #[inline(always)]
+    pub(crate) fn record(&self, field: &'static str, value: u64) {
+        #[cfg(feature = "tracing")]
+        self.0.record(field, value);
+    }
+}
+
+/// Enter a debug level `tracing` span for the rest of the scope, taking the same arguments as
+/// `tracing::debug_span!`. The arguments are not evaluated without the `tracing` feature.
+macro_rules! trace_span {
+    ($($args:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        let span = $crate::utils::TraceSpan(tracing::debug_span!($($args)*).entered());
+        #[cfg(not(feature = "tracing"))]
+        let span = $crate::utils::TraceSpan();
+        span
+    }};
+}
+pub(crate) use trace_span;
+
 #[derive(Default)]
 pub(crate) struct TempCollector<T: serde::Serialize + for<'a> serde::Deserialize<'a>> {
     // pub(crate) inner: Vec<T>,
@@ -29,3 +56,44 @@ impl<T: serde::Serialize + for<'a> serde::Deserialize<'a>> TempCollector<T> {
         self.inner.into_iter().map(|v| v.unwrap())
     }
 }
+
+/// The candidate closest to `name` by edit distance, if it is close enough to be a likely
+/// misspelling. Used for "did you mean" hints.
+pub(crate) fn closest_match<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|c| (edit_distance(name, c), c))
+        .filter(|(d, _)| *d <= max_distance)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c)
+}
+
+/// Edit distance between two strings counted in characters, where an edit is an insertion,
+/// a deletion, a substitution or a swap of two adjacent characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
@@ -0,0 +1,115 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Derive macros for the `FromEntity` and `IntoTx` traits of the `cozo` crate,
+//! re-exported by it when its `derive` feature is enabled.
+//!
+//! Each named field maps to the column of the same name, or to the column given by
+//! `#[cozo(rename = "...")]`. The fields are converted through their serde
+//! implementations, see `cozo::to_data_value` and `cozo::from_data_value`.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, Ident, LitStr, Result};
+
+/// Derive `cozo::FromEntity`, building the struct from the columns named after its
+/// fields. Missing columns are read as null, which only `Option` fields accept.
+#[proc_macro_derive(FromEntity, attributes(cozo))]
+pub fn derive_from_entity(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_from_entity(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Derive `cozo::IntoTx`, writing each field to the column named after it.
+#[proc_macro_derive(IntoTx, attributes(cozo))]
+pub fn derive_into_tx(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_into_tx(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// The fields of the struct with the columns they map to.
+fn columns(input: &DeriveInput) -> Result<Vec<(Ident, String)>> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(Error::new(
+                    Span::call_site(),
+                    "only structs with named fields can be mapped to rows",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new(
+                Span::call_site(),
+                "only structs can be mapped to rows",
+            ))
+        }
+    };
+    let mut columns = vec![];
+    for field in fields {
+        let ident = field.ident.clone().unwrap();
+        let mut column = ident.to_string();
+        for attr in &field.attrs {
+            if !attr.path().is_ident("cozo") {
+                continue;
+            }
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    column = meta.value()?.parse::<LitStr>()?.value();
+                    Ok(())
+                } else {
+                    Err(meta.error("unknown cozo attribute, expected `rename`"))
+                }
+            })?;
+        }
+        columns.push((ident, column));
+    }
+    Ok(columns)
+}
+
+fn expand_from_entity(input: &DeriveInput) -> Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let fields = columns(input)?.into_iter().map(|(ident, column)| {
+        quote! { #ident: ::cozo::entity_column(headers, row, #column)? }
+    });
+    Ok(quote! {
+        impl #impl_generics ::cozo::FromEntity for #name #ty_generics #where_clause {
+            fn from_entity(
+                headers: &[::std::string::String],
+                row: &[::cozo::DataValue],
+            ) -> ::std::result::Result<Self, ::cozo::Error> {
+                ::std::result::Result::Ok(Self { #(#fields,)* })
+            }
+        }
+    })
+}
+
+fn expand_into_tx(input: &DeriveInput) -> Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let (idents, columns): (Vec<_>, Vec<_>) = columns(input)?.into_iter().unzip();
+    Ok(quote! {
+        impl #impl_generics ::cozo::IntoTx for #name #ty_generics #where_clause {
+            fn columns() -> ::std::vec::Vec<::std::string::String> {
+                ::std::vec![#(::std::string::String::from(#columns)),*]
+            }
+            fn into_row(
+                self,
+            ) -> ::std::result::Result<::std::vec::Vec<::cozo::DataValue>, ::cozo::Error> {
+                ::std::result::Result::Ok(::std::vec![#(::cozo::to_data_value(&self.#idents)?),*])
+            }
+        }
+    })
+}
@@ -26,8 +26,8 @@ fn py_to_rows(ob: &PyAny) -> PyResult<Vec<Vec<DataValue>>> {
     Ok(res)
 }
 
-fn report2py(r: Report) -> PyErr {
-    PyException::new_err(r.to_string())
+fn report2py(r: impl Into<Report>) -> PyErr {
+    PyException::new_err(r.into().to_string())
 }
 
 fn py_to_named_rows(ob: &PyAny) -> PyResult<NamedRows> {
@@ -27,6 +27,7 @@ struct DbOpts;
 typedef Status::Code StatusCode;
 typedef Status::SubCode StatusSubCode;
 typedef Status::Severity StatusSeverity;
+typedef CompressionType DbCompressionType;
 typedef rust::Slice<const uint8_t> RustBytes;
 
 
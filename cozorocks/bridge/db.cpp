@@ -59,7 +59,7 @@ shared_ptr <RocksDbBridge> open_db(const DbOpts &opts, RocksDbStatus &status) {
     shared_ptr<Cache> cache = nullptr;
 
     if (opts.block_cache_size > 0) {
-        cache = NewLRUCache(1 * 1024 * 1024 * 1024);
+        cache = NewLRUCache(opts.block_cache_size);
     }
 
     if (!opts.options_path.empty()) {
@@ -106,10 +106,39 @@ shared_ptr <RocksDbBridge> open_db(const DbOpts &opts, RocksDbStatus &status) {
 
         options.enable_blob_garbage_collection = opts.enable_blob_garbage_collection;
     }
-    if (opts.use_bloom_filter) {
+    if (opts.options_path.empty()) {
+        options.compression = opts.compression;
+        if (opts.compression == kNoCompression) {
+            options.bottommost_compression = kNoCompression;
+        }
+        if (opts.set_compression_level) {
+            options.compression_opts.level = opts.compression_level;
+            options.bottommost_compression_opts.level = opts.compression_level;
+            options.bottommost_compression_opts.enabled = true;
+        }
+        if (opts.write_buffer_size > 0) {
+            options.write_buffer_size = opts.write_buffer_size;
+        }
+        if (opts.max_background_jobs > 0) {
+            options.max_background_jobs = opts.max_background_jobs;
+        }
+        options.use_fsync = opts.use_fsync;
+    }
+    if (opts.use_bloom_filter || (cache != nullptr && opts.options_path.empty())) {
         BlockBasedTableOptions table_options;
-        table_options.filter_policy.reset(NewBloomFilterPolicy(opts.bloom_filter_bits_per_key, false));
-        table_options.whole_key_filtering = opts.bloom_filter_whole_key_filtering;
+        if (opts.options_path.empty()) {
+            table_options.block_size = 16 * 1024;
+            table_options.cache_index_and_filter_blocks = true;
+            table_options.pin_l0_filter_and_index_blocks_in_cache = true;
+            table_options.format_version = 5;
+        }
+        if (opts.use_bloom_filter) {
+            table_options.filter_policy.reset(NewBloomFilterPolicy(opts.bloom_filter_bits_per_key, false));
+            table_options.whole_key_filtering = opts.bloom_filter_whole_key_filtering;
+        }
+        if (cache != nullptr) {
+            table_options.block_cache = cache;
+        }
         options.table_factory.reset(NewBlockBasedTableFactory(table_options));
     }
     if (opts.use_capped_prefix_extractor) {
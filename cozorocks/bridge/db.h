@@ -110,6 +110,46 @@ struct RocksDbBridge {
         write_status(s, status);
     }
 
+    inline void flush(RocksDbStatus &status) const {
+        auto s = db->FlushWAL(true);
+        if (!s.ok()) {
+            write_status(s, status);
+            return;
+        }
+        FlushOptions options;
+        options.wait = true;
+        write_status(db->Flush(options, db->DefaultColumnFamily()), status);
+    }
+
+    inline void sync_wal(RocksDbStatus &status) const {
+        write_status(db->FlushWAL(true), status);
+    }
+
+    inline void close(RocksDbStatus &status) const {
+        write_status(db->Close(), status);
+    }
+
+    [[nodiscard]] inline uint64_t approximate_size(RustBytes start, RustBytes end) const {
+        auto cf = db->DefaultColumnFamily();
+        Range range(convert_slice(start), convert_slice(end));
+        SizeApproximationOptions options;
+        options.include_memtables = true;
+        uint64_t size = 0;
+        auto s = db->GetApproximateSizes(options, cf, &range, 1, &size);
+        if (!s.ok()) {
+            return 0;
+        }
+        return size;
+    }
+
+    [[nodiscard]] inline rust::String get_property(rust::Str name) const {
+        string value;
+        if (!db->GetProperty(string(name), &value)) {
+            return {};
+        }
+        return {value};
+    }
+
     DB *get_base_db() const {
         return db->GetBaseDB();
     }
@@ -54,6 +54,12 @@ impl Default for DbOpts {
             fixed_prefix_extractor_len: 0,
             destroy_on_exit: false,
             block_cache_size: 0,
+            write_buffer_size: 0,
+            compression: DbCompressionType::kLZ4Compression,
+            set_compression_level: false,
+            compression_level: 0,
+            max_background_jobs: 0,
+            use_fsync: false,
         }
     }
 }
@@ -121,6 +127,28 @@ impl DbBuilder {
         self.opts.fixed_prefix_extractor_len = len;
         self
     }
+    pub fn block_cache_size(mut self, size: usize) -> Self {
+        self.opts.block_cache_size = size;
+        self
+    }
+    pub fn write_buffer_size(mut self, size: usize) -> Self {
+        self.opts.write_buffer_size = size;
+        self
+    }
+    pub fn compression(mut self, compression: DbCompressionType, level: Option<i32>) -> Self {
+        self.opts.compression = compression;
+        self.opts.set_compression_level = level.is_some();
+        self.opts.compression_level = level.unwrap_or_default();
+        self
+    }
+    pub fn max_background_jobs(mut self, val: i32) -> Self {
+        self.opts.max_background_jobs = val;
+        self
+    }
+    pub fn use_fsync(mut self, val: bool) -> Self {
+        self.opts.use_fsync = val;
+        self
+    }
     pub fn build(self) -> Result<RocksDb, RocksDbStatus> {
         let mut status = RocksDbStatus::default();
 
@@ -177,6 +205,47 @@ impl RocksDb {
             Err(status)
         }
     }
+    pub fn flush(&self) -> Result<(), RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        self.inner.flush(&mut status);
+        if status.is_ok() {
+            Ok(())
+        } else {
+            Err(status)
+        }
+    }
+    /// Write the buffered write-ahead log out and sync it to disk.
+    pub fn sync_wal(&self) -> Result<(), RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        self.inner.sync_wal(&mut status);
+        if status.is_ok() {
+            Ok(())
+        } else {
+            Err(status)
+        }
+    }
+    /// Close the database, releasing its files and its lock on the directory.
+    /// The database must not be used afterwards.
+    pub fn close(&self) -> Result<(), RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        self.inner.close(&mut status);
+        if status.is_ok() {
+            Ok(())
+        } else {
+            Err(status)
+        }
+    }
+    pub fn approximate_size(&self, lower: &[u8], upper: &[u8]) -> u64 {
+        self.inner.approximate_size(lower, upper)
+    }
+    pub fn property(&self, name: &str) -> Option<std::string::String> {
+        let value = self.inner.get_property(name);
+        if value.is_empty() {
+            None
+        } else {
+            Some(value)
+        }
+    }
     pub fn get_sst_writer(&self, path: &str) -> Result<SstWriter, RocksDbStatus> {
         let mut status = RocksDbStatus::default();
         let ret = self.inner.get_sst_writer(path, &mut status);
@@ -41,6 +41,19 @@ pub(crate) mod ffi {
         pub fixed_prefix_extractor_len: usize,
         pub destroy_on_exit: bool,
         pub block_cache_size: usize,
+        pub write_buffer_size: usize,
+        pub compression: DbCompressionType,
+        pub set_compression_level: bool,
+        pub compression_level: i32,
+        pub max_background_jobs: i32,
+        pub use_fsync: bool,
+    }
+
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub enum DbCompressionType {
+        kNoCompression = 0x0,
+        kLZ4Compression = 0x4,
+        kZSTD = 0x7,
     }
 
     #[derive(Clone, Debug, Eq, PartialEq)]
@@ -109,6 +122,7 @@ pub(crate) mod ffi {
         type StatusCode;
         type StatusSubCode;
         type StatusSeverity;
+        type DbCompressionType;
         type WriteOptions;
         type PinnableSlice;
         fn convert_pinnable_slice_back(s: &PinnableSlice) -> &[u8];
@@ -133,6 +147,11 @@ pub(crate) mod ffi {
             upper: &[u8],
             status: &mut RocksDbStatus,
         );
+        fn approximate_size(self: &RocksDbBridge, lower: &[u8], upper: &[u8]) -> u64;
+        fn get_property(self: &RocksDbBridge, name: &str) -> String;
+        fn flush(self: &RocksDbBridge, status: &mut RocksDbStatus);
+        fn sync_wal(self: &RocksDbBridge, status: &mut RocksDbStatus);
+        fn close(self: &RocksDbBridge, status: &mut RocksDbStatus);
         fn get_sst_writer(
             self: &RocksDbBridge,
             path: &str,
@@ -11,6 +11,7 @@
 
 pub use bridge::db::DbBuilder;
 pub use bridge::db::RocksDb;
+pub use bridge::ffi::DbCompressionType;
 pub use bridge::ffi::RocksDbStatus;
 pub use bridge::ffi::SnapshotBridge;
 pub use bridge::ffi::StatusCode;